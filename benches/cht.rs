@@ -8,12 +8,13 @@ use std::{
     thread,
 };
 
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 
 fn bench_single_thread_insertion(c: &mut Criterion) {
-    c.bench_function_over_inputs(
-        "moka-cht: single threaded insertion",
-        |b, &&numel| {
+    let mut group = c.benchmark_group("moka-cht: single threaded insertion");
+
+    for numel in [8, 64, 512, 4096, 32768].iter() {
+        group.bench_with_input(BenchmarkId::from_parameter(numel), numel, |b, &numel| {
             let map = HashMap::new();
 
             for i in 0..numel {
@@ -21,9 +22,10 @@ fn bench_single_thread_insertion(c: &mut Criterion) {
             }
 
             b.iter(|| map.insert(criterion::black_box(numel + 1), numel + 1))
-        },
-        [8, 64, 512, 4096, 32768].iter(),
-    );
+        });
+    }
+
+    group.finish();
 }
 
 fn bench_multi_thread_insertion(c: &mut Criterion) {
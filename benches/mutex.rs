@@ -9,7 +9,7 @@ use std::{
     thread,
 };
 
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 use hashbrown::{hash_map::Entry, HashMap};
 use parking_lot::RwLock;
 
@@ -57,9 +57,10 @@ impl<K: Hash + Eq, V, S: BuildHasher> ConcurrentHashMap<K, V, S> {
 }
 
 fn bench_single_thread_insertion(c: &mut Criterion) {
-    c.bench_function_over_inputs(
-        "hashbrown/parking_lot: single threaded insertion",
-        |b, &&numel| {
+    let mut group = c.benchmark_group("hashbrown/parking_lot: single threaded insertion");
+
+    for numel in [8, 64, 512, 4096, 32768].iter() {
+        group.bench_with_input(BenchmarkId::from_parameter(numel), numel, |b, &numel| {
             let map = ConcurrentHashMap::new();
 
             for i in 0..numel {
@@ -67,9 +68,10 @@ fn bench_single_thread_insertion(c: &mut Criterion) {
             }
 
             b.iter(|| map.insert(criterion::black_box(numel + 1), numel + 1))
-        },
-        [8, 64, 512, 4096, 32768].iter(),
-    );
+        });
+    }
+
+    group.finish();
 }
 
 fn bench_multi_thread_insertion(c: &mut Criterion) {
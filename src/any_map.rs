@@ -0,0 +1,294 @@
+//! A hash map whose values may be of different types.
+//!
+//! [`AnyValueMap`] stores its values as type-erased [`AnyValue`]s and offers
+//! typed accessors that downcast them, so that heterogeneous registries can be
+//! built without resorting to unsafe code.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use moka_cht::any_map::AnyValueMap;
+//!
+//! let map = AnyValueMap::new();
+//!
+//! map.insert("count", 5u32);
+//! map.insert("name", "foo".to_string());
+//!
+//! assert_eq!(map.get::<_, u32>("count").as_deref(), Some(&5));
+//! assert_eq!(map.get::<_, String>("name").as_deref(), Some(&"foo".to_string()));
+//!
+//! // looking up a value with the wrong type returns `None`
+//! assert_eq!(map.get::<_, u64>("count"), None);
+//!
+//! map.modify("count", |_, n: &u32| n + 1);
+//! assert_eq!(map.get::<_, u32>("count").as_deref(), Some(&6));
+//! ```
+//!
+//! [`AnyValueMap`]: ./struct.AnyValueMap.html
+//! [`AnyValue`]: ./type.AnyValue.html
+
+use crate::map::{Decision, DefaultHashBuilder, HashMap};
+
+use std::{
+    any::Any,
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+    sync::Arc,
+};
+
+/// A trait for viewing a value as a [`dyn Any`], which makes trait objects of
+/// its subtraits downcastable.
+///
+/// Declaring `AsAny` as a supertrait of a trait lets a map over boxed trait
+/// objects of that trait, such as a `HashMap<K, Box<dyn MyTrait>>`, look up
+/// and modify its values as their concrete types with its `get_as` and
+/// `modify_as` methods. `AsAny` is implemented for every sized type that
+/// implements [`Any`], so the implementers of the trait need nothing else.
+///
+/// [`dyn Any`]: https://doc.rust-lang.org/std/any/trait.Any.html
+/// [`Any`]: https://doc.rust-lang.org/std/any/trait.Any.html
+///
+/// # Examples
+///
+/// ```rust
+/// use moka_cht::{any_map::AsAny, HashMap};
+///
+/// trait Handler: AsAny + Send + Sync {
+///     fn handle(&self) -> u32;
+/// }
+///
+/// struct Counter(u32);
+///
+/// impl Handler for Counter {
+///     fn handle(&self) -> u32 {
+///         self.0
+///     }
+/// }
+///
+/// let map: HashMap<&str, Box<dyn Handler>> = HashMap::new();
+/// map.insert_and("counter", Box::new(Counter(1)), |_| ());
+///
+/// assert_eq!(map.get_as_and("counter", |c: &Counter| c.0), Some(1));
+/// assert!(map.modify_as("counter", |_, c: &Counter| Box::new(Counter(c.0 + 1))));
+/// assert_eq!(map.get_and("counter", |h| h.handle()), Some(2));
+///
+/// // a value of another type is left unchanged
+/// assert!(!map.modify_as("counter", |_, _: &String| unreachable!()));
+/// ```
+pub trait AsAny: Any {
+    /// Returns the value as a [`dyn Any`].
+    ///
+    /// [`dyn Any`]: https://doc.rust-lang.org/std/any/trait.Any.html
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A type-erased value stored in an [`AnyValueMap`].
+///
+/// [`AnyValueMap`]: ./struct.AnyValueMap.html
+pub type AnyValue = Arc<dyn Any + Send + Sync>;
+
+/// A lock-free hash map whose values may be of different types.
+///
+/// Values are stored as [`AnyValue`]s. Since they are reference counted,
+/// looking up a value never clones the value itself.
+///
+/// [`AnyValue`]: ./type.AnyValue.html
+pub struct AnyValueMap<K, S = DefaultHashBuilder> {
+    inner: HashMap<K, AnyValue, S>,
+}
+
+impl<K> AnyValueMap<K, DefaultHashBuilder> {
+    /// Creates an empty `AnyValueMap`.
+    pub fn new() -> Self {
+        Self {
+            inner: HashMap::new(),
+        }
+    }
+
+    /// Creates an empty `AnyValueMap` with the specified capacity.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            inner: HashMap::with_capacity(capacity),
+        }
+    }
+}
+
+impl<K, S> AnyValueMap<K, S> {
+    /// Creates an empty `AnyValueMap` that will use `build_hasher` to hash keys.
+    pub fn with_hasher(build_hasher: S) -> Self {
+        Self {
+            inner: HashMap::with_hasher(build_hasher),
+        }
+    }
+
+    /// Creates an empty `AnyValueMap` with the specified capacity that will use
+    /// `build_hasher` to hash keys.
+    pub fn with_capacity_and_hasher(capacity: usize, build_hasher: S) -> Self {
+        Self {
+            inner: HashMap::with_capacity_and_hasher(capacity, build_hasher),
+        }
+    }
+
+    /// Returns the number of elements in the map.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Returns `true` if the map contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Returns a reference to the underlying `HashMap`.
+    pub fn as_inner(&self) -> &HashMap<K, AnyValue, S> {
+        &self.inner
+    }
+}
+
+impl<K, S: Default> Default for AnyValueMap<K, S> {
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K: Hash + Eq, S: BuildHasher> AnyValueMap<K, S> {
+    /// Returns the value corresponding to the key if it is of type `T`.
+    ///
+    /// Returns `None` if the map does not contain the key or if the value is
+    /// of a different type.
+    pub fn get<Q: Hash + Eq + ?Sized, T: Any + Send + Sync>(&self, key: &Q) -> Option<Arc<T>>
+    where
+        K: Borrow<Q>,
+    {
+        self.inner
+            .get_and(key, |v| Arc::clone(v).downcast().ok())
+            .flatten()
+    }
+
+    /// Returns the type-erased value corresponding to the key.
+    pub fn get_any<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<AnyValue>
+    where
+        K: Borrow<Q>,
+    {
+        self.inner.get(key)
+    }
+
+    /// Returns `true` if the map contains a value of type `T` for the key.
+    pub fn contains<Q: Hash + Eq + ?Sized, T: Any + Send + Sync>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.inner.get_and(key, |v| v.is::<T>()).unwrap_or(false)
+    }
+
+    /// Returns `true` if the map contains a value of any type for the key.
+    pub fn contains_key<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.inner.get_and(key, |_| ()).is_some()
+    }
+
+    /// Inserts a key-value pair into the map, returning the type-erased value
+    /// previously corresponding to the key.
+    ///
+    /// The previous value may be of any type.
+    pub fn insert<T: Any + Send + Sync>(&self, key: K, value: T) -> Option<AnyValue> {
+        self.inner.insert(key, Arc::new(value))
+    }
+
+    /// Removes a key from the map, returning the type-erased value previously
+    /// corresponding to it.
+    pub fn remove<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<AnyValue>
+    where
+        K: Borrow<Q>,
+    {
+        self.inner.remove(key)
+    }
+
+    /// Modifies the value corresponding to a key if it is of type `T`,
+    /// returning the value previously corresponding to that key.
+    ///
+    /// If the value is of a different type, it is left unchanged, `on_modify`
+    /// is not invoked, and `None` is returned.
+    ///
+    /// `on_modify` may be invoked multiple times, even if `None` is returned.
+    pub fn modify<T: Any + Send + Sync, F: FnMut(&K, &T) -> T>(
+        &self,
+        key: K,
+        mut on_modify: F,
+    ) -> Option<Arc<T>> {
+        self.inner
+            .get_then_modify_and(
+                key,
+                |k, v| match v.downcast_ref() {
+                    Some(t) => Decision::Replace(Arc::new(on_modify(k, t))),
+                    None => Decision::Keep,
+                },
+                |_, v| Arc::clone(v).downcast().ok(),
+            )
+            .flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed_access() {
+        let map = AnyValueMap::new();
+
+        assert!(map.insert("foo", 5u32).is_none());
+        assert!(map.insert("bar", "baz".to_string()).is_none());
+        assert_eq!(map.len(), 2);
+
+        assert_eq!(map.get::<_, u32>("foo").as_deref(), Some(&5));
+        assert_eq!(map.get::<_, String>("foo"), None);
+        assert!(map.contains::<_, String>("bar"));
+        assert!(!map.contains::<_, u32>("bar"));
+        assert!(map.contains_key("bar"));
+
+        let previous = map.insert("foo", 1.5f64).unwrap();
+        assert_eq!(previous.downcast_ref::<u32>(), Some(&5));
+
+        let removed = map.remove("bar").unwrap();
+        assert_eq!(
+            removed.downcast_ref::<String>().map(String::as_str),
+            Some("baz")
+        );
+        assert!(!map.contains_key("bar"));
+
+        crate::test_util::run_deferred();
+    }
+
+    #[test]
+    fn modify_checks_type() {
+        let map = AnyValueMap::new();
+
+        map.insert("foo", 5u32);
+
+        // a mismatch leaves the bucket of the entry in place
+        let slot = |map: &AnyValueMap<_>| map.inner.get_and("foo", |v| v as *const AnyValue);
+        let before = slot(&map);
+        assert_eq!(
+            map.modify("foo", |_, _: &u64| unreachable!()),
+            None::<Arc<u64>>
+        );
+        assert_eq!(slot(&map), before);
+        assert_eq!(map.get::<_, u32>("foo").as_deref(), Some(&5));
+
+        assert_eq!(map.modify("foo", |_, n: &u32| n + 1).as_deref(), Some(&5));
+        assert_eq!(map.get::<_, u32>("foo").as_deref(), Some(&6));
+
+        assert_eq!(map.modify("bar", |_, n: &u32| n + 1), None);
+
+        crate::test_util::run_deferred();
+    }
+}
@@ -0,0 +1,430 @@
+//! A wrapper around [`HashMap`] with an API mirroring
+//! `std::collections::HashMap`.
+//!
+//! The [`compat::HashMap`][compat-hm] in this module exposes methods whose names
+//! and signatures follow [`std::collections::HashMap`][std-hm] as closely as a
+//! concurrent hash map allows, to minimize the changes needed when migrating
+//! existing single-threaded code to moka-cht. The differences are:
+//!
+//! - All methods take `&self`, including the mutating ones.
+//! - Methods that would return a reference into the map return a [`Ref`] instead.
+//!   A `Ref` pins the current thread to the current epoch, so it must not be held
+//!   for longer than necessary.
+//! - Methods that would move a value out of the map, such as [`insert`] and
+//!   [`remove`], return a clone of it instead.
+//! - [`entry`] returns an [`Entry`] that does not distinguish between occupied
+//!   and vacant entries, as that state could be changed at any time by another
+//!   thread.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use moka_cht::compat::HashMap;
+//!
+//! let map = HashMap::new();
+//!
+//! assert_eq!(map.insert("foo", 5), None);
+//! assert_eq!(map.get("foo").as_deref(), Some(&5));
+//!
+//! map.entry("bar").and_modify(|v| *v += 1).or_insert(0);
+//! assert_eq!(map.get("bar").as_deref(), Some(&0));
+//!
+//! map.entry("bar").and_modify(|v| *v += 1).or_insert(0);
+//! assert_eq!(map.get("bar").as_deref(), Some(&1));
+//!
+//! assert_eq!(map.remove("foo"), Some(5));
+//! assert!(!map.contains_key("foo"));
+//! ```
+//!
+//! [`HashMap`]: ../map/struct.HashMap.html
+//! [compat-hm]: ./struct.HashMap.html
+//! [std-hm]: https://doc.rust-lang.org/std/collections/struct.HashMap.html
+//! [`Ref`]: ../map/struct.Ref.html
+//! [`insert`]: ./struct.HashMap.html#method.insert
+//! [`remove`]: ./struct.HashMap.html#method.remove
+//! [`entry`]: ./struct.HashMap.html#method.entry
+//! [`Entry`]: ./struct.Entry.html
+
+pub use crate::map::Iter;
+
+use crate::map::{self, bucket, DefaultHashBuilder, Ref};
+
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+    iter::FromIterator,
+};
+
+/// A lock-free hash map with an API mirroring `std::collections::HashMap`.
+///
+/// See the [module-level documentation](./index.html) for how this type
+/// differs from the standard library hash map.
+#[derive(Default)]
+pub struct HashMap<K, V, S = DefaultHashBuilder> {
+    inner: map::HashMap<K, V, S>,
+}
+
+impl<K, V> HashMap<K, V, DefaultHashBuilder> {
+    /// Creates an empty `HashMap`.
+    ///
+    /// The hash map is initially created with a capacity of 0, so it will not
+    /// allocate a bucket pointer array until it is first inserted into.
+    pub fn new() -> Self {
+        Self::from(map::HashMap::new())
+    }
+
+    /// Creates an empty `HashMap` with the specified capacity.
+    ///
+    /// The hash map will be able to hold at least `capacity` elements without
+    /// reallocating its bucket pointer array. If `capacity` is 0, the hash map
+    /// will not allocate.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::from(map::HashMap::with_capacity(capacity))
+    }
+}
+
+impl<K, V, S> HashMap<K, V, S> {
+    /// Creates an empty `HashMap` which will use the given hash builder to hash
+    /// keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Self::from(map::HashMap::with_hasher(hash_builder))
+    }
+
+    /// Creates an empty `HashMap` with the specified capacity, using
+    /// `hash_builder` to hash the keys.
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+        Self::from(map::HashMap::with_capacity_and_hasher(
+            capacity,
+            hash_builder,
+        ))
+    }
+
+    /// Returns the number of elements the map can hold without reallocating its
+    /// bucket pointer array.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    /// Returns the number of elements in the map.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Returns `true` if the map contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Returns a reference to the map's [`BuildHasher`].
+    ///
+    /// [`BuildHasher`]: https://doc.rust-lang.org/std/hash/trait.BuildHasher.html
+    pub fn hasher(&self) -> &S {
+        self.inner.hasher()
+    }
+
+    /// Returns a reference to the wrapped [`moka_cht::HashMap`].
+    ///
+    /// [`moka_cht::HashMap`]: ../map/struct.HashMap.html
+    pub fn as_inner(&self) -> &map::HashMap<K, V, S> {
+        &self.inner
+    }
+
+    /// Unwraps this `HashMap`, returning the underlying [`moka_cht::HashMap`].
+    ///
+    /// [`moka_cht::HashMap`]: ../map/struct.HashMap.html
+    pub fn into_inner(self) -> map::HashMap<K, V, S> {
+        self.inner
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> HashMap<K, V, S> {
+    /// Returns a reference to the value corresponding to the key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn get<Q: Hash + Eq + ?Sized>(&self, k: &Q) -> Option<Ref<'_, K, V>>
+    where
+        K: Borrow<Q>,
+    {
+        self.get_key_value(k)
+    }
+
+    /// Returns a reference to the key-value pair corresponding to the supplied
+    /// key.
+    ///
+    /// The key and value can be accessed through [`Ref::key`] and
+    /// [`Ref::value`], or both at once through [`Ref::pair`].
+    ///
+    /// [`Ref::key`]: ../map/struct.Ref.html#method.key
+    /// [`Ref::value`]: ../map/struct.Ref.html#method.value
+    /// [`Ref::pair`]: ../map/struct.Ref.html#method.pair
+    pub fn get_key_value<Q: Hash + Eq + ?Sized>(&self, k: &Q) -> Option<Ref<'_, K, V>>
+    where
+        K: Borrow<Q>,
+    {
+        let guard = crossbeam_epoch::pin();
+        let bucket = self.inner.get_bucket(&guard, k)? as *const _;
+
+        Some(unsafe { Ref::new(guard, bucket) })
+    }
+
+    /// Returns `true` if the map contains a value for the specified key.
+    pub fn contains_key<Q: Hash + Eq + ?Sized>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.inner.get_and(k, |_| ()).is_some()
+    }
+
+    /// Inserts a key-value pair into the map, returning a clone of the value
+    /// previously corresponding to the key.
+    pub fn insert(&self, k: K, v: V) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.inner.insert(k, v)
+    }
+
+    /// Removes a key from the map, returning a clone of the value previously
+    /// corresponding to the key.
+    pub fn remove<Q: Hash + Eq + ?Sized>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        V: Clone,
+    {
+        self.inner.remove(k)
+    }
+
+    /// Removes a key from the map, returning a clone of the key-value pair
+    /// previously corresponding to the key.
+    pub fn remove_entry<Q: Hash + Eq + ?Sized>(&self, k: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q> + Clone,
+        V: Clone,
+    {
+        self.inner.remove_entry(k)
+    }
+
+    /// Gets the given key's corresponding entry in the map for in-place
+    /// manipulation.
+    pub fn entry(&self, key: K) -> Entry<'_, K, V, S> {
+        Entry { map: self, key }
+    }
+
+    /// An iterator visiting all key-value pairs in arbitrary order.
+    ///
+    /// The iterator is weakly consistent: it reflects the contents of the map
+    /// at some point at or after its creation, and it is not affected by
+    /// modifications made while it is being consumed.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let guard = crossbeam_epoch::pin();
+        let buckets = bucket::into_ptrs(self.inner.live_buckets(&guard));
+
+        unsafe { Iter::new(guard, buckets) }
+    }
+}
+
+impl<K, V, S> From<map::HashMap<K, V, S>> for HashMap<K, V, S> {
+    fn from(inner: map::HashMap<K, V, S>) -> Self {
+        Self { inner }
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> Extend<(K, V)> for HashMap<K, V, S> {
+    fn extend<T: IntoIterator<Item = (K, V)>>(&mut self, iter: T) {
+        for (k, v) in iter {
+            self.inner.insert_and(k, v, |_| ());
+        }
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Default> FromIterator<(K, V)> for HashMap<K, V, S> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        let mut map = Self::with_hasher(S::default());
+        map.extend(iter);
+
+        map
+    }
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> IntoIterator for &'a HashMap<K, V, S> {
+    type Item = Ref<'a, K, V>;
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// A view into a single entry in a map.
+///
+/// This `struct` is constructed from the [`entry`] method on [`HashMap`].
+///
+/// [`entry`]: ./struct.HashMap.html#method.entry
+/// [`HashMap`]: ./struct.HashMap.html
+pub struct Entry<'a, K, V, S> {
+    map: &'a HashMap<K, V, S>,
+    key: K,
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> Entry<'a, K, V, S> {
+    /// Returns a reference to this entry's key.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Ensures a value is in the entry by inserting the default if absent, and
+    /// returns a reference to the value in the entry.
+    pub fn or_insert(self, default: V) -> Ref<'a, K, V> {
+        self.or_insert_with(move || default)
+    }
+
+    /// Ensures a value is in the entry by inserting the result of the default
+    /// function if absent, and returns a reference to the value in the entry.
+    ///
+    /// `default` may be invoked even if another thread inserts a value for the
+    /// key first, in which case its result is dropped.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> Ref<'a, K, V> {
+        let guard = crossbeam_epoch::pin();
+        let bucket = self
+            .map
+            .inner
+            .get_or_insert_with_bucket(&guard, self.key, default) as *const _;
+
+        unsafe { Ref::new(guard, bucket) }
+    }
+
+    /// Ensures a value is in the entry by inserting the default value if
+    /// absent, and returns a reference to the value in the entry.
+    pub fn or_default(self) -> Ref<'a, K, V>
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Provides in-place mutable access to a clone of an occupied entry's value
+    /// before any potential inserts into the map. The modified clone replaces
+    /// the value in the map.
+    ///
+    /// `f` may be invoked more than once if the entry is concurrently modified.
+    pub fn and_modify<F: FnMut(&mut V)>(self, mut f: F) -> Self
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.map.inner.modify_and(
+            self.key.clone(),
+            |_, v| {
+                let mut v = v.clone();
+                f(&mut v);
+
+                v
+            },
+            |_| (),
+        );
+
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::run_deferred;
+
+    #[test]
+    fn get_insert_remove() {
+        let map = HashMap::new();
+
+        assert!(map.is_empty());
+        assert_eq!(map.insert("foo", 5), None);
+        assert_eq!(map.insert("foo", 6), Some(5));
+        assert_eq!(map.len(), 1);
+
+        assert!(map.contains_key("foo"));
+        assert_eq!(map.get("foo").as_deref(), Some(&6));
+        assert_eq!(map.get_key_value("foo").map(|r| *r.key()), Some("foo"));
+        assert!(map.get("bar").is_none());
+
+        assert_eq!(map.remove_entry("foo"), Some(("foo", 6)));
+        assert_eq!(map.remove("foo"), None);
+        assert!(map.is_empty());
+
+        run_deferred();
+    }
+
+    #[test]
+    fn entry() {
+        let map = HashMap::new();
+
+        assert_eq!(*map.entry("foo").or_insert(1), 1);
+        assert_eq!(*map.entry("foo").or_insert(2), 1);
+        assert_eq!(*map.entry("foo").and_modify(|v| *v += 10).or_insert(3), 11);
+        assert_eq!(*map.entry("bar").and_modify(|v| *v += 10).or_default(), 0);
+        assert_eq!(*map.entry("baz").or_insert_with(|| 7), 7);
+        assert_eq!(map.entry("qux").key(), &"qux");
+        assert_eq!(map.len(), 3);
+
+        run_deferred();
+    }
+
+    #[test]
+    fn iter() {
+        const MAX_VALUE: i32 = 512;
+
+        let map: HashMap<_, _> = (0..MAX_VALUE).map(|i| (i, i * 2)).collect();
+
+        for i in (0..MAX_VALUE).step_by(2) {
+            assert_eq!(map.remove(&i), Some(i * 2));
+        }
+
+        let mut pairs: Vec<_> = map.iter().map(|r| (*r.key(), *r.value())).collect();
+        pairs.sort_unstable();
+
+        let expected: Vec<_> = (1..MAX_VALUE).step_by(2).map(|i| (i, i * 2)).collect();
+        assert_eq!(pairs, expected);
+
+        let refs: Vec<_> = (&map).into_iter().collect();
+        assert_eq!(refs.len(), (MAX_VALUE / 2) as usize);
+
+        run_deferred();
+    }
+
+    #[test]
+    fn iter_refs_outlive_iterator() {
+        use crate::test_util::{DropNotifier, NoisyDropper};
+        use std::sync::Arc;
+
+        let parents: Vec<_> = (0..8).map(|_| Arc::new(DropNotifier::new())).collect();
+        let map: HashMap<_, _> = parents
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (i, NoisyDropper::new(Arc::clone(p), i)))
+            .collect();
+
+        // the references share the guard that the buckets were loaded under,
+        // which keeps the removed values alive after the iterator is dropped
+        let refs: Vec<_> = map.iter().collect();
+
+        for i in 0..parents.len() {
+            assert!(map.inner.remove_and(&i, |_| ()).is_some());
+        }
+
+        run_deferred();
+        assert!(parents.iter().all(|p| !p.was_dropped()));
+
+        let mut elems: Vec<_> = refs.iter().map(|r| r.value().elem).collect();
+        elems.sort_unstable();
+        assert_eq!(elems, (0..parents.len()).collect::<Vec<_>>());
+
+        drop(refs);
+        run_deferred();
+        assert!(parents.iter().all(|p| p.was_dropped()));
+    }
+}
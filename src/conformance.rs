@@ -0,0 +1,591 @@
+//! A test oracle that checks concurrent maps for linearizability.
+//!
+//! A [`Recorder`] records the operations that several threads perform on a
+//! map as a [`History`], in which every operation has the logical times at
+//! which it was invoked and at which it returned. [`History::check`] then
+//! searches, key by key, for an order of the operations that respects their
+//! real-time order and in which every operation returns what a sequential map
+//! would have returned. If there is none, the history is not linearizable and
+//! the operations on the offending key are returned as a [`Violation`].
+//!
+//! Linearizability is local, so the operations on each key can be checked on
+//! their own, which keeps the search small for histories with many keys. The
+//! search is still exponential in the number of operations on a key that
+//! overlap in time, so a history should spread its operations over a few keys
+//! per thread rather than hammering a single key.
+//!
+//! It is meant to validate changes to the bucket code of this crate, or of a
+//! fork of it, and can check other maps through the [`Target`] trait. This
+//! module is only available when the `conformance` feature is enabled.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use moka_cht::{conformance::Recorder, HashMap};
+//!
+//! use std::thread;
+//!
+//! let map = HashMap::new();
+//! let recorder = Recorder::new(&map);
+//!
+//! thread::scope(|s| {
+//!     for t in 0..4 {
+//!         let recorder = &recorder;
+//!
+//!         s.spawn(move || {
+//!             let mut session = recorder.session();
+//!
+//!             for i in 0..100 {
+//!                 let key = i % 4;
+//!                 session.insert(key, t * 100 + i);
+//!                 session.get(key);
+//!                 session.remove(key);
+//!             }
+//!         });
+//!     }
+//! });
+//!
+//! let history = recorder.into_history();
+//! assert_eq!(history.operations().len(), 1200);
+//! assert!(history.check().is_ok());
+//! ```
+//!
+//! [`Recorder`]: ./struct.Recorder.html
+//! [`History`]: ./struct.History.html
+//! [`History::check`]: ./struct.History.html#method.check
+//! [`Violation`]: ./struct.Violation.html
+//! [`Target`]: ./trait.Target.html
+
+use crate::{map::HashMap, ordering, segment::HashMap as SegmentedHashMap};
+
+use std::{
+    collections::{HashMap as StdHashMap, HashSet},
+    error,
+    fmt::{self, Display, Formatter},
+    hash::BuildHasher,
+    sync::{atomic::AtomicU64, Mutex},
+};
+
+/// A concurrent map whose operations a [`Recorder`] can record.
+///
+/// [`Recorder`]: ./struct.Recorder.html
+pub trait Target: Sync {
+    /// Looks up a key, returning its value.
+    fn get(&self, key: u64) -> Option<u64>;
+
+    /// Inserts a key-value pair, returning the previous value of the key.
+    fn insert(&self, key: u64, value: u64) -> Option<u64>;
+
+    /// Removes a key, returning its value.
+    fn remove(&self, key: u64) -> Option<u64>;
+}
+
+impl<S: BuildHasher + Sync> Target for HashMap<u64, u64, S> {
+    fn get(&self, key: u64) -> Option<u64> {
+        HashMap::get(self, &key)
+    }
+
+    fn insert(&self, key: u64, value: u64) -> Option<u64> {
+        HashMap::insert(self, key, value)
+    }
+
+    fn remove(&self, key: u64) -> Option<u64> {
+        HashMap::remove(self, &key)
+    }
+}
+
+impl<S: BuildHasher + Sync> Target for SegmentedHashMap<u64, u64, S> {
+    fn get(&self, key: u64) -> Option<u64> {
+        SegmentedHashMap::get(self, &key)
+    }
+
+    fn insert(&self, key: u64, value: u64) -> Option<u64> {
+        SegmentedHashMap::insert(self, key, value)
+    }
+
+    fn remove(&self, key: u64) -> Option<u64> {
+        SegmentedHashMap::remove(self, &key)
+    }
+}
+
+/// An operation on a single key together with what it returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OperationKind {
+    /// A lookup that returned `result`.
+    Get { key: u64, result: Option<u64> },
+    /// An insertion that returned the `previous` value of the key.
+    Insert {
+        key: u64,
+        value: u64,
+        previous: Option<u64>,
+    },
+    /// A removal that returned the `removed` value of the key.
+    Remove { key: u64, removed: Option<u64> },
+}
+
+impl OperationKind {
+    /// Returns the key of the operation.
+    pub fn key(&self) -> u64 {
+        match *self {
+            OperationKind::Get { key, .. }
+            | OperationKind::Insert { key, .. }
+            | OperationKind::Remove { key, .. } => key,
+        }
+    }
+
+    /// Applies the operation to the value of its key in a sequential map,
+    /// returning the new value, or `None` if the operation would have
+    /// returned something else.
+    fn apply(&self, value: Option<u64>) -> Option<Option<u64>> {
+        match *self {
+            OperationKind::Get { result, .. } if result == value => Some(value),
+            OperationKind::Insert {
+                value: new_value,
+                previous,
+                ..
+            } if previous == value => Some(Some(new_value)),
+            OperationKind::Remove { removed, .. } if removed == value => Some(None),
+            _ => None,
+        }
+    }
+}
+
+/// An operation of a [`History`].
+///
+/// The logical times are drawn from a counter shared by all threads of the
+/// recording, so an operation precedes another in real time if and only if it
+/// returned before the other was invoked.
+///
+/// [`History`]: ./struct.History.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Operation {
+    kind: OperationKind,
+    invoked: u64,
+    returned: u64,
+}
+
+impl Operation {
+    /// Returns the operation and what it returned.
+    pub fn kind(&self) -> &OperationKind {
+        &self.kind
+    }
+
+    /// Returns the logical time at which the operation was invoked.
+    pub fn invoked(&self) -> u64 {
+        self.invoked
+    }
+
+    /// Returns the logical time at which the operation returned.
+    pub fn returned(&self) -> u64 {
+        self.returned
+    }
+}
+
+/// Records the operations performed on a [`Target`] by several threads.
+///
+/// Each thread records through its own [`Session`], which buffers its
+/// operations so that the threads only synchronize on the logical clock while
+/// they run.
+///
+/// [`Target`]: ./trait.Target.html
+/// [`Session`]: ./struct.Session.html
+pub struct Recorder<'a, T: ?Sized> {
+    target: &'a T,
+    clock: AtomicU64,
+    operations: Mutex<Vec<Operation>>,
+}
+
+impl<'a, T: Target + ?Sized> Recorder<'a, T> {
+    /// Creates a recorder of the operations performed on `target`, which
+    /// should be empty, since the history is checked against a map whose keys
+    /// are all absent at first.
+    pub fn new(target: &'a T) -> Self {
+        Self {
+            target,
+            clock: AtomicU64::new(0),
+            operations: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Creates a session through which the current thread records its
+    /// operations. They are added to the history when the session is dropped.
+    pub fn session(&self) -> Session<'_, 'a, T> {
+        Session {
+            recorder: self,
+            operations: Vec::new(),
+        }
+    }
+
+    /// Returns the history of the operations recorded by the sessions.
+    pub fn into_history(self) -> History {
+        let mut operations = self
+            .operations
+            .into_inner()
+            .unwrap_or_else(|e| e.into_inner());
+        operations.sort_unstable_by_key(Operation::invoked);
+
+        History { operations }
+    }
+
+    // the ticks are a release sequence, so an operation that returned before
+    // another was invoked happens before it.
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, ordering::ACQ_REL)
+    }
+}
+
+/// Records the operations of a thread, created by [`Recorder::session`].
+///
+/// [`Recorder::session`]: ./struct.Recorder.html#method.session
+pub struct Session<'r, 'a, T: ?Sized> {
+    recorder: &'r Recorder<'a, T>,
+    operations: Vec<Operation>,
+}
+
+impl<'r, 'a, T: Target + ?Sized> Session<'r, 'a, T> {
+    /// Looks up a key and records the lookup.
+    pub fn get(&mut self, key: u64) -> Option<u64> {
+        self.record(|target| {
+            let result = target.get(key);
+
+            (OperationKind::Get { key, result }, result)
+        })
+    }
+
+    /// Inserts a key-value pair and records the insertion.
+    pub fn insert(&mut self, key: u64, value: u64) -> Option<u64> {
+        self.record(|target| {
+            let previous = target.insert(key, value);
+
+            (
+                OperationKind::Insert {
+                    key,
+                    value,
+                    previous,
+                },
+                previous,
+            )
+        })
+    }
+
+    /// Removes a key and records the removal.
+    pub fn remove(&mut self, key: u64) -> Option<u64> {
+        self.record(|target| {
+            let removed = target.remove(key);
+
+            (OperationKind::Remove { key, removed }, removed)
+        })
+    }
+
+    /// Performs an operation with `f` and records it as the operation that
+    /// `f` returns, which lets other methods of the target be checked as long
+    /// as their effect is one of an [`OperationKind`].
+    ///
+    /// [`OperationKind`]: ./enum.OperationKind.html
+    pub fn record<F: FnOnce(&T) -> (OperationKind, R), R>(&mut self, f: F) -> R {
+        let invoked = self.recorder.tick();
+        let (kind, result) = f(self.recorder.target);
+        let returned = self.recorder.tick();
+
+        self.operations.push(Operation {
+            kind,
+            invoked,
+            returned,
+        });
+
+        result
+    }
+}
+
+impl<'r, 'a, T: ?Sized> Drop for Session<'r, 'a, T> {
+    fn drop(&mut self) {
+        self.recorder
+            .operations
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .append(&mut self.operations);
+    }
+}
+
+/// The operations recorded by a [`Recorder`], ordered by the times at which
+/// they were invoked.
+///
+/// [`Recorder`]: ./struct.Recorder.html
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct History {
+    operations: Vec<Operation>,
+}
+
+impl History {
+    /// Returns the operations of the history.
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    /// Checks that the history is linearizable, starting from a map whose
+    /// keys are all absent.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`Violation`] holding the operations on the first key, in
+    /// ascending order, whose operations cannot be linearized.
+    ///
+    /// [`Violation`]: ./struct.Violation.html
+    pub fn check(&self) -> Result<(), Violation> {
+        let mut keys = StdHashMap::<_, Vec<_>>::new();
+
+        for operation in &self.operations {
+            keys.entry(operation.kind.key())
+                .or_default()
+                .push(*operation);
+        }
+
+        let mut keys: Vec<_> = keys.into_iter().collect();
+        keys.sort_unstable_by_key(|&(key, _)| key);
+
+        for (key, operations) in keys {
+            if !is_linearizable(&operations) {
+                return Err(Violation { key, operations });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The operations on a key of a [`History`] that cannot be linearized.
+///
+/// [`History`]: ./struct.History.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    key: u64,
+    operations: Vec<Operation>,
+}
+
+impl Violation {
+    /// Returns the key.
+    pub fn key(&self) -> u64 {
+        self.key
+    }
+
+    /// Returns the operations on the key, ordered by the times at which they
+    /// were invoked.
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the {} operations on key {} are not linearizable",
+            self.operations.len(),
+            self.key
+        )
+    }
+}
+
+impl error::Error for Violation {}
+
+/// A set of operations of a key, by their indices.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct OperationSet(Vec<u64>);
+
+impl OperationSet {
+    fn new(len: usize) -> Self {
+        Self(vec![0; (len + 63) / 64])
+    }
+
+    fn contains(&self, index: usize) -> bool {
+        self.0[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    fn insert(&mut self, index: usize) {
+        self.0[index / 64] |= 1 << (index % 64);
+    }
+}
+
+/// A step of the search: the operations linearized so far, the value of the
+/// key after them, and the operations that may be linearized next.
+struct Frame {
+    linearized: OperationSet,
+    value: Option<u64>,
+    candidates: Vec<usize>,
+}
+
+/// Searches for a linearization of the operations on a key, which are ordered
+/// by the times at which they were invoked, with the algorithm of Wing and
+/// Gong, memoizing the visited states as proposed by Lowe.
+fn is_linearizable(operations: &[Operation]) -> bool {
+    let candidates = |linearized: &OperationSet| {
+        // an operation may come next only if no pending operation returned
+        // before it was invoked.
+        let first_return = (0..operations.len())
+            .filter(|&i| !linearized.contains(i))
+            .map(|i| operations[i].returned)
+            .min()
+            .unwrap_or(u64::MAX);
+
+        (0..operations.len())
+            .take_while(|&i| operations[i].invoked < first_return)
+            .filter(|&i| !linearized.contains(i))
+            .collect()
+    };
+
+    let linearized = OperationSet::new(operations.len());
+    let mut visited = HashSet::new();
+    let mut stack = vec![Frame {
+        candidates: candidates(&linearized),
+        linearized,
+        value: None,
+    }];
+
+    // every frame but the first linearizes one more operation
+    while stack.len() <= operations.len() {
+        let frame = match stack.last_mut() {
+            Some(frame) => frame,
+            None => return false,
+        };
+
+        let index = match frame.candidates.pop() {
+            Some(index) => index,
+            None => {
+                stack.pop();
+
+                continue;
+            }
+        };
+
+        let value = match operations[index].kind.apply(frame.value) {
+            Some(value) => value,
+            None => continue,
+        };
+
+        let mut linearized = frame.linearized.clone();
+        linearized.insert(index);
+
+        if visited.insert((linearized.clone(), value)) {
+            stack.push(Frame {
+                candidates: candidates(&linearized),
+                linearized,
+                value,
+            });
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    fn operation(kind: OperationKind, invoked: u64, returned: u64) -> Operation {
+        Operation {
+            kind,
+            invoked,
+            returned,
+        }
+    }
+
+    fn new_history(mut operations: Vec<Operation>) -> History {
+        operations.sort_unstable_by_key(Operation::invoked);
+
+        History { operations }
+    }
+
+    #[test]
+    fn check() {
+        let insert = |value, previous| OperationKind::Insert {
+            key: 0,
+            value,
+            previous,
+        };
+        let get = |result| OperationKind::Get { key: 0, result };
+
+        // the lookup overlaps the insertion, so it may see either value
+        for result in [None, Some(1)] {
+            let history = new_history(vec![
+                operation(insert(1, None), 0, 3),
+                operation(get(result), 1, 2),
+            ]);
+            assert!(history.check().is_ok());
+        }
+
+        // the lookup starts after the insertion returned
+        let history = new_history(vec![
+            operation(insert(1, None), 0, 1),
+            operation(get(None), 2, 3),
+        ]);
+        let violation = history.check().unwrap_err();
+        assert_eq!(violation.key(), 0);
+        assert_eq!(violation.operations(), history.operations());
+
+        // both insertions cannot have seen an absent key
+        let history = new_history(vec![
+            operation(insert(1, None), 0, 2),
+            operation(insert(2, None), 1, 3),
+            operation(
+                OperationKind::Get {
+                    key: 1,
+                    result: None,
+                },
+                4,
+                5,
+            ),
+        ]);
+        assert_eq!(history.check().unwrap_err().key(), 0);
+
+        // a lost update: the removal returned a value that was overwritten
+        let history = new_history(vec![
+            operation(insert(1, None), 0, 1),
+            operation(insert(2, Some(1)), 2, 3),
+            operation(
+                OperationKind::Remove {
+                    key: 0,
+                    removed: Some(1),
+                },
+                4,
+                5,
+            ),
+        ]);
+        assert!(history.check().is_err());
+    }
+
+    #[test]
+    fn maps_are_linearizable() {
+        fn run<T: Target>(map: &T) {
+            let recorder = Recorder::new(map);
+
+            thread::scope(|s| {
+                for t in 0..4 {
+                    let recorder = &recorder;
+
+                    s.spawn(move || {
+                        let mut session = recorder.session();
+
+                        for i in 0..500 {
+                            let key = i % 8;
+                            session.insert(key, t * 1000 + i);
+                            session.get(key);
+
+                            if i % 3 == 2 {
+                                session.remove(key);
+                            }
+                        }
+                    });
+                }
+            });
+
+            let history = recorder.into_history();
+            assert_eq!(history.operations().len(), 4 * (500 + 500 + 166));
+            history.check().unwrap();
+        }
+
+        run(&HashMap::with_capacity(4));
+        run(&SegmentedHashMap::with_num_segments(4));
+    }
+}
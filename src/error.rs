@@ -0,0 +1,82 @@
+use std::fmt::{self, Display, Formatter};
+
+/// The error type for the fallible (`try_`) operations of the hash maps in this
+/// crate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// A map was requested to be created with zero segments.
+    ZeroSegments,
+    /// A segment index was not less than the number of segments.
+    SegmentIndexOutOfBounds {
+        /// The requested index.
+        index: usize,
+        /// The number of segments of the map.
+        num_segments: usize,
+    },
+    /// The requested capacity or number of segments exceeds the maximum that
+    /// can be allocated.
+    CapacityOverflow,
+    /// The entries passed to a bulk load were not sorted by hash.
+    UnsortedEntries,
+    /// A budgeted read ran out of its [`ReadBudget`] before finding its
+    /// answer, usually because it ran into a resize of the map.
+    ///
+    /// [`ReadBudget`]: map/struct.ReadBudget.html
+    Busy,
+    /// An insertion would have exceeded the limit of the [`MemoryBudget`] of
+    /// the map.
+    ///
+    /// [`MemoryBudget`]: map/struct.MemoryBudget.html
+    MemoryBudgetExceeded,
+    /// An incremental [`Delta`] was applied to a [`Replica`] at another position
+    /// than the one the delta was computed from.
+    ///
+    /// [`Delta`]: replica/struct.Delta.html
+    /// [`Replica`]: replica/struct.Replica.html
+    DeltaGap {
+        /// The position of the replica.
+        position: u64,
+        /// The position the delta was computed from.
+        base: u64,
+    },
+    /// A region of memory was too short or misaligned for a [`SharedHashMap`],
+    /// or did not hold a map with the expected layout.
+    ///
+    /// [`SharedHashMap`]: shared_memory/struct.SharedHashMap.html
+    InvalidSharedRegion,
+    /// An insertion into a [`SharedHashMap`] found all the slots of its key
+    /// taken by other keys.
+    ///
+    /// [`SharedHashMap`]: shared_memory/struct.SharedHashMap.html
+    SharedMapFull,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ZeroSegments => write!(f, "the number of segments must be greater than zero"),
+            Error::SegmentIndexOutOfBounds {
+                index,
+                num_segments,
+            } => write!(
+                f,
+                "segment index {} is out of bounds for a map with {} segments",
+                index, num_segments
+            ),
+            Error::CapacityOverflow => write!(f, "capacity overflow"),
+            Error::UnsortedEntries => write!(f, "the entries are not sorted by hash"),
+            Error::Busy => write!(f, "the read budget was exhausted"),
+            Error::MemoryBudgetExceeded => write!(f, "the memory budget was exceeded"),
+            Error::DeltaGap { position, base } => write!(
+                f,
+                "a delta computed from position {} cannot be applied at position {}",
+                base, position
+            ),
+            Error::InvalidSharedRegion => write!(f, "the shared memory region is invalid"),
+            Error::SharedMapFull => write!(f, "the shared hash map is full"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
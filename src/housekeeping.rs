@@ -0,0 +1,220 @@
+//! Periodic maintenance of the hash maps in this crate.
+//!
+//! The hash maps only grow on their own: removing entries leaves tombstones
+//! behind, and the bucket pointer arrays are never shrunk. Embedders that
+//! remove many entries should call `run_housekeeping` periodically, for
+//! example from a background task. Each call performs the following steps for
+//! as many segments as its [`Budget`] allows:
+//!
+//! 1. Shrinks the bucket pointer array if it is sparsely populated.
+//! 2. Otherwise, reclaims the tombstones if they take up a large part of the
+//!    array, as set by the [`TombstonePolicy`] of the map.
+//! 3. Flushes the garbage of the current thread to the global epoch-based
+//!    garbage collector, so that memory freed by the previous steps can be
+//!    reclaimed.
+//!
+//! Housekeeping is resumable: a call that runs out of budget returns before
+//! visiting every segment of a `SegmentedHashMap`, and the next call continues
+//! from the segment where it stopped.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use moka_cht::{housekeeping::Budget, SegmentedHashMap};
+//!
+//! let map = SegmentedHashMap::with_num_segments(4);
+//!
+//! for i in 0..4096 {
+//!     map.insert(i, i);
+//! }
+//!
+//! for i in 0..4096 {
+//!     map.remove(&i);
+//! }
+//!
+//! // call periodically
+//! while !map.run_housekeeping(Budget::new(1024)).is_pass_complete() {}
+//!
+//! assert!(map.capacity() < 4096);
+//! ```
+//!
+//! # Clearing in Batches
+//!
+//! Clearing a large map at once stalls the calling thread for as long as it
+//! takes to drop every entry. The `clear_in_batches` methods instead remove up
+//! to as many entries as a [`Budget`] allows per call, and resume from where
+//! the previous call stopped, so that a map can be cleared from the same
+//! periodic task. They return a [`ClearProgress`], which tells when the clear
+//! is complete. The removals leave tombstones behind, which the next pass of
+//! housekeeping reclaims, shrinking the arrays.
+//!
+//! The map stays usable while it is being cleared, and the entries are
+//! removed in an order that does not change when the map is resized: the
+//! entries that are in the map for the whole clear are removed, while those
+//! inserted during the clear may or may not be.
+//!
+//! ```rust
+//! use moka_cht::{housekeeping::Budget, HashMap};
+//!
+//! let map = HashMap::new();
+//!
+//! for i in 0..4096 {
+//!     map.insert(i, i);
+//! }
+//!
+//! // call periodically
+//! while !map.clear_in_batches(Budget::new(1024)).is_complete() {}
+//!
+//! assert!(map.is_empty());
+//! ```
+//!
+//! [`Budget`]: ./struct.Budget.html
+//! [`ClearProgress`]: ./struct.ClearProgress.html
+//! [`TombstonePolicy`]: ../map/enum.TombstonePolicy.html
+
+use crate::{map::bucket::RehashOp, ordering};
+
+use std::sync::{atomic::AtomicBool, Arc};
+
+/// A limit on the amount of work a single housekeeping call may perform.
+///
+/// The budget is measured in buckets visited, and is checked before moving on
+/// to the next segment. At least one segment is processed per call, even if
+/// that exceeds the budget.
+///
+/// When clearing a map in batches, the budget is the number of entries to
+/// remove, which may be exceeded by the few entries that share a slot of a
+/// bucket pointer array with the last one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Budget {
+    max_buckets: usize,
+}
+
+impl Budget {
+    /// Creates a budget of `max_buckets` bucket visits.
+    pub fn new(max_buckets: usize) -> Self {
+        Self { max_buckets }
+    }
+
+    /// Creates a budget that allows a complete pass over all segments.
+    pub fn unlimited() -> Self {
+        Self::new(usize::MAX)
+    }
+
+    pub(crate) fn max_buckets(&self) -> usize {
+        self.max_buckets
+    }
+}
+
+/// The outcome of a housekeeping call.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HousekeepingReport {
+    pub(crate) num_segments_visited: usize,
+    pub(crate) num_buckets_visited: usize,
+    pub(crate) num_shrunk: usize,
+    pub(crate) num_compacted: usize,
+    pub(crate) is_pass_complete: bool,
+}
+
+impl HousekeepingReport {
+    /// Returns the number of segments that were processed.
+    pub fn num_segments_visited(&self) -> usize {
+        self.num_segments_visited
+    }
+
+    /// Returns the number of buckets that were visited.
+    pub fn num_buckets_visited(&self) -> usize {
+        self.num_buckets_visited
+    }
+
+    /// Returns the number of segments whose bucket pointer arrays were shrunk.
+    pub fn num_shrunk(&self) -> usize {
+        self.num_shrunk
+    }
+
+    /// Returns the number of segments whose tombstones were reclaimed without
+    /// shrinking.
+    pub fn num_compacted(&self) -> usize {
+        self.num_compacted
+    }
+
+    /// Returns `true` if this call processed the last segment of the map, so
+    /// that the next call starts a new pass from the first segment.
+    pub fn is_pass_complete(&self) -> bool {
+        self.is_pass_complete
+    }
+
+    pub(crate) fn record_segment(&mut self, num_buckets_visited: usize, op: Option<RehashOp>) {
+        self.num_segments_visited += 1;
+        self.num_buckets_visited += num_buckets_visited;
+
+        match op {
+            Some(RehashOp::Shrink) => self.num_shrunk += 1,
+            Some(RehashOp::GcOnly) => self.num_compacted += 1,
+            Some(RehashOp::Expand) | Some(RehashOp::Reserve(_)) | None => (),
+        }
+    }
+
+    pub(crate) fn is_within(&self, budget: Budget) -> bool {
+        self.num_buckets_visited < budget.max_buckets
+    }
+}
+
+/// The outcome of a call to clear a map in batches.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClearProgress {
+    pub(crate) num_removed: usize,
+    pub(crate) is_complete: bool,
+}
+
+impl ClearProgress {
+    /// Returns the number of entries that were removed.
+    pub fn num_removed(&self) -> usize {
+        self.num_removed
+    }
+
+    /// Returns `true` if this call removed the last entries of the clear, so
+    /// that the next call starts a new one.
+    pub fn is_complete(&self) -> bool {
+        self.is_complete
+    }
+}
+
+/// Flushes the garbage of the current thread to the global garbage collector.
+pub(crate) fn flush_garbage() {
+    crossbeam_epoch::pin().flush();
+}
+
+// the most flushes a reclamation performs: garbage is destroyed once the epoch
+// has advanced twice past the one it was deferred in, and each flush advances
+// the epoch at most once
+const MAX_FLUSHES: usize = 3;
+
+/// Flushes the garbage of the current thread and advances the global epoch as
+/// far as the other pinned threads allow, until the garbage deferred so far by
+/// the current thread is destroyed, or at most `MAX_FLUSHES` times.
+///
+/// This is only a best-effort approximation, as crossbeam-epoch does not
+/// report how much garbage a flush destroys. A marker is deferred after the
+/// garbage, and the flushes stop once the marker is destroyed, which happens
+/// after the garbage deferred before it in practice. Garbage may outlive the
+/// call, for example if another thread is pinned to an older epoch or if
+/// there is more of it than a few flushes destroy, and is then destroyed by
+/// later flushes.
+pub(crate) fn reclaim_garbage() {
+    let is_destroyed = Arc::new(AtomicBool::new(false));
+
+    {
+        let is_destroyed = Arc::clone(&is_destroyed);
+
+        crossbeam_epoch::pin().defer(move || is_destroyed.store(true, ordering::RELEASE));
+    }
+
+    for _ in 0..MAX_FLUSHES {
+        flush_garbage();
+
+        if is_destroyed.load(ordering::ACQUIRE) {
+            break;
+        }
+    }
+}
@@ -0,0 +1,442 @@
+//! A hash map with a secondary index over its entries.
+//!
+//! An [`IndexedHashMap`] computes an index key for each of its entries with a
+//! function of the key and the value, and keeps the set of keys of each index
+//! key in a companion map, so that the entries with a given index key can be
+//! found without scanning the whole map.
+//!
+//! The index is updated alongside the mutations of the map, not atomically with
+//! them. Each key holds a count in the set of the index key of every value
+//! that is being written for it or is in the map, which is added before the
+//! value is written and subtracted once the value is replaced or removed.
+//! Concurrent writes to a key may therefore leave it in the sets of several
+//! index keys for a while, but never in none of the sets that its value
+//! belongs to, and once the writes are done, each key is only in the set of
+//! the index key of its value. Lookups check the index key of every entry that
+//! they find against the one they look for, so they never report an entry that
+//! does not belong to it.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use moka_cht::indexed_map::IndexedHashMap;
+//!
+//! // users by their ID, indexed by their country
+//! let users = IndexedHashMap::new(|_: &u32, &(_, country): &(&str, &'static str)| country);
+//!
+//! users.insert(1, ("alice", "fr"));
+//! users.insert(2, ("bob", "de"));
+//! users.insert(3, ("carol", "fr"));
+//!
+//! let mut french = users.find_keys("fr");
+//! french.sort_unstable();
+//! assert_eq!(french, [1, 3]);
+//!
+//! // moving a user updates the index
+//! users.insert(3, ("carol", "de"));
+//! assert_eq!(users.find_keys("fr"), [1]);
+//! assert_eq!(users.find("de").len(), 2);
+//! ```
+//!
+//! [`IndexedHashMap`]: ./struct.IndexedHashMap.html
+
+use crate::map::{DefaultHashBuilder, HashMap};
+
+use std::{
+    borrow::Borrow,
+    fmt::{self, Debug, Formatter},
+    hash::{BuildHasher, Hash},
+    sync::Arc,
+};
+
+// the keys of an index key, each with the number of values in or being
+// written to the map that have that index key
+type KeySet<K> = HashMap<K, usize>;
+
+/// A lock-free hash map that maintains a secondary index over its entries.
+///
+/// The index key of an entry is computed by `index_fn`, which must return the
+/// same index key every time it is invoked with the same key and value. The
+/// index is only maintained for the mutations made through the
+/// `IndexedHashMap`; see the [module documentation] for its consistency.
+///
+/// Index keys whose entries are all removed or moved to other index keys keep
+/// an empty set in the index until [`prune_index`] is called.
+///
+/// [module documentation]: ./index.html
+/// [`prune_index`]: #method.prune_index
+pub struct IndexedHashMap<K, V, I, F, S = DefaultHashBuilder> {
+    inner: HashMap<K, V, S>,
+    index: HashMap<I, Arc<KeySet<K>>>,
+    index_fn: F,
+}
+
+impl<K, V, I, F> IndexedHashMap<K, V, I, F, DefaultHashBuilder> {
+    /// Creates an empty `IndexedHashMap` that indexes its entries with
+    /// `index_fn`.
+    pub fn new(index_fn: F) -> Self {
+        Self::from_empty(HashMap::new(), index_fn)
+    }
+
+    /// Creates an empty `IndexedHashMap` with the specified capacity that
+    /// indexes its entries with `index_fn`.
+    pub fn with_capacity(capacity: usize, index_fn: F) -> Self {
+        Self::from_empty(HashMap::with_capacity(capacity), index_fn)
+    }
+}
+
+impl<K, V, I, F, S> IndexedHashMap<K, V, I, F, S> {
+    /// Creates an empty `IndexedHashMap` that will use `build_hasher` to hash
+    /// keys and indexes its entries with `index_fn`.
+    pub fn with_hasher(build_hasher: S, index_fn: F) -> Self {
+        Self::from_empty(HashMap::with_hasher(build_hasher), index_fn)
+    }
+
+    /// Returns the number of elements in the map.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Returns `true` if the map contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Returns the number of index keys in the index, including the ones
+    /// whose sets are empty.
+    pub fn index_len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Returns a reference to the underlying `HashMap`.
+    ///
+    /// The mutations made through the returned reference are not reflected in
+    /// the index.
+    pub fn as_inner(&self) -> &HashMap<K, V, S> {
+        &self.inner
+    }
+
+    fn from_empty(inner: HashMap<K, V, S>, index_fn: F) -> Self {
+        Self {
+            inner,
+            index: HashMap::new(),
+            index_fn,
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V, I: Hash + Eq, F: Fn(&K, &V) -> I, S: BuildHasher>
+    IndexedHashMap<K, V, I, F, S>
+{
+    /// Creates an `IndexedHashMap` from a map, indexing its entries with
+    /// `index_fn`.
+    ///
+    /// This allows the map to be configured with its builder first. The map
+    /// must not be mutated concurrently while its entries are being indexed.
+    pub fn from_map(inner: HashMap<K, V, S>, index_fn: F) -> Self {
+        let map = Self::from_empty(inner, index_fn);
+
+        map.inner
+            .for_each(|k, v| map.add_to_index((map.index_fn)(k, v), k));
+
+        map
+    }
+
+    /// Returns a clone of the value corresponding to the key.
+    pub fn get<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        V: Clone,
+    {
+        self.inner.get(key)
+    }
+
+    /// Returns the result of invoking a function with a reference to the
+    /// value corresponding to the key.
+    pub fn get_and<Q: Hash + Eq + ?Sized, G: FnOnce(&V) -> T, T>(
+        &self,
+        key: &Q,
+        with_value: G,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+    {
+        self.inner.get_and(key, with_value)
+    }
+
+    /// Returns `true` if the map contains a value for the key.
+    pub fn contains_key<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.inner.get_and(key, |_| ()).is_some()
+    }
+
+    /// Inserts a key-value pair into the map, returning a clone of the value
+    /// previously corresponding to the key, and moves the key to the index key
+    /// of the new value.
+    pub fn insert(&self, key: K, value: V) -> Option<V>
+    where
+        V: Clone,
+    {
+        let index_key = (self.index_fn)(&key, &value);
+        self.add_to_index(index_key, &key);
+
+        self.inner.insert_entry_and(key, value, |k, previous| {
+            self.remove_from_index(&(self.index_fn)(k, previous), k);
+
+            previous.clone()
+        })
+    }
+
+    /// Removes a key from the map, returning a clone of the value previously
+    /// corresponding to the key, and removes the key from the index.
+    pub fn remove<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        V: Clone,
+    {
+        self.inner.remove_entry_and(key, |k, previous| {
+            self.remove_from_index(&(self.index_fn)(k, previous), k);
+
+            previous.clone()
+        })
+    }
+
+    /// Invokes `with_entry` with a reference to the key and value of every
+    /// entry whose index key is `index_key`, in arbitrary order, and returns
+    /// the number of entries visited.
+    ///
+    /// Only the keys in the set of `index_key` are looked up, and the ones
+    /// whose current values have another index key are skipped. Like the
+    /// traversals of `HashMap`, this is weakly consistent: the entries that
+    /// are inserted, modified or removed concurrently may or may not be
+    /// visited.
+    pub fn find_and<Q: Hash + Eq + ?Sized, G: FnMut(&K, &V)>(
+        &self,
+        index_key: &Q,
+        mut with_entry: G,
+    ) -> usize
+    where
+        I: Borrow<Q>,
+    {
+        let keys = match self.index.get_and(index_key, Arc::clone) {
+            Some(keys) => keys,
+            None => return 0,
+        };
+        let mut found = 0;
+
+        keys.for_each(|key, _| {
+            self.inner.get_key_value_and(key, |k, v| {
+                if (self.index_fn)(k, v).borrow() == index_key {
+                    with_entry(k, v);
+                    found += 1;
+                }
+            });
+        });
+
+        found
+    }
+
+    /// Returns clones of the key-value pairs whose index key is `index_key`,
+    /// in arbitrary order.
+    ///
+    /// See [`find_and`] for details.
+    ///
+    /// [`find_and`]: #method.find_and
+    pub fn find<Q: Hash + Eq + ?Sized>(&self, index_key: &Q) -> Vec<(K, V)>
+    where
+        I: Borrow<Q>,
+        V: Clone,
+    {
+        let mut entries = Vec::new();
+        self.find_and(index_key, |k, v| entries.push((k.clone(), v.clone())));
+
+        entries
+    }
+
+    /// Returns clones of the keys of the entries whose index key is
+    /// `index_key`, in arbitrary order.
+    ///
+    /// See [`find_and`] for details.
+    ///
+    /// [`find_and`]: #method.find_and
+    pub fn find_keys<Q: Hash + Eq + ?Sized>(&self, index_key: &Q) -> Vec<K>
+    where
+        I: Borrow<Q>,
+    {
+        let mut keys = Vec::new();
+        self.find_and(index_key, |k, _| keys.push(k.clone()));
+
+        keys
+    }
+
+    /// Removes the index keys whose sets are empty from the index, returning
+    /// the number of index keys removed.
+    ///
+    /// Borrowing the map mutably proves that no key is being added to the sets
+    /// while they are checked.
+    pub fn prune_index(&mut self) -> usize {
+        self.index.retain(|_, keys| !keys.is_empty())
+    }
+
+    fn add_to_index(&self, index_key: I, key: &K) {
+        let keys = match self.index.get_and(&index_key, Arc::clone) {
+            Some(keys) => keys,
+            None => Arc::clone(&self.index.upsert_and_get(
+                index_key,
+                || Arc::new(HashMap::new()),
+                |_, keys| Arc::clone(keys),
+            )),
+        };
+
+        keys.insert_or_modify(key.clone(), 1, |_, &count| count + 1);
+    }
+
+    fn remove_from_index(&self, index_key: &I, key: &K) {
+        let keys = match self.index.get_and(index_key, Arc::clone) {
+            Some(keys) => keys,
+            None => return,
+        };
+
+        // the count of the key may be added to again before it is removed
+        keys.modify_and(key.clone(), |_, &count| count.saturating_sub(1), |_| ());
+        keys.remove_if(key, |_, &count| count == 0);
+    }
+}
+
+impl<K, V, I, F, S> Debug for IndexedHashMap<K, V, I, F, S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IndexedHashMap")
+            .field("len", &self.len())
+            .field("index_len", &self.index_len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        sync::{Arc, Barrier},
+        thread,
+    };
+
+    #[test]
+    fn find() {
+        let map = IndexedHashMap::new(|_: &u32, v: &u32| v % 3);
+        assert!(map.find(&0).is_empty());
+
+        for i in 0..30 {
+            assert_eq!(map.insert(i, i), None);
+        }
+
+        let mut keys = map.find_keys(&1);
+        keys.sort_unstable();
+        assert_eq!(keys, (1..30).step_by(3).collect::<Vec<_>>());
+        assert_eq!(map.index_len(), 3);
+
+        // replacing a value moves its key to the index key of the new value
+        assert_eq!(map.insert(1, 2), Some(1));
+        assert_eq!(map.find_and(&1, |_, _| ()), 9);
+        assert!(map.find(&2).contains(&(1, 2)));
+
+        assert_eq!(map.remove(&1), Some(2));
+        assert!(!map.find_keys(&2).contains(&1));
+        assert!(!map.contains_key(&1));
+        assert_eq!(map.get(&2), Some(2));
+        assert_eq!(map.get_and(&2, |v| v + 1), Some(3));
+    }
+
+    #[test]
+    fn from_map() {
+        let inner = HashMap::new();
+
+        for i in 0..10 {
+            inner.insert(i, i);
+        }
+
+        let map = IndexedHashMap::from_map(inner, |_, v| v % 2 == 0);
+        assert_eq!(map.len(), 10);
+
+        let mut keys = map.find_keys(&true);
+        keys.sort_unstable();
+        assert_eq!(keys, [0, 2, 4, 6, 8]);
+    }
+
+    #[test]
+    fn prune_index() {
+        let mut map = IndexedHashMap::new(|&k: &u32, _: &()| k);
+
+        for i in 0..10 {
+            map.insert(i, ());
+        }
+
+        for i in 0..5 {
+            map.remove(&i);
+        }
+
+        assert_eq!(map.index_len(), 10);
+        assert!(map.find(&0).is_empty());
+
+        assert_eq!(map.prune_index(), 5);
+        assert_eq!(map.index_len(), 5);
+        assert_eq!(map.find_keys(&7), [7]);
+    }
+
+    #[test]
+    fn concurrent_insert() {
+        const NUM_THREADS: usize = 8;
+        const NUM_KEYS: usize = 64;
+        const NUM_INSERTS: usize = 1000;
+
+        let map = Arc::new(IndexedHashMap::new(|_: &usize, v: &usize| v % 4));
+        let barrier = Arc::new(Barrier::new(NUM_THREADS));
+
+        let threads: Vec<_> = (0..NUM_THREADS)
+            .map(|i| {
+                let map = Arc::clone(&map);
+                let barrier = Arc::clone(&barrier);
+
+                thread::spawn(move || {
+                    barrier.wait();
+
+                    for j in 0..NUM_INSERTS {
+                        map.insert(j % NUM_KEYS, i + j);
+
+                        if j % 7 == 0 {
+                            map.remove(&((j + i) % NUM_KEYS));
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for result in threads.into_iter().map(thread::JoinHandle::join) {
+            assert!(result.is_ok());
+        }
+
+        // once the writes are done, each key is only in the set of the index
+        // key of its value, with a count of 1
+        for index_key in 0..4 {
+            let mut expected = Vec::new();
+            map.as_inner().for_each(|&k, &v| {
+                if v % 4 == index_key {
+                    expected.push(k);
+                }
+            });
+            expected.sort_unstable();
+
+            let keys = map.index.get(&index_key).unwrap();
+            let mut indexed = Vec::new();
+            keys.for_each(|&k, &count| {
+                assert_eq!(count, 1);
+                indexed.push(k);
+            });
+            indexed.sort_unstable();
+
+            assert_eq!(indexed, expected);
+        }
+    }
+}
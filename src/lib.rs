@@ -83,12 +83,34 @@
 //! [Junction]: https://github.com/preshing/junction
 //! [a tech talk]: https://youtu.be/HJ-719EGIts
 
+pub mod any_map;
+pub mod compat;
+#[cfg(feature = "conformance")]
+pub mod conformance;
+pub mod housekeeping;
+pub mod indexed_map;
 pub mod map;
+pub mod metrics;
+pub mod multi_map;
+pub mod replica;
 pub mod segment;
+pub mod sharding;
+#[cfg(feature = "shared-memory")]
+pub mod shared_memory;
+pub mod snapshot;
+#[cfg(feature = "workload")]
+pub mod workload;
+
+mod error;
+mod ordering;
 
 #[cfg(test)]
 #[macro_use]
 pub(crate) mod test_util;
 
+pub use error::Error;
+pub use map::bucket::hash;
 pub use map::HashMap;
 pub use segment::HashMap as SegmentedHashMap;
+
+pub use crossbeam_epoch as epoch;
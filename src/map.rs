@@ -1,24 +1,123 @@
 //! A lock-free hash map implemented with bucket pointer arrays, open addressing, and
 //! linear probing.
 
+pub(crate) mod batch;
 pub(crate) mod bucket;
 pub(crate) mod bucket_array_ref;
+mod builder;
+mod cached_hash;
+pub(crate) mod coalescing;
+mod config;
+mod context;
+mod decision;
+pub(crate) mod default_value;
+mod deterministic;
+mod downcast;
+mod entry_meta;
+#[cfg(feature = "futures")]
+pub(crate) mod entry_stream;
+pub(crate) mod exclusive;
+mod guarded;
+mod handle;
+mod key_set;
+pub(crate) mod loader;
+mod map_handle;
+pub(crate) mod memory_budget;
+mod not_removed;
+mod panic_policy;
+#[cfg(feature = "rayon")]
+pub(crate) mod parallel;
+mod partition;
+#[cfg(feature = "entry-pins")]
+mod pin;
+#[cfg(feature = "unstable")]
+pub mod raw;
+mod read_budget;
+mod reservation;
+mod scan;
+pub(crate) mod scrub;
+mod snapshot_view;
+#[cfg(feature = "entry-pins")]
+pub(crate) mod soft_removal;
+pub(crate) mod state;
+pub(crate) mod storage;
+mod tombstone_policy;
 
-use bucket::BucketArray;
+pub use batch::Batch;
+pub use bucket::SlotHint;
+pub use builder::Builder;
+pub use cached_hash::WithCachedHash;
+pub use config::MapConfig;
+pub use context::WithContext;
+pub use decision::Decision;
+pub use default_value::DefaultValue;
+pub use deterministic::{DeterministicHasher, DeterministicState};
+pub use exclusive::{Exclusive, IntoIter, IterMut};
+pub use guarded::{Iter, Keys, Ref, UnorderedIter, Values, ValuesCloned};
+pub use handle::EntryHandle;
+pub use key_set::KeySetView;
+pub use loader::Loader;
+pub use map_handle::MapHandle;
+pub use memory_budget::MemoryBudget;
+pub use not_removed::{NotRemoved, NotRemovedReason};
+pub use panic_policy::PanicPolicy;
+pub use partition::Partition;
+#[cfg(feature = "entry-pins")]
+pub use pin::EntryPin;
+pub use read_budget::ReadBudget;
+pub use reservation::ReservationToken;
+pub use scan::ScanCursor;
+pub use snapshot_view::SnapshotView;
+pub use state::MapState;
+pub use tombstone_policy::TombstonePolicy;
+
+#[cfg(feature = "instrument")]
+use crate::metrics::{Latencies, LatencyStats, OpReport};
+use crate::{
+    housekeeping::{self, Budget, ClearProgress, HousekeepingReport},
+    metrics::{
+        self, HandleOpCounts, HandleOpStats, HandleRegistry, MetricsRecorder, ProbeStats, Sampler,
+    },
+    ordering,
+    snapshot::{Snapshot, SnapshotDiff},
+    Error,
+};
+
+use bucket::{Bucket, BucketArray};
 use bucket_array_ref::BucketArrayRef;
+use coalescing::Coalescing;
+use default_value::BoxedDefaultValue;
+use loader::Loading;
+use memory_budget::MemoryCharge;
+use scrub::{NoScrub, Scrub};
+#[cfg(feature = "entry-pins")]
+use soft_removal::SoftRemovals;
+use state::Reservation;
 
 use std::{
+    any::Any,
     borrow::Borrow,
+    cell::RefCell,
     collections::hash_map::RandomState,
     hash::{BuildHasher, Hash},
-    sync::atomic::{self, AtomicUsize, Ordering},
+    ptr,
+    sync::{
+        atomic::{self, AtomicU64, AtomicUsize},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
 
-use crossbeam_epoch::{self, Atomic};
+use crossbeam_epoch::{self, Atomic, Guard};
 
 /// Default hasher for `HashMap`.
 pub type DefaultHashBuilder = RandomState;
 
+/// The flag bits that embedders can set on the entries of the hash maps with
+/// their `set_flags` methods.
+#[cfg(feature = "entry-flags")]
+pub const ENTRY_FLAGS: u8 = (bucket::FLAGS_MASK >> bucket::FLAGS_SHIFT) as u8;
+
 /// A lock-free hash map implemented with bucket pointer arrays, open addressing, and
 /// linear probing.
 ///
@@ -85,12 +184,21 @@ pub type DefaultHashBuilder = RandomState;
 /// trait, changes while it is in the map. This is normally only possible through
 /// [`Cell`], [`RefCell`], global state, I/O, or unsafe code.
 ///
+/// # Panics in Closures
+///
+/// If a closure passed to a method of the map panics, the panic unwinds out of
+/// the method and the map stays consistent: an entry being modified is left
+/// unchanged, the length of the map matches its entries, and no values are
+/// leaked. The map can be configured to abort the process instead. See
+/// [`PanicPolicy`] for details.
+///
 /// [`aHash`]: https://crates.io/crates/ahash
 /// [`default`]: #method.default
 /// [`with_hasher`]: #method.with_hasher
 /// [`with_capacity_and_hasher`]: #method.with_capacity_and_hasher
 /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
 /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+/// [`PanicPolicy`]: ./enum.PanicPolicy.html
 /// [`Cell`]: https://doc.rust-lang.org/std/cell/struct.Ref.html
 /// [`RefCell`]: https://doc.rust-lang.org/std/cell/struct.RefCell.html
 ///
@@ -99,9 +207,36 @@ pub struct HashMap<K, V, S = DefaultHashBuilder> {
     bucket_array: Atomic<bucket::BucketArray<K, V>>,
     build_hasher: S,
     len: AtomicUsize,
+    generation: AtomicU64,
+    access_clock: AtomicU64,
+    deterministic: bool,
+    panic_policy: PanicPolicy,
+    tombstone_policy: TombstonePolicy,
+    flush_after_bulk_removals: bool,
+    memory: Option<MemoryCharge<K, V>>,
+    loading: Option<Loading<K, V>>,
+    default_value: Option<BoxedDefaultValue<K, V>>,
+    coalescing: Option<Coalescing<K, V>>,
+    handles: HandleRegistry,
+    #[cfg(feature = "entry-pins")]
+    soft_removals: SoftRemovals<K, V>,
+    clear_cursor: Mutex<ScanCursor>,
+    reserved_slots: AtomicUsize,
+    // the last sequence number assigned to a mutation, if they are assigned
+    sequence: Option<AtomicU64>,
+    sampler: Option<Sampler>,
+    #[cfg(feature = "instrument")]
+    latencies: Latencies,
 }
 
 impl<K, V> HashMap<K, V, DefaultHashBuilder> {
+    /// Returns a [`Builder`] of a `HashMap`.
+    ///
+    /// [`Builder`]: ./struct.Builder.html
+    pub fn builder() -> Builder<K, V> {
+        Builder::new()
+    }
+
     /// Creates an empty `HashMap`.
     ///
     /// The hash map is initially created with a capacity of 0, so it will not
@@ -115,9 +250,30 @@ impl<K, V> HashMap<K, V, DefaultHashBuilder> {
     /// The hash map will be able to hold at least `capacity` elements without
     /// reallocating its bucket pointer array. If `capacity` is 0, the hash map
     /// will not allocate.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bucket pointer array for `capacity` elements would exceed
+    /// the maximum allocation size. See [`try_with_capacity`] for a
+    /// non-panicking version.
+    ///
+    /// [`try_with_capacity`]: #method.try_with_capacity
     pub fn with_capacity(capacity: usize) -> HashMap<K, V, DefaultHashBuilder> {
         HashMap::with_capacity_and_hasher(capacity, DefaultHashBuilder::default())
     }
+
+    /// Creates an empty `HashMap` with the specified capacity, returning an
+    /// error if the capacity is too large.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::CapacityOverflow`] if the bucket pointer array for
+    /// `capacity` elements would exceed the maximum allocation size.
+    ///
+    /// [`Error::CapacityOverflow`]: ../enum.Error.html#variant.CapacityOverflow
+    pub fn try_with_capacity(capacity: usize) -> Result<HashMap<K, V, DefaultHashBuilder>, Error> {
+        HashMap::try_with_capacity_and_hasher(capacity, DefaultHashBuilder::default())
+    }
 }
 
 impl<K, V, S> HashMap<K, V, S> {
@@ -136,21 +292,121 @@ impl<K, V, S> HashMap<K, V, S> {
     /// The hash map will be able to hold at least `capacity` elements without
     /// reallocating its bucket pointer array. If `capacity` is 0, the hash map
     /// will not allocate.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bucket pointer array for `capacity` elements would exceed
+    /// the maximum allocation size. See [`try_with_capacity_and_hasher`] for a
+    /// non-panicking version.
+    ///
+    /// [`try_with_capacity_and_hasher`]: #method.try_with_capacity_and_hasher
     pub fn with_capacity_and_hasher(capacity: usize, build_hasher: S) -> HashMap<K, V, S> {
+        Self::try_with_capacity_and_hasher(capacity, build_hasher)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Creates an empty `HashMap` with the specified capacity, using
+    /// `build_hasher` to hash the keys, returning an error if the capacity is
+    /// too large.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::CapacityOverflow`] if the bucket pointer array for
+    /// `capacity` elements would exceed the maximum allocation size.
+    ///
+    /// [`Error::CapacityOverflow`]: ../enum.Error.html#variant.CapacityOverflow
+    pub fn try_with_capacity_and_hasher(
+        capacity: usize,
+        build_hasher: S,
+    ) -> Result<HashMap<K, V, S>, Error> {
         let bucket_array = if capacity == 0 {
             Atomic::null()
         } else {
             Atomic::new(BucketArray::with_length(
                 0,
-                (capacity * 2).next_power_of_two(),
+                bucket::length_for_capacity(capacity)?,
             ))
         };
 
-        Self {
+        Ok(Self {
             bucket_array,
             build_hasher,
             len: AtomicUsize::new(0),
+            generation: AtomicU64::new(0),
+            access_clock: AtomicU64::new(0),
+            deterministic: false,
+            panic_policy: PanicPolicy::default(),
+            tombstone_policy: TombstonePolicy::default(),
+            flush_after_bulk_removals: false,
+            memory: None,
+            loading: None,
+            default_value: None,
+            coalescing: None,
+            handles: HandleRegistry::default(),
+            #[cfg(feature = "entry-pins")]
+            soft_removals: SoftRemovals::default(),
+            clear_cursor: Mutex::new(ScanCursor::new()),
+            reserved_slots: AtomicUsize::new(0),
+            sequence: None,
+            sampler: None,
+            #[cfg(feature = "instrument")]
+            latencies: Latencies::default(),
+        })
+    }
+
+    /// Creates a `HashMap` holding `entries`, using `build_hasher` to hash the
+    /// keys.
+    ///
+    /// Each entry is a key-value pair preceded by the hash of its key, as
+    /// returned by [`key_hash`] for a map with an equivalent hasher, and the
+    /// entries must be sorted by hash, like those of a [`Snapshot`]. The bucket
+    /// pointer array is built directly from them without going through the
+    /// insertion path: the sorted order brings entries with equal keys
+    /// together, and the entries are laid out in a single pass in time linear
+    /// in their number, which makes this much faster than inserting the
+    /// entries one by one. An entry whose hash was not computed by `build_hasher` can
+    /// not be found in the map. If several entries have equal keys, the last
+    /// one is kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::UnsortedEntries`] if the entries are not sorted by hash,
+    /// or [`Error::CapacityOverflow`] if the bucket pointer array for them
+    /// would exceed the maximum allocation size.
+    ///
+    /// [`key_hash`]: #method.key_hash
+    /// [`Snapshot`]: ../snapshot/struct.Snapshot.html
+    /// [`Error::UnsortedEntries`]: ../enum.Error.html#variant.UnsortedEntries
+    /// [`Error::CapacityOverflow`]: ../enum.Error.html#variant.CapacityOverflow
+    pub fn from_hash_sorted_entries<I: IntoIterator<Item = (u64, K, V)>>(
+        entries: I,
+        build_hasher: S,
+    ) -> Result<HashMap<K, V, S>, Error>
+    where
+        K: Hash + Eq,
+        S: BuildHasher,
+    {
+        let mut map = Self::with_hasher(build_hasher);
+        let mut previous_hash = 0;
+        let mut sorted_entries = Vec::new();
+
+        for (hash, key, value) in entries {
+            if hash < previous_hash {
+                return Err(Error::UnsortedEntries);
+            }
+
+            debug_assert_eq!(hash, bucket::hash(&map.build_hasher, &key));
+            previous_hash = hash;
+            sorted_entries.push((hash, key, value));
+        }
+
+        if !sorted_entries.is_empty() {
+            let (bucket_array, len) = BucketArray::from_hash_sorted_entries(0, sorted_entries)?;
+            map.bucket_array = Atomic::new(bucket_array);
+            *map.len.get_mut() = len;
         }
+
+        Ok(map)
     }
 
     /// Returns the number of elements in the map.
@@ -160,7 +416,7 @@ impl<K, V, S> HashMap<K, V, S> {
     /// This method on its own is safe, but other threads can add or remove
     /// elements at any time.
     pub fn len(&self) -> usize {
-        self.len.load(Ordering::Relaxed)
+        self.len.load(ordering::RELAXED)
     }
 
     /// Returns `true` if the map contains no elements.
@@ -186,15 +442,460 @@ impl<K, V, S> HashMap<K, V, S> {
     pub fn capacity(&self) -> usize {
         let guard = &crossbeam_epoch::pin();
 
-        let bucket_array_ptr = self.bucket_array.load_consume(guard);
+        let bucket_array_ptr = ordering::load_consume(&self.bucket_array, guard);
 
         unsafe { bucket_array_ptr.as_ref() }
             .map(BucketArray::capacity)
             .unwrap_or(0)
     }
+
+    /// Returns the generation of the bucket pointer array of the map.
+    ///
+    /// The generation is incremented every time the map is rehashed into a new
+    /// bucket pointer array, whether it grows, shrinks, or is compacted, so a
+    /// change of the generation means that the layout of the map changed. The
+    /// generation of a map without a bucket pointer array is 0.
+    pub fn bucket_array_generation(&self) -> usize {
+        let guard = &crossbeam_epoch::pin();
+
+        let bucket_array_ptr = ordering::load_consume(&self.bucket_array, guard);
+
+        unsafe { bucket_array_ptr.as_ref() }
+            .map(|a| a.epoch)
+            .unwrap_or(0)
+    }
+
+    /// Returns an estimate of the number of entries per slot of the bucket
+    /// pointer array of the map.
+    ///
+    /// This is the length of the map divided by the number of slots of its
+    /// current bucket pointer array. The array is grown before more than half
+    /// of its slots are taken, so the estimate stays at most about 0.5, and
+    /// the closer it gets, the longer the probes of the lookups. A map that has
+    /// no bucket pointer array yet has an estimate of 0.
+    ///
+    /// # Safety
+    ///
+    /// This method on its own is safe, but other threads can add or remove
+    /// elements and resize the map at any time.
+    pub fn keys_per_slot_estimate(&self) -> f64 {
+        let guard = &crossbeam_epoch::pin();
+
+        let bucket_array_ptr = ordering::load_consume(&self.bucket_array, guard);
+
+        match unsafe { bucket_array_ptr.as_ref() } {
+            Some(a) => self.len() as f64 / a.buckets.len() as f64,
+            None => 0.0,
+        }
+    }
+
+    /// Returns a reference to the map's [`BuildHasher`].
+    ///
+    /// [`BuildHasher`]: https://doc.rust-lang.org/std/hash/trait.BuildHasher.html
+    pub fn hasher(&self) -> &S {
+        &self.build_hasher
+    }
+
+    /// Returns the configuration of the map.
+    ///
+    /// See [`MapConfig`] for details.
+    ///
+    /// [`MapConfig`]: ./struct.MapConfig.html
+    pub fn config(&self) -> MapConfig {
+        MapConfig {
+            num_segments: 1,
+            segment_shift: 64,
+            segment_salt: 0,
+            hasher: std::any::type_name::<S>().to_owned(),
+            deterministic: self.deterministic,
+            panic_policy: self.panic_policy,
+            tombstone_policy: self.tombstone_policy,
+            memory_budget_limit: self.memory_budget().map(MemoryBudget::limit),
+            loader: self.loading.is_some(),
+            default_value: self.default_value.is_some(),
+            coalesce_window: self.coalesce_window(),
+            sequence_numbers: self.sequence.is_some(),
+            flush_after_bulk_removals: self.flush_after_bulk_removals,
+            sample_one_in: self.sampler.as_ref().map(Sampler::one_in),
+            instrument: cfg!(feature = "instrument"),
+        }
+    }
+
+    /// Returns the latency histograms of the operations on the map.
+    ///
+    /// See the [`metrics`] module for details.
+    ///
+    /// [`metrics`]: ../metrics/index.html
+    #[cfg(feature = "instrument")]
+    pub fn latency_stats(&self) -> LatencyStats {
+        self.latencies.stats()
+    }
+
+    /// Returns a read-only view of the keys of the map.
+    ///
+    /// The view does not copy the keys and gives no access to the values. See
+    /// [`KeySetView`] for details.
+    ///
+    /// [`KeySetView`]: struct.KeySetView.html
+    pub fn key_set(&self) -> KeySetView<'_, K, V, S> {
+        KeySetView::new(self)
+    }
+
+    /// Returns an empty [`Batch`] of insertions and removals, which are applied
+    /// to the map together when the batch is committed.
+    ///
+    /// Writing many entries through a batch avoids the per-entry overhead of
+    /// the individual methods. See [`Batch`] for details.
+    ///
+    /// [`Batch`]: struct.Batch.html
+    pub fn batch(&self) -> Batch<'_, K, V, S> {
+        Batch::new(self)
+    }
+
+    /// Returns the current generation of the map.
+    ///
+    /// Every entry is stamped with the generation of the map when its key was
+    /// inserted. Modifying the value of an entry keeps its generation, but
+    /// reinserting a key after it has been removed stamps it anew. The
+    /// generation of a new map is 0.
+    ///
+    /// This method is only available with the `generations` feature.
+    #[cfg(feature = "generations")]
+    pub fn generation(&self) -> u64 {
+        self.generation.load(ordering::ACQUIRE)
+    }
+
+    /// Returns the memory budget that the map charges the weights of its
+    /// entries to, if any.
+    ///
+    /// See [`Builder::memory_budget`].
+    ///
+    /// [`Builder::memory_budget`]: ./struct.Builder.html#method.memory_budget
+    pub fn memory_budget(&self) -> Option<&MemoryBudget> {
+        self.memory.as_ref().map(MemoryCharge::budget)
+    }
+
+    /// Returns the window within which the map coalesces the writes of its
+    /// `insert_coalesced` and `modify_coalesced` methods to the same key, if
+    /// any.
+    ///
+    /// See [`Builder::coalesce_window`].
+    ///
+    /// [`Builder::coalesce_window`]: ./struct.Builder.html#method.coalesce_window
+    pub fn coalesce_window(&self) -> Option<Duration> {
+        self.coalescing.as_ref().map(Coalescing::window)
+    }
+
+    /// Creates a handle to the map with a new ID, which counts the operations
+    /// performed through it and its clones.
+    ///
+    /// See [`MapHandle`] for details.
+    ///
+    /// [`MapHandle`]: ./struct.MapHandle.html
+    pub fn handle(self: &Arc<Self>) -> MapHandle<K, V, S> {
+        MapHandle::new(Arc::clone(self))
+    }
+
+    /// Returns a view of the map that passes `context` to the hooks of the map
+    /// for the operations performed through it.
+    ///
+    /// See [`WithContext`] for details.
+    ///
+    /// [`WithContext`]: ./struct.WithContext.html
+    pub fn with_context<'a>(&'a self, context: &'a dyn Any) -> WithContext<'a, K, V, S> {
+        WithContext::new(self, context)
+    }
+
+    /// Returns the operation counts of every live handle to the map, and the
+    /// combined counts of all handles that were dropped.
+    ///
+    /// The counts of handles used by other threads may lag slightly behind.
+    pub fn handle_op_counts(&self) -> (Vec<HandleOpStats>, HandleOpCounts) {
+        self.handles.op_counts()
+    }
+
+    /// Reports the operation counts of every live handle to the map to
+    /// `recorder`.
+    pub fn report_handle_op_counts(&self, recorder: &dyn MetricsRecorder) {
+        for stats in self.handles.op_counts().0 {
+            recorder.record_handle_op_counts(stats.id, &stats.counts);
+        }
+    }
+
+    /// Advances the generation of the map, returning the generation before the
+    /// call.
+    ///
+    /// Passing the returned generation to [`iter_upto_generation`] yields a
+    /// traversal that skips the keys inserted after this call returned.
+    ///
+    /// This method is only available with the `generations` feature.
+    ///
+    /// [`iter_upto_generation`]: #method.iter_upto_generation
+    #[cfg(feature = "generations")]
+    pub fn advance_generation(&self) -> u64 {
+        self.generation.fetch_add(1, ordering::ACQ_REL)
+    }
 }
 
 impl<K: Hash + Eq, V, S: BuildHasher> HashMap<K, V, S> {
+    /// Reserves capacity for at least `additional` more elements than the map
+    /// currently holds, reallocating its bucket pointer array if needed.
+    ///
+    /// Insertions made concurrently by other threads may use the reserved
+    /// capacity.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bucket pointer array for the new capacity would exceed
+    /// the maximum allocation size. See [`try_reserve`] for a non-panicking
+    /// version.
+    ///
+    /// [`try_reserve`]: #method.try_reserve
+    pub fn reserve(&self, additional: usize) {
+        self.try_reserve(additional)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Reserves capacity for at least `additional` more elements than the map
+    /// currently holds, returning an error if the capacity is too large.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::CapacityOverflow`] if the bucket pointer array for the
+    /// new capacity would exceed the maximum allocation size.
+    ///
+    /// [`Error::CapacityOverflow`]: ../enum.Error.html#variant.CapacityOverflow
+    pub fn try_reserve(&self, additional: usize) -> Result<(), Error> {
+        self.bucket_array_ref().reserve(additional)
+    }
+
+    /// Reserves `additional` slots for the insertions made through the
+    /// returned [`ReservationToken`], reallocating the bucket pointer array
+    /// if needed.
+    ///
+    /// Unlike [`reserve`], the slots are held for the token: insertions made
+    /// without it grow the map rather than use them, so the next `additional`
+    /// insertions of new keys through the token do not grow the map. The
+    /// slots left are released when the token is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bucket pointer array for the new capacity would exceed
+    /// the maximum allocation size. See [`try_reserve_slots`] for a
+    /// non-panicking version.
+    ///
+    /// [`ReservationToken`]: struct.ReservationToken.html
+    /// [`reserve`]: #method.reserve
+    /// [`try_reserve_slots`]: #method.try_reserve_slots
+    pub fn reserve_slots(&self, additional: usize) -> ReservationToken<'_, K, V, S> {
+        self.try_reserve_slots(additional)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Reserves `additional` slots for the insertions made through the
+    /// returned [`ReservationToken`], returning an error if the capacity is
+    /// too large.
+    ///
+    /// See [`reserve_slots`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::CapacityOverflow`] if the bucket pointer array for the
+    /// new capacity would exceed the maximum allocation size.
+    ///
+    /// [`ReservationToken`]: struct.ReservationToken.html
+    /// [`reserve_slots`]: #method.reserve_slots
+    /// [`Error::CapacityOverflow`]: ../enum.Error.html#variant.CapacityOverflow
+    pub fn try_reserve_slots(
+        &self,
+        additional: usize,
+    ) -> Result<ReservationToken<'_, K, V, S>, Error> {
+        self.bucket_array_ref().reserve_slots(additional)?;
+
+        Ok(ReservationToken::new(self, additional))
+    }
+
+    /// Returns an [`Exclusive`] view of the map, which inserts and removes
+    /// entries without atomic read-modify-write operations and without
+    /// leaving tombstones behind.
+    ///
+    /// Borrowing the map mutably proves that no other thread can access it,
+    /// which makes populating a map before sharing it, or draining it after,
+    /// cheaper. A resize left unfinished by other threads is finished first.
+    /// See [`Exclusive`] for details.
+    ///
+    /// [`Exclusive`]: struct.Exclusive.html
+    pub fn exclusive(&mut self) -> Exclusive<'_, K, V, S> {
+        Exclusive::new(self)
+    }
+
+    /// Returns a mutable reference to the value corresponding to the key.
+    ///
+    /// Borrowing the map mutably proves that no other thread can access it,
+    /// so the lookup does not pin the current thread to an epoch. A resize
+    /// left unfinished by other threads is finished first.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn get_mut<Q: Hash + Eq + ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+    {
+        exclusive::settle(self.bucket_array_ref());
+
+        let hash = bucket::hash(&self.build_hasher, key);
+        let access = *self.access_clock.get_mut();
+
+        exclusive::get_mut(&mut self.bucket_array, hash, key, access)
+            .map(|b| unsafe { b.value_mut() })
+    }
+
+    /// Inserts a key-value pair into the map, returning the value previously
+    /// corresponding to the key.
+    ///
+    /// Like [`Exclusive::insert`], this does not pin the current thread to an
+    /// epoch, and writes the bucket pointer array without atomic
+    /// read-modify-write operations. To insert many entries, get an
+    /// [`Exclusive`] view once with [`exclusive`] instead.
+    ///
+    /// If the map did have this key present, its value is overwritten in the
+    /// bucket that holds it, without allocating a new one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bucket pointer array would need to grow past the maximum
+    /// allocation size.
+    ///
+    /// [`Exclusive::insert`]: struct.Exclusive.html#method.insert
+    /// [`Exclusive`]: struct.Exclusive.html
+    /// [`exclusive`]: #method.exclusive
+    pub fn insert_mut(&mut self, key: K, value: V) -> Option<V> {
+        self.exclusive().insert(key, value)
+    }
+
+    /// An iterator visiting the entries of the map in arbitrary order, yielding
+    /// a reference to the key and a mutable reference to the value of each of
+    /// them.
+    ///
+    /// Borrowing the map mutably proves that no other thread can access it,
+    /// so the iterator does not pin the current thread to an epoch. A resize
+    /// left unfinished by other threads is finished first. The entries of a
+    /// deterministic map are visited in the order of their hashes.
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        exclusive::settle(self.bucket_array_ref());
+
+        let mut buckets = Vec::new();
+        exclusive::live_buckets_mut(&mut self.bucket_array, &mut buckets);
+
+        if self.deterministic {
+            let build_hasher = &self.build_hasher;
+            buckets.sort_by_cached_key(|b| bucket::hash(build_hasher, &b.key));
+        }
+
+        unsafe { IterMut::new(buckets) }
+    }
+
+    /// Removes every entry whose key `predicate` returns `true` for, returning
+    /// the number of entries removed, and frees everything that the map still
+    /// holds of those keys before returning.
+    ///
+    /// This erases the state of a tenant whose keys share a map with the keys
+    /// of other tenants. Besides the entries themselves, the tombstones and
+    /// the soft-removed entries of matching keys are freed, and the pending
+    /// coalesced writes to them are discarded, so that nothing is left in the
+    /// map for a later read, [`undelete`] or [`flush_coalesced`] to bring
+    /// back. The entries are removed in place without leaving tombstones, and
+    /// the garbage of the current thread is then flushed to the epoch
+    /// collector.
+    ///
+    /// Borrowing the map mutably proves that no other thread can access it. A
+    /// resize left unfinished by other threads is finished first. The keys and
+    /// values of the entries removed earlier through shared references may
+    /// still be waiting for their epoch to pass, and are dropped whenever the
+    /// collector gets to them. See [`wipe_tenant_zeroized`] to also scrub the
+    /// memory of the freed keys and values.
+    ///
+    /// [`undelete`]: #method.undelete
+    /// [`flush_coalesced`]: #method.flush_coalesced
+    /// [`wipe_tenant_zeroized`]: #method.wipe_tenant_zeroized
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::HashMap;
+    ///
+    /// let mut map = HashMap::new();
+    /// map.insert(("tenant-a", 1), 10);
+    /// map.insert(("tenant-a", 2), 20);
+    /// map.insert(("tenant-b", 1), 30);
+    ///
+    /// assert_eq!(map.wipe_tenant(|&(tenant, _)| tenant == "tenant-a"), 2);
+    /// assert_eq!(map.get(&("tenant-a", 1)), None);
+    /// assert_eq!(map.len(), 1);
+    /// ```
+    pub fn wipe_tenant<F: FnMut(&K) -> bool>(&mut self, predicate: F) -> usize {
+        self.wipe_tenant_with(predicate, &mut NoScrub)
+    }
+
+    /// Removes every entry whose key `predicate` returns `true` for like
+    /// [`wipe_tenant`], zeroing the keys and the values that it frees before
+    /// their memory is released.
+    ///
+    /// The values of the soft-removed entries and the pending coalesced writes
+    /// of matching keys are zeroed as well, and so are the keys of their
+    /// tombstones. Only what the map holds in its own memory is zeroed: the
+    /// heap allocations of keys and values are zeroed if their [`Zeroize`]
+    /// implementations do so, and copies made by clones or by earlier moves
+    /// are not reached.
+    ///
+    /// This method is only available with the `zeroize` feature.
+    ///
+    /// [`wipe_tenant`]: #method.wipe_tenant
+    /// [`Zeroize`]: https://docs.rs/zeroize/*/zeroize/trait.Zeroize.html
+    #[cfg(feature = "zeroize")]
+    pub fn wipe_tenant_zeroized<F: FnMut(&K) -> bool>(&mut self, predicate: F) -> usize
+    where
+        K: zeroize::Zeroize,
+        V: zeroize::Zeroize,
+    {
+        self.wipe_tenant_with(predicate, &mut scrub::Zeroize)
+    }
+
+    fn wipe_tenant_with<F: FnMut(&K) -> bool>(
+        &mut self,
+        mut predicate: F,
+        scrub: &mut impl Scrub<K, V>,
+    ) -> usize {
+        if let Some(coalescing) = &mut self.coalescing {
+            coalescing.discard(&mut predicate, scrub);
+        }
+
+        // the buckets of the soft-removed entries are pinned, so the pins have
+        // to be dropped before the buckets are freed
+        #[cfg(feature = "entry-pins")]
+        unsafe {
+            self.soft_removals.purge_if(&mut predicate, scrub)
+        };
+        exclusive::settle(self.bucket_array_ref());
+
+        let removed = exclusive::wipe(
+            &mut self.bucket_array,
+            &self.build_hasher,
+            self.memory.as_ref(),
+            predicate,
+            scrub,
+        );
+        *self.len.get_mut() -= removed;
+
+        housekeeping::flush_garbage();
+
+        removed
+    }
+
     /// Returns a clone of the value corresponding to the key.
     ///
     /// The key may be any borrowed form of the map's key type, but
@@ -212,6 +913,68 @@ impl<K: Hash + Eq, V, S: BuildHasher> HashMap<K, V, S> {
         self.get_key_value_and(key, |_, v| v.clone())
     }
 
+    /// Returns a clone of the value corresponding to the key, loading it with
+    /// the [`Loader`] of the map and inserting it if the key is missing.
+    ///
+    /// Concurrent calls that miss the same key wait for a single call to the
+    /// loader and share its result. If the key is inserted while its value is
+    /// being loaded, the inserted value is kept and returned instead of the
+    /// loaded one. Returns `None` if the loader has no value for the key, or
+    /// if the map has no loader and the key is missing.
+    ///
+    /// [`Loader`]: ./trait.Loader.html
+    pub fn get_or_load(&self, key: &K) -> Option<V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let loading = match &self.loading {
+            Some(loading) => loading,
+            None => return self.get(key),
+        };
+
+        loading.get_or_load(
+            key,
+            |k| self.get(k),
+            |k, v| {
+                let guard = &crossbeam_epoch::pin();
+
+                unsafe { self.get_or_insert_with_bucket(guard, k, || v).value() }.clone()
+            },
+        )
+    }
+
+    /// Returns a clone of the value corresponding to the key, inserting the
+    /// value created by the [`DefaultValue`] factory of the map if the key is
+    /// missing.
+    ///
+    /// The factory is only invoked if the key is missing. If the key is
+    /// inserted concurrently, the inserted value is kept and returned instead
+    /// of the created one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the map has no default value factory.
+    ///
+    /// [`DefaultValue`]: ./trait.DefaultValue.html
+    pub fn get_or_default(&self, key: K) -> V
+    where
+        V: Clone,
+    {
+        if let Some(value) = self.get(&key) {
+            return value;
+        }
+
+        let value = self
+            .default_value
+            .as_ref()
+            .expect("the map has no default value factory")
+            .default_value(&key);
+        let guard = &crossbeam_epoch::pin();
+
+        unsafe { self.get_or_insert_with_bucket(guard, key, || value).value() }.clone()
+    }
+
     /// Returns a clone of the the key-value pair corresponding to the supplied
     /// key.
     ///
@@ -275,262 +1038,2346 @@ impl<K: Hash + Eq, V, S: BuildHasher> HashMap<K, V, S> {
             .get_key_value_and(key, hash, with_entry)
     }
 
-    /// Inserts a key-value pair into the map, returning a clone of the value
-    /// previously corresponding to the key.
+    /// Returns a clone of the value corresponding to the key, performing at
+    /// most as much work as `budget` allows.
     ///
-    /// If the map did have this key present, both the key and value are
-    /// updated.
+    /// See [`get_and_within`] for details.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Busy`] if the budget runs out before the key is found
+    /// or known to be absent.
+    ///
+    /// [`get_and_within`]: #method.get_and_within
+    /// [`Error::Busy`]: ../enum.Error.html#variant.Busy
     #[inline]
-    pub fn insert(&self, key: K, value: V) -> Option<V>
+    pub fn get_within<Q: Hash + Eq + ?Sized>(
+        &self,
+        key: &Q,
+        budget: ReadBudget,
+    ) -> Result<Option<V>, Error>
     where
+        K: Borrow<Q>,
         V: Clone,
     {
-        self.insert_entry_and(key, value, |_, v| v.clone())
+        self.get_and_within(key, budget, V::clone)
     }
 
-    /// Inserts a key-value pair into the map, returning a clone of the
-    /// key-value pair previously corresponding to the supplied key.
+    /// Returns the result of invoking a function with a reference to the value
+    /// corresponding to the key, performing at most as much work as `budget`
+    /// allows.
     ///
-    /// If the map did have this key present, both the key and value are
-    /// updated.
-    #[inline]
-    pub fn insert_entry(&self, key: K, value: V) -> Option<(K, V)>
+    /// The work is measured in slots probed and in buckets migrated to a new
+    /// bucket pointer array, which a lookup helps with if it runs into a
+    /// resize. See [`ReadBudget`] for details.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Busy`] if the budget runs out before the key is found
+    /// or known to be absent, in which case `with_value` is not invoked.
+    ///
+    /// [`ReadBudget`]: struct.ReadBudget.html
+    /// [`Error::Busy`]: ../enum.Error.html#variant.Busy
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn get_and_within<Q: Hash + Eq + ?Sized, F: FnOnce(&V) -> T, T>(
+        &self,
+        key: &Q,
+        mut budget: ReadBudget,
+        with_value: F,
+    ) -> Result<Option<T>, Error>
     where
-        K: Clone,
-        V: Clone,
+        K: Borrow<Q>,
     {
-        self.insert_entry_and(key, value, |k, v| (k.clone(), v.clone()))
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        self.bucket_array_ref()
+            .get_key_value_and_within(key, hash, &mut budget, move |_, v| with_value(v))
+    }
+
+    /// Returns statistics on the probe lengths of the entries of the map.
+    ///
+    /// This scans the whole bucket pointer array. See the [`metrics`] module
+    /// for how to use the statistics to detect hash collision attacks.
+    ///
+    /// [`metrics`]: ../metrics/index.html
+    pub fn probe_stats(&self) -> ProbeStats {
+        self.bucket_array_ref().probe_stats()
+    }
+
+    /// Shrinks the bucket pointer array or reclaims its tombstones if needed,
+    /// and flushes the garbage of the current thread.
+    ///
+    /// A `HashMap` has a single bucket pointer array, so every call completes a
+    /// pass regardless of `budget`. See the [`housekeeping`] module for
+    /// details.
+    ///
+    /// Shrinking may reduce the capacity below the one the map was created
+    /// with.
+    ///
+    /// [`housekeeping`]: ../housekeeping/index.html
+    pub fn run_housekeeping(&self, budget: Budget) -> HousekeepingReport {
+        let _ = budget;
+        let mut report = HousekeepingReport::default();
+
+        let (num_buckets_visited, op) = self.bucket_array_ref().run_housekeeping();
+        report.record_segment(num_buckets_visited, op);
+        report.is_pass_complete = true;
+
+        housekeeping::flush_garbage();
+
+        report
+    }
+
+    /// Removes up to as many entries as `budget` allows, resuming the clear
+    /// that the previous call left incomplete, and flushes the garbage of the
+    /// current thread.
+    ///
+    /// Calling this repeatedly until it reports that the clear is complete
+    /// removes every entry that was in the map before the first call, without
+    /// stalling any call for longer than it takes to remove its batch. See the
+    /// [`housekeeping`] module for details.
+    ///
+    /// [`housekeeping`]: ../housekeeping/index.html
+    pub fn clear_in_batches(&self, budget: Budget) -> ClearProgress {
+        let mut cursor = self.clear_cursor.lock().unwrap_or_else(|e| e.into_inner());
+        let mut progress = ClearProgress::default();
+        let bucket_array_ref = self.bucket_array_ref();
+
+        {
+            let guard = &crossbeam_epoch::pin();
+            let (buckets, next) =
+                bucket_array_ref.scan(guard, cursor.position(), budget.max_buckets());
+
+            for this_bucket_ref in buckets {
+                let key = &this_bucket_ref.key;
+                let hash = bucket::hash(&self.build_hasher, key);
+
+                if bucket_array_ref
+                    .remove_entry_if_and(key, hash, |k, _| ptr::eq(k, key), |_, _| ())
+                    .is_some()
+                {
+                    progress.num_removed += 1;
+                }
+            }
+
+            cursor.advance(next, 1);
+        }
+
+        if cursor.is_done() {
+            *cursor = ScanCursor::new();
+            progress.is_complete = true;
+        }
+
+        housekeeping::flush_garbage();
+
+        progress
+    }
+
+    /// Hints the processor to load the memory that looking up a key will read
+    /// first, and returns without waiting for it.
+    ///
+    /// This hashes the key and prefetches the cache line of the bucket pointer
+    /// array where probing for the key starts. Calling it for each of a batch
+    /// of keys before looking them up overlaps the cache misses of the lookups
+    /// with other work, such as decoding the following keys. It has no other
+    /// effect, and does nothing on architectures without a stable prefetch
+    /// instruction.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::HashMap;
+    ///
+    /// let map = HashMap::new();
+    /// map.insert("foo", 1);
+    /// map.insert("bar", 2);
+    ///
+    /// let keys = ["foo", "bar", "baz"];
+    ///
+    /// for key in &keys {
+    ///     map.prefetch(key);
+    /// }
+    ///
+    /// let values: Vec<_> = keys.iter().map(|k| map.get(k)).collect();
+    /// assert_eq!(values, [Some(1), Some(2), None]);
+    /// ```
+    pub fn prefetch<Q: Hash + Eq + ?Sized>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+    {
+        let hash = bucket::hash(&self.build_hasher, key);
+
+        self.bucket_array_ref().prefetch(hash);
+    }
+
+    /// Returns references to the values corresponding to each of the keys, in
+    /// the same order as the keys.
+    ///
+    /// All lookups are performed under the single epoch guard `guard`, and the
+    /// returned references remain valid for as long as it is held. No values are
+    /// cloned. A returned value may have been concurrently removed or replaced
+    /// since it was looked up.
+    ///
+    /// The keys may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `guard` is not pinned to the default collector of
+    /// `crossbeam-epoch`, which the map reclaims its values through.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn get_many_ref<'g, 'k, Q: Hash + Eq + ?Sized + 'k, I: IntoIterator<Item = &'k Q>>(
+        &'g self,
+        keys: I,
+        guard: &'g Guard,
+    ) -> Vec<Option<&'g V>>
+    where
+        K: Borrow<Q>,
+    {
+        bucket::check_guard(guard);
+
+        let bucket_array_ref = self.bucket_array_ref();
+
+        keys.into_iter()
+            .map(|key| {
+                let hash = bucket::hash(&self.build_hasher, key);
+
+                bucket_array_ref
+                    .get_bucket(guard, key, hash)
+                    .map(|b| unsafe { b.value() })
+            })
+            .collect()
+    }
+
+    /// Returns an [`EntryHandle`] to the entry corresponding to the key, which
+    /// can later be resolved with [`resolve`] without probing.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`EntryHandle`]: ./struct.EntryHandle.html
+    /// [`resolve`]: #method.resolve
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn entry_handle<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<EntryHandle<K>>
+    where
+        K: Borrow<Q> + Clone,
+    {
+        let hash = bucket::hash(&self.build_hasher, key);
+        let guard = &crossbeam_epoch::pin();
+
+        self.bucket_array_ref()
+            .get_bucket_with_hint(guard, key, hash, None)
+            .map(|(b, hint)| EntryHandle::new(b.key.clone(), hint))
+    }
+
+    /// Returns an [`EntryPin`] to the entry corresponding to the key, which
+    /// keeps its key and value alive until it is dropped, even if the entry is
+    /// removed from the map in the meantime.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// This method is only available with the `entry-pins` feature.
+    ///
+    /// [`EntryPin`]: struct.EntryPin.html
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[cfg(feature = "entry-pins")]
+    pub fn pin_entry<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<EntryPin<'_, K, V>>
+    where
+        K: Borrow<Q>,
+    {
+        let hash = bucket::hash(&self.build_hasher, key);
+        let guard = &crossbeam_epoch::pin();
+
+        self.bucket_array_ref()
+            .get_bucket(guard, key, hash)
+            .and_then(|b| unsafe { EntryPin::new(b) })
+    }
+
+    /// Returns a [`Ref`] to the current entry of the key of `handle`, or `None`
+    /// if the key is not in the map.
+    ///
+    /// The slot that held the entry when `handle` was last resolved is checked
+    /// first. If the entry has been moved to a new bucket pointer array since,
+    /// the key is looked up again and `handle` is updated with its new slot.
+    ///
+    /// [`Ref`]: ./struct.Ref.html
+    pub fn resolve(&self, handle: &EntryHandle<K>) -> Option<Ref<'_, K, V>> {
+        let hash = handle.hash();
+        let guard = crossbeam_epoch::pin();
+
+        let (bucket_ref, hint) = self.bucket_array_ref().get_bucket_with_hint(
+            &guard,
+            handle.key(),
+            hash,
+            Some(handle.hint()),
+        )?;
+        let bucket_ptr: *const Bucket<K, V> = bucket_ref;
+        handle.update(hint);
+
+        Some(unsafe { Ref::new(guard, bucket_ptr) })
+    }
+
+    /// Returns the flags of the entry corresponding to the key.
+    ///
+    /// Each entry has the flag bits in [`ENTRY_FLAGS`], which are not used by
+    /// the map and can be set by embedders to mark entries, for example as
+    /// admitted or dirty, without maintaining an auxiliary map. The flags are
+    /// stored in the tag bits of the pointer to the entry, so they are updated
+    /// atomically with its value: they are kept when the value is modified or
+    /// replaced by an insertion, and cleared when the key is removed. A newly
+    /// inserted key has no flags set.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// This method is only available with the `entry-flags` feature.
+    ///
+    /// [`ENTRY_FLAGS`]: ./constant.ENTRY_FLAGS.html
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[cfg(feature = "entry-flags")]
+    pub fn fetch_flags<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<u8>
+    where
+        K: Borrow<Q>,
+    {
+        let hash = bucket::hash(&self.build_hasher, key);
+
+        self.bucket_array_ref()
+            .update_flags(key, hash, |f| f)
+            .map(|f| f as u8)
+    }
+
+    /// Sets the given flags of the entry corresponding to the key, returning
+    /// its previous flags.
+    ///
+    /// Returns `None` and does nothing if the key is not in the map.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `flags` has bits set outside of [`ENTRY_FLAGS`].
+    ///
+    /// This method is only available with the `entry-flags` feature.
+    ///
+    /// [`ENTRY_FLAGS`]: ./constant.ENTRY_FLAGS.html
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[cfg(feature = "entry-flags")]
+    pub fn set_flags<Q: Hash + Eq + ?Sized>(&self, key: &Q, flags: u8) -> Option<u8>
+    where
+        K: Borrow<Q>,
+    {
+        assert_eq!(
+            flags & !ENTRY_FLAGS,
+            0,
+            "the flags must be within ENTRY_FLAGS"
+        );
+
+        let hash = bucket::hash(&self.build_hasher, key);
+
+        self.bucket_array_ref()
+            .update_flags(key, hash, |f| f | flags as usize)
+            .map(|f| f as u8)
+    }
+
+    /// Clears the given flags of the entry corresponding to the key, returning
+    /// its previous flags.
+    ///
+    /// Returns `None` and does nothing if the key is not in the map.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `flags` has bits set outside of [`ENTRY_FLAGS`].
+    ///
+    /// This method is only available with the `entry-flags` feature.
+    ///
+    /// [`ENTRY_FLAGS`]: ./constant.ENTRY_FLAGS.html
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[cfg(feature = "entry-flags")]
+    pub fn clear_flags<Q: Hash + Eq + ?Sized>(&self, key: &Q, flags: u8) -> Option<u8>
+    where
+        K: Borrow<Q>,
+    {
+        assert_eq!(
+            flags & !ENTRY_FLAGS,
+            0,
+            "the flags must be within ENTRY_FLAGS"
+        );
+
+        let hash = bucket::hash(&self.build_hasher, key);
+
+        self.bucket_array_ref()
+            .update_flags(key, hash, |f| f & !(flags as usize))
+            .map(|f| f as u8)
+    }
+
+    /// An iterator visiting all entries in arbitrary order.
+    ///
+    /// The current thread is pinned while the iterator is alive, and the
+    /// entries it yields are [`Ref`]s that borrow the keys and values in place,
+    /// so nothing is cloned. The bucket pointer array is read after any
+    /// pending resize is completed, so every entry is yielded at most once.
+    /// The iterator is weakly consistent: it reflects the contents of the map
+    /// at some point at or after its creation, and it is not affected by
+    /// modifications made while it is being consumed.
+    ///
+    /// The entries of a [deterministic] map are yielded in the order of the
+    /// hashes of their keys.
+    ///
+    /// [`Ref`]: ./struct.Ref.html
+    /// [deterministic]: ./struct.Builder.html#method.deterministic
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::HashMap;
+    ///
+    /// let map = HashMap::new();
+    /// map.insert("a", 1);
+    /// map.insert("b", 2);
+    ///
+    /// let mut entries: Vec<_> = map.iter().map(|r| (*r.key(), *r.value())).collect();
+    /// entries.sort_unstable();
+    ///
+    /// assert_eq!(entries, [("a", 1), ("b", 2)]);
+    /// ```
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let guard = crossbeam_epoch::pin();
+        let buckets = bucket::into_ptrs(self.bucket_array_ref().live_buckets(&guard));
+
+        unsafe { Iter::new(guard, buckets) }
+    }
+
+    /// An iterator visiting a clone of every key in arbitrary order.
+    ///
+    /// The keys are visited like the entries returned by [`iter`], and the
+    /// iterator is weakly consistent in the same way. Use [`iter`] to borrow
+    /// the keys in place instead of cloning them.
+    ///
+    /// [`iter`]: #method.iter
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::HashMap;
+    ///
+    /// let map = HashMap::new();
+    /// map.insert("a", 1);
+    /// map.insert("b", 2);
+    ///
+    /// let mut keys: Vec<_> = map.keys().collect();
+    /// keys.sort_unstable();
+    ///
+    /// assert_eq!(keys, ["a", "b"]);
+    /// ```
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys::new(self.iter())
+    }
+
+    /// An iterator visiting references to all values in arbitrary order.
+    ///
+    /// The entries are collected when this is called, and
+    /// the returned references remain valid for as long as `guard` is held. No
+    /// values are cloned. A value may have been concurrently removed or
+    /// replaced since it was collected. See [`values_cloned`] for a version
+    /// that does not take a guard.
+    ///
+    /// [`values_cloned`]: #method.values_cloned
+    ///
+    /// # Panics
+    ///
+    /// Panics if `guard` is not pinned to the default collector of
+    /// `crossbeam-epoch`, which the map reclaims its values through.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::{epoch, HashMap};
+    ///
+    /// let map = HashMap::new();
+    /// map.insert("a", 1);
+    /// map.insert("b", 2);
+    ///
+    /// let guard = &epoch::pin();
+    /// let sum: i32 = map.values(guard).sum();
+    ///
+    /// assert_eq!(sum, 3);
+    /// ```
+    pub fn values<'g>(&'g self, guard: &'g Guard) -> Values<'g, K, V> {
+        bucket::check_guard(guard);
+
+        Values::new(self.bucket_array_ref().live_buckets(guard))
+    }
+
+    /// An iterator visiting a clone of every value in arbitrary order.
+    ///
+    /// The values are visited like the entries returned by [`iter`], and the
+    /// iterator is weakly consistent in the same way.
+    ///
+    /// [`iter`]: #method.iter
+    pub fn values_cloned(&self) -> ValuesCloned<'_, K, V> {
+        ValuesCloned::new(self.iter())
+    }
+
+    /// Invokes `f` with a reference to the key and value of every entry, in
+    /// arbitrary order.
+    ///
+    /// The bucket pointer array is walked in place under a single epoch guard,
+    /// buffering at most one cluster of occupied slots at a time, and the
+    /// references passed to `f` are only valid for the duration of the call.
+    /// Nothing is cloned, and the entries are visited in the order of their
+    /// slots even if the map is deterministic. Like [`iter`], the traversal is
+    /// weakly consistent, and `f` may modify the map, including the entry it
+    /// is invoked with. An entry is never visited twice, even if the map is
+    /// resized during the traversal.
+    ///
+    /// [`iter`]: #method.iter
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::HashMap;
+    ///
+    /// let map = HashMap::new();
+    /// map.insert("a", 1);
+    /// map.insert("b", 2);
+    ///
+    /// let mut sum = 0;
+    /// map.for_each(|_, v| sum += v);
+    ///
+    /// assert_eq!(sum, 3);
+    /// ```
+    pub fn for_each<F: FnMut(&K, &V)>(&self, mut f: F) {
+        let _panic_guard = self.panic_policy.enter();
+        let guard = &crossbeam_epoch::pin();
+
+        for b in self.bucket_array_ref().walk(guard) {
+            f(&b.key, unsafe { b.value() });
+        }
+    }
+
+    /// Invokes `collector` with a reference to the key and value of every
+    /// entry that satisfies `predicate`, in arbitrary order, returning the
+    /// number of matching entries.
+    ///
+    /// The entries are traversed in place like in [`for_each`], and
+    /// `predicate` is invoked with references to every entry as its slot is
+    /// read, so the entries that do not match are never cloned or collected.
+    /// Like [`for_each`], the traversal is weakly consistent.
+    ///
+    /// [`for_each`]: #method.for_each
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::HashMap;
+    ///
+    /// let map = HashMap::new();
+    ///
+    /// // the last access time of each key
+    /// for i in 0..100u64 {
+    ///     map.insert(i, i * 10);
+    /// }
+    ///
+    /// let mut stale = Vec::new();
+    /// let num_stale = map.scan_filtered(|_, &accessed| accessed < 50, |&k, _| stale.push(k));
+    ///
+    /// stale.sort_unstable();
+    /// assert_eq!(num_stale, 5);
+    /// assert_eq!(stale, [0, 1, 2, 3, 4]);
+    /// ```
+    pub fn scan_filtered<P, C>(&self, mut predicate: P, mut collector: C) -> usize
+    where
+        P: FnMut(&K, &V) -> bool,
+        C: FnMut(&K, &V),
+    {
+        let _panic_guard = self.panic_policy.enter();
+        let guard = &crossbeam_epoch::pin();
+        let mut num_matching = 0;
+
+        for b in self.bucket_array_ref().walk(guard) {
+            let (key, value) = (&b.key, unsafe { b.value() });
+
+            if predicate(key, value) {
+                collector(key, value);
+                num_matching += 1;
+            }
+        }
+
+        num_matching
+    }
+
+    /// Folds every entry into an accumulator by invoking `f` with the
+    /// accumulator and a reference to the key and value of the entry, in
+    /// arbitrary order, returning the final accumulator.
+    ///
+    /// The entries are traversed in place like in [`for_each`], under a single
+    /// epoch guard, and the references passed to `f` are only valid for
+    /// the duration of the call, so sums, counts, or maxima can be computed
+    /// without cloning any entry. Like [`for_each`], the traversal is weakly
+    /// consistent.
+    ///
+    /// [`for_each`]: #method.for_each
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::HashMap;
+    ///
+    /// let map = HashMap::new();
+    /// map.insert("a", 1);
+    /// map.insert("b", 2);
+    /// map.insert("c", 3);
+    ///
+    /// let (count, sum) = map.fold((0, 0), |(count, sum), _, v| (count + 1, sum + v));
+    ///
+    /// assert_eq!(count, 3);
+    /// assert_eq!(sum, 6);
+    /// ```
+    pub fn fold<A, F: FnMut(A, &K, &V) -> A>(&self, init: A, mut f: F) -> A {
+        let _panic_guard = self.panic_policy.enter();
+        let guard = &crossbeam_epoch::pin();
+        let mut acc = init;
+
+        for b in self.bucket_array_ref().walk(guard) {
+            acc = f(acc, &b.key, unsafe { b.value() });
+        }
+
+        acc
+    }
+
+    /// Maps every entry to a value with `map`, invoked with a reference to the
+    /// key and value of the entry, and reduces those values to a single one
+    /// with `reduce`, in arbitrary order. Returns `None` if the map is empty.
+    ///
+    /// The entries are traversed like in [`fold`], so nothing is cloned unless
+    /// `map` clones it. Like [`for_each`], the traversal is weakly consistent.
+    ///
+    /// [`fold`]: #method.fold
+    /// [`for_each`]: #method.for_each
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::HashMap;
+    ///
+    /// let map = HashMap::new();
+    /// map.insert("a", 3);
+    /// map.insert("b", 7);
+    /// map.insert("c", 5);
+    ///
+    /// assert_eq!(map.reduce(|_, &v| v, i32::max), Some(7));
+    /// assert_eq!(HashMap::<&str, i32>::new().reduce(|_, &v| v, i32::max), None);
+    /// ```
+    pub fn reduce<T, M, R>(&self, mut map: M, mut reduce: R) -> Option<T>
+    where
+        M: FnMut(&K, &V) -> T,
+        R: FnMut(T, T) -> T,
+    {
+        self.fold(None, |acc, k, v| {
+            let value = map(k, v);
+
+            Some(match acc {
+                Some(acc) => reduce(acc, value),
+                None => value,
+            })
+        })
+    }
+
+    /// Invokes `f` with a reference to the key and value of every entry, in
+    /// arbitrary order, replacing the value of the entry with the one `f`
+    /// returns, if any.
+    ///
+    /// The replacement is applied like [`modify`] applies its new values: a
+    /// new bucket holding a clone of the key and the replacement is swapped in
+    /// atomically, so concurrent readers see either the old value or the new
+    /// one. If the entry is concurrently modified, `f` is invoked again with
+    /// the current value, and if it is concurrently removed, it stays removed.
+    /// Like [`for_each`], the traversal is weakly consistent. `f` should not
+    /// modify the entry it is invoked with, as that makes the replacement
+    /// retry.
+    ///
+    /// [`modify`]: #method.modify
+    /// [`for_each`]: #method.for_each
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::HashMap;
+    ///
+    /// let map = HashMap::new();
+    /// map.insert("a", -1);
+    /// map.insert("b", 2);
+    ///
+    /// // clamp the negative values to zero
+    /// map.for_each_modify(|_, &v| if v < 0 { Some(0) } else { None });
+    ///
+    /// assert_eq!(map.get("a"), Some(0));
+    /// assert_eq!(map.get("b"), Some(2));
+    /// ```
+    pub fn for_each_modify<F: FnMut(&K, &V) -> Option<V>>(&self, mut f: F)
+    where
+        K: Clone,
+    {
+        let _panic_guard = self.panic_policy.enter();
+        let guard = &crossbeam_epoch::pin();
+        let bucket_array_ref = self.bucket_array_ref();
+
+        for b in bucket_array_ref.live_buckets(guard) {
+            let hash = bucket::hash(&self.build_hasher, &b.key);
+            bucket_array_ref.try_modify_entry_and(b.key.clone(), hash, &mut f, |_, _| ());
+        }
+    }
+
+    /// Invokes `f` with a reference to the key and value of every entry, in
+    /// arbitrary order, on the threads of the rayon pool.
+    ///
+    /// The entries are collected under an epoch guard of the calling thread,
+    /// which is held until `f` has been invoked with all of them, and are split
+    /// into chunks that the tasks of the pool visit in parallel. A map with few
+    /// entries is visited by the calling thread alone. Like [`for_each`], the
+    /// traversal is weakly consistent, and `f` may modify the map.
+    ///
+    /// This method is only available with the `rayon` feature.
+    ///
+    /// [`for_each`]: #method.for_each
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::HashMap;
+    ///
+    /// use std::sync::atomic::{AtomicU64, Ordering};
+    ///
+    /// let map = HashMap::new();
+    ///
+    /// for i in 0..100_000u64 {
+    ///     map.insert(i, i);
+    /// }
+    ///
+    /// let sum = AtomicU64::new(0);
+    /// map.par_for_each(|_, v| {
+    ///     sum.fetch_add(*v, Ordering::Relaxed);
+    /// });
+    ///
+    /// assert_eq!(sum.into_inner(), (0..100_000).sum());
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn par_for_each<F: Fn(&K, &V) + Sync>(&self, f: F)
+    where
+        K: Sync,
+        V: Sync,
+    {
+        let _panic_guard = self.panic_policy.enter();
+        let guard = &crossbeam_epoch::pin();
+        let buckets = self.bucket_array_ref().live_buckets_in_memory_order(guard);
+
+        parallel::for_each(&buckets, &f);
+    }
+
+    /// Returns a parallel iterator over clones of the key-value pairs of the
+    /// map, in arbitrary order.
+    ///
+    /// The entries are cloned by the tasks of the rayon pool, like
+    /// [`par_for_each`] visits them, before the iterator is returned, since the
+    /// epoch guard that protects them can not be handed to the pool. Prefer
+    /// [`par_for_each`] to avoid the clones.
+    ///
+    /// This method is only available with the `rayon` feature.
+    ///
+    /// [`par_for_each`]: #method.par_for_each
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::HashMap;
+    /// use rayon::prelude::*;
+    ///
+    /// let map = HashMap::new();
+    ///
+    /// for i in 0..100_000u64 {
+    ///     map.insert(i, i);
+    /// }
+    ///
+    /// assert_eq!(map.par_iter().filter(|(_, v)| v % 2 == 0).count(), 50_000);
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn par_iter(&self) -> impl rayon::iter::ParallelIterator<Item = (K, V)>
+    where
+        K: Clone + Send + Sync,
+        V: Clone + Send + Sync,
+    {
+        use rayon::prelude::*;
+
+        let guard = &crossbeam_epoch::pin();
+        let buckets = self.bucket_array_ref().live_buckets_in_memory_order(guard);
+
+        parallel::cloned(&buckets).into_par_iter()
+    }
+
+    /// An iterator visiting all entries in the order of the slots that hold
+    /// them, which is the fastest way to traverse the map.
+    ///
+    /// Nothing is collected up front: the bucket pointer array is read in
+    /// place as the iterator is consumed, one cluster of occupied slots at a
+    /// time, and the buckets of each cluster are prefetched as soon as it is
+    /// read. The order is arbitrary even for a [deterministic] map, and the
+    /// iterator is weakly consistent, as described for [`UnorderedIter`].
+    /// Prefer this for full scans, such as those of maintenance tasks, that do
+    /// not depend on the order of the entries.
+    ///
+    /// [deterministic]: ./struct.Builder.html#method.deterministic
+    /// [`UnorderedIter`]: ./struct.UnorderedIter.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::HashMap;
+    ///
+    /// let map = HashMap::new();
+    ///
+    /// for i in 0..100 {
+    ///     map.insert(i, i);
+    /// }
+    ///
+    /// let sum: i32 = map.iter_unordered_fast().map(|r| *r.value()).sum();
+    /// assert_eq!(sum, (0..100).sum());
+    /// ```
+    pub fn iter_unordered_fast(&self) -> UnorderedIter<'_, K, V, S> {
+        UnorderedIter::new(vec![self.bucket_array_ref()])
+    }
+
+    /// An iterator visiting the entries whose generation is at most
+    /// `generation`, in arbitrary order.
+    ///
+    /// A traversal started with the value returned by [`advance_generation`]
+    /// never observes keys inserted after that call returned, which is a
+    /// weaker but much cheaper form of consistency than a snapshot. Entries
+    /// removed or modified during the traversal may or may not be observed,
+    /// and insertions that overlap the call to `advance_generation` may be
+    /// stamped with either generation.
+    ///
+    /// This method is only available with the `generations` feature.
+    ///
+    /// [`advance_generation`]: #method.advance_generation
+    #[cfg(feature = "generations")]
+    pub fn iter_upto_generation(&self, generation: u64) -> Iter<'_, K, V> {
+        let guard = crossbeam_epoch::pin();
+        let mut buckets = self.bucket_array_ref().live_buckets(&guard);
+        buckets.retain(|b| b.generation() <= generation);
+        let buckets = bucket::into_ptrs(buckets);
+
+        unsafe { Iter::new(guard, buckets) }
+    }
+
+    /// An iterator visiting the entries whose keys hash into the inclusive
+    /// range from `lo` to `hi`, in arbitrary order.
+    ///
+    /// The iterator is weakly consistent, like the one returned by
+    /// [`iter`]. See the [`sharding`] module for details.
+    ///
+    /// [`iter`]: #method.iter
+    /// [`sharding`]: ../sharding/index.html
+    pub fn entries_in_hash_range(&self, lo: u64, hi: u64) -> Iter<'_, K, V> {
+        let guard = crossbeam_epoch::pin();
+        let mut buckets = self.bucket_array_ref().live_buckets(&guard);
+        buckets.retain(|b| (lo..=hi).contains(&bucket::hash(&self.build_hasher, &b.key)));
+        let buckets = bucket::into_ptrs(buckets);
+
+        unsafe { Iter::new(guard, buckets) }
+    }
+
+    /// Returns a [`Ref`] to an entry of the map picked at random, or `None` if
+    /// the map is empty.
+    ///
+    /// A slot of the bucket pointer array is picked at random, and the first
+    /// entry from that slot on is returned. This takes constant time on
+    /// average and does not collect the entries, which makes it suitable for
+    /// random eviction and sampling-based estimates. The choice is only
+    /// approximately uniform: an entry that follows a long run of empty slots
+    /// is more likely to be returned than one that follows another entry.
+    ///
+    /// [`Ref`]: ./struct.Ref.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::HashMap;
+    ///
+    /// let map = HashMap::new();
+    /// assert!(map.random_entry().is_none());
+    ///
+    /// for i in 0..100 {
+    ///     map.insert(i, i * 2);
+    /// }
+    ///
+    /// let entry = map.random_entry().unwrap();
+    /// assert_eq!(*entry.value(), entry.key() * 2);
+    /// ```
+    pub fn random_entry(&self) -> Option<Ref<'_, K, V>> {
+        let guard = crossbeam_epoch::pin();
+        let bucket_ptr: *const Bucket<K, V> = self
+            .bucket_array_ref()
+            .random_bucket(&guard, metrics::random())?;
+
+        Some(unsafe { Ref::new(guard, bucket_ptr) })
+    }
+
+    /// Returns an iterator over the next entries of the scan at `cursor`, and
+    /// moves `cursor` past them.
+    ///
+    /// At least `limit` entries are returned unless the scan completes, and
+    /// possibly a few more, as the entries are visited by the slots of their
+    /// keys in the bucket pointer array. The map may be modified and resized
+    /// between the calls. See [`ScanCursor`] for which entries are returned.
+    ///
+    /// [`ScanCursor`]: ./struct.ScanCursor.html
+    pub fn scan(&self, cursor: &mut ScanCursor, limit: usize) -> Iter<'_, K, V> {
+        let guard = crossbeam_epoch::pin();
+
+        if cursor.is_done() {
+            return unsafe { Iter::new(guard, Vec::new()) };
+        }
+
+        let (buckets, next) = self
+            .bucket_array_ref()
+            .scan(&guard, cursor.position(), limit);
+        let buckets = bucket::into_ptrs(buckets);
+        cursor.advance(next, 1);
+
+        unsafe { Iter::new(guard, buckets) }
+    }
+
+    /// Invokes `f` with the next entries of the scan at `cursor`, and returns
+    /// the cursor moved past them.
+    ///
+    /// This visits the same entries as [`scan`], but no epoch guard outlives
+    /// the call, so a large map can be swept a batch at a time, for example a
+    /// few thousand entries per tick, by keeping only the returned cursor
+    /// between the calls.
+    ///
+    /// [`scan`]: #method.scan
+    pub fn scan_and<F: FnMut(&K, &V)>(
+        &self,
+        mut cursor: ScanCursor,
+        limit: usize,
+        mut f: F,
+    ) -> ScanCursor {
+        if cursor.is_done() {
+            return cursor;
+        }
+
+        let guard = &crossbeam_epoch::pin();
+        let (buckets, next) = self
+            .bucket_array_ref()
+            .scan(guard, cursor.position(), limit);
+
+        for b in buckets {
+            f(&b.key, unsafe { b.value() });
+        }
+
+        cursor.advance(next, 1);
+
+        cursor
+    }
+
+    /// Returns a stream of clones of the key-value pairs of the map, which are
+    /// scanned in batches of about `batch_size` entries.
+    ///
+    /// Each batch is scanned like with [`scan_and`] when the stream runs out
+    /// of cloned entries, under an epoch guard that is dropped before the
+    /// entries are yielded, so the stream never holds a guard across an
+    /// `.await`, and a poll never visits more than one batch. The stream has
+    /// the guarantees of a scan with a [`ScanCursor`], even if the map is
+    /// modified or resized between the batches.
+    ///
+    /// This method is only available with the `futures` feature.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `batch_size` is 0.
+    ///
+    /// [`scan_and`]: #method.scan_and
+    /// [`ScanCursor`]: ./struct.ScanCursor.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::HashMap;
+    /// use futures::{executor, StreamExt};
+    ///
+    /// let map = HashMap::new();
+    ///
+    /// for i in 0..1000 {
+    ///     map.insert(i, i);
+    /// }
+    ///
+    /// let stream = map.entry_stream(64);
+    /// let sum = executor::block_on(stream.fold(0, |sum, (_, v)| async move { sum + v }));
+    ///
+    /// assert_eq!(sum, (0..1000).sum());
+    /// ```
+    #[cfg(feature = "futures")]
+    pub fn entry_stream(&self, batch_size: usize) -> impl futures_core::Stream<Item = (K, V)> + '_
+    where
+        K: Clone,
+        V: Clone,
+    {
+        crate::map::entry_stream::EntryStream::new(batch_size, move |cursor, limit, batch| {
+            self.scan_and(cursor, limit, |k, v| batch.push((k.clone(), v.clone())))
+        })
+    }
+
+    /// Splits the map into `n` disjoint [`Partition`]s, which can be scanned
+    /// independently with [`scan_partition`], for example on the threads of a
+    /// pool.
+    ///
+    /// The partitions split the scan positions of the keys into ranges of equal
+    /// size, so they hold about the same number of entries. See [`Partition`]
+    /// for details.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is 0.
+    ///
+    /// [`Partition`]: ./struct.Partition.html
+    /// [`scan_partition`]: #method.scan_partition
+    pub fn partitions(&self, n: usize) -> Vec<Partition> {
+        Partition::split(n, 1)
+    }
+
+    /// Returns an iterator over the next entries of `partition`, and moves
+    /// `partition` past them.
+    ///
+    /// At least `limit` entries are returned unless the scan of the partition
+    /// completes, and possibly a few more, like [`scan`]. See [`Partition`]
+    /// for details.
+    ///
+    /// [`scan`]: #method.scan
+    /// [`Partition`]: ./struct.Partition.html
+    pub fn scan_partition(&self, partition: &mut Partition, limit: usize) -> Iter<'_, K, V> {
+        let guard = crossbeam_epoch::pin();
+
+        if partition.is_done() {
+            return unsafe { Iter::new(guard, Vec::new()) };
+        }
+
+        let (mut buckets, next) = self
+            .bucket_array_ref()
+            .scan(&guard, partition.position(), limit);
+
+        if let Some(end) = partition.cut(next) {
+            buckets
+                .retain(|b| bucket::scan_position(bucket::hash(&self.build_hasher, &b.key)) < end);
+        }
+
+        let buckets = bucket::into_ptrs(buckets);
+        partition.advance(next);
+
+        unsafe { Iter::new(guard, buckets) }
+    }
+
+    /// Returns the hash of `key` as computed by the map's hasher.
+    ///
+    /// This is the hash that [`entries_in_hash_range`] selects entries by.
+    ///
+    /// [`entries_in_hash_range`]: #method.entries_in_hash_range
+    pub fn key_hash<Q: Hash + ?Sized>(&self, key: &Q) -> u64
+    where
+        K: Borrow<Q>,
+    {
+        bucket::hash(&self.build_hasher, key)
+    }
+
+    /// Returns an iterator over at most `limit` entries of the map,
+    /// approximately least recently used first.
+    ///
+    /// An entry is used when its key is inserted, its value is modified, or it
+    /// is found by a lookup. Uses are recorded as ticks of a coarse access
+    /// clock that every call to this method advances, so entries used between
+    /// the same two calls are equally stale and are yielded in arbitrary order.
+    /// Embedders can implement eviction by calling this method periodically and
+    /// removing the yielded entries until they are under budget, without
+    /// maintaining a separate LRU list.
+    ///
+    /// This scans the whole map and sorts the stalest entries.
+    ///
+    /// This method is only available with the `access-times` feature.
+    #[cfg(feature = "access-times")]
+    pub fn scan_by_staleness(&self, limit: usize) -> Iter<'_, K, V> {
+        let guard = crossbeam_epoch::pin();
+        self.access_clock.fetch_add(1, ordering::RELAXED);
+
+        let buckets = self.bucket_array_ref().live_buckets(&guard);
+        let buckets = bucket::into_ptrs(bucket::stalest(buckets, limit));
+
+        unsafe { Iter::new(guard, buckets) }
+    }
+
+    /// Returns an order-independent fingerprint of the entries of the map.
+    ///
+    /// The fingerprint is the wrapping sum of the hashes of all key-value
+    /// pairs, computed with the map's hasher. Maps with equal entries have
+    /// equal fingerprints if their hashers produce the same hashes, which is
+    /// not the case for two instances of the default hasher, so maps compared
+    /// across replicas should use a deterministic hasher.
+    ///
+    /// The fingerprint is weakly consistent: entries that are inserted,
+    /// modified, or removed concurrently may or may not be reflected in it.
+    pub fn content_fingerprint(&self) -> u64
+    where
+        V: Hash,
+    {
+        let guard = &crossbeam_epoch::pin();
+
+        self.bucket_array_ref()
+            .live_buckets(guard)
+            .into_iter()
+            .map(|b| bucket::hash(&self.build_hasher, &(&b.key, unsafe { b.value() })))
+            .fold(0, u64::wrapping_add)
+    }
+
+    /// Returns a point-in-time copy of the entries of the map, along with their
+    /// hashes.
+    ///
+    /// See the [`snapshot`] module for details.
+    ///
+    /// [`snapshot`]: ../snapshot/index.html
+    pub fn snapshot(&self) -> Snapshot<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let guard = &crossbeam_epoch::pin();
+        let entries: Vec<_> = self
+            .bucket_array_ref()
+            .live_buckets(guard)
+            .into_iter()
+            .map(|b| {
+                (bucket::hash(&self.build_hasher, &b.key), &b.key, unsafe {
+                    b.value()
+                })
+            })
+            .collect();
+
+        Snapshot::new(
+            entries
+                .into_iter()
+                .map(|(h, k, v)| (h, k.clone(), v.clone()))
+                .collect(),
+        )
+    }
+
+    /// Returns the keys that were added, removed, or whose values changed since
+    /// `snapshot` was taken.
+    ///
+    /// The values of the map are compared in place without being cloned.
+    /// `snapshot` must have been taken from this map. See the [`snapshot`]
+    /// module for details.
+    ///
+    /// [`snapshot`]: ../snapshot/index.html
+    pub fn changes_since(&self, snapshot: &Snapshot<K, V>) -> SnapshotDiff<K>
+    where
+        K: Clone,
+        V: PartialEq,
+    {
+        let guard = &crossbeam_epoch::pin();
+        let mut entries: Vec<_> = self
+            .bucket_array_ref()
+            .live_buckets(guard)
+            .into_iter()
+            .map(|b| {
+                (bucket::hash(&self.build_hasher, &b.key), &b.key, unsafe {
+                    b.value()
+                })
+            })
+            .collect();
+
+        entries.sort_unstable_by_key(|&(hash, _, _)| hash);
+
+        SnapshotDiff::between(&snapshot.sorted_entries(), &entries)
+    }
+
+    /// Returns clones of the key-value pairs of the map, in arbitrary order.
+    ///
+    /// The entries are cloned under a single epoch guard. The result is weakly
+    /// consistent: entries that are inserted, modified, or removed
+    /// concurrently may or may not be reflected in it. Unlike [`snapshot`],
+    /// the hashes of the keys are not computed, so this is the cheapest way to
+    /// dump the map, for example for debugging.
+    ///
+    /// [`snapshot`]: #method.snapshot
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::HashMap;
+    ///
+    /// let map = HashMap::new();
+    /// map.insert("a", 1);
+    /// map.insert("b", 2);
+    ///
+    /// let mut entries = map.entries_snapshot();
+    /// entries.sort_unstable();
+    ///
+    /// assert_eq!(entries, [("a", 1), ("b", 2)]);
+    /// ```
+    pub fn entries_snapshot(&self) -> Vec<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let guard = &crossbeam_epoch::pin();
+
+        self.bucket_array_ref()
+            .live_buckets(guard)
+            .into_iter()
+            .map(|b| (b.key.clone(), unsafe { b.value() }.clone()))
+            .collect()
+    }
+
+    /// Returns clones of the keys of the map, in arbitrary order.
+    ///
+    /// See [`entries_snapshot`] for details.
+    ///
+    /// [`entries_snapshot`]: #method.entries_snapshot
+    pub fn keys_snapshot(&self) -> Vec<K>
+    where
+        K: Clone,
+    {
+        let guard = &crossbeam_epoch::pin();
+
+        self.bucket_array_ref()
+            .live_buckets(guard)
+            .into_iter()
+            .map(|b| b.key.clone())
+            .collect()
+    }
+
+    /// Returns clones of the values of the map, in arbitrary order.
+    ///
+    /// See [`entries_snapshot`] for details.
+    ///
+    /// [`entries_snapshot`]: #method.entries_snapshot
+    pub fn values_snapshot(&self) -> Vec<V>
+    where
+        V: Clone,
+    {
+        let guard = &crossbeam_epoch::pin();
+
+        self.bucket_array_ref()
+            .live_buckets(guard)
+            .into_iter()
+            .map(|b| unsafe { b.value() }.clone())
+            .collect()
+    }
+
+    /// Returns a view of the entries of the map, which can be read and
+    /// iterated repeatedly while the map is modified.
+    ///
+    /// The view keeps the current thread pinned until it is dropped. See
+    /// [`SnapshotView`] for details.
+    ///
+    /// [`SnapshotView`]: ./struct.SnapshotView.html
+    pub fn snapshot_view(&self) -> SnapshotView<'_, K, V, S> {
+        let guard = crossbeam_epoch::pin();
+        let buckets = self
+            .bucket_array_ref()
+            .live_buckets(&guard)
+            .into_iter()
+            .map(|b| (bucket::hash(&self.build_hasher, &b.key), b as *const _))
+            .collect();
+
+        unsafe { SnapshotView::new(guard, buckets, &self.build_hasher) }
+    }
+
+    /// Inserts a key-value pair into the map, returning a clone of the value
+    /// previously corresponding to the key.
+    ///
+    /// If the map did have this key present, both the key and value are
+    /// updated.
+    ///
+    /// The new value is always stored in a new bucket, and the previous one
+    /// is retired to the garbage collector, even if the values have the same
+    /// layout: other threads may still hold references to the previous value,
+    /// through a [`Ref`], an iterator, or a [`SnapshotView`], so it must not
+    /// be overwritten in place. [`insert_mut`] overwrites it in place, as it
+    /// borrows the map exclusively.
+    ///
+    /// [`Ref`]: ./struct.Ref.html
+    /// [`SnapshotView`]: ./struct.SnapshotView.html
+    /// [`insert_mut`]: #method.insert_mut
+    #[inline]
+    pub fn insert(&self, key: K, value: V) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.insert_entry_and(key, value, |_, v| v.clone())
+    }
+
+    /// Inserts a key-value pair into the map, returning a clone of the
+    /// key-value pair previously corresponding to the supplied key.
+    ///
+    /// If the map did have this key present, both the key and value are
+    /// updated.
+    #[inline]
+    pub fn insert_entry(&self, key: K, value: V) -> Option<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.insert_entry_and(key, value, |k, v| (k.clone(), v.clone()))
+    }
+
+    /// Inserts a key-value pair into the map, returning the result of invoking
+    /// a function with a reference to the value previously corresponding to the
+    /// key.
+    ///
+    /// If the map did have this key present, both the key and value are
+    /// updated.
+    #[inline]
+    pub fn insert_and<F: FnOnce(&V) -> T, T>(
+        &self,
+        key: K,
+        value: V,
+        with_previous_value: F,
+    ) -> Option<T> {
+        self.insert_entry_and(key, value, move |_, v| with_previous_value(v))
+    }
+
+    /// Inserts a key-value pair into the map, returning the result of invoking
+    /// a function with a reference to the key-value pair previously
+    /// corresponding to the supplied key.
+    ///
+    /// If the map did have this key present, both the key and value are
+    /// updated.
+    #[inline]
+    pub fn insert_entry_and<F: FnOnce(&K, &V) -> T, T>(
+        &self,
+        key: K,
+        value: V,
+        with_previous_entry: F,
+    ) -> Option<T> {
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        self.bucket_array_ref()
+            .insert_entry_and(key, hash, value, with_previous_entry)
+    }
+
+    /// Inserts a key-value pair into the map like [`insert`], unless the
+    /// weight of the pair does not fit in the memory budget of the map.
+    ///
+    /// A map without a memory budget always inserts the pair. See
+    /// [`MemoryBudget`] for details.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MemoryBudgetExceeded`] if charging the weight of the
+    /// pair would exceed the limit of the memory budget of the map, in which
+    /// case the key and the value are dropped and the map is left unchanged.
+    ///
+    /// [`insert`]: #method.insert
+    /// [`MemoryBudget`]: ./struct.MemoryBudget.html
+    /// [`Error::MemoryBudgetExceeded`]: ../enum.Error.html#variant.MemoryBudgetExceeded
+    pub fn try_insert(&self, key: K, value: V) -> Result<Option<V>, Error>
+    where
+        V: Clone,
+    {
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        self.bucket_array_ref()
+            .try_insert_entry_and(key, hash, value, |_, v| v.clone())
+    }
+
+    /// Inserts a key-value pair into the map, returning a clone of the value
+    /// previously corresponding to the key.
+    ///
+    /// This is equivalent to [`insert`], except that the key is written
+    /// directly into the slot described by `hint` if it is still valid. A hint
+    /// that was obtained for another key or that is no longer valid is ignored.
+    ///
+    /// [`insert`]: #method.insert
+    pub fn insert_with_hint(&self, key: K, value: V, hint: SlotHint) -> Option<V>
+    where
+        V: Clone,
+    {
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        self.bucket_array_ref()
+            .insert_entry_with_hint_and(key, hash, value, Some(hint), |_, v| v.clone())
+    }
+
+    /// Inserts a key-value pair into the map if `condition` returns `true` for
+    /// the current [`MapState`], returning a clone of the value previously
+    /// corresponding to the key.
+    ///
+    /// A slot is reserved in the length of the map before `condition` is
+    /// called, and released afterwards, so that concurrent calls see distinct
+    /// lengths and cannot all admit a key past a bound on the length. While the
+    /// reservation is held, [`len`] may be overestimated by one.
+    ///
+    /// Returns the key-value pair back if `condition` returned `false`.
+    ///
+    /// [`MapState`]: ./struct.MapState.html
+    /// [`len`]: #method.len
+    pub fn insert_if<F: FnOnce(&MapState) -> bool>(
+        &self,
+        key: K,
+        value: V,
+        condition: F,
+    ) -> Result<Option<V>, (K, V)>
+    where
+        V: Clone,
+    {
+        let _panic_guard = self.panic_policy.enter();
+        let (_reservation, len) = Reservation::acquire(&self.len);
+        let state = MapState {
+            map_len: len,
+            segment_len: len,
+            segment_capacity: self.capacity(),
+        };
+
+        if condition(&state) {
+            Ok(self.insert(key, value))
+        } else {
+            Err((key, value))
+        }
+    }
+
+    /// Removes a key from the map, returning a clone of the value previously
+    /// corresponding to the key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[inline]
+    pub fn remove<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        V: Clone,
+    {
+        self.remove_entry_if_and(key, |_, _| true, |_, v| v.clone())
+    }
+
+    /// Removes a key from the map, returning a clone of the key-value pair
+    /// previously corresponding to the key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[inline]
+    pub fn remove_entry<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q> + Clone,
+        V: Clone,
+    {
+        self.remove_entry_if_and(key, |_, _| true, |k, v| (k.clone(), v.clone()))
+    }
+
+    /// Remove a key from the map, returning the result of invoking a function
+    /// with a reference to the value previously corresponding to the key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[inline]
+    pub fn remove_and<Q: Hash + Eq + ?Sized, F: FnOnce(&V) -> T, T>(
+        &self,
+        key: &Q,
+        with_previous_value: F,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+    {
+        self.remove_entry_if_and(key, |_, _| true, move |_, v| with_previous_value(v))
+    }
+
+    /// Removes a key from the map, returning the result of invoking a function
+    /// with a reference to the key-value pair previously corresponding to the
+    /// key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[inline]
+    pub fn remove_entry_and<Q: Hash + Eq + ?Sized, F: FnOnce(&K, &V) -> T, T>(
+        &self,
+        key: &Q,
+        with_previous_entry: F,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+    {
+        self.remove_entry_if_and(key, |_, _| true, with_previous_entry)
+    }
+
+    /// Removes a key from the map, returning the result of invoking a function
+    /// with a reference to the key-value pair previously corresponding to the
+    /// key, along with a hint of the slot it occupied.
+    ///
+    /// Passing the hint to [`insert_with_hint`] reinserts the key into the same
+    /// slot without probing, if the slot is still valid. This makes refreshing
+    /// an entry by removing and reinserting it cheaper.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`insert_with_hint`]: #method.insert_with_hint
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn remove_entry_with_hint_and<Q: Hash + Eq + ?Sized, F: FnOnce(&K, &V) -> T, T>(
+        &self,
+        key: &Q,
+        with_previous_entry: F,
+    ) -> Option<(T, SlotHint)>
+    where
+        K: Borrow<Q>,
+    {
+        let hash = bucket::hash(&self.build_hasher, key);
+
+        self.bucket_array_ref().remove_entry_if_with_hint_and(
+            key,
+            hash,
+            |_, _| true,
+            with_previous_entry,
+        )
+    }
+
+    /// Removes a key from the map if a condition is met, returning a clone of
+    /// the value previously corresponding to the key.
+    ///
+    /// `condition` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    pub fn remove_if<Q: Hash + Eq + ?Sized, F: FnMut(&K, &V) -> bool>(
+        &self,
+        key: &Q,
+        condition: F,
+    ) -> Option<V>
+    where
+        K: Borrow<Q>,
+        V: Clone,
+    {
+        self.remove_entry_if_and(key, condition, move |_, v| v.clone())
+    }
+
+    /// Removes a key from the map if a condition is met, returning a clone of
+    /// the value previously corresponding to the key, or why it was not
+    /// removed.
+    ///
+    /// This is [`remove_if`], except that a failed removal tells apart a key
+    /// that was absent, an entry that `condition` rejected, and an entry that
+    /// `condition` accepted but that was removed concurrently before this
+    /// removal could be applied. If the entry is replaced concurrently,
+    /// `condition` is invoked again with the new value, and the removal fails
+    /// only if it rejects it.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`NotRemoved`] with the reason why the entry was not
+    /// removed, in which case the map is left unchanged.
+    ///
+    /// [`remove_if`]: #method.remove_if
+    /// [`NotRemoved`]: ./struct.NotRemoved.html
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::{map::NotRemovedReason, HashMap};
+    ///
+    /// let map = HashMap::new();
+    /// map.insert("pinned", true);
+    /// map.insert("stale", false);
+    ///
+    /// let evict = |_: &&str, &pinned: &bool| !pinned;
+    ///
+    /// assert_eq!(map.try_remove_if("stale", evict), Ok(false));
+    /// assert_eq!(
+    ///     map.try_remove_if("stale", evict).unwrap_err().reason(),
+    ///     NotRemovedReason::Absent
+    /// );
+    /// assert_eq!(
+    ///     map.try_remove_if("pinned", evict).unwrap_err().reason(),
+    ///     NotRemovedReason::ConditionFalse
+    /// );
+    /// ```
+    pub fn try_remove_if<Q: Hash + Eq + ?Sized, F: FnMut(&K, &V) -> bool>(
+        &self,
+        key: &Q,
+        mut condition: F,
+    ) -> Result<V, NotRemoved>
+    where
+        K: Borrow<Q>,
+        V: Clone,
+    {
+        let mut last_decision = None;
+
+        self.remove_entry_if_and(
+            key,
+            |k, v| {
+                let decision = condition(k, v);
+                last_decision = Some(decision);
+
+                decision
+            },
+            |_, v| v.clone(),
+        )
+        .ok_or_else(|| NotRemoved::from_last_decision(last_decision))
     }
 
-    /// Inserts a key-value pair into the map, returning the result of invoking
-    /// a function with a reference to the value previously corresponding to the
-    /// key.
+    /// Removes a key from the map if a condition is met, returning a clone of
+    /// the key-value pair previously corresponding to the key.
     ///
-    /// If the map did have this key present, both the key and value are
-    /// updated.
+    /// `condition` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
     #[inline]
-    pub fn insert_and<F: FnOnce(&V) -> T, T>(
+    pub fn remove_entry_if<Q: Hash + Eq + ?Sized, F: FnMut(&K, &V) -> bool>(
         &self,
-        key: K,
-        value: V,
-        with_previous_value: F,
-    ) -> Option<T> {
-        self.insert_entry_and(key, value, move |_, v| with_previous_value(v))
+        key: &Q,
+        condition: F,
+    ) -> Option<(K, V)>
+    where
+        K: Clone + Borrow<Q>,
+        V: Clone,
+    {
+        self.remove_entry_if_and(key, condition, move |k, v| (k.clone(), v.clone()))
     }
 
-    /// Inserts a key-value pair into the map, returning the result of invoking
-    /// a function with a reference to the key-value pair previously
-    /// corresponding to the supplied key.
+    /// Remove a key from the map if a condition is met, returning the result of
+    /// invoking a function with a reference to the value previously
+    /// corresponding to the key.
     ///
-    /// If the map did have this key present, both the key and value are
-    /// updated.
+    /// `condition` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
     #[inline]
-    pub fn insert_entry_and<F: FnOnce(&K, &V) -> T, T>(
+    pub fn remove_if_and<Q: Hash + Eq + ?Sized, F: FnMut(&K, &V) -> bool, G: FnOnce(&V) -> T, T>(
         &self,
-        key: K,
-        value: V,
-        with_previous_entry: F,
-    ) -> Option<T> {
-        let hash = bucket::hash(&self.build_hasher, &key);
+        key: &Q,
+        condition: F,
+        with_previous_value: G,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+    {
+        self.remove_entry_if_and(key, condition, move |_, v| with_previous_value(v))
+    }
 
-        self.bucket_array_ref()
-            .insert_entry_and(key, hash, value, with_previous_entry)
+    /// Removes a key from the map if a condition is met, evaluating the
+    /// condition only once against a clone of the value, and returning the
+    /// result of invoking a function with a reference to the value previously
+    /// corresponding to the key.
+    ///
+    /// Unlike [`remove_if_and`], `condition` is invoked at most once, on a
+    /// clone of the value taken before the removal is attempted, and while the
+    /// current thread is not pinned, so it may be expensive or non-reentrant.
+    /// The semantics are weaker: the key is only removed if its value is still
+    /// equal to the clone when the removal is attempted. If the value has been
+    /// modified in the meantime, nothing is removed and [`None`] is returned,
+    /// without invoking `condition` again. This is
+    /// [`remove_if_unchanged_and`] applied to the result of a lookup.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`remove_if_and`]: #method.remove_if_and
+    /// [`remove_if_unchanged_and`]: #method.remove_if_unchanged_and
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    pub fn remove_if_cloned_and<
+        Q: Hash + Eq + ?Sized,
+        F: FnOnce(&V) -> bool,
+        G: FnOnce(&V) -> T,
+        T,
+    >(
+        &self,
+        key: &Q,
+        condition: F,
+        with_previous_value: G,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+        V: Clone + PartialEq,
+    {
+        let value = self.get(key)?;
+
+        if condition(&value) {
+            self.remove_if_unchanged_and(key, &value, with_previous_value)
+        } else {
+            None
+        }
     }
 
-    /// Removes a key from the map, returning a clone of the value previously
+    /// Removes a key from the map if its value is equal to `expected`,
+    /// returning the result of invoking a function with a reference to the
+    /// value previously corresponding to the key.
+    ///
+    /// This completes a removal whose condition was evaluated on a clone of the
+    /// value obtained earlier, for example by [`get`], possibly across an
+    /// `.await` point of an asynchronous predicate. The removal only takes
+    /// place if the value has not been modified since, as far as [`PartialEq`]
+    /// can tell.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`get`]: #method.get
+    /// [`PartialEq`]: https://doc.rust-lang.org/std/cmp/trait.PartialEq.html
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[inline]
+    pub fn remove_if_unchanged_and<Q: Hash + Eq + ?Sized, G: FnOnce(&V) -> T, T>(
+        &self,
+        key: &Q,
+        expected: &V,
+        with_previous_value: G,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+        V: PartialEq,
+    {
+        self.remove_if_and(key, |_, v| v == expected, with_previous_value)
+    }
+
+    /// Removes every entry for which `predicate` returns `false`, returning
+    /// the number of entries removed.
+    ///
+    /// The entries are visited in the order of their slots under a single epoch guard, and each of them
+    /// is removed with the same conditional removal as [`remove_if`], so
+    /// `predicate` is evaluated against the value the removal takes effect on.
+    /// It is invoked at least once for every entry that is in the map for the
+    /// whole call, and may be invoked again for an entry that is concurrently
+    /// modified. Like a sequence of calls to [`remove_if`], this is not
+    /// atomic: entries that are inserted concurrently may or may not be
+    /// visited.
+    ///
+    /// The removed entries are destroyed once the garbage of the current
+    /// thread is flushed. A map built with the `flush_after_bulk_removals`
+    /// option of its builder flushes it before returning.
+    ///
+    /// [`remove_if`]: #method.remove_if
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::HashMap;
+    ///
+    /// let sessions = HashMap::new();
+    ///
+    /// for id in 0..10 {
+    ///     sessions.insert(id, id * 100);
+    /// }
+    ///
+    /// // drop the sessions that were last seen before 500
+    /// assert_eq!(sessions.retain(|_, &last_seen| last_seen >= 500), 5);
+    /// assert_eq!(sessions.len(), 5);
+    /// assert_eq!(sessions.get(&7), Some(700));
+    /// ```
+    pub fn retain<F: FnMut(&K, &V) -> bool>(&self, mut predicate: F) -> usize {
+        self.extract_if_and(|k, v| !predicate(k, v), |_, _| ())
+    }
+
+    /// Removes every entry, invoking `with_entry` with each removed key-value
+    /// pair, and returns the number of entries removed.
+    ///
+    /// The slots of the bucket pointer array are visited in order, and each
+    /// live bucket is turned into a tombstone with a single atomic operation,
+    /// without hashing its key or probing for it as [`remove`] would. Each
+    /// entry is handed to exactly one caller: an entry removed or replaced
+    /// concurrently is either drained or left to the operation that won the
+    /// race. Like a sequence of calls to [`remove`], this is not atomic:
+    /// entries that are inserted concurrently may or may not be drained. See
+    /// [`retain`] for when the drained entries are destroyed.
+    ///
+    /// The references passed to `with_entry` are only valid for the duration
+    /// of the call.
+    ///
+    /// [`remove`]: #method.remove
+    /// [`retain`]: #method.retain
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::HashMap;
+    ///
+    /// let dirty = HashMap::new();
+    /// dirty.insert("a", 1);
+    /// dirty.insert("b", 2);
+    ///
+    /// let mut flushed = Vec::new();
+    /// assert_eq!(dirty.drain_and(|k, v| flushed.push((*k, *v))), 2);
+    ///
+    /// flushed.sort_unstable();
+    /// assert_eq!(flushed, [("a", 1), ("b", 2)]);
+    /// assert!(dirty.is_empty());
+    /// ```
+    pub fn drain_and<F: FnMut(&K, &V)>(&self, with_entry: F) -> usize {
+        let removed = self.bucket_array_ref().drain_and(with_entry);
+        self.reclaim_bulk_removals(removed);
+
+        removed
+    }
+
+    /// Removes every entry, returning clones of the removed key-value pairs in
+    /// arbitrary order.
+    ///
+    /// See [`drain_and`] for details.
+    ///
+    /// [`drain_and`]: #method.drain_and
+    pub fn drain(&self) -> Vec<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let mut entries = Vec::with_capacity(self.len());
+        self.drain_and(|k, v| entries.push((k.clone(), v.clone())));
+
+        entries
+    }
+
+    /// Removes every entry for which `predicate` returns `true`, invoking
+    /// `with_entry` with each removed key-value pair, and returns the number of
+    /// entries removed.
+    ///
+    /// This is [`retain`] with the opposite predicate, except that the removed
+    /// entries are handed to `with_entry`, which is invoked at most once for
+    /// each of them with the value that `predicate` last accepted. See
+    /// [`retain`] for how concurrent modifications are handled.
+    ///
+    /// The references passed to `with_entry` are only valid for the duration
+    /// of the call.
+    ///
+    /// [`retain`]: #method.retain
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::HashMap;
+    ///
+    /// let cache = HashMap::new();
+    ///
+    /// for id in 0..10 {
+    ///     cache.insert(id, id * 100);
+    /// }
+    ///
+    /// // expire the entries that were last seen before 300
+    /// let mut expired = Vec::new();
+    /// let num_expired = cache.extract_if_and(
+    ///     |_, &last_seen| last_seen < 300,
+    ///     |&id, _| expired.push(id),
+    /// );
+    ///
+    /// expired.sort_unstable();
+    /// assert_eq!(num_expired, 3);
+    /// assert_eq!(expired, [0, 1, 2]);
+    /// assert_eq!(cache.len(), 7);
+    /// ```
+    pub fn extract_if_and<F: FnMut(&K, &V) -> bool, G: FnMut(&K, &V)>(
+        &self,
+        mut predicate: F,
+        mut with_entry: G,
+    ) -> usize {
+        let bucket_array_ref = self.bucket_array_ref();
+        let mut removed = 0;
+
+        {
+            let guard = &crossbeam_epoch::pin();
+
+            for b in bucket_array_ref.live_buckets(guard) {
+                let hash = bucket::hash(&self.build_hasher, &b.key);
+
+                if bucket_array_ref
+                    .remove_entry_if_and(&b.key, hash, &mut predicate, &mut with_entry)
+                    .is_some()
+                {
+                    removed += 1;
+                }
+            }
+        }
+
+        self.reclaim_bulk_removals(removed);
+
+        removed
+    }
+
+    /// Removes every entry for which `predicate` returns `true`, returning
+    /// clones of the removed key-value pairs in arbitrary order.
+    ///
+    /// See [`extract_if_and`] for details.
+    ///
+    /// [`extract_if_and`]: #method.extract_if_and
+    pub fn extract_if<F: FnMut(&K, &V) -> bool>(&self, predicate: F) -> Vec<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let mut entries = Vec::new();
+        self.extract_if_and(predicate, |k, v| entries.push((k.clone(), v.clone())));
+
+        entries
+    }
+
+    /// Moves the entry of a key to `destination`, replacing the value of the
+    /// key there, if any, and returns whether the key was in this map.
+    ///
+    /// The entry is inserted into `destination` before it is removed from this
+    /// map, so there is no moment at which it is in neither map. It is in
+    /// both between the insertion and the removal, a window that only spans
+    /// the two operations of the calling thread and never waits for other
+    /// threads. The removal only takes effect if the entry was not modified
+    /// since it was copied:
+    ///
+    /// - If the entry is concurrently modified, the move is retried with the
+    ///   new value, so `destination` ends up with the value the entry had when
+    ///   it was removed.
+    /// - If the entry is concurrently removed, the move is abandoned, and the
+    ///   copy inserted into `destination` is removed again unless it was
+    ///   replaced since. The value `destination` held for the key before the
+    ///   move is not restored.
+    ///
+    /// The key and the value are cloned into `destination`, which may use
+    /// another hasher than this map.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::HashMap;
+    ///
+    /// let hot = HashMap::new();
+    /// let cold = HashMap::new();
+    /// cold.insert("foo", 1);
+    ///
+    /// // promote the entry to the hot tier
+    /// assert!(cold.move_entry("foo", &hot));
+    /// assert_eq!(hot.get("foo"), Some(1));
+    /// assert_eq!(cold.get("foo"), None);
+    ///
+    /// assert!(!cold.move_entry("foo", &hot));
+    /// ```
+    pub fn move_entry<Q: Hash + Eq + ?Sized, S2: BuildHasher>(
+        &self,
+        key: &Q,
+        destination: &HashMap<K, V, S2>,
+    ) -> bool
+    where
+        K: Borrow<Q> + Clone,
+        V: Clone,
+    {
+        let _panic_guard = self.panic_policy.enter();
+        let guard = &crossbeam_epoch::pin();
+        let hash = bucket::hash(&self.build_hasher, key);
+        let source_ref = self.bucket_array_ref();
+        let destination_ref = destination.bucket_array_ref();
+        // the bucket last inserted into the destination and the hash of its key
+        let mut copy: Option<(&Bucket<K, V>, u64)> = None;
+
+        loop {
+            let bucket = match source_ref.get_bucket(guard, key, hash) {
+                Some(b) => b,
+                None => {
+                    if let Some((copy, copy_hash)) = copy {
+                        let copy_value = unsafe { copy.value() };
+
+                        destination_ref.remove_entry_if_and::<K, _, _, _>(
+                            &copy.key,
+                            copy_hash,
+                            |_, v| ptr::eq(v, copy_value),
+                            |_, _| (),
+                        );
+                    }
+
+                    return false;
+                }
+            };
+
+            let value = unsafe { bucket.value() };
+            let destination_hash = bucket::hash(&destination.build_hasher, &bucket.key);
+            let (_, current) = destination_ref.insert_with_or_modify(
+                guard,
+                bucket.key.clone(),
+                destination_hash,
+                |_| value.clone(),
+                |_, _| value.clone(),
+            );
+
+            // buckets are replaced rather than mutated, so the value is at the
+            // same address as long as the entry was not modified
+            if source_ref
+                .remove_entry_if_and(key, hash, |_, v| ptr::eq(v, value), |_, _| ())
+                .is_some()
+            {
+                return true;
+            }
+
+            copy = Some((current, destination_hash));
+        }
+    }
+
+    /// Removes a key from the map if a condition is met, returning the result
+    /// of invoking a function with a reference to the key-value pair previously
     /// corresponding to the key.
     ///
+    /// `condition` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    #[inline]
+    pub fn remove_entry_if_and<
+        Q: Hash + Eq + ?Sized,
+        F: FnMut(&K, &V) -> bool,
+        G: FnOnce(&K, &V) -> T,
+        T,
+    >(
+        &self,
+        key: &Q,
+        condition: F,
+        with_previous_entry: G,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+    {
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        self.bucket_array_ref()
+            .remove_entry_if_and(key, hash, condition, with_previous_entry)
+    }
+
+    /// Removes a key from the map, but keeps its entry until the next call to
+    /// [`purge`] so that [`undelete`] can restore it. Returns `true` if the key
+    /// was in the map.
+    ///
+    /// The entry is hidden from all reads, and counts neither in [`len`] nor
+    /// against the memory budget of the map, as if it had been removed; its
+    /// key and value are kept without being cloned. Soft-removing the key
+    /// again after it was inserted again replaces the entry that was kept.
+    ///
     /// The key may be any borrowed form of the map's key type, but
     /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
     /// the key type.
     ///
+    /// This method is only available with the `entry-pins` feature.
+    ///
+    /// [`purge`]: #method.purge
+    /// [`undelete`]: #method.undelete
+    /// [`len`]: #method.len
     /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
     /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
-    #[inline]
-    pub fn remove<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<V>
+    #[cfg(feature = "entry-pins")]
+    pub fn soft_remove<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> bool
     where
         K: Borrow<Q>,
-        V: Clone,
     {
-        self.remove_entry_if_and(key, |_, _| true, |_, v| v.clone())
+        let hash = bucket::hash(&self.build_hasher, key);
+        let bucket_array_ref = self.bucket_array_ref();
+        let guard = &crossbeam_epoch::pin();
+
+        unsafe {
+            self.soft_removals.soft_remove(
+                hash,
+                || bucket_array_ref.get_bucket(guard, key, hash),
+                |pinned| {
+                    bucket_array_ref
+                        .remove_entry_if_and(key, hash, |k, _| ptr::eq(k, pinned), |_, _| ())
+                        .is_some()
+                },
+            )
+        }
     }
 
-    /// Removes a key from the map, returning a clone of the key-value pair
-    /// previously corresponding to the key.
+    /// Restores the entry of a key that was soft-removed by [`soft_remove`]
+    /// and not purged since, returning `true` if it was restored.
+    ///
+    /// The entry is not restored if the key was inserted again since it was
+    /// soft-removed, in which case the entry is dropped. Its key and value are
+    /// cloned back into the map.
     ///
     /// The key may be any borrowed form of the map's key type, but
     /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
     /// the key type.
     ///
+    /// This method is only available with the `entry-pins` feature.
+    ///
+    /// [`soft_remove`]: #method.soft_remove
     /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
     /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
-    #[inline]
-    pub fn remove_entry<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<(K, V)>
+    #[cfg(feature = "entry-pins")]
+    pub fn undelete<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> bool
     where
         K: Borrow<Q> + Clone,
         V: Clone,
     {
-        self.remove_entry_if_and(key, |_, _| true, |k, v| (k.clone(), v.clone()))
+        let hash = bucket::hash(&self.build_hasher, key);
+
+        let pinned = match self.soft_removals.take(hash, key) {
+            Some(pinned) => pinned,
+            None => return false,
+        };
+
+        let guard = &crossbeam_epoch::pin();
+
+        self.bucket_array_ref()
+            .get_or_insert_with(guard, pinned.key().clone(), hash, || pinned.value().clone())
+            .1
     }
 
-    /// Remove a key from the map, returning the result of invoking a function
-    /// with a reference to the value previously corresponding to the key.
+    /// Drops the entries that were soft-removed by [`soft_remove`] and not
+    /// restored since, returning their number.
     ///
-    /// The key may be any borrowed form of the map's key type, but
-    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
-    /// the key type.
+    /// A value that a concurrent reader may still be reading is dropped once
+    /// the reader is done with it.
     ///
-    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
-    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
-    #[inline]
-    pub fn remove_and<Q: Hash + Eq + ?Sized, F: FnOnce(&V) -> T, T>(
-        &self,
-        key: &Q,
-        with_previous_value: F,
-    ) -> Option<T>
-    where
-        K: Borrow<Q>,
-    {
-        self.remove_entry_if_and(key, |_, _| true, move |_, v| with_previous_value(v))
+    /// This method is only available with the `entry-pins` feature.
+    ///
+    /// [`soft_remove`]: #method.soft_remove
+    #[cfg(feature = "entry-pins")]
+    pub fn purge(&self) -> usize {
+        self.soft_removals.purge()
     }
 
-    /// Removes a key from the map, returning the result of invoking a function
-    /// with a reference to the key-value pair previously corresponding to the
-    /// key.
+    /// Inserts a key-value pair into the map, returning a clone of the value
+    /// previously corresponding to the key and the sequence number assigned
+    /// to the insertion.
     ///
-    /// The key may be any borrowed form of the map's key type, but
-    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
-    /// the key type.
+    /// A map built with [`Builder::sequence_numbers`] assigns increasing
+    /// sequence numbers, starting from 1, to its insertions, modifications and
+    /// removals. The numbers of the mutations of a key increase in the order in
+    /// which the mutations take effect, so that consumers such as change logs
+    /// or replicas can apply them in the same order without a global lock.
+    /// Other maps return 0.
     ///
-    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
-    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
-    #[inline]
-    pub fn remove_entry_and<Q: Hash + Eq + ?Sized, F: FnOnce(&K, &V) -> T, T>(
-        &self,
-        key: &Q,
-        with_previous_entry: F,
-    ) -> Option<T>
+    /// Every mutation is assigned a number, including those made by methods
+    /// that do not return it, and a mutation whose publication is retried may
+    /// skip numbers. The number of the last mutation of a key is returned by
+    /// [`get_sequence`].
+    ///
+    /// This method is only available with the `sequence-numbers` feature.
+    ///
+    /// [`Builder::sequence_numbers`]: ./struct.Builder.html#method.sequence_numbers
+    /// [`get_sequence`]: #method.get_sequence
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::HashMap;
+    ///
+    /// let map = HashMap::builder().sequence_numbers().build();
+    ///
+    /// let (previous, first) = map.insert_sequenced("foo", 1);
+    /// assert_eq!(previous, None);
+    ///
+    /// let (previous, second) = map.insert_sequenced("foo", 2);
+    /// assert_eq!(previous, Some(1));
+    /// assert!(second > first);
+    ///
+    /// let (value, third) = map.remove_sequenced("foo").unwrap();
+    /// assert_eq!(value, 2);
+    /// assert!(third > second);
+    /// ```
+    #[cfg(feature = "sequence-numbers")]
+    pub fn insert_sequenced(&self, key: K, value: V) -> (Option<V>, u64)
     where
-        K: Borrow<Q>,
+        V: Clone,
     {
-        self.remove_entry_if_and(key, |_, _| true, with_previous_entry)
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        self.bucket_array_ref()
+            .insert_sequenced_entry_and(key, hash, value, |_, v| v.clone())
     }
 
-    /// Removes a key from the map if a condition is met, returning a clone of
-    /// the value previously corresponding to the key.
+    /// Removes a key from the map, returning a clone of the value previously
+    /// corresponding to the key and the sequence number assigned to the
+    /// removal.
     ///
-    /// `condition` will be invoked at least once if [`Some`] is returned. It
-    /// may also be invoked one or more times if [`None`] is returned.
+    /// See [`insert_sequenced`] for how sequence numbers are assigned.
     ///
     /// The key may be any borrowed form of the map's key type, but
     /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
     /// the key type.
     ///
+    /// This method is only available with the `sequence-numbers` feature.
+    ///
+    /// [`insert_sequenced`]: #method.insert_sequenced
     /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
     /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
-    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
-    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
-    pub fn remove_if<Q: Hash + Eq + ?Sized, F: FnMut(&K, &V) -> bool>(
-        &self,
-        key: &Q,
-        condition: F,
-    ) -> Option<V>
+    #[cfg(feature = "sequence-numbers")]
+    pub fn remove_sequenced<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<(V, u64)>
     where
         K: Borrow<Q>,
         V: Clone,
     {
-        self.remove_entry_if_and(key, condition, move |_, v| v.clone())
+        let hash = bucket::hash(&self.build_hasher, key);
+
+        self.bucket_array_ref().remove_sequenced_entry_if_and(
+            key,
+            hash,
+            |_, _| true,
+            |_, v| v.clone(),
+        )
     }
 
-    /// Removes a key from the map if a condition is met, returning a clone of
-    /// the key-value pair previously corresponding to the key.
+    /// Inserts a key-value pair into the map, returning a clone of the value
+    /// previously corresponding to the key and the [`OpReport`] of the
+    /// insertion.
     ///
-    /// `condition` will be invoked at least once if [`Some`] is returned. It
-    /// may also be invoked one or more times if [`None`] is returned.
+    /// The report tells how expensive the insertion was, so that adaptive
+    /// callers can back off from the keys where writes get slow. See the
+    /// [`metrics`] module for details.
     ///
-    /// The key may be any borrowed form of the map's key type, but
-    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
-    /// the key type.
+    /// This method is only available with the `instrument` feature.
     ///
-    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
-    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
-    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
-    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
-    #[inline]
-    pub fn remove_entry_if<Q: Hash + Eq + ?Sized, F: FnMut(&K, &V) -> bool>(
-        &self,
-        key: &Q,
-        condition: F,
-    ) -> Option<(K, V)>
+    /// [`OpReport`]: ../metrics/struct.OpReport.html
+    /// [`metrics`]: ../metrics/index.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::HashMap;
+    ///
+    /// let map = HashMap::new();
+    ///
+    /// let (previous, report) = map.insert_reported("foo", 1);
+    /// assert_eq!(previous, None);
+    /// assert_eq!(report.probe_length(), 1);
+    /// assert_eq!(report.retries(), 0);
+    /// ```
+    #[cfg(feature = "instrument")]
+    pub fn insert_reported(&self, key: K, value: V) -> (Option<V>, OpReport)
     where
-        K: Clone + Borrow<Q>,
         V: Clone,
     {
-        self.remove_entry_if_and(key, condition, move |k, v| (k.clone(), v.clone()))
+        crate::metrics::record(|| self.insert(key, value))
     }
 
-    /// Remove a key from the map if a condition is met, returning the result of
-    /// invoking a function with a reference to the value previously
-    /// corresponding to the key.
+    /// Removes a key from the map, returning a clone of the value previously
+    /// corresponding to the key and the [`OpReport`] of the removal.
     ///
-    /// `condition` will be invoked at least once if [`Some`] is returned. It
-    /// may also be invoked one or more times if [`None`] is returned.
+    /// See [`insert_reported`] for details.
     ///
-    /// The key may be any borrowed form of the map's key type, but
-    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
-    /// the key type.
+    /// This method is only available with the `instrument` feature.
     ///
-    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
-    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
-    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
-    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
-    #[inline]
-    pub fn remove_if_and<Q: Hash + Eq + ?Sized, F: FnMut(&K, &V) -> bool, G: FnOnce(&V) -> T, T>(
-        &self,
-        key: &Q,
-        condition: F,
-        with_previous_value: G,
-    ) -> Option<T>
+    /// [`OpReport`]: ../metrics/struct.OpReport.html
+    /// [`insert_reported`]: #method.insert_reported
+    #[cfg(feature = "instrument")]
+    pub fn remove_reported<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> (Option<V>, OpReport)
     where
         K: Borrow<Q>,
+        V: Clone,
     {
-        self.remove_entry_if_and(key, condition, move |_, v| with_previous_value(v))
+        crate::metrics::record(|| self.remove(key))
     }
 
-    /// Removes a key from the map if a condition is met, returning the result
-    /// of invoking a function with a reference to the key-value pair previously
-    /// corresponding to the key.
+    /// Returns the sequence number assigned to the last mutation of the entry
+    /// of a key, or 0 if the map does not assign them.
     ///
-    /// `condition` will be invoked at least once if [`Some`] is returned. It
-    /// may also be invoked one or more times if [`None`] is returned.
+    /// See [`insert_sequenced`] for how sequence numbers are assigned.
     ///
     /// The key may be any borrowed form of the map's key type, but
     /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
     /// the key type.
     ///
+    /// This method is only available with the `sequence-numbers` feature.
+    ///
+    /// [`insert_sequenced`]: #method.insert_sequenced
     /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
     /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
-    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
-    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
-    #[inline]
-    pub fn remove_entry_if_and<
-        Q: Hash + Eq + ?Sized,
-        F: FnMut(&K, &V) -> bool,
-        G: FnOnce(&K, &V) -> T,
-        T,
-    >(
-        &self,
-        key: &Q,
-        condition: F,
-        with_previous_entry: G,
-    ) -> Option<T>
+    #[cfg(feature = "sequence-numbers")]
+    pub fn get_sequence<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<u64>
     where
         K: Borrow<Q>,
     {
-        let hash = bucket::hash(&self.build_hasher, &key);
+        let hash = bucket::hash(&self.build_hasher, key);
 
-        self.bucket_array_ref()
-            .remove_entry_if_and(key, hash, condition, with_previous_entry)
+        self.bucket_array_ref().get_sequence(key, hash)
     }
 
     /// If no value corresponds to the key, insert a new key-value pair into
@@ -695,8 +3542,129 @@ impl<K: Hash + Eq, V, S: BuildHasher> HashMap<K, V, S> {
     /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
     /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
     #[inline]
-    pub fn insert_with_or_modify_and<
-        F: FnOnce() -> V,
+    pub fn insert_with_or_modify_and<
+        F: FnOnce() -> V,
+        G: FnMut(&K, &V) -> V,
+        H: FnOnce(&V) -> T,
+        T,
+    >(
+        &self,
+        key: K,
+        on_insert: F,
+        on_modify: G,
+        with_old_value: H,
+    ) -> Option<T> {
+        self.insert_with_or_modify_entry_and(key, on_insert, on_modify, move |_, v| {
+            with_old_value(v)
+        })
+    }
+
+    /// If no value corresponds to the key, invoke a default function to insert
+    /// a new key-value pair into the map. Otherwise, modify the existing value
+    /// and return the result of invoking a function with a reference to the
+    /// key-value pair previously corresponding to the supplied key.
+    ///
+    /// `on_insert` may be invoked, even if [`None`] is returned.
+    ///
+    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    #[inline]
+    pub fn insert_with_or_modify_entry_and<
+        F: FnOnce() -> V,
+        G: FnMut(&K, &V) -> V,
+        H: FnOnce(&K, &V) -> T,
+        T,
+    >(
+        &self,
+        key: K,
+        on_insert: F,
+        on_modify: G,
+        with_old_entry: H,
+    ) -> Option<T> {
+        self.insert_with_key_or_modify_entry_and(
+            key,
+            move |_| on_insert(),
+            on_modify,
+            with_old_entry,
+        )
+    }
+
+    /// If no value corresponds to the key, invoke a default function with a
+    /// reference to the key to insert a new key-value pair into the map.
+    /// Otherwise, modify the existing value and return a clone of the value
+    /// previously corresponding to the key.
+    ///
+    /// This is equivalent to [`insert_with_or_modify`], except that `on_insert`
+    /// can use the key without it being cloned beforehand.
+    ///
+    /// `on_insert` may be invoked, even if [`None`] is returned.
+    ///
+    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// [`insert_with_or_modify`]: #method.insert_with_or_modify
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    #[inline]
+    pub fn insert_with_key_or_modify<F: FnOnce(&K) -> V, G: FnMut(&K, &V) -> V>(
+        &self,
+        key: K,
+        on_insert: F,
+        on_modify: G,
+    ) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.insert_with_key_or_modify_entry_and(key, on_insert, on_modify, |_, v| v.clone())
+    }
+
+    /// If no value corresponds to the key, invoke a default function with a
+    /// reference to the key to insert a new key-value pair into the map.
+    /// Otherwise, modify the existing value and return a clone of the
+    /// key-value pair previously corresponding to the key.
+    ///
+    /// `on_insert` may be invoked, even if [`None`] is returned.
+    ///
+    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    #[inline]
+    pub fn insert_with_key_or_modify_entry<F: FnOnce(&K) -> V, G: FnMut(&K, &V) -> V>(
+        &self,
+        key: K,
+        on_insert: F,
+        on_modify: G,
+    ) -> Option<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.insert_with_key_or_modify_entry_and(key, on_insert, on_modify, |k, v| {
+            (k.clone(), v.clone())
+        })
+    }
+
+    /// If no value corresponds to the key, invoke a default function with a
+    /// reference to the key to insert a new key-value pair into the map.
+    /// Otherwise, modify the existing value and return the result of invoking
+    /// a function with a reference to the value previously corresponding to
+    /// the key.
+    ///
+    /// `on_insert` may be invoked, even if [`None`] is returned.
+    ///
+    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    #[inline]
+    pub fn insert_with_key_or_modify_and<
+        F: FnOnce(&K) -> V,
         G: FnMut(&K, &V) -> V,
         H: FnOnce(&V) -> T,
         T,
@@ -707,15 +3675,16 @@ impl<K: Hash + Eq, V, S: BuildHasher> HashMap<K, V, S> {
         on_modify: G,
         with_old_value: H,
     ) -> Option<T> {
-        self.insert_with_or_modify_entry_and(key, on_insert, on_modify, move |_, v| {
+        self.insert_with_key_or_modify_entry_and(key, on_insert, on_modify, move |_, v| {
             with_old_value(v)
         })
     }
 
-    /// If no value corresponds to the key, invoke a default function to insert
-    /// a new key-value pair into the map. Otherwise, modify the existing value
-    /// and return the result of invoking a function with a reference to the
-    /// key-value pair previously corresponding to the supplied key.
+    /// If no value corresponds to the key, invoke a default function with a
+    /// reference to the key to insert a new key-value pair into the map.
+    /// Otherwise, modify the existing value and return the result of invoking
+    /// a function with a reference to the key-value pair previously
+    /// corresponding to the supplied key.
     ///
     /// `on_insert` may be invoked, even if [`None`] is returned.
     ///
@@ -725,8 +3694,8 @@ impl<K: Hash + Eq, V, S: BuildHasher> HashMap<K, V, S> {
     /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
     /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
     #[inline]
-    pub fn insert_with_or_modify_entry_and<
-        F: FnOnce() -> V,
+    pub fn insert_with_key_or_modify_entry_and<
+        F: FnOnce(&K) -> V,
         G: FnMut(&K, &V) -> V,
         H: FnOnce(&K, &V) -> T,
         T,
@@ -748,6 +3717,90 @@ impl<K: Hash + Eq, V, S: BuildHasher> HashMap<K, V, S> {
         )
     }
 
+    /// If no value corresponds to the key, invoke a default function to insert
+    /// a new key-value pair into the map. Otherwise, modify the existing value
+    /// and return a clone of the value previously corresponding to the key.
+    ///
+    /// This is equivalent to [`insert_with_or_modify`], except that both
+    /// functions are passed `state` by mutable reference. `on_modify` may be
+    /// invoked more than once when other threads concurrently modify the
+    /// entry, and the state is kept across those invocations, so work that
+    /// does not depend on the current value, such as parsing or lookups, can be
+    /// done on the first invocation and reused by the retries. The state is
+    /// left with the caller afterwards.
+    ///
+    /// `on_insert` may be invoked, even if [`None`] is returned.
+    ///
+    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// [`insert_with_or_modify`]: #method.insert_with_or_modify
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    #[inline]
+    pub fn insert_with_or_modify_with_state<
+        T,
+        F: FnOnce(&mut T) -> V,
+        G: FnMut(&mut T, &K, &V) -> V,
+    >(
+        &self,
+        key: K,
+        state: &mut T,
+        on_insert: F,
+        mut on_modify: G,
+    ) -> Option<V>
+    where
+        V: Clone,
+    {
+        // the functions are never invoked at the same time, but they are both
+        // alive for the whole operation.
+        let state = RefCell::new(state);
+
+        self.insert_with_or_modify_entry_and(
+            key,
+            || on_insert(&mut state.borrow_mut()),
+            |k, v| on_modify(&mut state.borrow_mut(), k, v),
+            |_, v| v.clone(),
+        )
+    }
+
+    /// If no value corresponds to the key, invoke a default function to insert
+    /// a new key-value pair into the map. Otherwise, modify the existing value.
+    /// Returns a [`Ref`] to the resulting key-value pair.
+    ///
+    /// This is equivalent to calling [`insert_with_or_modify`] followed by a
+    /// lookup, but only traverses the map once. The returned value is the one
+    /// written by this call, even if another thread has since replaced it.
+    ///
+    /// `on_insert` may be invoked, even if the key was present.
+    ///
+    /// `on_modify` may be invoked one or more times, even if the key was not
+    /// present.
+    ///
+    /// [`Ref`]: struct.Ref.html
+    /// [`insert_with_or_modify`]: #method.insert_with_or_modify
+    #[inline]
+    pub fn upsert_and_get<F: FnOnce() -> V, G: FnMut(&K, &V) -> V>(
+        &self,
+        key: K,
+        on_insert: F,
+        on_modify: G,
+    ) -> Ref<'_, K, V> {
+        let hash = bucket::hash(&self.build_hasher, &key);
+        let guard = crossbeam_epoch::pin();
+
+        let (_, current_bucket_ref) = self.bucket_array_ref().insert_with_or_modify(
+            &guard,
+            key,
+            hash,
+            move |_| on_insert(),
+            on_modify,
+        );
+        let current_bucket_ptr: *const Bucket<K, V> = current_bucket_ref;
+
+        unsafe { Ref::new(guard, current_bucket_ptr) }
+    }
+
     /// Modifies the value corresponding to a key, returning a clone of the
     /// value previously corresponding to that key.
     #[inline]
@@ -797,47 +3850,315 @@ impl<K: Hash + Eq, V, S: BuildHasher> HashMap<K, V, S> {
         self.bucket_array_ref()
             .modify_entry_and(key, hash, on_modify, with_old_entry)
     }
+
+    /// Looks up the value corresponding to a key and keeps, replaces or
+    /// removes it as `decide` returns, returning a clone of the value that
+    /// `decide` was last called with.
+    ///
+    /// Unlike a [`get`] followed by a [`modify`] or a [`remove`], this probes
+    /// for the key once and carries out the decision at the slot where the key
+    /// was found. If another thread replaces the value before the decision is
+    /// carried out, `decide` is called again with the new value, so the
+    /// decision is always made on the value it changes. `decide` is not called
+    /// if the key is not in the map.
+    ///
+    /// [`get`]: #method.get
+    /// [`modify`]: #method.modify
+    /// [`remove`]: #method.remove
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::{map::Decision, HashMap};
+    ///
+    /// let map = HashMap::new();
+    /// map.insert("foo", 1);
+    /// map.insert("bar", 0);
+    ///
+    /// let decide = |_: &_, v: &i32| match *v {
+    ///     0 => Decision::Remove,
+    ///     v if v < 10 => Decision::Replace(v + 1),
+    ///     _ => Decision::Keep,
+    /// };
+    ///
+    /// assert_eq!(map.get_then_modify("foo", decide), Some(1));
+    /// assert_eq!(map.get_then_modify("bar", decide), Some(0));
+    /// assert_eq!(map.get_then_modify("baz", decide), None);
+    ///
+    /// assert_eq!(map.get("foo"), Some(2));
+    /// assert_eq!(map.get("bar"), None);
+    /// ```
+    #[inline]
+    pub fn get_then_modify<F: FnMut(&K, &V) -> Decision<V>>(&self, key: K, decide: F) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.get_then_modify_and(key, decide, |_, v| v.clone())
+    }
+
+    /// Looks up the value corresponding to a key and keeps, replaces or
+    /// removes it as `decide` returns, returning the result of invoking a
+    /// function with a reference to the key-value pair that `decide` was last
+    /// called with.
+    ///
+    /// See [`get_then_modify`] for details.
+    ///
+    /// [`get_then_modify`]: #method.get_then_modify
+    pub fn get_then_modify_and<F: FnMut(&K, &V) -> Decision<V>, G: FnOnce(&K, &V) -> T, T>(
+        &self,
+        key: K,
+        decide: F,
+        with_entry: G,
+    ) -> Option<T> {
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        self.bucket_array_ref()
+            .get_then_modify_entry_and(key, hash, decide, with_entry)
+            .map(|(t, _)| t)
+    }
+
+    /// Inserts a key-value pair into the map, coalescing it with the other
+    /// coalesced writes to the key within the coalescing window of the map.
+    ///
+    /// The first coalesced write to a key is applied right away and opens a
+    /// window for the key, which lasts as long as set with the
+    /// [`coalesce_window`] method of the builder. The coalesced writes to the
+    /// key while the window is open are kept pending outside of the map, each
+    /// one replacing the previous one, and only the last is applied when the
+    /// window closes. The values in between are dropped right away instead of
+    /// being deferred to the garbage collector, which suits maps whose keys are
+    /// overwritten many times per second, such as telemetry.
+    ///
+    /// Lookups do not see the pending values. A window is closed by the next
+    /// coalesced write to a key that shares its lock, so it may stay open past
+    /// its deadline while the map is not written to; [`flush_coalesced`]
+    /// closes all windows. A pending value is only applied if the key still
+    /// has a value then, so a removal of the key discards it.
+    ///
+    /// If the map has no coalescing window, this behaves like [`insert`].
+    ///
+    /// [`coalesce_window`]: ./struct.Builder.html#method.coalesce_window
+    /// [`flush_coalesced`]: #method.flush_coalesced
+    /// [`insert`]: #method.insert
+    pub fn insert_coalesced(&self, key: K, value: V)
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let coalescing = match &self.coalescing {
+            Some(coalescing) => coalescing,
+            None => {
+                self.insert_entry_and(key, value, |_, _| ());
+
+                return;
+            }
+        };
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        coalescing.insert(
+            hash,
+            key,
+            value,
+            |k, v| {
+                self.insert_entry_and(k, v, |_, _| ());
+            },
+            |k, v| self.apply_pending(k, v),
+        );
+    }
+
+    /// Modifies the value corresponding to a key, coalescing the modification
+    /// with the other coalesced writes to the key within the coalescing window
+    /// of the map, and returns `false` if the key has no value.
+    ///
+    /// While the window of the key is open, `on_modify` is invoked with the
+    /// pending value of the key, if any, and otherwise with the value in the
+    /// map. It is invoked under a lock that the coalesced writes to other keys
+    /// may share, so it must not write to the map with coalescing. See
+    /// [`insert_coalesced`] for how the writes are coalesced.
+    ///
+    /// If the map has no coalescing window, this behaves like [`modify`].
+    ///
+    /// [`insert_coalesced`]: #method.insert_coalesced
+    /// [`modify`]: #method.modify
+    pub fn modify_coalesced<F: FnMut(&K, &V) -> V>(&self, key: K, on_modify: F) -> bool
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let coalescing = match &self.coalescing {
+            Some(coalescing) => coalescing,
+            None => return self.modify_entry_and(key, on_modify, |_, _| ()).is_some(),
+        };
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        coalescing.modify(
+            hash,
+            key,
+            on_modify,
+            |k, f| self.modify_entry_and(k, f, |_, _| ()).is_some(),
+            |k, f| self.get_key_value_and(k, f),
+            |k, v| self.apply_pending(k, v),
+        )
+    }
+
+    /// Applies the pending values of the coalesced writes to the map and
+    /// closes their windows.
+    ///
+    /// See [`insert_coalesced`] for details.
+    ///
+    /// [`insert_coalesced`]: #method.insert_coalesced
+    pub fn flush_coalesced(&self)
+    where
+        K: Clone,
+        V: Clone,
+    {
+        if let Some(coalescing) = &self.coalescing {
+            coalescing.flush(|k, v| self.apply_pending(k, v));
+        }
+    }
+
+    // the value is cloned because it may be applied more than once if the
+    // bucket is replaced concurrently.
+    fn apply_pending(&self, key: K, value: V)
+    where
+        V: Clone,
+    {
+        self.modify_entry_and(key, move |_, _| value.clone(), |_, _| ());
+    }
 }
 
 impl<K, V, S> HashMap<K, V, S> {
+    fn reclaim_bulk_removals(&self, num_removed: usize) {
+        if self.flush_after_bulk_removals && num_removed > 0 {
+            housekeeping::reclaim_garbage();
+        }
+    }
+
     #[inline]
     fn bucket_array_ref(&'_ self) -> BucketArrayRef<'_, K, V, S> {
         BucketArrayRef {
             bucket_array: &self.bucket_array,
             build_hasher: &self.build_hasher,
             len: &self.len,
+            generation: &self.generation,
+            access_clock: &self.access_clock,
+            deterministic: self.deterministic,
+            panic_policy: self.panic_policy,
+            tombstone_policy: self.tombstone_policy,
+            memory: self.memory.as_ref(),
+            fingerprint: None,
+            reserved_slots: &self.reserved_slots,
+            sequence: self.sequence.as_ref(),
+            sampler: self.sampler.as_ref(),
+            segment: 0,
+            context: None,
+            #[cfg(feature = "instrument")]
+            latencies: &self.latencies,
         }
     }
 }
 
-impl<K, V, S> Drop for HashMap<K, V, S> {
-    fn drop(&mut self) {
-        let guard = unsafe { &crossbeam_epoch::unprotected() };
-        atomic::fence(Ordering::Acquire);
+impl<K: Hash + Eq, V, S: BuildHasher> HashMap<K, V, S> {
+    pub(crate) fn get_bucket<'g, Q: Hash + Eq + ?Sized>(
+        &self,
+        guard: &'g Guard,
+        key: &Q,
+    ) -> Option<&'g Bucket<K, V>>
+    where
+        K: Borrow<Q>,
+    {
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        self.bucket_array_ref().get_bucket(guard, key, hash)
+    }
 
-        let mut current_ptr = self.bucket_array.load(Ordering::Relaxed, guard);
+    pub(crate) fn get_or_insert_with_bucket<'g, F: FnOnce() -> V>(
+        &self,
+        guard: &'g Guard,
+        key: K,
+        on_insert: F,
+    ) -> &'g Bucket<K, V> {
+        let hash = bucket::hash(&self.build_hasher, &key);
 
-        while let Some(current_ref) = unsafe { current_ptr.as_ref() } {
-            let next_ptr = current_ref.next.load(Ordering::Relaxed, guard);
+        self.bucket_array_ref()
+            .get_or_insert_with(guard, key, hash, on_insert)
+            .0
+    }
 
-            for this_bucket_ptr in current_ref
-                .buckets
-                .iter()
-                .map(|b| b.load(Ordering::Relaxed, guard))
-                .filter(|p| !p.is_null())
-                .filter(|p| next_ptr.is_null() || p.tag() & bucket::TOMBSTONE_TAG == 0)
-            {
-                // only delete tombstones from the newest bucket array
-                // the only way this becomes a memory leak is if there was a panic during a rehash,
-                // in which case i'm going to say that running destructors and freeing memory is
-                // best-effort, and my best effort is to not do it
-                unsafe { bucket::defer_acquire_destroy(guard, this_bucket_ptr) };
-            }
+    pub(crate) fn live_buckets<'g>(&self, guard: &'g Guard) -> Vec<&'g Bucket<K, V>> {
+        self.bucket_array_ref().live_buckets(guard)
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> IntoIterator for HashMap<K, V, S> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    /// Creates an iterator that moves the entries out of the map in arbitrary
+    /// order, yielding the key and the value of each of them without cloning
+    /// them.
+    ///
+    /// A resize left unfinished by other threads is finished first. The
+    /// entries of a deterministic map are yielded in the order of their
+    /// hashes. The writes still pending in the coalescing windows of the map
+    /// are dropped along with it; call [`flush_coalesced`] first to keep them.
+    ///
+    /// [`flush_coalesced`]: #method.flush_coalesced
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::HashMap;
+    ///
+    /// let map = HashMap::new();
+    /// map.insert(1, String::from("a"));
+    /// map.insert(2, String::from("b"));
+    ///
+    /// let mut entries: Vec<_> = map.into_iter().collect();
+    /// entries.sort_unstable();
+    ///
+    /// assert_eq!(entries, [(1, String::from("a")), (2, String::from("b"))]);
+    /// ```
+    fn into_iter(mut self) -> IntoIter<K, V> {
+        // the buckets of the soft-removed entries are pinned, so the pins have
+        // to be dropped before the buckets are reclaimed
+        #[cfg(feature = "entry-pins")]
+        self.soft_removals.purge();
+        exclusive::settle(self.bucket_array_ref());
 
-            unsafe { bucket::defer_acquire_destroy(guard, current_ptr) };
+        let mut entries = Vec::with_capacity(*self.len.get_mut());
+        exclusive::take_entries(&mut self.bucket_array, self.memory.as_ref(), &mut entries);
+        *self.len.get_mut() = 0;
 
-            current_ptr = next_ptr;
+        if self.deterministic {
+            let build_hasher = &self.build_hasher;
+            entries.sort_by_cached_key(|(k, _)| bucket::hash(build_hasher, k));
         }
+
+        IntoIter::new(entries)
+    }
+}
+
+impl<K, V, S> Drop for HashMap<K, V, S> {
+    fn drop(&mut self) {
+        // the buckets of the soft-removed entries are pinned, so the pins have
+        // to be dropped before the buckets are reclaimed
+        #[cfg(feature = "entry-pins")]
+        self.soft_removals.purge();
+
+        let guard = unsafe { &crossbeam_epoch::unprotected() };
+        atomic::fence(ordering::ACQUIRE);
+
+        let memory = &self.memory;
+        let current_ptr = self.bucket_array.load(ordering::RELAXED, guard);
+
+        unsafe {
+            bucket::destroy_bucket_arrays(guard, current_ptr, |b| {
+                if let Some(memory) = memory {
+                    memory.release(&b.key, b.value());
+                }
+            })
+        };
     }
 }
 
@@ -847,5 +4168,286 @@ mod tests {
 
     use super::*;
 
+    use std::{collections::hash_map::DefaultHasher, hash::BuildHasherDefault};
+
     write_test_cases_for_me!(HashMap);
+
+    #[test]
+    fn run_housekeeping() {
+        const NUM_VALUES: i32 = 512;
+
+        let map = HashMap::new();
+        assert_eq!(
+            map.run_housekeeping(Budget::unlimited())
+                .num_buckets_visited(),
+            0
+        );
+
+        for i in 0..NUM_VALUES {
+            map.insert(i, i);
+        }
+
+        // removing a quarter of the entries leaves enough tombstones to compact
+        for i in (0..NUM_VALUES).filter(|i| i % 4 == 0) {
+            assert_eq!(map.remove(&i), Some(i));
+        }
+
+        let capacity = map.capacity();
+        let report = map.run_housekeeping(Budget::new(0));
+        assert_eq!(report.num_compacted(), 1);
+        assert!(report.is_pass_complete());
+        assert_eq!(map.capacity(), capacity);
+
+        for i in (0..NUM_VALUES).filter(|i| i % 4 != 0) {
+            assert_eq!(map.remove(&i), Some(i));
+        }
+
+        let report = map.run_housekeeping(Budget::unlimited());
+        assert_eq!(report.num_shrunk(), 1);
+        assert_eq!(map.capacity(), capacity / 2);
+        assert!(map.is_empty());
+
+        crate::test_util::run_deferred();
+    }
+
+    #[test]
+    fn tombstone_policy() {
+        const NUM_VALUES: i32 = 512;
+
+        let num_tombstones = |map: &HashMap<i32, i32>| {
+            let stats = map.probe_stats();
+            stats.num_occupied - stats.num_entries
+        };
+
+        // the number of tombstones left by housekeeping and by an exclusive
+        // borrow after removing a quarter of the entries
+        for (policy, after_housekeeping, after_exclusive) in [
+            (TombstonePolicy::KeepUntilRehash, 128, 128),
+            (TombstonePolicy::CompactAtRatio(0.25), 0, 0),
+            (TombstonePolicy::CompactAtRatio(0.5), 128, 128),
+            (TombstonePolicy::BackwardShiftInExclusiveMode, 128, 0),
+        ] {
+            let mut map = HashMap::builder()
+                .capacity(NUM_VALUES as usize)
+                .tombstone_policy(policy)
+                .build();
+
+            for i in 0..NUM_VALUES {
+                map.insert(i, i);
+            }
+
+            for i in (0..NUM_VALUES).filter(|i| i % 4 == 0) {
+                assert_eq!(map.remove(&i), Some(i));
+            }
+
+            map.run_housekeeping(Budget::unlimited());
+            assert_eq!(num_tombstones(&map), after_housekeeping, "{:?}", policy);
+
+            map.exclusive();
+            assert_eq!(num_tombstones(&map), after_exclusive, "{:?}", policy);
+
+            for i in 0..NUM_VALUES {
+                let expected = if i % 4 == 0 { None } else { Some(i) };
+                assert_eq!(map.get(&i), expected);
+            }
+        }
+
+        crate::test_util::run_deferred();
+    }
+
+    #[test]
+    #[should_panic(expected = "the compaction ratio must be greater than 0 and at most 1")]
+    fn tombstone_policy_zero_ratio() {
+        HashMap::<i32, i32>::builder().tombstone_policy(TombstonePolicy::CompactAtRatio(0.0));
+    }
+
+    #[cfg(feature = "instrument")]
+    #[test]
+    fn latency_stats() {
+        let map = HashMap::new();
+
+        for i in 0..256 {
+            map.insert(i, i);
+        }
+
+        for i in 0..128 {
+            assert_eq!(map.get(&i), Some(i));
+            assert_eq!(map.remove(&i), Some(i));
+        }
+
+        let stats = map.latency_stats();
+        assert_eq!(stats.insert().count(), 256);
+        assert_eq!(stats.get().count(), 128);
+        assert_eq!(stats.remove().count(), 128);
+        assert!(stats.rehash_assist().count() > 0);
+        assert!(stats.get().value_at_quantile(0.5).is_some());
+
+        crate::test_util::run_deferred();
+    }
+
+    #[test]
+    fn try_with_capacity() {
+        assert_eq!(
+            HashMap::<i32, i32>::try_with_capacity(usize::MAX).err(),
+            Some(Error::CapacityOverflow)
+        );
+        assert_eq!(
+            HashMap::<i32, i32>::try_with_capacity(usize::MAX / 4).err(),
+            Some(Error::CapacityOverflow)
+        );
+
+        let map = HashMap::<i32, i32>::try_with_capacity(16).unwrap();
+        assert_eq!(map.capacity(), 16);
+    }
+
+    #[test]
+    #[cfg(feature = "entry-flags")]
+    #[should_panic]
+    fn set_invalid_flags() {
+        let map = HashMap::new();
+        map.insert(0, 0);
+        map.set_flags(&0, ENTRY_FLAGS + 1);
+    }
+
+    #[test]
+    fn abort_policy_while_unwinding() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        struct InsertOnDrop<'a>(&'a HashMap<i32, i32>);
+
+        impl Drop for InsertOnDrop<'_> {
+            fn drop(&mut self) {
+                self.0.insert(0, 0);
+            }
+        }
+
+        let map = HashMap::builder().panic_policy(PanicPolicy::Abort).build();
+
+        // a map used by a destructor while another panic unwinds must not
+        // abort the process.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _insert_on_drop = InsertOnDrop(&map);
+            panic!("unrelated");
+        }));
+        assert!(result.is_err());
+        assert_eq!(map.get(&0), Some(0));
+    }
+
+    #[test]
+    fn from_hash_sorted_entries() {
+        const NUM_VALUES: i32 = 512;
+
+        let hasher = BuildHasherDefault::<DefaultHasher>::default();
+        let map = HashMap::with_hasher(hasher.clone());
+
+        for i in 0..NUM_VALUES {
+            assert_eq!(map.insert(i, i), None);
+        }
+
+        let entries = map.snapshot().into_hashed_entries();
+        let loaded = HashMap::from_hash_sorted_entries(entries, hasher.clone()).unwrap();
+        assert_eq!(loaded.len(), NUM_VALUES as usize);
+
+        for i in 0..NUM_VALUES {
+            assert_eq!(loaded.get(&i), Some(i));
+        }
+
+        assert_eq!(loaded.insert(NUM_VALUES, NUM_VALUES), None);
+        assert_eq!(loaded.remove(&0), Some(0));
+        assert_eq!(loaded.len(), NUM_VALUES as usize);
+
+        let mut entries = map.snapshot().into_hashed_entries();
+        let index = entries.iter().position(|&(_, k, _)| k == 0).unwrap();
+        entries.insert(index + 1, (entries[index].0, 0, NUM_VALUES));
+        let loaded = HashMap::from_hash_sorted_entries(entries, hasher.clone()).unwrap();
+        assert_eq!(loaded.len(), NUM_VALUES as usize);
+        assert_eq!(loaded.get(&0), Some(NUM_VALUES));
+
+        let mut entries = map.snapshot().into_hashed_entries();
+        entries.swap(0, 1);
+        assert_eq!(
+            HashMap::from_hash_sorted_entries(entries, hasher.clone()).err(),
+            Some(Error::UnsortedEntries)
+        );
+
+        let entries: Vec<(u64, i32, i32)> = Vec::new();
+        let loaded = HashMap::from_hash_sorted_entries(entries, hasher).unwrap();
+        assert!(loaded.is_empty());
+    }
+
+    #[test]
+    fn memory_budget() {
+        let budget = MemoryBudget::new(10);
+        let map = HashMap::builder()
+            .memory_budget(budget.clone(), |_: &i32, v: &Vec<u8>| v.len())
+            .build();
+        assert_eq!(map.memory_budget().map(MemoryBudget::limit), Some(10));
+        assert!(HashMap::<i32, i32>::new().memory_budget().is_none());
+
+        assert_eq!(map.insert(0, vec![0; 4]), None);
+        assert_eq!(budget.used(), 4);
+        assert_eq!(map.insert(0, vec![0; 2]), Some(vec![0; 4]));
+        assert_eq!(budget.used(), 2);
+        assert_eq!(map.modify(0, |_, v| vec![0; v.len() + 1]), Some(vec![0; 2]));
+        assert_eq!(budget.used(), 3);
+        assert_eq!(map.insert_or_modify(1, vec![0; 3], |_, _| vec![]), None);
+        assert_eq!(
+            map.insert_or_modify(1, vec![], |_, v| v.repeat(2)),
+            Some(vec![0; 3])
+        );
+        assert_eq!(budget.used(), 9);
+
+        assert_eq!(
+            map.try_insert(2, vec![0; 2]),
+            Err(Error::MemoryBudgetExceeded)
+        );
+        assert_eq!(map.get(&2), None);
+        assert_eq!(budget.used(), 9);
+
+        // the infallible insertions go over the limit
+        assert_eq!(map.insert(2, vec![0; 2]), None);
+        assert_eq!(budget.used(), 11);
+        assert_eq!(budget.available(), 0);
+
+        assert_eq!(map.remove(&1), Some(vec![0; 6]));
+        assert_eq!(budget.used(), 5);
+        assert_eq!(map.try_insert(3, vec![0; 5]), Ok(None));
+        assert_eq!(budget.used(), 10);
+
+        let mut map = map;
+        assert_eq!(map.insert_mut(3, vec![0; 1]), Some(vec![0; 5]));
+        assert_eq!(budget.used(), 6);
+        assert_eq!(map.exclusive().remove(&0), Some(vec![0; 3]));
+        assert_eq!(budget.used(), 3);
+
+        drop(map);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn bucket_array_introspection() {
+        let map = HashMap::new();
+        assert_eq!(map.bucket_array_generation(), 0);
+        assert_eq!(map.keys_per_slot_estimate(), 0.0);
+
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+
+        let generation = map.bucket_array_generation();
+        assert!(generation > 0);
+
+        let estimate = map.keys_per_slot_estimate();
+        assert!(estimate > 0.0 && estimate <= 0.5);
+
+        for i in 0..1000 {
+            map.remove(&i);
+        }
+
+        map.run_housekeeping(crate::housekeeping::Budget::unlimited());
+        assert!(map.bucket_array_generation() > generation);
+        assert_eq!(map.keys_per_slot_estimate(), 0.0);
+
+        crate::test_util::run_deferred();
+    }
 }
@@ -1,14 +1,45 @@
+use super::{
+    entry_meta::{AccessTime, Generation, Sequence},
+    read_budget::ReadBudget,
+    storage::ValueSlot,
+    Decision, TombstonePolicy,
+};
+use crate::{metrics::ProbeStats, ordering, Error};
+
 use std::{
     borrow::Borrow,
+    collections::{HashMap, HashSet},
     hash::{BuildHasher, Hash, Hasher},
-    mem::{self, MaybeUninit},
+    mem,
+    panic::{self, AssertUnwindSafe},
     ptr,
-    sync::atomic::{self, Ordering},
+    sync::atomic::{self, AtomicU64},
 };
 
+#[cfg(feature = "entry-pins")]
+use std::sync::atomic::AtomicUsize;
+
 use crossbeam_epoch::{Atomic, CompareExchangeError, Guard, Owned, Shared};
 
 type SharedBucket<'g, K, V> = Shared<'g, Bucket<K, V>>;
+type OwnedBucket<K, V> = Owned<Bucket<K, V>>;
+
+/// The maximum length of a bucket pointer array, which is limited by the
+/// maximum allocation size.
+const MAX_LENGTH: usize = isize::MAX as usize / mem::size_of::<usize>();
+
+/// The length of the first bucket pointer array of a map.
+pub(crate) const DEFAULT_LENGTH: usize = 128;
+
+/// Returns the length of a bucket pointer array that can hold `capacity`
+/// elements.
+pub(crate) fn length_for_capacity(capacity: usize) -> Result<usize, Error> {
+    capacity
+        .checked_mul(2)
+        .and_then(usize::checked_next_power_of_two)
+        .filter(|&length| length <= MAX_LENGTH)
+        .ok_or(Error::CapacityOverflow)
+}
 
 pub(crate) struct BucketArray<K, V> {
     pub(crate) buckets: Box<[Atomic<Bucket<K, V>>]>,
@@ -42,6 +73,362 @@ impl<K, V> BucketArray<K, V> {
     }
 }
 
+impl<K: Eq, V> BucketArray<K, V> {
+    /// Builds a bucket array directly from entries and the hashes of their
+    /// keys, which must be sorted by hash, returning it with the number of
+    /// distinct keys it holds.
+    ///
+    /// A later entry replaces an earlier one with an equal key. Equal keys have
+    /// equal hashes, so duplicates are merged in one pass over the sorted
+    /// entries. The distinct entries are then laid out in one pass in the order
+    /// of their home slots, carrying the probe position forward, so no slot is
+    /// probed and no key is compared while placing them.
+    pub(crate) fn from_hash_sorted_entries(
+        epoch: usize,
+        entries: Vec<(u64, K, V)>,
+    ) -> Result<(Self, usize), Error> {
+        let entries = merge_equal_keys(entries);
+        let len = entries.len();
+        let length = length_for_capacity(len)?;
+        let mask = length - 1;
+
+        // a counting sort by home slot, which takes the low bits of the hashes
+        // while the entries are sorted by their high bits
+        let mut starts = vec![0; length];
+
+        for &(hash, _, _) in entries.iter() {
+            let home = hash as usize & mask;
+
+            if home + 1 < length {
+                starts[home + 1] += 1;
+            }
+        }
+
+        for i in 1..length {
+            starts[i] += starts[i - 1];
+        }
+
+        let mut by_home: Vec<Option<(u64, K, V)>> = Vec::with_capacity(len);
+        by_home.resize_with(len, || None);
+
+        for entry in entries {
+            let home = entry.0 as usize & mask;
+            by_home[starts[home]] = Some(entry);
+            starts[home] += 1;
+        }
+
+        let mut slots: Vec<Option<OwnedBucket<K, V>>> = Vec::with_capacity(length);
+        slots.resize_with(length, || None);
+
+        let mut position = 0;
+        let mut wrapped = Vec::new();
+
+        for (hash, key, value) in by_home.into_iter().map(Option::unwrap) {
+            position = position.max(hash as usize & mask);
+            let bucket = Owned::new(Bucket::new(key, value));
+
+            if position < length {
+                slots[position] = Some(bucket);
+                position += 1;
+            } else {
+                wrapped.push(bucket);
+            }
+        }
+
+        // the entries that ran past the end of the array continue their probe
+        // sequences from its start, which is never full since the array is at
+        // most half full
+        let mut position = 0;
+
+        for bucket in wrapped {
+            while slots[position].is_some() {
+                position += 1;
+            }
+
+            slots[position] = Some(bucket);
+        }
+
+        let buckets = slots
+            .into_iter()
+            .map(|slot| slot.map_or_else(Atomic::null, Atomic::from))
+            .collect();
+
+        Ok((
+            Self {
+                buckets,
+                next: Atomic::null(),
+                epoch,
+            },
+            len,
+        ))
+    }
+
+    /// Builds a bucket array from live buckets that were moved out of other
+    /// bucket arrays and the hashes of their keys, which must be distinct.
+    ///
+    /// The flag tags of the buckets are kept.
+    pub(crate) fn from_hashed_buckets(
+        epoch: usize,
+        buckets: Vec<(u64, OwnedBucket<K, V>)>,
+    ) -> Result<Self, Error> {
+        let length = length_for_capacity(buckets.len())?;
+        let mask = length - 1;
+        let mut slots: Vec<Option<OwnedBucket<K, V>>> = Vec::with_capacity(length);
+        slots.resize_with(length, || None);
+
+        for (hash, bucket) in buckets {
+            let mut i = hash as usize & mask;
+
+            while slots[i].is_some() {
+                debug_assert!(slots[i].as_ref().unwrap().key != bucket.key);
+                i = (i + 1) & mask;
+            }
+
+            slots[i] = Some(bucket);
+        }
+
+        let buckets = slots
+            .into_iter()
+            .map(|slot| slot.map_or_else(Atomic::null, Atomic::from))
+            .collect();
+
+        Ok(Self {
+            buckets,
+            next: Atomic::null(),
+            epoch,
+        })
+    }
+}
+
+// the following methods may only be called on a bucket array that no other
+// thread can access, which the hash maps guarantee by borrowing themselves
+// mutably. they use plain loads and stores, and free buckets immediately.
+impl<K: Eq, V> BucketArray<K, V> {
+    /// Inserts a key-value pair, returning the previous value of the key, if
+    /// any.
+    ///
+    /// The value of a live bucket with the same key is replaced in place,
+    /// keeping the generation and the flags of the entry. The key and the
+    /// value are given back if no slot is left.
+    pub(crate) fn insert_exclusive(
+        &mut self,
+        hash: u64,
+        key: K,
+        value: V,
+        stamp: Stamp,
+    ) -> Result<Option<V>, (K, V)> {
+        let guard = unsafe { crossbeam_epoch::unprotected() };
+        let mask = self.buckets.len() - 1;
+        let offset = hash as usize & mask;
+
+        for i in (0..self.buckets.len()).map(|i| i.wrapping_add(offset) & mask) {
+            let mut this_bucket_ptr = self.buckets[i].load(ordering::RELAXED, guard);
+
+            if !this_bucket_ptr.is_null() {
+                let this_bucket_ref = unsafe { this_bucket_ptr.deref_mut() };
+
+                if this_bucket_ref.key != key {
+                    continue;
+                }
+
+                if this_bucket_ptr.tag() & TOMBSTONE_TAG == 0 {
+                    this_bucket_ref.last_access.set_mut(stamp.access);
+                    this_bucket_ref.sequence.set_mut(stamp.next_sequence());
+
+                    return Ok(Some(unsafe { this_bucket_ref.value.replace(value) }));
+                }
+
+                // the value of a tombstone has already been taken or dropped
+                mem::drop(unsafe { this_bucket_ptr.into_owned() });
+            }
+
+            let mut bucket_ptr = Owned::new(Bucket::new(key, value));
+            bucket_ptr.stamp(stamp, None);
+            self.buckets[i].store(bucket_ptr, ordering::RELAXED);
+
+            return Ok(None);
+        }
+
+        Err((key, value))
+    }
+
+    /// Frees every tombstone, shifting the buckets that follow each of them
+    /// back like [`remove_exclusive`] does.
+    ///
+    /// [`remove_exclusive`]: #method.remove_exclusive
+    pub(crate) fn purge_tombstones_exclusive<H: BuildHasher>(&mut self, build_hasher: &H)
+    where
+        K: Hash,
+    {
+        self.free_buckets_exclusive(build_hasher, |_, is_tombstone| is_tombstone);
+    }
+
+    /// Frees every bucket for which `free` returns `true`, dropping its value
+    /// unless it is a tombstone, and shifts the buckets that follow each of
+    /// them back like [`remove_exclusive`] does.
+    ///
+    /// `free` is invoked with each bucket and whether it is a tombstone, and
+    /// may be invoked again with a bucket that it kept if the bucket is
+    /// shifted back.
+    ///
+    /// [`remove_exclusive`]: #method.remove_exclusive
+    pub(crate) fn free_buckets_exclusive<H, F>(&mut self, build_hasher: &H, mut free: F)
+    where
+        K: Hash,
+        H: BuildHasher,
+        F: FnMut(&mut Bucket<K, V>, bool) -> bool,
+    {
+        let guard = unsafe { crossbeam_epoch::unprotected() };
+
+        for i in 0..self.buckets.len() {
+            // a bucket to be freed may be shifted back into the slot that was
+            // just freed
+            loop {
+                let mut this_bucket_ptr = self.buckets[i].load(ordering::RELAXED, guard);
+
+                if this_bucket_ptr.is_null() {
+                    break;
+                }
+
+                let is_tombstone = this_bucket_ptr.tag() & TOMBSTONE_TAG != 0;
+
+                if !free(unsafe { this_bucket_ptr.deref_mut() }, is_tombstone) {
+                    break;
+                }
+
+                let mut this_bucket = unsafe { this_bucket_ptr.into_owned() };
+
+                // the value of a tombstone has already been taken or dropped
+                if !is_tombstone {
+                    unsafe { this_bucket.value.drop_value() };
+                }
+
+                mem::drop(this_bucket);
+                self.buckets[i].store(Shared::null(), ordering::RELAXED);
+                self.shift_back(build_hasher, i);
+            }
+        }
+    }
+
+    /// Removes a key without leaving a tombstone, returning its entry, if any.
+    ///
+    /// The buckets that follow the removed one are shifted back into the
+    /// vacated slot where their probe sequences allow it, so that every key
+    /// can still be reached from its home slot without crossing an empty one.
+    pub(crate) fn remove_exclusive<Q: ?Sized + Eq, H: BuildHasher>(
+        &mut self,
+        build_hasher: &H,
+        hash: u64,
+        key: &Q,
+    ) -> Option<(K, V)>
+    where
+        K: Hash + Borrow<Q>,
+    {
+        let guard = unsafe { crossbeam_epoch::unprotected() };
+        let mask = self.buckets.len() - 1;
+        let offset = hash as usize & mask;
+
+        for i in (0..self.buckets.len()).map(|i| i.wrapping_add(offset) & mask) {
+            let this_bucket_ptr = self.buckets[i].load(ordering::RELAXED, guard);
+            let this_bucket_ref = unsafe { this_bucket_ptr.as_ref() }?;
+
+            if this_bucket_ref.key.borrow() != key {
+                continue;
+            } else if this_bucket_ptr.tag() & TOMBSTONE_TAG != 0 {
+                return None;
+            }
+
+            self.buckets[i].store(Shared::null(), ordering::RELAXED);
+            self.shift_back(build_hasher, i);
+
+            let Bucket { key, value, .. } = *unsafe { this_bucket_ptr.into_owned() }.into_box();
+
+            return Some((key, unsafe { value.take() }));
+        }
+
+        None
+    }
+
+    /// Moves the buckets into a new bucket array of twice the length, freeing
+    /// the tombstones, and returns the new array. This array is left empty.
+    pub(crate) fn grow_exclusive<H: BuildHasher>(&mut self, build_hasher: &H) -> Self
+    where
+        K: Hash,
+    {
+        let guard = unsafe { crossbeam_epoch::unprotected() };
+        let next_array = Self::with_length(
+            self.epoch + 1,
+            RehashOp::Expand.next_length(self.buckets.len()),
+        );
+        let mask = next_array.buckets.len() - 1;
+
+        for this_bucket in self.buckets.iter() {
+            let this_bucket_ptr = this_bucket.load(ordering::RELAXED, guard);
+            this_bucket.store(Shared::null(), ordering::RELAXED);
+
+            let this_bucket_ref = match unsafe { this_bucket_ptr.as_ref() } {
+                Some(this_bucket_ref) => this_bucket_ref,
+                None => continue,
+            };
+
+            if this_bucket_ptr.tag() & TOMBSTONE_TAG != 0 {
+                mem::drop(unsafe { this_bucket_ptr.into_owned() });
+
+                continue;
+            }
+
+            let mut i = hash(build_hasher, &this_bucket_ref.key) as usize & mask;
+
+            while !next_array.buckets[i]
+                .load(ordering::RELAXED, guard)
+                .is_null()
+            {
+                i = (i + 1) & mask;
+            }
+
+            next_array.buckets[i].store(
+                this_bucket_ptr.with_tag(this_bucket_ptr.tag() & FLAGS_MASK),
+                ordering::RELAXED,
+            );
+        }
+
+        next_array
+    }
+
+    /// Fills the empty slot at `hole` with the first following bucket that
+    /// may occupy it, and repeats with the slot vacated by that bucket, until
+    /// an empty slot is reached.
+    fn shift_back<H: BuildHasher>(&mut self, build_hasher: &H, mut hole: usize)
+    where
+        K: Hash,
+    {
+        let guard = unsafe { crossbeam_epoch::unprotected() };
+        let mask = self.buckets.len() - 1;
+        let mut i = (hole + 1) & mask;
+
+        loop {
+            let this_bucket_ptr = self.buckets[i].load(ordering::RELAXED, guard);
+
+            let this_bucket_ref = match unsafe { this_bucket_ptr.as_ref() } {
+                Some(this_bucket_ref) => this_bucket_ref,
+                None => break,
+            };
+
+            let home = hash(build_hasher, &this_bucket_ref.key) as usize & mask;
+
+            // the bucket may move back if the hole lies between its home slot
+            // and its current slot
+            if i.wrapping_sub(home) & mask >= i.wrapping_sub(hole) & mask {
+                self.buckets[hole].store(this_bucket_ptr, ordering::RELAXED);
+                self.buckets[i].store(Shared::null(), ordering::RELAXED);
+                hole = i;
+            }
+
+            i = (i + 1) & mask;
+        }
+    }
+}
+
 impl<'g, K: 'g + Eq, V: 'g> BucketArray<K, V> {
     pub(crate) fn get<Q: ?Sized + Eq>(
         &self,
@@ -52,12 +439,28 @@ impl<'g, K: 'g + Eq, V: 'g> BucketArray<K, V> {
     where
         K: Borrow<Q>,
     {
-        let loop_result = self.probe_loop(guard, hash, |_, _, this_bucket_ptr| {
+        self.get_with_index(guard, hash, key).map(|(p, _)| p)
+    }
+
+    /// Like [`get`], but also returns the index of the slot that holds the
+    /// bucket, or 0 if the key was not found.
+    ///
+    /// [`get`]: #method.get
+    pub(crate) fn get_with_index<Q: ?Sized + Eq>(
+        &self,
+        guard: &'g Guard,
+        hash: u64,
+        key: &Q,
+    ) -> Result<(SharedBucket<'g, K, V>, usize), RelocatedError>
+    where
+        K: Borrow<Q>,
+    {
+        let loop_result = self.probe_loop(guard, hash, |i, _, this_bucket_ptr| {
             let this_bucket_ref = if let Some(this_bucket_ref) = unsafe { this_bucket_ptr.as_ref() }
             {
                 this_bucket_ref
             } else {
-                return ProbeLoopAction::Return(Shared::null());
+                return ProbeLoopAction::Return((Shared::null(), 0));
             };
 
             let this_key = &this_bucket_ref.key;
@@ -72,16 +475,125 @@ impl<'g, K: 'g + Eq, V: 'g> BucketArray<K, V> {
                 Shared::null()
             };
 
-            ProbeLoopAction::Return(result_ptr)
+            ProbeLoopAction::Return((result_ptr, i))
         });
 
         match loop_result {
             ProbeLoopResult::Returned(t) => Ok(t),
-            ProbeLoopResult::LoopEnded => Ok(Shared::null()),
+            ProbeLoopResult::LoopEnded => Ok((Shared::null(), 0)),
+            ProbeLoopResult::FoundSentinelTag => Err(RelocatedError),
+        }
+    }
+
+    /// Returns the number of slots probed to find `key`, including the one
+    /// that holds its bucket, live or not, or the empty one that ends the
+    /// probe.
+    pub(crate) fn probe_length<Q: ?Sized + Eq>(
+        &self,
+        guard: &'g Guard,
+        hash: u64,
+        key: &Q,
+    ) -> Result<usize, RelocatedError>
+    where
+        K: Borrow<Q>,
+    {
+        let mask = self.buckets.len() - 1;
+        let offset = hash as usize & mask;
+
+        let loop_result = self.probe_loop(guard, hash, |i, _, this_bucket_ptr| {
+            match unsafe { this_bucket_ptr.as_ref() } {
+                Some(this_bucket_ref) if this_bucket_ref.key.borrow() != key => {
+                    ProbeLoopAction::Continue
+                }
+                _ => ProbeLoopAction::Return((i.wrapping_sub(offset) & mask) + 1),
+            }
+        });
+
+        match loop_result {
+            ProbeLoopResult::Returned(length) => Ok(length),
+            ProbeLoopResult::LoopEnded => Ok(self.buckets.len()),
             ProbeLoopResult::FoundSentinelTag => Err(RelocatedError),
         }
     }
 
+    /// Like [`get`], but probes at most as many slots as `budget` allows,
+    /// returning [`Error::Busy`] if it runs out.
+    ///
+    /// [`get`]: #method.get
+    /// [`Error::Busy`]: ../../enum.Error.html#variant.Busy
+    pub(crate) fn get_within<Q: ?Sized + Eq>(
+        &self,
+        guard: &'g Guard,
+        hash: u64,
+        key: &Q,
+        budget: &mut ReadBudget,
+    ) -> Result<Result<SharedBucket<'g, K, V>, RelocatedError>, Error>
+    where
+        K: Borrow<Q>,
+    {
+        let loop_result = self.probe_loop(guard, hash, |_, _, this_bucket_ptr| {
+            if !budget.spend_probe() {
+                return ProbeLoopAction::Return(Err(Error::Busy));
+            }
+
+            let this_bucket_ref = if let Some(this_bucket_ref) = unsafe { this_bucket_ptr.as_ref() }
+            {
+                this_bucket_ref
+            } else {
+                return ProbeLoopAction::Return(Ok(Shared::null()));
+            };
+
+            if this_bucket_ref.key.borrow() != key {
+                return ProbeLoopAction::Continue;
+            }
+
+            if this_bucket_ptr.tag() & TOMBSTONE_TAG == 0 {
+                ProbeLoopAction::Return(Ok(this_bucket_ptr))
+            } else {
+                ProbeLoopAction::Return(Ok(Shared::null()))
+            }
+        });
+
+        match loop_result {
+            ProbeLoopResult::Returned(result) => result.map(Ok),
+            ProbeLoopResult::LoopEnded => Ok(Ok(Shared::null())),
+            ProbeLoopResult::FoundSentinelTag => Ok(Err(RelocatedError)),
+        }
+    }
+
+    /// Looks up a key in the slot at `index` without probing.
+    ///
+    /// Returns the bucket if the slot holds a live bucket with the key, or a
+    /// null pointer if it holds a tombstone with the key, which means that
+    /// the key is absent from this bucket array. Returns `None` if the slot
+    /// holds another key or has been relocated, in which case the key should
+    /// be looked up by probing.
+    pub(crate) fn get_at<Q: ?Sized + Eq>(
+        &self,
+        guard: &'g Guard,
+        index: usize,
+        key: &Q,
+    ) -> Option<SharedBucket<'g, K, V>>
+    where
+        K: Borrow<Q>,
+    {
+        let this_bucket_ptr = ordering::load_consume(self.buckets.get(index)?, guard);
+
+        if this_bucket_ptr.tag() & SENTINEL_TAG != 0 {
+            return None;
+        }
+
+        let this_bucket_ref = unsafe { this_bucket_ptr.as_ref() }?;
+
+        if this_bucket_ref.key.borrow() != key {
+            None
+        } else if this_bucket_ptr.tag() & TOMBSTONE_TAG == 0 {
+            Some(this_bucket_ptr)
+        } else {
+            Some(Shared::null())
+        }
+    }
+
     // https://rust-lang.github.io/rust-clippy/master/index.html#type_complexity
     #[allow(clippy::type_complexity)]
     pub(crate) fn insert(
@@ -89,26 +601,34 @@ impl<'g, K: 'g + Eq, V: 'g> BucketArray<K, V> {
         guard: &'g Guard,
         hash: u64,
         bucket_ptr: Owned<Bucket<K, V>>,
+        stamp: Stamp,
     ) -> Result<SharedBucket<'g, K, V>, Owned<Bucket<K, V>>> {
         let mut maybe_bucket_ptr = Some(bucket_ptr);
 
         let loop_result = self.probe_loop(guard, hash, |_, this_bucket, this_bucket_ptr| {
-            let bucket_ptr = maybe_bucket_ptr.take().unwrap();
-            let key = &bucket_ptr.key;
+            let mut bucket_ptr = maybe_bucket_ptr.take().unwrap();
+            let mut replaced_bucket_ref = None;
 
-            if let Some(Bucket { key: this_key, .. }) = unsafe { this_bucket_ptr.as_ref() } {
-                if this_key != key {
+            if let Some(this_bucket_ref) = unsafe { this_bucket_ptr.as_ref() } {
+                if this_bucket_ref.key != bucket_ptr.key {
                     maybe_bucket_ptr = Some(bucket_ptr);
 
                     return ProbeLoopAction::Continue;
                 }
+
+                if this_bucket_ptr.tag() & TOMBSTONE_TAG == 0 {
+                    replaced_bucket_ref = Some(this_bucket_ref);
+                }
             }
 
+            bucket_ptr.stamp(stamp, replaced_bucket_ref);
+            let bucket_ptr = bucket_ptr.with_tag(live_flags(this_bucket_ptr));
+
             match this_bucket.compare_exchange_weak(
                 this_bucket_ptr,
                 bucket_ptr,
-                Ordering::Release,
-                Ordering::Relaxed,
+                ordering::RELEASE,
+                ordering::RELAXED,
                 guard,
             ) {
                 Ok(_) => ProbeLoopAction::Return(this_bucket_ptr),
@@ -125,13 +645,71 @@ impl<'g, K: 'g + Eq, V: 'g> BucketArray<K, V> {
             .ok_or_else(|| maybe_bucket_ptr.unwrap())
     }
 
-    pub(crate) fn remove_if<Q: ?Sized + Eq, F: FnMut(&K, &V) -> bool>(
+    /// Inserts a bucket into the slot at `index` without probing, if the slot
+    /// holds a live bucket or a tombstone with the same key.
+    ///
+    /// A key occupies at most one slot of a bucket array, so this is
+    /// equivalent to [`insert`] when it succeeds. Otherwise, the bucket is
+    /// returned and should be inserted by probing.
+    ///
+    /// [`insert`]: #method.insert
+    pub(crate) fn insert_at(
+        &self,
+        guard: &'g Guard,
+        index: usize,
+        mut bucket_ptr: OwnedBucket<K, V>,
+        stamp: Stamp,
+    ) -> Result<SharedBucket<'g, K, V>, OwnedBucket<K, V>> {
+        let this_bucket = match self.buckets.get(index) {
+            Some(this_bucket) => this_bucket,
+            None => return Err(bucket_ptr),
+        };
+
+        loop {
+            let this_bucket_ptr = ordering::load_consume(this_bucket, guard);
+
+            if this_bucket_ptr.tag() & SENTINEL_TAG != 0 {
+                return Err(bucket_ptr);
+            }
+
+            let this_bucket_ref = match unsafe { this_bucket_ptr.as_ref() } {
+                Some(this_bucket_ref) if this_bucket_ref.key == bucket_ptr.key => this_bucket_ref,
+                _ => return Err(bucket_ptr),
+            };
+
+            if this_bucket_ptr.tag() & TOMBSTONE_TAG == 0 {
+                bucket_ptr.stamp(stamp, Some(this_bucket_ref));
+            } else {
+                bucket_ptr.stamp(stamp, None);
+            }
+
+            bucket_ptr = bucket_ptr.with_tag(live_flags(this_bucket_ptr));
+
+            match this_bucket.compare_exchange_weak(
+                this_bucket_ptr,
+                bucket_ptr,
+                ordering::RELEASE,
+                ordering::RELAXED,
+                guard,
+            ) {
+                Ok(_) => return Ok(this_bucket_ptr),
+                Err(CompareExchangeError { new, .. }) => bucket_ptr = new,
+            }
+
+            ordering::audit_fence();
+        }
+    }
+
+    /// Replaces the entry flags of the live bucket with `key` by the result of
+    /// `f`, returning the previous flags, or `None` if the key was not found.
+    #[cfg(feature = "entry-flags")]
+    pub(crate) fn update_flags<Q: ?Sized + Eq, F: FnMut(usize) -> usize>(
         &self,
         guard: &'g Guard,
         hash: u64,
         key: &Q,
-        mut condition: F,
-    ) -> Result<SharedBucket<'g, K, V>, F>
+        mut f: F,
+    ) -> Result<Option<usize>, F>
     where
         K: Borrow<Q>,
     {
@@ -140,86 +718,218 @@ impl<'g, K: 'g + Eq, V: 'g> BucketArray<K, V> {
             {
                 this_bucket_ref
             } else {
-                return ProbeLoopAction::Return(Shared::null());
+                return ProbeLoopAction::Return(None);
             };
 
-            let this_key = &this_bucket_ref.key;
-
-            if this_key.borrow() != key {
+            if this_bucket_ref.key.borrow() != key {
                 return ProbeLoopAction::Continue;
             } else if this_bucket_ptr.tag() & TOMBSTONE_TAG != 0 {
-                return ProbeLoopAction::Return(Shared::null());
+                return ProbeLoopAction::Return(None);
             }
 
-            let this_value = unsafe { &*this_bucket_ref.maybe_value.as_ptr() };
+            let flags = (this_bucket_ptr.tag() & FLAGS_MASK) >> FLAGS_SHIFT;
+            let new_flags = f(flags);
+            debug_assert_eq!(new_flags & !(FLAGS_MASK >> FLAGS_SHIFT), 0);
 
-            if !condition(this_key, this_value) {
-                return ProbeLoopAction::Return(Shared::null());
+            if new_flags == flags {
+                return ProbeLoopAction::Return(Some(flags));
             }
 
-            let new_bucket_ptr = this_bucket_ptr.with_tag(TOMBSTONE_TAG);
+            // the borrowed tag is cleared like when a bucket is replaced, so
+            // that a thread still copying this bucket from the previous array
+            // does not overwrite the new flags
+            let new_bucket_ptr = this_bucket_ptr.with_tag(new_flags << FLAGS_SHIFT);
 
             match this_bucket.compare_exchange_weak(
                 this_bucket_ptr,
                 new_bucket_ptr,
-                Ordering::Release,
-                Ordering::Relaxed,
+                ordering::RELEASE,
+                ordering::RELAXED,
                 guard,
             ) {
-                Ok(_) => ProbeLoopAction::Return(new_bucket_ptr),
+                Ok(_) => ProbeLoopAction::Return(Some(flags)),
                 Err(_) => ProbeLoopAction::Reload,
             }
         });
 
         match loop_result {
             ProbeLoopResult::Returned(t) => Ok(t),
-            ProbeLoopResult::LoopEnded => Ok(Shared::null()),
-            ProbeLoopResult::FoundSentinelTag => Err(condition),
+            ProbeLoopResult::LoopEnded => Ok(None),
+            ProbeLoopResult::FoundSentinelTag => Err(f),
         }
     }
 
-    // https://rust-lang.github.io/rust-clippy/master/index.html#type_complexity
-    #[allow(clippy::type_complexity)]
-    pub(crate) fn modify<F: FnMut(&K, &V) -> V>(
+    pub(crate) fn remove_if<Q: ?Sized + Eq, F: FnMut(&K, &V) -> bool>(
         &self,
         guard: &'g Guard,
         hash: u64,
-        key_or_owned_bucket: KeyOrOwnedBucket<K, V>,
-        mut modifier: F,
-    ) -> Result<SharedBucket<'g, K, V>, (KeyOrOwnedBucket<K, V>, F)> {
-        let mut maybe_key_or_owned_bucket = Some(key_or_owned_bucket);
-
-        let loop_result = self.probe_loop(guard, hash, |_, this_bucket, this_bucket_ptr| {
-            let key_or_owned_bucket = maybe_key_or_owned_bucket.take().unwrap();
-
+        key: &Q,
+        stamp: Stamp,
+        mut condition: F,
+    ) -> Result<(SharedBucket<'g, K, V>, usize), F>
+    where
+        K: Borrow<Q>,
+    {
+        let loop_result = self.probe_loop(guard, hash, |i, this_bucket, this_bucket_ptr| {
             let this_bucket_ref = if let Some(this_bucket_ref) = unsafe { this_bucket_ptr.as_ref() }
             {
                 this_bucket_ref
             } else {
-                maybe_key_or_owned_bucket = Some(key_or_owned_bucket);
-
-                return ProbeLoopAction::Return(Shared::null());
+                return ProbeLoopAction::Return((Shared::null(), i));
             };
 
             let this_key = &this_bucket_ref.key;
-            let key = key_or_owned_bucket.key();
 
-            if key != this_key {
-                maybe_key_or_owned_bucket = Some(key_or_owned_bucket);
+            if this_key.borrow() != key {
+                return ProbeLoopAction::Continue;
+            } else if this_bucket_ptr.tag() & TOMBSTONE_TAG != 0 {
+                return ProbeLoopAction::Return((Shared::null(), i));
+            }
+
+            if !unsafe { condition(this_key, this_bucket_ref.value()) } {
+                return ProbeLoopAction::Return((Shared::null(), i));
+            }
+
+            let new_bucket_ptr = this_bucket_ptr.with_tag(TOMBSTONE_TAG);
+            let sequence = stamp.next_sequence();
+
+            match this_bucket.compare_exchange_weak(
+                this_bucket_ptr,
+                new_bucket_ptr,
+                ordering::RELEASE,
+                ordering::RELAXED,
+                guard,
+            ) {
+                Ok(_) => {
+                    if stamp.sequence.is_some() {
+                        this_bucket_ref.sequence.set(sequence);
+                    }
+
+                    ProbeLoopAction::Return((new_bucket_ptr, i))
+                }
+                Err(_) => ProbeLoopAction::Reload,
+            }
+        });
+
+        match loop_result {
+            ProbeLoopResult::Returned(t) => Ok(t),
+            ProbeLoopResult::LoopEnded => Ok((Shared::null(), 0)),
+            ProbeLoopResult::FoundSentinelTag => Err(condition),
+        }
+    }
+
+    /// Turns every live bucket into a tombstone in the order of the slots,
+    /// invoking `on_taken` with each of them, as tagged after the removal.
+    ///
+    /// Returns an error if a slot was relocated to the next array, in which
+    /// case the slots after it are left for the caller to take from there.
+    pub(crate) fn take_all<F: FnMut(SharedBucket<'g, K, V>)>(
+        &self,
+        guard: &'g Guard,
+        stamp: Stamp,
+        mut on_taken: F,
+    ) -> Result<(), RelocatedError> {
+        for this_bucket in self.buckets.iter() {
+            loop {
+                let this_bucket_ptr = ordering::load_consume(this_bucket, guard);
+
+                if this_bucket_ptr.tag() & SENTINEL_TAG != 0 {
+                    return Err(RelocatedError);
+                }
+
+                let this_bucket_ref = match unsafe { this_bucket_ptr.as_ref() } {
+                    Some(r) if this_bucket_ptr.tag() & TOMBSTONE_TAG == 0 => r,
+                    _ => break,
+                };
+
+                let new_bucket_ptr = this_bucket_ptr.with_tag(TOMBSTONE_TAG);
+                let sequence = stamp.next_sequence();
+
+                if this_bucket
+                    .compare_exchange_weak(
+                        this_bucket_ptr,
+                        new_bucket_ptr,
+                        ordering::RELEASE,
+                        ordering::RELAXED,
+                        guard,
+                    )
+                    .is_ok()
+                {
+                    if stamp.sequence.is_some() {
+                        this_bucket_ref.sequence.set(sequence);
+                    }
+
+                    on_taken(new_bucket_ptr);
+
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the value of the key with the one returned by `modifier`,
+    /// returning the replaced bucket, or null if the key is not in the array
+    /// or `modifier` returned `None`, which leaves the value unchanged.
+    // https://rust-lang.github.io/rust-clippy/master/index.html#type_complexity
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn modify<F: FnMut(&K, &V) -> Option<V>>(
+        &self,
+        guard: &'g Guard,
+        hash: u64,
+        key_or_owned_bucket: KeyOrOwnedBucket<K, V>,
+        stamp: Stamp,
+        mut modifier: F,
+    ) -> Result<SharedBucket<'g, K, V>, (KeyOrOwnedBucket<K, V>, F)> {
+        let mut maybe_key_or_owned_bucket = Some(key_or_owned_bucket);
+
+        let loop_result = self.probe_loop(guard, hash, |_, this_bucket, this_bucket_ptr| {
+            let mut key_or_owned_bucket = maybe_key_or_owned_bucket.take().unwrap();
+
+            let this_bucket_ref = if let Some(this_bucket_ref) = unsafe { this_bucket_ptr.as_ref() }
+            {
+                this_bucket_ref
+            } else {
+                maybe_key_or_owned_bucket = Some(key_or_owned_bucket);
+
+                return ProbeLoopAction::Return(Shared::null());
+            };
+
+            let this_key = &this_bucket_ref.key;
+            let key = key_or_owned_bucket.key();
+
+            if key != this_key {
+                maybe_key_or_owned_bucket = Some(key_or_owned_bucket);
 
                 return ProbeLoopAction::Continue;
             }
 
             if this_bucket_ptr.tag() & TOMBSTONE_TAG == 0 {
-                let this_value = unsafe { &*this_bucket_ref.maybe_value.as_ptr() };
-                let new_value = modifier(this_key, this_value);
-                let new_bucket = key_or_owned_bucket.into_bucket(new_value);
+                let new_value = on_unwind(
+                    &mut key_or_owned_bucket,
+                    |_| unsafe { modifier(this_key, this_bucket_ref.value()) },
+                    KeyOrOwnedBucket::drop_value,
+                );
+
+                let new_value = match new_value {
+                    Some(new_value) => new_value,
+                    None => {
+                        key_or_owned_bucket.drop_value();
+
+                        return ProbeLoopAction::Return(Shared::null());
+                    }
+                };
+
+                let mut new_bucket = key_or_owned_bucket.into_bucket(new_value);
+                new_bucket.stamp(stamp, Some(this_bucket_ref));
+                let new_bucket = new_bucket.with_tag(live_flags(this_bucket_ptr));
 
                 if let Err(CompareExchangeError { new, .. }) = this_bucket.compare_exchange_weak(
                     this_bucket_ptr,
                     new_bucket,
-                    Ordering::Release,
-                    Ordering::Relaxed,
+                    ordering::RELEASE,
+                    ordering::RELAXED,
                     guard,
                 ) {
                     maybe_key_or_owned_bucket = Some(KeyOrOwnedBucket::OwnedBucket(new));
@@ -238,21 +948,133 @@ impl<'g, K: 'g + Eq, V: 'g> BucketArray<K, V> {
             .ok_or_else(|| (maybe_key_or_owned_bucket.unwrap(), modifier))
     }
 
+    /// Keeps, replaces or removes the entry of the key as `decide` returns,
+    /// returning the bucket it was last called with and what was done, or
+    /// null if the key is not in the array.
+    ///
+    /// If the bucket is replaced before the decision is carried out, `decide`
+    /// is called again with the bucket that replaced it, which is in the same
+    /// slot, so the key is not probed for again.
+    // https://rust-lang.github.io/rust-clippy/master/index.html#type_complexity
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn decide<F: FnMut(&K, &V) -> Decision<V>>(
+        &self,
+        guard: &'g Guard,
+        hash: u64,
+        key_or_owned_bucket: KeyOrOwnedBucket<K, V>,
+        stamp: Stamp,
+        mut decide: F,
+    ) -> Result<(SharedBucket<'g, K, V>, Decided), (KeyOrOwnedBucket<K, V>, F)> {
+        let mut maybe_key_or_owned_bucket = Some(key_or_owned_bucket);
+
+        let loop_result = self.probe_loop(guard, hash, |_, this_bucket, this_bucket_ptr| {
+            let mut key_or_owned_bucket = maybe_key_or_owned_bucket.take().unwrap();
+
+            let this_bucket_ref = if let Some(this_bucket_ref) = unsafe { this_bucket_ptr.as_ref() }
+            {
+                this_bucket_ref
+            } else {
+                key_or_owned_bucket.drop_value();
+
+                return ProbeLoopAction::Return((Shared::null(), Decided::Kept));
+            };
+
+            let this_key = &this_bucket_ref.key;
+
+            if key_or_owned_bucket.key() != this_key {
+                maybe_key_or_owned_bucket = Some(key_or_owned_bucket);
+
+                return ProbeLoopAction::Continue;
+            } else if this_bucket_ptr.tag() & TOMBSTONE_TAG != 0 {
+                key_or_owned_bucket.drop_value();
+
+                return ProbeLoopAction::Return((Shared::null(), Decided::Kept));
+            }
+
+            let decision = on_unwind(
+                &mut key_or_owned_bucket,
+                |_| unsafe { decide(this_key, this_bucket_ref.value()) },
+                KeyOrOwnedBucket::drop_value,
+            );
+
+            match decision {
+                Decision::Keep => {
+                    key_or_owned_bucket.drop_value();
+
+                    ProbeLoopAction::Return((this_bucket_ptr, Decided::Kept))
+                }
+                Decision::Replace(new_value) => {
+                    let mut new_bucket = key_or_owned_bucket.into_bucket(new_value);
+                    new_bucket.stamp(stamp, Some(this_bucket_ref));
+                    let new_bucket = new_bucket.with_tag(live_flags(this_bucket_ptr));
+
+                    match this_bucket.compare_exchange_weak(
+                        this_bucket_ptr,
+                        new_bucket,
+                        ordering::RELEASE,
+                        ordering::RELAXED,
+                        guard,
+                    ) {
+                        Ok(_) => ProbeLoopAction::Return((this_bucket_ptr, Decided::Replaced)),
+                        Err(CompareExchangeError { new, .. }) => {
+                            maybe_key_or_owned_bucket = Some(KeyOrOwnedBucket::OwnedBucket(new));
+
+                            ProbeLoopAction::Reload
+                        }
+                    }
+                }
+                Decision::Remove => {
+                    let new_bucket_ptr = this_bucket_ptr.with_tag(TOMBSTONE_TAG);
+                    let sequence = stamp.next_sequence();
+
+                    match this_bucket.compare_exchange_weak(
+                        this_bucket_ptr,
+                        new_bucket_ptr,
+                        ordering::RELEASE,
+                        ordering::RELAXED,
+                        guard,
+                    ) {
+                        Ok(_) => {
+                            if stamp.sequence.is_some() {
+                                this_bucket_ref.sequence.set(sequence);
+                            }
+
+                            key_or_owned_bucket.drop_value();
+
+                            ProbeLoopAction::Return((new_bucket_ptr, Decided::Removed))
+                        }
+                        Err(_) => {
+                            maybe_key_or_owned_bucket = Some(key_or_owned_bucket);
+
+                            ProbeLoopAction::Reload
+                        }
+                    }
+                }
+            }
+        });
+
+        loop_result
+            .returned()
+            .ok_or_else(|| (maybe_key_or_owned_bucket.unwrap(), decide))
+    }
+
     // https://rust-lang.github.io/rust-clippy/master/index.html#type_complexity
     #[allow(clippy::type_complexity)]
-    pub(crate) fn insert_or_modify<F: FnOnce() -> V, G: FnMut(&K, &V) -> V>(
+    pub(crate) fn insert_or_modify<F: FnOnce(&K) -> V, G: FnMut(&K, &V) -> V>(
         &self,
         guard: &'g Guard,
         hash: u64,
         state: InsertOrModifyState<K, V, F>,
+        stamp: Stamp,
         mut modifier: G,
-    ) -> Result<SharedBucket<'g, K, V>, (InsertOrModifyState<K, V, F>, G)> {
+    ) -> Result<(SharedBucket<'g, K, V>, SharedBucket<'g, K, V>), (InsertOrModifyState<K, V, F>, G)>
+    {
         let mut maybe_state = Some(state);
 
         let loop_result = self.probe_loop(guard, hash, |_, this_bucket, this_bucket_ptr| {
-            let state = maybe_state.take().unwrap();
+            let mut state = maybe_state.take().unwrap();
 
-            let (new_bucket, maybe_insert_value) =
+            let (mut new_bucket, maybe_insert_value) =
                 if let Some(this_bucket_ref) = unsafe { this_bucket_ptr.as_ref() } {
                     let this_key = &this_bucket_ref.key;
 
@@ -263,10 +1085,14 @@ impl<'g, K: 'g + Eq, V: 'g> BucketArray<K, V> {
                     }
 
                     if this_bucket_ptr.tag() & TOMBSTONE_TAG == 0 {
-                        let this_value = unsafe { &*this_bucket_ref.maybe_value.as_ptr() };
-                        let new_value = modifier(this_key, this_value);
+                        let new_value = on_unwind(
+                            &mut state,
+                            |_| unsafe { modifier(this_key, this_bucket_ref.value()) },
+                            InsertOrModifyState::drop_value,
+                        );
 
-                        let (new_bucket, insert_value) = state.into_modify_bucket(new_value);
+                        let (mut new_bucket, insert_value) = state.into_modify_bucket(new_value);
+                        new_bucket.stamp(stamp, Some(this_bucket_ref));
 
                         (new_bucket, Some(insert_value))
                     } else {
@@ -276,21 +1102,28 @@ impl<'g, K: 'g + Eq, V: 'g> BucketArray<K, V> {
                     (state.into_insert_bucket(), None)
                 };
 
-            if let Err(CompareExchangeError { new, .. }) = this_bucket.compare_exchange_weak(
+            if maybe_insert_value.is_none() {
+                new_bucket.stamp(stamp, None);
+            }
+
+            let new_bucket = new_bucket.with_tag(live_flags(this_bucket_ptr));
+
+            match this_bucket.compare_exchange_weak(
                 this_bucket_ptr,
                 new_bucket,
-                Ordering::Release,
-                Ordering::Relaxed,
+                ordering::RELEASE,
+                ordering::RELAXED,
                 guard,
             ) {
-                maybe_state = Some(InsertOrModifyState::from_bucket_value(
-                    new,
-                    maybe_insert_value,
-                ));
+                Ok(new_bucket_ptr) => ProbeLoopAction::Return((this_bucket_ptr, new_bucket_ptr)),
+                Err(CompareExchangeError { new, .. }) => {
+                    maybe_state = Some(InsertOrModifyState::from_bucket_value(
+                        new,
+                        maybe_insert_value,
+                    ));
 
-                ProbeLoopAction::Reload
-            } else {
-                ProbeLoopAction::Return(this_bucket_ptr)
+                    ProbeLoopAction::Reload
+                }
             }
         });
 
@@ -299,12 +1132,333 @@ impl<'g, K: 'g + Eq, V: 'g> BucketArray<K, V> {
             .ok_or_else(|| (maybe_state.unwrap(), modifier))
     }
 
+    // https://rust-lang.github.io/rust-clippy/master/index.html#type_complexity
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn get_or_insert<F: FnOnce(&K) -> V>(
+        &self,
+        guard: &'g Guard,
+        hash: u64,
+        state: InsertOrModifyState<K, V, F>,
+        stamp: Stamp,
+    ) -> Result<(SharedBucket<'g, K, V>, SharedBucket<'g, K, V>), InsertOrModifyState<K, V, F>>
+    {
+        let mut maybe_state = Some(state);
+
+        let loop_result = self.probe_loop(guard, hash, |_, this_bucket, this_bucket_ptr| {
+            let state = maybe_state.take().unwrap();
+
+            if let Some(this_bucket_ref) = unsafe { this_bucket_ptr.as_ref() } {
+                if &this_bucket_ref.key != state.key() {
+                    maybe_state = Some(state);
+
+                    return ProbeLoopAction::Continue;
+                }
+
+                if this_bucket_ptr.tag() & TOMBSTONE_TAG == 0 {
+                    if let InsertOrModifyState::AttemptedInsertion(mut b) = state {
+                        unsafe { b.value.drop_value() };
+                    }
+
+                    return ProbeLoopAction::Return((this_bucket_ptr, this_bucket_ptr));
+                }
+            }
+
+            let mut new_bucket = state.into_insert_bucket();
+            new_bucket.stamp(stamp, None);
+            let new_bucket = new_bucket.with_tag(0);
+
+            match this_bucket.compare_exchange_weak(
+                this_bucket_ptr,
+                new_bucket,
+                ordering::RELEASE,
+                ordering::RELAXED,
+                guard,
+            ) {
+                Ok(new_bucket_ptr) => ProbeLoopAction::Return((this_bucket_ptr, new_bucket_ptr)),
+                Err(CompareExchangeError { new, .. }) => {
+                    maybe_state = Some(InsertOrModifyState::AttemptedInsertion(new));
+
+                    ProbeLoopAction::Reload
+                }
+            }
+        });
+
+        loop_result.returned().ok_or_else(|| maybe_state.unwrap())
+    }
+
+    /// Prefetches the cache line of the home slot of `hash`, where the probing
+    /// for its key starts.
+    pub(crate) fn prefetch_home_slot(&self, hash: u64) {
+        let index = hash as usize & (self.buckets.len() - 1);
+
+        prefetch(&self.buckets[index]);
+    }
+
+    /// Returns the first live bucket in the slots from `start` on, wrapping
+    /// around the end of the array, or `None` if the array has none.
+    pub(crate) fn first_live_bucket_from(
+        &self,
+        guard: &'g Guard,
+        start: usize,
+    ) -> Result<Option<&'g Bucket<K, V>>, RelocatedError> {
+        let mask = self.buckets.len() - 1;
+
+        for i in (0..self.buckets.len()).map(|i| i.wrapping_add(start) & mask) {
+            let this_bucket_ptr = ordering::load_consume(&self.buckets[i], guard);
+
+            if this_bucket_ptr.tag() & SENTINEL_TAG != 0 {
+                return Err(RelocatedError);
+            }
+
+            if this_bucket_ptr.tag() & TOMBSTONE_TAG != 0 {
+                continue;
+            }
+
+            if let Some(this_bucket_ref) = unsafe { this_bucket_ptr.as_ref() } {
+                return Ok(Some(this_bucket_ref));
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub(crate) fn live_buckets(
+        &self,
+        guard: &'g Guard,
+    ) -> Result<Vec<&'g Bucket<K, V>>, RelocatedError> {
+        let mut buckets = Vec::new();
+
+        for this_bucket in self.buckets.iter() {
+            let this_bucket_ptr = ordering::load_consume(this_bucket, guard);
+
+            if this_bucket_ptr.tag() & SENTINEL_TAG != 0 {
+                return Err(RelocatedError);
+            }
+
+            if this_bucket_ptr.tag() & TOMBSTONE_TAG != 0 {
+                continue;
+            }
+
+            if let Some(this_bucket_ref) = unsafe { this_bucket_ptr.as_ref() } {
+                buckets.push(this_bucket_ref);
+            }
+        }
+
+        Ok(buckets)
+    }
+
+    /// Returns the index of the first empty slot, or 0 if every slot is
+    /// occupied.
+    ///
+    /// A walk over the clusters of the array starts there, so that no cluster
+    /// wraps around from the end of the walk to its start. See
+    /// [`read_cluster`].
+    ///
+    /// [`read_cluster`]: #method.read_cluster
+    pub(crate) fn first_empty_slot(&self, guard: &'g Guard) -> Result<usize, RelocatedError> {
+        for (i, this_bucket) in self.buckets.iter().enumerate() {
+            let this_bucket_ptr = ordering::load_consume(this_bucket, guard);
+
+            if this_bucket_ptr.tag() & SENTINEL_TAG != 0 {
+                return Err(RelocatedError);
+            }
+
+            if this_bucket_ptr.is_null() {
+                return Ok(i);
+            }
+        }
+
+        Ok(0)
+    }
+
+    /// Reads the slots of a walk that started at slot `origin`, from the
+    /// `start`th one on until an empty one or the end of the walk, pushing the
+    /// live buckets onto `buckets`. Returns the number of slots of the walk
+    /// that were read so far, including the empty one.
+    ///
+    /// The slots read this way form a cluster: a probe never crosses an empty
+    /// slot, so if `origin` was empty, every bucket that was in the array when
+    /// the walk started and whose home slot is in the cluster is in the
+    /// cluster too.
+    pub(crate) fn read_cluster(
+        &self,
+        guard: &'g Guard,
+        origin: usize,
+        start: usize,
+        buckets: &mut Vec<*const Bucket<K, V>>,
+    ) -> Result<usize, RelocatedError> {
+        let mask = self.buckets.len() - 1;
+        let mut end = start;
+
+        while end < self.buckets.len() {
+            let this_bucket_ptr =
+                ordering::load_consume(&self.buckets[end.wrapping_add(origin) & mask], guard);
+            end += 1;
+
+            if this_bucket_ptr.tag() & SENTINEL_TAG != 0 {
+                return Err(RelocatedError);
+            }
+
+            if this_bucket_ptr.tag() & TOMBSTONE_TAG != 0 {
+                continue;
+            }
+
+            if this_bucket_ptr.is_null() {
+                break;
+            }
+
+            buckets.push(this_bucket_ptr.as_raw());
+        }
+
+        Ok(end)
+    }
+
+    /// Collects the live buckets whose keys have [scan positions] of at least
+    /// `from`, visiting the home slots in the order of the scan positions of
+    /// their keys until at least `limit` buckets are collected.
+    ///
+    /// Returns the buckets in the order of their scan positions, and the scan
+    /// position to resume from, or `None` if the last home slot was visited.
+    /// A scan never stops in the middle of a home slot, so the returned
+    /// position is the first of a home slot in this bucket array and in all
+    /// larger ones.
+    ///
+    /// [scan positions]: fn.scan_position.html
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn scan<H: BuildHasher>(
+        &self,
+        guard: &'g Guard,
+        build_hasher: &H,
+        from: u64,
+        limit: usize,
+    ) -> Result<(Vec<&'g Bucket<K, V>>, Option<u64>), RelocatedError>
+    where
+        K: Hash,
+    {
+        let mask = self.buckets.len() - 1;
+        let bits = self.buckets.len().trailing_zeros();
+        let shift = 64 - bits;
+        let mut buckets = Vec::new();
+        // the hashes of the buckets seen so far by slot, since the home slots
+        // of a run are visited separately and each walk covers the rest of it
+        let mut hashes: HashMap<usize, (*const Bucket<K, V>, u64)> = HashMap::new();
+
+        // the home slots in the order of their scan positions, which are the
+        // positions of the slots with their bits reversed
+        let mut home = from >> shift;
+
+        let next = loop {
+            let offset = (home << shift).reverse_bits() as usize;
+
+            // the buckets whose home slot is `offset` are in the run of
+            // occupied slots that starts there
+            for i in (0..self.buckets.len()).map(|i| i.wrapping_add(offset) & mask) {
+                let this_bucket_ptr = ordering::load_consume(&self.buckets[i], guard);
+
+                if this_bucket_ptr.tag() & SENTINEL_TAG != 0 {
+                    return Err(RelocatedError);
+                }
+
+                let this_bucket_ref = match unsafe { this_bucket_ptr.as_ref() } {
+                    Some(this_bucket_ref) => this_bucket_ref,
+                    None => break,
+                };
+
+                if this_bucket_ptr.tag() & TOMBSTONE_TAG != 0 {
+                    continue;
+                }
+
+                let this_hash = match hashes.get(&i) {
+                    Some(&(ptr, this_hash)) if ptr == this_bucket_ptr.as_raw() => this_hash,
+                    _ => {
+                        let this_hash = hash(build_hasher, &this_bucket_ref.key);
+                        hashes.insert(i, (this_bucket_ptr.as_raw(), this_hash));
+
+                        this_hash
+                    }
+                };
+                let position = scan_position(this_hash);
+
+                if this_hash as usize & mask == offset && position >= from {
+                    buckets.push((position, this_bucket_ref));
+                }
+            }
+
+            home += 1;
+
+            if home >> bits != 0 {
+                break None;
+            }
+
+            if buckets.len() >= limit {
+                break Some(home << shift);
+            }
+        };
+
+        buckets.sort_by_key(|(position, _)| *position);
+
+        Ok((buckets.into_iter().map(|(_, b)| b).collect(), next))
+    }
+
+    pub(crate) fn probe_stats<H: BuildHasher>(
+        &self,
+        guard: &'g Guard,
+        build_hasher: &H,
+    ) -> Result<ProbeStats, RelocatedError>
+    where
+        K: Hash,
+    {
+        let mask = self.buckets.len() - 1;
+        let mut stats = ProbeStats {
+            num_buckets: self.buckets.len(),
+            ..Default::default()
+        };
+
+        for (i, this_bucket) in self.buckets.iter().enumerate() {
+            let this_bucket_ptr = ordering::load_consume(this_bucket, guard);
+
+            if this_bucket_ptr.tag() & SENTINEL_TAG != 0 {
+                return Err(RelocatedError);
+            }
+
+            let this_bucket_ref = if let Some(this_bucket_ref) = unsafe { this_bucket_ptr.as_ref() }
+            {
+                this_bucket_ref
+            } else {
+                continue;
+            };
+
+            stats.num_occupied += 1;
+
+            if this_bucket_ptr.tag() & TOMBSTONE_TAG != 0 {
+                continue;
+            }
+
+            let offset = hash(build_hasher, &this_bucket_ref.key) as usize & mask;
+            let probe_length = (i.wrapping_sub(offset) & mask) + 1;
+
+            stats.num_entries += 1;
+            stats.total_probe_length += probe_length;
+            stats.max_probe_length = stats.max_probe_length.max(probe_length);
+        }
+
+        Ok(stats)
+    }
+
+    /// Inserts a bucket that is being migrated from the previous bucket array,
+    /// returning the index of its slot, or `None` if the bucket does not have
+    /// to be inserted because this array already holds it or a newer bucket
+    /// of its key.
+    ///
+    /// Returns an error if a live bucket has no room in this array, because
+    /// it is full or has been relocated in turn, so that the caller migrates
+    /// the bucket to a later array instead of losing it.
     fn insert_for_grow(
         &self,
         guard: &'g Guard,
         hash: u64,
         bucket_ptr: SharedBucket<'g, K, V>,
-    ) -> Option<usize> {
+    ) -> Result<Option<usize>, RelocatedError> {
         assert!(!bucket_ptr.is_null());
         assert_eq!(bucket_ptr.tag() & SENTINEL_TAG, 0);
         assert_ne!(bucket_ptr.tag() & BORROWED_TAG, 0);
@@ -328,8 +1482,8 @@ impl<'g, K: 'g + Eq, V: 'g> BucketArray<K, V> {
                 .compare_exchange_weak(
                     this_bucket_ptr,
                     bucket_ptr,
-                    Ordering::Release,
-                    Ordering::Relaxed,
+                    ordering::RELEASE,
+                    ordering::RELAXED,
                     guard,
                 )
                 .is_ok()
@@ -340,7 +1494,12 @@ impl<'g, K: 'g + Eq, V: 'g> BucketArray<K, V> {
             }
         });
 
-        loop_result.returned().flatten()
+        match loop_result {
+            ProbeLoopResult::Returned(maybe_index) => Ok(maybe_index),
+            // a tombstone only has to replace an older bucket of its key
+            _ if bucket_ptr.tag() & TOMBSTONE_TAG != 0 => Ok(None),
+            ProbeLoopResult::LoopEnded | ProbeLoopResult::FoundSentinelTag => Err(RelocatedError),
+        }
     }
 }
 
@@ -362,13 +1521,16 @@ impl<'g, K: 'g, V: 'g> BucketArray<K, V> {
             let this_bucket = &self.buckets[i];
 
             loop {
-                let this_bucket_ptr = this_bucket.load_consume(guard);
+                let this_bucket_ptr = ordering::load_consume(this_bucket, guard);
 
                 if this_bucket_ptr.tag() & SENTINEL_TAG != 0 {
                     return ProbeLoopResult::FoundSentinelTag;
                 }
 
-                match f(i, this_bucket, this_bucket_ptr) {
+                let action = f(i, this_bucket, this_bucket_ptr);
+                ordering::audit_fence();
+
+                match action {
                     ProbeLoopAction::Continue => break,
                     ProbeLoopAction::Reload => (),
                     ProbeLoopAction::Return(t) => return ProbeLoopResult::Returned(t),
@@ -387,125 +1549,487 @@ impl<'g, K: 'g, V: 'g> BucketArray<K, V> {
     where
         K: Hash + Eq,
     {
-        let next_array = self.next_array(guard);
-        assert!(self.buckets.len() <= next_array.buckets.len());
+        self.rehash_with(guard, build_hasher, RehashOp::Expand)
+    }
+
+    /// Migrates the buckets to the next bucket array, creating it according to
+    /// `op` if no other thread has done so yet.
+    pub(crate) fn rehash_with<H: BuildHasher>(
+        &self,
+        guard: &'g Guard,
+        build_hasher: &H,
+        op: RehashOp,
+    ) -> &'g BucketArray<K, V>
+    where
+        K: Hash + Eq,
+    {
+        let next_array = self.next_array(guard, op);
 
         for this_bucket in self.buckets.iter() {
-            let mut maybe_state: Option<(usize, SharedBucket<'g, K, V>)> = None;
+            Self::migrate_bucket(guard, build_hasher, next_array, this_bucket);
+        }
 
-            loop {
-                let this_bucket_ptr = this_bucket.load_consume(guard);
+        next_array
+    }
 
-                if this_bucket_ptr.tag() & SENTINEL_TAG != 0 {
-                    break;
-                }
+    /// Like [`rehash`], but migrates at most as many buckets as `budget`
+    /// allows, starting from the slot of `hash` so that concurrent readers
+    /// spread their help over the array.
+    ///
+    /// Returns [`Error::Busy`] if the budget runs out before every bucket has
+    /// been migrated. The buckets migrated so far stay migrated.
+    ///
+    /// [`rehash`]: #method.rehash
+    /// [`Error::Busy`]: ../../enum.Error.html#variant.Busy
+    pub(crate) fn rehash_within<H: BuildHasher>(
+        &self,
+        guard: &'g Guard,
+        build_hasher: &H,
+        hash: u64,
+        budget: &mut ReadBudget,
+    ) -> Result<&'g BucketArray<K, V>, Error>
+    where
+        K: Hash + Eq,
+    {
+        let next_array = self.next_array(guard, RehashOp::Expand);
+        let offset = hash as usize & (self.buckets.len() - 1);
 
-                let to_put_ptr = this_bucket_ptr.with_tag(this_bucket_ptr.tag() | BORROWED_TAG);
+        for i in
+            (0..self.buckets.len()).map(|i| (i.wrapping_add(offset)) & (self.buckets.len() - 1))
+        {
+            if !budget.spend_migration() {
+                return Err(Error::Busy);
+            }
 
-                if let Some((index, mut next_bucket_ptr)) = maybe_state {
-                    assert!(!this_bucket_ptr.is_null());
+            Self::migrate_bucket(guard, build_hasher, next_array, &self.buckets[i]);
+        }
 
-                    let next_bucket = &next_array.buckets[index];
+        Ok(next_array)
+    }
 
-                    while next_bucket_ptr.tag() & BORROWED_TAG != 0
-                        && next_bucket
-                            .compare_exchange_weak(
-                                next_bucket_ptr,
-                                to_put_ptr,
-                                Ordering::Release,
-                                Ordering::Relaxed,
-                                guard,
-                            )
-                            .is_err()
-                    {
-                        next_bucket_ptr = next_bucket.load_consume(guard);
-                    }
-                } else if let Some(this_bucket_ref) = unsafe { this_bucket_ptr.as_ref() } {
-                    let key = &this_bucket_ref.key;
-                    let hash = hash(build_hasher, key);
+    /// Moves the bucket in `this_bucket` to `next_array`, unless another thread
+    /// has already done so, and replaces it with a sentinel.
+    fn migrate_bucket<H: BuildHasher>(
+        guard: &'g Guard,
+        build_hasher: &H,
+        next_array: &'g BucketArray<K, V>,
+        this_bucket: &Atomic<Bucket<K, V>>,
+    ) where
+        K: Hash + Eq,
+    {
+        #[allow(clippy::type_complexity)]
+        let mut maybe_state: Option<(
+            &'g BucketArray<K, V>,
+            usize,
+            SharedBucket<'g, K, V>,
+        )> = None;
+
+        loop {
+            let this_bucket_ptr = ordering::load_consume(this_bucket, guard);
+
+            if this_bucket_ptr.tag() & SENTINEL_TAG != 0 {
+                break;
+            }
+
+            let to_put_ptr = this_bucket_ptr.with_tag(this_bucket_ptr.tag() | BORROWED_TAG);
 
-                    if let Some(index) = next_array.insert_for_grow(guard, hash, to_put_ptr) {
-                        maybe_state = Some((index, to_put_ptr));
+            if let Some((target_array, index, mut next_bucket_ptr)) = maybe_state {
+                assert!(!this_bucket_ptr.is_null());
+
+                let next_bucket = &target_array.buckets[index];
+
+                while next_bucket_ptr.tag() & BORROWED_TAG != 0
+                    && next_bucket
+                        .compare_exchange_weak(
+                            next_bucket_ptr,
+                            to_put_ptr,
+                            ordering::RELEASE,
+                            ordering::RELAXED,
+                            guard,
+                        )
+                        .is_err()
+                {
+                    next_bucket_ptr = ordering::load_consume(next_bucket, guard);
+                }
+            } else if let Some(this_bucket_ref) = unsafe { this_bucket_ptr.as_ref() } {
+                // hashing and comparing keys are the only calls into user
+                // code, and they come before either array is changed, so a
+                // panic leaves the bucket in this array only and the next
+                // thread to find a sentinel resumes the rehash
+                let key = &this_bucket_ref.key;
+                let hash = hash(build_hasher, key);
+                let mut target_array = next_array;
+
+                // a shrunk array can be filled by concurrent insertions before
+                // every bucket is migrated to it, in which case the bucket
+                // goes to an expanded array instead of being overwritten by
+                // the sentinel below
+                loop {
+                    match target_array.insert_for_grow(guard, hash, to_put_ptr) {
+                        Ok(maybe_index) => {
+                            maybe_state =
+                                maybe_index.map(|index| (target_array, index, to_put_ptr));
+
+                            break;
+                        }
+                        Err(_) => {
+                            target_array =
+                                target_array.rehash_with(guard, build_hasher, RehashOp::Expand);
+                        }
                     }
                 }
+            }
 
-                if this_bucket
-                    .compare_exchange_weak(
-                        this_bucket_ptr,
-                        Shared::null().with_tag(SENTINEL_TAG),
-                        Ordering::Release,
-                        Ordering::Relaxed,
-                        guard,
-                    )
-                    .is_ok()
+            if this_bucket
+                .compare_exchange_weak(
+                    this_bucket_ptr,
+                    Shared::null().with_tag(SENTINEL_TAG),
+                    ordering::RELEASE,
+                    ordering::RELAXED,
+                    guard,
+                )
+                .is_ok()
+            {
+                if !this_bucket_ptr.is_null()
+                    && this_bucket_ptr.tag() & TOMBSTONE_TAG != 0
+                    && maybe_state.is_none()
                 {
-                    if !this_bucket_ptr.is_null()
-                        && this_bucket_ptr.tag() & TOMBSTONE_TAG != 0
-                        && maybe_state.is_none()
-                    {
-                        unsafe { defer_destroy_bucket(guard, this_bucket_ptr) };
-                    }
+                    unsafe { defer_destroy_bucket(guard, this_bucket_ptr) };
+                }
 
-                    break;
+                break;
+            }
+        }
+    }
+
+    fn next_array(&self, guard: &'g Guard, op: RehashOp) -> &'g BucketArray<K, V> {
+        let mut maybe_new_next = None;
+
+        loop {
+            let next_ptr = ordering::load_consume(&self.next, guard);
+
+            if let Some(next_ref) = unsafe { next_ptr.as_ref() } {
+                return next_ref;
+            }
+
+            let new_next = maybe_new_next.unwrap_or_else(|| {
+                Owned::new(BucketArray::with_length(
+                    self.epoch + 1,
+                    op.next_length(self.buckets.len()),
+                ))
+            });
+
+            match self.next.compare_exchange_weak(
+                Shared::null(),
+                new_next,
+                ordering::RELEASE,
+                ordering::RELAXED,
+                guard,
+            ) {
+                Ok(p) => {
+                    crate::metrics::note_resize();
+
+                    return unsafe { p.deref() };
+                }
+                Err(CompareExchangeError { new, .. }) => {
+                    maybe_new_next = Some(new);
                 }
             }
         }
+    }
+}
+
+/// An opaque hint of the slot that a removed entry occupied.
+///
+/// A hint is returned by the `remove_entry_with_hint_and` methods of the hash
+/// maps, and can be passed to their `insert_with_hint` methods to reinsert the
+/// same key into the same slot without probing. A hint that is no longer
+/// valid, for example because the map has been resized since, is ignored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SlotHint {
+    pub(crate) hash: u64,
+    pub(crate) epoch: usize,
+    pub(crate) index: usize,
+}
+
+/// How the length of the next bucket array is chosen when a thread starts a
+/// rehash.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RehashOp {
+    /// Doubles the length, because the array is full.
+    Expand,
+    /// Halves the length, because the array is sparsely populated.
+    Shrink,
+    /// Keeps the length, only to reclaim tombstones.
+    GcOnly,
+    /// Grows the array to at least the given length, to reserve capacity.
+    Reserve(usize),
+}
+
+impl RehashOp {
+    /// The length below which bucket arrays are not shrunk.
+    pub(crate) const MIN_LENGTH: usize = 128;
+
+    /// Returns the operation that housekeeping should perform on a bucket
+    /// array with the given statistics, if any.
+    pub(crate) fn for_housekeeping(
+        stats: &ProbeStats,
+        tombstone_policy: TombstonePolicy,
+    ) -> Option<Self> {
+        let capacity = stats.num_buckets / 2;
+        let num_tombstones = stats.num_occupied - stats.num_entries;
+
+        if stats.num_buckets > Self::MIN_LENGTH && stats.num_entries < capacity / 4 {
+            Some(RehashOp::Shrink)
+        } else if tombstone_policy.should_compact(num_tombstones, capacity) {
+            Some(RehashOp::GcOnly)
+        } else {
+            None
+        }
+    }
+
+    fn next_length(self, length: usize) -> usize {
+        match self {
+            RehashOp::Expand => length
+                .checked_mul(2)
+                .filter(|&length| length <= MAX_LENGTH)
+                .unwrap_or_else(|| panic!("{}", Error::CapacityOverflow)),
+            RehashOp::Shrink => length / 2,
+            RehashOp::GcOnly => length,
+            RehashOp::Reserve(min_length) => length.max(min_length),
+        }
+    }
+}
+
+// with the entry-flags feature, aligned so that bucket pointers have room for
+// the entry flags after the internal tags
+#[cfg_attr(not(feature = "entry-flags"), repr(align(8)))]
+#[cfg_attr(feature = "entry-flags", repr(align(32)))]
+#[derive(Debug)]
+pub(crate) struct Bucket<K, V> {
+    pub(crate) key: K,
+    pub(crate) value: ValueSlot<V>,
+    // kept when the value is modified in place, but not when the key is
+    // reinserted after removal
+    generation: Generation,
+    last_access: AccessTime,
+    pub(crate) sequence: Sequence,
+    // the references to the bucket in units of PIN_UNIT, one held by the map
+    // until the bucket is reclaimed and one per EntryPin, and the PIN_* flags
+    #[cfg(feature = "entry-pins")]
+    pins: AtomicUsize,
+}
+
+impl<K, V> Bucket<K, V> {
+    pub(crate) fn new(key: K, value: V) -> Bucket<K, V> {
+        Bucket {
+            key,
+            value: ValueSlot::new(value),
+            generation: Generation::new(0),
+            last_access: AccessTime::new(0),
+            sequence: Sequence::new(0),
+            #[cfg(feature = "entry-pins")]
+            pins: AtomicUsize::new(PIN_UNIT),
+        }
+    }
+
+    /// Stamps a bucket that is about to be published, keeping the generation
+    /// of the live bucket it replaces, if any.
+    ///
+    /// A bucket is stamped again each time its publication is retried, after
+    /// the slot it replaces has been loaded, so that the sequence numbers of
+    /// the mutations of a key increase in the order in which they take effect.
+    fn stamp(&mut self, stamp: Stamp, replaced: Option<&Self>) {
+        self.generation =
+            Generation::new(replaced.map_or(stamp.generation, |b| b.generation.get()));
+        self.last_access.set_mut(stamp.access);
+        self.sequence.set_mut(stamp.next_sequence());
+    }
+
+    /// Adds a pin to the bucket, returning `false` if its entry has been
+    /// removed.
+    #[cfg(feature = "entry-pins")]
+    ///
+    /// # Safety
+    ///
+    /// The bucket must be protected by a guard, or by another pin.
+    pub(crate) unsafe fn try_pin(&self) -> bool {
+        let pins = self.pins.fetch_add(PIN_UNIT, ordering::ACQUIRE);
+
+        if pins & PIN_REMOVED == 0 {
+            true
+        } else {
+            unpin(self as *const _ as *mut Self);
+
+            false
+        }
+    }
+}
+
+impl<K, V> Bucket<K, V> {
+    #[cfg(feature = "generations")]
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation.get()
+    }
+
+    #[cfg(feature = "access-times")]
+    pub(crate) fn last_access(&self) -> u64 {
+        self.last_access.get()
+    }
+
+    /// Records an access at `access` on the map's access clock.
+    pub(crate) fn touch(&self, access: u64) {
+        self.last_access.touch(access);
+    }
 
-        next_array
+    /// Returns whether the map has released its reference to the bucket, after
+    /// which only the pins keep it alive.
+    #[cfg(feature = "entry-pins")]
+    pub(crate) fn is_released(&self) -> bool {
+        self.pins.load(ordering::ACQUIRE) & PIN_RELEASED != 0
     }
 
-    fn next_array(&self, guard: &'g Guard) -> &'g BucketArray<K, V> {
-        let mut maybe_new_next = None;
+    /// Adds a pin to a bucket that is already pinned by the caller.
+    #[cfg(feature = "entry-pins")]
+    pub(crate) fn add_pin(&self) {
+        self.pins.fetch_add(PIN_UNIT, ordering::RELAXED);
+    }
+}
 
-        loop {
-            let next_ptr = self.next.load_consume(guard);
+/// Removes a pin that was added by [`Bucket::try_pin`], retiring the value of
+/// the bucket if its entry was removed while it was pinned, and reclaiming the
+/// bucket if the map has released it.
+///
+/// # Safety
+///
+/// The caller must own the pin, and must not use the bucket afterwards.
+#[cfg(feature = "entry-pins")]
+pub(crate) unsafe fn unpin<K, V>(ptr: *mut Bucket<K, V>) {
+    unpin_with(ptr, |value| {
+        crossbeam_epoch::pin().defer_unchecked(move || mem::drop(value))
+    });
+}
 
-            if let Some(next_ref) = unsafe { next_ptr.as_ref() } {
-                return next_ref;
+/// Removes a pin like [`unpin`], but drops a value that the pin retires right
+/// away.
+///
+/// # Safety
+///
+/// The caller must own the pin, must not use the bucket afterwards, and must
+/// have exclusive access to the map that the bucket belongs to, so that no
+/// guard can still reference the value.
+#[cfg(feature = "entry-pins")]
+pub(crate) unsafe fn unpin_exclusive<K, V>(ptr: *mut Bucket<K, V>) {
+    unpin_with(ptr, mem::drop);
+}
+
+#[cfg(feature = "entry-pins")]
+unsafe fn unpin_with<K, V>(ptr: *mut Bucket<K, V>, retire: impl FnOnce(V)) {
+    let bucket = &*ptr;
+    let mut pins = bucket.pins.load(ordering::ACQUIRE);
+
+    // the last pin of a removed entry retires the value while the map still
+    // holds the bucket. other threads can not add pins after the removal, and
+    // the last pin can not be cloned while it is being dropped, so a pin that
+    // observes that it is the last one really is. readers that loaded the
+    // bucket before the removal may still hold references to the value under
+    // their guards, so it is moved out and dropped once they have unpinned.
+    while pins & (PIN_VALUE_PENDING | PIN_RELEASED) == PIN_VALUE_PENDING && pins / PIN_UNIT == 2 {
+        match bucket.pins.compare_exchange_weak(
+            pins,
+            pins & !PIN_VALUE_PENDING,
+            ordering::ACQ_REL,
+            ordering::ACQUIRE,
+        ) {
+            Ok(_) => {
+                retire(bucket.value.take());
+
+                break;
             }
+            Err(current) => pins = current,
+        }
+    }
 
-            let new_next = maybe_new_next.unwrap_or_else(|| {
-                Owned::new(BucketArray::with_length(
-                    self.epoch + 1,
-                    self.buckets.len() * 2,
-                ))
-            });
+    let pins = bucket.pins.fetch_sub(PIN_UNIT, ordering::ACQ_REL) - PIN_UNIT;
+    reclaim_if_unreferenced(ptr, pins);
+}
 
-            match self.next.compare_exchange_weak(
-                Shared::null(),
-                new_next,
-                Ordering::Release,
-                Ordering::Relaxed,
-                guard,
-            ) {
-                Ok(p) => return unsafe { p.deref() },
-                Err(CompareExchangeError { new, .. }) => {
-                    maybe_new_next = Some(new);
-                }
-            }
+/// Releases the map's reference to a bucket that is no longer reachable,
+/// setting `flags`, which must not be set yet.
+#[cfg(feature = "entry-pins")]
+unsafe fn release<K, V>(ptr: *mut Bucket<K, V>, flags: usize) {
+    // adding the flags and removing the reference is a single subtraction
+    let delta = PIN_UNIT - flags;
+    let pins = (*ptr).pins.fetch_sub(delta, ordering::ACQ_REL) - delta;
+    reclaim_if_unreferenced(ptr, pins);
+}
+
+#[cfg(feature = "entry-pins")]
+unsafe fn reclaim_if_unreferenced<K, V>(ptr: *mut Bucket<K, V>, pins: usize) {
+    if pins / PIN_UNIT == 0 {
+        // the map's reference is only released by a deferred function, after
+        // which no guard can still reach the bucket, so only the pins could
+        // have read the value
+        if pins & PIN_VALUE_PENDING != 0 {
+            (*ptr).value.drop_value();
         }
+
+        mem::drop(Owned::from_raw(ptr));
     }
 }
 
-#[repr(align(8))]
-#[derive(Debug)]
-pub(crate) struct Bucket<K, V> {
-    pub(crate) key: K,
-    pub(crate) maybe_value: MaybeUninit<V>,
+/// The metadata a bucket is stamped with when it is published.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Stamp<'a> {
+    pub(crate) generation: u64,
+    pub(crate) access: u64,
+    // the counter that assigns sequence numbers to the mutations of a
+    // segment, if the map assigns them
+    pub(crate) sequence: Option<&'a AtomicU64>,
+}
+
+impl Stamp<'_> {
+    /// Assigns the next sequence number, or returns 0 if the map does not
+    /// assign them.
+    ///
+    /// The number must be taken after the slot that the mutation replaces has
+    /// been loaded: the mutation that published the slot took its number
+    /// before, so a relaxed increment is ordered after it.
+    pub(crate) fn next_sequence(&self) -> u64 {
+        self.sequence
+            .map_or(0, |s| s.fetch_add(1, ordering::RELAXED) + 1)
+    }
 }
 
 impl<K, V> Bucket<K, V> {
-    pub(crate) fn new(key: K, value: V) -> Bucket<K, V> {
-        Bucket {
-            key,
-            maybe_value: MaybeUninit::new(value),
-        }
+    /// # Safety
+    ///
+    /// The bucket must not be a tombstone whose value has been moved out.
+    pub(crate) unsafe fn value(&self) -> &V {
+        self.value.get()
+    }
+
+    /// # Safety
+    ///
+    /// The bucket must not be a tombstone whose value has been moved out.
+    pub(crate) unsafe fn value_mut(&mut self) -> &mut V {
+        self.value.get_mut()
     }
 }
 
 #[derive(Debug, Eq, PartialEq)]
 pub(crate) struct RelocatedError;
 
+/// What [`BucketArray::decide`] did with the entry it found.
+///
+/// [`BucketArray::decide`]: struct.BucketArray.html#method.decide
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Decided {
+    Kept,
+    Replaced,
+    Removed,
+}
+
 pub(crate) enum KeyOrOwnedBucket<K, V> {
     Key(K),
     OwnedBucket(Owned<Bucket<K, V>>),
@@ -523,25 +2047,29 @@ impl<K, V> KeyOrOwnedBucket<K, V> {
         match self {
             Self::Key(k) => Owned::new(Bucket::new(k, value)),
             Self::OwnedBucket(mut b) => {
-                unsafe {
-                    mem::drop(
-                        mem::replace(&mut b.maybe_value, MaybeUninit::new(value)).assume_init(),
-                    )
-                };
+                unsafe { mem::drop(b.value.replace(value)) };
 
                 b
             }
         }
     }
+
+    /// Drops the value held by the bucket of an attempted modification, before
+    /// it is dropped while unwinding.
+    fn drop_value(&mut self) {
+        if let Self::OwnedBucket(b) = self {
+            unsafe { b.value.drop_value() };
+        }
+    }
 }
 
-pub(crate) enum InsertOrModifyState<K, V, F: FnOnce() -> V> {
+pub(crate) enum InsertOrModifyState<K, V, F: FnOnce(&K) -> V> {
     New(K, F),
     AttemptedInsertion(Owned<Bucket<K, V>>),
     AttemptedModification(Owned<Bucket<K, V>>, ValueOrFunction<V, F>),
 }
 
-impl<K, V, F: FnOnce() -> V> InsertOrModifyState<K, V, F> {
+impl<K, V, F: FnOnce(&K) -> V> InsertOrModifyState<K, V, F> {
     fn from_bucket_value(
         bucket: Owned<Bucket<K, V>>,
         value_or_function: Option<ValueOrFunction<V, F>>,
@@ -563,40 +2091,48 @@ impl<K, V, F: FnOnce() -> V> InsertOrModifyState<K, V, F> {
 
     fn into_insert_bucket(self) -> Owned<Bucket<K, V>> {
         match self {
-            InsertOrModifyState::New(k, f) => Owned::new(Bucket::new(k, f())),
+            InsertOrModifyState::New(k, f) => {
+                let value = f(&k);
+
+                Owned::new(Bucket::new(k, value))
+            }
             InsertOrModifyState::AttemptedInsertion(b) => b,
             InsertOrModifyState::AttemptedModification(mut b, v_or_f) => {
-                unsafe {
-                    mem::drop(
-                        mem::replace(&mut b.maybe_value, MaybeUninit::new(v_or_f.into_value()))
-                            .assume_init(),
-                    )
-                };
+                let value = on_unwind(
+                    &mut b,
+                    |b| v_or_f.into_value(&b.key),
+                    |b| unsafe { b.value.drop_value() },
+                );
+                unsafe { mem::drop(b.value.replace(value)) };
 
                 b
             }
         }
     }
 
-    fn into_modify_bucket(self, value: V) -> (Owned<Bucket<K, V>>, ValueOrFunction<V, F>) {
+    /// Drops the value held by the bucket of an attempted insertion or
+    /// modification, before the state is dropped while unwinding.
+    fn drop_value(&mut self) {
+        match self {
+            InsertOrModifyState::New(..) => (),
+            InsertOrModifyState::AttemptedInsertion(b)
+            | InsertOrModifyState::AttemptedModification(b, _) => unsafe { b.value.drop_value() },
+        }
+    }
+
+    fn into_modify_bucket(self, value: V) -> (OwnedBucket<K, V>, ValueOrFunction<V, F>) {
         match self {
             InsertOrModifyState::New(k, f) => (
                 Owned::new(Bucket::new(k, value)),
                 ValueOrFunction::Function(f),
             ),
             InsertOrModifyState::AttemptedInsertion(mut b) => {
-                let insert_value = unsafe {
-                    mem::replace(&mut b.maybe_value, MaybeUninit::new(value)).assume_init()
-                };
+                let insert_value = unsafe { b.value.replace(value) };
 
                 (b, ValueOrFunction::Value(insert_value))
             }
             InsertOrModifyState::AttemptedModification(mut b, v_or_f) => {
-                unsafe {
-                    mem::drop(
-                        mem::replace(&mut b.maybe_value, MaybeUninit::new(value)).assume_init(),
-                    )
-                };
+                unsafe { mem::drop(b.value.replace(value)) };
 
                 (b, v_or_f)
             }
@@ -604,27 +2140,151 @@ impl<K, V, F: FnOnce() -> V> InsertOrModifyState<K, V, F> {
     }
 }
 
-pub(crate) enum ValueOrFunction<V, F: FnOnce() -> V> {
+pub(crate) enum ValueOrFunction<V, F> {
     Value(V),
     Function(F),
 }
 
-impl<V, F: FnOnce() -> V> ValueOrFunction<V, F> {
-    fn into_value(self) -> V {
+impl<V, F> ValueOrFunction<V, F> {
+    fn into_value<K>(self, key: &K) -> V
+    where
+        F: FnOnce(&K) -> V,
+    {
         match self {
             ValueOrFunction::Value(v) => v,
-            ValueOrFunction::Function(f) => f(),
+            ValueOrFunction::Function(f) => f(key),
         }
     }
 }
 
-pub(crate) fn hash<K: ?Sized + Hash, H: BuildHasher>(build_hasher: &H, key: &K) -> u64 {
+/// Returns the result of invoking `f` with `state`, or invokes `cleanup` with
+/// `state` and resumes unwinding if `f` panics.
+///
+/// The buckets that are carried between the attempts of an operation do not
+/// drop their values by themselves, so the values are dropped by `cleanup`
+/// when a closure passed by the user panics, instead of being leaked.
+fn on_unwind<S, T, F: FnOnce(&S) -> T, G: FnOnce(&mut S)>(state: &mut S, f: F, cleanup: G) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(|| f(state))) {
+        Ok(t) => t,
+        Err(payload) => {
+            cleanup(state);
+            panic::resume_unwind(payload)
+        }
+    }
+}
+
+/// Returns at most `limit` of `buckets`, least recently accessed first.
+#[cfg(feature = "access-times")]
+pub(crate) fn stalest<K, V>(buckets: Vec<&Bucket<K, V>>, limit: usize) -> Vec<&Bucket<K, V>> {
+    // read each access time once, as they may be concurrently updated. ties
+    // are broken by position so that the order is deterministic if the
+    // buckets are
+    let mut buckets: Vec<_> = buckets
+        .into_iter()
+        .enumerate()
+        .map(|(i, b)| ((b.last_access(), i), b))
+        .collect();
+
+    if limit < buckets.len() {
+        buckets.select_nth_unstable_by_key(limit, |&(key, _)| key);
+        buckets.truncate(limit);
+    }
+
+    buckets.sort_unstable_by_key(|&(key, _)| key);
+
+    buckets.into_iter().map(|(_, b)| b).collect()
+}
+
+/// Hashes `key` with a hasher created by `build_hasher`, exactly like the hash
+/// maps in this crate do.
+///
+/// This is the hash returned by the `key_hash` methods of the maps. A
+/// `SegmentedHashMap` selects the segment of a key by the most significant
+/// bits of this hash, and both maps select the first slot to probe by its
+/// least significant bits. Other data structures, such as sketches, filters,
+/// or routing tables, can call this function to hash keys identically to a map
+/// that shares their hasher.
+///
+/// This function is part of the stable API: it will keep computing the same
+/// hashes, and the maps will keep using it, in all semver-compatible releases.
+///
+/// # Examples
+///
+/// ```rust
+/// use moka_cht::{map::DeterministicState, HashMap};
+///
+/// let map = HashMap::builder().deterministic().build();
+/// map.insert("foo", 1);
+///
+/// assert_eq!(moka_cht::hash(&DeterministicState, "foo"), map.key_hash("foo"));
+/// ```
+pub fn hash<K: ?Sized + Hash, H: BuildHasher>(build_hasher: &H, key: &K) -> u64 {
     let mut hasher = build_hasher.build_hasher();
     key.hash(&mut hasher);
 
     hasher.finish()
 }
 
+/// Panics unless `guard` is pinned to the default collector, which the maps
+/// retire their buckets and values through, so that references that are bound
+/// to `guard` are valid for as long as it is held.
+pub(crate) fn check_guard(guard: &Guard) {
+    assert!(
+        guard.collector() == Some(crossbeam_epoch::default_collector()),
+        "the guard must be pinned to the default collector"
+    );
+}
+
+/// Returns the position of a key with the hash `hash` in the order in which
+/// scans visit the keys, which is the hash with its bits reversed.
+///
+/// The low bits of a hash select the home slot of its key, so the keys are in
+/// the order of their home slots in a bucket array of any length, and the keys
+/// of a slot are the keys of the two slots it splits into when the array
+/// doubles in length.
+pub(crate) fn scan_position(hash: u64) -> u64 {
+    hash.reverse_bits()
+}
+
+/// Converts references to buckets into pointers, which do not borrow the
+/// guard that the buckets were loaded under, reusing the allocation of
+/// `buckets`.
+pub(crate) fn into_ptrs<K, V>(buckets: Vec<&Bucket<K, V>>) -> Vec<*const Bucket<K, V>> {
+    let mut buckets = mem::ManuallyDrop::new(buckets);
+
+    // a reference and a pointer to a sized type have the same layout
+    unsafe {
+        Vec::from_raw_parts(
+            buckets.as_mut_ptr() as *mut *const Bucket<K, V>,
+            buckets.len(),
+            buckets.capacity(),
+        )
+    }
+}
+
+/// Hints the processor to load the cache line at `ptr`, which is about to be
+/// read. This does nothing on architectures without a stable prefetch
+/// instruction.
+#[inline]
+pub(crate) fn prefetch<T>(ptr: *const T) {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+
+        _mm_prefetch::<_MM_HINT_T0>(ptr as *const i8);
+    }
+
+    #[cfg(target_arch = "x86")]
+    unsafe {
+        use std::arch::x86::{_mm_prefetch, _MM_HINT_T0};
+
+        _mm_prefetch::<_MM_HINT_T0>(ptr as *const i8);
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "x86")))]
+    let _ = ptr;
+}
+
 enum ProbeLoopAction<T> {
     Continue,
     Reload,
@@ -646,33 +2306,65 @@ impl<T> ProbeLoopResult<T> {
     }
 }
 
-pub(crate) unsafe fn defer_destroy_bucket<'g, K, V>(
-    guard: &'g Guard,
-    mut ptr: SharedBucket<'g, K, V>,
-) {
+pub(crate) unsafe fn defer_destroy_bucket<'g, K, V>(guard: &'g Guard, ptr: SharedBucket<'g, K, V>) {
     assert!(!ptr.is_null());
 
     guard.defer_unchecked(move || {
-        atomic::fence(Ordering::Acquire);
+        atomic::fence(ordering::ACQUIRE);
+
+        #[cfg(feature = "entry-pins")]
+        {
+            let flags = if ptr.tag() & TOMBSTONE_TAG == 0 {
+                PIN_RELEASED | PIN_VALUE_PENDING
+            } else {
+                PIN_RELEASED
+            };
 
-        if ptr.tag() & TOMBSTONE_TAG == 0 {
-            ptr::drop_in_place(ptr.deref_mut().maybe_value.as_mut_ptr());
+            release(ptr.as_raw() as *mut Bucket<K, V>, flags);
         }
 
-        mem::drop(ptr.into_owned());
+        #[cfg(not(feature = "entry-pins"))]
+        {
+            let mut bucket = ptr.into_owned();
+
+            if ptr.tag() & TOMBSTONE_TAG == 0 {
+                bucket.value.drop_value();
+            }
+        }
     });
 }
 
 pub(crate) unsafe fn defer_destroy_tombstone<'g, K, V>(
     guard: &'g Guard,
-    mut ptr: SharedBucket<'g, K, V>,
+    ptr: SharedBucket<'g, K, V>,
 ) {
     assert!(!ptr.is_null());
     assert_ne!(ptr.tag() & TOMBSTONE_TAG, 0);
 
-    atomic::fence(Ordering::Acquire);
+    // a pin that was added before the removal keeps the value, and the last
+    // one drops it
+    #[cfg(feature = "entry-pins")]
+    {
+        let pins = ptr
+            .deref()
+            .pins
+            .fetch_update(ordering::ACQ_REL, ordering::ACQUIRE, |pins| {
+                if pins / PIN_UNIT > 1 {
+                    Some(pins | PIN_REMOVED | PIN_VALUE_PENDING)
+                } else {
+                    Some(pins | PIN_REMOVED)
+                }
+            })
+            .unwrap();
+
+        if pins / PIN_UNIT > 1 {
+            return;
+        }
+    }
+
+    atomic::fence(ordering::ACQUIRE);
     // read the value now, but defer its destruction for later
-    let value = ptr::read(ptr.deref_mut().maybe_value.as_ptr());
+    let value = ptr.deref().value.take();
 
     // to be entirely honest, i don't know what order deferred functions are
     // called in crossbeam-epoch. in the case that the deferred functions are
@@ -684,14 +2376,116 @@ pub(crate) unsafe fn defer_acquire_destroy<'g, T>(guard: &'g Guard, ptr: Shared<
     assert!(!ptr.is_null());
 
     guard.defer_unchecked(move || {
-        atomic::fence(Ordering::Acquire);
+        atomic::fence(ordering::ACQUIRE);
         mem::drop(ptr.into_owned());
     });
 }
 
+/// Destroys the bucket pointer arrays from `current_ptr` on and the buckets
+/// they hold, invoking `on_live` with each bucket that holds a value before it
+/// is destroyed.
+///
+/// A rehash that was interrupted, for example by a panicking `Hash`
+/// implementation, leaves the arrays holding different parts of the entries,
+/// and possibly the same bucket in two of them. The slot of a bucket in the
+/// older array is only replaced by a sentinel once the bucket has been moved,
+/// so the older array is authoritative and each bucket is destroyed once, as
+/// it is held there.
+///
+/// # Safety
+///
+/// No other thread may access the arrays or the buckets, and `guard` must not
+/// defer the destruction, i.e. it must be `crossbeam_epoch::unprotected()`.
+pub(crate) unsafe fn destroy_bucket_arrays<'g, K, V>(
+    guard: &'g Guard,
+    mut current_ptr: Shared<'g, BucketArray<K, V>>,
+    mut on_live: impl FnMut(&Bucket<K, V>),
+) {
+    // the buckets of the arrays that were being rehashed, which may also be
+    // held by the next array
+    let mut destroyed = HashSet::new();
+
+    while let Some(current_ref) = current_ptr.as_ref() {
+        let next_ptr = current_ref.next.load(ordering::RELAXED, guard);
+
+        for this_bucket_ptr in current_ref
+            .buckets
+            .iter()
+            .map(|b| b.load(ordering::RELAXED, guard))
+            .filter(|p| !p.is_null())
+        {
+            if destroyed.contains(&this_bucket_ptr.as_raw()) {
+                continue;
+            }
+
+            if !next_ptr.is_null() {
+                destroyed.insert(this_bucket_ptr.as_raw());
+            }
+
+            if this_bucket_ptr.tag() & TOMBSTONE_TAG == 0 {
+                on_live(this_bucket_ptr.deref());
+            }
+
+            defer_destroy_bucket(guard, this_bucket_ptr);
+        }
+
+        defer_acquire_destroy(guard, current_ptr);
+
+        current_ptr = next_ptr;
+    }
+}
+
 pub(crate) const SENTINEL_TAG: usize = 0b001; // set on old table buckets when copied into a new table
 pub(crate) const TOMBSTONE_TAG: usize = 0b010; // set when the value has been destroyed
 pub(crate) const BORROWED_TAG: usize = 0b100; // set on new table buckets when copied from an old table
+#[cfg(feature = "entry-flags")]
+pub(crate) const FLAGS_SHIFT: usize = 3;
+#[cfg(feature = "entry-flags")]
+pub(crate) const FLAGS_MASK: usize = 0b11000; // the entry flags, kept when a live bucket is replaced
+#[cfg(not(feature = "entry-flags"))]
+pub(crate) const FLAGS_MASK: usize = 0; // no room for entry flags without the extra alignment
+
+#[cfg(feature = "entry-pins")]
+const PIN_REMOVED: usize = 0b001; // set when the entry is removed, after which no pins are added
+#[cfg(feature = "entry-pins")]
+const PIN_VALUE_PENDING: usize = 0b010; // set when the value is to be dropped by the last reference
+#[cfg(feature = "entry-pins")]
+const PIN_RELEASED: usize = 0b100; // set when the map releases its reference
+#[cfg(feature = "entry-pins")]
+const PIN_UNIT: usize = 0b1000;
+
+/// Merges the entries with equal keys, keeping the first key and the last value.
+///
+/// The entries must be sorted by hash, so that the entries with equal keys are
+/// in the same run of equal hashes.
+fn merge_equal_keys<K: Eq, V>(entries: Vec<(u64, K, V)>) -> Vec<(u64, K, V)> {
+    let mut merged: Vec<(u64, K, V)> = Vec::with_capacity(entries.len());
+    let mut run_start = 0;
+
+    for (hash, key, value) in entries {
+        match merged.last() {
+            Some(&(last_hash, _, _)) if last_hash == hash => (),
+            _ => run_start = merged.len(),
+        }
+
+        match merged[run_start..].iter_mut().find(|entry| entry.1 == key) {
+            Some(entry) => entry.2 = value,
+            None => merged.push((hash, key, value)),
+        }
+    }
+
+    merged
+}
+
+/// Returns the entry flags of the bucket that `ptr` points to, or 0 if it is
+/// a tombstone, to be carried over to the bucket that replaces it.
+fn live_flags<K, V>(ptr: SharedBucket<'_, K, V>) -> usize {
+    if ptr.tag() & TOMBSTONE_TAG == 0 {
+        ptr.tag() & FLAGS_MASK
+    } else {
+        0
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -699,6 +2493,41 @@ mod tests {
 
     use std::collections::hash_map::RandomState;
 
+    #[test]
+    fn entry_flags_fit_in_tag() {
+        assert!(mem::align_of::<Bucket<u8, u8>>() > FLAGS_MASK);
+        assert_eq!(
+            FLAGS_MASK & (SENTINEL_TAG | TOMBSTONE_TAG | BORROWED_TAG),
+            0
+        );
+    }
+
+    #[test]
+    fn bucket_size() {
+        // the optional metadata and the pin count take no space unless their
+        // features are enabled, and buckets are only padded to make room for
+        // the entry flags with the entry-flags feature
+        let optional = [
+            cfg!(feature = "generations"),
+            cfg!(feature = "access-times"),
+            cfg!(feature = "sequence-numbers"),
+            cfg!(feature = "entry-pins"),
+        ]
+        .iter()
+        .filter(|&&enabled| enabled)
+        .count();
+        let unpadded = 16 + 8 * optional;
+        let expected = if !cfg!(feature = "entry-flags") {
+            unpadded
+        } else if unpadded <= 32 {
+            32
+        } else {
+            64
+        };
+
+        assert_eq!(mem::size_of::<Bucket<u64, u64>>(), expected);
+    }
+
     #[test]
     fn get_insert_remove() {
         let build_hasher = RandomState::new();
@@ -722,44 +2551,65 @@ mod tests {
         assert_eq!(buckets.get(guard, h3, k3), Ok(Shared::null()));
 
         let b1 = Owned::new(Bucket::new(k1, v1)).into_shared(guard);
-        assert!(is_ok_null(
-            buckets.insert(guard, h1, unsafe { b1.into_owned() })
-        ));
+        assert!(is_ok_null(buckets.insert(
+            guard,
+            h1,
+            unsafe { b1.into_owned() },
+            Stamp::default()
+        )));
 
         assert_eq!(buckets.get(guard, h1, k1), Ok(b1));
         assert_eq!(buckets.get(guard, h2, k2), Ok(Shared::null()));
         assert_eq!(buckets.get(guard, h3, k3), Ok(Shared::null()));
 
         let b2 = Owned::new(Bucket::new(k2, v2)).into_shared(guard);
-        assert!(is_ok_null(
-            buckets.insert(guard, h2, unsafe { b2.into_owned() })
-        ));
+        assert!(is_ok_null(buckets.insert(
+            guard,
+            h2,
+            unsafe { b2.into_owned() },
+            Stamp::default()
+        )));
 
         assert_eq!(buckets.get(guard, h1, k1), Ok(b1));
         assert_eq!(buckets.get(guard, h2, k2), Ok(b2));
         assert_eq!(buckets.get(guard, h3, k3), Ok(Shared::null()));
 
         let b3 = Owned::new(Bucket::new(k3, v3)).into_shared(guard);
-        assert!(is_ok_null(
-            buckets.insert(guard, h3, unsafe { b3.into_owned() })
-        ));
+        assert!(is_ok_null(buckets.insert(
+            guard,
+            h3,
+            unsafe { b3.into_owned() },
+            Stamp::default()
+        )));
 
         assert_eq!(buckets.get(guard, h1, k1), Ok(b1));
         assert_eq!(buckets.get(guard, h2, k2), Ok(b2));
         assert_eq!(buckets.get(guard, h3, k3), Ok(b3));
 
         assert_eq!(
-            buckets.remove_if(guard, h1, k1, |_, _| true).ok().unwrap(),
+            buckets
+                .remove_if(guard, h1, k1, Stamp::default(), |_, _| true)
+                .ok()
+                .unwrap()
+                .0,
             b1.with_tag(TOMBSTONE_TAG)
         );
         unsafe { defer_destroy_tombstone(guard, b1.with_tag(TOMBSTONE_TAG)) };
         assert_eq!(
-            buckets.remove_if(guard, h2, k2, |_, _| true).ok().unwrap(),
+            buckets
+                .remove_if(guard, h2, k2, Stamp::default(), |_, _| true)
+                .ok()
+                .unwrap()
+                .0,
             b2.with_tag(TOMBSTONE_TAG)
         );
         unsafe { defer_destroy_tombstone(guard, b2.with_tag(TOMBSTONE_TAG)) };
         assert_eq!(
-            buckets.remove_if(guard, h3, k3, |_, _| true).ok().unwrap(),
+            buckets
+                .remove_if(guard, h3, k3, Stamp::default(), |_, _| true)
+                .ok()
+                .unwrap()
+                .0,
             b3.with_tag(TOMBSTONE_TAG)
         );
         unsafe { defer_destroy_tombstone(guard, b3.with_tag(TOMBSTONE_TAG)) };
@@ -769,7 +2619,7 @@ mod tests {
         assert_eq!(buckets.get(guard, h3, k3), Ok(Shared::null()));
 
         for this_bucket in buckets.buckets.iter() {
-            let this_bucket_ptr = this_bucket.swap(Shared::null(), Ordering::Relaxed, guard);
+            let this_bucket_ptr = this_bucket.swap(Shared::null(), ordering::RELAXED, guard);
 
             if this_bucket_ptr.is_null() {
                 continue;
@@ -781,6 +2631,348 @@ mod tests {
         }
     }
 
+    #[test]
+    fn from_hash_sorted_entries_wraps_around() {
+        let guard = unsafe { &crossbeam_epoch::unprotected() };
+
+        // sorted by hash, and all but the last at the end of a 16-slot array,
+        // so that the probe sequences run past its end and into the entry at
+        // its start
+        let hashes = [
+            15,
+            14,
+            (1 << 32) | 15,
+            (2 << 32) | 15,
+            (3 << 32) | 15,
+            4 << 32,
+        ];
+        let mut entries: Vec<_> = hashes
+            .iter()
+            .enumerate()
+            .map(|(i, &h)| (h, i, i * 10))
+            .collect();
+        entries.insert(3, ((1 << 32) | 15, 2, 200));
+
+        let (buckets, len) = BucketArray::from_hash_sorted_entries(0, entries).unwrap();
+        assert_eq!(buckets.buckets.len(), 16);
+        assert_eq!(len, hashes.len());
+
+        for (i, &h) in hashes.iter().enumerate() {
+            let bucket_ptr = buckets.get(guard, h, &i).unwrap();
+            let value = unsafe { *bucket_ptr.deref().value.get() };
+            assert_eq!(value, if i == 2 { 200 } else { i * 10 });
+        }
+
+        for this_bucket in buckets.buckets.iter() {
+            let this_bucket_ptr = this_bucket.swap(Shared::null(), ordering::RELAXED, guard);
+
+            if !this_bucket_ptr.is_null() {
+                unsafe { defer_destroy_bucket(guard, this_bucket_ptr) };
+            }
+        }
+    }
+
+    #[test]
+    fn scan_hashes_each_bucket_once() {
+        use std::{
+            hash::{BuildHasherDefault, Hasher},
+            sync::atomic::AtomicUsize,
+        };
+
+        static NUM_HASHES: AtomicUsize = AtomicUsize::new(0);
+
+        // puts every key in the run that starts at slot 0
+        #[derive(Default)]
+        struct ZeroHasher;
+
+        impl Hasher for ZeroHasher {
+            fn finish(&self) -> u64 {
+                NUM_HASHES.fetch_add(1, ordering::RELAXED);
+
+                0
+            }
+
+            fn write(&mut self, _: &[u8]) {}
+        }
+
+        const NUM_ENTRIES: usize = 32;
+
+        let guard = unsafe { &crossbeam_epoch::unprotected() };
+        let entries = (0..NUM_ENTRIES).map(|i| (0, i, i)).collect();
+        let (buckets, _) = BucketArray::from_hash_sorted_entries(0, entries).unwrap();
+
+        let build_hasher = BuildHasherDefault::<ZeroHasher>::default();
+        let (scanned, next) = buckets.scan(guard, &build_hasher, 0, usize::MAX).unwrap();
+        assert_eq!(scanned.len(), NUM_ENTRIES);
+        assert_eq!(next, None);
+        assert_eq!(NUM_HASHES.load(ordering::RELAXED), NUM_ENTRIES);
+
+        for this_bucket in buckets.buckets.iter() {
+            let this_bucket_ptr = this_bucket.swap(Shared::null(), ordering::RELAXED, guard);
+
+            if !this_bucket_ptr.is_null() {
+                unsafe { defer_destroy_bucket(guard, this_bucket_ptr) };
+            }
+        }
+    }
+
+    #[test]
+    fn into_ptrs_reuses_allocation() {
+        let buckets = [Bucket::new(0, 0), Bucket::new(1, 10)];
+        let refs: Vec<_> = buckets.iter().collect();
+        let allocation = refs.as_ptr() as usize;
+
+        let ptrs = into_ptrs(refs);
+        assert_eq!(ptrs.as_ptr() as usize, allocation);
+        assert_eq!(ptrs, [&buckets[0] as *const _, &buckets[1] as *const _]);
+    }
+
+    #[test]
+    fn panicking_modifier_drops_attempted_values() {
+        use crate::test_util::{DropNotifier, NoisyDropper};
+        use std::sync::Arc;
+
+        let build_hasher = RandomState::new();
+        let buckets = BucketArray::with_length(0, 16);
+        let guard = unsafe { &crossbeam_epoch::unprotected() };
+
+        let k = "foo";
+        let h = hash(&build_hasher, k);
+        let parents: Vec<_> = std::iter::repeat_with(|| Arc::new(DropNotifier::new()))
+            .take(3)
+            .collect();
+        let value = |i: usize| NoisyDropper::new(Arc::clone(&parents[i]), i);
+
+        let b = Owned::new(Bucket::new(k, value(0))).into_shared(guard);
+        assert!(is_ok_null(buckets.insert(
+            guard,
+            h,
+            unsafe { b.into_owned() },
+            Stamp::default()
+        )));
+
+        // buckets carried over from a failed compare-exchange hold a value
+        let state = InsertOrModifyState::<_, _, fn(&_) -> _>::AttemptedInsertion(Owned::new(
+            Bucket::new(k, value(1)),
+        ));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            buckets.insert_or_modify(guard, h, state, Stamp::default(), |_, _| panic!())
+        }));
+        assert!(result.is_err());
+        assert!(parents[1].was_dropped());
+
+        let key_or_owned_bucket =
+            KeyOrOwnedBucket::OwnedBucket(Owned::new(Bucket::new(k, value(2))));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            buckets.modify(
+                guard,
+                h,
+                key_or_owned_bucket,
+                Stamp::default(),
+                |_, _| panic!(),
+            )
+        }));
+        assert!(result.is_err());
+        assert!(parents[2].was_dropped());
+
+        assert_eq!(buckets.get(guard, h, k), Ok(b));
+        assert!(!parents[0].was_dropped());
+
+        unsafe { defer_destroy_bucket(guard, b) };
+        assert!(parents[0].was_dropped());
+    }
+
+    #[test]
+    fn budgeted_get_and_rehash() {
+        const NUM_KEYS: u32 = 8;
+
+        let build_hasher = RandomState::new();
+        let buckets = BucketArray::with_length(0, 16);
+        let guard = unsafe { &crossbeam_epoch::unprotected() };
+        let h = |k: u32| hash(&build_hasher, &k);
+
+        for k in 0..NUM_KEYS {
+            let b = Owned::new(Bucket::new(k, k));
+            assert!(is_ok_null(buckets.insert(guard, h(k), b, Stamp::default())));
+        }
+
+        for k in 0..NUM_KEYS {
+            let b = buckets.get_within(guard, h(k), &k, &mut ReadBudget::unlimited());
+            assert_eq!(unsafe { b.unwrap().unwrap().deref() }.key, k);
+        }
+
+        assert_eq!(
+            buckets.get_within(guard, h(0), &0, &mut ReadBudget::new(0, 0)),
+            Err(Error::Busy)
+        );
+
+        let mut budget = ReadBudget::new(usize::MAX, 4);
+        assert_eq!(
+            buckets
+                .rehash_within(guard, &build_hasher, h(0), &mut budget)
+                .err(),
+            Some(Error::Busy)
+        );
+        assert_eq!(budget, ReadBudget::new(usize::MAX, 0));
+
+        // the slot of the key was migrated first
+        assert_eq!(
+            buckets.get_within(guard, h(0), &0, &mut ReadBudget::unlimited()),
+            Ok(Err(RelocatedError))
+        );
+
+        let next_array = buckets
+            .rehash_within(guard, &build_hasher, h(0), &mut ReadBudget::unlimited())
+            .unwrap();
+
+        for k in 0..NUM_KEYS {
+            let b = next_array.get(guard, h(k), &k).unwrap();
+            assert_eq!(unsafe { b.deref() }.key, k);
+            unsafe { defer_acquire_destroy(guard, b) };
+        }
+
+        unsafe { defer_acquire_destroy(guard, buckets.next.load(ordering::RELAXED, guard)) };
+    }
+
+    #[test]
+    fn shrink_into_full_array() {
+        const NUM_KEYS: u32 = 4;
+        const NUM_INSERTED: u32 = 8;
+
+        let build_hasher = RandomState::new();
+        let buckets = BucketArray::with_length(0, 16);
+        let guard = unsafe { &crossbeam_epoch::unprotected() };
+        let h = |k: u32| hash(&build_hasher, &k);
+
+        for k in 0..NUM_KEYS {
+            let b = Owned::new(Bucket::new(k, k));
+            assert!(is_ok_null(buckets.insert(guard, h(k), b, Stamp::default())));
+        }
+
+        // concurrent insertions fill the shrunk array before any bucket is
+        // migrated to it
+        let shrunk_array = buckets.next_array(guard, RehashOp::Shrink);
+        assert_eq!(shrunk_array.buckets.len(), 8);
+
+        for k in (0..NUM_INSERTED).map(|k| k + NUM_KEYS) {
+            let b = Owned::new(Bucket::new(k, k));
+            assert!(is_ok_null(shrunk_array.insert(
+                guard,
+                h(k),
+                b,
+                Stamp::default()
+            )));
+        }
+
+        assert_eq!(
+            buckets.rehash_with(guard, &build_hasher, RehashOp::Shrink) as *const _,
+            shrunk_array as *const _
+        );
+
+        // the buckets that did not fit went to an expanded array
+        let next_array = unsafe {
+            shrunk_array
+                .next
+                .load(ordering::RELAXED, guard)
+                .as_ref()
+                .unwrap()
+        };
+        assert_eq!(next_array.buckets.len(), 16);
+
+        for k in 0..NUM_KEYS + NUM_INSERTED {
+            assert_eq!(shrunk_array.get(guard, h(k), &k), Err(RelocatedError));
+
+            let b = next_array.get(guard, h(k), &k).unwrap();
+            assert_eq!(unsafe { b.deref() }.key, k);
+            unsafe { defer_acquire_destroy(guard, b) };
+        }
+
+        unsafe {
+            defer_acquire_destroy(guard, shrunk_array.next.load(ordering::RELAXED, guard));
+            defer_acquire_destroy(guard, buckets.next.load(ordering::RELAXED, guard));
+        }
+    }
+
+    #[test]
+    fn exclusive_insert_remove() {
+        const NUM_KEYS: u32 = 32;
+
+        let build_hasher = RandomState::new();
+        let mut buckets = BucketArray::with_length(0, 64);
+        let guard = unsafe { &crossbeam_epoch::unprotected() };
+        let h = |k: u32| hash(&build_hasher, &k);
+
+        for k in 0..NUM_KEYS {
+            assert_eq!(
+                buckets.insert_exclusive(h(k), k, k, Stamp::default()),
+                Ok(None)
+            );
+        }
+
+        assert_eq!(
+            buckets.insert_exclusive(h(0), 0, 1, Stamp::default()),
+            Ok(Some(0))
+        );
+
+        // a tombstone is replaced by a new bucket
+        let (tombstone_ptr, _) = buckets
+            .remove_if(guard, h(1), &1, Stamp::default(), |_, _| true)
+            .ok()
+            .unwrap();
+        unsafe { defer_destroy_tombstone(guard, tombstone_ptr) };
+        assert_eq!(
+            buckets.insert_exclusive(h(1), 1, 1, Stamp::default()),
+            Ok(None)
+        );
+
+        let num_occupied = |buckets: &BucketArray<u32, u32>| {
+            buckets
+                .buckets
+                .iter()
+                .filter(|b| !b.load(ordering::RELAXED, guard).is_null())
+                .count()
+        };
+
+        // remove the keys in an order unrelated to their slots, checking that
+        // the remaining ones can still be found and that no tombstone is left
+        let mut remaining: Vec<u32> = (0..NUM_KEYS).collect();
+
+        for k in (0..NUM_KEYS).map(|i| (i * 7) % NUM_KEYS) {
+            let expected = if k == 0 { (0, 1) } else { (k, k) };
+            assert_eq!(
+                buckets.remove_exclusive(&build_hasher, h(k), &k),
+                Some(expected)
+            );
+            assert_eq!(buckets.remove_exclusive(&build_hasher, h(k), &k), None);
+            remaining.retain(|&j| j != k);
+
+            for &j in remaining.iter() {
+                let b = buckets.get(guard, h(j), &j).unwrap();
+                assert_eq!(unsafe { b.deref() }.key, j);
+            }
+
+            assert_eq!(num_occupied(&buckets), remaining.len());
+        }
+
+        for k in 0..NUM_KEYS {
+            assert_eq!(
+                buckets.insert_exclusive(h(k), k, k, Stamp::default()),
+                Ok(None)
+            );
+        }
+
+        let mut next_array = buckets.grow_exclusive(&build_hasher);
+        assert_eq!(next_array.buckets.len(), 128);
+        assert_eq!(num_occupied(&buckets), 0);
+
+        for k in 0..NUM_KEYS {
+            assert_eq!(
+                next_array.remove_exclusive(&build_hasher, h(k), &k),
+                Some((k, k))
+            );
+        }
+    }
+
     fn is_ok_null<'g, K, V, E>(maybe_bucket_ptr: Result<SharedBucket<'g, K, V>, E>) -> bool {
         if let Ok(bucket_ptr) = maybe_bucket_ptr {
             bucket_ptr.is_null()
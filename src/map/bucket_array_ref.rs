@@ -1,9 +1,24 @@
-use super::bucket::{self, Bucket, BucketArray, InsertOrModifyState, KeyOrOwnedBucket};
+use super::{
+    bucket::{
+        self, Bucket, BucketArray, Decided, InsertOrModifyState, KeyOrOwnedBucket, RehashOp,
+        SlotHint, Stamp,
+    },
+    memory_budget::MemoryCharge,
+    Decision, PanicPolicy, ReadBudget, TombstonePolicy,
+};
+#[cfg(feature = "instrument")]
+use crate::metrics::Latencies;
+use crate::{
+    metrics::{self, Operation, OperationSample, ProbeStats, SampledOperation, Sampler, Timer},
+    ordering, Error,
+};
 
 use std::{
+    any::Any,
     borrow::Borrow,
     hash::{BuildHasher, Hash},
-    sync::atomic::{AtomicUsize, Ordering},
+    ptr,
+    sync::atomic::{AtomicU64, AtomicUsize},
 };
 
 use crossbeam_epoch::{Atomic, CompareExchangeError, Guard, Owned, Shared};
@@ -12,6 +27,26 @@ pub(crate) struct BucketArrayRef<'a, K, V, S> {
     pub(crate) bucket_array: &'a Atomic<BucketArray<K, V>>,
     pub(crate) build_hasher: &'a S,
     pub(crate) len: &'a AtomicUsize,
+    pub(crate) generation: &'a AtomicU64,
+    pub(crate) access_clock: &'a AtomicU64,
+    // whether live buckets are returned in the order of their hashes
+    pub(crate) deterministic: bool,
+    pub(crate) panic_policy: PanicPolicy,
+    pub(crate) tombstone_policy: TombstonePolicy,
+    pub(crate) memory: Option<&'a MemoryCharge<K, V>>,
+    // the bits of the hashes inserted into a segment, see `fingerprint_bit`
+    pub(crate) fingerprint: Option<&'a AtomicU64>,
+    // the slots held by reservation tokens, which other insertions leave free
+    pub(crate) reserved_slots: &'a AtomicUsize,
+    // the counter of the sequence numbers assigned to mutations, if enabled
+    pub(crate) sequence: Option<&'a AtomicU64>,
+    pub(crate) sampler: Option<&'a Sampler>,
+    // the index of the segment, or 0 for a `HashMap`
+    pub(crate) segment: usize,
+    // the context passed to the hooks, if the operation came through a view
+    pub(crate) context: Option<&'a dyn Any>,
+    #[cfg(feature = "instrument")]
+    pub(crate) latencies: &'a Latencies,
 }
 
 impl<'a, K: Hash + Eq, V, S: BuildHasher> BucketArrayRef<'a, K, V, S> {
@@ -24,7 +59,127 @@ impl<'a, K: Hash + Eq, V, S: BuildHasher> BucketArrayRef<'a, K, V, S> {
     where
         K: Borrow<Q>,
     {
+        let _panic_guard = self.panic_policy.enter();
+        let guard = &crossbeam_epoch::pin();
+
+        self.get_bucket(guard, key, hash)
+            .map(|b| with_entry(&b.key, unsafe { b.value() }))
+    }
+
+    /// Returns the sequence number of the last mutation of the entry of
+    /// `key`, which is 0 if the map does not assign them.
+    #[cfg(feature = "sequence-numbers")]
+    pub(crate) fn get_sequence<Q: Hash + Eq + ?Sized>(&self, key: &Q, hash: u64) -> Option<u64>
+    where
+        K: Borrow<Q>,
+    {
+        let _panic_guard = self.panic_policy.enter();
+        let guard = &crossbeam_epoch::pin();
+
+        self.get_bucket(guard, key, hash).map(|b| b.sequence.get())
+    }
+
+    /// Prefetches the cache line where a lookup of `hash` starts probing,
+    /// without waiting for it. Does nothing if the bucket pointer array is not
+    /// allocated, or if no key with the hash can be in the map.
+    pub(crate) fn prefetch(&self, hash: u64) {
+        if !self.may_contain(hash) {
+            return;
+        }
+
+        let guard = &crossbeam_epoch::pin();
+
+        if let Some(bucket_array_ref) =
+            unsafe { ordering::load_consume(self.bucket_array, guard).as_ref() }
+        {
+            bucket_array_ref.prefetch_home_slot(hash);
+        }
+    }
+
+    pub(crate) fn get_key_value_and_within<Q: Hash + Eq + ?Sized, F: FnOnce(&K, &V) -> T, T>(
+        &self,
+        key: &Q,
+        hash: u64,
+        budget: &mut ReadBudget,
+        with_entry: F,
+    ) -> Result<Option<T>, Error>
+    where
+        K: Borrow<Q>,
+    {
+        let _panic_guard = self.panic_policy.enter();
         let guard = &crossbeam_epoch::pin();
+
+        Ok(self
+            .get_bucket_within(guard, key, hash, budget)?
+            .map(|b| with_entry(&b.key, unsafe { b.value() })))
+    }
+
+    /// Like [`get_bucket`], but returns [`Error::Busy`] if `budget` runs out.
+    ///
+    /// [`get_bucket`]: #method.get_bucket
+    /// [`Error::Busy`]: ../../enum.Error.html#variant.Busy
+    pub(crate) fn get_bucket_within<'g, Q: Hash + Eq + ?Sized>(
+        &self,
+        guard: &'g Guard,
+        key: &Q,
+        hash: u64,
+        budget: &mut ReadBudget,
+    ) -> Result<Option<&'g Bucket<K, V>>, Error>
+    where
+        K: Borrow<Q>,
+    {
+        let _timer = self.start_timer(Operation::Get);
+
+        if !self.may_contain(hash) {
+            metrics::count_lookup(false);
+
+            return Ok(None);
+        }
+
+        let current_ref = self.get(guard);
+        let mut bucket_array_ref = current_ref;
+
+        let result = loop {
+            match bucket_array_ref.get_within(guard, hash, key, budget) {
+                Ok(Ok(p)) => break Ok(unsafe { p.as_ref() }),
+                Ok(Err(_)) => match self.rehash_within(guard, bucket_array_ref, hash, budget) {
+                    Ok(next_array_ref) => bucket_array_ref = next_array_ref,
+                    Err(e) => break Err(e),
+                },
+                Err(e) => break Err(e),
+            }
+        };
+
+        self.swing(guard, current_ref, bucket_array_ref);
+
+        if let Ok(result) = result {
+            metrics::count_lookup(result.is_some());
+
+            if let Some(b) = result {
+                b.touch(self.access_clock.load(ordering::RELAXED));
+            }
+        }
+
+        result
+    }
+
+    pub(crate) fn get_bucket<'g, Q: Hash + Eq + ?Sized>(
+        &self,
+        guard: &'g Guard,
+        key: &Q,
+        hash: u64,
+    ) -> Option<&'g Bucket<K, V>>
+    where
+        K: Borrow<Q>,
+    {
+        let _timer = self.start_timer(Operation::Get);
+
+        if !self.may_contain(hash) {
+            metrics::count_lookup(false);
+
+            return None;
+        }
+
         let current_ref = self.get(guard);
         let mut bucket_array_ref = current_ref;
 
@@ -35,21 +190,114 @@ impl<'a, K: Hash + Eq, V, S: BuildHasher> BucketArrayRef<'a, K, V, S> {
                 .get(guard, hash, key)
                 .map(|p| unsafe { p.as_ref() })
             {
-                Ok(Some(Bucket {
-                    key,
-                    maybe_value: value,
-                })) => {
-                    result = Some(with_entry(key, unsafe { &*value.as_ptr() }));
+                Ok(r) => {
+                    result = r;
 
                     break;
                 }
-                Ok(None) => {
-                    result = None;
+                Err(_) => {
+                    bucket_array_ref = self.rehash(guard, bucket_array_ref);
+                }
+            }
+        }
+
+        self.swing(guard, current_ref, bucket_array_ref);
+        metrics::count_lookup(result.is_some());
+        self.sample(guard, SampledOperation::Get, bucket_array_ref, hash, key);
+
+        if let Some(b) = result {
+            b.touch(self.access_clock.load(ordering::RELAXED));
+        }
+
+        result
+    }
+
+    /// Looks up a key through the slot described by `hint` if it is still
+    /// valid, or by probing otherwise, returning the bucket and a hint of the
+    /// slot that holds it.
+    pub(crate) fn get_bucket_with_hint<'g, Q: Hash + Eq + ?Sized>(
+        &self,
+        guard: &'g Guard,
+        key: &Q,
+        hash: u64,
+        hint: Option<SlotHint>,
+    ) -> Option<(&'g Bucket<K, V>, SlotHint)>
+    where
+        K: Borrow<Q>,
+    {
+        let _timer = self.start_timer(Operation::Get);
+
+        if !self.may_contain(hash) {
+            metrics::count_lookup(false);
+
+            return None;
+        }
+
+        let current_ref = self.get(guard);
+        let mut bucket_array_ref = current_ref;
+
+        let mut result = match hint {
+            Some(h) if h.hash == hash && h.epoch == bucket_array_ref.epoch => bucket_array_ref
+                .get_at(guard, h.index, key)
+                .map(|p| (unsafe { p.as_ref() }, h.index)),
+            _ => None,
+        };
+
+        while result.is_none() {
+            match bucket_array_ref.get_with_index(guard, hash, key) {
+                Ok((p, index)) => result = Some((unsafe { p.as_ref() }, index)),
+                Err(_) => bucket_array_ref = self.rehash(guard, bucket_array_ref),
+            }
+        }
+
+        self.swing(guard, current_ref, bucket_array_ref);
+
+        let (bucket, index) = result.unwrap();
+        metrics::count_lookup(bucket.is_some());
+
+        bucket.map(|b| {
+            b.touch(self.access_clock.load(ordering::RELAXED));
+
+            let hint = SlotHint {
+                hash,
+                epoch: bucket_array_ref.epoch,
+                index,
+            };
+
+            (b, hint)
+        })
+    }
+
+    #[cfg(feature = "entry-flags")]
+    pub(crate) fn update_flags<Q: Hash + Eq + ?Sized, F: FnMut(usize) -> usize>(
+        &self,
+        key: &Q,
+        hash: u64,
+        mut f: F,
+    ) -> Option<usize>
+    where
+        K: Borrow<Q>,
+    {
+        if !self.may_contain(hash) {
+            return None;
+        }
+
+        let guard = &crossbeam_epoch::pin();
+        let current_ref = self.get(guard);
+        let mut bucket_array_ref = current_ref;
+
+        let result;
+
+        loop {
+            match bucket_array_ref.update_flags(guard, hash, key, f) {
+                Ok(r) => {
+                    result = r;
 
                     break;
                 }
-                Err(_) => {
-                    bucket_array_ref = bucket_array_ref.rehash(guard, self.build_hasher);
+                Err(g) => {
+                    f = g;
+                    bucket_array_ref = self.rehash(guard, bucket_array_ref);
                 }
             }
         }
@@ -66,50 +314,166 @@ impl<'a, K: Hash + Eq, V, S: BuildHasher> BucketArrayRef<'a, K, V, S> {
         value: V,
         with_previous_entry: F,
     ) -> Option<T> {
+        self.insert_entry_with_hint_and(key, hash, value, None, with_previous_entry)
+    }
+
+    pub(crate) fn insert_entry_with_hint_and<F: FnOnce(&K, &V) -> T, T>(
+        &self,
+        key: K,
+        hash: u64,
+        value: V,
+        hint: Option<SlotHint>,
+        with_previous_entry: F,
+    ) -> Option<T> {
+        if let Some(memory) = self.memory {
+            memory.charge(&key, &value, self.context);
+        }
+
+        self.insert_charged_entry_and(key, hash, value, hint, false, with_previous_entry)
+            .0
+    }
+
+    /// Like [`insert_entry_and`], but for an insertion that holds one of the
+    /// reserved slots, which never grows the bucket array because of the load.
+    ///
+    /// [`insert_entry_and`]: #method.insert_entry_and
+    pub(crate) fn insert_reserved_entry_and<F: FnOnce(&K, &V) -> T, T>(
+        &self,
+        key: K,
+        hash: u64,
+        value: V,
+        with_previous_entry: F,
+    ) -> Option<T> {
+        if let Some(memory) = self.memory {
+            memory.charge(&key, &value, self.context);
+        }
+
+        self.insert_charged_entry_and(key, hash, value, None, true, with_previous_entry)
+            .0
+    }
+
+    /// Like [`insert_entry_and`], but returns
+    /// [`Error::MemoryBudgetExceeded`] instead of inserting an entry that does
+    /// not fit in the memory budget of the map.
+    ///
+    /// [`insert_entry_and`]: #method.insert_entry_and
+    /// [`Error::MemoryBudgetExceeded`]: ../../enum.Error.html#variant.MemoryBudgetExceeded
+    pub(crate) fn try_insert_entry_and<F: FnOnce(&K, &V) -> T, T>(
+        &self,
+        key: K,
+        hash: u64,
+        value: V,
+        with_previous_entry: F,
+    ) -> Result<Option<T>, Error> {
+        if let Some(memory) = self.memory {
+            memory.try_charge(&key, &value, self.context)?;
+        }
+
+        Ok(self
+            .insert_charged_entry_and(key, hash, value, None, false, with_previous_entry)
+            .0)
+    }
+
+    /// Like [`insert_entry_and`], but also returns the sequence number assigned
+    /// to the insertion, which is 0 if the map does not assign them.
+    ///
+    /// [`insert_entry_and`]: #method.insert_entry_and
+    #[cfg(feature = "sequence-numbers")]
+    pub(crate) fn insert_sequenced_entry_and<F: FnOnce(&K, &V) -> T, T>(
+        &self,
+        key: K,
+        hash: u64,
+        value: V,
+        with_previous_entry: F,
+    ) -> (Option<T>, u64) {
+        if let Some(memory) = self.memory {
+            memory.charge(&key, &value, self.context);
+        }
+
+        self.insert_charged_entry_and(key, hash, value, None, false, with_previous_entry)
+    }
+
+    /// Inserts an entry whose weight has already been charged to the memory
+    /// budget of the map, if any, returning the sequence number assigned to
+    /// the insertion along with the result of `with_previous_entry`.
+    fn insert_charged_entry_and<F: FnOnce(&K, &V) -> T, T>(
+        &self,
+        key: K,
+        hash: u64,
+        value: V,
+        hint: Option<SlotHint>,
+        reserved: bool,
+        with_previous_entry: F,
+    ) -> (Option<T>, u64) {
+        let _panic_guard = self.panic_policy.enter();
+        let _timer = self.start_timer(Operation::Insert);
+        metrics::count_insert();
+        self.add_to_fingerprint(hash);
+
         let guard = &crossbeam_epoch::pin();
         let current_ref = self.get(guard);
         let mut bucket_array_ref = current_ref;
         let mut bucket_ptr = Owned::new(Bucket::new(key, value));
+        // the bucket keeps its address when it is published, where `guard`
+        // keeps it valid
+        let new_bucket: *const Bucket<K, V> = &*bucket_ptr;
+        let stamp = self.stamp();
+        let mut hint = hint.filter(|h| h.hash == hash);
 
         let result;
+        let sequence;
 
         loop {
-            while self.len.load(Ordering::Relaxed) > bucket_array_ref.capacity() {
-                bucket_array_ref = bucket_array_ref.rehash(guard, self.build_hasher);
+            while !reserved && self.is_overloaded(bucket_array_ref) {
+                bucket_array_ref = self.rehash(guard, bucket_array_ref);
             }
 
-            match bucket_array_ref.insert(guard, hash, bucket_ptr) {
+            let insert_result = match hint.take() {
+                Some(h) if h.epoch == bucket_array_ref.epoch => bucket_array_ref
+                    .insert_at(guard, h.index, bucket_ptr, stamp)
+                    .or_else(|p| bucket_array_ref.insert(guard, hash, p, stamp)),
+                _ => bucket_array_ref.insert(guard, hash, bucket_ptr, stamp),
+            };
+
+            match insert_result {
                 Ok(previous_bucket_ptr) => {
+                    sequence = unsafe { (*new_bucket).sequence.get() };
+
                     if let Some(previous_bucket_ref) = unsafe { previous_bucket_ptr.as_ref() } {
+                        // the bucket is retired before the closure is invoked,
+                        // so that it is not leaked if the closure panics. it
+                        // stays valid while `guard` is pinned.
+                        unsafe { bucket::defer_destroy_bucket(guard, previous_bucket_ptr) };
+
                         if previous_bucket_ptr.tag() & bucket::TOMBSTONE_TAG != 0 {
-                            self.len.fetch_add(1, Ordering::Relaxed);
+                            self.len.fetch_add(1, ordering::RELAXED);
                             result = None;
                         } else {
-                            let Bucket {
-                                key,
-                                maybe_value: value,
-                            } = previous_bucket_ref;
-                            result = Some(with_previous_entry(key, unsafe { &*value.as_ptr() }));
+                            let Bucket { key, .. } = previous_bucket_ref;
+                            let value = unsafe { previous_bucket_ref.value() };
+                            self.release(key, value);
+                            result = Some(with_previous_entry(key, value));
                         }
-
-                        unsafe { bucket::defer_destroy_bucket(guard, previous_bucket_ptr) };
                     } else {
-                        self.len.fetch_add(1, Ordering::Relaxed);
+                        self.len.fetch_add(1, ordering::RELAXED);
                         result = None;
                     }
 
                     break;
                 }
                 Err(p) => {
+                    metrics::note_retry();
                     bucket_ptr = p;
-                    bucket_array_ref = bucket_array_ref.rehash(guard, self.build_hasher);
+                    bucket_array_ref = self.rehash(guard, bucket_array_ref);
                 }
             }
         }
 
         self.swing(guard, current_ref, bucket_array_ref);
+        let key = unsafe { &(*new_bucket).key };
+        self.sample(guard, SampledOperation::Insert, bucket_array_ref, hash, key);
 
-        result
+        (result, sequence)
     }
 
     pub(crate) fn remove_entry_if_and<
@@ -121,30 +485,108 @@ impl<'a, K: Hash + Eq, V, S: BuildHasher> BucketArrayRef<'a, K, V, S> {
         &self,
         key: &Q,
         hash: u64,
-        mut condition: F,
+        condition: F,
         with_previous_entry: G,
     ) -> Option<T>
     where
         K: Borrow<Q>,
     {
+        self.remove_entry_if_with_hint_and(key, hash, condition, with_previous_entry)
+            .map(|(t, _)| t)
+    }
+
+    /// Like [`remove_entry_if_and`], but also returns the sequence number
+    /// assigned to the removal, which is 0 if the map does not assign them.
+    ///
+    /// [`remove_entry_if_and`]: #method.remove_entry_if_and
+    #[cfg(feature = "sequence-numbers")]
+    pub(crate) fn remove_sequenced_entry_if_and<
+        Q: Hash + Eq + ?Sized,
+        F: FnMut(&K, &V) -> bool,
+        G: FnOnce(&K, &V) -> T,
+        T,
+    >(
+        &self,
+        key: &Q,
+        hash: u64,
+        condition: F,
+        with_previous_entry: G,
+    ) -> Option<(T, u64)>
+    where
+        K: Borrow<Q>,
+    {
+        self.remove_bucket_if_and(key, hash, condition, with_previous_entry)
+            .map(|(t, _, sequence)| (t, sequence))
+    }
+
+    pub(crate) fn remove_entry_if_with_hint_and<
+        Q: Hash + Eq + ?Sized,
+        F: FnMut(&K, &V) -> bool,
+        G: FnOnce(&K, &V) -> T,
+        T,
+    >(
+        &self,
+        key: &Q,
+        hash: u64,
+        condition: F,
+        with_previous_entry: G,
+    ) -> Option<(T, SlotHint)>
+    where
+        K: Borrow<Q>,
+    {
+        self.remove_bucket_if_and(key, hash, condition, with_previous_entry)
+            .map(|(t, hint, _)| (t, hint))
+    }
+
+    /// Removes the entry of `key` if `condition` holds, returning the result
+    /// of `with_previous_entry`, the slot the entry was removed from, and the
+    /// sequence number assigned to the removal.
+    fn remove_bucket_if_and<
+        Q: Hash + Eq + ?Sized,
+        F: FnMut(&K, &V) -> bool,
+        G: FnOnce(&K, &V) -> T,
+        T,
+    >(
+        &self,
+        key: &Q,
+        hash: u64,
+        mut condition: F,
+        with_previous_entry: G,
+    ) -> Option<(T, SlotHint, u64)>
+    where
+        K: Borrow<Q>,
+    {
+        let _panic_guard = self.panic_policy.enter();
+        let _timer = self.start_timer(Operation::Remove);
+
+        if !self.may_contain(hash) {
+            return None;
+        }
+
         let guard = &crossbeam_epoch::pin();
         let current_ref = self.get(guard);
         let mut bucket_array_ref = current_ref;
+        let stamp = self.stamp();
 
         let result;
 
         loop {
-            match bucket_array_ref.remove_if(guard, hash, key, condition) {
-                Ok(previous_bucket_ptr) => {
+            match bucket_array_ref.remove_if(guard, hash, key, stamp, condition) {
+                Ok((previous_bucket_ptr, index)) => {
                     if let Some(previous_bucket_ref) = unsafe { previous_bucket_ptr.as_ref() } {
-                        let Bucket {
-                            key,
-                            maybe_value: value,
-                        } = previous_bucket_ref;
-                        self.len.fetch_sub(1, Ordering::Relaxed);
-                        result = Some(with_previous_entry(key, unsafe { &*value.as_ptr() }));
-
+                        self.len.fetch_sub(1, ordering::RELAXED);
                         unsafe { bucket::defer_destroy_tombstone(guard, previous_bucket_ptr) };
+
+                        let Bucket { key, .. } = previous_bucket_ref;
+                        let value = unsafe { previous_bucket_ref.value() };
+                        self.release(key, value);
+                        let hint = SlotHint {
+                            hash,
+                            epoch: bucket_array_ref.epoch,
+                            index,
+                        };
+                        let sequence = previous_bucket_ref.sequence.get();
+                        result = Some((with_previous_entry(key, value), hint, sequence));
                     } else {
                         result = None;
                     }
@@ -152,19 +594,56 @@ impl<'a, K: Hash + Eq, V, S: BuildHasher> BucketArrayRef<'a, K, V, S> {
                     break;
                 }
                 Err(c) => {
+                    metrics::note_retry();
                     condition = c;
-                    bucket_array_ref = bucket_array_ref.rehash(guard, self.build_hasher);
+                    bucket_array_ref = self.rehash(guard, bucket_array_ref);
                 }
             }
         }
 
         self.swing(guard, current_ref, bucket_array_ref);
+        self.sample(guard, SampledOperation::Remove, bucket_array_ref, hash, key);
 
         result
     }
 
+    /// Removes every entry, invoking `with_entry` with each of them, and
+    /// returns the number of entries removed.
+    pub(crate) fn drain_and<F: FnMut(&K, &V)>(&self, mut with_entry: F) -> usize {
+        let _panic_guard = self.panic_policy.enter();
+        let guard = &crossbeam_epoch::pin();
+        let current_ref = self.get(guard);
+        let mut bucket_array_ref = current_ref;
+        let stamp = self.stamp();
+        let mut num_removed = 0;
+
+        // the buckets taken from an array before it was relocated are
+        // tombstones in the next one, so they are not taken twice
+        while bucket_array_ref
+            .take_all(guard, stamp, |previous_bucket_ptr| {
+                self.len.fetch_sub(1, ordering::RELAXED);
+                num_removed += 1;
+                // the bucket is retired before the closure is invoked, so that
+                // it is not leaked if the closure panics
+                unsafe { bucket::defer_destroy_tombstone(guard, previous_bucket_ptr) };
+
+                let Bucket { key, .. } = unsafe { previous_bucket_ptr.deref() };
+                let value = unsafe { previous_bucket_ptr.deref().value() };
+                self.release(key, value);
+                with_entry(key, value);
+            })
+            .is_err()
+        {
+            bucket_array_ref = self.rehash(guard, bucket_array_ref);
+        }
+
+        self.swing(guard, current_ref, bucket_array_ref);
+
+        num_removed
+    }
+
     pub(crate) fn insert_with_or_modify_entry_and<
-        F: FnOnce() -> V,
+        F: FnOnce(&K) -> V,
         G: FnMut(&K, &V) -> V,
         H: FnOnce(&K, &V) -> T,
         T,
@@ -173,39 +652,61 @@ impl<'a, K: Hash + Eq, V, S: BuildHasher> BucketArrayRef<'a, K, V, S> {
         key: K,
         hash: u64,
         on_insert: F,
-        mut on_modify: G,
+        on_modify: G,
         with_old_entry: H,
     ) -> Option<T> {
+        let _panic_guard = self.panic_policy.enter();
         let guard = &crossbeam_epoch::pin();
+
+        self.insert_with_or_modify(guard, key, hash, on_insert, on_modify)
+            .0
+            .map(|b| with_old_entry(&b.key, unsafe { b.value() }))
+    }
+
+    /// Returns the previous bucket if the key was present, and the bucket that
+    /// has been inserted.
+    pub(crate) fn insert_with_or_modify<'g, F: FnOnce(&K) -> V, G: FnMut(&K, &V) -> V>(
+        &self,
+        guard: &'g Guard,
+        key: K,
+        hash: u64,
+        on_insert: F,
+        mut on_modify: G,
+    ) -> (Option<&'g Bucket<K, V>>, &'g Bucket<K, V>) {
+        let _panic_guard = self.panic_policy.enter();
         let current_ref = self.get(guard);
         let mut bucket_array_ref = current_ref;
         let mut state = InsertOrModifyState::New(key, on_insert);
+        let stamp = self.stamp();
+        self.add_to_fingerprint(hash);
 
         let result;
 
         loop {
-            while self.len.load(Ordering::Relaxed) > bucket_array_ref.capacity() {
-                bucket_array_ref = bucket_array_ref.rehash(guard, self.build_hasher);
+            while self.is_overloaded(bucket_array_ref) {
+                bucket_array_ref = self.rehash(guard, bucket_array_ref);
             }
 
-            match bucket_array_ref.insert_or_modify(guard, hash, state, on_modify) {
-                Ok(previous_bucket_ptr) => {
+            match bucket_array_ref.insert_or_modify(guard, hash, state, stamp, on_modify) {
+                Ok((previous_bucket_ptr, current_bucket_ptr)) => {
+                    let current_bucket_ref = unsafe { current_bucket_ptr.deref() };
+                    self.charge(current_bucket_ref);
+
                     if let Some(previous_bucket_ref) = unsafe { previous_bucket_ptr.as_ref() } {
                         if previous_bucket_ptr.tag() & bucket::TOMBSTONE_TAG != 0 {
-                            self.len.fetch_add(1, Ordering::Relaxed);
-                            result = None;
+                            self.len.fetch_add(1, ordering::RELAXED);
+                            result = (None, current_bucket_ref);
                         } else {
-                            let Bucket {
-                                key,
-                                maybe_value: value,
-                            } = previous_bucket_ref;
-                            result = Some(with_old_entry(key, unsafe { &*value.as_ptr() }));
+                            self.release(&previous_bucket_ref.key, unsafe {
+                                previous_bucket_ref.value()
+                            });
+                            result = (Some(previous_bucket_ref), current_bucket_ref);
                         }
 
                         unsafe { bucket::defer_destroy_bucket(guard, previous_bucket_ptr) };
                     } else {
-                        self.len.fetch_add(1, Ordering::Relaxed);
-                        result = None;
+                        self.len.fetch_add(1, ordering::RELAXED);
+                        result = (None, current_bucket_ref);
                     }
 
                     break;
@@ -213,41 +714,389 @@ impl<'a, K: Hash + Eq, V, S: BuildHasher> BucketArrayRef<'a, K, V, S> {
                 Err((s, f)) => {
                     state = s;
                     on_modify = f;
-                    bucket_array_ref = bucket_array_ref.rehash(guard, self.build_hasher);
+                    bucket_array_ref = self.rehash(guard, bucket_array_ref);
                 }
             }
         }
 
         self.swing(guard, current_ref, bucket_array_ref);
+        let key = &result.1.key;
+        self.sample(guard, SampledOperation::Insert, bucket_array_ref, hash, key);
 
         result
     }
 
-    pub(crate) fn modify_entry_and<F: FnMut(&K, &V) -> V, G: FnOnce(&K, &V) -> T, T>(
+    pub(crate) fn get_or_insert_with<'g, F: FnOnce() -> V>(
         &self,
+        guard: &'g Guard,
         key: K,
         hash: u64,
-        mut on_modify: F,
-        with_old_entry: G,
-    ) -> Option<T> {
-        let guard = &crossbeam_epoch::pin();
+        on_insert: F,
+    ) -> (&'g Bucket<K, V>, bool) {
+        let _panic_guard = self.panic_policy.enter();
         let current_ref = self.get(guard);
         let mut bucket_array_ref = current_ref;
-        let mut key_or_owned_bucket = KeyOrOwnedBucket::Key(key);
+        let mut state = InsertOrModifyState::New(key, move |_: &K| on_insert());
+        let stamp = self.stamp();
+        self.add_to_fingerprint(hash);
 
         let result;
 
         loop {
-            match bucket_array_ref.modify(guard, hash, key_or_owned_bucket, on_modify) {
-                Ok(previous_bucket_ptr) => {
-                    if let Some(previous_bucket_ref) = unsafe { previous_bucket_ptr.as_ref() } {
-                        let Bucket {
-                            key,
-                            maybe_value: value,
-                        } = previous_bucket_ref;
-                        result = Some(with_old_entry(key, unsafe { &*value.as_ptr() }));
+            while self.is_overloaded(bucket_array_ref) {
+                bucket_array_ref = self.rehash(guard, bucket_array_ref);
+            }
 
+            match bucket_array_ref.get_or_insert(guard, hash, state, stamp) {
+                Ok((previous_bucket_ptr, current_bucket_ptr)) => {
+                    let inserted = if previous_bucket_ptr.is_null() {
+                        true
+                    } else if previous_bucket_ptr.tag() & bucket::TOMBSTONE_TAG != 0 {
                         unsafe { bucket::defer_destroy_bucket(guard, previous_bucket_ptr) };
+
+                        true
+                    } else {
+                        false
+                    };
+
+                    let current_bucket_ref = unsafe { current_bucket_ptr.deref() };
+
+                    if inserted {
+                        self.len.fetch_add(1, ordering::RELAXED);
+                        self.charge(current_bucket_ref);
+                    }
+
+                    result = (current_bucket_ref, inserted);
+
+                    break;
+                }
+                Err(s) => {
+                    state = s;
+                    bucket_array_ref = self.rehash(guard, bucket_array_ref);
+                }
+            }
+        }
+
+        self.swing(guard, current_ref, bucket_array_ref);
+
+        result
+    }
+
+    pub(crate) fn live_buckets<'g>(&self, guard: &'g Guard) -> Vec<&'g Bucket<K, V>> {
+        let mut result = self.live_buckets_in_memory_order(guard);
+
+        if self.deterministic {
+            result.sort_by_cached_key(|b| bucket::hash(self.build_hasher, &b.key));
+        }
+
+        result
+    }
+
+    /// Collects the live buckets in the order of the slots that hold them,
+    /// even if the map is deterministic.
+    pub(crate) fn live_buckets_in_memory_order<'g>(
+        &self,
+        guard: &'g Guard,
+    ) -> Vec<&'g Bucket<K, V>> {
+        let current_ref = self.get(guard);
+        let mut bucket_array_ref = current_ref;
+
+        let result;
+
+        loop {
+            match bucket_array_ref.live_buckets(guard) {
+                Ok(buckets) => {
+                    result = buckets;
+
+                    break;
+                }
+                Err(_) => {
+                    bucket_array_ref = self.rehash(guard, bucket_array_ref);
+                }
+            }
+        }
+
+        self.swing(guard, current_ref, bucket_array_ref);
+
+        result
+    }
+
+    /// Walks the live buckets in place, in the order of the slots that hold
+    /// them, even if the map is deterministic. See [`Walk`].
+    ///
+    /// [`Walk`]: struct.Walk.html
+    pub(crate) fn walk<'g>(self, guard: &'g Guard) -> LiveBuckets<'a, 'g, K, V, S> {
+        LiveBuckets {
+            map: self,
+            guard,
+            walk: Walk::new(),
+        }
+    }
+
+    /// Collects the live buckets from the scan position `from` on, returning
+    /// them with the scan position to resume from. See [`BucketArray::scan`].
+    ///
+    /// [`BucketArray::scan`]: ../bucket/struct.BucketArray.html#method.scan
+    pub(crate) fn scan<'g>(
+        &self,
+        guard: &'g Guard,
+        from: u64,
+        limit: usize,
+    ) -> (Vec<&'g Bucket<K, V>>, Option<u64>) {
+        if ordering::load_consume(self.bucket_array, guard).is_null() {
+            return (Vec::new(), None);
+        }
+
+        let current_ref = self.get(guard);
+        let mut bucket_array_ref = current_ref;
+
+        let result;
+
+        loop {
+            match bucket_array_ref.scan(guard, self.build_hasher, from, limit) {
+                Ok(r) => {
+                    result = r;
+
+                    break;
+                }
+                Err(_) => {
+                    bucket_array_ref = self.rehash(guard, bucket_array_ref);
+                }
+            }
+        }
+
+        self.swing(guard, current_ref, bucket_array_ref);
+
+        result
+    }
+
+    /// Returns the first live bucket from the slot selected by `random` on,
+    /// or `None` if there is none. See [`BucketArray::first_live_bucket_from`].
+    ///
+    /// [`BucketArray::first_live_bucket_from`]: ../bucket/struct.BucketArray.html#method.first_live_bucket_from
+    pub(crate) fn random_bucket<'g>(
+        &self,
+        guard: &'g Guard,
+        random: u64,
+    ) -> Option<&'g Bucket<K, V>> {
+        if ordering::load_consume(self.bucket_array, guard).is_null() {
+            return None;
+        }
+
+        let current_ref = self.get(guard);
+        let mut bucket_array_ref = current_ref;
+
+        let result;
+
+        loop {
+            match bucket_array_ref.first_live_bucket_from(guard, random as usize) {
+                Ok(r) => {
+                    result = r;
+
+                    break;
+                }
+                Err(_) => {
+                    bucket_array_ref = self.rehash(guard, bucket_array_ref);
+                }
+            }
+        }
+
+        self.swing(guard, current_ref, bucket_array_ref);
+
+        result
+    }
+
+    pub(crate) fn probe_stats(&self) -> ProbeStats {
+        let guard = &crossbeam_epoch::pin();
+
+        if ordering::load_consume(self.bucket_array, guard).is_null() {
+            return ProbeStats::default();
+        }
+
+        let current_ref = self.get(guard);
+        let mut bucket_array_ref = current_ref;
+
+        let result;
+
+        loop {
+            match bucket_array_ref.probe_stats(guard, self.build_hasher) {
+                Ok(stats) => {
+                    result = stats;
+
+                    break;
+                }
+                Err(_) => {
+                    bucket_array_ref = self.rehash(guard, bucket_array_ref);
+                }
+            }
+        }
+
+        self.swing(guard, current_ref, bucket_array_ref);
+
+        result
+    }
+
+    /// Shrinks the bucket array or reclaims its tombstones if needed.
+    ///
+    /// Returns the number of buckets that were visited and the operation that
+    /// was performed, if any.
+    pub(crate) fn run_housekeeping(&self) -> (usize, Option<RehashOp>) {
+        let guard = &crossbeam_epoch::pin();
+
+        if ordering::load_consume(self.bucket_array, guard).is_null() {
+            return (0, None);
+        }
+
+        let current_ref = self.get(guard);
+        let mut bucket_array_ref = current_ref;
+
+        let stats = loop {
+            match bucket_array_ref.probe_stats(guard, self.build_hasher) {
+                Ok(stats) => break stats,
+                Err(_) => {
+                    bucket_array_ref = self.rehash(guard, bucket_array_ref);
+                }
+            }
+        };
+
+        let mut num_visited = stats.num_buckets;
+        let maybe_op = RehashOp::for_housekeeping(&stats, self.tombstone_policy);
+
+        if let Some(op) = maybe_op {
+            num_visited += stats.num_buckets;
+            bucket_array_ref = bucket_array_ref.rehash_with(guard, self.build_hasher, op);
+
+            // concurrent insertions may have outgrown the shrunk array while
+            // it was being filled
+            if op == RehashOp::Shrink
+                && self.len.load(ordering::RELAXED) > bucket_array_ref.capacity()
+            {
+                num_visited += bucket_array_ref.buckets.len();
+                bucket_array_ref =
+                    bucket_array_ref.rehash_with(guard, self.build_hasher, RehashOp::Expand);
+            }
+        }
+
+        self.swing(guard, current_ref, bucket_array_ref);
+
+        (num_visited, maybe_op)
+    }
+
+    /// Grows the bucket array, if needed, so that it can hold `additional`
+    /// more elements than the current length without being reallocated.
+    pub(crate) fn reserve(&self, additional: usize) -> Result<(), Error> {
+        let length = self
+            .len
+            .load(ordering::RELAXED)
+            .checked_add(additional)
+            .ok_or(Error::CapacityOverflow)
+            .and_then(bucket::length_for_capacity)?;
+
+        let guard = &crossbeam_epoch::pin();
+
+        if ordering::load_consume(self.bucket_array, guard).is_null() {
+            // allocate the first array with the reserved length, rather than
+            // with the default one only to reallocate it
+            let _ = self.bucket_array.compare_exchange(
+                Shared::null(),
+                Owned::new(BucketArray::with_length(0, length)),
+                ordering::RELEASE,
+                ordering::RELAXED,
+                guard,
+            );
+        }
+
+        let current_ref = self.get(guard);
+        let mut bucket_array_ref = current_ref;
+
+        while bucket_array_ref.buckets.len() < length {
+            bucket_array_ref =
+                bucket_array_ref.rehash_with(guard, self.build_hasher, RehashOp::Reserve(length));
+        }
+
+        self.swing(guard, current_ref, bucket_array_ref);
+
+        Ok(())
+    }
+
+    /// Reserves `additional` slots for reservation tokens, growing the bucket
+    /// array so that it can hold them on top of the entries and the slots
+    /// reserved before.
+    pub(crate) fn reserve_slots(&self, additional: usize) -> Result<(), Error> {
+        let reserved = self
+            .reserved_slots
+            .fetch_update(ordering::RELAXED, ordering::RELAXED, |r| {
+                r.checked_add(additional)
+            })
+            .map_err(|_| Error::CapacityOverflow)?
+            + additional;
+
+        self.reserve(reserved).map_err(|e| {
+            self.reserved_slots.fetch_sub(additional, ordering::RELAXED);
+            e
+        })
+    }
+
+    pub(crate) fn modify_entry_and<F: FnMut(&K, &V) -> V, G: FnOnce(&K, &V) -> T, T>(
+        &self,
+        key: K,
+        hash: u64,
+        mut on_modify: F,
+        with_old_entry: G,
+    ) -> Option<T> {
+        self.try_modify_entry_and(key, hash, |k, v| Some(on_modify(k, v)), with_old_entry)
+    }
+
+    /// Like [`modify_entry_and`], but leaves the value unchanged and returns
+    /// `None` if `on_modify` returns `None`.
+    ///
+    /// [`modify_entry_and`]: #method.modify_entry_and
+    pub(crate) fn try_modify_entry_and<F: FnMut(&K, &V) -> Option<V>, G: FnOnce(&K, &V) -> T, T>(
+        &self,
+        key: K,
+        hash: u64,
+        mut on_modify: F,
+        with_old_entry: G,
+    ) -> Option<T> {
+        if !self.may_contain(hash) {
+            return None;
+        }
+
+        let _panic_guard = self.panic_policy.enter();
+        let guard = &crossbeam_epoch::pin();
+        let current_ref = self.get(guard);
+        let mut bucket_array_ref = current_ref;
+        let mut key_or_owned_bucket = KeyOrOwnedBucket::Key(key);
+        let stamp = self.stamp();
+        // the value returned by the last call is the one that is stored
+        let mut new_weight = 0;
+        let mut on_modify = |k: &K, v: &V| {
+            let new_value = on_modify(k, v)?;
+
+            if let Some(memory) = self.memory {
+                new_weight = memory.weigh(k, &new_value);
+            }
+
+            Some(new_value)
+        };
+
+        let result;
+
+        loop {
+            match bucket_array_ref.modify(guard, hash, key_or_owned_bucket, stamp, on_modify) {
+                Ok(previous_bucket_ptr) => {
+                    if let Some(previous_bucket_ref) = unsafe { previous_bucket_ptr.as_ref() } {
+                        unsafe { bucket::defer_destroy_bucket(guard, previous_bucket_ptr) };
+
+                        let Bucket { key, .. } = previous_bucket_ref;
+                        let value = unsafe { previous_bucket_ref.value() };
+
+                        if let Some(memory) = self.memory {
+                            memory.charge_weight(new_weight, self.context);
+                            memory.release(key, value);
+                        }
+
+                        result = Some(with_old_entry(key, value));
                     } else {
                         result = None;
                     }
@@ -257,7 +1106,91 @@ impl<'a, K: Hash + Eq, V, S: BuildHasher> BucketArrayRef<'a, K, V, S> {
                 Err((kb, f)) => {
                     key_or_owned_bucket = kb;
                     on_modify = f;
-                    bucket_array_ref = bucket_array_ref.rehash(guard, self.build_hasher);
+                    bucket_array_ref = self.rehash(guard, bucket_array_ref);
+                }
+            }
+        }
+
+        self.swing(guard, current_ref, bucket_array_ref);
+
+        result
+    }
+
+    /// Carries out the decision that `decide` makes on the entry of `key` at
+    /// the slot where it was found, returning the result of invoking
+    /// `with_entry` with the entry that `decide` was last called with, along
+    /// with what was done.
+    pub(crate) fn get_then_modify_entry_and<
+        F: FnMut(&K, &V) -> Decision<V>,
+        G: FnOnce(&K, &V) -> T,
+        T,
+    >(
+        &self,
+        key: K,
+        hash: u64,
+        mut decide: F,
+        with_entry: G,
+    ) -> Option<(T, Decided)> {
+        if !self.may_contain(hash) {
+            return None;
+        }
+
+        let _panic_guard = self.panic_policy.enter();
+        let guard = &crossbeam_epoch::pin();
+        let current_ref = self.get(guard);
+        let mut bucket_array_ref = current_ref;
+        let mut key_or_owned_bucket = KeyOrOwnedBucket::Key(key);
+        let stamp = self.stamp();
+        // the value returned by the last call is the one that is stored
+        let mut new_weight = 0;
+        let mut decide = |k: &K, v: &V| {
+            let decision = decide(k, v);
+
+            if let (Some(memory), Decision::Replace(new_value)) = (self.memory, &decision) {
+                new_weight = memory.weigh(k, new_value);
+            }
+
+            decision
+        };
+
+        let result;
+
+        loop {
+            match bucket_array_ref.decide(guard, hash, key_or_owned_bucket, stamp, decide) {
+                Ok((bucket_ptr, decided)) => {
+                    if let Some(bucket_ref) = unsafe { bucket_ptr.as_ref() } {
+                        let Bucket { key, .. } = bucket_ref;
+                        let value = unsafe { bucket_ref.value() };
+
+                        match decided {
+                            Decided::Kept => (),
+                            Decided::Replaced => {
+                                unsafe { bucket::defer_destroy_bucket(guard, bucket_ptr) };
+
+                                if let Some(memory) = self.memory {
+                                    memory.charge_weight(new_weight, self.context);
+                                }
+
+                                self.release(key, value);
+                            }
+                            Decided::Removed => {
+                                self.len.fetch_sub(1, ordering::RELAXED);
+                                unsafe { bucket::defer_destroy_tombstone(guard, bucket_ptr) };
+                                self.release(key, value);
+                            }
+                        }
+
+                        result = Some((with_entry(key, value), decided));
+                    } else {
+                        result = None;
+                    }
+
+                    break;
+                }
+                Err((kb, f)) => {
+                    key_or_owned_bucket = kb;
+                    decide = f;
+                    bucket_array_ref = self.rehash(guard, bucket_array_ref);
                 }
             }
         }
@@ -266,29 +1199,162 @@ impl<'a, K: Hash + Eq, V, S: BuildHasher> BucketArrayRef<'a, K, V, S> {
 
         result
     }
+
+    /// Returns `false` if no key with the hash can be in the map, because no
+    /// key sharing its fingerprint bit was inserted since the fingerprint was
+    /// last reset.
+    fn may_contain(&self, hash: u64) -> bool {
+        match self.fingerprint {
+            Some(fingerprint) => fingerprint.load(ordering::RELAXED) & fingerprint_bit(hash) != 0,
+            None => true,
+        }
+    }
+
+    /// Returns `true` if the bucket array must grow before an insertion that
+    /// does not hold a reserved slot.
+    fn is_overloaded(&self, bucket_array_ref: &BucketArray<K, V>) -> bool {
+        self.len
+            .load(ordering::RELAXED)
+            .saturating_add(self.reserved_slots.load(ordering::RELAXED))
+            > bucket_array_ref.capacity()
+    }
+
+    // the bit is set before the bucket is published, so a lookup that can
+    // observe the bucket also observes the bit.
+    fn add_to_fingerprint(&self, hash: u64) {
+        if let Some(fingerprint) = self.fingerprint {
+            let bit = fingerprint_bit(hash);
+
+            if fingerprint.load(ordering::RELAXED) & bit == 0 {
+                fingerprint.fetch_or(bit, ordering::RELAXED);
+            }
+        }
+    }
+
+    /// Passes the operation on `key` to the sampler if it picks it, and notes
+    /// its probe length in the report of the operation if it is recorded,
+    /// probing for the key again from `bucket_array_ref` to measure it.
+    #[inline]
+    fn sample<'g, Q: Eq + ?Sized>(
+        &self,
+        guard: &'g Guard,
+        operation: SampledOperation,
+        mut bucket_array_ref: &'g BucketArray<K, V>,
+        hash: u64,
+        key: &Q,
+    ) where
+        K: Borrow<Q>,
+    {
+        let sampler = self.sampler.filter(|s| s.pick());
+
+        if sampler.is_none() && !metrics::is_recording_report() {
+            return;
+        }
+
+        // the arrays that replaced this one are only followed, never helped to
+        // be filled, so that sampling does not add work to the operation
+        let probe_length = loop {
+            match bucket_array_ref.probe_length(guard, hash, key) {
+                Ok(length) => break length,
+                Err(_) => {
+                    match unsafe { ordering::load_consume(&bucket_array_ref.next, guard).as_ref() }
+                    {
+                        Some(next_ref) => bucket_array_ref = next_ref,
+                        None => break 0,
+                    }
+                }
+            }
+        };
+
+        metrics::note_probe_length(probe_length);
+
+        if let Some(sampler) = sampler {
+            sampler.record(
+                &OperationSample {
+                    operation,
+                    probe_length,
+                    segment: self.segment,
+                    hash,
+                },
+                self.context,
+            );
+        }
+    }
+
+    fn charge(&self, bucket: &Bucket<K, V>) {
+        if let Some(memory) = self.memory {
+            memory.charge(&bucket.key, unsafe { bucket.value() }, self.context);
+        }
+    }
+
+    fn release(&self, key: &K, value: &V) {
+        if let Some(memory) = self.memory {
+            memory.release(key, value);
+        }
+    }
+
+    fn rehash<'g>(
+        &self,
+        guard: &'g Guard,
+        bucket_array_ref: &'g BucketArray<K, V>,
+    ) -> &'g BucketArray<K, V> {
+        let _timer = self.start_timer(Operation::RehashAssist);
+
+        bucket_array_ref.rehash(guard, self.build_hasher)
+    }
+
+    fn rehash_within<'g>(
+        &self,
+        guard: &'g Guard,
+        bucket_array_ref: &'g BucketArray<K, V>,
+        hash: u64,
+        budget: &mut ReadBudget,
+    ) -> Result<&'g BucketArray<K, V>, Error> {
+        let _timer = self.start_timer(Operation::RehashAssist);
+
+        bucket_array_ref.rehash_within(guard, self.build_hasher, hash, budget)
+    }
 }
 
 impl<'a, 'g, K, V, S> BucketArrayRef<'a, K, V, S> {
-    fn get(&self, guard: &'g Guard) -> &'g BucketArray<K, V> {
-        const DEFAULT_LENGTH: usize = 128;
+    #[cfg(feature = "instrument")]
+    #[inline]
+    fn start_timer(&self, operation: Operation) -> Timer<'a> {
+        self.latencies.start_timer(operation)
+    }
 
+    #[cfg(not(feature = "instrument"))]
+    #[inline]
+    fn start_timer(&self, _operation: Operation) -> Timer<'a> {
+        Timer::default()
+    }
+
+    fn stamp(&self) -> Stamp<'a> {
+        Stamp {
+            generation: self.generation.load(ordering::ACQUIRE),
+            access: self.access_clock.load(ordering::RELAXED),
+            sequence: self.sequence,
+        }
+    }
+
+    fn get(&self, guard: &'g Guard) -> &'g BucketArray<K, V> {
         let mut maybe_new_bucket_array = None;
 
         loop {
-            let bucket_array_ptr = self.bucket_array.load_consume(guard);
+            let bucket_array_ptr = ordering::load_consume(self.bucket_array, guard);
 
             if let Some(bucket_array_ref) = unsafe { bucket_array_ptr.as_ref() } {
                 return bucket_array_ref;
             }
 
             let new_bucket_array = maybe_new_bucket_array
-                .unwrap_or_else(|| Owned::new(BucketArray::with_length(0, DEFAULT_LENGTH)));
+                .unwrap_or_else(|| Owned::new(BucketArray::with_length(0, bucket::DEFAULT_LENGTH)));
 
             match self.bucket_array.compare_exchange_weak(
                 Shared::null(),
                 new_bucket_array,
-                Ordering::Release,
-                Ordering::Relaxed,
+                ordering::RELEASE,
+                ordering::RELAXED,
                 guard,
             ) {
                 Ok(b) => return unsafe { b.as_ref() }.unwrap(),
@@ -316,13 +1382,13 @@ impl<'a, 'g, K, V, S> BucketArrayRef<'a, K, V, S> {
             match self.bucket_array.compare_exchange_weak(
                 current_ptr,
                 min_ptr,
-                Ordering::Release,
-                Ordering::Relaxed,
+                ordering::RELEASE,
+                ordering::RELAXED,
                 guard,
             ) {
                 Ok(_) => unsafe { bucket::defer_acquire_destroy(guard, current_ptr) },
                 Err(_) => {
-                    let new_ptr = self.bucket_array.load_consume(guard);
+                    let new_ptr = ordering::load_consume(self.bucket_array, guard);
                     assert!(!new_ptr.is_null());
 
                     current_ptr = new_ptr;
@@ -332,3 +1398,176 @@ impl<'a, 'g, K, V, S> BucketArrayRef<'a, K, V, S> {
         }
     }
 }
+
+/// A walk over the live buckets of a map that reads its bucket pointer array
+/// in place, one cluster of occupied slots at a time.
+///
+/// Only the buckets of the cluster being visited are buffered. A cluster holds
+/// every bucket whose home slot is in it, so if the array is relocated during
+/// the walk, the walk moves on to the next array and skips the buckets whose
+/// home slots in the earlier arrays were in the clusters it already visited.
+/// No entry is visited twice, and every entry that is in the map during the
+/// whole walk is visited. The buckets of a cluster are prefetched as soon as
+/// the cluster is read.
+///
+/// The state of the walk does not borrow the guard it is read under, so that
+/// an iterator can own both.
+pub(crate) struct Walk<K, V> {
+    // the bucket array being walked, or null until the walk starts
+    bucket_array: *const BucketArray<K, V>,
+    // the empty slot the walk of `bucket_array` started from, once found
+    origin: Option<usize>,
+    // the number of slots from `origin` on whose clusters were read
+    num_read: usize,
+    cluster: Vec<*const Bucket<K, V>>,
+    num_yielded: usize,
+    // the home slots that were visited in the earlier bucket arrays
+    visited: Vec<VisitedSlots>,
+}
+
+// the `len` slots from `origin` on of a bucket array with `mask + 1` slots
+struct VisitedSlots {
+    mask: usize,
+    origin: usize,
+    len: usize,
+}
+
+impl VisitedSlots {
+    fn contains(&self, hash: u64) -> bool {
+        (hash as usize).wrapping_sub(self.origin) & self.mask < self.len
+    }
+}
+
+impl<K: Hash + Eq, V> Walk<K, V> {
+    pub(crate) fn new() -> Self {
+        Self {
+            bucket_array: ptr::null(),
+            origin: None,
+            num_read: 0,
+            cluster: Vec::new(),
+            num_yielded: 0,
+            visited: Vec::new(),
+        }
+    }
+
+    /// Returns the next live bucket of `map`, or `None` if the walk is over.
+    ///
+    /// # Safety
+    ///
+    /// Every call for the same walk must pass the same `map`, and `guard` must
+    /// have been pinned continuously since the first call.
+    pub(crate) unsafe fn next<'g, S: BuildHasher>(
+        &mut self,
+        map: &BucketArrayRef<'_, K, V, S>,
+        guard: &'g Guard,
+    ) -> Option<&'g Bucket<K, V>> {
+        if self.bucket_array.is_null() {
+            self.bucket_array = map.get(guard);
+        }
+
+        loop {
+            if let Some(&b) = self.cluster.get(self.num_yielded) {
+                self.num_yielded += 1;
+
+                return Some(&*b);
+            }
+
+            let bucket_array_ref: &'g BucketArray<K, V> = &*self.bucket_array;
+
+            if self.num_read == bucket_array_ref.buckets.len() {
+                return None;
+            }
+
+            let origin = match self.origin {
+                Some(origin) => origin,
+                None => match bucket_array_ref.first_empty_slot(guard) {
+                    Ok(origin) => *self.origin.insert(origin),
+                    Err(_) => {
+                        self.relocate(map, guard, bucket_array_ref);
+
+                        continue;
+                    }
+                },
+            };
+
+            self.cluster.clear();
+            self.num_yielded = 0;
+
+            match bucket_array_ref.read_cluster(guard, origin, self.num_read, &mut self.cluster) {
+                Ok(num_read) => self.num_read = num_read,
+                Err(_) => {
+                    self.relocate(map, guard, bucket_array_ref);
+
+                    continue;
+                }
+            }
+
+            if !self.visited.is_empty() {
+                let visited = &self.visited;
+
+                self.cluster.retain(|&b| {
+                    let hash = bucket::hash(map.build_hasher, &(*b).key);
+
+                    !visited.iter().any(|v| v.contains(hash))
+                });
+            }
+
+            // the buckets are scattered over the heap, so the rest of the
+            // cluster is loaded while its first bucket is being used
+            for &b in self.cluster.iter().skip(1) {
+                bucket::prefetch(b);
+            }
+        }
+    }
+
+    fn relocate<'g, S: BuildHasher>(
+        &mut self,
+        map: &BucketArrayRef<'_, K, V, S>,
+        guard: &'g Guard,
+        bucket_array_ref: &'g BucketArray<K, V>,
+    ) {
+        if let Some(origin) = self.origin.take() {
+            if self.num_read > 0 {
+                self.visited.push(VisitedSlots {
+                    mask: bucket_array_ref.buckets.len() - 1,
+                    origin,
+                    len: self.num_read,
+                });
+            }
+        }
+
+        let next_ref = map.rehash(guard, bucket_array_ref);
+        map.swing(guard, bucket_array_ref, next_ref);
+
+        self.bucket_array = next_ref;
+        self.num_read = 0;
+        self.cluster.clear();
+        self.num_yielded = 0;
+    }
+}
+
+/// A [`Walk`] over the live buckets of a map under a borrowed guard.
+///
+/// [`Walk`]: struct.Walk.html
+pub(crate) struct LiveBuckets<'a, 'g, K, V, S> {
+    map: BucketArrayRef<'a, K, V, S>,
+    guard: &'g Guard,
+    walk: Walk<K, V>,
+}
+
+impl<'a, 'g, K: 'g + Hash + Eq, V: 'g, S: BuildHasher> Iterator for LiveBuckets<'a, 'g, K, V, S> {
+    type Item = &'g Bucket<K, V>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        unsafe { self.walk.next(&self.map, self.guard) }
+    }
+}
+
+/// Returns the bit that stands for `hash` in the fingerprint of a segment.
+///
+/// The bit is selected by bits of the hash that neither select the segment,
+/// unless there are more than 2^26 segments, nor the slot, unless the bucket
+/// pointer array has more than 2^32 slots.
+pub(crate) fn fingerprint_bit(hash: u64) -> u64 {
+    1 << ((hash >> 32) & 63)
+}
@@ -0,0 +1,323 @@
+use super::{
+    coalescing::Coalescing, default_value::BoxedDefaultValue, loader::Loading,
+    memory_budget::MemoryCharge, DefaultHashBuilder, DefaultValue, DeterministicState, HashMap,
+    Loader, MemoryBudget, PanicPolicy, TombstonePolicy,
+};
+use crate::{
+    metrics::{OperationSample, Sampler},
+    Error,
+};
+
+use std::{any::Any, marker::PhantomData, sync::atomic::AtomicU64, time::Duration};
+
+/// A builder of [`HashMap`]s.
+///
+/// A `Builder` is created by [`HashMap::builder`], and collects the options of
+/// a map that would otherwise require a combination of constructors.
+///
+/// [`HashMap`]: ./struct.HashMap.html
+/// [`HashMap::builder`]: ./struct.HashMap.html#method.builder
+///
+/// # Examples
+///
+/// ```rust
+/// use moka_cht::HashMap;
+///
+/// let map = HashMap::builder().capacity(16).deterministic().build();
+/// map.insert("foo", 1);
+/// map.insert("bar", 2);
+///
+/// let keys: Vec<_> = map.iter().map(|r| *r.key()).collect();
+///
+/// // the order is the same in every run of the program
+/// assert_eq!(keys.len(), 2);
+/// ```
+pub struct Builder<K, V, S = DefaultHashBuilder> {
+    capacity: usize,
+    build_hasher: S,
+    deterministic: bool,
+    panic_policy: PanicPolicy,
+    tombstone_policy: TombstonePolicy,
+    flush_after_bulk_removals: bool,
+    memory: Option<MemoryCharge<K, V>>,
+    loading: Option<Loading<K, V>>,
+    default_value: Option<BoxedDefaultValue<K, V>>,
+    coalescing: Option<Coalescing<K, V>>,
+    sequence_numbers: bool,
+    sampler: Option<Sampler>,
+    _marker: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K, V> Builder<K, V, DefaultHashBuilder> {
+    pub(crate) fn new() -> Self {
+        Self {
+            capacity: 0,
+            build_hasher: DefaultHashBuilder::default(),
+            deterministic: false,
+            panic_policy: PanicPolicy::default(),
+            tombstone_policy: TombstonePolicy::default(),
+            flush_after_bulk_removals: false,
+            memory: None,
+            loading: None,
+            default_value: None,
+            coalescing: None,
+            sequence_numbers: false,
+            sampler: None,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<K, V, S> Builder<K, V, S> {
+    /// Sets the number of elements the map will be able to hold without
+    /// reallocating its bucket pointer array. The default is 0, which does not
+    /// allocate until the map is first inserted into.
+    pub fn capacity(self, capacity: usize) -> Self {
+        Self { capacity, ..self }
+    }
+
+    /// Sets the hash builder used to hash the keys.
+    pub fn hasher<T>(self, build_hasher: T) -> Builder<K, V, T> {
+        Builder {
+            capacity: self.capacity,
+            build_hasher,
+            deterministic: self.deterministic,
+            panic_policy: self.panic_policy,
+            tombstone_policy: self.tombstone_policy,
+            flush_after_bulk_removals: self.flush_after_bulk_removals,
+            memory: self.memory,
+            loading: self.loading,
+            default_value: self.default_value,
+            coalescing: self.coalescing,
+            sequence_numbers: self.sequence_numbers,
+            sampler: self.sampler,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Makes the map deterministic, for tests that compare its contents with
+    /// golden files.
+    ///
+    /// The keys are hashed with a [`DeterministicState`], and the iterators of
+    /// the map, as well as the snapshots and diffs taken from it, visit the
+    /// entries in the order of their hashes rather than of the slots that hold
+    /// them. The order therefore only depends on the keys in the map, and not
+    /// on the process, the platform, or the history of the map. Entries whose
+    /// hashes are equal are still visited in the order of their slots.
+    ///
+    /// Sorting the entries makes iteration slower, and the hashes are
+    /// predictable, so this is not meant for production use.
+    ///
+    /// [`DeterministicState`]: ./struct.DeterministicState.html
+    pub fn deterministic(self) -> Builder<K, V, DeterministicState> {
+        Builder {
+            deterministic: true,
+            ..self.hasher(DeterministicState)
+        }
+    }
+
+    /// Sets what the map does when a closure passed to one of its methods
+    /// panics. The default is [`PanicPolicy::Unwind`].
+    ///
+    /// [`PanicPolicy::Unwind`]: ./enum.PanicPolicy.html#variant.Unwind
+    pub fn panic_policy(self, panic_policy: PanicPolicy) -> Self {
+        Self {
+            panic_policy,
+            ..self
+        }
+    }
+
+    /// Sets how the map reclaims the tombstones that its removals leave
+    /// behind. The default is `TombstonePolicy::CompactAtRatio(0.25)`.
+    ///
+    /// See [`TombstonePolicy`] for details.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the policy is `CompactAtRatio` with a ratio that is not
+    /// greater than 0 and at most 1.
+    ///
+    /// [`TombstonePolicy`]: ./enum.TombstonePolicy.html
+    pub fn tombstone_policy(self, tombstone_policy: TombstonePolicy) -> Self {
+        tombstone_policy.validate();
+
+        Self {
+            tombstone_policy,
+            ..self
+        }
+    }
+
+    /// Makes the map charge the weights of its entries, as computed by
+    /// `weigher`, to `budget`, which it may share with other maps.
+    ///
+    /// See [`MemoryBudget`] for when the weights are charged and released.
+    ///
+    /// [`MemoryBudget`]: ./struct.MemoryBudget.html
+    pub fn memory_budget(
+        self,
+        budget: MemoryBudget,
+        weigher: impl Fn(&K, &V) -> usize + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            memory: Some(MemoryCharge::new(budget, weigher)),
+            ..self
+        }
+    }
+
+    /// Makes the map load the values of missing keys with `loader` in its
+    /// `get_or_load` method.
+    ///
+    /// See [`Loader`] for details.
+    ///
+    /// [`Loader`]: ./trait.Loader.html
+    pub fn loader(self, loader: impl Loader<K, V> + Send + Sync + 'static) -> Self {
+        Self {
+            loading: Some(Loading::new(loader)),
+            ..self
+        }
+    }
+
+    /// Makes the map create the values of missing keys with `default_value` in
+    /// its `get_or_default` method.
+    ///
+    /// See [`DefaultValue`] for details.
+    ///
+    /// [`DefaultValue`]: ./trait.DefaultValue.html
+    pub fn default_value(
+        self,
+        default_value: impl DefaultValue<K, V> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            default_value: Some(Box::new(default_value)),
+            ..self
+        }
+    }
+
+    /// Makes the map coalesce the writes of its `insert_coalesced` and
+    /// `modify_coalesced` methods to the same key within `window` of each
+    /// other, publishing only the last of them.
+    ///
+    /// See [`insert_coalesced`] for details.
+    ///
+    /// [`insert_coalesced`]: ./struct.HashMap.html#method.insert_coalesced
+    pub fn coalesce_window(self, window: Duration) -> Self {
+        Self {
+            coalescing: Some(Coalescing::new(window)),
+            ..self
+        }
+    }
+
+    /// Makes the map assign a sequence number to each of its mutations,
+    /// which its `insert_sequenced` and `remove_sequenced` methods return.
+    ///
+    /// See [`insert_sequenced`] for details.
+    ///
+    /// This method is only available with the `sequence-numbers` feature.
+    ///
+    /// [`insert_sequenced`]: ./struct.HashMap.html#method.insert_sequenced
+    #[cfg(feature = "sequence-numbers")]
+    pub fn sequence_numbers(self) -> Self {
+        Self {
+            sequence_numbers: true,
+            ..self
+        }
+    }
+
+    /// Makes the `retain`, `extract_if`, and `drain` methods of the map, and
+    /// their `_and` versions, flush the garbage of the current thread and
+    /// advance the global epoch once they finish.
+    ///
+    /// The buckets and the values removed by a bulk removal are otherwise
+    /// destroyed whenever later operations of the same thread flush its
+    /// garbage, which may take long if the thread rarely touches a map
+    /// afterwards. Flushing returns their memory as soon as no other thread is
+    /// pinned to an older epoch, at the cost of a few more atomic operations
+    /// per call. This is best-effort: at most a few flushes are performed, so
+    /// some of the garbage may outlive the call if other threads hold the
+    /// epoch back or if much of it was deferred.
+    pub fn flush_after_bulk_removals(self) -> Self {
+        Self {
+            flush_after_bulk_removals: true,
+            ..self
+        }
+    }
+
+    /// Makes the map invoke `callback` for about one in `one_in` of its
+    /// lookups, insertions, and removals, picked at random.
+    ///
+    /// The callback is invoked by the thread that performed the operation,
+    /// after it completed, so it should return quickly. See
+    /// [`OperationSample`] for what it is passed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `one_in` is 0.
+    ///
+    /// [`OperationSample`]: ../metrics/struct.OperationSample.html
+    pub fn sample_operations(
+        self,
+        one_in: u64,
+        callback: impl Fn(&OperationSample) + Send + Sync + 'static,
+    ) -> Self {
+        self.sample_operations_with_context(one_in, move |sample, _| callback(sample))
+    }
+
+    /// Like [`sample_operations`], but `callback` is also passed the context
+    /// of the operation, if it was performed through a `with_context` view of
+    /// the map.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `one_in` is 0.
+    ///
+    /// [`sample_operations`]: #method.sample_operations
+    pub fn sample_operations_with_context(
+        self,
+        one_in: u64,
+        callback: impl Fn(&OperationSample, Option<&dyn Any>) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            sampler: Some(Sampler::new(one_in, callback)),
+            ..self
+        }
+    }
+
+    /// Creates the map.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bucket pointer array for the capacity would exceed the
+    /// maximum allocation size. See [`try_build`] for a non-panicking version.
+    ///
+    /// [`try_build`]: #method.try_build
+    pub fn build(self) -> HashMap<K, V, S> {
+        self.try_build().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Creates the map, returning an error if the capacity is too large.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::CapacityOverflow`] if the bucket pointer array for the
+    /// capacity would exceed the maximum allocation size.
+    ///
+    /// [`Error::CapacityOverflow`]: ../enum.Error.html#variant.CapacityOverflow
+    pub fn try_build(self) -> Result<HashMap<K, V, S>, Error> {
+        let mut map = HashMap::try_with_capacity_and_hasher(self.capacity, self.build_hasher)?;
+        map.deterministic = self.deterministic;
+        map.panic_policy = self.panic_policy;
+        map.tombstone_policy = self.tombstone_policy;
+        map.flush_after_bulk_removals = self.flush_after_bulk_removals;
+        map.memory = self.memory;
+        map.loading = self.loading;
+        map.default_value = self.default_value;
+        map.coalescing = self.coalescing;
+        map.sampler = self.sampler;
+
+        if self.sequence_numbers {
+            map.sequence = Some(AtomicU64::new(0));
+        }
+
+        Ok(map)
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hash, Hasher},
+    sync::Mutex,
+};
+
+/// A key that carries a precomputed hash of itself.
+///
+/// The [`Hash`] implementation of a `WithCachedHash` only writes the cached
+/// hash, so a hash map hashes a single `u64` instead of the key whenever it
+/// needs the hash of an entry, notably when it moves the entries to a larger
+/// bucket pointer array. This makes resizing much cheaper for keys that are
+/// expensive to hash, such as long strings. The key itself is only hashed once,
+/// when the `WithCachedHash` is created.
+///
+/// Lookups must use a `WithCachedHash` too, as the hash of a key differs from
+/// that of its `WithCachedHash`. Two of them are equal if their hashes and
+/// their keys are equal, so the key is only compared when the hashes match.
+///
+/// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+///
+/// # Examples
+///
+/// ```rust
+/// use moka_cht::{map::WithCachedHash, HashMap};
+///
+/// let map = HashMap::new();
+/// let url = "https://example.com/a/very/long/path".to_string();
+///
+/// map.insert(WithCachedHash::new(url.clone()), 1);
+///
+/// assert_eq!(map.get(&WithCachedHash::new(url)), Some(1));
+/// ```
+#[derive(Clone, Debug)]
+pub struct WithCachedHash<K> {
+    hash: u64,
+    key: K,
+}
+
+static PROCESS_BUILD_HASHER: Mutex<Option<RandomState>> = Mutex::new(None);
+
+thread_local! {
+    // a copy of the process-wide hasher, so that it is only locked once per
+    // thread
+    static BUILD_HASHER: RandomState = PROCESS_BUILD_HASHER
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get_or_insert_with(RandomState::new)
+        .clone();
+}
+
+impl<K: Hash> WithCachedHash<K> {
+    /// Creates a `WithCachedHash` by hashing `key` with a hasher that is seeded
+    /// randomly once per process.
+    ///
+    /// The hashes are the same for equal keys within a process, but differ
+    /// between processes, which protects maps with untrusted keys from hash
+    /// collision attacks.
+    pub fn new(key: K) -> Self {
+        BUILD_HASHER.with(|build_hasher| Self::with_hasher(key, build_hasher))
+    }
+
+    /// Creates a `WithCachedHash` by hashing `key` with `build_hasher`.
+    ///
+    /// The keys of a map must all be hashed with the same hasher, or with
+    /// hashers that compute the same hashes.
+    pub fn with_hasher<S: BuildHasher>(key: K, build_hasher: &S) -> Self {
+        let mut hasher = build_hasher.build_hasher();
+        key.hash(&mut hasher);
+
+        Self {
+            hash: hasher.finish(),
+            key,
+        }
+    }
+}
+
+impl<K> WithCachedHash<K> {
+    /// Returns a reference to the key.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Returns the cached hash of the key.
+    pub fn cached_hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// Returns the key, discarding the cached hash.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+}
+
+impl<K> Hash for WithCachedHash<K> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash);
+    }
+}
+
+impl<K: PartialEq> PartialEq for WithCachedHash<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash && self.key == other.key
+    }
+}
+
+impl<K: Eq> Eq for WithCachedHash<K> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{HashMap, SegmentedHashMap};
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // counts the times the keys are hashed
+    static NUM_HASHES: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug, PartialEq, Eq)]
+    struct CountedKey(usize);
+
+    impl Hash for CountedKey {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            NUM_HASHES.fetch_add(1, Ordering::Relaxed);
+            self.0.hash(state);
+        }
+    }
+
+    #[test]
+    fn resizes_do_not_hash_keys() {
+        const NUM_VALUES: usize = 4096;
+
+        let map = HashMap::new();
+        let segmented = SegmentedHashMap::with_num_segments(4);
+
+        for i in 0..NUM_VALUES {
+            let key = WithCachedHash::new(CountedKey(i));
+            assert_eq!(key.cached_hash(), WithCachedHash::new(CountedKey(i)).hash);
+
+            map.insert(WithCachedHash::new(CountedKey(i)), i);
+            segmented.insert(key, i);
+        }
+
+        // every key was hashed once for each `WithCachedHash` created above,
+        // and never by the maps while they grew
+        assert_eq!(NUM_HASHES.load(Ordering::Relaxed), NUM_VALUES * 3);
+        assert!(map.capacity() >= NUM_VALUES);
+
+        for i in 0..NUM_VALUES {
+            let key = WithCachedHash::new(CountedKey(i));
+
+            assert_eq!(map.get(&key), Some(i));
+            assert_eq!(segmented.get(&key), Some(i));
+        }
+
+        let key = WithCachedHash::new(CountedKey(NUM_VALUES));
+        assert_eq!(map.get(&key), None);
+        assert_eq!(key.into_key(), CountedKey(NUM_VALUES));
+    }
+
+    #[test]
+    fn equality() {
+        let build_hasher = RandomState::new();
+
+        let a = WithCachedHash::with_hasher("foo", &build_hasher);
+        let b = WithCachedHash::with_hasher("foo", &build_hasher);
+        let c = WithCachedHash::with_hasher("bar", &build_hasher);
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(a.cached_hash(), b.cached_hash());
+        assert_eq!(*a.key(), "foo");
+    }
+}
@@ -0,0 +1,297 @@
+use super::scrub::Scrub;
+
+use std::{
+    collections::{HashMap as StdHashMap, VecDeque},
+    hash::Hash,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+// the writes are spread over shards by the hashes of their keys, so that
+// writes to different keys rarely contend for the same lock.
+const NUM_SHARDS: usize = 64;
+
+/// The coalescing windows of a map.
+///
+/// The first coalesced write to a key is published right away and opens a
+/// window for the key. The writes to the key while the window is open are kept
+/// pending, each replacing the previous one, which is dropped without ever
+/// being published. When the window closes, the pending value, if any, is
+/// published and opens a new window, and otherwise the window is forgotten.
+///
+/// Windows are closed by the coalesced writes to the keys of the same shard
+/// and by `flush`, so they may stay open past their deadline.
+pub(crate) struct Coalescing<K, V> {
+    window: Duration,
+    shards: Box<[Mutex<Shard<K, V>>]>,
+}
+
+struct Shard<K, V> {
+    windows: StdHashMap<K, Window<V>>,
+    // the deadlines are taken under the lock, so they are in ascending order.
+    // a deadline is stale if the window of its key was reopened since.
+    deadlines: VecDeque<(Instant, K)>,
+}
+
+struct Window<V> {
+    deadline: Instant,
+    pending: Option<V>,
+}
+
+impl<K, V> Coalescing<K, V> {
+    pub(crate) fn new(window: Duration) -> Self {
+        Self {
+            window,
+            shards: (0..NUM_SHARDS)
+                .map(|_| {
+                    Mutex::new(Shard {
+                        windows: StdHashMap::new(),
+                        deadlines: VecDeque::new(),
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    pub(crate) fn window(&self) -> Duration {
+        self.window
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> Coalescing<K, V> {
+    /// Inserts `value` with `insert` if the key has no open window, and keeps
+    /// it pending otherwise. `replace` publishes the pending values of the
+    /// windows that close.
+    pub(crate) fn insert<I: FnOnce(K, V), R: FnMut(K, V)>(
+        &self,
+        hash: u64,
+        key: K,
+        value: V,
+        insert: I,
+        replace: R,
+    ) {
+        let now = Instant::now();
+        let mut shard = self.lock(hash);
+        shard.close_windows(now, self.window, replace);
+
+        match shard.windows.get_mut(&key) {
+            Some(window) => window.pending = Some(value),
+            None => {
+                insert(key.clone(), value);
+                shard.open_window(key, now + self.window);
+            }
+        }
+    }
+
+    /// Modifies the value of the key with `modify` if the key has no open
+    /// window, and otherwise keeps the result of `on_modify` pending, invoking
+    /// it with the pending value or else the value that `compute` passes it.
+    /// Returns `false` if the key has no value.
+    pub(crate) fn modify<F, M, C, R>(
+        &self,
+        hash: u64,
+        key: K,
+        mut on_modify: F,
+        modify: M,
+        compute: C,
+        replace: R,
+    ) -> bool
+    where
+        F: FnMut(&K, &V) -> V,
+        M: FnOnce(K, &mut F) -> bool,
+        C: FnOnce(&K, &mut F) -> Option<V>,
+        R: FnMut(K, V),
+    {
+        let now = Instant::now();
+        let mut shard = self.lock(hash);
+        shard.close_windows(now, self.window, replace);
+
+        match shard.windows.get_mut(&key) {
+            Some(window) => {
+                let value = match &window.pending {
+                    Some(pending) => on_modify(&key, pending),
+                    None => match compute(&key, &mut on_modify) {
+                        Some(value) => value,
+                        None => return false,
+                    },
+                };
+                window.pending = Some(value);
+
+                true
+            }
+            None => {
+                let modified = modify(key.clone(), &mut on_modify);
+
+                if modified {
+                    shard.open_window(key, now + self.window);
+                }
+
+                modified
+            }
+        }
+    }
+
+    /// Publishes the pending values of all windows with `replace` and closes
+    /// the windows.
+    pub(crate) fn flush<R: FnMut(K, V)>(&self, mut replace: R) {
+        for shard in self.shards.iter() {
+            let mut shard = shard.lock().unwrap_or_else(|e| e.into_inner());
+            shard.deadlines.clear();
+
+            for (key, window) in shard.windows.drain() {
+                if let Some(value) = window.pending {
+                    replace(key, value);
+                }
+            }
+        }
+    }
+
+    fn lock(&self, hash: u64) -> MutexGuard<'_, Shard<K, V>> {
+        self.shards[hash as usize % NUM_SHARDS]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<K: Hash + Eq, V> Coalescing<K, V> {
+    /// Closes the windows of the keys for which `predicate` returns `true`
+    /// without publishing their pending values, which are scrubbed along
+    /// with the keys before they are dropped.
+    pub(crate) fn discard<P: FnMut(&K) -> bool>(
+        &mut self,
+        mut predicate: P,
+        scrub: &mut impl Scrub<K, V>,
+    ) {
+        for shard in self.shards.iter_mut() {
+            let shard = shard.get_mut().unwrap_or_else(|e| e.into_inner());
+
+            shard.windows = shard
+                .windows
+                .drain()
+                .filter_map(|(mut key, mut window)| {
+                    if predicate(&key) {
+                        scrub.scrub_key(&mut key);
+
+                        if let Some(value) = &mut window.pending {
+                            scrub.scrub_value(value);
+                        }
+
+                        None
+                    } else {
+                        Some((key, window))
+                    }
+                })
+                .collect();
+
+            shard.deadlines.retain_mut(|(_, key)| {
+                let discarded = predicate(key);
+
+                if discarded {
+                    scrub.scrub_key(key);
+                }
+
+                !discarded
+            });
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> Shard<K, V> {
+    fn open_window(&mut self, key: K, deadline: Instant) {
+        self.deadlines.push_back((deadline, key.clone()));
+        self.windows.insert(
+            key,
+            Window {
+                deadline,
+                pending: None,
+            },
+        );
+    }
+
+    fn close_windows<R: FnMut(K, V)>(&mut self, now: Instant, window: Duration, mut replace: R) {
+        while let Some((deadline, _)) = self.deadlines.front() {
+            if *deadline > now {
+                break;
+            }
+
+            let (deadline, key) = self.deadlines.pop_front().unwrap();
+
+            let pending = match self.windows.get_mut(&key) {
+                Some(window) if window.deadline == deadline => window.pending.take(),
+                _ => continue,
+            };
+
+            match pending {
+                Some(value) => {
+                    replace(key.clone(), value);
+
+                    // the published value opens a new window, so that a key
+                    // is published at most once per window.
+                    let deadline = now + window;
+                    self.windows.get_mut(&key).unwrap().deadline = deadline;
+                    self.deadlines.push_back((deadline, key));
+                }
+                None => {
+                    self.windows.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{cell::RefCell, thread};
+
+    #[test]
+    fn windows() {
+        let coalescing = Coalescing::new(Duration::from_millis(50));
+        let published = RefCell::new(Vec::new());
+        let publish = |k: u32, v: u32| published.borrow_mut().push((k, v));
+
+        for i in 0..10 {
+            coalescing.insert(0, 0, i, publish, publish);
+        }
+
+        // the first write opens the window and the others are pending
+        assert_eq!(*published.borrow(), [(0, 0)]);
+
+        thread::sleep(Duration::from_millis(60));
+
+        // the window closes on the next write to the shard
+        coalescing.insert(0, 1, 0, publish, publish);
+        assert_eq!(*published.borrow(), [(0, 0), (0, 9), (1, 0)]);
+
+        // the window of the published value is open, so the modified value
+        // is computed from the value in the map and kept pending
+        let modified = coalescing.modify(
+            0,
+            0,
+            |_, v| v + 1,
+            |_, _| unreachable!(),
+            |k, on_modify| Some(on_modify(k, &9)),
+            publish,
+        );
+        assert!(modified);
+        assert_eq!(published.borrow().len(), 3);
+
+        coalescing.flush(publish);
+        assert_eq!(*published.borrow(), [(0, 0), (0, 9), (1, 0), (0, 10)]);
+
+        // the windows are closed, so the writes are published right away
+        let modified = coalescing.modify(
+            0,
+            2,
+            |_, v| v + 1,
+            |_, _| false,
+            |_, _| unreachable!(),
+            publish,
+        );
+        assert!(!modified);
+
+        coalescing.insert(0, 0, 20, publish, publish);
+        assert_eq!(published.borrow().last(), Some(&(0, 20)));
+    }
+}
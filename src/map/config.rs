@@ -0,0 +1,115 @@
+use super::{PanicPolicy, TombstonePolicy};
+
+use std::time::Duration;
+
+/// The configuration of a hash map, as returned by the `config` methods of the
+/// maps.
+///
+/// It records how a map was constructed, which is otherwise lost once the
+/// builder is consumed, so that it can be included in diagnostics, such as
+/// support bundles. With the `serde` feature, it implements `Serialize` and
+/// `Deserialize`. Two configurations can be compared with [`diff`].
+///
+/// [`diff`]: #method.diff
+///
+/// # Examples
+///
+/// ```rust
+/// use moka_cht::{map::TombstonePolicy, HashMap};
+///
+/// let map: HashMap<i32, i32> = HashMap::builder()
+///     .tombstone_policy(TombstonePolicy::KeepUntilRehash)
+///     .flush_after_bulk_removals()
+///     .build();
+///
+/// let config = map.config();
+/// assert_eq!(config.num_segments, 1);
+/// assert!(config.flush_after_bulk_removals);
+///
+/// let default_config = HashMap::<i32, i32>::new().config();
+/// assert_eq!(
+///     config.diff(&default_config),
+///     ["tombstone_policy", "flush_after_bulk_removals"]
+/// );
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MapConfig {
+    /// The number of segments, which is 1 for a `HashMap`.
+    pub num_segments: usize,
+    /// The number of bits that the hash of a key is shifted right by to select
+    /// its segment, which is 64 for a `HashMap`.
+    pub segment_shift: u32,
+    /// The salt that selects the segments of the keys, which is 0 for a
+    /// `HashMap`.
+    pub segment_salt: u64,
+    /// The name of the type of the hasher, as returned by
+    /// [`std::any::type_name`].
+    ///
+    /// [`std::any::type_name`]: https://doc.rust-lang.org/std/any/fn.type_name.html
+    pub hasher: String,
+    /// Whether the entries are iterated in the order of their hashes.
+    pub deterministic: bool,
+    /// The policy for panics in the middle of an operation.
+    pub panic_policy: PanicPolicy,
+    /// The policy for the tombstones left behind by removals, which governs
+    /// when the bucket pointer arrays are compacted besides growing when
+    /// half of their slots are taken and shrinking during housekeeping.
+    pub tombstone_policy: TombstonePolicy,
+    /// The limit of the memory budget of the map, if it has one.
+    pub memory_budget_limit: Option<usize>,
+    /// Whether the map has a loader.
+    pub loader: bool,
+    /// Whether the map has a default value factory.
+    pub default_value: bool,
+    /// The window within which writes to the same key are coalesced, if any.
+    pub coalesce_window: Option<Duration>,
+    /// Whether the map assigns sequence numbers to its mutations, which
+    /// requires the `sequence-numbers` feature.
+    pub sequence_numbers: bool,
+    /// Whether the garbage is flushed after bulk removals.
+    pub flush_after_bulk_removals: bool,
+    /// About one in how many operations are sampled, if any.
+    pub sample_one_in: Option<u64>,
+    /// Whether the crate was built with the `instrument` feature, which
+    /// records latency histograms and operation reports.
+    pub instrument: bool,
+}
+
+impl MapConfig {
+    /// Returns the names of the fields whose values differ between `self` and
+    /// `other`, in the order of their declaration.
+    pub fn diff(&self, other: &MapConfig) -> Vec<&'static str> {
+        let mut fields = Vec::new();
+
+        macro_rules! compare {
+            ($($field:ident),*) => {
+                $(
+                    if self.$field != other.$field {
+                        fields.push(stringify!($field));
+                    }
+                )*
+            };
+        }
+
+        compare!(
+            num_segments,
+            segment_shift,
+            segment_salt,
+            hasher,
+            deterministic,
+            panic_policy,
+            tombstone_policy,
+            memory_budget_limit,
+            loader,
+            default_value,
+            coalesce_window,
+            sequence_numbers,
+            flush_after_bulk_removals,
+            sample_one_in,
+            instrument
+        );
+
+        fields
+    }
+}
@@ -0,0 +1,15 @@
+/// What the `get_then_modify` methods of the hash maps do with an entry, as
+/// decided by their closure after seeing its value.
+///
+/// The decision is carried out at the slot where the entry was found, without
+/// probing for the key again. If another thread replaces the entry in the
+/// meantime, the closure is called again with the new value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Decision<V> {
+    /// Leaves the entry unchanged.
+    Keep,
+    /// Replaces the value of the entry.
+    Replace(V),
+    /// Removes the entry.
+    Remove,
+}
@@ -0,0 +1,48 @@
+/// A factory of the values of keys that are missing from a hash map, which
+/// turns the map into a map of accumulators.
+///
+/// A factory is attached to a map through the `default_value` methods of the
+/// builders, and is called by the `get_or_default` methods of the maps to
+/// create the value that they insert for a missing key, so that the call sites
+/// do not each pass a closure. Unlike a [`Loader`], a factory always has a
+/// value, and concurrent misses on the same key are not de-duplicated: each of
+/// them creates a value, and the one that is inserted first is kept.
+///
+/// Every closure of type `Fn(&K) -> V` is a factory.
+///
+/// The maps do not implement [`Index`], which would have to return a plain
+/// reference to a value that another thread may remove and reclaim at any
+/// time. Use `get_or_default`, or `get_and` to read a value in place.
+///
+/// [`Loader`]: ./trait.Loader.html
+/// [`Index`]: https://doc.rust-lang.org/std/ops/trait.Index.html
+///
+/// # Examples
+///
+/// ```rust
+/// use moka_cht::HashMap;
+///
+/// let word_lengths = HashMap::builder()
+///     .default_value(|word: &&str| word.len())
+///     .build();
+///
+/// assert_eq!(word_lengths.get_or_default("moka"), 4);
+/// assert_eq!(word_lengths.get("moka"), Some(4));
+///
+/// // the value of a key in the map is kept
+/// word_lengths.insert("cht", 0);
+/// assert_eq!(word_lengths.get_or_default("cht"), 0);
+/// ```
+pub trait DefaultValue<K, V> {
+    /// Creates the value to insert for `key`.
+    fn default_value(&self, key: &K) -> V;
+}
+
+impl<K, V, F: Fn(&K) -> V> DefaultValue<K, V> for F {
+    fn default_value(&self, key: &K) -> V {
+        self(key)
+    }
+}
+
+/// The factory of a map, as stored by the map.
+pub(crate) type BoxedDefaultValue<K, V> = Box<dyn DefaultValue<K, V> + Send + Sync>;
@@ -0,0 +1,119 @@
+use std::hash::{BuildHasher, Hasher};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// A [`BuildHasher`] that always creates hashers with the same fixed seed.
+///
+/// The hashes computed by a [`DeterministicHasher`] only depend on the bytes
+/// written to it, and integers are written the same way on every platform, so
+/// the hashes are the same across processes and targets. This makes the
+/// layout of a hash map, and the order of its iterators, reproducible, which is
+/// useful for golden-file tests. Maps using it are usually created with the
+/// `deterministic` methods of the builders.
+///
+/// The hashes are only guaranteed to stay the same across versions of Rust for
+/// keys whose [`Hash`] implementations write integers and byte slices
+/// themselves. How the implementations of the standard library encode other
+/// types is not specified: a `str`, for example, is currently written as its
+/// bytes followed by a `0xff` byte, and a slice is preceded by its length, but
+/// this may change in a future version of Rust.
+///
+/// The hashes are predictable, so this hasher offers no protection against
+/// hash collision attacks, and should not be used with untrusted keys.
+///
+/// [`BuildHasher`]: https://doc.rust-lang.org/std/hash/trait.BuildHasher.html
+/// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+/// [`DeterministicHasher`]: ./struct.DeterministicHasher.html
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeterministicState;
+
+impl BuildHasher for DeterministicState {
+    type Hasher = DeterministicHasher;
+
+    fn build_hasher(&self) -> DeterministicHasher {
+        DeterministicHasher {
+            state: FNV_OFFSET_BASIS,
+        }
+    }
+}
+
+/// The hasher created by [`DeterministicState`].
+///
+/// It computes the 64-bit FNV-1a hash of the written bytes, followed by the
+/// finalizer of MurmurHash3 to spread the entropy to all bits. Integers are
+/// written in little-endian byte order, and `usize` and `isize` as 64-bit
+/// integers, so that the hashes do not depend on the platform.
+///
+/// [`DeterministicState`]: ./struct.DeterministicState.html
+#[derive(Clone, Debug)]
+pub struct DeterministicHasher {
+    state: u64,
+}
+
+impl Hasher for DeterministicHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.state ^= u64::from(byte);
+            self.state = self.state.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    // sign-extended to 64 bits, which the default implementation, writing the
+    // `usize` with the same bits, does not do on 32-bit targets
+    fn write_isize(&mut self, i: isize) {
+        self.write_u64(i as i64 as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        let mut hash = self.state;
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        hash ^= hash >> 33;
+
+        hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::hash::Hash;
+
+    fn hash<T: Hash + ?Sized>(value: &T) -> u64 {
+        crate::hash(&DeterministicState, value)
+    }
+
+    #[test]
+    fn stable_hashes() {
+        assert_eq!(hash(&0u64), 8_922_497_616_986_557_598);
+        assert_eq!(hash(&42usize), hash(&42u64));
+        assert_eq!(hash(&-1isize), hash(&-1i64));
+        assert_eq!(hash(&-1i32), hash(&u32::MAX));
+        assert_eq!(hash("foo"), 17_263_796_143_136_586_204);
+        assert_ne!(hash(&1u64), hash(&2u64));
+    }
+}
@@ -0,0 +1,131 @@
+//! The optional metadata of the entries of a map.
+//!
+//! Each piece of metadata is recorded in every bucket only if the Cargo
+//! feature that reads it is enabled. Otherwise its type is zero-sized, writing
+//! it does nothing, and reading it returns 0, so that the bucket code does not
+//! have to be gated on the features.
+
+#[cfg(any(feature = "access-times", feature = "sequence-numbers"))]
+use crate::ordering;
+
+#[cfg(any(feature = "access-times", feature = "sequence-numbers"))]
+use std::sync::atomic::AtomicU64;
+
+/// The generation of the map when the key of an entry was inserted.
+#[cfg(feature = "generations")]
+#[derive(Debug)]
+pub(crate) struct Generation(u64);
+
+#[cfg(not(feature = "generations"))]
+#[derive(Debug)]
+pub(crate) struct Generation;
+
+#[cfg(feature = "generations")]
+impl Generation {
+    pub(crate) fn new(generation: u64) -> Self {
+        Self(generation)
+    }
+
+    pub(crate) fn get(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(not(feature = "generations"))]
+impl Generation {
+    pub(crate) fn new(_generation: u64) -> Self {
+        Self
+    }
+
+    pub(crate) fn get(&self) -> u64 {
+        0
+    }
+}
+
+/// The tick of the access clock of the map when an entry was last inserted,
+/// modified, or looked up.
+#[cfg(feature = "access-times")]
+#[derive(Debug)]
+pub(crate) struct AccessTime(AtomicU64);
+
+#[cfg(not(feature = "access-times"))]
+#[derive(Debug)]
+pub(crate) struct AccessTime;
+
+#[cfg(feature = "access-times")]
+impl AccessTime {
+    pub(crate) fn new(access: u64) -> Self {
+        Self(AtomicU64::new(access))
+    }
+
+    pub(crate) fn get(&self) -> u64 {
+        self.0.load(ordering::RELAXED)
+    }
+
+    pub(crate) fn set_mut(&mut self, access: u64) {
+        *self.0.get_mut() = access;
+    }
+
+    pub(crate) fn touch(&self, access: u64) {
+        // avoid dirtying the cache line if the entry was already accessed
+        // during this tick
+        if self.0.load(ordering::RELAXED) != access {
+            self.0.store(access, ordering::RELAXED);
+        }
+    }
+}
+
+#[cfg(not(feature = "access-times"))]
+impl AccessTime {
+    pub(crate) fn new(_access: u64) -> Self {
+        Self
+    }
+
+    pub(crate) fn set_mut(&mut self, _access: u64) {}
+
+    pub(crate) fn touch(&self, _access: u64) {}
+}
+
+/// The sequence number of the mutation that published a bucket, or of the
+/// removal that made it a tombstone; 0 if the map does not assign them.
+#[cfg(feature = "sequence-numbers")]
+#[derive(Debug)]
+pub(crate) struct Sequence(AtomicU64);
+
+#[cfg(not(feature = "sequence-numbers"))]
+#[derive(Debug)]
+pub(crate) struct Sequence;
+
+#[cfg(feature = "sequence-numbers")]
+impl Sequence {
+    pub(crate) fn new(sequence: u64) -> Self {
+        Self(AtomicU64::new(sequence))
+    }
+
+    pub(crate) fn get(&self) -> u64 {
+        self.0.load(ordering::RELAXED)
+    }
+
+    pub(crate) fn set(&self, sequence: u64) {
+        self.0.store(sequence, ordering::RELAXED);
+    }
+
+    pub(crate) fn set_mut(&mut self, sequence: u64) {
+        *self.0.get_mut() = sequence;
+    }
+}
+
+#[cfg(not(feature = "sequence-numbers"))]
+impl Sequence {
+    pub(crate) fn new(_sequence: u64) -> Self {
+        Self
+    }
+
+    pub(crate) fn get(&self) -> u64 {
+        0
+    }
+
+    pub(crate) fn set(&self, _sequence: u64) {}
+
+    pub(crate) fn set_mut(&mut self, _sequence: u64) {}
+}
@@ -0,0 +1,78 @@
+use super::ScanCursor;
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    vec,
+};
+
+use futures_core::Stream;
+
+/// A stream of clones of the entries of a hash map, which are scanned in
+/// batches by the `entry_stream` methods of the maps.
+///
+/// `scan` clones the entries of the batch at a cursor into a vector, and
+/// returns the cursor moved past them, without holding an epoch guard past
+/// the call.
+pub(crate) struct EntryStream<K, V, F> {
+    scan: F,
+    cursor: ScanCursor,
+    batch_size: usize,
+    batch: vec::IntoIter<(K, V)>,
+}
+
+impl<K, V, F: FnMut(ScanCursor, usize, &mut Vec<(K, V)>) -> ScanCursor> EntryStream<K, V, F> {
+    pub(crate) fn new(batch_size: usize, scan: F) -> Self {
+        assert!(
+            batch_size > 0,
+            "the batch size of an entry stream must not be 0"
+        );
+
+        Self {
+            scan,
+            cursor: ScanCursor::new(),
+            batch_size,
+            batch: Vec::new().into_iter(),
+        }
+    }
+}
+
+// The stream is never pinned structurally.
+impl<K, V, F> Unpin for EntryStream<K, V, F> {}
+
+impl<K, V, F: FnMut(ScanCursor, usize, &mut Vec<(K, V)>) -> ScanCursor> Stream
+    for EntryStream<K, V, F>
+{
+    type Item = (K, V);
+
+    fn poll_next(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            if let Some(entry) = this.batch.next() {
+                return Poll::Ready(Some(entry));
+            }
+
+            if this.cursor.is_done() {
+                return Poll::Ready(None);
+            }
+
+            let mut batch = Vec::with_capacity(this.batch_size);
+            this.cursor = (this.scan)(this.cursor, this.batch_size, &mut batch);
+            this.batch = batch.into_iter();
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.batch.len();
+
+        (
+            len,
+            if self.cursor.is_done() {
+                Some(len)
+            } else {
+                None
+            },
+        )
+    }
+}
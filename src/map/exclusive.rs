@@ -0,0 +1,575 @@
+use super::{
+    bucket::{self, Bucket, BucketArray, Stamp},
+    bucket_array_ref::BucketArrayRef,
+    memory_budget::MemoryCharge,
+    scrub::Scrub,
+    DefaultHashBuilder, HashMap, TombstonePolicy,
+};
+use crate::{metrics, ordering};
+
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+    mem, vec,
+};
+
+use crossbeam_epoch::{Atomic, Guard, Shared};
+
+/// A view of a [`HashMap`] that is borrowed exclusively, which inserts and
+/// removes entries without synchronizing with other threads.
+///
+/// An `Exclusive` view is created by [`HashMap::exclusive`]. As long as it
+/// exists, no other thread can access the map, so insertions write the bucket
+/// pointer array with plain loads and stores instead of compare-and-swap
+/// loops, and replace the values of existing entries in place. Removals do
+/// not leave tombstones behind: the entries that follow a removed one are
+/// shifted back into its slot, so that building up or tearing down a map
+/// before it is shared, or after it is no longer, does not degrade its probe
+/// lengths or require housekeeping afterwards. If the tombstone policy of the
+/// map is [`BackwardShiftInExclusiveMode`], creating the view also reclaims
+/// the tombstones left by the removals that were performed while the map was
+/// shared, in the same way.
+///
+/// The entries written through the view are stamped with the generation and
+/// the access clock of the map, like the ones written through the map itself.
+///
+/// [`HashMap`]: struct.HashMap.html
+/// [`HashMap::exclusive`]: struct.HashMap.html#method.exclusive
+/// [`BackwardShiftInExclusiveMode`]: enum.TombstonePolicy.html#variant.BackwardShiftInExclusiveMode
+///
+/// # Examples
+///
+/// ```rust
+/// use moka_cht::HashMap;
+///
+/// let mut map = HashMap::new();
+///
+/// let mut exclusive = map.exclusive();
+///
+/// for i in 0..1024 {
+///     exclusive.insert(i, i * 2);
+/// }
+///
+/// for i in 0..512 {
+///     assert_eq!(exclusive.remove(&i), Some(i * 2));
+/// }
+///
+/// assert_eq!(exclusive.get(&768), Some(&1536));
+/// assert_eq!(map.len(), 512);
+/// ```
+pub struct Exclusive<'a, K, V, S = DefaultHashBuilder> {
+    map: &'a mut HashMap<K, V, S>,
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> Exclusive<'a, K, V, S> {
+    pub(crate) fn new(map: &'a mut HashMap<K, V, S>) -> Self {
+        settle(map.bucket_array_ref());
+
+        if map.tombstone_policy == TombstonePolicy::BackwardShiftInExclusiveMode {
+            purge_tombstones(&mut map.bucket_array, &map.build_hasher);
+        }
+
+        Self { map }
+    }
+}
+
+impl<'a, K, V, S> Exclusive<'a, K, V, S> {
+    /// Returns the number of elements in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the map contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> Exclusive<'a, K, V, S> {
+    /// Returns a reference to the value corresponding to the key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn get<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        let hash = bucket::hash(&self.map.build_hasher, key);
+        let access = self.map.access_clock.load(ordering::RELAXED);
+
+        get(&self.map.bucket_array, hash, key, access).map(|b| unsafe { b.value() })
+    }
+
+    /// Inserts a key-value pair into the map, returning the value previously
+    /// corresponding to the key.
+    ///
+    /// If the key was already present, its value is replaced in place and the
+    /// key passed in is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bucket pointer array would need to grow past the maximum
+    /// allocation size.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let map = &mut *self.map;
+        let hash = bucket::hash(&map.build_hasher, &key);
+        let stamp = Stamp {
+            generation: *map.generation.get_mut(),
+            access: *map.access_clock.get_mut(),
+            sequence: map.sequence.as_ref(),
+        };
+        let len = map.len.get_mut();
+
+        charge_insert(&map.bucket_array, map.memory.as_ref(), hash, &key, &value);
+
+        let previous = insert(
+            &mut map.bucket_array,
+            &map.build_hasher,
+            *len,
+            hash,
+            key,
+            value,
+            stamp,
+        );
+
+        if previous.is_none() {
+            *len += 1;
+        }
+
+        previous
+    }
+
+    /// Removes a key from the map, returning the value corresponding to the
+    /// key if the key was previously in the map.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn remove<Q: Hash + Eq + ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        self.remove_entry(key).map(|(_, v)| v)
+    }
+
+    /// Removes a key from the map, returning the stored key and value if the
+    /// key was previously in the map.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn remove_entry<Q: Hash + Eq + ?Sized>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+    {
+        let map = &mut *self.map;
+        let hash = bucket::hash(&map.build_hasher, key);
+
+        let removed = remove_entry(&mut map.bucket_array, &map.build_hasher, hash, key);
+
+        if let Some((k, v)) = &removed {
+            *map.len.get_mut() -= 1;
+
+            if let Some(memory) = &map.memory {
+                memory.release(k, v);
+            }
+        }
+
+        removed
+    }
+}
+
+/// An iterator over the entries of a mutably borrowed hash map, yielding a
+/// reference to the key and a mutable reference to the value of each of them.
+///
+/// This `struct` is created by the `iter_mut` methods of the hash maps.
+pub struct IterMut<'a, K, V> {
+    buckets: vec::IntoIter<&'a mut Bucket<K, V>>,
+}
+
+impl<'a, K, V> IterMut<'a, K, V> {
+    /// # Safety
+    ///
+    /// `buckets` must not contain tombstones.
+    pub(crate) unsafe fn new(buckets: Vec<&'a mut Bucket<K, V>>) -> Self {
+        Self {
+            buckets: buckets.into_iter(),
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Bucket { key, value, .. } = self.buckets.next()?;
+
+        Some((key, unsafe { value.get_mut() }))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.buckets.size_hint()
+    }
+}
+
+/// An iterator that moves the entries out of a hash map, yielding the key and
+/// the value of each of them.
+///
+/// This `struct` is created by the `into_iter` methods of the hash maps,
+/// provided by the [`IntoIterator`] trait.
+///
+/// [`IntoIterator`]: https://doc.rust-lang.org/std/iter/trait.IntoIterator.html
+pub struct IntoIter<K, V> {
+    entries: vec::IntoIter<(K, V)>,
+}
+
+impl<K, V> IntoIter<K, V> {
+    pub(crate) fn new(entries: Vec<(K, V)>) -> Self {
+        Self {
+            entries: entries.into_iter(),
+        }
+    }
+}
+
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+/// Finishes the resize of a bucket pointer array in progress, if any, so that
+/// the current array holds every entry and has no newer arrays.
+pub(crate) fn settle<K: Hash + Eq, V, S: BuildHasher>(
+    bucket_array_ref: BucketArrayRef<'_, K, V, S>,
+) {
+    {
+        let guard = &crossbeam_epoch::pin();
+
+        match unsafe { ordering::load_consume(bucket_array_ref.bucket_array, guard).as_ref() } {
+            Some(array_ref) if !array_ref.next.load(ordering::RELAXED, guard).is_null() => {
+                bucket_array_ref.live_buckets(guard);
+            }
+            _ => return,
+        }
+    }
+
+    // a budgeted read may have allocated a newer array without moving any
+    // bucket into it, which leaves it behind after the resize is finished.
+    let guard = unsafe { crossbeam_epoch::unprotected() };
+    let array_ref = unsafe {
+        bucket_array_ref
+            .bucket_array
+            .load(ordering::RELAXED, guard)
+            .deref()
+    };
+    let mut next_ptr = array_ref
+        .next
+        .swap(Shared::null(), ordering::RELAXED, guard);
+
+    while let Some(next_ref) = unsafe { next_ptr.as_ref() } {
+        let next_next_ptr = next_ref.next.load(ordering::RELAXED, guard);
+        unsafe { bucket::defer_acquire_destroy(guard, next_ptr) };
+        next_ptr = next_next_ptr;
+    }
+}
+
+/// Looks up a key in a settled bucket pointer array.
+pub(crate) fn get<'a, Q: Eq + ?Sized, K: Borrow<Q> + Eq, V>(
+    bucket_array: &'a Atomic<BucketArray<K, V>>,
+    hash: u64,
+    key: &Q,
+    access: u64,
+) -> Option<&'a Bucket<K, V>> {
+    let guard: &'a Guard = unsafe { crossbeam_epoch::unprotected() };
+    let array_ref = unsafe { bucket_array.load(ordering::RELAXED, guard).as_ref() }?;
+
+    // buckets are never relocated while the map is borrowed exclusively
+    let result = array_ref
+        .get(guard, hash, key)
+        .ok()
+        .and_then(|p| unsafe { p.as_ref() });
+
+    metrics::count_lookup(result.is_some());
+
+    if let Some(b) = result {
+        b.touch(access);
+    }
+
+    result
+}
+
+/// Looks up a key in a settled bucket pointer array, returning its bucket for
+/// modification.
+pub(crate) fn get_mut<'a, Q: Eq + ?Sized, K: Borrow<Q> + Eq, V>(
+    bucket_array: &'a mut Atomic<BucketArray<K, V>>,
+    hash: u64,
+    key: &Q,
+    access: u64,
+) -> Option<&'a mut Bucket<K, V>> {
+    let guard: &'a Guard = unsafe { crossbeam_epoch::unprotected() };
+    let array_ref = unsafe { bucket_array.load(ordering::RELAXED, guard).as_ref() }?;
+
+    let result = array_ref
+        .get(guard, hash, key)
+        .ok()
+        .filter(|p| !p.is_null())
+        .map(|mut p| unsafe { p.deref_mut() });
+
+    metrics::count_lookup(result.is_some());
+
+    if let Some(b) = &result {
+        b.touch(access);
+    }
+
+    result
+}
+
+/// Appends the live buckets of a settled bucket pointer array to `buckets`.
+pub(crate) fn live_buckets_mut<'a, K, V>(
+    bucket_array: &'a mut Atomic<BucketArray<K, V>>,
+    buckets: &mut Vec<&'a mut Bucket<K, V>>,
+) {
+    let guard: &'a Guard = unsafe { crossbeam_epoch::unprotected() };
+    let array_ref = match unsafe { bucket_array.load(ordering::RELAXED, guard).as_ref() } {
+        Some(array_ref) => array_ref,
+        None => return,
+    };
+
+    for this_bucket in array_ref.buckets.iter() {
+        let mut this_bucket_ptr = this_bucket.load(ordering::RELAXED, guard);
+
+        if !this_bucket_ptr.is_null() && this_bucket_ptr.tag() & bucket::TOMBSTONE_TAG == 0 {
+            buckets.push(unsafe { this_bucket_ptr.deref_mut() });
+        }
+    }
+}
+
+/// Charges the weight of a key-value pair about to be inserted into a settled
+/// bucket pointer array to `memory`, releasing the weight of the entry it will
+/// replace, if any.
+pub(crate) fn charge_insert<K: Eq, V>(
+    bucket_array: &Atomic<BucketArray<K, V>>,
+    memory: Option<&MemoryCharge<K, V>>,
+    hash: u64,
+    key: &K,
+    value: &V,
+) {
+    let memory = match memory {
+        Some(memory) => memory,
+        None => return,
+    };
+
+    memory.charge(key, value, None);
+
+    let guard = unsafe { crossbeam_epoch::unprotected() };
+
+    if let Some(array_ref) = unsafe { bucket_array.load(ordering::RELAXED, guard).as_ref() } {
+        if let Some(b) = array_ref
+            .get(guard, hash, key)
+            .ok()
+            .and_then(|p| unsafe { p.as_ref() })
+        {
+            memory.release(&b.key, unsafe { b.value() });
+        }
+    }
+}
+
+/// Inserts a key-value pair into a settled bucket pointer array that holds
+/// `len` entries, growing it like concurrent insertions would.
+pub(crate) fn insert<K: Hash + Eq, V, S: BuildHasher>(
+    bucket_array: &mut Atomic<BucketArray<K, V>>,
+    build_hasher: &S,
+    len: usize,
+    hash: u64,
+    key: K,
+    value: V,
+    stamp: Stamp,
+) -> Option<V> {
+    metrics::count_insert();
+
+    let mut entry = (key, value);
+
+    loop {
+        let array_ref = array_mut(bucket_array);
+
+        if len <= array_ref.capacity() {
+            match array_ref.insert_exclusive(hash, entry.0, entry.1, stamp) {
+                Ok(previous) => return previous,
+                Err(returned) => entry = returned,
+            }
+        }
+
+        let next_array = array_ref.grow_exclusive(build_hasher);
+        let array = mem::replace(bucket_array, Atomic::new(next_array));
+        mem::drop(unsafe { array.into_owned() });
+    }
+}
+
+/// Removes a key from a settled bucket pointer array without leaving a
+/// tombstone.
+pub(crate) fn remove_entry<Q: Eq + ?Sized, K: Hash + Eq + Borrow<Q>, V, S: BuildHasher>(
+    bucket_array: &mut Atomic<BucketArray<K, V>>,
+    build_hasher: &S,
+    hash: u64,
+    key: &Q,
+) -> Option<(K, V)> {
+    let guard = unsafe { crossbeam_epoch::unprotected() };
+    let mut array_ptr = bucket_array.load(ordering::RELAXED, guard);
+
+    if array_ptr.is_null() {
+        return None;
+    }
+
+    unsafe { array_ptr.deref_mut() }.remove_exclusive(build_hasher, hash, key)
+}
+
+/// Frees the tombstones of a settled bucket pointer array in place.
+pub(crate) fn purge_tombstones<K: Hash + Eq, V, S: BuildHasher>(
+    bucket_array: &mut Atomic<BucketArray<K, V>>,
+    build_hasher: &S,
+) {
+    let guard = unsafe { crossbeam_epoch::unprotected() };
+    let mut array_ptr = bucket_array.load(ordering::RELAXED, guard);
+
+    if !array_ptr.is_null() {
+        unsafe { array_ptr.deref_mut() }.purge_tombstones_exclusive(build_hasher);
+    }
+}
+
+/// Frees the buckets of a settled bucket pointer array whose keys `predicate`
+/// returns `true` for, tombstones included, scrubbing their keys and values
+/// before they are dropped. Returns the number of entries removed, and
+/// releases their weights from `memory`.
+pub(crate) fn wipe<K: Hash + Eq, V, S: BuildHasher, P: FnMut(&K) -> bool>(
+    bucket_array: &mut Atomic<BucketArray<K, V>>,
+    build_hasher: &S,
+    memory: Option<&MemoryCharge<K, V>>,
+    mut predicate: P,
+    scrub: &mut impl Scrub<K, V>,
+) -> usize {
+    let guard = unsafe { crossbeam_epoch::unprotected() };
+    let mut array_ptr = bucket_array.load(ordering::RELAXED, guard);
+    let mut removed = 0;
+
+    if array_ptr.is_null() {
+        return 0;
+    }
+
+    unsafe { array_ptr.deref_mut() }.free_buckets_exclusive(build_hasher, |b, is_tombstone| {
+        if !predicate(&b.key) {
+            return false;
+        }
+
+        // the value of a tombstone has already been taken or dropped
+        if !is_tombstone {
+            let value = unsafe { b.value.get_mut() };
+
+            if let Some(memory) = memory {
+                memory.release(&b.key, value);
+            }
+
+            scrub.scrub_value(value);
+            removed += 1;
+        }
+
+        scrub.scrub_key(&mut b.key);
+
+        true
+    });
+
+    removed
+}
+
+/// Drops the entries of a settled bucket pointer array and frees it, leaving
+/// no array behind, and releases their weights from `memory`.
+pub(crate) fn clear<K, V>(
+    bucket_array: &mut Atomic<BucketArray<K, V>>,
+    memory: Option<&MemoryCharge<K, V>>,
+) {
+    let guard = unsafe { crossbeam_epoch::unprotected() };
+    let array = match unsafe { mem::replace(bucket_array, Atomic::null()).try_into_owned() } {
+        Some(array) => array,
+        None => return,
+    };
+
+    for this_bucket in array.buckets.iter() {
+        let this_bucket_ptr = this_bucket.load(ordering::RELAXED, guard);
+
+        if this_bucket_ptr.is_null() {
+            continue;
+        }
+
+        let mut this_bucket = unsafe { this_bucket_ptr.into_owned() };
+
+        if this_bucket_ptr.tag() & bucket::TOMBSTONE_TAG == 0 {
+            if let Some(memory) = memory {
+                memory.release(&this_bucket.key, unsafe { this_bucket.value() });
+            }
+
+            unsafe { this_bucket.value.drop_value() };
+        }
+    }
+}
+
+/// Moves the entries of a settled bucket pointer array into `entries` and
+/// frees it, leaving no array behind, and releases their weights from
+/// `memory`.
+pub(crate) fn take_entries<K, V>(
+    bucket_array: &mut Atomic<BucketArray<K, V>>,
+    memory: Option<&MemoryCharge<K, V>>,
+    entries: &mut Vec<(K, V)>,
+) {
+    let guard = unsafe { crossbeam_epoch::unprotected() };
+    let array = match unsafe { mem::replace(bucket_array, Atomic::null()).try_into_owned() } {
+        Some(array) => array,
+        None => return,
+    };
+
+    for this_bucket in array.buckets.iter() {
+        let this_bucket_ptr = this_bucket.load(ordering::RELAXED, guard);
+
+        if this_bucket_ptr.is_null() {
+            continue;
+        }
+
+        let this_bucket = unsafe { this_bucket_ptr.into_owned() };
+
+        // the value of a tombstone has already been taken or dropped
+        if this_bucket_ptr.tag() & bucket::TOMBSTONE_TAG != 0 {
+            continue;
+        }
+
+        if let Some(memory) = memory {
+            memory.release(&this_bucket.key, unsafe { this_bucket.value() });
+        }
+
+        let Bucket { key, value, .. } = *this_bucket.into_box();
+        entries.push((key, unsafe { value.take() }));
+    }
+}
+
+fn array_mut<K, V>(bucket_array: &mut Atomic<BucketArray<K, V>>) -> &mut BucketArray<K, V> {
+    let guard = unsafe { crossbeam_epoch::unprotected() };
+
+    if bucket_array.load(ordering::RELAXED, guard).is_null() {
+        *bucket_array = Atomic::new(BucketArray::with_length(0, bucket::DEFAULT_LENGTH));
+    }
+
+    unsafe { bucket_array.load(ordering::RELAXED, guard).deref_mut() }
+}
@@ -0,0 +1,301 @@
+use super::{
+    bucket::{self, Bucket},
+    bucket_array_ref::{BucketArrayRef, Walk},
+};
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    hash::{BuildHasher, Hash},
+    marker::PhantomData,
+    ops::Deref,
+    rc::Rc,
+    vec,
+};
+
+use crossbeam_epoch::Guard;
+
+/// A reference to a key-value pair in a hash map that keeps the current thread
+/// pinned to the epoch in which it was obtained.
+///
+/// While a `Ref` is alive, the entry it refers to will not be destroyed, even if
+/// it is concurrently removed or replaced by another thread. The referenced
+/// key-value pair is therefore not necessarily still present in the map.
+///
+/// Holding a `Ref` for a long time prevents the garbage collector from
+/// reclaiming memory for all hash maps in the process, so it should be dropped
+/// as soon as possible. Like the epoch guard it contains, a `Ref` cannot be sent
+/// to another thread.
+pub struct Ref<'a, K, V> {
+    _guard: RefGuard,
+    bucket: *const Bucket<K, V>,
+    _marker: PhantomData<&'a Bucket<K, V>>,
+}
+
+// the guard that keeps a `Ref` pinned, which the iterators share among the
+// references they yield, since they load the buckets under a single guard
+enum RefGuard {
+    Owned { _guard: Guard },
+    Shared { _guard: Rc<Guard> },
+}
+
+impl<'a, K, V> Ref<'a, K, V> {
+    /// # Safety
+    ///
+    /// `bucket` must point to a non-tombstone bucket that was loaded while the
+    /// current thread was pinned, and `guard` must have been pinned before that
+    /// load and continuously since.
+    pub(crate) unsafe fn new(guard: Guard, bucket: *const Bucket<K, V>) -> Self {
+        Self {
+            _guard: RefGuard::Owned { _guard: guard },
+            bucket,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Like [`new`], but keeps `guard` pinned along with the other references
+    /// that share it.
+    ///
+    /// # Safety
+    ///
+    /// `bucket` must point to a non-tombstone bucket that was loaded while
+    /// `guard` was pinned.
+    ///
+    /// [`new`]: #method.new
+    unsafe fn with_shared_guard(guard: &Rc<Guard>, bucket: *const Bucket<K, V>) -> Self {
+        Self {
+            _guard: RefGuard::Shared {
+                _guard: Rc::clone(guard),
+            },
+            bucket,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the key of the entry.
+    pub fn key(&self) -> &K {
+        unsafe { &(*self.bucket).key }
+    }
+
+    /// Returns a reference to the value of the entry.
+    pub fn value(&self) -> &V {
+        unsafe { (*self.bucket).value() }
+    }
+
+    /// Returns references to both the key and the value of the entry.
+    pub fn pair(&self) -> (&K, &V) {
+        (self.key(), self.value())
+    }
+}
+
+impl<'a, K, V> Deref for Ref<'a, K, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        self.value()
+    }
+}
+
+impl<'a, K: Debug, V: Debug> Debug for Ref<'a, K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ref")
+            .field("key", self.key())
+            .field("value", self.value())
+            .finish()
+    }
+}
+
+// the number of buckets ahead of an iterator whose cache lines are prefetched
+const PREFETCH_DISTANCE: usize = 8;
+
+/// An iterator over the entries of a hash map, yielding a [`Ref`] to each of
+/// them.
+///
+/// This `struct` is created by methods such as [`HashMap::iter`] and
+/// [`HashMap::iter_upto_generation`]. It reflects the contents of the map at
+/// some point at or after its creation, and it is not affected by modifications
+/// made while it is being consumed.
+///
+/// [`Ref`]: ./struct.Ref.html
+/// [`HashMap::iter`]: ./struct.HashMap.html#method.iter
+/// [`HashMap::iter_upto_generation`]: ./struct.HashMap.html#method.iter_upto_generation
+pub struct Iter<'a, K, V> {
+    guard: Rc<Guard>,
+    buckets: vec::IntoIter<*const Bucket<K, V>>,
+    _marker: PhantomData<&'a Bucket<K, V>>,
+}
+
+impl<'a, K, V> Iter<'a, K, V> {
+    /// # Safety
+    ///
+    /// `buckets` must point to non-tombstone buckets that were loaded while
+    /// `guard` was pinned.
+    pub(crate) unsafe fn new(guard: Guard, buckets: Vec<*const Bucket<K, V>>) -> Self {
+        Self {
+            guard: Rc::new(guard),
+            buckets: buckets.into_iter(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = Ref<'a, K, V>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bucket = self.buckets.next()?;
+
+        // the buckets are scattered over the heap, so the ones a few entries
+        // ahead are loaded while this one is being used
+        if let Some(&ahead) = self.buckets.as_slice().get(PREFETCH_DISTANCE) {
+            bucket::prefetch(ahead);
+        }
+
+        Some(unsafe { Ref::with_shared_guard(&self.guard, bucket) })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.buckets.size_hint()
+    }
+}
+
+/// An iterator over the entries of a hash map in the order of the slots that
+/// hold them, yielding a [`Ref`] to each of them.
+///
+/// This `struct` is created by the `iter_unordered_fast` methods of the hash
+/// maps. Unlike [`Iter`], it does not collect the entries when it is created,
+/// but reads the bucket pointer arrays of the map in place as it is consumed,
+/// under an epoch guard that it holds until it is dropped. It is weakly
+/// consistent: every entry that is in the map while the iterator is consumed
+/// is yielded exactly once, even if the map is resized, and entries inserted
+/// or removed in the meantime may or may not be yielded.
+///
+/// [`Ref`]: ./struct.Ref.html
+/// [`Iter`]: ./struct.Iter.html
+pub struct UnorderedIter<'a, K, V, S> {
+    guard: Rc<Guard>,
+    bucket_array_refs: vec::IntoIter<BucketArrayRef<'a, K, V, S>>,
+    current: Option<BucketArrayRef<'a, K, V, S>>,
+    walk: Walk<K, V>,
+}
+
+impl<'a, K: Hash + Eq, V, S> UnorderedIter<'a, K, V, S> {
+    /// `bucket_array_refs` are walked one after the other.
+    pub(crate) fn new(bucket_array_refs: Vec<BucketArrayRef<'a, K, V, S>>) -> Self {
+        let mut bucket_array_refs = bucket_array_refs.into_iter();
+
+        Self {
+            guard: Rc::new(crossbeam_epoch::pin()),
+            current: bucket_array_refs.next(),
+            bucket_array_refs,
+            walk: Walk::new(),
+        }
+    }
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> Iterator for UnorderedIter<'a, K, V, S> {
+    type Item = Ref<'a, K, V>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let bucket_array_ref = self.current.as_ref()?;
+
+            if let Some(bucket) = unsafe { self.walk.next(bucket_array_ref, &self.guard) } {
+                return Some(unsafe { Ref::with_shared_guard(&self.guard, bucket) });
+            }
+
+            self.current = self.bucket_array_refs.next();
+            self.walk = Walk::new();
+        }
+    }
+}
+
+/// An iterator over the keys of a hash map, yielding a clone of each of them.
+///
+/// This `struct` is created by the `keys` methods of the hash maps. Like
+/// [`Iter`], it reflects the contents of the map at some point at or after its
+/// creation, and it is not affected by modifications made while it is being
+/// consumed.
+///
+/// [`Iter`]: ./struct.Iter.html
+pub struct Keys<'a, K, V> {
+    inner: Iter<'a, K, V>,
+}
+
+impl<'a, K, V> Keys<'a, K, V> {
+    pub(crate) fn new(inner: Iter<'a, K, V>) -> Self {
+        Self { inner }
+    }
+}
+
+impl<'a, K: Clone, V> Iterator for Keys<'a, K, V> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|r| r.key().clone())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// An iterator over references to the values of a hash map, which remain valid
+/// for as long as the epoch guard they were collected under is held.
+///
+/// This `struct` is created by the `values` methods of the hash maps. A value
+/// may have been concurrently removed or replaced since it was collected.
+pub struct Values<'g, K, V> {
+    buckets: vec::IntoIter<&'g Bucket<K, V>>,
+}
+
+impl<'g, K, V> Values<'g, K, V> {
+    /// `buckets` must be non-tombstone buckets.
+    pub(crate) fn new(buckets: Vec<&'g Bucket<K, V>>) -> Self {
+        Self {
+            buckets: buckets.into_iter(),
+        }
+    }
+}
+
+impl<'g, K, V> Iterator for Values<'g, K, V> {
+    type Item = &'g V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.buckets.next().map(|b| unsafe { b.value() })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.buckets.size_hint()
+    }
+}
+
+/// An iterator over the values of a hash map, yielding a clone of each of
+/// them.
+///
+/// This `struct` is created by the `values_cloned` methods of the hash maps.
+/// Like [`Iter`], it reflects the contents of the map at some point at or
+/// after its creation, and it is not affected by modifications made while it
+/// is being consumed.
+///
+/// [`Iter`]: ./struct.Iter.html
+pub struct ValuesCloned<'a, K, V> {
+    inner: Iter<'a, K, V>,
+}
+
+impl<'a, K, V> ValuesCloned<'a, K, V> {
+    pub(crate) fn new(inner: Iter<'a, K, V>) -> Self {
+        Self { inner }
+    }
+}
+
+impl<'a, K, V: Clone> Iterator for ValuesCloned<'a, K, V> {
+    type Item = V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|r| r.value().clone())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,86 @@
+use super::bucket::SlotHint;
+
+use crate::ordering;
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    sync::atomic::AtomicUsize,
+};
+
+/// A long-lived handle to an entry of a hash map, which can be resolved to the
+/// current key-value pair of the entry without probing.
+///
+/// A handle owns a clone of the key of the entry, along with its hash and the
+/// location of the slot that held the entry when the handle was last resolved.
+/// Unlike a [`Ref`], it does not pin the current thread, so it can be stored in
+/// other data structures, for example in the nodes of an eviction queue that
+/// point back at the entries of a map.
+///
+/// A handle stays valid across the migrations of entries to new bucket pointer
+/// arrays when the map is resized: resolving it after a migration looks the key
+/// up once and updates the cached location, so that later resolutions go
+/// straight to the slot again. Resolving a handle whose key has been removed
+/// returns `None`, and resolving it again after the key is reinserted returns
+/// the new entry.
+///
+/// A handle is created by the `entry_handle` methods of the hash maps, and
+/// must only be resolved by the `resolve` method of the map that created it,
+/// or of a map whose hasher produces the same hashes.
+///
+/// [`Ref`]: ./struct.Ref.html
+pub struct EntryHandle<K> {
+    key: K,
+    hash: u64,
+    epoch: AtomicUsize,
+    index: AtomicUsize,
+}
+
+impl<K> EntryHandle<K> {
+    pub(crate) fn new(key: K, hint: SlotHint) -> Self {
+        Self {
+            key,
+            hash: hint.hash,
+            epoch: AtomicUsize::new(hint.epoch),
+            index: AtomicUsize::new(hint.index),
+        }
+    }
+
+    /// Returns a reference to the key of the entry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub(crate) fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    // the epoch and the index are loaded separately, so they may come from
+    // different updates. such a hint is harmless, because the key is compared
+    // before the slot is used.
+    pub(crate) fn hint(&self) -> SlotHint {
+        SlotHint {
+            hash: self.hash,
+            epoch: self.epoch.load(ordering::RELAXED),
+            index: self.index.load(ordering::RELAXED),
+        }
+    }
+
+    pub(crate) fn update(&self, hint: SlotHint) {
+        self.epoch.store(hint.epoch, ordering::RELAXED);
+        self.index.store(hint.index, ordering::RELAXED);
+    }
+}
+
+impl<K: Clone> Clone for EntryHandle<K> {
+    fn clone(&self) -> Self {
+        Self::new(self.key.clone(), self.hint())
+    }
+}
+
+impl<K: Debug> Debug for EntryHandle<K> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EntryHandle")
+            .field("key", &self.key)
+            .finish()
+    }
+}
@@ -0,0 +1,132 @@
+use super::{DefaultHashBuilder, HashMap};
+
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+};
+
+/// A read-only view of the keys of a [`HashMap`], which behaves like a
+/// `HashSet`.
+///
+/// A `KeySetView` borrows the map it was created from and never copies its
+/// keys. It gives no access to the values, so it can be handed to code that
+/// only needs to know which keys are present.
+///
+/// A `KeySetView` is created by [`HashMap::key_set`].
+///
+/// [`HashMap`]: struct.HashMap.html
+/// [`HashMap::key_set`]: struct.HashMap.html#method.key_set
+///
+/// # Examples
+///
+/// ```rust
+/// use moka_cht::{map::KeySetView, HashMap};
+///
+/// fn is_known(ids: KeySetView<'_, u64, String>, id: u64) -> bool {
+///     ids.contains(&id)
+/// }
+///
+/// let map = HashMap::new();
+/// map.insert(42, "foo".to_string());
+///
+/// assert!(is_known(map.key_set(), 42));
+/// assert!(!is_known(map.key_set(), 43));
+/// ```
+pub struct KeySetView<'a, K, V, S = DefaultHashBuilder> {
+    map: &'a HashMap<K, V, S>,
+}
+
+impl<'a, K, V, S> KeySetView<'a, K, V, S> {
+    pub(crate) fn new(map: &'a HashMap<K, V, S>) -> Self {
+        Self { map }
+    }
+
+    /// Returns the number of keys in the set.
+    ///
+    /// See [`HashMap::len`] for the caveats of concurrent use.
+    ///
+    /// [`HashMap::len`]: struct.HashMap.html#method.len
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the set contains no keys.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> KeySetView<'a, K, V, S> {
+    /// Returns `true` if the set contains the key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[inline]
+    pub fn contains<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.map.get_key_value_and(key, |_, _| ()).is_some()
+    }
+
+    /// Returns a clone of the key in the set that is equal to the supplied
+    /// key.
+    #[inline]
+    pub fn get<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<K>
+    where
+        K: Borrow<Q> + Clone,
+    {
+        self.get_and(key, K::clone)
+    }
+
+    /// Returns the result of invoking a function with a reference to the key
+    /// in the set that is equal to the supplied key.
+    #[inline]
+    pub fn get_and<Q: Hash + Eq + ?Sized, F: FnOnce(&K) -> T, T>(
+        &self,
+        key: &Q,
+        with_key: F,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+    {
+        self.map.get_key_value_and(key, move |k, _| with_key(k))
+    }
+}
+
+impl<'a, K, V, S> Clone for KeySetView<'a, K, V, S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, K, V, S> Copy for KeySetView<'a, K, V, S> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contains_reflects_map() {
+        let map = HashMap::new();
+        let keys = map.key_set();
+
+        assert!(keys.is_empty());
+        assert!(!keys.contains("foo"));
+
+        map.insert("foo".to_string(), 5);
+        assert_eq!(keys.len(), 1);
+        assert!(keys.contains("foo"));
+        assert_eq!(keys.get("foo").as_deref(), Some("foo"));
+        assert_eq!(keys.get_and("foo", String::len), Some(3));
+
+        map.remove("foo");
+        assert!(keys.is_empty());
+        assert!(!keys.contains("foo"));
+        assert_eq!(keys.get("foo"), None);
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    collections::HashMap as StdHashMap,
+    hash::Hash,
+    sync::{Arc, Condvar, Mutex},
+};
+
+/// A source of the values of keys that are missing from a hash map, which
+/// turns the map into a read-through cache.
+///
+/// A loader is attached to a map through the `loader` methods of the
+/// builders, and is called by the `get_or_load` methods of the maps on a miss.
+/// Concurrent misses on the same key are de-duplicated: a single call to the
+/// loader is in flight for a key at a time, and the threads that miss the key
+/// meanwhile block until it returns and share its result.
+///
+/// Loading happens on the thread that missed the key, which blocks until the
+/// loader returns, so asynchronous sources have to be wrapped in a blocking
+/// call.
+///
+/// Every closure of type `Fn(&K) -> Option<V>` is a loader.
+///
+/// # Examples
+///
+/// ```rust
+/// use moka_cht::HashMap;
+///
+/// let map = HashMap::builder()
+///     .loader(|k: &u32| if *k < 10 { Some(k * 2) } else { None })
+///     .build();
+///
+/// assert_eq!(map.get_or_load(&4), Some(8));
+/// assert_eq!(map.get(&4), Some(8));
+/// assert_eq!(map.get_or_load(&20), None);
+/// assert_eq!(map.len(), 1);
+/// ```
+pub trait Loader<K, V> {
+    /// Loads the value of `key`, returning `None` if it has none, in which
+    /// case nothing is inserted into the map.
+    fn load(&self, key: &K) -> Option<V>;
+}
+
+impl<K, V, F: Fn(&K) -> Option<V>> Loader<K, V> for F {
+    fn load(&self, key: &K) -> Option<V> {
+        self(key)
+    }
+}
+
+/// The loader of a map together with the loads in flight.
+pub(crate) struct Loading<K, V> {
+    loader: Box<dyn Loader<K, V> + Send + Sync>,
+    flights: Mutex<StdHashMap<K, Arc<Flight<V>>>>,
+}
+
+struct Flight<V> {
+    state: Mutex<FlightState<V>>,
+    landed: Condvar,
+}
+
+enum FlightState<V> {
+    Loading,
+    Loaded(Option<V>),
+    // the loader panicked, and the waiting threads try again
+    Abandoned,
+}
+
+impl<K, V> Loading<K, V> {
+    pub(crate) fn new(loader: impl Loader<K, V> + Send + Sync + 'static) -> Self {
+        Self {
+            loader: Box::new(loader),
+            flights: Mutex::new(StdHashMap::new()),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Loading<K, V> {
+    /// Returns the value of `key` as found by `get`, or else loads it and
+    /// passes it to `insert`, which inserts it unless the key was inserted
+    /// meanwhile, and returns the value in the map.
+    pub(crate) fn get_or_load<G: Fn(&K) -> Option<V>, I: Fn(K, V) -> V>(
+        &self,
+        key: &K,
+        get: G,
+        insert: I,
+    ) -> Option<V> {
+        loop {
+            if let Some(value) = get(key) {
+                return Some(value);
+            }
+
+            let (flight, is_leader) = {
+                let mut flights = self.flights.lock().unwrap_or_else(|e| e.into_inner());
+
+                match flights.get(key) {
+                    Some(flight) => (Arc::clone(flight), false),
+                    None => {
+                        let flight = Arc::new(Flight {
+                            state: Mutex::new(FlightState::Loading),
+                            landed: Condvar::new(),
+                        });
+                        flights.insert(key.clone(), Arc::clone(&flight));
+
+                        (flight, true)
+                    }
+                }
+            };
+
+            if is_leader {
+                let mut landing = Landing {
+                    loading: self,
+                    key,
+                    flight: &flight,
+                    state: FlightState::Abandoned,
+                };
+
+                // the previous flight may have landed between the lookup
+                // above and the take-off of this one.
+                let result = get(key).or_else(|| {
+                    let value = self.loader.load(key)?;
+
+                    Some(insert(key.clone(), value))
+                });
+                landing.state = FlightState::Loaded(result.clone());
+
+                return result;
+            }
+
+            let mut state = flight.state.lock().unwrap_or_else(|e| e.into_inner());
+
+            while let FlightState::Loading = *state {
+                state = flight.landed.wait(state).unwrap_or_else(|e| e.into_inner());
+            }
+
+            if let FlightState::Loaded(result) = &*state {
+                return result.clone();
+            }
+        }
+    }
+}
+
+/// Ends a flight when dropped, even if the loader panics.
+struct Landing<'a, K: Hash + Eq, V> {
+    loading: &'a Loading<K, V>,
+    key: &'a K,
+    flight: &'a Flight<V>,
+    state: FlightState<V>,
+}
+
+impl<'a, K: Hash + Eq, V> Drop for Landing<'a, K, V> {
+    fn drop(&mut self) {
+        self.loading
+            .flights
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(self.key);
+
+        let state = std::mem::replace(&mut self.state, FlightState::Abandoned);
+        *self.flight.state.lock().unwrap_or_else(|e| e.into_inner()) = state;
+        self.flight.landed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ordering, HashMap};
+
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        sync::{atomic::AtomicUsize, Arc, Barrier},
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn single_flight() {
+        const NUM_THREADS: usize = 8;
+
+        let num_loads = Arc::new(AtomicUsize::new(0));
+        let map = {
+            let num_loads = Arc::clone(&num_loads);
+
+            HashMap::builder()
+                .loader(move |k: &u32| {
+                    num_loads.fetch_add(1, ordering::RELAXED);
+                    thread::sleep(Duration::from_millis(50));
+
+                    if *k == 1 {
+                        panic!("load");
+                    }
+
+                    Some(k + 1)
+                })
+                .build()
+        };
+        let barrier = Barrier::new(NUM_THREADS);
+
+        thread::scope(|s| {
+            for _ in 0..NUM_THREADS {
+                s.spawn(|| {
+                    barrier.wait();
+                    assert_eq!(map.get_or_load(&0), Some(1));
+                });
+            }
+        });
+
+        assert_eq!(num_loads.load(ordering::RELAXED), 1);
+        assert_eq!(map.len(), 1);
+
+        // the threads waiting for a load that panics load the key themselves
+        thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| map.get_or_load(&1)));
+                    assert!(result.is_err());
+                });
+            }
+        });
+
+        assert_eq!(num_loads.load(ordering::RELAXED), 3);
+        assert_eq!(map.get(&1), None);
+    }
+}
@@ -0,0 +1,228 @@
+use super::{DefaultHashBuilder, HashMap};
+use crate::metrics::{HandleCounters, HandleOpCounts};
+
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+    sync::Arc,
+};
+
+/// A reference-counted handle to a [`HashMap`] that counts the operations
+/// performed through it.
+///
+/// A `MapHandle` is created by [`HashMap::handle`], which gives every handle
+/// an ID that is unique within the map. Clones of a handle share its ID and
+/// its counts, so a subsystem can clone its handle freely, and the counts of
+/// all handles can be reported with [`HashMap::report_handle_op_counts`] to
+/// tell which subsystem is hammering the map.
+///
+/// Only the operations performed through the methods of the handle are
+/// counted. The other methods of the map are reachable through [`map`], and
+/// are not counted.
+///
+/// [`HashMap`]: struct.HashMap.html
+/// [`HashMap::handle`]: struct.HashMap.html#method.handle
+/// [`HashMap::report_handle_op_counts`]: struct.HashMap.html#method.report_handle_op_counts
+/// [`map`]: #method.map
+///
+/// # Examples
+///
+/// ```rust
+/// use moka_cht::HashMap;
+///
+/// use std::sync::Arc;
+///
+/// let map = Arc::new(HashMap::new());
+/// let sessions = map.handle();
+/// let reaper = map.handle();
+///
+/// sessions.insert(1, "alice");
+/// sessions.insert(2, "bob");
+/// assert_eq!(reaper.remove(&1), Some("alice"));
+/// assert_eq!(reaper.get(&1), None);
+///
+/// assert_eq!(sessions.op_counts().inserts, 2);
+/// assert_eq!(reaper.op_counts().removes, 1);
+/// assert_eq!(reaper.op_counts().misses, 1);
+/// ```
+pub struct MapHandle<K, V, S = DefaultHashBuilder> {
+    map: Arc<HashMap<K, V, S>>,
+    counters: Arc<HandleCounters>,
+}
+
+impl<K, V, S> MapHandle<K, V, S> {
+    pub(crate) fn new(map: Arc<HashMap<K, V, S>>) -> Self {
+        let counters = map.handles.register();
+
+        Self { map, counters }
+    }
+
+    /// Returns the ID of the handle.
+    pub fn id(&self) -> u64 {
+        self.counters.id()
+    }
+
+    /// Returns the map.
+    pub fn map(&self) -> &Arc<HashMap<K, V, S>> {
+        &self.map
+    }
+
+    /// Returns the counts of the operations performed through the handle and
+    /// its clones.
+    pub fn op_counts(&self) -> HandleOpCounts {
+        self.counters.counts()
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> MapHandle<K, V, S> {
+    /// Returns a clone of the value corresponding to the key, counting a hit
+    /// or a miss.
+    ///
+    /// See [`HashMap::get`].
+    ///
+    /// [`HashMap::get`]: struct.HashMap.html#method.get
+    pub fn get<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        V: Clone,
+    {
+        self.get_and(key, V::clone)
+    }
+
+    /// Returns the result of invoking a function with a reference to the
+    /// value corresponding to the key, counting a hit or a miss.
+    ///
+    /// See [`HashMap::get_and`].
+    ///
+    /// [`HashMap::get_and`]: struct.HashMap.html#method.get_and
+    pub fn get_and<Q: Hash + Eq + ?Sized, F: FnOnce(&V) -> T, T>(
+        &self,
+        key: &Q,
+        with_value: F,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+    {
+        let result = self.map.get_and(key, with_value);
+        self.counters.count_lookup(result.is_some());
+
+        result
+    }
+
+    /// Inserts a key-value pair into the map, returning a clone of the value
+    /// previously corresponding to the key, and counts an insertion.
+    ///
+    /// See [`HashMap::insert`].
+    ///
+    /// [`HashMap::insert`]: struct.HashMap.html#method.insert
+    pub fn insert(&self, key: K, value: V) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.insert_and(key, value, V::clone)
+    }
+
+    /// Inserts a key-value pair into the map, returning the result of invoking
+    /// a function with a reference to the value previously corresponding to
+    /// the key, and counts an insertion.
+    ///
+    /// See [`HashMap::insert_and`].
+    ///
+    /// [`HashMap::insert_and`]: struct.HashMap.html#method.insert_and
+    pub fn insert_and<F: FnOnce(&V) -> T, T>(
+        &self,
+        key: K,
+        value: V,
+        with_previous_value: F,
+    ) -> Option<T> {
+        self.counters.count_insert();
+
+        self.map.insert_and(key, value, with_previous_value)
+    }
+
+    /// Inserts a value for a key if it is absent, or else replaces its value
+    /// with the result of `on_modify`, and counts an insertion.
+    ///
+    /// See [`HashMap::insert_or_modify`].
+    ///
+    /// [`HashMap::insert_or_modify`]: struct.HashMap.html#method.insert_or_modify
+    pub fn insert_or_modify<F: FnMut(&K, &V) -> V>(
+        &self,
+        key: K,
+        value: V,
+        on_modify: F,
+    ) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.counters.count_insert();
+
+        self.map.insert_or_modify(key, value, on_modify)
+    }
+
+    /// Modifies the value corresponding to a key, returning a clone of the
+    /// value previously corresponding to that key, and counts a modification.
+    ///
+    /// See [`HashMap::modify`].
+    ///
+    /// [`HashMap::modify`]: struct.HashMap.html#method.modify
+    pub fn modify<F: FnMut(&K, &V) -> V>(&self, key: K, on_modify: F) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.counters.count_modify();
+
+        self.map.modify(key, on_modify)
+    }
+
+    /// Removes a key from the map, returning a clone of the value previously
+    /// corresponding to the key, and counts a removal.
+    ///
+    /// See [`HashMap::remove`].
+    ///
+    /// [`HashMap::remove`]: struct.HashMap.html#method.remove
+    pub fn remove<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        V: Clone,
+    {
+        self.remove_and(key, V::clone)
+    }
+
+    /// Removes a key from the map, returning the result of invoking a function
+    /// with a reference to the value previously corresponding to the key, and
+    /// counts a removal.
+    ///
+    /// See [`HashMap::remove_and`].
+    ///
+    /// [`HashMap::remove_and`]: struct.HashMap.html#method.remove_and
+    pub fn remove_and<Q: Hash + Eq + ?Sized, F: FnOnce(&V) -> T, T>(
+        &self,
+        key: &Q,
+        with_previous_value: F,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+    {
+        self.counters.count_remove();
+
+        self.map.remove_and(key, with_previous_value)
+    }
+}
+
+impl<K, V, S> Clone for MapHandle<K, V, S> {
+    fn clone(&self) -> Self {
+        self.counters.add_clone();
+
+        Self {
+            map: Arc::clone(&self.map),
+            counters: Arc::clone(&self.counters),
+        }
+    }
+}
+
+impl<K, V, S> Drop for MapHandle<K, V, S> {
+    fn drop(&mut self) {
+        self.map.handles.drop_handle(&self.counters);
+    }
+}
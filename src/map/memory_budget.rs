@@ -0,0 +1,305 @@
+use crate::{ordering, Error};
+
+use std::{
+    any::Any,
+    fmt::{self, Debug, Formatter},
+    sync::{atomic::AtomicUsize, Arc},
+};
+
+/// A limit on the total weight of the entries of one or more hash maps, which
+/// share it.
+///
+/// A `MemoryBudget` is a handle: its clones refer to the same limit and usage,
+/// so a process that runs many maps can give each of them a clone, through the
+/// `memory_budget` methods of the builders, and bound the memory they take
+/// together rather than one by one. Each map weighs its entries with its own
+/// weigher, in whatever unit the budget is expressed in.
+///
+/// A map charges the weight of an entry to the budget when the entry is
+/// inserted, and releases it when the entry is removed or replaced, when the
+/// map is cleared, or when the map is dropped. The `try_insert` methods of the
+/// maps return [`Error::MemoryBudgetExceeded`] instead of inserting an entry
+/// that does not fit in the budget. The other methods that insert entries
+/// never fail: they charge the budget even if that exceeds its limit, and
+/// call the listener of the budget, if any, so that the application can evict
+/// entries or shed load.
+///
+/// The weight of an entry is computed when it is inserted and again when it is
+/// removed, so the weigher must return the same weight for an entry for as
+/// long as it is in the map. Values modified in place through `get_mut` or
+/// `iter_mut` are not weighed again. Values kept alive by an [`EntryPin`]
+/// after their entry is removed no longer count towards the budget.
+///
+/// [`Error::MemoryBudgetExceeded`]: ../enum.Error.html#variant.MemoryBudgetExceeded
+/// [`EntryPin`]: ./struct.EntryPin.html
+///
+/// # Examples
+///
+/// ```rust
+/// use moka_cht::{map::MemoryBudget, HashMap};
+///
+/// let budget = MemoryBudget::new(8);
+///
+/// let a = HashMap::builder()
+///     .memory_budget(budget.clone(), |_: &u32, v: &String| v.len())
+///     .build();
+/// let b = HashMap::builder()
+///     .memory_budget(budget.clone(), |_: &u32, v: &String| v.len())
+///     .build();
+///
+/// assert_eq!(a.try_insert(1, "four".to_string()), Ok(None));
+/// assert_eq!(b.try_insert(1, "four".to_string()), Ok(None));
+/// assert_eq!(budget.used(), 8);
+///
+/// assert!(a.try_insert(2, "more".to_string()).is_err());
+///
+/// b.remove(&1);
+/// assert_eq!(a.try_insert(2, "more".to_string()), Ok(None));
+/// ```
+#[derive(Clone)]
+pub struct MemoryBudget {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    limit: usize,
+    used: AtomicUsize,
+    on_exceeded: Option<Box<Listener>>,
+}
+
+type Listener = dyn Fn(usize, Option<&dyn Any>) + Send + Sync;
+
+impl MemoryBudget {
+    /// Creates a budget with a limit of `limit`.
+    pub fn new(limit: usize) -> Self {
+        Self::with_inner(limit, None)
+    }
+
+    /// Creates a budget with a limit of `limit` that calls `on_exceeded`
+    /// whenever a charge takes its usage above the limit, or would have for a
+    /// `try_insert` that was rejected. The listener is passed that usage.
+    ///
+    /// The listener is called on the thread that inserts the entry, and must
+    /// not insert into the maps that share the budget.
+    pub fn with_listener(
+        limit: usize,
+        on_exceeded: impl Fn(usize) + Send + Sync + 'static,
+    ) -> Self {
+        Self::with_inner(limit, Some(Box::new(move |usage, _| on_exceeded(usage))))
+    }
+
+    /// Like [`with_listener`], but the listener is also passed the context of
+    /// the operation that charged the budget, if it was performed through a
+    /// `with_context` view of a map.
+    ///
+    /// [`with_listener`]: #method.with_listener
+    pub fn with_context_listener(
+        limit: usize,
+        on_exceeded: impl Fn(usize, Option<&dyn Any>) + Send + Sync + 'static,
+    ) -> Self {
+        Self::with_inner(limit, Some(Box::new(on_exceeded)))
+    }
+
+    fn with_inner(limit: usize, on_exceeded: Option<Box<Listener>>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                limit,
+                used: AtomicUsize::new(0),
+                on_exceeded,
+            }),
+        }
+    }
+
+    /// Returns the limit of the budget.
+    pub fn limit(&self) -> usize {
+        self.inner.limit
+    }
+
+    /// Returns the total weight of the entries charged to the budget.
+    pub fn used(&self) -> usize {
+        usage(self.inner.used.load(ordering::RELAXED))
+    }
+
+    /// Returns the weight that can still be charged to the budget without
+    /// exceeding its limit.
+    pub fn available(&self) -> usize {
+        self.limit().saturating_sub(self.used())
+    }
+
+    /// Charges `weight` to the budget even if that exceeds its limit.
+    fn charge(&self, weight: usize, context: Option<&dyn Any>) {
+        let used = self.inner.used.fetch_add(weight, ordering::RELAXED);
+
+        self.check_usage(usage(used.wrapping_add(weight)), context);
+    }
+
+    /// Charges `weight` to the budget, returning an error if that would
+    /// exceed its limit.
+    fn try_charge(&self, weight: usize, context: Option<&dyn Any>) -> Result<(), Error> {
+        let limit = self.inner.limit;
+
+        self.inner
+            .used
+            .fetch_update(ordering::RELAXED, ordering::RELAXED, |u| {
+                Some(u.wrapping_add(weight)).filter(|&n| usage(n) <= limit && usage(n) >= usage(u))
+            })
+            .map(|_| ())
+            .map_err(|used| {
+                self.check_usage(usage(used).saturating_add(weight), context);
+
+                Error::MemoryBudgetExceeded
+            })
+    }
+
+    fn release(&self, weight: usize) {
+        self.inner.used.fetch_sub(weight, ordering::RELAXED);
+    }
+
+    fn check_usage(&self, usage: usize, context: Option<&dyn Any>) {
+        if usage > self.inner.limit {
+            if let Some(on_exceeded) = &self.inner.on_exceeded {
+                on_exceeded(usage, context);
+            }
+        }
+    }
+}
+
+// an entry may be released by a removal before the insertion that added it has
+// charged it, so the counter wraps around below zero for a moment, and is read
+// as zero meanwhile.
+fn usage(used: usize) -> usize {
+    if (used as isize) < 0 {
+        0
+    } else {
+        used
+    }
+}
+
+impl Debug for MemoryBudget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryBudget")
+            .field("limit", &self.limit())
+            .field("used", &self.used())
+            .finish()
+    }
+}
+
+type Weigher<K, V> = dyn Fn(&K, &V) -> usize + Send + Sync;
+
+/// A memory budget together with the weigher of the map that charges it.
+pub(crate) struct MemoryCharge<K, V> {
+    budget: MemoryBudget,
+    weigher: Arc<Weigher<K, V>>,
+}
+
+impl<K, V> MemoryCharge<K, V> {
+    pub(crate) fn new(
+        budget: MemoryBudget,
+        weigher: impl Fn(&K, &V) -> usize + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            budget,
+            weigher: Arc::new(weigher),
+        }
+    }
+
+    pub(crate) fn budget(&self) -> &MemoryBudget {
+        &self.budget
+    }
+
+    pub(crate) fn weigh(&self, key: &K, value: &V) -> usize {
+        (self.weigher)(key, value)
+    }
+
+    pub(crate) fn charge(&self, key: &K, value: &V, context: Option<&dyn Any>) {
+        self.budget.charge(self.weigh(key, value), context);
+    }
+
+    pub(crate) fn charge_weight(&self, weight: usize, context: Option<&dyn Any>) {
+        self.budget.charge(weight, context);
+    }
+
+    pub(crate) fn try_charge(
+        &self,
+        key: &K,
+        value: &V,
+        context: Option<&dyn Any>,
+    ) -> Result<(), Error> {
+        self.budget.try_charge(self.weigh(key, value), context)
+    }
+
+    pub(crate) fn release(&self, key: &K, value: &V) {
+        self.budget.release(self.weigh(key, value));
+    }
+}
+
+impl<K, V> Clone for MemoryCharge<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            budget: self.budget.clone(),
+            weigher: Arc::clone(&self.weigher),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn charge_and_release() {
+        let exceeded = Arc::new(AtomicUsize::new(0));
+        let budget = {
+            let exceeded = Arc::clone(&exceeded);
+
+            MemoryBudget::with_listener(10, move |usage| {
+                exceeded.store(usage, ordering::RELAXED);
+            })
+        };
+
+        budget.charge(6, None);
+        assert_eq!(budget.try_charge(4, None), Ok(()));
+        assert_eq!(budget.used(), 10);
+        assert_eq!(budget.available(), 0);
+        assert_eq!(exceeded.load(ordering::RELAXED), 0);
+
+        assert_eq!(budget.try_charge(1, None), Err(Error::MemoryBudgetExceeded));
+        assert_eq!(budget.used(), 10);
+        assert_eq!(exceeded.load(ordering::RELAXED), 11);
+
+        budget.clone().charge(2, None);
+        assert_eq!(budget.used(), 12);
+        assert_eq!(exceeded.load(ordering::RELAXED), 12);
+
+        budget.release(5);
+        assert_eq!(budget.used(), 7);
+        assert_eq!(budget.available(), 3);
+
+        // a release that overtakes its charge is not lost
+        budget.release(20);
+        assert_eq!(budget.used(), 0);
+        budget.charge(20, None);
+        assert_eq!(budget.used(), 7);
+    }
+
+    #[test]
+    fn context_listener() {
+        let exceeded = Arc::new(AtomicUsize::new(0));
+        let budget = {
+            let exceeded = Arc::clone(&exceeded);
+
+            MemoryBudget::with_context_listener(10, move |usage, context| {
+                let request_id = context.and_then(|c| c.downcast_ref::<usize>());
+                exceeded.store(usage + request_id.copied().unwrap_or(0), ordering::RELAXED);
+            })
+        };
+
+        budget.charge(11, None);
+        assert_eq!(exceeded.load(ordering::RELAXED), 11);
+
+        assert!(budget.try_charge(1, Some(&100usize)).is_err());
+        assert_eq!(exceeded.load(ordering::RELAXED), 112);
+    }
+}
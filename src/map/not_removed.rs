@@ -0,0 +1,56 @@
+use std::fmt::{self, Display, Formatter};
+
+/// The error returned by the `try_remove_if` methods of the maps when they do
+/// not remove an entry, with the reason why.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NotRemoved {
+    reason: NotRemovedReason,
+}
+
+/// Why a conditional removal did not remove an entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum NotRemovedReason {
+    /// The map had no entry for the key.
+    Absent,
+    /// The map had an entry for the key, and the condition rejected its
+    /// current value.
+    ConditionFalse,
+    /// The condition accepted the entry for the key, but a concurrent
+    /// operation removed the entry before it could be.
+    Contended,
+}
+
+impl NotRemoved {
+    /// Returns why the entry was not removed.
+    pub fn reason(&self) -> NotRemovedReason {
+        self.reason
+    }
+
+    /// Classifies a failed removal by the result of the last invocation of its
+    /// condition, if any.
+    pub(crate) fn from_last_decision(last_decision: Option<bool>) -> Self {
+        let reason = match last_decision {
+            None => NotRemovedReason::Absent,
+            Some(false) => NotRemovedReason::ConditionFalse,
+            // the accepted entry was gone when the removal was retried
+            Some(true) => NotRemovedReason::Contended,
+        };
+
+        Self { reason }
+    }
+}
+
+impl Display for NotRemoved {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.reason {
+            NotRemovedReason::Absent => write!(f, "the key is absent"),
+            NotRemovedReason::ConditionFalse => write!(f, "the condition rejected the entry"),
+            NotRemovedReason::Contended => {
+                write!(f, "the entry was concurrently removed")
+            }
+        }
+    }
+}
+
+impl std::error::Error for NotRemoved {}
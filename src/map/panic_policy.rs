@@ -0,0 +1,68 @@
+use std::{process, thread};
+
+/// What a hash map does when a closure passed to one of its methods panics
+/// while the map is being read or modified.
+///
+/// The closures include the functions that create, modify, or inspect values,
+/// such as the `on_insert` and `on_modify` functions of `insert_with_or_modify`
+/// and the conditions of `remove_if`, as well as the [`Clone`] implementations
+/// of the values that are returned.
+///
+/// With either policy, a panic never leaves a map in an inconsistent state:
+///
+/// - If the panic happens before the map is changed, for example in
+///   `on_modify`, the entry is left unchanged, as if the method had not been
+///   called.
+/// - If the panic happens after the map is changed, for example in the closure
+///   passed to `insert_and`, the change is kept.
+/// - The length of the map always matches its entries.
+/// - The values that were created for the operation but not inserted, and the
+///   entries that were replaced or removed, are dropped rather than leaked.
+///   Entries that other threads may still be reading are dropped once those
+///   threads are done with them.
+/// - If the `Hash` or `Eq` implementation of a key panics while the map is
+///   being resized, the entries that were not moved yet stay where they are,
+///   and the next operation that runs into the resize finishes it. Dropping
+///   the map reclaims every entry either way.
+///
+/// The map can therefore be used after the panic has been caught.
+///
+/// Both hash maps use [`Unwind`] by default. It can be changed with the
+/// `panic_policy` methods of their builders.
+///
+/// [`Clone`]: https://doc.rust-lang.org/std/clone/trait.Clone.html
+/// [`Unwind`]: #variant.Unwind
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PanicPolicy {
+    /// Unwinds out of the method, as the panic would do without the map.
+    #[default]
+    Unwind,
+    /// Aborts the process, for applications that treat a panic in the middle
+    /// of an operation on shared state as unrecoverable.
+    Abort,
+}
+
+impl PanicPolicy {
+    /// Returns a guard that applies the policy to the panics that happen while
+    /// it is alive.
+    pub(crate) fn enter(self) -> PanicGuard {
+        PanicGuard {
+            // a method that is called while the thread is already unwinding,
+            // e.g. from a destructor, must not abort when it returns normally.
+            abort: self == PanicPolicy::Abort && !thread::panicking(),
+        }
+    }
+}
+
+pub(crate) struct PanicGuard {
+    abort: bool,
+}
+
+impl Drop for PanicGuard {
+    fn drop(&mut self) {
+        if self.abort && thread::panicking() {
+            process::abort();
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use super::bucket::Bucket;
+
+use rayon::prelude::*;
+
+/// The number of buckets that a task of a parallel traversal visits.
+///
+/// A bucket pointer array with fewer live buckets than this is traversed by a
+/// single task.
+pub(crate) const CHUNK_LEN: usize = 8 * 1024;
+
+/// Invokes `f` with the key and value of every bucket, splitting the buckets
+/// into chunks that are visited by the tasks of the rayon pool.
+///
+/// The buckets must stay protected by a guard of the calling thread until
+/// this returns, which it does once every task is done.
+pub(crate) fn for_each<K: Sync, V: Sync, F: Fn(&K, &V) + Sync>(buckets: &[&Bucket<K, V>], f: &F) {
+    if buckets.len() <= CHUNK_LEN {
+        for b in buckets {
+            f(&b.key, unsafe { b.value() });
+        }
+
+        return;
+    }
+
+    buckets.par_chunks(CHUNK_LEN).for_each(|chunk| {
+        for b in chunk {
+            f(&b.key, unsafe { b.value() });
+        }
+    });
+}
+
+/// Returns clones of the keys and values of every bucket, cloned by the tasks
+/// of the rayon pool.
+///
+/// The buckets must stay protected by a guard of the calling thread until
+/// this returns.
+pub(crate) fn cloned<K: Clone + Send + Sync, V: Clone + Send + Sync>(
+    buckets: &[&Bucket<K, V>],
+) -> Vec<(K, V)> {
+    buckets
+        .par_chunks(CHUNK_LEN)
+        .flat_map_iter(|chunk| {
+            chunk
+                .iter()
+                .map(|b| (b.key.clone(), unsafe { b.value() }.clone()))
+        })
+        .collect()
+}
@@ -0,0 +1,140 @@
+/// A disjoint part of a hash map that can be scanned independently of the
+/// other parts.
+///
+/// The partitions of a map are created by the `partitions` methods of the
+/// maps, which split the order in which a [`ScanCursor`] visits the entries
+/// into consecutive ranges of about equal size, and advanced in batches by
+/// their `scan_partition` methods. Every entry belongs to exactly one
+/// partition, selected by its segment and the hash of its key, so the
+/// partitions can be moved to the threads of any pool and scanned there to
+/// export a whole map in parallel. A partition holds no reference to the map
+/// and no epoch guard, and the scan of each partition has the same guarantees
+/// as a scan started with a [`ScanCursor`], even if the map is resized between
+/// the batches.
+///
+/// A partition must only be used with the map that created it.
+///
+/// [`ScanCursor`]: ./struct.ScanCursor.html
+///
+/// # Examples
+///
+/// ```rust
+/// use moka_cht::HashMap;
+///
+/// use std::{sync::Arc, thread};
+///
+/// let map = Arc::new(HashMap::new());
+///
+/// for i in 0..1000 {
+///     map.insert(i, i);
+/// }
+///
+/// let threads: Vec<_> = map
+///     .partitions(4)
+///     .into_iter()
+///     .map(|mut partition| {
+///         let map = Arc::clone(&map);
+///
+///         thread::spawn(move || {
+///             let mut sum = 0;
+///
+///             while !partition.is_done() {
+///                 sum += map
+///                     .scan_partition(&mut partition, 64)
+///                     .map(|r| *r.value())
+///                     .sum::<i32>();
+///             }
+///
+///             sum
+///         })
+///     })
+///     .collect();
+///
+/// let sum: i32 = threads.into_iter().map(|t| t.join().unwrap()).sum();
+/// assert_eq!(sum, (0..1000).sum());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Partition {
+    segment: usize,
+    position: u64,
+    end_segment: usize,
+    end_position: u64,
+    done: bool,
+}
+
+impl Partition {
+    /// Splits the scan positions of `num_segments` segments into `n`
+    /// consecutive partitions.
+    pub(crate) fn split(n: usize, num_segments: usize) -> Vec<Self> {
+        assert!(n > 0, "a map cannot be split into 0 partitions");
+
+        // the scan positions of all segments, one after the other
+        let total = (num_segments as u128) << 64;
+        let (step, remainder) = (total / n as u128, total % n as u128);
+        let boundary = |i: usize| {
+            let b = step * i as u128 + remainder * i as u128 / n as u128;
+
+            ((b >> 64) as usize, b as u64)
+        };
+
+        (0..n)
+            .map(|i| {
+                let (segment, position) = boundary(i);
+                let (end_segment, end_position) = boundary(i + 1);
+
+                Self {
+                    segment,
+                    position,
+                    end_segment,
+                    end_position,
+                    done: (segment, position) >= (end_segment, end_position),
+                }
+            })
+            .collect()
+    }
+
+    /// Returns `true` if the scan of the partition is complete.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Returns the index of the segment that the scan of the partition is in,
+    /// which is always 0 for a [`HashMap`].
+    ///
+    /// [`HashMap`]: ./struct.HashMap.html
+    pub fn segment(&self) -> usize {
+        self.segment
+    }
+
+    pub(crate) fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Returns the scan position that a batch of the current segment which
+    /// stops at `next` has to be cut at, if it goes past the end of the
+    /// partition.
+    pub(crate) fn cut(&self, next: Option<u64>) -> Option<u64> {
+        match next {
+            _ if self.segment != self.end_segment => None,
+            Some(next) if next <= self.end_position => None,
+            _ => Some(self.end_position),
+        }
+    }
+
+    /// Moves the partition to `position` in the current segment, or to the
+    /// start of the next segment if it is `None`, and completes it if that is
+    /// past its end.
+    pub(crate) fn advance(&mut self, position: Option<u64>) {
+        match position {
+            Some(position) => self.position = position,
+            None => {
+                self.segment += 1;
+                self.position = 0;
+            }
+        }
+
+        if (self.segment, self.position) >= (self.end_segment, self.end_position) {
+            self.done = true;
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use super::bucket::{self, Bucket};
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    marker::PhantomData,
+    ptr::NonNull,
+};
+
+/// A counted reference to an entry of a hash map, which keeps the key and the
+/// value of the entry alive until it is dropped.
+///
+/// An `EntryPin` is created by the `pin_entry` methods of the hash maps. It
+/// does not prevent the entry from being removed or replaced: after a removal,
+/// lookups do not find the entry anymore, but the pin still gives access to
+/// the key and the value, which are not dropped before the last pin. Likewise,
+/// after the value of the entry is replaced, the pin keeps the value it was
+/// created for.
+///
+/// Unlike a [`Ref`], a pin does not pin the current thread to an epoch, so it
+/// can be held across `await` points and sent to other threads, and does not
+/// delay the reclamation of memory elsewhere in the map. Each pin costs an
+/// atomic increment when it is created or cloned and an atomic decrement when
+/// it is dropped.
+///
+/// [`Ref`]: ./struct.Ref.html
+pub struct EntryPin<'a, K, V> {
+    bucket: NonNull<Bucket<K, V>>,
+    _marker: PhantomData<&'a Bucket<K, V>>,
+}
+
+impl<'a, K, V> EntryPin<'a, K, V> {
+    /// Pins the bucket, returning `None` if its entry has been removed.
+    ///
+    /// # Safety
+    ///
+    /// The bucket must be protected by a guard, and must belong to a map that
+    /// outlives `'a`.
+    pub(crate) unsafe fn new(bucket: &Bucket<K, V>) -> Option<Self> {
+        if bucket.try_pin() {
+            Some(Self {
+                bucket: NonNull::from(bucket),
+                _marker: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Returns a reference to the key of the entry.
+    pub fn key(&self) -> &K {
+        &self.bucket().key
+    }
+
+    /// Returns a reference to the value of the entry.
+    pub fn value(&self) -> &V {
+        unsafe { self.bucket().value() }
+    }
+
+    /// Returns references to the key and the value of the entry.
+    pub fn pair(&self) -> (&K, &V) {
+        (self.key(), self.value())
+    }
+
+    fn bucket(&self) -> &Bucket<K, V> {
+        unsafe { self.bucket.as_ref() }
+    }
+}
+
+impl<'a, K, V> Clone for EntryPin<'a, K, V> {
+    fn clone(&self) -> Self {
+        self.bucket().add_pin();
+
+        Self {
+            bucket: self.bucket,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, K, V> Drop for EntryPin<'a, K, V> {
+    fn drop(&mut self) {
+        unsafe { bucket::unpin(self.bucket.as_ptr()) };
+    }
+}
+
+impl<'a, K: Debug, V: Debug> Debug for EntryPin<'a, K, V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EntryPin")
+            .field("key", self.key())
+            .field("value", self.value())
+            .finish()
+    }
+}
+
+// the last pin to be dropped may drop the key and the value
+unsafe impl<'a, K: Send + Sync, V: Send + Sync> Send for EntryPin<'a, K, V> {}
+unsafe impl<'a, K: Send + Sync, V: Send + Sync> Sync for EntryPin<'a, K, V> {}
@@ -0,0 +1,303 @@
+//! Low-level access to the bucket pointer arrays of a hash map.
+//!
+//! A [`RawTable`] exposes the probing, resizing, and reclamation machinery
+//! behind [`HashMap`] at the level of hashes and epoch guards, for building
+//! custom lock-free structures on top of it, such as sets that carry auxiliary
+//! data with their keys or indexes that look up entries by hashes they
+//! computed themselves.
+//!
+//! This module is only available when the `unstable` feature is enabled. Its
+//! API may change in any release, including patch releases.
+//!
+//! # Contracts
+//!
+//! The methods of a [`RawTable`] take the hash of the key they operate on
+//! rather than computing it. It must be the hash returned by
+//! [`RawTable::hash`] for that key, as the entries are moved to a new bucket
+//! pointer array by rehashing their keys with the hasher of the table. A
+//! method that is passed a different hash does not cause undefined behavior,
+//! but it may miss the entry, insert a duplicate of it, or leave the entry
+//! unreachable after the next resize.
+//!
+//! The methods that return references to keys and values take an epoch
+//! [`Guard`], and the references live as long as it does. While the guard is
+//! pinned, an entry that is removed or replaced by another thread is not
+//! destroyed, so the references may point to an entry that is no longer in
+//! the table. The guard must be pinned to the default collector of
+//! `crossbeam-epoch`, which the table uses to reclaim its buckets; the methods
+//! panic if it is not.
+//!
+//! [`RawTable`]: ./struct.RawTable.html
+//! [`RawTable::hash`]: ./struct.RawTable.html#method.hash
+//! [`HashMap`]: ../struct.HashMap.html
+//! [`Guard`]: ../../epoch/struct.Guard.html
+//!
+//! # Examples
+//!
+//! ```rust
+//! use moka_cht::{epoch, map::raw::RawTable};
+//!
+//! let table = RawTable::new();
+//! let guard = &epoch::pin();
+//!
+//! let hash = table.hash("foo");
+//! let ((_, value), inserted) = table.get_or_insert_with(guard, hash, "foo", || 1);
+//! assert_eq!(*value, 1);
+//! assert!(inserted);
+//!
+//! assert_eq!(table.find(guard, hash, "foo"), Some((&"foo", &1)));
+//! assert_eq!(table.remove_if(hash, "foo", |_, v| *v == 1, |_, v| *v), Some(1));
+//! assert_eq!(table.find(guard, hash, "foo"), None);
+//! ```
+
+use super::{
+    bucket::{check_guard, Bucket},
+    DefaultHashBuilder, HashMap,
+};
+
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+};
+
+use crossbeam_epoch::Guard;
+
+/// A lock-free hash table whose operations take precomputed hashes and epoch
+/// guards.
+///
+/// A `RawTable` has the same layout as a [`HashMap`] and can be converted to
+/// and from one. See the [module documentation] for the contracts of its
+/// methods.
+///
+/// [`HashMap`]: ../struct.HashMap.html
+/// [module documentation]: ./index.html
+pub struct RawTable<K, V, S = DefaultHashBuilder> {
+    map: HashMap<K, V, S>,
+}
+
+impl<K, V> RawTable<K, V, DefaultHashBuilder> {
+    /// Creates an empty `RawTable`.
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S> RawTable<K, V, S> {
+    /// Creates an empty `RawTable` that will use `build_hasher` to rehash
+    /// keys when it is resized.
+    pub fn with_hasher(build_hasher: S) -> Self {
+        Self {
+            map: HashMap::with_hasher(build_hasher),
+        }
+    }
+
+    /// Creates an empty `RawTable` with the specified capacity that will use
+    /// `build_hasher` to rehash keys when it is resized.
+    pub fn with_capacity_and_hasher(capacity: usize, build_hasher: S) -> Self {
+        Self {
+            map: HashMap::with_capacity_and_hasher(capacity, build_hasher),
+        }
+    }
+
+    /// Returns the number of entries in the table.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the table contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns a reference to the table's `BuildHasher`.
+    pub fn hasher(&self) -> &S {
+        self.map.hasher()
+    }
+
+    /// Converts the table into a `HashMap` with the same entries.
+    pub fn into_map(self) -> HashMap<K, V, S> {
+        self.map
+    }
+}
+
+impl<K, V> Default for RawTable<K, V, DefaultHashBuilder> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, S> From<HashMap<K, V, S>> for RawTable<K, V, S> {
+    fn from(map: HashMap<K, V, S>) -> Self {
+        Self { map }
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> RawTable<K, V, S> {
+    /// Returns the hash of `key`, which must be passed to the methods that
+    /// operate on it.
+    pub fn hash<Q: Hash + ?Sized>(&self, key: &Q) -> u64
+    where
+        K: Borrow<Q>,
+    {
+        self.map.key_hash(key)
+    }
+
+    /// Returns references to the entry of `key`, which has the hash `hash`.
+    pub fn find<'g, Q: Hash + Eq + ?Sized>(
+        &'g self,
+        guard: &'g Guard,
+        hash: u64,
+        key: &Q,
+    ) -> Option<(&'g K, &'g V)>
+    where
+        K: Borrow<Q>,
+    {
+        check_guard(guard);
+
+        self.map
+            .bucket_array_ref()
+            .get_bucket(guard, key, hash)
+            .map(entry)
+    }
+
+    /// Returns references to the entry of `key`, which has the hash `hash`,
+    /// inserting it with the value returned by `on_insert` if it is not in
+    /// the table, and whether it was inserted.
+    pub fn get_or_insert_with<'g, F: FnOnce() -> V>(
+        &'g self,
+        guard: &'g Guard,
+        hash: u64,
+        key: K,
+        on_insert: F,
+    ) -> ((&'g K, &'g V), bool) {
+        check_guard(guard);
+
+        let (bucket, inserted) = self
+            .map
+            .bucket_array_ref()
+            .get_or_insert_with(guard, key, hash, on_insert);
+
+        (entry(bucket), inserted)
+    }
+
+    /// Inserts the value returned by `on_insert` for `key`, which has the
+    /// hash `hash`, or replaces its value with the one returned by
+    /// `on_modify`, and returns references to the previous entry, if any, and
+    /// to the current one.
+    ///
+    /// `on_modify` may be invoked multiple times if the entry is concurrently
+    /// modified.
+    #[allow(clippy::type_complexity)]
+    pub fn insert_with_or_modify<'g, F: FnOnce(&K) -> V, G: FnMut(&K, &V) -> V>(
+        &'g self,
+        guard: &'g Guard,
+        hash: u64,
+        key: K,
+        on_insert: F,
+        on_modify: G,
+    ) -> (Option<(&'g K, &'g V)>, (&'g K, &'g V)) {
+        check_guard(guard);
+
+        let (previous, current) = self
+            .map
+            .bucket_array_ref()
+            .insert_with_or_modify(guard, key, hash, on_insert, on_modify);
+
+        (previous.map(entry), entry(current))
+    }
+
+    /// Removes the entry of `key`, which has the hash `hash`, if `condition`
+    /// returns `true` for it, and returns the result of invoking
+    /// `with_previous_entry` with it.
+    ///
+    /// `condition` may be invoked multiple times if the entry is concurrently
+    /// modified.
+    pub fn remove_if<Q: Hash + Eq + ?Sized, F: FnMut(&K, &V) -> bool, G: FnOnce(&K, &V) -> T, T>(
+        &self,
+        hash: u64,
+        key: &Q,
+        condition: F,
+        with_previous_entry: G,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+    {
+        self.map
+            .bucket_array_ref()
+            .remove_entry_if_and(key, hash, condition, with_previous_entry)
+    }
+
+    /// Returns references to the entries of the table, in the order of the
+    /// slots that hold them.
+    ///
+    /// The entries are collected after any pending resize is completed, so
+    /// each of them is returned once.
+    pub fn entries<'g>(&'g self, guard: &'g Guard) -> Vec<(&'g K, &'g V)> {
+        check_guard(guard);
+
+        self.map
+            .bucket_array_ref()
+            .live_buckets_in_memory_order(guard)
+            .into_iter()
+            .map(entry)
+            .collect()
+    }
+}
+
+fn entry<K, V>(bucket: &Bucket<K, V>) -> (&K, &V) {
+    // the bucket pointer arrays only hand out buckets that hold a value
+    (&bucket.key, unsafe { bucket.value() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crossbeam_epoch::Collector;
+
+    #[test]
+    fn raw_table() {
+        const NUM_VALUES: i32 = 512;
+
+        let table = RawTable::new();
+        let guard = &crossbeam_epoch::pin();
+
+        for i in 0..NUM_VALUES {
+            let hash = table.hash(&i);
+            assert!(table.get_or_insert_with(guard, hash, i, || i).1);
+        }
+
+        assert_eq!(table.len(), NUM_VALUES as usize);
+
+        let hash = table.hash(&0);
+        let (previous, current) = table.insert_with_or_modify(guard, hash, 0, |_| 0, |_, v| v + 1);
+        assert_eq!(previous, Some((&0, &0)));
+        assert_eq!(current, (&0, &1));
+
+        // the references outlive the removal while the guard is pinned
+        assert_eq!(
+            table.remove_if(hash, &0, |_, v| *v == 1, |_, v| *v),
+            Some(1)
+        );
+        assert_eq!(previous.unwrap().1, &0);
+        assert_eq!(table.find(guard, hash, &0), None);
+
+        let mut entries: Vec<_> = table.entries(guard).into_iter().map(|(k, _)| *k).collect();
+        entries.sort_unstable();
+        assert_eq!(entries, (1..NUM_VALUES).collect::<Vec<_>>());
+
+        let map = table.into_map();
+        assert_eq!(map.get(&1), Some(1));
+        assert_eq!(RawTable::from(map).len(), NUM_VALUES as usize - 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "the guard must be pinned to the default collector")]
+    fn foreign_guard() {
+        let table: RawTable<i32, i32> = RawTable::new();
+        let collector = Collector::new();
+        let handle = collector.register();
+
+        table.find(&handle.pin(), 0, &0);
+    }
+}
@@ -0,0 +1,57 @@
+/// A limit on the amount of work a single budgeted read of a hash map may
+/// perform.
+///
+/// An ordinary lookup that runs into a bucket pointer array being resized first
+/// helps migrate every bucket of the array, which takes time proportional to
+/// the length of the array. A budgeted read, such as the `get_and_within`
+/// methods of the hash maps, instead returns [`Error::Busy`] once it has
+/// probed `max_probes` slots or migrated `max_migrated` buckets, so that
+/// callers with soft real-time requirements can bound the worst-case time of
+/// their reads and retry later, or fall back to another source.
+///
+/// The buckets migrated by a read that runs out of budget stay migrated, so
+/// repeated budgeted reads make progress on the resize. Resizes are started by
+/// insertions, which always complete them.
+///
+/// [`Error::Busy`]: ../enum.Error.html#variant.Busy
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReadBudget {
+    max_probes: usize,
+    max_migrated: usize,
+}
+
+impl ReadBudget {
+    /// Creates a budget of `max_probes` slots probed and `max_migrated`
+    /// buckets migrated to a new bucket pointer array.
+    pub fn new(max_probes: usize, max_migrated: usize) -> Self {
+        Self {
+            max_probes,
+            max_migrated,
+        }
+    }
+
+    /// Creates a budget that is never exhausted.
+    pub fn unlimited() -> Self {
+        Self::new(usize::MAX, usize::MAX)
+    }
+
+    /// Spends one probe, returning `false` if none is left.
+    pub(crate) fn spend_probe(&mut self) -> bool {
+        spend(&mut self.max_probes)
+    }
+
+    /// Spends one bucket migration, returning `false` if none is left.
+    pub(crate) fn spend_migration(&mut self) -> bool {
+        spend(&mut self.max_migrated)
+    }
+}
+
+fn spend(remaining: &mut usize) -> bool {
+    if *remaining == 0 {
+        false
+    } else {
+        *remaining -= 1;
+
+        true
+    }
+}
@@ -0,0 +1,139 @@
+use super::{bucket, DefaultHashBuilder, HashMap};
+
+use crate::ordering;
+
+use std::hash::{BuildHasher, Hash};
+
+/// A number of slots reserved in a [`HashMap`], which guarantees that the
+/// insertions made through the token do not grow the map.
+///
+/// A `ReservationToken` is created by [`HashMap::reserve_slots`], which grows
+/// the bucket pointer array up front if needed, so that latency-critical paths
+/// can move the cost of growing the map to a setup phase. The insertions made
+/// without the token leave the reserved slots free, and grow the map instead
+/// of taking them.
+///
+/// Each insertion through the token that adds a new key uses one of its
+/// slots, while an insertion that replaces the value of a key uses none. Once
+/// the slots are used up, the token inserts like the map does. The slots left
+/// are released when the token is dropped.
+///
+/// An insertion through the token may still have to help other threads finish
+/// a resize that they started, or grow the map if the removals of other keys
+/// have left no free slot behind them.
+///
+/// [`HashMap`]: struct.HashMap.html
+/// [`HashMap::reserve_slots`]: struct.HashMap.html#method.reserve_slots
+///
+/// # Examples
+///
+/// ```rust
+/// use moka_cht::HashMap;
+///
+/// let map = HashMap::new();
+/// let mut token = map.reserve_slots(64);
+/// let capacity = map.capacity();
+/// assert!(capacity >= 64);
+///
+/// // other insertions do not take the reserved slots
+/// for i in 0..capacity {
+///     map.insert(i, i);
+/// }
+///
+/// let capacity = map.capacity();
+///
+/// for i in 0..64 {
+///     token.insert(capacity + i, i);
+/// }
+///
+/// assert_eq!(token.remaining(), 0);
+/// assert_eq!(map.capacity(), capacity);
+/// ```
+pub struct ReservationToken<'a, K, V, S = DefaultHashBuilder> {
+    map: &'a HashMap<K, V, S>,
+    remaining: usize,
+}
+
+impl<'a, K, V, S> ReservationToken<'a, K, V, S> {
+    pub(crate) fn new(map: &'a HashMap<K, V, S>, remaining: usize) -> Self {
+        Self { map, remaining }
+    }
+
+    /// Returns the number of slots left.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> ReservationToken<'a, K, V, S> {
+    /// Inserts a key-value pair into the map, returning a clone of the value
+    /// previously corresponding to the key.
+    ///
+    /// See [`HashMap::insert`].
+    ///
+    /// [`HashMap::insert`]: struct.HashMap.html#method.insert
+    pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.insert_entry_and(key, value, |_, v| v.clone())
+    }
+
+    /// Inserts a key-value pair into the map, returning the result of invoking
+    /// a function with a reference to the value previously corresponding to
+    /// the key.
+    ///
+    /// See [`HashMap::insert_and`].
+    ///
+    /// [`HashMap::insert_and`]: struct.HashMap.html#method.insert_and
+    pub fn insert_and<F: FnOnce(&V) -> T, T>(
+        &mut self,
+        key: K,
+        value: V,
+        with_previous_value: F,
+    ) -> Option<T> {
+        self.insert_entry_and(key, value, move |_, v| with_previous_value(v))
+    }
+
+    /// Inserts a key-value pair into the map, returning the result of invoking
+    /// a function with a reference to the key-value pair previously
+    /// corresponding to the supplied key.
+    ///
+    /// See [`HashMap::insert_entry_and`].
+    ///
+    /// [`HashMap::insert_entry_and`]: struct.HashMap.html#method.insert_entry_and
+    pub fn insert_entry_and<F: FnOnce(&K, &V) -> T, T>(
+        &mut self,
+        key: K,
+        value: V,
+        with_previous_entry: F,
+    ) -> Option<T> {
+        if self.remaining == 0 {
+            return self.map.insert_entry_and(key, value, with_previous_entry);
+        }
+
+        let hash = bucket::hash(&self.map.build_hasher, &key);
+
+        let result = self.map.bucket_array_ref().insert_reserved_entry_and(
+            key,
+            hash,
+            value,
+            with_previous_entry,
+        );
+
+        if result.is_none() {
+            self.remaining -= 1;
+            self.map.reserved_slots.fetch_sub(1, ordering::RELAXED);
+        }
+
+        result
+    }
+}
+
+impl<'a, K, V, S> Drop for ReservationToken<'a, K, V, S> {
+    fn drop(&mut self) {
+        self.map
+            .reserved_slots
+            .fetch_sub(self.remaining, ordering::RELAXED);
+    }
+}
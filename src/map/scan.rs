@@ -0,0 +1,90 @@
+/// The position of a resumable scan over the entries of a hash map.
+///
+/// A scan is started with a new cursor and advanced in batches by the `scan`
+/// methods of the maps, which visit the entries in a fixed order determined
+/// by the hashes of their keys, and move the cursor past the entries they
+/// return. A [`SegmentedHashMap`] is scanned segment by segment.
+///
+/// Since the position of an entry in the scan only depends on the hash of its
+/// key, and not on the slot it occupies, the maps may grow or shrink between
+/// the batches of a scan. The guarantees are those of a weakly consistent
+/// iterator:
+///
+/// - An entry that is in the map for the whole scan is returned exactly once.
+/// - An entry that is inserted or removed during the scan may or may not be
+///   returned, but at most once for each time its key is inserted.
+/// - An entry whose value is replaced during the scan is returned at most
+///   once, with either value.
+///
+/// An entry is returned at most once for each time its key is inserted because
+/// a key keeps its position: once the cursor has moved past it, it is not
+/// returned again even if it is removed and inserted again.
+///
+/// [`SegmentedHashMap`]: ../segment/map/struct.HashMap.html
+///
+/// # Examples
+///
+/// ```rust
+/// use moka_cht::{map::ScanCursor, HashMap};
+///
+/// let map = HashMap::new();
+///
+/// for i in 0..1000 {
+///     map.insert(i, i);
+/// }
+///
+/// let mut cursor = ScanCursor::new();
+/// let mut num_entries = 0;
+///
+/// while !cursor.is_done() {
+///     num_entries += map.scan(&mut cursor, 100).count();
+///
+///     // the map may grow between batches
+///     map.insert(num_entries + 1000, 0);
+/// }
+///
+/// assert!(num_entries >= 1000);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ScanCursor {
+    segment: usize,
+    position: u64,
+    done: bool,
+}
+
+impl ScanCursor {
+    /// Creates a cursor at the start of a scan.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `true` if the scan is complete.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Returns the index of the segment that the scan is in, which is always 0
+    /// for a [`HashMap`].
+    ///
+    /// [`HashMap`]: ./struct.HashMap.html
+    pub fn segment(&self) -> usize {
+        self.segment
+    }
+
+    pub(crate) fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Moves the cursor to `position` in the current segment, or to the end of
+    /// the segment if it is `None`.
+    pub(crate) fn advance(&mut self, position: Option<u64>, num_segments: usize) {
+        match position {
+            Some(position) => self.position = position,
+            None if self.segment + 1 < num_segments => {
+                self.segment += 1;
+                self.position = 0;
+            }
+            None => self.done = true,
+        }
+    }
+}
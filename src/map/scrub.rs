@@ -0,0 +1,33 @@
+/// Overwrites the keys and the values that a map drops while it wipes
+/// entries, before their memory is freed.
+pub(crate) trait Scrub<K, V> {
+    fn scrub_key(&mut self, key: &mut K);
+
+    fn scrub_value(&mut self, value: &mut V);
+}
+
+/// Drops the keys and the values as they are.
+pub(crate) struct NoScrub;
+
+impl<K, V> Scrub<K, V> for NoScrub {
+    fn scrub_key(&mut self, _: &mut K) {}
+
+    fn scrub_value(&mut self, _: &mut V) {}
+}
+
+/// Zeroes the keys and the values with [`zeroize`].
+///
+/// [`zeroize`]: https://docs.rs/zeroize/
+#[cfg(feature = "zeroize")]
+pub(crate) struct Zeroize;
+
+#[cfg(feature = "zeroize")]
+impl<K: zeroize::Zeroize, V: zeroize::Zeroize> Scrub<K, V> for Zeroize {
+    fn scrub_key(&mut self, key: &mut K) {
+        key.zeroize();
+    }
+
+    fn scrub_value(&mut self, value: &mut V) {
+        value.zeroize();
+    }
+}
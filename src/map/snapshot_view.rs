@@ -0,0 +1,180 @@
+use super::{
+    bucket::{self, Bucket},
+    DefaultHashBuilder,
+};
+use crate::snapshot::Snapshot;
+
+use std::{
+    borrow::Borrow,
+    fmt::{self, Debug, Formatter},
+    hash::{BuildHasher, Hash},
+};
+
+use crossbeam_epoch::Guard;
+
+/// A view of the entries of a hash map at about the time it was taken, which
+/// can be read and iterated repeatedly while the map is modified.
+///
+/// A view is taken with the `snapshot_view` methods of the maps. It holds the
+/// buckets of the entries that were in the map, which are immutable, and keeps
+/// the current thread pinned so that none of them is destroyed while the view
+/// is alive, even if its entry is removed or replaced. Nothing is cloned, and
+/// reads of the view always return the same entries, so a report that reads
+/// the view several times is consistent with itself. Use
+/// [`to_snapshot`] to clone the entries into a [`Snapshot`] that can outlive
+/// the view.
+///
+/// Taking a view is weakly consistent: it reflects the contents of the map at
+/// some point at or after the start of the call, and modifications made while
+/// it is being taken may or may not be observed.
+///
+/// Like a [`Ref`], a view prevents the garbage collector from reclaiming
+/// memory for all hash maps in the process while it is alive, so it should be
+/// dropped as soon as possible, and it cannot be sent to another thread.
+///
+/// [`to_snapshot`]: #method.to_snapshot
+/// [`Snapshot`]: ../snapshot/struct.Snapshot.html
+/// [`Ref`]: ./struct.Ref.html
+///
+/// # Examples
+///
+/// ```rust
+/// use moka_cht::HashMap;
+///
+/// let map = HashMap::new();
+/// map.insert("foo", 1);
+/// map.insert("bar", 2);
+///
+/// let view = map.snapshot_view();
+///
+/// map.insert("foo", 3);
+/// map.remove("bar");
+///
+/// assert_eq!(view.get("foo"), Some(&1));
+/// assert_eq!(view.get("bar"), Some(&2));
+/// assert_eq!(view.iter().map(|(_, v)| v).sum::<i32>(), 3);
+/// ```
+pub struct SnapshotView<'a, K, V, S = DefaultHashBuilder> {
+    _guard: Guard,
+    // the buckets with the hashes of their keys, sorted by hash
+    buckets: Vec<(u64, *const Bucket<K, V>)>,
+    build_hasher: &'a S,
+}
+
+impl<'a, K, V, S> SnapshotView<'a, K, V, S> {
+    /// # Safety
+    ///
+    /// The buckets must be non-tombstone buckets that were loaded while the
+    /// current thread was pinned, and `guard` must have been pinned before
+    /// those loads and continuously since.
+    pub(crate) unsafe fn new(
+        guard: Guard,
+        mut buckets: Vec<(u64, *const Bucket<K, V>)>,
+        build_hasher: &'a S,
+    ) -> Self {
+        buckets.sort_unstable_by_key(|&(hash, _)| hash);
+
+        Self {
+            _guard: guard,
+            buckets,
+            build_hasher,
+        }
+    }
+
+    /// Returns the number of entries in the view.
+    pub fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// Returns `true` if the view has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// Returns an iterator over the entries of the view, in the order of the
+    /// hashes of their keys.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.buckets
+            .iter()
+            .map(|&(_, b)| unsafe { (&(*b).key, (*b).value()) })
+    }
+
+    /// Returns clones of the entries of the view, along with their hashes.
+    ///
+    /// See the [`snapshot`] module for details.
+    ///
+    /// [`snapshot`]: ../snapshot/index.html
+    pub fn to_snapshot(&self) -> Snapshot<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        Snapshot::new(
+            self.buckets
+                .iter()
+                .map(|&(hash, b)| unsafe { (hash, (*b).key.clone(), (*b).value().clone()) })
+                .collect(),
+        )
+    }
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> SnapshotView<'a, K, V, S> {
+    /// Returns a reference to the value corresponding to the key in the view.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn get<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        self.get_key_value(key).map(|(_, v)| v)
+    }
+
+    /// Returns references to the key-value pair corresponding to the key in
+    /// the view.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn get_key_value<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+    {
+        let hash = bucket::hash(self.build_hasher, key);
+        let start = self.buckets.partition_point(|&(h, _)| h < hash);
+
+        self.buckets[start..]
+            .iter()
+            .take_while(|&&(h, _)| h == hash)
+            .map(|&(_, b)| unsafe { (&(*b).key, (*b).value()) })
+            .find(|(k, _)| (*k).borrow() == key)
+    }
+
+    /// Returns `true` if the view contains an entry for the key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn contains_key<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.get_key_value(key).is_some()
+    }
+}
+
+impl<'a, K: Debug, V: Debug, S> Debug for SnapshotView<'a, K, V, S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
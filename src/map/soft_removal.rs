@@ -0,0 +1,239 @@
+use super::{
+    bucket::{self, Bucket},
+    scrub::Scrub,
+};
+
+use std::{
+    borrow::Borrow, collections::HashMap as StdHashMap, hash::Hash, mem, ptr::NonNull, sync::Mutex,
+};
+
+/// The entries of a map that were soft-removed and not purged yet.
+///
+/// A soft-removed entry is removed from the bucket pointer array like any
+/// other, so that no read path has to tell it apart from a live entry, but
+/// its bucket stays pinned, which keeps the key and the value alive until the
+/// entry is purged or restored.
+pub(crate) struct SoftRemovals<K, V> {
+    // the pinned buckets by the hashes of their keys
+    buckets: Mutex<StdHashMap<u64, Vec<Pinned<K, V>>>>,
+}
+
+impl<K, V> Default for SoftRemovals<K, V> {
+    fn default() -> Self {
+        Self {
+            buckets: Mutex::new(StdHashMap::new()),
+        }
+    }
+}
+
+impl<K, V> SoftRemovals<K, V> {
+    /// Drops the soft-removed entries, returning their number. The values are
+    /// dropped once the guards that may still reference them are dropped.
+    pub(crate) fn purge(&self) -> usize {
+        let buckets = mem::take(&mut *self.buckets.lock().unwrap_or_else(|e| e.into_inner()));
+
+        // the keys and the values are dropped outside of the lock
+        buckets.into_values().map(|pinned| pinned.len()).sum()
+    }
+
+    /// Drops the soft-removed entries whose keys `predicate` returns `true`
+    /// for, returning their number. Their values are scrubbed before they are
+    /// dropped, and so are the keys of the buckets that the map no longer
+    /// holds; the other keys stay in the tombstones of their buckets.
+    ///
+    /// # Safety
+    ///
+    /// The caller must have exclusive access to the map that owns `self`.
+    pub(crate) unsafe fn purge_if<P: FnMut(&K) -> bool>(
+        &mut self,
+        mut predicate: P,
+        scrub: &mut impl Scrub<K, V>,
+    ) -> usize {
+        let buckets = self.buckets.get_mut().unwrap_or_else(|e| e.into_inner());
+        let mut purged = 0;
+
+        buckets.retain(|_, same_hash| {
+            let mut kept = Vec::new();
+
+            for mut pinned in same_hash.drain(..) {
+                if !predicate(pinned.key()) {
+                    kept.push(pinned);
+                    continue;
+                }
+
+                // no other pin can read the bucket while the map is borrowed
+                // mutably
+                let bucket = pinned.bucket.as_mut();
+                scrub.scrub_value(bucket.value.get_mut());
+
+                if bucket.is_released() {
+                    scrub.scrub_key(&mut bucket.key);
+                }
+
+                pinned.unpin_exclusive();
+                purged += 1;
+            }
+
+            *same_hash = kept;
+
+            !same_hash.is_empty()
+        });
+
+        purged
+    }
+}
+
+impl<K: Hash + Eq, V> SoftRemovals<K, V> {
+    /// Pins the bucket that `get` finds and removes it with `remove`, which
+    /// must only remove the entry whose key is at the given address, until
+    /// either `get` finds no bucket or the removal succeeds. Returns `false`
+    /// in the former case.
+    ///
+    /// # Safety
+    ///
+    /// The buckets that `get` returns must be protected by a guard, and must
+    /// belong to the map that owns `self`.
+    pub(crate) unsafe fn soft_remove<'g, G, R>(&self, hash: u64, mut get: G, mut remove: R) -> bool
+    where
+        K: 'g,
+        V: 'g,
+        G: FnMut() -> Option<&'g Bucket<K, V>>,
+        R: FnMut(*const K) -> bool,
+    {
+        loop {
+            let bucket = match get() {
+                Some(bucket) => bucket,
+                None => return false,
+            };
+
+            // the entry was removed since it was found
+            let pinned = match Pinned::new(bucket) {
+                Some(pinned) => pinned,
+                None => continue,
+            };
+
+            if remove(pinned.key()) {
+                self.park(hash, pinned);
+
+                return true;
+            }
+        }
+    }
+
+    /// Takes the soft-removed entry of `key` out, returning `None` if there is
+    /// none.
+    pub(crate) fn take<Q: Hash + Eq + ?Sized>(&self, hash: u64, key: &Q) -> Option<Pinned<K, V>>
+    where
+        K: Borrow<Q>,
+    {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let pinned = buckets.get_mut(&hash)?;
+        let index = pinned.iter().position(|p| p.key().borrow() == key)?;
+        let result = pinned.swap_remove(index);
+
+        if pinned.is_empty() {
+            buckets.remove(&hash);
+        }
+
+        Some(result)
+    }
+
+    /// Keeps `pinned`, replacing an older soft-removed entry of its key.
+    fn park(&self, hash: u64, pinned: Pinned<K, V>) {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let same_hash = buckets.entry(hash).or_default();
+
+        match same_hash.iter_mut().find(|p| p.key() == pinned.key()) {
+            Some(older) => *older = pinned,
+            None => same_hash.push(pinned),
+        }
+    }
+}
+
+/// A pin on the bucket of a soft-removed entry.
+///
+/// This is an [`EntryPin`] without a lifetime, which the map can own.
+///
+/// [`EntryPin`]: ./struct.EntryPin.html
+pub(crate) struct Pinned<K, V> {
+    bucket: NonNull<Bucket<K, V>>,
+}
+
+impl<K, V> Pinned<K, V> {
+    /// Pins the bucket, returning `None` if its entry has been removed.
+    ///
+    /// # Safety
+    ///
+    /// The bucket must be protected by a guard, and must belong to a map that
+    /// outlives the pin.
+    unsafe fn new(bucket: &Bucket<K, V>) -> Option<Self> {
+        if bucket.try_pin() {
+            Some(Self {
+                bucket: NonNull::from(bucket),
+            })
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn key(&self) -> &K {
+        unsafe { &self.bucket.as_ref().key }
+    }
+
+    pub(crate) fn value(&self) -> &V {
+        unsafe { self.bucket.as_ref().value() }
+    }
+
+    /// Drops the pin, dropping the value right away if the pin retires it.
+    ///
+    /// # Safety
+    ///
+    /// The caller must have exclusive access to the map that owns the bucket.
+    unsafe fn unpin_exclusive(self) {
+        let bucket = self.bucket;
+        mem::forget(self);
+
+        bucket::unpin_exclusive(bucket.as_ptr());
+    }
+}
+
+impl<K, V> Drop for Pinned<K, V> {
+    fn drop(&mut self) {
+        unsafe { bucket::unpin(self.bucket.as_ptr()) };
+    }
+}
+
+// the last pin to be dropped may drop the key and the value
+unsafe impl<K: Send + Sync, V: Send + Sync> Send for Pinned<K, V> {}
+unsafe impl<K: Send + Sync, V: Send + Sync> Sync for Pinned<K, V> {}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_util, HashMap};
+
+    use std::sync::Arc;
+
+    #[test]
+    fn drop_values() {
+        let value = Arc::new(0);
+        let map = HashMap::new();
+        map.insert(0, Arc::clone(&value));
+        map.insert(1, Arc::clone(&value));
+
+        assert!(map.soft_remove(&0));
+        assert!(map.soft_remove(&1));
+        test_util::run_deferred();
+
+        // the soft-removed values are kept until they are purged
+        assert_eq!(Arc::strong_count(&value), 3);
+        assert!(map.undelete(&1));
+        assert_eq!(map.purge(), 1);
+        test_util::run_deferred();
+        assert_eq!(Arc::strong_count(&value), 2);
+
+        assert!(map.soft_remove(&1));
+        drop(map);
+        test_util::run_deferred();
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
@@ -0,0 +1,62 @@
+use crate::ordering;
+
+use std::sync::atomic::AtomicUsize;
+
+/// The size and load of a hash map, as seen by the predicate of an
+/// `insert_if` call.
+///
+/// For a `HashMap`, the segment is the whole map. For a `SegmentedHashMap`, it
+/// is the segment that the key would be inserted into.
+///
+/// The lengths include a reservation for the key being inserted and for the
+/// keys of all other `insert_if` calls in progress, so concurrent calls see
+/// distinct lengths. For example, a predicate that admits a key only while
+/// `map_len()` is at most a bound never lets those calls grow the map past it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MapState {
+    pub(crate) map_len: usize,
+    pub(crate) segment_len: usize,
+    pub(crate) segment_capacity: usize,
+}
+
+impl MapState {
+    /// Returns the number of elements in the map, counting the key being
+    /// inserted.
+    pub fn map_len(&self) -> usize {
+        self.map_len
+    }
+
+    /// Returns the number of elements in the segment, counting the key being
+    /// inserted.
+    pub fn segment_len(&self) -> usize {
+        self.segment_len
+    }
+
+    /// Returns the number of elements the segment can hold without
+    /// reallocating its bucket pointer array.
+    pub fn segment_capacity(&self) -> usize {
+        self.segment_capacity
+    }
+}
+
+/// A slot reserved in a length by `insert_if`, which is released when the
+/// reservation is dropped, even if the predicate panics.
+pub(crate) struct Reservation<'a> {
+    len: &'a AtomicUsize,
+}
+
+impl<'a> Reservation<'a> {
+    /// Reserves a slot in `len`, returning the reservation and the length
+    /// including it.
+    pub(crate) fn acquire(len: &'a AtomicUsize) -> (Self, usize) {
+        let reserved_len = len.fetch_add(1, ordering::RELAXED) + 1;
+
+        (Self { len }, reserved_len)
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        self.len.fetch_sub(1, ordering::RELAXED);
+    }
+}
@@ -0,0 +1,76 @@
+//! The storage of values within buckets.
+
+use std::{
+    mem::{self, MaybeUninit},
+    ptr,
+};
+
+/// Stores the value inline in the heap-allocated bucket.
+///
+/// A slot does not drop its value when it is dropped; the bucket code is
+/// responsible for calling [`take`] or [`drop_value`] exactly once for every
+/// value passed to [`new`] or [`replace`].
+///
+/// [`take`]: #method.take
+/// [`drop_value`]: #method.drop_value
+/// [`new`]: #method.new
+/// [`replace`]: #method.replace
+#[derive(Debug)]
+pub(crate) struct ValueSlot<V>(MaybeUninit<V>);
+
+impl<V> ValueSlot<V> {
+    /// Creates a slot holding `value`.
+    pub(crate) fn new(value: V) -> Self {
+        Self(MaybeUninit::new(value))
+    }
+
+    /// Returns a reference to the stored value.
+    ///
+    /// # Safety
+    ///
+    /// The slot must hold a value, i.e. it has not been moved out by [`take`]
+    /// or destroyed by [`drop_value`].
+    ///
+    /// [`take`]: #method.take
+    /// [`drop_value`]: #method.drop_value
+    pub(crate) unsafe fn get(&self) -> &V {
+        &*self.0.as_ptr()
+    }
+
+    /// Returns a mutable reference to the stored value.
+    ///
+    /// # Safety
+    ///
+    /// The slot must hold a value.
+    pub(crate) unsafe fn get_mut(&mut self) -> &mut V {
+        &mut *self.0.as_mut_ptr()
+    }
+
+    /// Moves the stored value out, leaving the slot without a value.
+    ///
+    /// # Safety
+    ///
+    /// The slot must hold a value, and the caller must ensure that nothing
+    /// accesses the value through the slot afterwards.
+    pub(crate) unsafe fn take(&self) -> V {
+        ptr::read(self.0.as_ptr())
+    }
+
+    /// Replaces the stored value, returning the previous one.
+    ///
+    /// # Safety
+    ///
+    /// The slot must hold a value.
+    pub(crate) unsafe fn replace(&mut self, value: V) -> V {
+        mem::replace(&mut self.0, MaybeUninit::new(value)).assume_init()
+    }
+
+    /// Drops the stored value in place, leaving the slot without a value.
+    ///
+    /// # Safety
+    ///
+    /// The slot must hold a value.
+    pub(crate) unsafe fn drop_value(&mut self) {
+        ptr::drop_in_place(self.0.as_mut_ptr());
+    }
+}
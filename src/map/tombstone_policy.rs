@@ -0,0 +1,76 @@
+/// How a hash map reclaims the tombstones that its removals leave behind.
+///
+/// A removal through a shared reference turns the bucket of the entry into a
+/// tombstone, which keeps its slot in the bucket pointer array so that
+/// concurrent lookups of other keys are not cut short. Tombstones take up
+/// memory and lengthen the probes that cross them until they are reclaimed,
+/// and reclaiming them costs a pass over the array. The policies trade these
+/// costs differently:
+///
+/// - [`KeepUntilRehash`] never spends a pass on tombstones alone: they are
+///   only reclaimed when the array is resized, either because it is full or
+///   because housekeeping shrinks it. This suits maps whose removals are
+///   followed by insertions that reuse their slots.
+/// - [`CompactAtRatio`] makes housekeeping rebuild the array once the
+///   tombstones take up the given fraction of its capacity, which bounds their
+///   memory and their effect on probe lengths at the cost of the rebuilds.
+/// - [`BackwardShiftInExclusiveMode`] leaves the concurrent operations and
+///   housekeeping alone, and reclaims every tombstone in place when the map is
+///   borrowed exclusively with its `exclusive` method, shifting the entries
+///   that follow each tombstone back into its slot. This suits maps that go
+///   through exclusive phases, for example a periodic rebuild, and should not
+///   be compacted while they are shared.
+///
+/// Removals through an `Exclusive` view never leave tombstones behind,
+/// whatever the policy.
+///
+/// Both hash maps use `CompactAtRatio(0.25)` by default. It can be changed
+/// with the `tombstone_policy` methods of their builders.
+///
+/// [`KeepUntilRehash`]: #variant.KeepUntilRehash
+/// [`CompactAtRatio`]: #variant.CompactAtRatio
+/// [`BackwardShiftInExclusiveMode`]: #variant.BackwardShiftInExclusiveMode
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TombstonePolicy {
+    /// Keeps the tombstones until the bucket pointer array is resized.
+    KeepUntilRehash,
+    /// Reclaims the tombstones during housekeeping once they take up at least
+    /// the given fraction of the capacity of the bucket pointer array, which
+    /// must be greater than 0 and at most 1.
+    CompactAtRatio(f64),
+    /// Reclaims the tombstones in place whenever the map is borrowed
+    /// exclusively.
+    BackwardShiftInExclusiveMode,
+}
+
+impl TombstonePolicy {
+    /// Returns whether housekeeping should reclaim `num_tombstones` tombstones
+    /// from a bucket pointer array of the given capacity.
+    pub(crate) fn should_compact(self, num_tombstones: usize, capacity: usize) -> bool {
+        match self {
+            TombstonePolicy::CompactAtRatio(ratio) => {
+                num_tombstones > 0 && num_tombstones as f64 >= capacity as f64 * ratio
+            }
+            TombstonePolicy::KeepUntilRehash | TombstonePolicy::BackwardShiftInExclusiveMode => {
+                false
+            }
+        }
+    }
+
+    /// Panics if the policy is not valid.
+    pub(crate) fn validate(self) {
+        if let TombstonePolicy::CompactAtRatio(ratio) = self {
+            assert!(
+                ratio > 0.0 && ratio <= 1.0,
+                "the compaction ratio must be greater than 0 and at most 1"
+            );
+        }
+    }
+}
+
+impl Default for TombstonePolicy {
+    fn default() -> Self {
+        TombstonePolicy::CompactAtRatio(0.25)
+    }
+}
@@ -0,0 +1,415 @@
+//! Telemetry for the hash maps in this crate.
+//!
+//! Metrics are pulled from a map by the embedder, typically from a periodic
+//! task, and pushed to a [`MetricsRecorder`] that forwards them to the
+//! embedder's monitoring system.
+//!
+//! # Probe Anomaly Score
+//!
+//! With a good hash function, the keys of a map are spread uniformly over its
+//! bucket pointer array, and the number of buckets that must be probed to find
+//! a key closely follows its expected value for linear probing. An adversary
+//! who can choose keys that collide under the map's hash function makes the
+//! probe sequences grow far beyond that value, which degrades latency long
+//! before it becomes visible elsewhere.
+//!
+//! [`ProbeStats::anomaly_score`] is the ratio of the observed mean probe length
+//! to the expected one, so it stays close to 1 under normal operation.
+//! [`ProbeAnomalyTracker`] smooths the scores of consecutive observations and
+//! reports them to a [`MetricsRecorder`], so that operators can alert on a
+//! sustained high score.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use moka_cht::{
+//!     metrics::{MetricsRecorder, ProbeAnomalyTracker},
+//!     SegmentedHashMap,
+//! };
+//!
+//! struct Alerter;
+//!
+//! impl MetricsRecorder for Alerter {
+//!     fn record_probe_anomaly_score(&self, segment: usize, score: f64) {
+//!         if score > 10.0 {
+//!             eprintln!("suspicious key pattern in segment {}", segment);
+//!         }
+//!     }
+//! }
+//!
+//! let map = SegmentedHashMap::with_num_segments(4);
+//!
+//! for i in 0..1024 {
+//!     map.insert(i, i);
+//! }
+//!
+//! let mut tracker = ProbeAnomalyTracker::new(0.5);
+//!
+//! // call periodically
+//! tracker.observe(&map.probe_stats(), &Alerter);
+//! assert!(tracker.scores().iter().all(|&s| s < 10.0));
+//! ```
+//!
+//! # Latency Histograms
+//!
+//! When the `instrument` feature is enabled, the hash maps record coarse
+//! histograms of the latencies of lookups, insertions, removals, and of the
+//! time operations spend helping to migrate entries during a resize. They are
+//! returned by the `latency_stats` methods as [`LatencyStats`]. The feature is
+//! disabled by default because it reads the clock twice per operation.
+//!
+//! # Per-Thread Operation Counts
+//!
+//! The `instrument` feature also enables counting the lookup hits and misses
+//! and the insertions performed by each thread, over all hash maps. Each thread
+//! only writes its own counters, so no shared atomic is updated per
+//! operation. The counts are gathered on demand by `thread_op_counts`, which
+//! lets a thread-per-core server attribute map load to specific threads.
+//!
+//! # Per-Handle Operation Counts
+//!
+//! A map shared through an `Arc` can hand out handles with its `handle`
+//! method, one per subsystem that uses the map. The operations performed
+//! through a handle and its clones are counted under the ID of the handle,
+//! and the `report_handle_op_counts` methods of the maps push the counts of
+//! all handles to a [`MetricsRecorder`], which tells which subsystem is
+//! hammering the map. Unlike the per-thread counts, these do not require the
+//! `instrument` feature.
+//!
+//! # Operation Sampling
+//!
+//! The `sample_operations` methods of the builders register a callback that
+//! is invoked for about one in N lookups, insertions, and removals, picked at
+//! random, with an [`OperationSample`] describing the operation: its type, the
+//! length of the probe sequence of its key, its segment, and the hash of its
+//! key. This gives a picture of the access patterns of a map in production at
+//! a fraction of the cost of the `instrument` feature, as the operations that
+//! are not sampled only draw a thread-local random number.
+//!
+//! # Operation Reports
+//!
+//! With the `instrument` feature, the `insert_reported` and `remove_reported`
+//! methods of the maps return an [`OpReport`] along with their results, which
+//! tells how long the probe sequence of the key is after the operation,
+//! whether the operation started a resize, and how many times it had to start
+//! over because of a concurrent resize. This lets adaptive callers, such as
+//! admission policies, back off from the keys and segments where writes
+//! become expensive. The other operations do not pay for the reports.
+//!
+//! [`MetricsRecorder`]: ./trait.MetricsRecorder.html
+//! [`OpReport`]: ./struct.OpReport.html
+//! [`LatencyStats`]: ./struct.LatencyStats.html
+//! [`ProbeStats::anomaly_score`]: ./struct.ProbeStats.html#method.anomaly_score
+//! [`ProbeAnomalyTracker`]: ./struct.ProbeAnomalyTracker.html
+//! [`OperationSample`]: ./struct.OperationSample.html
+
+mod handle;
+#[cfg(any(feature = "instrument", feature = "workload"))]
+mod latency;
+#[cfg(feature = "instrument")]
+mod report;
+mod sampling;
+#[cfg(feature = "instrument")]
+mod thread;
+
+pub(crate) use handle::{HandleCounters, HandleRegistry};
+pub use handle::{HandleOpCounts, HandleOpStats};
+#[cfg(any(feature = "instrument", feature = "workload"))]
+pub use latency::LatencySnapshot;
+#[cfg(feature = "instrument")]
+pub use latency::LatencyStats;
+#[cfg(feature = "workload")]
+pub(crate) use latency::LocalHistogram;
+#[cfg(feature = "instrument")]
+pub(crate) use latency::{Latencies, Timer};
+#[cfg(feature = "instrument")]
+pub(crate) use report::record;
+#[cfg(feature = "instrument")]
+pub use report::OpReport;
+pub(crate) use sampling::{random, Sampler};
+pub use sampling::{OperationSample, SampledOperation};
+#[cfg(feature = "instrument")]
+pub use thread::{current_thread_op_counts, thread_op_counts, ThreadOpCounts, ThreadOpStats};
+
+/// A type of operation whose latency is recorded when the `instrument` feature
+/// is enabled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Operation {
+    Get,
+    Insert,
+    Remove,
+    RehashAssist,
+}
+
+/// A no-op stand-in for the latency timer when the `instrument` feature is
+/// disabled.
+#[cfg(not(feature = "instrument"))]
+#[derive(Default)]
+pub(crate) struct Timer<'a>(std::marker::PhantomData<&'a ()>);
+
+/// Counts a lookup in the operation counts of the current thread.
+#[inline]
+pub(crate) fn count_lookup(hit: bool) {
+    #[cfg(feature = "instrument")]
+    thread::count_lookup(hit);
+    #[cfg(not(feature = "instrument"))]
+    let _ = hit;
+}
+
+/// Counts an insertion in the operation counts of the current thread.
+#[inline]
+pub(crate) fn count_insert() {
+    #[cfg(feature = "instrument")]
+    thread::count_insert();
+}
+
+/// Returns `true` if the current thread records the report of the operation
+/// it performs.
+#[cfg(feature = "instrument")]
+#[inline]
+pub(crate) fn is_recording_report() -> bool {
+    report::is_recording()
+}
+
+/// Returns `false`, as no reports are recorded without the `instrument`
+/// feature.
+#[cfg(not(feature = "instrument"))]
+#[inline]
+pub(crate) fn is_recording_report() -> bool {
+    false
+}
+
+/// Notes the probe length of the key after the operation in the report of the
+/// current thread, if it is recorded.
+#[inline]
+pub(crate) fn note_probe_length(probe_length: usize) {
+    #[cfg(feature = "instrument")]
+    report::note_probe_length(probe_length);
+    #[cfg(not(feature = "instrument"))]
+    let _ = probe_length;
+}
+
+/// Notes that the operation started a resize in the report of the current
+/// thread, if it is recorded.
+#[inline]
+pub(crate) fn note_resize() {
+    #[cfg(feature = "instrument")]
+    report::note_resize();
+}
+
+/// Notes that the operation started over in a new bucket pointer array in the
+/// report of the current thread, if it is recorded.
+#[inline]
+pub(crate) fn note_retry() {
+    #[cfg(feature = "instrument")]
+    report::note_retry();
+}
+
+/// A sink for the metrics of a hash map.
+///
+/// All methods have empty default implementations, so implementors only need
+/// to override the ones for the metrics they are interested in.
+pub trait MetricsRecorder {
+    /// Records the smoothed probe anomaly score of a segment.
+    ///
+    /// For a `HashMap`, `segment` is always 0.
+    fn record_probe_anomaly_score(&self, segment: usize, score: f64) {
+        let _ = (segment, score);
+    }
+
+    /// Records the operation counts of a map handle, identified by its ID.
+    fn record_handle_op_counts(&self, handle: u64, counts: &HandleOpCounts) {
+        let _ = (handle, counts);
+    }
+}
+
+/// Statistics on the probe lengths of the entries of a bucket pointer array.
+///
+/// The probe length of an entry is the number of buckets that must be probed
+/// to find it. Statistics are collected by scanning the whole bucket pointer
+/// array, so they should not be collected on every operation.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ProbeStats {
+    pub(crate) num_buckets: usize,
+    pub(crate) num_occupied: usize,
+    pub(crate) num_entries: usize,
+    pub(crate) total_probe_length: usize,
+    pub(crate) max_probe_length: usize,
+}
+
+impl ProbeStats {
+    /// Returns the number of buckets in the bucket pointer array.
+    pub fn num_buckets(&self) -> usize {
+        self.num_buckets
+    }
+
+    /// Returns the number of entries that were scanned.
+    pub fn num_entries(&self) -> usize {
+        self.num_entries
+    }
+
+    /// Returns the largest probe length of any entry.
+    pub fn max_probe_length(&self) -> usize {
+        self.max_probe_length
+    }
+
+    /// Returns the mean probe length of the entries, or 0 if there are none.
+    pub fn mean_probe_length(&self) -> f64 {
+        if self.num_entries == 0 {
+            0.0
+        } else {
+            self.total_probe_length as f64 / self.num_entries as f64
+        }
+    }
+
+    /// Returns the mean probe length expected for linear probing with
+    /// uniformly distributed hashes at the current load.
+    ///
+    /// Removed entries that have not been reclaimed yet are counted towards the
+    /// load, as they lengthen probe sequences in the same way.
+    pub fn expected_mean_probe_length(&self) -> f64 {
+        if self.num_buckets == 0 {
+            return 1.0;
+        }
+
+        let load = (self.num_occupied as f64 / self.num_buckets as f64).min(0.99);
+
+        0.5 * (1.0 + 1.0 / (1.0 - load))
+    }
+
+    /// Returns the ratio of the observed mean probe length to the expected
+    /// one.
+    ///
+    /// The score is close to 1 when hashes are uniformly distributed and grows
+    /// with the severity of hash collisions. It is 1 if there are no entries.
+    pub fn anomaly_score(&self) -> f64 {
+        if self.num_entries == 0 {
+            1.0
+        } else {
+            self.mean_probe_length() / self.expected_mean_probe_length()
+        }
+    }
+}
+
+/// Tracks an exponentially weighted moving average of the probe anomaly score
+/// of each segment of a map.
+#[derive(Clone, Debug)]
+pub struct ProbeAnomalyTracker {
+    smoothing: f64,
+    scores: Vec<f64>,
+}
+
+impl ProbeAnomalyTracker {
+    /// Creates a tracker with the given smoothing factor.
+    ///
+    /// The smoothing factor is the weight of a new observation in the moving
+    /// average, and is clamped to the range `(0, 1]`. A factor of 1 disables
+    /// smoothing.
+    pub fn new(smoothing: f64) -> Self {
+        Self {
+            smoothing: smoothing.clamp(f64::MIN_POSITIVE, 1.0),
+            scores: Vec::new(),
+        }
+    }
+
+    /// Updates the scores with one set of statistics per segment and reports
+    /// the smoothed scores to `recorder`.
+    ///
+    /// The first observation of a segment initializes its score.
+    pub fn observe(&mut self, stats: &[ProbeStats], recorder: &dyn MetricsRecorder) {
+        self.scores.truncate(stats.len());
+        let num_known = self.scores.len();
+
+        for (segment, s) in stats.iter().enumerate() {
+            let score = s.anomaly_score();
+
+            if segment < num_known {
+                let previous = self.scores[segment];
+                self.scores[segment] = previous + self.smoothing * (score - previous);
+            } else {
+                self.scores.push(score);
+            }
+
+            recorder.record_probe_anomaly_score(segment, self.scores[segment]);
+        }
+    }
+
+    /// Returns the smoothed score of each segment.
+    pub fn scores(&self) -> &[f64] {
+        &self.scores
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::HashMap;
+
+    use std::{
+        cell::RefCell,
+        hash::{BuildHasherDefault, Hasher},
+    };
+
+    #[derive(Default)]
+    struct ConstantHasher;
+
+    impl Hasher for ConstantHasher {
+        fn finish(&self) -> u64 {
+            0
+        }
+
+        fn write(&mut self, _: &[u8]) {}
+    }
+
+    #[derive(Default)]
+    struct Recorder(RefCell<Vec<(usize, f64)>>);
+
+    impl MetricsRecorder for Recorder {
+        fn record_probe_anomaly_score(&self, segment: usize, score: f64) {
+            self.0.borrow_mut().push((segment, score));
+        }
+    }
+
+    #[test]
+    fn uniform_hashes() {
+        let map = HashMap::new();
+        assert_eq!(map.probe_stats(), ProbeStats::default());
+        assert_eq!(map.probe_stats().anomaly_score(), 1.0);
+
+        for i in 0..1024 {
+            map.insert(i, i);
+        }
+
+        let stats = map.probe_stats();
+        assert_eq!(stats.num_entries(), 1024);
+        assert!(stats.anomaly_score() < 2.0, "{:?}", stats);
+
+        crate::test_util::run_deferred();
+    }
+
+    #[test]
+    fn colliding_hashes() {
+        let map = HashMap::with_hasher(BuildHasherDefault::<ConstantHasher>::default());
+
+        for i in 0..64 {
+            map.insert(i, i);
+        }
+
+        let stats = map.probe_stats();
+        assert_eq!(stats.max_probe_length(), 64);
+        assert!(stats.anomaly_score() > 10.0, "{:?}", stats);
+
+        let recorder = Recorder::default();
+        let mut tracker = ProbeAnomalyTracker::new(0.5);
+
+        tracker.observe(&[ProbeStats::default()], &recorder);
+        tracker.observe(&[stats], &recorder);
+
+        let expected = 1.0 + 0.5 * (stats.anomaly_score() - 1.0);
+        assert_eq!(tracker.scores(), &[expected]);
+        assert_eq!(*recorder.0.borrow(), vec![(0, 1.0), (0, expected)]);
+
+        crate::test_util::run_deferred();
+    }
+}
@@ -0,0 +1,210 @@
+use crate::ordering;
+
+use std::{
+    ops::AddAssign,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize},
+        Arc, Mutex,
+    },
+};
+
+/// The counters of a map handle, shared by its clones.
+///
+/// Clones of a handle may be used from several threads, so the counters are
+/// incremented with read-modify-write operations.
+#[derive(Debug)]
+pub(crate) struct HandleCounters {
+    id: u64,
+    clones: AtomicUsize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    inserts: AtomicU64,
+    modifies: AtomicU64,
+    removes: AtomicU64,
+}
+
+impl HandleCounters {
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Counts a new clone of the handle.
+    pub(crate) fn add_clone(&self) {
+        self.clones.fetch_add(1, ordering::RELAXED);
+    }
+
+    pub(crate) fn count_lookup(&self, hit: bool) {
+        if hit {
+            self.hits.fetch_add(1, ordering::RELAXED);
+        } else {
+            self.misses.fetch_add(1, ordering::RELAXED);
+        }
+    }
+
+    pub(crate) fn count_insert(&self) {
+        self.inserts.fetch_add(1, ordering::RELAXED);
+    }
+
+    pub(crate) fn count_modify(&self) {
+        self.modifies.fetch_add(1, ordering::RELAXED);
+    }
+
+    pub(crate) fn count_remove(&self) {
+        self.removes.fetch_add(1, ordering::RELAXED);
+    }
+
+    pub(crate) fn counts(&self) -> HandleOpCounts {
+        HandleOpCounts {
+            hits: self.hits.load(ordering::RELAXED),
+            misses: self.misses.load(ordering::RELAXED),
+            inserts: self.inserts.load(ordering::RELAXED),
+            modifies: self.modifies.load(ordering::RELAXED),
+            removes: self.removes.load(ordering::RELAXED),
+        }
+    }
+}
+
+/// The handles of a map, and the combined counts of the handles that were
+/// dropped.
+#[derive(Debug, Default)]
+pub(crate) struct HandleRegistry {
+    inner: Mutex<RegistryInner>,
+}
+
+#[derive(Debug, Default)]
+struct RegistryInner {
+    next_id: u64,
+    handles: Vec<Arc<HandleCounters>>,
+    dropped: HandleOpCounts,
+}
+
+impl HandleRegistry {
+    /// Registers the counters of a new handle with the next ID.
+    pub(crate) fn register(&self) -> Arc<HandleCounters> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+
+        let counters = Arc::new(HandleCounters {
+            id: inner.next_id,
+            clones: AtomicUsize::new(1),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            inserts: AtomicU64::new(0),
+            modifies: AtomicU64::new(0),
+            removes: AtomicU64::new(0),
+        });
+        inner.next_id += 1;
+        inner.handles.push(Arc::clone(&counters));
+
+        counters
+    }
+
+    /// Counts a dropped clone of a handle, unregistering the handle and adding
+    /// its counts to the counts of dropped handles when it was the last one.
+    pub(crate) fn drop_handle(&self, counters: &Arc<HandleCounters>) {
+        if counters.clones.fetch_sub(1, ordering::ACQ_REL) != 1 {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+
+        inner.handles.retain(|c| !Arc::ptr_eq(c, counters));
+        inner.dropped += counters.counts();
+    }
+
+    pub(crate) fn op_counts(&self) -> (Vec<HandleOpStats>, HandleOpCounts) {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+
+        let handles = inner
+            .handles
+            .iter()
+            .map(|c| HandleOpStats {
+                id: c.id,
+                counts: c.counts(),
+            })
+            .collect();
+
+        (handles, inner.dropped)
+    }
+}
+
+/// Operation counts of a map handle.
+///
+/// Returned by the `op_counts` methods of the handles and the
+/// `handle_op_counts` methods of the maps.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HandleOpCounts {
+    /// The number of lookups that found the key.
+    pub hits: u64,
+    /// The number of lookups that did not find the key.
+    pub misses: u64,
+    /// The number of calls to `insert` and `insert_or_modify`.
+    pub inserts: u64,
+    /// The number of calls to `modify`.
+    pub modifies: u64,
+    /// The number of removals, whether or not they found the key.
+    pub removes: u64,
+}
+
+impl AddAssign for HandleOpCounts {
+    fn add_assign(&mut self, other: Self) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.inserts += other.inserts;
+        self.modifies += other.modifies;
+        self.removes += other.removes;
+    }
+}
+
+/// The operation counts of a live map handle, returned by the
+/// `handle_op_counts` methods of the maps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HandleOpStats {
+    /// The ID of the handle, which is unique within its map.
+    pub id: u64,
+    /// The operation counts of the handle and its clones.
+    pub counts: HandleOpCounts,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry() {
+        let registry = HandleRegistry::default();
+        let first = registry.register();
+        let second = registry.register();
+        assert_eq!((first.id(), second.id()), (0, 1));
+
+        first.count_lookup(true);
+        first.count_lookup(false);
+        second.count_insert();
+        second.count_modify();
+        second.count_remove();
+
+        let (handles, dropped) = registry.op_counts();
+        assert_eq!(handles.len(), 2);
+        assert_eq!(handles[0].counts.hits, 1);
+        assert_eq!(handles[0].counts.misses, 1);
+        assert_eq!(dropped, HandleOpCounts::default());
+
+        // the handle is unregistered when its last clone is dropped
+        second.add_clone();
+        registry.drop_handle(&second);
+        assert_eq!(registry.op_counts().0.len(), 2);
+        registry.drop_handle(&second);
+
+        let (handles, dropped) = registry.op_counts();
+        assert_eq!(handles.len(), 1);
+        assert_eq!(handles[0].id, 0);
+        assert_eq!(
+            dropped,
+            HandleOpCounts {
+                inserts: 1,
+                modifies: 1,
+                removes: 1,
+                ..HandleOpCounts::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,283 @@
+#[cfg(feature = "instrument")]
+use super::Operation;
+#[cfg(feature = "instrument")]
+use crate::ordering;
+
+use std::time::Duration;
+#[cfg(feature = "instrument")]
+use std::{sync::atomic::AtomicU64, time::Instant};
+
+const NUM_BUCKETS: usize = 32;
+
+/// Returns the index of the histogram bucket that counts `duration`.
+fn bucket_index(duration: Duration) -> usize {
+    let nanos = duration.as_nanos().min(u128::from(u64::MAX)) as u64;
+
+    ((u64::BITS - nanos.leading_zeros()) as usize).min(NUM_BUCKETS - 1)
+}
+
+/// A lock-free histogram of durations with power-of-two nanosecond buckets.
+///
+/// The `i`-th bucket counts the durations of less than `2^i` nanoseconds that
+/// did not fit into any previous bucket. The last bucket also counts all longer
+/// durations.
+#[cfg(feature = "instrument")]
+#[derive(Debug, Default)]
+pub(crate) struct LatencyHistogram {
+    counts: [AtomicU64; NUM_BUCKETS],
+}
+
+#[cfg(feature = "instrument")]
+impl LatencyHistogram {
+    pub(crate) fn record(&self, duration: Duration) {
+        self.counts[bucket_index(duration)].fetch_add(1, ordering::RELAXED);
+    }
+
+    fn snapshot(&self) -> LatencySnapshot {
+        let mut counts = [0; NUM_BUCKETS];
+
+        for (count, c) in counts.iter_mut().zip(self.counts.iter()) {
+            *count = c.load(ordering::RELAXED);
+        }
+
+        LatencySnapshot { counts }
+    }
+}
+
+/// A histogram with the same buckets as [`LatencyHistogram`] that is only
+/// written by one thread, so recording does not need atomic operations.
+///
+/// [`LatencyHistogram`]: ./struct.LatencyHistogram.html
+#[cfg(feature = "workload")]
+#[derive(Clone, Debug, Default)]
+pub(crate) struct LocalHistogram {
+    counts: [u64; NUM_BUCKETS],
+}
+
+#[cfg(feature = "workload")]
+impl LocalHistogram {
+    pub(crate) fn record(&mut self, duration: Duration) {
+        self.counts[bucket_index(duration)] += 1;
+    }
+
+    pub(crate) fn merge(&mut self, other: &Self) {
+        for (count, c) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += c;
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> LatencySnapshot {
+        LatencySnapshot {
+            counts: self.counts,
+        }
+    }
+}
+
+/// The latency histograms of a hash map, one per operation type.
+#[cfg(feature = "instrument")]
+#[derive(Debug, Default)]
+pub(crate) struct Latencies {
+    get: LatencyHistogram,
+    insert: LatencyHistogram,
+    remove: LatencyHistogram,
+    rehash_assist: LatencyHistogram,
+}
+
+#[cfg(feature = "instrument")]
+impl Latencies {
+    pub(crate) fn start_timer(&self, operation: Operation) -> Timer<'_> {
+        let histogram = match operation {
+            Operation::Get => &self.get,
+            Operation::Insert => &self.insert,
+            Operation::Remove => &self.remove,
+            Operation::RehashAssist => &self.rehash_assist,
+        };
+
+        Timer {
+            histogram,
+            start: Instant::now(),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> LatencyStats {
+        LatencyStats {
+            get: self.get.snapshot(),
+            insert: self.insert.snapshot(),
+            remove: self.remove.snapshot(),
+            rehash_assist: self.rehash_assist.snapshot(),
+        }
+    }
+}
+
+/// Records the time elapsed since its creation when it is dropped.
+#[cfg(feature = "instrument")]
+pub(crate) struct Timer<'a> {
+    histogram: &'a LatencyHistogram,
+    start: Instant,
+}
+
+#[cfg(feature = "instrument")]
+impl<'a> Drop for Timer<'a> {
+    fn drop(&mut self) {
+        self.histogram.record(self.start.elapsed());
+    }
+}
+
+/// A point-in-time copy of a latency histogram.
+///
+/// Durations are recorded in power-of-two nanosecond buckets, so all values
+/// returned by this type are upper bounds with a relative error of up to 100%.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LatencySnapshot {
+    counts: [u64; NUM_BUCKETS],
+}
+
+impl LatencySnapshot {
+    /// Returns the number of recorded operations.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Returns an upper bound of the latency at quantile `q`, or `None` if no
+    /// operations have been recorded.
+    ///
+    /// `q` is clamped to the range `[0, 1]`, so `value_at_quantile(0.99)`
+    /// returns the 99th percentile.
+    pub fn value_at_quantile(&self, q: f64) -> Option<Duration> {
+        let count = self.count();
+
+        if count == 0 {
+            return None;
+        }
+
+        let rank = ((q.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+
+        self.buckets().find_map(|(upper_bound, c)| {
+            seen += c;
+
+            if seen >= rank {
+                Some(upper_bound)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Returns an iterator over the non-empty buckets of the histogram, as
+    /// pairs of the exclusive upper bound of the bucket and the number of
+    /// operations in it.
+    ///
+    /// The upper bound of the last bucket is reported as `Duration::MAX`.
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &c)| c > 0)
+            .map(|(i, &c)| {
+                let upper_bound = if i == NUM_BUCKETS - 1 {
+                    Duration::MAX
+                } else {
+                    Duration::from_nanos(1 << i)
+                };
+
+                (upper_bound, c)
+            })
+    }
+}
+
+/// The latency histograms of a hash map, returned by `latency_stats`.
+#[cfg(feature = "instrument")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LatencyStats {
+    get: LatencySnapshot,
+    insert: LatencySnapshot,
+    remove: LatencySnapshot,
+    rehash_assist: LatencySnapshot,
+}
+
+#[cfg(feature = "instrument")]
+impl LatencyStats {
+    /// Returns the latencies of lookups, including `get_many_ref` (per key).
+    pub fn get(&self) -> &LatencySnapshot {
+        &self.get
+    }
+
+    /// Returns the latencies of the `insert` family of methods.
+    pub fn insert(&self) -> &LatencySnapshot {
+        &self.insert
+    }
+
+    /// Returns the latencies of the `remove` family of methods.
+    pub fn remove(&self) -> &LatencySnapshot {
+        &self.remove
+    }
+
+    /// Returns the time spent by any operation helping to migrate entries to a
+    /// new bucket pointer array.
+    pub fn rehash_assist(&self) -> &LatencySnapshot {
+        &self.rehash_assist
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "instrument")]
+    #[test]
+    fn histogram() {
+        let histogram = LatencyHistogram::default();
+        assert_eq!(histogram.snapshot().value_at_quantile(0.5), None);
+
+        histogram.record(Duration::from_nanos(0));
+        histogram.record(Duration::from_nanos(100));
+        histogram.record(Duration::from_nanos(128));
+        histogram.record(Duration::from_secs(3600));
+
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count(), 4);
+        assert_eq!(
+            snapshot.buckets().collect::<Vec<_>>(),
+            vec![
+                (Duration::from_nanos(1), 1),
+                (Duration::from_nanos(128), 1),
+                (Duration::from_nanos(256), 1),
+                (Duration::MAX, 1),
+            ]
+        );
+        assert_eq!(
+            snapshot.value_at_quantile(0.0),
+            Some(Duration::from_nanos(1))
+        );
+        assert_eq!(
+            snapshot.value_at_quantile(0.5),
+            Some(Duration::from_nanos(128))
+        );
+        assert_eq!(
+            snapshot.value_at_quantile(0.75),
+            Some(Duration::from_nanos(256))
+        );
+        assert_eq!(snapshot.value_at_quantile(1.0), Some(Duration::MAX));
+    }
+
+    #[cfg(feature = "workload")]
+    #[test]
+    fn local_histogram() {
+        let mut histogram = LocalHistogram::default();
+        histogram.record(Duration::from_nanos(100));
+
+        let mut other = LocalHistogram::default();
+        other.record(Duration::from_nanos(100));
+        other.record(Duration::from_secs(3600));
+
+        histogram.merge(&other);
+
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count(), 3);
+        assert_eq!(
+            snapshot.buckets().collect::<Vec<_>>(),
+            vec![(Duration::from_nanos(128), 2), (Duration::MAX, 1)]
+        );
+    }
+}
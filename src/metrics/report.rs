@@ -0,0 +1,88 @@
+use std::cell::Cell;
+
+/// The cost of a single write operation on a hash map.
+///
+/// Returned along with the results of the `_reported` methods of the maps,
+/// such as `insert_reported` and `remove_reported`, so that adaptive callers,
+/// like admission policies, can back off from the segments where operations
+/// become expensive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OpReport {
+    pub(crate) probe_length: usize,
+    pub(crate) caused_resize: bool,
+    pub(crate) retries: usize,
+}
+
+impl OpReport {
+    /// Returns the number of slots that are probed to find the key after the
+    /// operation, including the one that holds it or the empty one that ends
+    /// the probe, or 0 if the map has no bucket pointer array.
+    pub fn probe_length(&self) -> usize {
+        self.probe_length
+    }
+
+    /// Returns `true` if the operation started a resize of the bucket pointer
+    /// array, by allocating the array that the entries are moved into.
+    pub fn caused_resize(&self) -> bool {
+        self.caused_resize
+    }
+
+    /// Returns the number of times the operation found its bucket pointer
+    /// array being resized, helped to move the entries, and started over in
+    /// the new array.
+    pub fn retries(&self) -> usize {
+        self.retries
+    }
+}
+
+thread_local! {
+    // the report of the operation that the current thread is performing, if
+    // it is recorded
+    static REPORT: Cell<Option<OpReport>> = const { Cell::new(None) };
+}
+
+/// Restores the report of an enclosing operation, if any, even if the
+/// recorded one panics.
+struct Restore(Option<OpReport>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let _ = REPORT.try_with(|r| r.set(self.0));
+    }
+}
+
+/// Invokes `f`, returning its result with the report of the operation it
+/// performs on the current thread.
+pub(crate) fn record<T>(f: impl FnOnce() -> T) -> (T, OpReport) {
+    let restore = Restore(REPORT.with(|r| r.replace(Some(OpReport::default()))));
+    let result = f();
+    let report = REPORT.with(Cell::get).unwrap_or_default();
+    drop(restore);
+
+    (result, report)
+}
+
+pub(crate) fn is_recording() -> bool {
+    REPORT.try_with(|r| r.get().is_some()).unwrap_or(false)
+}
+
+fn update(f: impl FnOnce(&mut OpReport)) {
+    let _ = REPORT.try_with(|r| {
+        if let Some(mut report) = r.get() {
+            f(&mut report);
+            r.set(Some(report));
+        }
+    });
+}
+
+pub(crate) fn note_probe_length(probe_length: usize) {
+    update(|r| r.probe_length = probe_length);
+}
+
+pub(crate) fn note_resize() {
+    update(|r| r.caused_resize = true);
+}
+
+pub(crate) fn note_retry() {
+    update(|r| r.retries += 1);
+}
@@ -0,0 +1,178 @@
+use std::{
+    any::Any,
+    cell::Cell,
+    collections::hash_map::RandomState,
+    fmt::{self, Debug, Formatter},
+    hash::{BuildHasher, Hasher},
+};
+
+/// The type of an operation reported to the sampling callback of a map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SampledOperation {
+    /// A lookup of a key.
+    Get,
+    /// An insertion or a modification of an entry.
+    Insert,
+    /// A removal of an entry.
+    Remove,
+}
+
+/// An operation picked by the sampling callback of a map, as passed to it.
+///
+/// The callback is set with the `sample_operations` methods of the builders,
+/// or with their `sample_operations_with_context` methods to also be passed
+/// the context of the operation, if it was performed through a `with_context`
+/// view of the map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OperationSample {
+    pub(crate) operation: SampledOperation,
+    pub(crate) probe_length: usize,
+    pub(crate) segment: usize,
+    pub(crate) hash: u64,
+}
+
+impl OperationSample {
+    /// Returns the type of the operation.
+    pub fn operation(&self) -> SampledOperation {
+        self.operation
+    }
+
+    /// Returns the number of slots that are probed to find the key after the
+    /// operation, including the one that holds it or the empty one that ends
+    /// the probe, or 0 if the map has no bucket pointer array yet.
+    pub fn probe_length(&self) -> usize {
+        self.probe_length
+    }
+
+    /// Returns the index of the segment of the key, which is always 0 for a
+    /// [`HashMap`].
+    ///
+    /// [`HashMap`]: ../map/struct.HashMap.html
+    pub fn segment(&self) -> usize {
+        self.segment
+    }
+
+    /// Returns the hash of the key, as computed by the hasher of the map.
+    pub fn key_hash(&self) -> u64 {
+        self.hash
+    }
+}
+
+thread_local! {
+    // the state of the xorshift generator that picks the sampled operations
+    static RANDOM: Cell<u64> = Cell::new(RandomState::new().build_hasher().finish() | 1);
+}
+
+/// Picks about one in `one_in` operations of a map at random and passes them to
+/// a callback.
+pub(crate) struct Sampler {
+    one_in: u64,
+    callback: Box<Callback>,
+}
+
+type Callback = dyn Fn(&OperationSample, Option<&dyn Any>) + Send + Sync;
+
+impl Sampler {
+    pub(crate) fn new(
+        one_in: u64,
+        callback: impl Fn(&OperationSample, Option<&dyn Any>) + Send + Sync + 'static,
+    ) -> Self {
+        assert!(one_in > 0, "the sampling rate must be positive");
+
+        Self {
+            one_in,
+            callback: Box::new(callback),
+        }
+    }
+
+    /// Returns whether the current operation is sampled.
+    ///
+    /// Each thread draws from its own generator, so no shared state is
+    /// written by the operations that are not sampled.
+    #[inline]
+    pub(crate) fn pick(&self) -> bool {
+        self.one_in == 1 || RANDOM.with(next_random) < u64::MAX / self.one_in
+    }
+
+    /// Returns about one in how many operations are sampled.
+    pub(crate) fn one_in(&self) -> u64 {
+        self.one_in
+    }
+
+    pub(crate) fn record(&self, sample: &OperationSample, context: Option<&dyn Any>) {
+        (self.callback)(sample, context);
+    }
+}
+
+impl Debug for Sampler {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sampler")
+            .field("one_in", &self.one_in)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returns a random number from the generator of the current thread.
+pub(crate) fn random() -> u64 {
+    RANDOM.with(next_random)
+}
+
+fn next_random(state: &Cell<u64>) -> u64 {
+    let mut x = state.get();
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    state.set(x);
+
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[test]
+    fn pick() {
+        const NUM_OPERATIONS: usize = 100_000;
+
+        let sampler = Sampler::new(1, |_, _| ());
+        assert!((0..100).all(|_| sampler.pick()));
+
+        let sampler = Sampler::new(100, |_, _| ());
+        let num_picked = (0..NUM_OPERATIONS).filter(|_| sampler.pick()).count();
+        assert!(num_picked > NUM_OPERATIONS / 200 && num_picked < NUM_OPERATIONS / 50);
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let sampler = {
+            let count = Arc::clone(&count);
+
+            Sampler::new(1, move |s, context| {
+                assert_eq!(s.operation(), SampledOperation::Get);
+                assert_eq!(context.and_then(|c| c.downcast_ref()), Some(&"request"));
+                count.fetch_add(1, Ordering::Relaxed);
+            })
+        };
+
+        sampler.record(
+            &OperationSample {
+                operation: SampledOperation::Get,
+                probe_length: 1,
+                segment: 0,
+                hash: 0,
+            },
+            Some(&"request"),
+        );
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "the sampling rate must be positive")]
+    fn zero_rate() {
+        Sampler::new(0, |_, _| ());
+    }
+}
@@ -0,0 +1,218 @@
+use crate::ordering;
+
+use std::{
+    sync::{atomic::AtomicU64, Arc, Mutex},
+    thread::{self, ThreadId},
+};
+
+/// The counters of a single thread.
+///
+/// They are only written by their own thread, so they are incremented with a
+/// plain load and store instead of a read-modify-write operation.
+#[derive(Debug)]
+struct ThreadCounters {
+    id: ThreadId,
+    name: Option<String>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    inserts: AtomicU64,
+}
+
+impl ThreadCounters {
+    fn counts(&self) -> ThreadOpCounts {
+        ThreadOpCounts {
+            hits: self.hits.load(ordering::RELAXED),
+            misses: self.misses.load(ordering::RELAXED),
+            inserts: self.inserts.load(ordering::RELAXED),
+        }
+    }
+}
+
+/// Unregisters the counters of a thread when it exits, adding them to the
+/// counts of exited threads.
+struct Registration(Arc<ThreadCounters>);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+
+        registry.threads.retain(|c| !Arc::ptr_eq(c, &self.0));
+        registry.exited += self.0.counts();
+    }
+}
+
+struct Registry {
+    threads: Vec<Arc<ThreadCounters>>,
+    exited: ThreadOpCounts,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    threads: Vec::new(),
+    exited: ThreadOpCounts {
+        hits: 0,
+        misses: 0,
+        inserts: 0,
+    },
+});
+
+thread_local! {
+    static COUNTERS: Registration = {
+        let current = thread::current();
+        let counters = Arc::new(ThreadCounters {
+            id: current.id(),
+            name: current.name().map(ToOwned::to_owned),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            inserts: AtomicU64::new(0),
+        });
+
+        REGISTRY
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .threads
+            .push(Arc::clone(&counters));
+
+        Registration(counters)
+    };
+}
+
+fn increment(select: impl FnOnce(&ThreadCounters) -> &AtomicU64) {
+    // the counters may already be destroyed if a map is used from another
+    // thread-local destructor
+    let _ = COUNTERS.try_with(|r| {
+        let counter = select(&r.0);
+        counter.store(counter.load(ordering::RELAXED) + 1, ordering::RELAXED);
+    });
+}
+
+pub(crate) fn count_lookup(hit: bool) {
+    if hit {
+        increment(|c| &c.hits);
+    } else {
+        increment(|c| &c.misses);
+    }
+}
+
+pub(crate) fn count_insert() {
+    increment(|c| &c.inserts);
+}
+
+/// Operation counts of the hash maps in this process.
+///
+/// Returned by [`current_thread_op_counts`] and [`thread_op_counts`].
+///
+/// [`current_thread_op_counts`]: ./fn.current_thread_op_counts.html
+/// [`thread_op_counts`]: ./fn.thread_op_counts.html
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ThreadOpCounts {
+    /// The number of lookups that found the key.
+    pub hits: u64,
+    /// The number of lookups that did not find the key.
+    pub misses: u64,
+    /// The number of calls to the `insert` family of methods.
+    pub inserts: u64,
+}
+
+impl std::ops::AddAssign for ThreadOpCounts {
+    fn add_assign(&mut self, other: Self) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.inserts += other.inserts;
+    }
+}
+
+/// The operation counts of a thread, returned by [`thread_op_counts`].
+///
+/// [`thread_op_counts`]: ./fn.thread_op_counts.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ThreadOpStats {
+    /// The ID of the thread.
+    pub id: ThreadId,
+    /// The name of the thread, if it has one.
+    pub name: Option<String>,
+    /// The operation counts of the thread.
+    pub counts: ThreadOpCounts,
+}
+
+/// Returns the operation counts of the current thread, over all hash maps.
+pub fn current_thread_op_counts() -> ThreadOpCounts {
+    COUNTERS.try_with(|r| r.0.counts()).unwrap_or_default()
+}
+
+/// Returns the operation counts of every live thread that has used a hash map,
+/// and the combined counts of all threads that have exited.
+///
+/// The counts of other threads are read without synchronizing with them, so
+/// they may lag slightly behind.
+pub fn thread_op_counts() -> (Vec<ThreadOpStats>, ThreadOpCounts) {
+    let registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+
+    let threads = registry
+        .threads
+        .iter()
+        .map(|c| ThreadOpStats {
+            id: c.id,
+            name: c.name.clone(),
+            counts: c.counts(),
+        })
+        .collect();
+
+    (threads, registry.exited)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::HashMap;
+
+    #[test]
+    fn counts_per_thread() {
+        let map = HashMap::new();
+        let before = current_thread_op_counts();
+
+        map.insert(1, 1);
+        assert_eq!(map.get(&1), Some(1));
+        assert_eq!(map.get(&2), None);
+
+        let mut after = before;
+        after += ThreadOpCounts {
+            hits: 1,
+            misses: 1,
+            inserts: 1,
+        };
+        assert_eq!(current_thread_op_counts(), after);
+
+        let child = thread::Builder::new()
+            .name("counts_per_thread".to_owned())
+            .spawn(|| {
+                let map = HashMap::new();
+
+                for i in 0..10 {
+                    map.insert(i, i);
+                }
+
+                let (threads, _) = thread_op_counts();
+                let this = threads.iter().find(|t| t.id == thread::current().id());
+
+                this.map(|t| (t.name.clone(), t.counts))
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+
+        assert_eq!(
+            child,
+            Some((
+                Some("counts_per_thread".to_owned()),
+                ThreadOpCounts {
+                    hits: 0,
+                    misses: 0,
+                    inserts: 10,
+                }
+            ))
+        );
+
+        crate::test_util::run_deferred();
+    }
+}
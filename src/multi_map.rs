@@ -0,0 +1,381 @@
+//! A hash map that holds several values per key.
+//!
+//! [`MultiMap`] stores the values of each key in a [`SmallValues`], which
+//! keeps up to `N` values inline in the bucket of the key and spills them to a
+//! heap-allocated vector past that. Since the buckets of a map are never
+//! mutated in place, appending a value copies the values of the key into a new
+//! bucket, but keys with few values, which are the common case of most
+//! multimaps, are copied without allocating.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use moka_cht::multi_map::MultiMap;
+//!
+//! let map: MultiMap<_, _> = MultiMap::new();
+//!
+//! assert_eq!(map.append("foo", 1), 1);
+//! assert_eq!(map.append("foo", 2), 2);
+//! assert_eq!(map.append("bar", 3), 1);
+//!
+//! let values = map.get("foo").unwrap();
+//! assert!(values.is_inline());
+//! assert_eq!(values.to_vec(), [1, 2]);
+//!
+//! // the third value of a key spills to the heap
+//! map.append("foo", 4);
+//! assert!(!map.get_and("foo", |v| v.is_inline()).unwrap());
+//! ```
+//!
+//! [`MultiMap`]: ./struct.MultiMap.html
+//! [`SmallValues`]: ./struct.SmallValues.html
+
+use crate::map::{DefaultHashBuilder, HashMap};
+
+use std::{
+    array,
+    borrow::Borrow,
+    fmt::{self, Debug, Formatter},
+    hash::{BuildHasher, Hash},
+};
+
+/// The number of values that a [`MultiMap`] keeps inline by default.
+///
+/// [`MultiMap`]: ./struct.MultiMap.html
+pub const DEFAULT_INLINE_VALUES: usize = 2;
+
+/// The values of a key in a [`MultiMap`], in the order in which they were
+/// appended.
+///
+/// Up to `N` values are stored inline, and more are stored in a vector.
+///
+/// [`MultiMap`]: ./struct.MultiMap.html
+#[derive(Clone)]
+pub struct SmallValues<V, const N: usize> {
+    repr: Repr<V, N>,
+}
+
+#[derive(Clone)]
+enum Repr<V, const N: usize> {
+    // the first `len` elements of `values` are `Some`
+    Inline { len: usize, values: [Option<V>; N] },
+    Spilled(Vec<V>),
+}
+
+impl<V, const N: usize> SmallValues<V, N> {
+    /// Creates an empty `SmallValues`.
+    pub fn new() -> Self {
+        Self {
+            repr: Repr::Inline {
+                len: 0,
+                values: array::from_fn(|_| None),
+            },
+        }
+    }
+
+    /// Returns the number of values.
+    pub fn len(&self) -> usize {
+        match &self.repr {
+            Repr::Inline { len, .. } => *len,
+            Repr::Spilled(values) => values.len(),
+        }
+    }
+
+    /// Returns `true` if there are no values.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `true` if the values are stored inline rather than in a vector.
+    pub fn is_inline(&self) -> bool {
+        matches!(self.repr, Repr::Inline { .. })
+    }
+
+    /// Returns the value at `index`, or `None` if it is out of bounds.
+    pub fn get(&self, index: usize) -> Option<&V> {
+        match &self.repr {
+            Repr::Inline { len, values } if index < *len => values[index].as_ref(),
+            Repr::Inline { .. } => None,
+            Repr::Spilled(values) => values.get(index),
+        }
+    }
+
+    /// Returns an iterator over the values, in the order in which they were
+    /// appended.
+    pub fn iter(&self) -> impl Iterator<Item = &V> + '_ {
+        (0..self.len()).filter_map(move |i| self.get(i))
+    }
+
+    /// Returns the values as a vector.
+    pub fn to_vec(&self) -> Vec<V>
+    where
+        V: Clone,
+    {
+        self.iter().cloned().collect()
+    }
+
+    /// Returns a copy of the values with `value` appended, which stays inline
+    /// if there is room for it.
+    fn appended(&self, value: V) -> Self
+    where
+        V: Clone,
+    {
+        let repr = match &self.repr {
+            Repr::Inline { len, values } if *len < N => {
+                let mut values = values.clone();
+                values[*len] = Some(value);
+
+                Repr::Inline {
+                    len: len + 1,
+                    values,
+                }
+            }
+            _ => {
+                let mut values = Vec::with_capacity(self.len() + 1);
+                values.extend(self.iter().cloned());
+                values.push(value);
+
+                Repr::Spilled(values)
+            }
+        };
+
+        Self { repr }
+    }
+}
+
+impl<V, const N: usize> Default for SmallValues<V, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V: Debug, const N: usize> Debug for SmallValues<V, N> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<V: PartialEq, const N: usize> PartialEq for SmallValues<V, N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl<V: Eq, const N: usize> Eq for SmallValues<V, N> {}
+
+/// A lock-free hash map that holds several values per key.
+///
+/// The values of a key are stored in a [`SmallValues`], which keeps up to `N`
+/// of them inline. Concurrent appends to a key are applied one after the other
+/// and never lose a value.
+///
+/// [`SmallValues`]: ./struct.SmallValues.html
+pub struct MultiMap<K, V, S = DefaultHashBuilder, const N: usize = DEFAULT_INLINE_VALUES> {
+    inner: HashMap<K, SmallValues<V, N>, S>,
+}
+
+impl<K, V, const N: usize> MultiMap<K, V, DefaultHashBuilder, N> {
+    /// Creates an empty `MultiMap`.
+    pub fn new() -> Self {
+        Self {
+            inner: HashMap::new(),
+        }
+    }
+
+    /// Creates an empty `MultiMap` with the specified capacity.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            inner: HashMap::with_capacity(capacity),
+        }
+    }
+}
+
+impl<K, V, S, const N: usize> MultiMap<K, V, S, N> {
+    /// Creates an empty `MultiMap` that will use `build_hasher` to hash keys.
+    pub fn with_hasher(build_hasher: S) -> Self {
+        Self {
+            inner: HashMap::with_hasher(build_hasher),
+        }
+    }
+
+    /// Creates an empty `MultiMap` with the specified capacity that will use
+    /// `build_hasher` to hash keys.
+    pub fn with_capacity_and_hasher(capacity: usize, build_hasher: S) -> Self {
+        Self {
+            inner: HashMap::with_capacity_and_hasher(capacity, build_hasher),
+        }
+    }
+
+    /// Returns the number of keys in the map.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Returns `true` if the map contains no keys.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Returns a reference to the underlying `HashMap`.
+    pub fn as_inner(&self) -> &HashMap<K, SmallValues<V, N>, S> {
+        &self.inner
+    }
+}
+
+impl<K, V, S: Default, const N: usize> Default for MultiMap<K, V, S, N> {
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher, const N: usize> MultiMap<K, V, S, N> {
+    /// Appends a value to the values of a key, inserting the key if it is not
+    /// in the map, and returns the number of values of the key after the
+    /// append.
+    ///
+    /// The values of the key are copied into a new bucket, without allocating
+    /// if they fit inline. If the key is concurrently modified, the copy is
+    /// made again from the values that replaced the ones it was made from.
+    pub fn append(&self, key: K, value: V) -> usize
+    where
+        V: Clone,
+    {
+        let first = value.clone();
+
+        self.inner
+            .insert_with_or_modify_and(
+                key,
+                move || SmallValues::new().appended(first),
+                |_, values| values.appended(value.clone()),
+                SmallValues::len,
+            )
+            .map_or(1, |len| len + 1)
+    }
+
+    /// Returns a clone of the values corresponding to the key.
+    pub fn get<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<SmallValues<V, N>>
+    where
+        K: Borrow<Q>,
+        V: Clone,
+    {
+        self.inner.get(key)
+    }
+
+    /// Returns the result of invoking a function with a reference to the
+    /// values corresponding to the key.
+    pub fn get_and<Q: Hash + Eq + ?Sized, F: FnOnce(&SmallValues<V, N>) -> T, T>(
+        &self,
+        key: &Q,
+        with_values: F,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+    {
+        self.inner.get_and(key, with_values)
+    }
+
+    /// Returns `true` if the map contains values for the key.
+    pub fn contains_key<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.inner.get_and(key, |_| ()).is_some()
+    }
+
+    /// Removes a key from the map, returning a clone of the values previously
+    /// corresponding to it.
+    pub fn remove<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<SmallValues<V, N>>
+    where
+        K: Borrow<Q>,
+        V: Clone,
+    {
+        self.inner.remove(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        sync::{Arc, Barrier},
+        thread,
+    };
+
+    #[test]
+    fn small_values() {
+        let values = SmallValues::<u32, 2>::new();
+        assert!(values.is_empty());
+        assert!(values.is_inline());
+        assert_eq!(values.get(0), None);
+
+        let values = values.appended(1).appended(2);
+        assert_eq!(values.len(), 2);
+        assert!(values.is_inline());
+        assert_eq!(values.get(1), Some(&2));
+        assert_eq!(values.get(2), None);
+
+        let spilled = values.appended(3);
+        assert!(!spilled.is_inline());
+        assert_eq!(spilled.to_vec(), [1, 2, 3]);
+        assert_eq!(format!("{:?}", spilled), "[1, 2, 3]");
+        assert_ne!(spilled, values);
+
+        // no values are stored inline if `N` is 0
+        let values = SmallValues::<u32, 0>::new().appended(1);
+        assert!(!values.is_inline());
+        assert_eq!(values.to_vec(), [1]);
+    }
+
+    #[test]
+    fn append() {
+        let map: MultiMap<_, _> = MultiMap::new();
+
+        assert_eq!(map.append("foo", 1), 1);
+        assert_eq!(map.append("foo", 2), 2);
+        assert_eq!(map.append("foo", 3), 3);
+        assert_eq!(map.append("bar", 4), 1);
+        assert_eq!(map.len(), 2);
+
+        assert_eq!(map.get("foo").unwrap().to_vec(), [1, 2, 3]);
+        assert_eq!(map.get_and("bar", |v| v.is_inline()), Some(true));
+        assert!(map.contains_key("bar"));
+
+        assert_eq!(map.remove("bar").unwrap().to_vec(), [4]);
+        assert!(!map.contains_key("bar"));
+        assert_eq!(map.get("bar"), None);
+    }
+
+    #[test]
+    fn concurrent_append() {
+        const NUM_THREADS: usize = 8;
+        const NUM_VALUES: usize = 100;
+
+        let map: Arc<MultiMap<_, _>> = Arc::new(MultiMap::new());
+        let barrier = Arc::new(Barrier::new(NUM_THREADS));
+
+        let threads: Vec<_> = (0..NUM_THREADS)
+            .map(|i| {
+                let map = Arc::clone(&map);
+                let barrier = Arc::clone(&barrier);
+
+                thread::spawn(move || {
+                    barrier.wait();
+
+                    for j in 0..NUM_VALUES {
+                        map.append(j % 4, i * NUM_VALUES + j);
+                    }
+                })
+            })
+            .collect();
+
+        for result in threads.into_iter().map(thread::JoinHandle::join) {
+            assert!(result.is_ok());
+        }
+
+        let mut values: Vec<_> = (0..4).flat_map(|k| map.get(&k).unwrap().to_vec()).collect();
+        values.sort_unstable();
+
+        assert_eq!(values, (0..NUM_THREADS * NUM_VALUES).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,58 @@
+//! The memory orderings of the atomic operations in this crate.
+//!
+//! Every atomic operation takes its ordering from this module, so that the
+//! choices can be changed in one place. With the `ordering-audit` feature,
+//! every ordering is upgraded to `SeqCst`, consume loads become `SeqCst` loads,
+//! and a `SeqCst` fence is issued after every step of a probe. This is much
+//! slower and is only meant for bisecting suspected memory ordering bugs on
+//! weakly ordered architectures such as ARM: a bug that disappears with the
+//! feature enabled is likely caused by an ordering that is too weak.
+
+use crossbeam_epoch::{Atomic, Guard, Shared};
+
+#[cfg(feature = "ordering-audit")]
+use std::sync::atomic;
+
+pub(crate) use self::table::*;
+
+#[cfg(not(feature = "ordering-audit"))]
+mod table {
+    use std::sync::atomic::Ordering;
+
+    pub(crate) const RELAXED: Ordering = Ordering::Relaxed;
+    pub(crate) const ACQUIRE: Ordering = Ordering::Acquire;
+    pub(crate) const RELEASE: Ordering = Ordering::Release;
+    pub(crate) const ACQ_REL: Ordering = Ordering::AcqRel;
+}
+
+#[cfg(feature = "ordering-audit")]
+mod table {
+    use std::sync::atomic::Ordering;
+
+    pub(crate) const RELAXED: Ordering = Ordering::SeqCst;
+    pub(crate) const ACQUIRE: Ordering = Ordering::SeqCst;
+    pub(crate) const RELEASE: Ordering = Ordering::SeqCst;
+    pub(crate) const ACQ_REL: Ordering = Ordering::SeqCst;
+}
+
+/// Loads a pointer that is about to be dereferenced.
+#[inline]
+pub(crate) fn load_consume<'g, T>(atomic: &Atomic<T>, guard: &'g Guard) -> Shared<'g, T> {
+    #[cfg(not(feature = "ordering-audit"))]
+    {
+        atomic.load_consume(guard)
+    }
+
+    #[cfg(feature = "ordering-audit")]
+    {
+        atomic.load(atomic::Ordering::SeqCst, guard)
+    }
+}
+
+/// Issues a `SeqCst` fence with the `ordering-audit` feature, and does nothing
+/// otherwise.
+#[inline]
+pub(crate) fn audit_fence() {
+    #[cfg(feature = "ordering-audit")]
+    atomic::fence(atomic::Ordering::SeqCst);
+}
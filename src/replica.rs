@@ -0,0 +1,698 @@
+//! Read-mostly replication of a hash map to local replicas.
+//!
+//! A [`Primary`] wraps the `HashMap` that takes the writes, and records the
+//! keys of its insertions, modifications, and removals in a bounded change
+//! log. A [`Replica`] is a read-only copy of it that answers lookups locally,
+//! without touching the memory of the primary, and is brought up to date by
+//! applying the [`Delta`]s that the primary computes from its change log.
+//!
+//! A delta holds the keys that changed since the position of the replica in
+//! the change log, each with its current value, or `None` if it was removed,
+//! so a key that changed many times is only shipped once. If the replica is
+//! so far behind that the change log no longer holds its position, the delta
+//! holds all the entries of the primary instead, and the replica is resynced
+//! from scratch.
+//!
+//! Replicas in the same process as the primary are synced with
+//! [`Replica::sync`], or [`Replica::sync_if_stale`], which bounds how stale
+//! their reads can be. Deltas are plain data, so replicas in other processes
+//! can be synced by shipping the deltas with any transport, and applying them
+//! with [`Replica::apply`].
+//!
+//! The change log is guarded by a single lock, which every mutation of the
+//! primary takes, so its writers are serialized. See [`Primary`] for details.
+//!
+//! Mutations are logged after they take effect, and deltas read the values
+//! after the log, so a replica converges to the primary once it has applied
+//! the deltas that cover all its mutations. A replica is only ever as
+//! consistent as a map being concurrently mutated: it may observe the changes
+//! of a delta in any order while the delta is being applied.
+//!
+//! [`Primary`]: ./struct.Primary.html
+//! [`Replica`]: ./struct.Replica.html
+//! [`Delta`]: ./struct.Delta.html
+//! [`Replica::sync`]: ./struct.Replica.html#method.sync
+//! [`Replica::sync_if_stale`]: ./struct.Replica.html#method.sync_if_stale
+//! [`Replica::apply`]: ./struct.Replica.html#method.apply
+//!
+//! # Examples
+//!
+//! ```rust
+//! use moka_cht::replica::{Primary, Replica};
+//!
+//! use std::time::Duration;
+//!
+//! let primary = Primary::new(1024);
+//! primary.insert("foo", 1);
+//!
+//! let replica = Replica::new();
+//! assert_eq!(replica.get("foo"), None);
+//!
+//! replica.sync(&primary);
+//! assert_eq!(replica.get("foo"), Some(1));
+//!
+//! primary.insert("bar", 2);
+//! primary.remove("foo");
+//!
+//! // the replica was synced less than a minute ago, so it is not synced again
+//! assert!(!replica.sync_if_stale(&primary, Duration::from_secs(60)));
+//! assert_eq!(replica.get("bar"), None);
+//! assert_eq!(replica.lag(&primary), 2);
+//!
+//! // deltas can also be shipped to replicas in other processes
+//! let delta = primary.delta_since(replica.position());
+//! assert_eq!(delta.len(), 2);
+//!
+//! replica.apply(delta).unwrap();
+//! assert_eq!(replica.get("foo"), None);
+//! assert_eq!(replica.get("bar"), Some(2));
+//! ```
+
+use crate::{
+    map::{DefaultHashBuilder, HashMap},
+    Error,
+};
+
+use std::{
+    borrow::Borrow,
+    collections::{HashSet, VecDeque},
+    hash::{BuildHasher, Hash},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// A hash map whose mutations are recorded in a change log, from which the
+/// deltas of its [`Replica`]s are computed.
+///
+/// # Writes are serialized
+///
+/// The change log is a single queue behind a `Mutex`. Every insertion,
+/// modification, and removal through a `Primary` takes that lock to log its
+/// key, and so does [`delta_since`] to read the log, so the writers of a
+/// `Primary` are serialized on it even though the map it wraps is lock-free.
+/// Lookups go straight to the map and never take the lock.
+///
+/// A `Primary` is meant for read-mostly maps, whose reads are served by the
+/// replicas. A map with many concurrent writers should not be wrapped in one.
+///
+/// See the [module-level documentation](./index.html) for details.
+///
+/// [`delta_since`]: #method.delta_since
+///
+/// [`Replica`]: ./struct.Replica.html
+pub struct Primary<K, V, S = DefaultHashBuilder> {
+    map: HashMap<K, V, S>,
+    log: Mutex<ChangeLog<K>>,
+}
+
+// the keys of the last `capacity` mutations. the key at index `i` was logged at
+// position `base + i + 1`.
+struct ChangeLog<K> {
+    capacity: usize,
+    base: u64,
+    keys: VecDeque<K>,
+}
+
+impl<K, V> Primary<K, V, DefaultHashBuilder> {
+    /// Creates an empty `Primary` whose change log holds the keys of up to
+    /// `log_capacity` mutations.
+    ///
+    /// The replicas that fall further behind than that are resynced with all
+    /// the entries of the map.
+    pub fn new(log_capacity: usize) -> Self {
+        Self::with_map(HashMap::new(), log_capacity)
+    }
+}
+
+impl<K, V, S> Primary<K, V, S> {
+    /// Creates a `Primary` wrapping `map`, whose change log holds the keys of
+    /// up to `log_capacity` mutations.
+    ///
+    /// The entries already in `map` are shipped to the replicas with the first
+    /// delta they are synced with.
+    pub fn with_map(map: HashMap<K, V, S>, log_capacity: usize) -> Self {
+        Self {
+            map,
+            // new replicas start at position 0, which is never in the log, so
+            // that they are resynced with the initial entries
+            log: Mutex::new(ChangeLog {
+                capacity: log_capacity,
+                base: 1,
+                keys: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Returns the number of entries in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the map contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns the position of the last mutation in the change log, which is
+    /// the position of the replicas that are up to date.
+    pub fn position(&self) -> u64 {
+        let log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+
+        log.base + log.keys.len() as u64
+    }
+
+    /// Converts the primary into its map, discarding the change log.
+    pub fn into_map(self) -> HashMap<K, V, S> {
+        self.map
+    }
+
+    fn log(&self, key: K) {
+        let mut log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+
+        if log.capacity == 0 {
+            log.base += 1;
+
+            return;
+        }
+
+        if log.keys.len() == log.capacity {
+            log.keys.pop_front();
+            log.base += 1;
+        }
+
+        log.keys.push_back(key);
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone, S: BuildHasher> Primary<K, V, S> {
+    /// Returns a clone of the value corresponding to the key.
+    pub fn get<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        self.map.get(key)
+    }
+
+    /// Inserts a key-value pair into the map and logs the key, returning a
+    /// clone of the value previously corresponding to the key.
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        let previous = self.map.insert(key.clone(), value);
+        self.log(key);
+
+        previous
+    }
+
+    /// Removes a key from the map and logs it if it was present, returning a
+    /// clone of the value previously corresponding to the key.
+    pub fn remove<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        let (key, value) = self.map.remove_entry(key)?;
+        self.log(key);
+
+        Some(value)
+    }
+
+    /// Modifies the value corresponding to a key and logs the key if it was
+    /// present, returning a clone of the value previously corresponding to
+    /// the key.
+    ///
+    /// `on_modify` may be invoked multiple times if the entry is concurrently
+    /// modified.
+    pub fn modify<F: FnMut(&K, &V) -> V>(&self, key: K, on_modify: F) -> Option<V> {
+        let previous = self.map.modify(key.clone(), on_modify)?;
+        self.log(key);
+
+        Some(previous)
+    }
+
+    /// Returns the changes that bring a replica at `position` up to date.
+    ///
+    /// If the change log still holds `position`, the delta holds the keys
+    /// logged after it with their current values. Otherwise, it holds all the
+    /// entries of the map, and replaces the contents of the replica it is
+    /// applied to.
+    pub fn delta_since(&self, position: u64) -> Delta<K, V> {
+        let (base, to, keys) = {
+            let log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+            let to = log.base + log.keys.len() as u64;
+
+            if position < log.base || position > to {
+                (None, to, Vec::new())
+            } else {
+                let skip = (position - log.base) as usize;
+                let keys: Vec<_> = log.keys.iter().skip(skip).cloned().collect();
+
+                (Some(position), to, keys)
+            }
+        };
+
+        // the values are read after the keys were logged, so they reflect the
+        // mutations up to `to`
+        let changes = if base.is_some() {
+            let mut seen = HashSet::with_capacity(keys.len());
+
+            keys.into_iter()
+                .filter(|k| seen.insert(k.clone()))
+                .map(|k| {
+                    let value = self.map.get(&k);
+
+                    (k, value)
+                })
+                .collect()
+        } else {
+            self.map
+                .snapshot()
+                .into_hashed_entries()
+                .into_iter()
+                .map(|(_, k, v)| (k, Some(v)))
+                .collect()
+        };
+
+        Delta {
+            base,
+            position: to,
+            changes,
+        }
+    }
+}
+
+/// The changes that bring a [`Replica`] up to date with its [`Primary`].
+///
+/// A delta is either incremental, in which case it holds the keys that
+/// changed since its base position with their values, or `None` for removed
+/// keys, or full, in which case it holds all the entries of the primary.
+///
+/// [`Replica`]: ./struct.Replica.html
+/// [`Primary`]: ./struct.Primary.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Delta<K, V> {
+    base: Option<u64>,
+    position: u64,
+    changes: Vec<(K, Option<V>)>,
+}
+
+impl<K, V> Delta<K, V> {
+    /// Creates a delta from its parts, as returned by [`base`], [`position`],
+    /// and [`into_changes`], typically after shipping them to another process.
+    ///
+    /// [`base`]: #method.base
+    /// [`position`]: #method.position
+    /// [`into_changes`]: #method.into_changes
+    pub fn from_parts(base: Option<u64>, position: u64, changes: Vec<(K, Option<V>)>) -> Self {
+        Self {
+            base,
+            position,
+            changes,
+        }
+    }
+
+    /// Returns the position of the replicas this delta applies to, or `None`
+    /// if it is a full delta, which applies to any replica.
+    pub fn base(&self) -> Option<u64> {
+        self.base
+    }
+
+    /// Returns the position of the replicas this delta is applied to.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Returns `true` if the delta holds all the entries of the primary.
+    pub fn is_full(&self) -> bool {
+        self.base.is_none()
+    }
+
+    /// Returns the number of changes in the delta.
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    /// Returns `true` if the delta holds no changes.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Returns the changes, each a key and its value, or `None` if it was
+    /// removed.
+    pub fn changes(&self) -> &[(K, Option<V>)] {
+        &self.changes
+    }
+
+    /// Converts the delta into its changes.
+    pub fn into_changes(self) -> Vec<(K, Option<V>)> {
+        self.changes
+    }
+}
+
+/// A read-only copy of a [`Primary`] that answers lookups locally.
+///
+/// Lookups are lock-free and may run concurrently with the application of a
+/// delta. See the [module-level documentation](./index.html) for details.
+///
+/// [`Primary`]: ./struct.Primary.html
+pub struct Replica<K, V, S = DefaultHashBuilder> {
+    map: HashMap<K, V, S>,
+    state: Mutex<ReplicaState>,
+}
+
+struct ReplicaState {
+    position: u64,
+    synced_at: Option<Instant>,
+}
+
+impl<K, V> Replica<K, V, DefaultHashBuilder> {
+    /// Creates an empty `Replica`, which is resynced with all the entries of
+    /// its primary when it is first synced.
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V> Default for Replica<K, V, DefaultHashBuilder> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, S> Replica<K, V, S> {
+    /// Creates an empty `Replica` that will use `build_hasher` to hash the
+    /// keys.
+    pub fn with_hasher(build_hasher: S) -> Self {
+        Self {
+            map: HashMap::with_hasher(build_hasher),
+            state: Mutex::new(ReplicaState {
+                position: 0,
+                synced_at: None,
+            }),
+        }
+    }
+
+    /// Returns the number of entries in the replica.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the replica contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns the position of the replica in the change log of its primary,
+    /// which is 0 until it is first synced.
+    pub fn position(&self) -> u64 {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .position
+    }
+
+    /// Returns the time elapsed since the replica was last synced, or `None`
+    /// if it never was.
+    ///
+    /// For a replica synced with deltas shipped from another process, this
+    /// does not include the time the deltas took to arrive.
+    pub fn staleness(&self) -> Option<Duration> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        state.synced_at.map(|t| t.elapsed())
+    }
+
+    /// Returns the number of mutations of `primary` that the replica has not
+    /// applied yet, counting a key that changed several times once per
+    /// change.
+    pub fn lag<S2>(&self, primary: &Primary<K, V, S2>) -> u64 {
+        primary.position().saturating_sub(self.position())
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone, S: BuildHasher> Replica<K, V, S> {
+    /// Returns a clone of the value corresponding to the key.
+    pub fn get<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        self.map.get(key)
+    }
+
+    /// Returns the result of invoking a function with a reference to the value
+    /// corresponding to the key.
+    pub fn get_and<Q: Hash + Eq + ?Sized, F: FnOnce(&V) -> T, T>(
+        &self,
+        key: &Q,
+        with_value: F,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+    {
+        self.map.get_and(key, with_value)
+    }
+
+    /// Applies a delta computed by the primary of the replica.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::DeltaGap`] if the delta is incremental and does not
+    /// start at the position of the replica, in which case the replica is left
+    /// unchanged and must be synced with a delta computed from its position.
+    ///
+    /// [`Error::DeltaGap`]: ../enum.Error.html#variant.DeltaGap
+    pub fn apply(&self, delta: Delta<K, V>) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        self.apply_locked(&mut state, delta)
+    }
+
+    /// Brings the replica up to date with `primary`.
+    pub fn sync<S2: BuildHasher>(&self, primary: &Primary<K, V, S2>) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let delta = primary.delta_since(state.position);
+
+        // the delta starts at the position of the replica, which cannot move
+        // while the state is locked
+        self.apply_locked(&mut state, delta).unwrap();
+    }
+
+    /// Brings the replica up to date with `primary` if it was last synced
+    /// more than `max_staleness` ago, or never was, returning whether it was
+    /// synced.
+    ///
+    /// Calling this before lookups bounds how stale they can be.
+    pub fn sync_if_stale<S2: BuildHasher>(
+        &self,
+        primary: &Primary<K, V, S2>,
+        max_staleness: Duration,
+    ) -> bool {
+        match self.staleness() {
+            Some(staleness) if staleness <= max_staleness => false,
+            _ => {
+                self.sync(primary);
+
+                true
+            }
+        }
+    }
+
+    fn apply_locked(&self, state: &mut ReplicaState, delta: Delta<K, V>) -> Result<(), Error> {
+        match delta.base {
+            Some(base) if base != state.position => {
+                return Err(Error::DeltaGap {
+                    position: state.position,
+                    base,
+                });
+            }
+            Some(_) => {
+                for (key, value) in delta.changes {
+                    match value {
+                        Some(value) => {
+                            self.map.insert(key, value);
+                        }
+                        None => {
+                            self.map.remove(&key);
+                        }
+                    }
+                }
+            }
+            None => {
+                let keys: HashSet<_> = delta.changes.iter().map(|(k, _)| k.clone()).collect();
+
+                for (key, value) in delta.changes {
+                    if let Some(value) = value {
+                        self.map.insert(key, value);
+                    }
+                }
+
+                // the entries are replaced before the stale ones are removed,
+                // so that lookups of the keys that are kept do not miss
+                let stale: Vec<_> = self.map.keys().filter(|k| !keys.contains(k)).collect();
+
+                for key in stale {
+                    self.map.remove(&key);
+                }
+            }
+        }
+
+        state.position = delta.position;
+        state.synced_at = Some(Instant::now());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn incremental_and_full_deltas() {
+        let primary = Primary::new(4);
+        let replica = Replica::new();
+
+        for i in 0..8 {
+            primary.insert(i, i);
+        }
+
+        // a new replica is always resynced in full
+        let delta = primary.delta_since(replica.position());
+        assert!(delta.is_full());
+        assert_eq!(delta.len(), 8);
+        replica.apply(delta).unwrap();
+        assert_eq!(replica.position(), primary.position());
+        assert_eq!(replica.len(), 8);
+
+        primary.insert(0, 10);
+        primary.modify(0, |_, v| v + 1);
+        assert_eq!(primary.remove(&1), Some(1));
+        assert_eq!(primary.remove(&100), None);
+
+        // the changes of a key are shipped once
+        let delta = primary.delta_since(replica.position());
+        assert_eq!(delta.base(), Some(replica.position()));
+        assert_eq!(delta.len(), 2);
+        assert!(delta.changes().contains(&(0, Some(11))));
+        assert!(delta.changes().contains(&(1, None)));
+
+        // a delta that does not start at the replica is refused
+        let mut parts = delta.clone();
+        parts.base = Some(replica.position() - 1);
+        assert_eq!(
+            replica.apply(parts),
+            Err(Error::DeltaGap {
+                position: replica.position(),
+                base: replica.position() - 1
+            })
+        );
+
+        replica.apply(delta).unwrap();
+        assert_eq!(replica.get(&0), Some(11));
+        assert_eq!(replica.get(&1), None);
+        assert_eq!(replica.lag(&primary), 0);
+
+        // the log no longer holds the position of a replica that fell behind
+        for i in 2..8 {
+            primary.remove(&i);
+        }
+
+        primary.insert(100, 100);
+        assert_eq!(replica.lag(&primary), 7);
+
+        let delta = primary.delta_since(replica.position());
+        assert!(delta.is_full());
+
+        let (base, position, changes) = (delta.base(), delta.position(), delta.into_changes());
+        replica
+            .apply(Delta::from_parts(base, position, changes))
+            .unwrap();
+
+        let mut entries: Vec<_> = replica.map.iter().map(|r| (*r.key(), *r.value())).collect();
+        entries.sort_unstable();
+        assert_eq!(entries, [(0, 11), (100, 100)]);
+    }
+
+    #[test]
+    fn empty_log() {
+        let primary = Primary::new(0);
+        let replica = Replica::new();
+
+        primary.insert("foo", 1);
+        replica.sync(&primary);
+        assert_eq!(replica.get("foo"), Some(1));
+
+        primary.insert("foo", 2);
+        assert!(primary.delta_since(replica.position()).is_full());
+        assert!(primary.delta_since(primary.position()).is_empty());
+
+        replica.sync(&primary);
+        assert_eq!(replica.get_and("foo", |v| v * 2), Some(4));
+    }
+
+    #[test]
+    fn sync_if_stale() {
+        let primary = Primary::new(16);
+        let replica = Replica::new();
+        assert_eq!(replica.staleness(), None);
+
+        primary.insert(0, 0);
+        assert!(replica.sync_if_stale(&primary, Duration::from_secs(60)));
+        assert!(replica.staleness().is_some());
+
+        primary.insert(1, 1);
+        assert!(!replica.sync_if_stale(&primary, Duration::from_secs(60)));
+        assert_eq!(replica.get(&1), None);
+
+        assert!(replica.sync_if_stale(&primary, Duration::ZERO));
+        assert_eq!(replica.get(&1), Some(1));
+    }
+
+    #[test]
+    fn concurrent_sync() {
+        const NUM_THREADS: usize = 4;
+        const NUM_VALUES: usize = 512;
+
+        let primary = Arc::new(Primary::new(64));
+        let replica = Arc::new(Replica::new());
+
+        let writers: Vec<_> = (0..NUM_THREADS)
+            .map(|t| {
+                let primary = Arc::clone(&primary);
+
+                thread::spawn(move || {
+                    for i in 0..NUM_VALUES {
+                        let key = i * NUM_THREADS + t;
+                        primary.insert(key, i);
+
+                        if i % 3 == 0 {
+                            primary.remove(&key);
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        let readers: Vec<_> = (0..NUM_THREADS)
+            .map(|_| {
+                let (primary, replica) = (Arc::clone(&primary), Arc::clone(&replica));
+
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        replica.sync(&primary);
+                    }
+                })
+            })
+            .collect();
+
+        for t in writers.into_iter().chain(readers) {
+            t.join().unwrap();
+        }
+
+        replica.sync(&primary);
+        assert_eq!(replica.len(), primary.len());
+
+        for key in 0..NUM_VALUES * NUM_THREADS {
+            assert_eq!(replica.get(&key), primary.get(&key));
+        }
+    }
+}
@@ -1,20 +1,72 @@
 //! A lock-free hash map implemented with segmented bucket pointer arrays, open
 //! addressing, and linear probing.
 
-use crate::map::{
-    bucket::{self, BucketArray},
-    bucket_array_ref::BucketArrayRef,
-    DefaultHashBuilder,
+mod batch;
+mod builder;
+mod context;
+mod downcast;
+mod exclusive;
+mod key_set;
+mod map_handle;
+mod reservation;
+mod segment_scan;
+mod stream;
+
+pub use batch::Batch;
+pub use builder::Builder;
+pub use context::WithContext;
+pub use exclusive::Exclusive;
+pub use key_set::KeySetView;
+pub use map_handle::MapHandle;
+pub use reservation::ReservationToken;
+pub use segment_scan::SegmentScan;
+pub use stream::SegmentImport;
+
+#[cfg(feature = "entry-flags")]
+use crate::map::ENTRY_FLAGS;
+#[cfg(feature = "entry-pins")]
+use crate::map::{soft_removal::SoftRemovals, EntryPin};
+#[cfg(feature = "instrument")]
+use crate::metrics::{Latencies, LatencyStats, OpReport};
+use crate::{
+    housekeeping::{self, Budget, ClearProgress, HousekeepingReport},
+    map::{
+        bucket::{self, Bucket, BucketArray, Decided},
+        bucket_array_ref::{fingerprint_bit, BucketArrayRef},
+        coalescing::Coalescing,
+        default_value::BoxedDefaultValue,
+        loader::Loading,
+        memory_budget::MemoryCharge,
+        scrub::{NoScrub, Scrub},
+        state::Reservation,
+        Decision, DefaultHashBuilder, EntryHandle, IntoIter, Iter, IterMut, Keys, MapConfig,
+        MapState, MemoryBudget, NotRemoved, PanicPolicy, Partition, ReadBudget, Ref, ScanCursor,
+        SlotHint, SnapshotView, TombstonePolicy, UnorderedIter, Values, ValuesCloned,
+    },
+    metrics::{
+        self, HandleOpCounts, HandleOpStats, HandleRegistry, MetricsRecorder, ProbeStats, Sampler,
+    },
+    ordering,
+    snapshot::{Snapshot, SnapshotDiff},
+    Error,
 };
 
 use std::{
+    any::Any,
     borrow::Borrow,
+    cell::RefCell,
+    collections::{hash_map::Entry, HashMap as StdHashMap},
     hash::{BuildHasher, Hash},
-    ptr,
-    sync::atomic::{self, AtomicUsize, Ordering},
+    io::{self, Read, Write},
+    mem, panic, ptr,
+    sync::{
+        atomic::{self, AtomicU64, AtomicUsize},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
 
-use crossbeam_epoch::Atomic;
+use crossbeam_epoch::{Atomic, Guard, Shared};
 
 /// A lock-free hash map implemented with segmented bucket pointer arrays, open
 /// addressing, and linear probing.
@@ -103,6 +155,14 @@ use crossbeam_epoch::Atomic;
 /// the [`Eq`] trait, changes while it is in the map. This is normally only
 /// possible through [`Cell`], [`RefCell`], global state, I/O, or unsafe code.
 ///
+/// # Panics in Closures
+///
+/// If a closure passed to a method of the map panics, the panic unwinds out of
+/// the method and the map stays consistent: an entry being modified is left
+/// unchanged, the length of the map matches its entries, and no values are
+/// leaked. The map can be configured to abort the process instead. See
+/// [`PanicPolicy`] for details.
+///
 /// [`aHash`]: https://crates.io/crates/ahash
 /// [`default`]: #method.default
 /// [`with_hasher`]: #method.with_hasher
@@ -115,6 +175,7 @@ use crossbeam_epoch::Atomic;
 /// [`new`]: #method.new
 /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
 /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+/// [`PanicPolicy`]: ../../map/enum.PanicPolicy.html
 /// [`Cell`]: https://doc.rust-lang.org/std/cell/struct.Ref.html
 /// [`RefCell`]: https://doc.rust-lang.org/std/cell/struct.RefCell.html
 pub struct HashMap<K, V, S = DefaultHashBuilder> {
@@ -122,10 +183,39 @@ pub struct HashMap<K, V, S = DefaultHashBuilder> {
     build_hasher: S,
     len: AtomicUsize,
     segment_shift: u32,
+    segment_salt: u64,
+    housekeeping_cursor: AtomicUsize,
+    generation: AtomicU64,
+    access_clock: AtomicU64,
+    deterministic: bool,
+    panic_policy: PanicPolicy,
+    tombstone_policy: TombstonePolicy,
+    flush_after_bulk_removals: bool,
+    memory: Option<MemoryCharge<K, V>>,
+    loading: Option<Loading<K, V>>,
+    default_value: Option<BoxedDefaultValue<K, V>>,
+    coalescing: Option<Coalescing<K, V>>,
+    handles: HandleRegistry,
+    #[cfg(feature = "entry-pins")]
+    soft_removals: SoftRemovals<K, V>,
+    clear_cursor: Mutex<ScanCursor>,
+    // whether the segments assign sequence numbers to their mutations
+    sequenced: bool,
+    sampler: Option<Sampler>,
+    #[cfg(feature = "instrument")]
+    latencies: Latencies,
 }
 
 #[cfg(feature = "num-cpus")]
 impl<K, V> HashMap<K, V, DefaultHashBuilder> {
+    /// Returns a [`Builder`] of a `HashMap` with at least twice as many
+    /// segments as the system has CPUs.
+    ///
+    /// [`Builder`]: ./struct.Builder.html
+    pub fn builder() -> Builder<K, V> {
+        Builder::new(default_num_segments())
+    }
+
     /// Creates an empty `HashMap`.
     ///
     /// The hash map is initially created with a capacity of 0, so it will not
@@ -151,6 +241,13 @@ impl<K, V> HashMap<K, V, DefaultHashBuilder> {
     ///
     /// The `HashMap` will be created with at least twice as many segments as
     /// the system has CPUs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is too large to be allocated. See
+    /// [`try_with_capacity`] for a non-panicking version.
+    ///
+    /// [`try_with_capacity`]: #method.try_with_capacity
     pub fn with_capacity(capacity: usize) -> Self {
         Self::with_num_segments_capacity_and_hasher(
             default_num_segments(),
@@ -158,6 +255,23 @@ impl<K, V> HashMap<K, V, DefaultHashBuilder> {
             DefaultHashBuilder::default(),
         )
     }
+
+    /// Creates an empty `HashMap` with the specified capacity, returning an
+    /// error if the capacity is too large.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::CapacityOverflow`] if `capacity` is too large to be
+    /// allocated.
+    ///
+    /// [`Error::CapacityOverflow`]: ../../enum.Error.html#variant.CapacityOverflow
+    pub fn try_with_capacity(capacity: usize) -> Result<Self, Error> {
+        Self::try_with_num_segments_capacity_and_hasher(
+            default_num_segments(),
+            capacity,
+            DefaultHashBuilder::default(),
+        )
+    }
 }
 
 #[cfg(feature = "num-cpus")]
@@ -185,9 +299,34 @@ impl<K, V, S: BuildHasher> HashMap<K, V, S> {
     ///
     /// The `HashMap` will be created with at least twice as many segments as
     /// the system has CPUs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is too large to be allocated. See
+    /// [`try_with_capacity_and_hasher`] for a non-panicking version.
+    ///
+    /// [`try_with_capacity_and_hasher`]: #method.try_with_capacity_and_hasher
     pub fn with_capacity_and_hasher(capacity: usize, build_hasher: S) -> Self {
         Self::with_num_segments_capacity_and_hasher(default_num_segments(), capacity, build_hasher)
     }
+
+    /// Creates an empty `HashMap` with the specified capacity, using
+    /// `build_hasher` to hash the keys, returning an error if the capacity is
+    /// too large.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::CapacityOverflow`] if `capacity` is too large to be
+    /// allocated.
+    ///
+    /// [`Error::CapacityOverflow`]: ../../enum.Error.html#variant.CapacityOverflow
+    pub fn try_with_capacity_and_hasher(capacity: usize, build_hasher: S) -> Result<Self, Error> {
+        Self::try_with_num_segments_capacity_and_hasher(
+            default_num_segments(),
+            capacity,
+            build_hasher,
+        )
+    }
 }
 
 impl<K, V> HashMap<K, V, DefaultHashBuilder> {
@@ -199,11 +338,33 @@ impl<K, V> HashMap<K, V, DefaultHashBuilder> {
     ///
     /// # Panics
     ///
-    /// Panics if `num_segments` is 0.
+    /// Panics if `num_segments` is 0 or too large to be allocated. See
+    /// [`try_with_num_segments`] for a non-panicking version.
+    ///
+    /// [`try_with_num_segments`]: #method.try_with_num_segments
     pub fn with_num_segments(num_segments: usize) -> Self {
         Self::with_num_segments_capacity_and_hasher(num_segments, 0, DefaultHashBuilder::default())
     }
 
+    /// Creates an empty `HashMap` with the specified number of segments,
+    /// returning an error if the number of segments is invalid.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ZeroSegments`] if `num_segments` is 0, or
+    /// [`Error::CapacityOverflow`] if `num_segments` is too large
+    /// to be allocated.
+    ///
+    /// [`Error::ZeroSegments`]: ../../enum.Error.html#variant.ZeroSegments
+    /// [`Error::CapacityOverflow`]: ../../enum.Error.html#variant.CapacityOverflow
+    pub fn try_with_num_segments(num_segments: usize) -> Result<Self, Error> {
+        Self::try_with_num_segments_capacity_and_hasher(
+            num_segments,
+            0,
+            DefaultHashBuilder::default(),
+        )
+    }
+
     /// Creates an empty `HashMap` with the specified number of segments and
     /// capacity.
     ///
@@ -214,7 +375,11 @@ impl<K, V> HashMap<K, V, DefaultHashBuilder> {
     ///
     /// # Panics
     ///
-    /// Panics if `num_segments` is 0.
+    /// Panics if `num_segments` is 0, or if `num_segments` or `capacity` is
+    /// too large to be allocated. See [`try_with_num_segments_and_capacity`]
+    /// for a non-panicking version.
+    ///
+    /// [`try_with_num_segments_and_capacity`]: #method.try_with_num_segments_and_capacity
     pub fn with_num_segments_and_capacity(num_segments: usize, capacity: usize) -> Self {
         Self::with_num_segments_capacity_and_hasher(
             num_segments,
@@ -222,6 +387,28 @@ impl<K, V> HashMap<K, V, DefaultHashBuilder> {
             DefaultHashBuilder::default(),
         )
     }
+
+    /// Creates an empty `HashMap` with the specified number of segments and
+    /// capacity, returning an error if either is invalid.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ZeroSegments`] if `num_segments` is 0, or
+    /// [`Error::CapacityOverflow`] if `num_segments` or `capacity` is too large
+    /// to be allocated.
+    ///
+    /// [`Error::ZeroSegments`]: ../../enum.Error.html#variant.ZeroSegments
+    /// [`Error::CapacityOverflow`]: ../../enum.Error.html#variant.CapacityOverflow
+    pub fn try_with_num_segments_and_capacity(
+        num_segments: usize,
+        capacity: usize,
+    ) -> Result<Self, Error> {
+        Self::try_with_num_segments_capacity_and_hasher(
+            num_segments,
+            capacity,
+            DefaultHashBuilder::default(),
+        )
+    }
 }
 
 impl<K, V, S> HashMap<K, V, S> {
@@ -234,11 +421,33 @@ impl<K, V, S> HashMap<K, V, S> {
     ///
     /// # Panics
     ///
-    /// Panics if `num_segments` is 0.
+    /// Panics if `num_segments` is 0 or too large to be allocated. See
+    /// [`try_with_num_segments_and_hasher`] for a non-panicking version.
+    ///
+    /// [`try_with_num_segments_and_hasher`]: #method.try_with_num_segments_and_hasher
     pub fn with_num_segments_and_hasher(num_segments: usize, build_hasher: S) -> Self {
         Self::with_num_segments_capacity_and_hasher(num_segments, 0, build_hasher)
     }
 
+    /// Creates an empty `HashMap` with the specified number of segments, using
+    /// `build_hasher` to hash the keys, returning an error if the number of
+    /// segments is invalid.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ZeroSegments`] if `num_segments` is 0, or
+    /// [`Error::CapacityOverflow`] if `num_segments` is too large
+    /// to be allocated.
+    ///
+    /// [`Error::ZeroSegments`]: ../../enum.Error.html#variant.ZeroSegments
+    /// [`Error::CapacityOverflow`]: ../../enum.Error.html#variant.CapacityOverflow
+    pub fn try_with_num_segments_and_hasher(
+        num_segments: usize,
+        build_hasher: S,
+    ) -> Result<Self, Error> {
+        Self::try_with_num_segments_capacity_and_hasher(num_segments, 0, build_hasher)
+    }
+
     /// Creates an empty `HashMap` with the specified number of segments and
     /// capacity, using `build_hasher` to hash the keys.
     ///
@@ -249,15 +458,46 @@ impl<K, V, S> HashMap<K, V, S> {
     ///
     /// # Panics
     ///
-    /// Panics if `num_segments` is 0.
+    /// Panics if `num_segments` is 0, or if `num_segments` or `capacity` is
+    /// too large to be allocated. See
+    /// [`try_with_num_segments_capacity_and_hasher`] for a non-panicking
+    /// version.
+    ///
+    /// [`try_with_num_segments_capacity_and_hasher`]: #method.try_with_num_segments_capacity_and_hasher
     pub fn with_num_segments_capacity_and_hasher(
         num_segments: usize,
         capacity: usize,
         build_hasher: S,
     ) -> Self {
-        assert!(num_segments > 0);
+        Self::try_with_num_segments_capacity_and_hasher(num_segments, capacity, build_hasher)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Creates an empty `HashMap` with the specified number of segments and
+    /// capacity, using `build_hasher` to hash the keys, returning an error if
+    /// either is invalid.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ZeroSegments`] if `num_segments` is 0, or
+    /// [`Error::CapacityOverflow`] if `num_segments` or `capacity` is too large
+    /// to be allocated.
+    ///
+    /// [`Error::ZeroSegments`]: ../../enum.Error.html#variant.ZeroSegments
+    /// [`Error::CapacityOverflow`]: ../../enum.Error.html#variant.CapacityOverflow
+    pub fn try_with_num_segments_capacity_and_hasher(
+        num_segments: usize,
+        capacity: usize,
+        build_hasher: S,
+    ) -> Result<Self, Error> {
+        if num_segments == 0 {
+            return Err(Error::ZeroSegments);
+        }
 
-        let actual_num_segments = num_segments.next_power_of_two();
+        let actual_num_segments = num_segments
+            .checked_next_power_of_two()
+            .filter(|&n| n <= isize::MAX as usize / mem::size_of::<Segment<K, V>>())
+            .ok_or(Error::CapacityOverflow)?;
         let segment_shift = 64 - actual_num_segments.trailing_zeros();
 
         let mut segments = Vec::with_capacity(actual_num_segments);
@@ -268,24 +508,132 @@ impl<K, V, S> HashMap<K, V, S> {
                 segments.set_len(actual_num_segments);
             }
         } else {
-            let actual_capacity = (capacity * 2).next_power_of_two();
+            let actual_capacity = bucket::length_for_capacity(capacity)?;
 
             for _ in 0..actual_num_segments {
                 segments.push(Segment {
                     bucket_array: Atomic::new(BucketArray::with_length(0, actual_capacity)),
                     len: AtomicUsize::new(0),
+                    fingerprint: AtomicU64::new(0),
+                    reserved_slots: AtomicUsize::new(0),
+                    sequence: AtomicU64::new(0),
                 });
             }
         }
 
         let segments = segments.into_boxed_slice();
 
-        Self {
+        Ok(Self {
             segments,
             build_hasher,
             len: AtomicUsize::new(0),
             segment_shift,
+            segment_salt: 0,
+            housekeeping_cursor: AtomicUsize::new(0),
+            generation: AtomicU64::new(0),
+            access_clock: AtomicU64::new(0),
+            deterministic: false,
+            panic_policy: PanicPolicy::default(),
+            tombstone_policy: TombstonePolicy::default(),
+            flush_after_bulk_removals: false,
+            memory: None,
+            loading: None,
+            default_value: None,
+            coalescing: None,
+            handles: HandleRegistry::default(),
+            #[cfg(feature = "entry-pins")]
+            soft_removals: SoftRemovals::default(),
+            clear_cursor: Mutex::new(ScanCursor::new()),
+            sequenced: false,
+            sampler: None,
+            #[cfg(feature = "instrument")]
+            latencies: Latencies::default(),
+        })
+    }
+
+    /// Creates a `HashMap` with the specified number of segments holding
+    /// `entries`, using `build_hasher` to hash the keys.
+    ///
+    /// Each entry is a key-value pair preceded by the hash of its key, as
+    /// returned by [`key_hash`] for a map with an equivalent hasher, and the
+    /// entries must be sorted by hash, like those of a [`Snapshot`]. Since the
+    /// segment of an entry is selected by the most significant bits of its
+    /// hash, the entries of each segment are contiguous, and the bucket pointer
+    /// array of each segment is built directly from them without going through
+    /// the insertion path, in a single pass in time linear in their number,
+    /// which makes this much faster than inserting the entries one by one. An entry whose hash was not computed by
+    /// `build_hasher` can not be found in the map. If several entries have
+    /// equal keys, the last one is kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::ZeroSegments`] if `num_segments` is 0,
+    /// [`Error::UnsortedEntries`] if the entries are not sorted by hash, or
+    /// [`Error::CapacityOverflow`] if the segments or their bucket pointer
+    /// arrays would exceed the maximum allocation size.
+    ///
+    /// [`key_hash`]: #method.key_hash
+    /// [`Snapshot`]: ../../snapshot/struct.Snapshot.html
+    /// [`Error::ZeroSegments`]: ../../enum.Error.html#variant.ZeroSegments
+    /// [`Error::UnsortedEntries`]: ../../enum.Error.html#variant.UnsortedEntries
+    /// [`Error::CapacityOverflow`]: ../../enum.Error.html#variant.CapacityOverflow
+    pub fn from_hash_sorted_entries<I: IntoIterator<Item = (u64, K, V)>>(
+        num_segments: usize,
+        entries: I,
+        build_hasher: S,
+    ) -> Result<Self, Error>
+    where
+        K: Hash + Eq,
+        S: BuildHasher,
+    {
+        let mut map = Self::try_with_num_segments_and_hasher(num_segments, build_hasher)?;
+        let mut previous_hash = 0;
+        let mut index = 0;
+        let mut segment_entries = Vec::new();
+
+        for (hash, key, value) in entries {
+            if hash < previous_hash {
+                return Err(Error::UnsortedEntries);
+            }
+
+            debug_assert_eq!(hash, bucket::hash(&map.build_hasher, &key));
+            previous_hash = hash;
+
+            let this_index = map.segment_index_from_hash(hash);
+
+            if this_index != index {
+                map.load_segment(index, mem::take(&mut segment_entries))?;
+                index = this_index;
+            }
+
+            segment_entries.push((hash, key, value));
+        }
+
+        map.load_segment(index, segment_entries)?;
+
+        Ok(map)
+    }
+
+    fn load_segment(&mut self, index: usize, entries: Vec<(u64, K, V)>) -> Result<(), Error>
+    where
+        K: Eq,
+    {
+        if entries.is_empty() {
+            return Ok(());
         }
+
+        let fingerprint = entries
+            .iter()
+            .fold(0, |f, &(hash, _, _)| f | fingerprint_bit(hash));
+        let (bucket_array, len) = BucketArray::from_hash_sorted_entries(0, entries)?;
+
+        let segment = &mut self.segments[index];
+        segment.bucket_array = Atomic::new(bucket_array);
+        *segment.len.get_mut() = len;
+        *segment.fingerprint.get_mut() = fingerprint;
+        *self.len.get_mut() += len;
+
+        Ok(())
     }
 
     /// Returns the number of elements in the map.
@@ -295,7 +643,7 @@ impl<K, V, S> HashMap<K, V, S> {
     /// This method on its own is safe, but other threads can add or remove
     /// elements at any time.
     pub fn len(&self) -> usize {
-        self.len.load(Ordering::Relaxed)
+        self.len.load(ordering::RELAXED)
     }
 
     /// Returns `true` if the map contains no elements.
@@ -323,7 +671,7 @@ impl<K, V, S> HashMap<K, V, S> {
 
         self.segments
             .iter()
-            .map(|s| s.bucket_array.load_consume(guard))
+            .map(|s| ordering::load_consume(&s.bucket_array, guard))
             .map(|p| unsafe { p.as_ref() })
             .map(|a| a.map(BucketArray::capacity).unwrap_or(0))
             .min()
@@ -340,25 +688,302 @@ impl<K, V, S> HashMap<K, V, S> {
     ///
     /// This method on its own is safe, but other threads can increase the
     /// capacity of a segment at any time by adding elements.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than the number of segments. See
+    /// [`try_segment_capacity`] for a non-panicking version.
+    ///
+    /// [`try_segment_capacity`]: #method.try_segment_capacity
     pub fn segment_capacity(&self, index: usize) -> usize {
-        assert!(index < self.segments.len());
+        self.try_segment_capacity(index)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Returns the number of elements the `index`-th segment of the map can
+    /// hold without reallocating a bucket pointer array, or an error if there
+    /// is no such segment.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::SegmentIndexOutOfBounds`] if `index` is not less than
+    /// the number of segments.
+    ///
+    /// [`Error::SegmentIndexOutOfBounds`]: ../../enum.Error.html#variant.SegmentIndexOutOfBounds
+    pub fn try_segment_capacity(&self, index: usize) -> Result<usize, Error> {
+        let segment = self
+            .segments
+            .get(index)
+            .ok_or(Error::SegmentIndexOutOfBounds {
+                index,
+                num_segments: self.segments.len(),
+            })?;
 
         let guard = &crossbeam_epoch::pin();
 
-        unsafe {
-            self.segments[index]
-                .bucket_array
-                .load_consume(guard)
-                .as_ref()
+        Ok(
+            unsafe { ordering::load_consume(&segment.bucket_array, guard).as_ref() }
+                .map(BucketArray::capacity)
+                .unwrap_or(0),
+        )
+    }
+
+    /// Returns the generation of the bucket pointer array of the `index`-th
+    /// segment of the map.
+    ///
+    /// The generation is incremented every time the segment is rehashed into
+    /// a new bucket pointer array, whether it grows, shrinks, or is compacted,
+    /// so a change of the generation means that the layout of the segment
+    /// changed. The generation of a segment without a bucket pointer array is
+    /// 0, which it is again after the segment is emptied by [`clear_segment`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than the number of segments. See
+    /// [`try_bucket_array_generation`] for a non-panicking version.
+    ///
+    /// [`clear_segment`]: #method.clear_segment
+    /// [`try_bucket_array_generation`]: #method.try_bucket_array_generation
+    pub fn bucket_array_generation(&self, index: usize) -> usize {
+        self.try_bucket_array_generation(index)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Returns the generation of the bucket pointer array of the `index`-th
+    /// segment of the map, or an error if there is no such segment.
+    ///
+    /// See [`bucket_array_generation`] for details.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::SegmentIndexOutOfBounds`] if `index` is not less than
+    /// the number of segments.
+    ///
+    /// [`bucket_array_generation`]: #method.bucket_array_generation
+    /// [`Error::SegmentIndexOutOfBounds`]: ../../enum.Error.html#variant.SegmentIndexOutOfBounds
+    pub fn try_bucket_array_generation(&self, index: usize) -> Result<usize, Error> {
+        let segment = self
+            .segments
+            .get(index)
+            .ok_or(Error::SegmentIndexOutOfBounds {
+                index,
+                num_segments: self.segments.len(),
+            })?;
+
+        let guard = &crossbeam_epoch::pin();
+
+        Ok(
+            unsafe { ordering::load_consume(&segment.bucket_array, guard).as_ref() }
+                .map(|a| a.epoch)
+                .unwrap_or(0),
+        )
+    }
+
+    /// Returns an estimate of the number of entries per slot of the bucket
+    /// pointer arrays of the map.
+    ///
+    /// This is the length of the map divided by the number of slots of the
+    /// current bucket pointer arrays of all segments. A bucket pointer array
+    /// is grown before more than half of its slots are taken, so the estimate
+    /// stays at most about 0.5, and the closer it gets, the longer the probes
+    /// of the lookups. A map that has no bucket pointer array yet has an
+    /// estimate of 0.
+    ///
+    /// # Safety
+    ///
+    /// This method on its own is safe, but other threads can add or remove
+    /// elements and resize the segments at any time.
+    pub fn keys_per_slot_estimate(&self) -> f64 {
+        let guard = &crossbeam_epoch::pin();
+
+        let num_slots: usize = self
+            .segments
+            .iter()
+            .map(|s| ordering::load_consume(&s.bucket_array, guard))
+            .map(|p| unsafe { p.as_ref() })
+            .map(|a| a.map(|a| a.buckets.len()).unwrap_or(0))
+            .sum();
+
+        if num_slots == 0 {
+            0.0
+        } else {
+            self.len() as f64 / num_slots as f64
         }
-        .map(BucketArray::capacity)
-        .unwrap_or(0)
     }
 
     /// Returns the number of segments in the map.
     pub fn num_segments(&self) -> usize {
         self.segments.len()
     }
+
+    /// Returns the number of bits that the hash of a key is shifted right by
+    /// to select its segment.
+    ///
+    /// The segment of a key is selected by the highest `64 - segment_shift()`
+    /// bits of its hash, after it is mixed with the [`segment_salt`] if that is
+    /// not 0. A map with a single segment has a shift of 64.
+    ///
+    /// [`segment_salt`]: #method.segment_salt
+    pub fn segment_shift(&self) -> u32 {
+        self.segment_shift
+    }
+
+    /// Returns an empty [`Batch`] of insertions and removals, which are applied
+    /// to the map together, one segment at a time, when the batch is
+    /// committed.
+    ///
+    /// Writing many entries through a batch avoids the per-entry overhead of
+    /// the individual methods. See [`Batch`] for details.
+    ///
+    /// [`Batch`]: ./struct.Batch.html
+    pub fn batch(&self) -> Batch<'_, K, V, S> {
+        Batch::new(self)
+    }
+
+    /// Returns the salt that selects the segments of the keys.
+    ///
+    /// See [`set_segment_salt`] for details.
+    ///
+    /// [`set_segment_salt`]: #method.set_segment_salt
+    pub fn segment_salt(&self) -> u64 {
+        self.segment_salt
+    }
+
+    /// Returns a reference to the map's [`BuildHasher`].
+    ///
+    /// [`BuildHasher`]: https://doc.rust-lang.org/std/hash/trait.BuildHasher.html
+    pub fn hasher(&self) -> &S {
+        &self.build_hasher
+    }
+
+    /// Returns the configuration of the map.
+    ///
+    /// See [`MapConfig`] for details.
+    ///
+    /// [`MapConfig`]: ../../map/struct.MapConfig.html
+    pub fn config(&self) -> MapConfig {
+        MapConfig {
+            num_segments: self.segments.len(),
+            segment_shift: self.segment_shift,
+            segment_salt: self.segment_salt,
+            hasher: std::any::type_name::<S>().to_owned(),
+            deterministic: self.deterministic,
+            panic_policy: self.panic_policy,
+            tombstone_policy: self.tombstone_policy,
+            memory_budget_limit: self.memory_budget().map(MemoryBudget::limit),
+            loader: self.loading.is_some(),
+            default_value: self.default_value.is_some(),
+            coalesce_window: self.coalesce_window(),
+            sequence_numbers: self.sequenced,
+            flush_after_bulk_removals: self.flush_after_bulk_removals,
+            sample_one_in: self.sampler.as_ref().map(Sampler::one_in),
+            instrument: cfg!(feature = "instrument"),
+        }
+    }
+
+    /// Returns the current generation of the map.
+    ///
+    /// Every entry is stamped with the generation of the map when its key was
+    /// inserted. Modifying the value of an entry keeps its generation, but
+    /// reinserting a key after it has been removed stamps it anew. The
+    /// generation is shared by all segments, and that of a new map is 0.
+    ///
+    /// This method is only available with the `generations` feature.
+    #[cfg(feature = "generations")]
+    pub fn generation(&self) -> u64 {
+        self.generation.load(ordering::ACQUIRE)
+    }
+
+    /// Returns the memory budget that the map charges the weights of its
+    /// entries to, if any.
+    ///
+    /// See [`Builder::memory_budget`].
+    ///
+    /// [`Builder::memory_budget`]: ./struct.Builder.html#method.memory_budget
+    pub fn memory_budget(&self) -> Option<&MemoryBudget> {
+        self.memory.as_ref().map(MemoryCharge::budget)
+    }
+
+    /// Returns the window within which the map coalesces the writes of its
+    /// `insert_coalesced` and `modify_coalesced` methods to the same key, if
+    /// any.
+    ///
+    /// See [`Builder::coalesce_window`].
+    ///
+    /// [`Builder::coalesce_window`]: ./struct.Builder.html#method.coalesce_window
+    pub fn coalesce_window(&self) -> Option<Duration> {
+        self.coalescing.as_ref().map(Coalescing::window)
+    }
+
+    /// Creates a handle to the map with a new ID, which counts the operations
+    /// performed through it and its clones.
+    ///
+    /// See [`MapHandle`] for details.
+    ///
+    /// [`MapHandle`]: ./struct.MapHandle.html
+    pub fn handle(self: &Arc<Self>) -> MapHandle<K, V, S> {
+        MapHandle::new(Arc::clone(self))
+    }
+
+    /// Returns a view of the map that passes `context` to the hooks of the map
+    /// for the operations performed through it.
+    ///
+    /// See [`WithContext`] for details.
+    ///
+    /// [`WithContext`]: ./struct.WithContext.html
+    pub fn with_context<'a>(&'a self, context: &'a dyn Any) -> WithContext<'a, K, V, S> {
+        WithContext::new(self, context)
+    }
+
+    /// Returns the operation counts of every live handle to the map, and the
+    /// combined counts of all handles that were dropped.
+    ///
+    /// The counts of handles used by other threads may lag slightly behind.
+    pub fn handle_op_counts(&self) -> (Vec<HandleOpStats>, HandleOpCounts) {
+        self.handles.op_counts()
+    }
+
+    /// Reports the operation counts of every live handle to the map to
+    /// `recorder`.
+    pub fn report_handle_op_counts(&self, recorder: &dyn MetricsRecorder) {
+        for stats in self.handles.op_counts().0 {
+            recorder.record_handle_op_counts(stats.id, &stats.counts);
+        }
+    }
+
+    /// Advances the generation of the map, returning the generation before the
+    /// call.
+    ///
+    /// Passing the returned generation to [`iter_upto_generation`] yields a
+    /// traversal that skips the keys inserted after this call returned.
+    ///
+    /// This method is only available with the `generations` feature.
+    ///
+    /// [`iter_upto_generation`]: #method.iter_upto_generation
+    #[cfg(feature = "generations")]
+    pub fn advance_generation(&self) -> u64 {
+        self.generation.fetch_add(1, ordering::ACQ_REL)
+    }
+
+    /// Returns the latency histograms of the operations on the map.
+    ///
+    /// See the [`metrics`] module for details.
+    ///
+    /// [`metrics`]: ../../metrics/index.html
+    #[cfg(feature = "instrument")]
+    pub fn latency_stats(&self) -> LatencyStats {
+        self.latencies.stats()
+    }
+
+    /// Returns a read-only view of the keys of the map.
+    ///
+    /// The view does not copy the keys and gives no access to the values. See
+    /// [`KeySetView`] for details.
+    ///
+    /// [`KeySetView`]: struct.KeySetView.html
+    pub fn key_set(&self) -> KeySetView<'_, K, V, S> {
+        KeySetView::new(self)
+    }
 }
 
 impl<K, V, S: BuildHasher> HashMap<K, V, S> {
@@ -372,178 +997,141 @@ impl<K, V, S: BuildHasher> HashMap<K, V, S> {
 
         self.segment_index_from_hash(hash)
     }
-}
 
-impl<K: Hash + Eq, V, S: BuildHasher> HashMap<K, V, S> {
-    /// Returns a clone of the value corresponding to the key.
+    /// Returns the hash of `key` as computed by the map's hasher.
     ///
-    /// The key may be any borrowed form of the map's key type, but
-    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
-    /// the key type.
+    /// This is the hash that [`entries_in_hash_range`] selects entries by.
     ///
-    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
-    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
-    #[inline]
-    pub fn get<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<V>
+    /// [`entries_in_hash_range`]: #method.entries_in_hash_range
+    pub fn key_hash<Q: Hash + ?Sized>(&self, key: &Q) -> u64
     where
         K: Borrow<Q>,
-        V: Clone,
     {
-        self.get_key_value_and(key, |_, v| v.clone())
+        bucket::hash(&self.build_hasher, key)
     }
+}
 
-    /// Returns a clone of the the key-value pair corresponding to the supplied
-    /// key.
+impl<K: Hash + Eq, V, S: BuildHasher> HashMap<K, V, S> {
+    /// Reserves capacity for at least `additional` more elements than each
+    /// segment currently holds, reallocating their bucket pointer arrays if
+    /// needed.
     ///
-    /// The supplied key may be any borrowed form of the map's key type, but
-    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for the key
-    /// type.
+    /// Like the capacity passed to the constructors, the reservation applies
+    /// to every segment, as the segments of the keys to be inserted are not
+    /// known in advance. Insertions made concurrently by other threads may use
+    /// the reserved capacity.
     ///
-    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
-    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
-    #[inline]
-    pub fn get_key_value<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<(K, V)>
-    where
-        K: Borrow<Q> + Clone,
-        V: Clone,
-    {
-        self.get_key_value_and(key, |k, v| (k.clone(), v.clone()))
-    }
-
-    /// Returns the result of invoking a function with a reference to the value
-    /// corresponding to the key.
+    /// # Panics
     ///
-    /// The key may be any borrowed form of the map's key type, but
-    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
-    /// the key type.
+    /// Panics if a bucket pointer array for the new capacity would exceed the
+    /// maximum allocation size. See [`try_reserve`] for a non-panicking
+    /// version.
     ///
-    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
-    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
-    #[inline]
-    pub fn get_and<Q: Hash + Eq + ?Sized, F: FnOnce(&V) -> T, T>(
-        &self,
-        key: &Q,
-        with_value: F,
-    ) -> Option<T>
-    where
-        K: Borrow<Q>,
-    {
-        self.get_key_value_and(key, move |_, v| with_value(v))
+    /// [`try_reserve`]: #method.try_reserve
+    pub fn reserve(&self, additional: usize) {
+        self.try_reserve(additional)
+            .unwrap_or_else(|e| panic!("{}", e))
     }
 
-    /// Returns the result of invoking a function with a reference to the
-    /// key-value pair corresponding to the supplied key.
+    /// Reserves capacity for at least `additional` more elements than each
+    /// segment currently holds, returning an error if the capacity is too
+    /// large.
     ///
-    /// The supplied key may be any borrowed form of the map's key type, but
-    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for the key
-    /// type.
+    /// # Errors
     ///
-    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
-    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
-    #[inline]
-    pub fn get_key_value_and<Q: Hash + Eq + ?Sized, F: FnOnce(&K, &V) -> T, T>(
-        &self,
-        key: &Q,
-        with_entry: F,
-    ) -> Option<T>
-    where
-        K: Borrow<Q>,
-    {
-        let hash = bucket::hash(&self.build_hasher, &key);
-
-        self.bucket_array_ref(hash)
-            .get_key_value_and(key, hash, with_entry)
-    }
-
-    /// Inserts a key-value pair into the map, returning a clone of the value
-    /// previously corresponding to the key.
+    /// Returns [`Error::CapacityOverflow`] if a bucket pointer array for the
+    /// new capacity would exceed the maximum allocation size. The segments
+    /// that were grown before the error was detected keep their new capacity.
     ///
-    /// If the map did have this key present, both the key and value are
-    /// updated.
-    #[inline]
-    pub fn insert(&self, key: K, value: V) -> Option<V>
-    where
-        V: Clone,
-    {
-        self.insert_entry_and(key, value, |_, v| v.clone())
+    /// [`Error::CapacityOverflow`]: ../../enum.Error.html#variant.CapacityOverflow
+    pub fn try_reserve(&self, additional: usize) -> Result<(), Error> {
+        (0..self.segments.len())
+            .try_for_each(|index| self.segment_bucket_array_ref(index).reserve(additional))
     }
 
-    /// Inserts a key-value pair into the map, returning a clone of the
-    /// key-value pair previously corresponding to the supplied key.
+    /// Reserves `additional` slots in each segment for the insertions made
+    /// through the returned [`ReservationToken`], reallocating the bucket
+    /// pointer arrays if needed.
     ///
-    /// If the map did have this key present, both the key and value are
-    /// updated.
-    #[inline]
-    pub fn insert_entry(&self, key: K, value: V) -> Option<(K, V)>
-    where
-        K: Clone,
-        V: Clone,
-    {
-        self.insert_entry_and(key, value, |k, v| (k.clone(), v.clone()))
-    }
-
-    /// Inserts a key-value pair into the map, returning the result of invoking
-    /// a function with a reference to the value previously corresponding to the
-    /// key.
+    /// Like [`reserve`], this reserves `additional` slots in every segment,
+    /// as the segments of the keys to be inserted are not known in advance.
+    /// Unlike it, the slots are held for the token: insertions made without
+    /// it grow the segments rather than use them, so the next `additional`
+    /// insertions of new keys into a segment through the token do not grow
+    /// it. The slots left are released when the token is dropped.
     ///
-    /// If the map did have this key present, both the key and value are
-    /// updated.
-    #[inline]
-    pub fn insert_and<F: FnOnce(&V) -> T, T>(
-        &self,
-        key: K,
-        value: V,
-        with_previous_value: F,
-    ) -> Option<T> {
-        self.insert_entry_and(key, value, move |_, v| with_previous_value(v))
+    /// # Panics
+    ///
+    /// Panics if a bucket pointer array for the new capacity would exceed the
+    /// maximum allocation size. See [`try_reserve_slots`] for a non-panicking
+    /// version.
+    ///
+    /// [`ReservationToken`]: ./struct.ReservationToken.html
+    /// [`reserve`]: #method.reserve
+    /// [`try_reserve_slots`]: #method.try_reserve_slots
+    pub fn reserve_slots(&self, additional: usize) -> ReservationToken<'_, K, V, S> {
+        self.try_reserve_slots(additional)
+            .unwrap_or_else(|e| panic!("{}", e))
     }
 
-    /// Inserts a key-value pair into the map, returning the result of invoking
-    /// a function with a reference to the key-value pair previously
-    /// corresponding to the supplied key.
+    /// Reserves `additional` slots in each segment for the insertions made
+    /// through the returned [`ReservationToken`], returning an error if the
+    /// capacity is too large.
     ///
-    /// If the map did have this key present, both the key and value are
-    /// updated.
-    #[inline]
-    pub fn insert_entry_and<F: FnOnce(&K, &V) -> T, T>(
+    /// See [`reserve_slots`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::CapacityOverflow`] if a bucket pointer array for the
+    /// new capacity would exceed the maximum allocation size. The slots
+    /// reserved before the error was detected are released, but the segments
+    /// that were grown keep their new capacity.
+    ///
+    /// [`ReservationToken`]: ./struct.ReservationToken.html
+    /// [`reserve_slots`]: #method.reserve_slots
+    /// [`Error::CapacityOverflow`]: ../../enum.Error.html#variant.CapacityOverflow
+    pub fn try_reserve_slots(
         &self,
-        key: K,
-        value: V,
-        with_previous_entry: F,
-    ) -> Option<T> {
-        let hash = bucket::hash(&self.build_hasher, &key);
-
-        let result =
-            self.bucket_array_ref(hash)
-                .insert_entry_and(key, hash, value, with_previous_entry);
+        additional: usize,
+    ) -> Result<ReservationToken<'_, K, V, S>, Error> {
+        for index in 0..self.segments.len() {
+            if let Err(e) = self
+                .segment_bucket_array_ref(index)
+                .reserve_slots(additional)
+            {
+                for segment in &self.segments[..index] {
+                    segment
+                        .reserved_slots
+                        .fetch_sub(additional, ordering::RELAXED);
+                }
 
-        if result.is_none() {
-            self.len.fetch_add(1, Ordering::Relaxed);
+                return Err(e);
+            }
         }
 
-        result
+        Ok(ReservationToken::new(self, additional))
     }
 
-    /// Removes a key from the map, returning a clone of the value previously
-    /// corresponding to the key.
+    /// Returns an [`Exclusive`] view of the map, which inserts and removes
+    /// entries without atomic read-modify-write operations and without
+    /// leaving tombstones behind.
     ///
-    /// The key may be any borrowed form of the map's key type, but
-    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
-    /// the key type.
+    /// Borrowing the map mutably proves that no other thread can access it,
+    /// which makes populating a map before sharing it, or draining it after,
+    /// cheaper. Resizes left unfinished by other threads are finished first.
+    /// See [`Exclusive`] for details.
     ///
-    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
-    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
-    #[inline]
-    pub fn remove<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<V>
-    where
-        K: Borrow<Q>,
-        V: Clone,
-    {
-        self.remove_entry_if_and(key, |_, _| true, |_, v| v.clone())
+    /// [`Exclusive`]: ./struct.Exclusive.html
+    pub fn exclusive(&mut self) -> Exclusive<'_, K, V, S> {
+        Exclusive::new(self)
     }
 
-    /// Removes a key from the map, returning a clone of the key-value pair
-    /// previously corresponding to the key.
+    /// Returns a mutable reference to the value corresponding to the key.
+    ///
+    /// Borrowing the map mutably proves that no other thread can access it,
+    /// so the lookup does not pin the current thread to an epoch. A resize of
+    /// the segment of the key left unfinished by other threads is finished
+    /// first.
     ///
     /// The key may be any borrowed form of the map's key type, but
     /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
@@ -551,555 +1139,5597 @@ impl<K: Hash + Eq, V, S: BuildHasher> HashMap<K, V, S> {
     ///
     /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
     /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
-    #[inline]
-    pub fn remove_entry<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<(K, V)>
+    pub fn get_mut<Q: Hash + Eq + ?Sized>(&mut self, key: &Q) -> Option<&mut V>
     where
-        K: Borrow<Q> + Clone,
-        V: Clone,
+        K: Borrow<Q>,
     {
-        self.remove_entry_if_and(key, |_, _| true, |k, v| (k.clone(), v.clone()))
+        let hash = bucket::hash(&self.build_hasher, key);
+        let index = self.segment_index_from_hash(hash);
+        crate::map::exclusive::settle(self.segment_bucket_array_ref(index));
+
+        let access = *self.access_clock.get_mut();
+
+        crate::map::exclusive::get_mut(&mut self.segments[index].bucket_array, hash, key, access)
+            .map(|b| unsafe { b.value_mut() })
     }
 
-    /// Remove a key from the map, returning the result of invoking a function
-    /// with a reference to the value previously corresponding to the key.
+    /// Inserts a key-value pair into the map, returning the value previously
+    /// corresponding to the key.
     ///
-    /// The key may be any borrowed form of the map's key type, but
-    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
-    /// the key type.
+    /// Like [`Exclusive::insert`], this does not pin the current thread to an
+    /// epoch, and writes the bucket pointer array of the segment and the
+    /// lengths without atomic read-modify-write operations. To insert many
+    /// entries, get an [`Exclusive`] view once with [`exclusive`] instead.
     ///
-    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
-    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
-    #[inline]
-    pub fn remove_and<Q: Hash + Eq + ?Sized, F: FnOnce(&V) -> T, T>(
-        &self,
-        key: &Q,
-        with_previous_value: F,
-    ) -> Option<T>
-    where
-        K: Borrow<Q>,
-    {
-        self.remove_entry_if_and(key, |_, _| true, move |_, v| with_previous_value(v))
+    /// If the map did have this key present, its value is overwritten in the
+    /// bucket that holds it, without allocating a new one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bucket pointer array of the segment would need to grow
+    /// past the maximum allocation size.
+    ///
+    /// [`Exclusive::insert`]: ./struct.Exclusive.html#method.insert
+    /// [`Exclusive`]: ./struct.Exclusive.html
+    /// [`exclusive`]: #method.exclusive
+    pub fn insert_mut(&mut self, key: K, value: V) -> Option<V> {
+        self.exclusive().insert(key, value)
     }
 
-    /// Removes a key from the map, returning the result of invoking a function
-    /// with a reference to the key-value pair previously corresponding to the
-    /// key.
+    /// An iterator visiting the entries of the map in arbitrary order, yielding
+    /// a reference to the key and a mutable reference to the value of each of
+    /// them.
     ///
-    /// The key may be any borrowed form of the map's key type, but
-    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
-    /// the key type.
-    ///
-    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
-    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
-    #[inline]
-    pub fn remove_entry_and<Q: Hash + Eq + ?Sized, F: FnOnce(&K, &V) -> T, T>(
-        &self,
-        key: &Q,
-        with_previous_entry: F,
-    ) -> Option<T>
-    where
-        K: Borrow<Q>,
-    {
-        self.remove_entry_if_and(key, |_, _| true, with_previous_entry)
+    /// Borrowing the map mutably proves that no other thread can access it,
+    /// so the iterator does not pin the current thread to an epoch. Resizes
+    /// left unfinished by other threads are finished first. The entries of a
+    /// deterministic map are visited in the order of their hashes within each
+    /// segment, and the segments in order.
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        for index in 0..self.segments.len() {
+            crate::map::exclusive::settle(self.segment_bucket_array_ref(index));
+        }
+
+        let build_hasher = &self.build_hasher;
+        let mut buckets = Vec::with_capacity(*self.len.get_mut());
+
+        for segment in self.segments.iter_mut() {
+            let start = buckets.len();
+            crate::map::exclusive::live_buckets_mut(&mut segment.bucket_array, &mut buckets);
+
+            if self.deterministic {
+                buckets[start..].sort_by_cached_key(|b| bucket::hash(build_hasher, &b.key));
+            }
+        }
+
+        unsafe { IterMut::new(buckets) }
     }
 
-    /// Removes a key from the map if a condition is met, returning a clone of
-    /// the value previously corresponding to the key.
+    /// Removes every entry whose key `predicate` returns `true` for, returning
+    /// the number of entries removed, and frees everything that the map still
+    /// holds of those keys before returning.
     ///
-    /// `condition` will be invoked at least once if [`Some`] is returned. It
-    /// may also be invoked one or more times if [`None`] is returned.
+    /// This erases the state of a tenant whose keys share a map with the keys
+    /// of other tenants. Besides the entries themselves, the tombstones and
+    /// the soft-removed entries of matching keys are freed, and the pending
+    /// coalesced writes to them are discarded, so that nothing is left in the
+    /// map for a later read, [`undelete`] or [`flush_coalesced`] to bring
+    /// back. The entries are removed in place without leaving tombstones, and
+    /// the garbage of the current thread is then flushed to the epoch
+    /// collector.
     ///
-    /// The key may be any borrowed form of the map's key type, but
-    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
-    /// the key type.
+    /// Borrowing the map mutably proves that no other thread can access it. A
+    /// resize left unfinished by other threads is finished first. The keys and
+    /// values of the entries removed earlier through shared references may
+    /// still be waiting for their epoch to pass, and are dropped whenever the
+    /// collector gets to them. See [`wipe_tenant_zeroized`] to also scrub the
+    /// memory of the freed keys and values.
     ///
-    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
-    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
-    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
-    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
-    pub fn remove_if<Q: Hash + Eq + ?Sized, F: FnMut(&K, &V) -> bool>(
-        &self,
-        key: &Q,
-        condition: F,
-    ) -> Option<V>
-    where
-        K: Borrow<Q>,
-        V: Clone,
-    {
-        self.remove_entry_if_and(key, condition, move |_, v| v.clone())
+    /// When the keys of a tenant map to known segments, [`clear_segment`]
+    /// drops them without visiting the other segments.
+    ///
+    /// [`undelete`]: #method.undelete
+    /// [`flush_coalesced`]: #method.flush_coalesced
+    /// [`wipe_tenant_zeroized`]: #method.wipe_tenant_zeroized
+    /// [`clear_segment`]: #method.clear_segment
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// let mut map = SegmentedHashMap::with_num_segments(4);
+    /// map.insert(("tenant-a", 1), 10);
+    /// map.insert(("tenant-a", 2), 20);
+    /// map.insert(("tenant-b", 1), 30);
+    ///
+    /// assert_eq!(map.wipe_tenant(|&(tenant, _)| tenant == "tenant-a"), 2);
+    /// assert_eq!(map.get(&("tenant-a", 1)), None);
+    /// assert_eq!(map.len(), 1);
+    /// ```
+    pub fn wipe_tenant<F: FnMut(&K) -> bool>(&mut self, predicate: F) -> usize {
+        self.wipe_tenant_with(predicate, &mut NoScrub)
     }
 
-    /// Removes a key from the map if a condition is met, returning a clone of
-    /// the key-value pair previously corresponding to the key.
+    /// Removes every entry whose key `predicate` returns `true` for like
+    /// [`wipe_tenant`], zeroing the keys and the values that it frees before
+    /// their memory is released.
     ///
-    /// `condition` will be invoked at least once if [`Some`] is returned. It
-    /// may also be invoked one or more times if [`None`] is returned.
+    /// The values of the soft-removed entries and the pending coalesced writes
+    /// of matching keys are zeroed as well, and so are the keys of their
+    /// tombstones. Only what the map holds in its own memory is zeroed: the
+    /// heap allocations of keys and values are zeroed if their [`Zeroize`]
+    /// implementations do so, and copies made by clones or by earlier moves
+    /// are not reached.
     ///
-    /// The key may be any borrowed form of the map's key type, but
-    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
-    /// the key type.
+    /// This method is only available with the `zeroize` feature.
     ///
-    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
-    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
-    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
-    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
-    #[inline]
-    pub fn remove_entry_if<Q: Hash + Eq + ?Sized, F: FnMut(&K, &V) -> bool>(
-        &self,
-        key: &Q,
-        condition: F,
-    ) -> Option<(K, V)>
+    /// [`wipe_tenant`]: #method.wipe_tenant
+    /// [`Zeroize`]: https://docs.rs/zeroize/*/zeroize/trait.Zeroize.html
+    #[cfg(feature = "zeroize")]
+    pub fn wipe_tenant_zeroized<F: FnMut(&K) -> bool>(&mut self, predicate: F) -> usize
     where
-        K: Clone + Borrow<Q>,
-        V: Clone,
+        K: zeroize::Zeroize,
+        V: zeroize::Zeroize,
     {
-        self.remove_entry_if_and(key, condition, move |k, v| (k.clone(), v.clone()))
+        self.wipe_tenant_with(predicate, &mut crate::map::scrub::Zeroize)
     }
 
-    /// Remove a key from the map if a condition is met, returning the result of
-    /// invoking a function with a reference to the value previously
-    /// corresponding to the key.
+    fn wipe_tenant_with<F: FnMut(&K) -> bool>(
+        &mut self,
+        mut predicate: F,
+        scrub: &mut impl Scrub<K, V>,
+    ) -> usize {
+        if let Some(coalescing) = &mut self.coalescing {
+            coalescing.discard(&mut predicate, scrub);
+        }
+
+        // the buckets of the soft-removed entries are pinned, so the pins have
+        // to be dropped before the buckets are freed
+        #[cfg(feature = "entry-pins")]
+        unsafe {
+            self.soft_removals.purge_if(&mut predicate, scrub)
+        };
+
+        for index in 0..self.segments.len() {
+            crate::map::exclusive::settle(self.segment_bucket_array_ref(index));
+        }
+
+        let mut removed = 0;
+
+        for segment in self.segments.iter_mut() {
+            let removed_from_segment = crate::map::exclusive::wipe(
+                &mut segment.bucket_array,
+                &self.build_hasher,
+                self.memory.as_ref(),
+                &mut predicate,
+                scrub,
+            );
+            *segment.len.get_mut() -= removed_from_segment;
+            removed += removed_from_segment;
+        }
+
+        *self.len.get_mut() -= removed;
+
+        housekeeping::flush_garbage();
+
+        removed
+    }
+
+    /// Removes every entry of the `index`-th segment, returning the number of
+    /// entries removed.
     ///
-    /// `condition` will be invoked at least once if [`Some`] is returned. It
-    /// may also be invoked one or more times if [`None`] is returned.
+    /// The bucket pointer array of the segment is detached as a whole instead
+    /// of removing its entries one by one, and is freed along with the entries
+    /// without leaving tombstones or deferred garbage behind. The next
+    /// insertion into the segment allocates a new array. When the keys of a
+    /// logical partition map to known segments, which [`segment_index`]
+    /// reports, this drops the partition cheaply.
     ///
-    /// The key may be any borrowed form of the map's key type, but
-    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
-    /// the key type.
+    /// Detaching the array requires exclusive access to the map, for the same
+    /// reasons as [`set_segment_salt`]. The other segments are left untouched.
     ///
-    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
-    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
-    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
-    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
-    #[inline]
-    pub fn remove_if_and<Q: Hash + Eq + ?Sized, F: FnMut(&K, &V) -> bool, G: FnOnce(&V) -> T, T>(
-        &self,
-        key: &Q,
-        condition: F,
-        with_previous_value: G,
-    ) -> Option<T>
-    where
-        K: Borrow<Q>,
-    {
-        self.remove_entry_if_and(key, condition, move |_, v| with_previous_value(v))
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than the number of segments. See
+    /// [`try_clear_segment`] for a non-panicking version.
+    ///
+    /// [`segment_index`]: #method.segment_index
+    /// [`set_segment_salt`]: #method.set_segment_salt
+    /// [`try_clear_segment`]: #method.try_clear_segment
+    pub fn clear_segment(&mut self, index: usize) -> usize {
+        self.try_clear_segment(index)
+            .unwrap_or_else(|e| panic!("{}", e))
     }
 
-    /// Removes a key from the map if a condition is met, returning the result
-    /// of invoking a function with a reference to the key-value pair previously
-    /// corresponding to the key.
+    /// Removes every entry of the `index`-th segment, returning the number of
+    /// entries removed, or an error if there is no such segment.
     ///
-    /// `condition` will be invoked at least once if [`Some`] is returned. It
-    /// may also be invoked one or more times if [`None`] is returned.
+    /// # Errors
     ///
-    /// The key may be any borrowed form of the map's key type, but
-    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
-    /// the key type.
+    /// Returns [`Error::SegmentIndexOutOfBounds`] if `index` is not less than
+    /// the number of segments.
     ///
-    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
-    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
-    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
-    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
-    #[inline]
-    pub fn remove_entry_if_and<
-        Q: Hash + Eq + ?Sized,
-        F: FnMut(&K, &V) -> bool,
-        G: FnOnce(&K, &V) -> T,
-        T,
-    >(
+    /// [`Error::SegmentIndexOutOfBounds`]: ../../enum.Error.html#variant.SegmentIndexOutOfBounds
+    pub fn try_clear_segment(&mut self, index: usize) -> Result<usize, Error> {
+        if index >= self.segments.len() {
+            return Err(Error::SegmentIndexOutOfBounds {
+                index,
+                num_segments: self.segments.len(),
+            });
+        }
+
+        crate::map::exclusive::settle(self.segment_bucket_array_ref(index));
+
+        let segment = &mut self.segments[index];
+        crate::map::exclusive::clear(&mut segment.bucket_array, self.memory.as_ref());
+
+        let removed = mem::replace(segment.len.get_mut(), 0);
+        *segment.fingerprint.get_mut() = 0;
+        *self.len.get_mut() -= removed;
+
+        Ok(removed)
+    }
+
+    /// Writes the entries of the `index`-th segment to `writer` as a stream
+    /// that [`import_segment`] reads, returning the number of entries written.
+    ///
+    /// This lets a live process hand its segments one by one to a replacement
+    /// process, for example during a blue-green deployment, while it keeps
+    /// serving the segments that have not been handed over yet. `encode`
+    /// appends the encoding of each entry to a buffer; the stream frames the
+    /// encodings with consecutive sequence numbers and ends with their count,
+    /// so that the importing side detects truncated, reordered, or dropped
+    /// frames.
+    ///
+    /// The entries are encoded under a single epoch guard into a buffer that
+    /// is written after the guard is released, so a slow writer does not
+    /// delay the reclamation of memory in the map. Like
+    /// [`iter`], the export is not a snapshot: entries
+    /// inserted into or removed from the segment meanwhile may or may not be
+    /// part of the stream. The entries of a
+    /// deterministic map are written in the order of their hashes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`InvalidInput`] wrapping
+    /// [`Error::SegmentIndexOutOfBounds`] if `index` is not less than the
+    /// number of segments, or the error of `writer` if writing fails.
+    ///
+    /// [`import_segment`]: #method.import_segment
+    /// [`iter`]: #method.iter
+    /// [`InvalidInput`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html#variant.InvalidInput
+    /// [`Error::SegmentIndexOutOfBounds`]: ../../enum.Error.html#variant.SegmentIndexOutOfBounds
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    /// use std::convert::TryInto;
+    ///
+    /// let old = SegmentedHashMap::with_num_segments(4);
+    /// let new = SegmentedHashMap::with_num_segments(8);
+    ///
+    /// for i in 0..100u32 {
+    ///     old.insert(i, i * 2);
+    /// }
+    ///
+    /// for index in 0..old.num_segments() {
+    ///     let mut stream = Vec::new();
+    ///     old.export_segment(index, &mut stream, |k, v, buf| {
+    ///         buf.extend_from_slice(&k.to_le_bytes());
+    ///         buf.extend_from_slice(&v.to_le_bytes());
+    ///     })?;
+    ///
+    ///     let import = new.import_segment(stream.as_slice(), |bytes| {
+    ///         let k = u32::from_le_bytes(bytes[..4].try_into().unwrap());
+    ///         let v = u32::from_le_bytes(bytes[4..].try_into().unwrap());
+    ///         Ok((k, v))
+    ///     })?;
+    ///     assert_eq!(import.segment_index(), index);
+    /// }
+    ///
+    /// assert_eq!(new.len(), 100);
+    /// assert_eq!(new.get(&21), Some(42));
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn export_segment<W: Write, F: FnMut(&K, &V, &mut Vec<u8>)>(
         &self,
-        key: &Q,
-        condition: F,
-        with_previous_entry: G,
-    ) -> Option<T>
+        index: usize,
+        mut writer: W,
+        mut encode: F,
+    ) -> io::Result<usize> {
+        let num_segments = self.segments.len();
+
+        if index >= num_segments {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                Error::SegmentIndexOutOfBounds {
+                    index,
+                    num_segments,
+                },
+            ));
+        }
+
+        let mut stream = stream::SegmentWriter::new(index, num_segments);
+
+        {
+            let _panic_guard = self.panic_policy.enter();
+            let guard = &crossbeam_epoch::pin();
+
+            for b in self.segment_bucket_array_ref(index).live_buckets(guard) {
+                stream.entry(|buf| encode(&b.key, unsafe { b.value() }, buf));
+            }
+        }
+
+        let (stream, num_entries) = stream.finish();
+        writer.write_all(&stream)?;
+
+        Ok(num_entries)
+    }
+
+    /// Inserts the entries of a stream written by [`export_segment`] into the
+    /// map, returning a summary of the imported segment.
+    ///
+    /// `decode` turns the encoding of each entry back into a key-value pair.
+    /// The keys are hashed anew, so the maps need not share their hasher or
+    /// their number of segments, and the entries are inserted like with
+    /// [`insert`], replacing the values of keys that are already present.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`InvalidData`] if the stream is not a
+    /// segment stream or its frames are out of sequence, of kind
+    /// [`UnexpectedEof`] if it is truncated, or the error of `reader` or of
+    /// `decode`. The entries read before the error stay in the map.
+    ///
+    /// [`export_segment`]: #method.export_segment
+    /// [`insert`]: #method.insert
+    /// [`InvalidData`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html#variant.InvalidData
+    /// [`UnexpectedEof`]: https://doc.rust-lang.org/std/io/enum.ErrorKind.html#variant.UnexpectedEof
+    pub fn import_segment<R: Read, F: FnMut(&[u8]) -> io::Result<(K, V)>>(
+        &self,
+        reader: R,
+        mut decode: F,
+    ) -> io::Result<SegmentImport> {
+        let mut stream = stream::SegmentReader::new(reader)?;
+
+        while let Some(payload) = stream.next_entry()? {
+            let (key, value) = decode(payload)?;
+            self.insert_entry_and(key, value, |_, _| ());
+        }
+
+        Ok(stream.finish())
+    }
+
+    /// Returns the salt among the current one and `num_candidates` others that
+    /// spreads the weights of `hot_keys` most evenly across the segments.
+    ///
+    /// Each hot key is paired with a weight, such as the number of times it was
+    /// accessed in a sample of the workload. A salt is better than another if
+    /// the heaviest segment it selects for the hot keys is lighter. The current
+    /// salt is returned if no candidate is strictly better, so that the result
+    /// can be passed to [`set_segment_salt`] without moving entries needlessly.
+    ///
+    /// The candidates only depend on the current salt, so calling this method
+    /// again after applying its result tries new ones.
+    ///
+    /// [`set_segment_salt`]: #method.set_segment_salt
+    pub fn balanced_segment_salt<'k, Q, I>(&self, hot_keys: I, num_candidates: usize) -> u64
     where
         K: Borrow<Q>,
+        Q: Hash + ?Sized + 'k,
+        I: IntoIterator<Item = (&'k Q, u64)>,
     {
-        let hash = bucket::hash(&self.build_hasher, &key);
+        let hot_keys: Vec<_> = hot_keys
+            .into_iter()
+            .map(|(key, weight)| (bucket::hash(&self.build_hasher, key), weight))
+            .collect();
+        let mut loads = vec![0u64; self.segments.len()];
 
-        self.bucket_array_ref(hash)
-            .remove_entry_if_and(key, hash, condition, move |k, v| {
-                self.len.fetch_sub(1, Ordering::Relaxed);
+        let mut max_load = |salt: u64| {
+            loads.iter_mut().for_each(|load| *load = 0);
 
-                with_previous_entry(k, v)
-            })
+            for &(hash, weight) in hot_keys.iter() {
+                let index = segment_index_from_salted_hash(hash, salt, self.segment_shift);
+                loads[index] = loads[index].saturating_add(weight);
+            }
+
+            loads.iter().copied().max().unwrap_or(0)
+        };
+
+        let mut best_salt = self.segment_salt;
+        let mut best_load = max_load(best_salt);
+        let mut state = self.segment_salt;
+
+        for _ in 0..num_candidates {
+            if best_load == 0 {
+                break;
+            }
+
+            // the candidates are the outputs of a splitmix64 generator seeded
+            // with the current salt.
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut salt = state;
+            salt = (salt ^ (salt >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            salt = (salt ^ (salt >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            salt ^= salt >> 31;
+
+            let load = max_load(salt);
+
+            if load < best_load {
+                best_salt = salt;
+                best_load = load;
+            }
+        }
+
+        best_salt
     }
 
-    /// If no value corresponds to the key, insert a new key-value pair into
-    /// the map. Otherwise, modify the existing value and return a clone of the
-    /// value previously corresponding to the key.
+    /// Changes the salt that selects the segments of the keys, moving the
+    /// entries to their new segments.
     ///
-    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
-    /// may also be invoked one or more times if [`None`] is returned.
+    /// The segment of a key is selected by the most significant bits of its
+    /// hash, so keys whose hashes share those bits, such as a few very hot keys
+    /// of a skewed workload, contend on the same segment. With a salt other
+    /// than 0, the segment is instead selected by a mix of the hash and the
+    /// salt, which separates such keys for most salts. A salt can be chosen
+    /// from a sample of the hot keys with [`balanced_segment_salt`]. The salt
+    /// of a new map is 0, which can be changed with the `segment_salt` method
+    /// of its [`Builder`].
     ///
-    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
-    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    /// The entries are moved without being cloned, keeping their generations,
+    /// access times, and flags. Moving them requires exclusive access to the
+    /// map, so the salt can only be changed offline. Rebalancing a map online,
+    /// by migrating its entries while other threads keep using it, is not
+    /// supported; callers that share the map between threads can get exclusive
+    /// access by swapping it out of its container, or by rebalancing while the
+    /// threads are paused. The length of the map is unchanged, and the capacity of each
+    /// segment fits the entries it receives. The tombstones of removed entries
+    /// are discarded, so [`EntryHandle`]s resolve by looking their keys up
+    /// again.
+    ///
+    /// With a salt other than 0, the segments no longer split the hashes into
+    /// contiguous ranges, so [`entries_in_hash_range`] scans every segment, and
+    /// the iterators of a deterministic map visit the entries in the order of
+    /// their hashes within each segment only.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a segment would receive more entries than a bucket pointer
+    /// array can hold. See [`try_set_segment_salt`] for a non-panicking
+    /// version.
+    ///
+    /// [`balanced_segment_salt`]: #method.balanced_segment_salt
+    /// [`Builder`]: ./struct.Builder.html
+    /// [`EntryHandle`]: ../../map/struct.EntryHandle.html
+    /// [`entries_in_hash_range`]: #method.entries_in_hash_range
+    /// [`try_set_segment_salt`]: #method.try_set_segment_salt
+    pub fn set_segment_salt(&mut self, salt: u64) {
+        self.try_set_segment_salt(salt)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Changes the salt that selects the segments of the keys, moving the
+    /// entries to their new segments, returning an error if a segment would
+    /// receive too many entries.
+    ///
+    /// Like [`set_segment_salt`], this requires exclusive access to the map,
+    /// so the salt can only be changed offline.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::CapacityOverflow`] if a bucket pointer array for the
+    /// entries of a segment would exceed the maximum allocation size. The map
+    /// is left unchanged.
+    ///
+    /// [`set_segment_salt`]: #method.set_segment_salt
+    /// [`Error::CapacityOverflow`]: ../../enum.Error.html#variant.CapacityOverflow
+    pub fn try_set_segment_salt(&mut self, salt: u64) -> Result<(), Error> {
+        if salt == self.segment_salt {
+            return Ok(());
+        }
+
+        // finish the resizes in progress, so that the current bucket pointer
+        // array of each segment holds all of its entries.
+        {
+            let guard = &crossbeam_epoch::pin();
+
+            for (index, segment) in self.segments.iter().enumerate() {
+                if !segment
+                    .bucket_array
+                    .load(ordering::RELAXED, guard)
+                    .is_null()
+                {
+                    self.segment_bucket_array_ref(index).live_buckets(guard);
+                }
+            }
+        }
+
+        // no other thread can access the map, so the buckets are not protected
+        // and are freed or moved immediately.
+        let guard = unsafe { crossbeam_epoch::unprotected() };
+        let mut moved = Vec::new();
+        moved.resize_with(self.segments.len(), Vec::new);
+        let mut epoch = 0;
+
+        for segment in self.segments.iter() {
+            let array_ptr = segment.bucket_array.load(ordering::RELAXED, guard);
+
+            if let Some(array_ref) = unsafe { array_ptr.as_ref() } {
+                epoch = epoch.max(array_ref.epoch + 1);
+
+                for this_bucket_ptr in array_ref
+                    .buckets
+                    .iter()
+                    .map(|b| b.load(ordering::RELAXED, guard))
+                    .filter(|p| !p.is_null() && p.tag() & bucket::TOMBSTONE_TAG == 0)
+                {
+                    let hash =
+                        bucket::hash(&self.build_hasher, &unsafe { this_bucket_ptr.deref() }.key);
+                    let index = segment_index_from_salted_hash(hash, salt, self.segment_shift);
+                    moved[index].push((hash, this_bucket_ptr));
+                }
+            }
+        }
+
+        for buckets in moved.iter() {
+            bucket::length_for_capacity(buckets.len())?;
+        }
+
+        for segment in self.segments.iter_mut() {
+            let array_ptr = segment
+                .bucket_array
+                .swap(Shared::null(), ordering::RELAXED, guard);
+
+            if let Some(array_ref) = unsafe { array_ptr.as_ref() } {
+                for this_bucket_ptr in array_ref
+                    .buckets
+                    .iter()
+                    .map(|b| b.load(ordering::RELAXED, guard))
+                    .filter(|p| !p.is_null() && p.tag() & bucket::TOMBSTONE_TAG != 0)
+                {
+                    unsafe { bucket::defer_acquire_destroy(guard, this_bucket_ptr) };
+                }
+            }
+
+            // a resize may have allocated newer arrays without copying any
+            // buckets into them yet, so they only hold borrowed pointers.
+            let mut current_ptr = array_ptr;
+
+            while let Some(current_ref) = unsafe { current_ptr.as_ref() } {
+                let next_ptr = current_ref.next.load(ordering::RELAXED, guard);
+                unsafe { bucket::defer_acquire_destroy(guard, current_ptr) };
+                current_ptr = next_ptr;
+            }
+        }
+
+        for (segment, buckets) in self.segments.iter_mut().zip(moved) {
+            *segment.len.get_mut() = buckets.len();
+            *segment.fingerprint.get_mut() = buckets
+                .iter()
+                .fold(0, |f, &(hash, _)| f | fingerprint_bit(hash));
+
+            if buckets.is_empty() {
+                continue;
+            }
+
+            let buckets = buckets
+                .into_iter()
+                .map(|(hash, p)| {
+                    let p = p.with_tag(p.tag() & bucket::FLAGS_MASK);
+
+                    (hash, unsafe { p.into_owned() })
+                })
+                .collect();
+            let array = BucketArray::from_hashed_buckets(epoch, buckets)
+                .expect("the capacity was checked above");
+            segment.bucket_array = Atomic::new(array);
+        }
+
+        self.segment_salt = salt;
+
+        Ok(())
+    }
+
+    /// Returns a clone of the value corresponding to the key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
     #[inline]
-    pub fn insert_or_modify<F: FnMut(&K, &V) -> V>(
-        &self,
-        key: K,
-        value: V,
-        on_modify: F,
-    ) -> Option<V>
+    pub fn get<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<V>
     where
+        K: Borrow<Q>,
         V: Clone,
     {
-        self.insert_with_or_modify_entry_and(key, move || value, on_modify, |_, v| v.clone())
+        self.get_key_value_and(key, |_, v| v.clone())
     }
 
-    /// If no value corresponds to the key, insert a new key-value pair into
-    /// the map. Otherwise, modify the existing value and return a clone of the
-    /// key-value pair previously corresponding to the key.
+    /// Returns a clone of the value corresponding to the key, loading it with
+    /// the [`Loader`] of the map and inserting it if the key is missing.
     ///
-    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
-    /// may also be invoked one or more times if [`None`] is returned.
+    /// Concurrent calls that miss the same key wait for a single call to the
+    /// loader and share its result. If the key is inserted while its value is
+    /// being loaded, the inserted value is kept and returned instead of the
+    /// loaded one. Returns `None` if the loader has no value for the key, or
+    /// if the map has no loader and the key is missing.
     ///
-    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
-    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
-    #[inline]
-    pub fn insert_or_modify_entry<F: FnMut(&K, &V) -> V>(
-        &self,
-        key: K,
-        value: V,
-        on_modify: F,
-    ) -> Option<(K, V)>
+    /// [`Loader`]: ../../map/trait.Loader.html
+    pub fn get_or_load(&self, key: &K) -> Option<V>
     where
         K: Clone,
         V: Clone,
     {
-        self.insert_with_or_modify_entry_and(
+        let loading = match &self.loading {
+            Some(loading) => loading,
+            None => return self.get(key),
+        };
+
+        loading.get_or_load(
             key,
-            move || value,
-            on_modify,
-            |k, v| (k.clone(), v.clone()),
+            |k| self.get(k),
+            |k, v| {
+                let hash = bucket::hash(&self.build_hasher, &k);
+                let guard = &crossbeam_epoch::pin();
+
+                let (b, inserted) =
+                    self.bucket_array_ref(hash)
+                        .get_or_insert_with(guard, k, hash, || v);
+
+                if inserted {
+                    self.len.fetch_add(1, ordering::RELAXED);
+                }
+
+                unsafe { b.value() }.clone()
+            },
         )
     }
 
-    /// If no value corresponds to the key, invoke a default function to insert
-    /// a new key-value pair into the map. Otherwise, modify the existing value
-    /// and return a clone of the value previously corresponding to the key.
+    /// Returns a clone of the value corresponding to the key, inserting the
+    /// value created by the [`DefaultValue`] factory of the map if the key is
+    /// missing.
     ///
-    /// `on_insert` may be invoked, even if [`None`] is returned.
+    /// The factory is only invoked if the key is missing. If the key is
+    /// inserted concurrently, the inserted value is kept and returned instead
+    /// of the created one.
     ///
-    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
-    /// may also be invoked one or more times if [`None`] is returned.
+    /// # Panics
     ///
-    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
-    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
-    #[inline]
-    pub fn insert_with_or_modify<F: FnOnce() -> V, G: FnMut(&K, &V) -> V>(
-        &self,
-        key: K,
-        on_insert: F,
-        on_modify: G,
-    ) -> Option<V>
+    /// Panics if the map has no default value factory.
+    ///
+    /// [`DefaultValue`]: ../../map/trait.DefaultValue.html
+    pub fn get_or_default(&self, key: K) -> V
     where
         V: Clone,
     {
-        self.insert_with_or_modify_entry_and(key, on_insert, on_modify, |_, v| v.clone())
+        if let Some(value) = self.get(&key) {
+            return value;
+        }
+
+        let value = self
+            .default_value
+            .as_ref()
+            .expect("the map has no default value factory")
+            .default_value(&key);
+        let hash = bucket::hash(&self.build_hasher, &key);
+        let guard = &crossbeam_epoch::pin();
+
+        let (b, inserted) =
+            self.bucket_array_ref(hash)
+                .get_or_insert_with(guard, key, hash, || value);
+
+        if inserted {
+            self.len.fetch_add(1, ordering::RELAXED);
+        }
+
+        unsafe { b.value() }.clone()
     }
 
-    /// If no value corresponds to the key, invoke a default function to insert
-    /// a new key-value pair into the map. Otherwise, modify the existing value
-    /// and return a clone of the key-value pair previously corresponding to the
+    /// Returns a clone of the the key-value pair corresponding to the supplied
     /// key.
     ///
-    /// `on_insert` may be invoked, even if [`None`] is returned.
-    ///
-    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
-    /// may also be invoked one or more times if [`None`] is returned.
+    /// The supplied key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for the key
+    /// type.
     ///
-    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
-    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
     #[inline]
-    pub fn insert_with_or_modify_entry<F: FnOnce() -> V, G: FnMut(&K, &V) -> V>(
-        &self,
-        key: K,
-        on_insert: F,
-        on_modify: G,
-    ) -> Option<(K, V)>
+    pub fn get_key_value<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<(K, V)>
     where
-        K: Clone,
+        K: Borrow<Q> + Clone,
         V: Clone,
     {
-        self.insert_with_or_modify_entry_and(key, on_insert, on_modify, |k, v| {
-            (k.clone(), v.clone())
-        })
+        self.get_key_value_and(key, |k, v| (k.clone(), v.clone()))
     }
 
-    /// If no value corresponds to the key, insert a new key-value pair into
-    /// the map. Otherwise, modify the existing value and return the result of
-    /// invoking a function with a reference to the value previously
+    /// Returns the result of invoking a function with a reference to the value
     /// corresponding to the key.
     ///
-    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
-    /// may also be invoked one or more times if [`None`] is returned.
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
     ///
-    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
-    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
     #[inline]
-    pub fn insert_or_modify_and<F: FnMut(&K, &V) -> V, G: FnOnce(&V) -> T, T>(
+    pub fn get_and<Q: Hash + Eq + ?Sized, F: FnOnce(&V) -> T, T>(
         &self,
-        key: K,
-        value: V,
-        on_modify: F,
-        with_old_value: G,
-    ) -> Option<T> {
-        self.insert_with_or_modify_entry_and(
-            key,
-            move || value,
-            on_modify,
-            move |_, v| with_old_value(v),
-        )
+        key: &Q,
+        with_value: F,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+    {
+        self.get_key_value_and(key, move |_, v| with_value(v))
     }
 
-    /// If no value corresponds to the key, insert a new key-value pair into
-    /// the map. Otherwise, modify the existing value and return the result of
-    /// invoking a function with a reference to the key-value pair previously
-    /// corresponding to the supplied key.
+    /// Returns the result of invoking a function with a reference to the
+    /// key-value pair corresponding to the supplied key.
     ///
-    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
-    /// may also be invoked one or more times if [`None`] is returned.
+    /// The supplied key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for the key
+    /// type.
     ///
-    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
-    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
     #[inline]
-    pub fn insert_or_modify_entry_and<F: FnMut(&K, &V) -> V, G: FnOnce(&K, &V) -> T, T>(
+    pub fn get_key_value_and<Q: Hash + Eq + ?Sized, F: FnOnce(&K, &V) -> T, T>(
         &self,
-        key: K,
-        value: V,
-        on_modify: F,
-        with_old_entry: G,
-    ) -> Option<T> {
-        self.insert_with_or_modify_entry_and(key, move || value, on_modify, with_old_entry)
+        key: &Q,
+        with_entry: F,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+    {
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        self.bucket_array_ref(hash)
+            .get_key_value_and(key, hash, with_entry)
     }
 
-    /// If no value corresponds to the key, invoke a default function to insert
-    /// a new key-value pair into the map. Otherwise, modify the existing value
-    /// and return the result of invoking a function with a reference to the
-    /// value previously corresponding to the key.
+    /// Returns a clone of the value corresponding to the key, performing at
+    /// most as much work as `budget` allows.
     ///
-    /// `on_insert` may be invoked, even if [`None`] is returned.
+    /// See [`get_and_within`] for details.
     ///
-    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
-    /// may also be invoked one or more times if [`None`] is returned.
+    /// # Errors
     ///
-    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
-    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    /// Returns [`Error::Busy`] if the budget runs out before the key is found
+    /// or known to be absent.
+    ///
+    /// [`get_and_within`]: #method.get_and_within
+    /// [`Error::Busy`]: ../../enum.Error.html#variant.Busy
     #[inline]
-    pub fn insert_with_or_modify_and<
-        F: FnOnce() -> V,
-        G: FnMut(&K, &V) -> V,
-        H: FnOnce(&V) -> T,
-        T,
-    >(
+    pub fn get_within<Q: Hash + Eq + ?Sized>(
         &self,
-        key: K,
-        on_insert: F,
-        on_modify: G,
-        with_old_value: H,
-    ) -> Option<T> {
-        self.insert_with_or_modify_entry_and(key, on_insert, on_modify, move |_, v| {
-            with_old_value(v)
-        })
+        key: &Q,
+        budget: ReadBudget,
+    ) -> Result<Option<V>, Error>
+    where
+        K: Borrow<Q>,
+        V: Clone,
+    {
+        self.get_and_within(key, budget, V::clone)
+    }
+
+    /// Returns the result of invoking a function with a reference to the value
+    /// corresponding to the key, performing at most as much work as `budget`
+    /// allows.
+    ///
+    /// The work is measured in slots probed and in buckets migrated to a new
+    /// bucket pointer array, which a lookup helps with if it runs into a
+    /// resize. See [`ReadBudget`] for details.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Busy`] if the budget runs out before the key is found
+    /// or known to be absent, in which case `with_value` is not invoked.
+    ///
+    /// [`ReadBudget`]: ../../map/struct.ReadBudget.html
+    /// [`Error::Busy`]: ../../enum.Error.html#variant.Busy
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn get_and_within<Q: Hash + Eq + ?Sized, F: FnOnce(&V) -> T, T>(
+        &self,
+        key: &Q,
+        mut budget: ReadBudget,
+        with_value: F,
+    ) -> Result<Option<T>, Error>
+    where
+        K: Borrow<Q>,
+    {
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        self.bucket_array_ref(hash)
+            .get_key_value_and_within(key, hash, &mut budget, move |_, v| with_value(v))
+    }
+
+    /// Returns statistics on the probe lengths of the entries of each
+    /// segment of the map.
+    ///
+    /// This scans the whole bucket pointer array. See the [`metrics`] module
+    /// for how to use the statistics to detect hash collision attacks.
+    ///
+    /// [`metrics`]: ../../metrics/index.html
+    pub fn probe_stats(&self) -> Vec<ProbeStats> {
+        (0..self.segments.len())
+            .map(|i| self.segment_bucket_array_ref(i).probe_stats())
+            .collect()
+    }
+
+    /// Shrinks the bucket pointer arrays or reclaims their tombstones if
+    /// needed, for as many segments as `budget` allows, and flushes the garbage
+    /// of the current thread.
+    ///
+    /// Each call resumes from the segment after the last one processed by the
+    /// previous call. See the [`housekeeping`] module for details.
+    ///
+    /// Shrinking may reduce the capacity below the one the map was created
+    /// with.
+    ///
+    /// [`housekeeping`]: ../../housekeeping/index.html
+    pub fn run_housekeeping(&self, budget: Budget) -> HousekeepingReport {
+        let num_segments = self.segments.len();
+        let mut report = HousekeepingReport::default();
+
+        while report.num_segments_visited() < num_segments
+            && (report.num_segments_visited() == 0 || report.is_within(budget))
+        {
+            let index = self.housekeeping_cursor.fetch_add(1, ordering::RELAXED) % num_segments;
+
+            let (num_buckets_visited, op) = self.segment_bucket_array_ref(index).run_housekeeping();
+            report.record_segment(num_buckets_visited, op);
+
+            if index == num_segments - 1 {
+                report.is_pass_complete = true;
+
+                break;
+            }
+        }
+
+        housekeeping::flush_garbage();
+
+        report
+    }
+
+    /// Removes up to as many entries as `budget` allows, resuming the clear
+    /// that the previous call left incomplete, and flushes the garbage of the
+    /// current thread.
+    ///
+    /// Calling this repeatedly until it reports that the clear is complete
+    /// removes every entry that was in the map before the first call, segment
+    /// by segment, without stalling any call for longer than it takes to remove
+    /// its batch. See the [`housekeeping`] module for details.
+    ///
+    /// [`housekeeping`]: ../../housekeeping/index.html
+    pub fn clear_in_batches(&self, budget: Budget) -> ClearProgress {
+        let mut cursor = self.clear_cursor.lock().unwrap_or_else(|e| e.into_inner());
+        let mut progress = ClearProgress::default();
+        let mut num_visited = 0;
+
+        loop {
+            let bucket_array_ref = self.segment_bucket_array_ref(cursor.segment());
+            let guard = &crossbeam_epoch::pin();
+            let (buckets, next) = bucket_array_ref.scan(
+                guard,
+                cursor.position(),
+                budget.max_buckets().saturating_sub(num_visited),
+            );
+            num_visited += buckets.len();
+
+            for this_bucket_ref in buckets {
+                let key = &this_bucket_ref.key;
+                let hash = bucket::hash(&self.build_hasher, key);
+
+                if bucket_array_ref
+                    .remove_entry_if_and(key, hash, |k, _| ptr::eq(k, key), |_, _| ())
+                    .is_some()
+                {
+                    self.len.fetch_sub(1, ordering::RELAXED);
+                    progress.num_removed += 1;
+                }
+            }
+
+            cursor.advance(next, self.segments.len());
+
+            if cursor.is_done() || num_visited >= budget.max_buckets() {
+                break;
+            }
+        }
+
+        if cursor.is_done() {
+            *cursor = ScanCursor::new();
+            progress.is_complete = true;
+        }
+
+        housekeeping::flush_garbage();
+
+        progress
+    }
+
+    /// Hints the processor to load the memory that looking up a key will read
+    /// first, and returns without waiting for it.
+    ///
+    /// This hashes the key and prefetches the cache line of the bucket pointer
+    /// array where probing for the key starts. Calling it for each of a batch
+    /// of keys before looking them up overlaps the cache misses of the lookups
+    /// with other work, such as decoding the following keys. It has no other
+    /// effect, and does nothing on architectures without a stable prefetch
+    /// instruction.
+    ///
+    /// A key that was never inserted into its segment, according to the
+    /// fingerprint of the segment, is not prefetched, since looking it up does
+    /// not probe.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn prefetch<Q: Hash + Eq + ?Sized>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+    {
+        let hash = bucket::hash(&self.build_hasher, key);
+
+        self.bucket_array_ref(hash).prefetch(hash);
+    }
+
+    /// Returns references to the values corresponding to each of the keys, in
+    /// the same order as the keys.
+    ///
+    /// All lookups are performed under the single epoch guard `guard`, and the
+    /// returned references remain valid for as long as it is held. No values are
+    /// cloned. A returned value may have been concurrently removed or replaced
+    /// since it was looked up.
+    ///
+    /// The keys are looked up one segment at a time.
+    ///
+    /// The keys may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `guard` is not pinned to the default collector of
+    /// `crossbeam-epoch`, which the map reclaims its values through.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn get_many_ref<'g, 'k, Q: Hash + Eq + ?Sized + 'k, I: IntoIterator<Item = &'k Q>>(
+        &'g self,
+        keys: I,
+        guard: &'g Guard,
+    ) -> Vec<Option<&'g V>>
+    where
+        K: Borrow<Q>,
+    {
+        bucket::check_guard(guard);
+
+        let mut lookups: Vec<_> = keys
+            .into_iter()
+            .map(|key| (bucket::hash(&self.build_hasher, key), key))
+            .enumerate()
+            .collect();
+        lookups.sort_by_key(|&(_, (hash, _))| self.segment_index_from_hash(hash));
+
+        let mut values = vec![None; lookups.len()];
+
+        for (i, (hash, key)) in lookups {
+            values[i] = self
+                .bucket_array_ref(hash)
+                .get_bucket(guard, key, hash)
+                .map(|b| unsafe { b.value() });
+        }
+
+        values
+    }
+
+    /// Returns an [`EntryHandle`] to the entry corresponding to the key, which
+    /// can later be resolved with [`resolve`] without probing.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`EntryHandle`]: ../../map/struct.EntryHandle.html
+    /// [`resolve`]: #method.resolve
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn entry_handle<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<EntryHandle<K>>
+    where
+        K: Borrow<Q> + Clone,
+    {
+        let hash = bucket::hash(&self.build_hasher, key);
+        let guard = &crossbeam_epoch::pin();
+
+        self.bucket_array_ref(hash)
+            .get_bucket_with_hint(guard, key, hash, None)
+            .map(|(b, hint)| EntryHandle::new(b.key.clone(), hint))
+    }
+
+    /// Returns an [`EntryPin`] to the entry corresponding to the key, which
+    /// keeps its key and value alive until it is dropped, even if the entry is
+    /// removed from the map in the meantime.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// This method is only available with the `entry-pins` feature.
+    ///
+    /// [`EntryPin`]: ../../map/struct.EntryPin.html
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[cfg(feature = "entry-pins")]
+    pub fn pin_entry<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<EntryPin<'_, K, V>>
+    where
+        K: Borrow<Q>,
+    {
+        let hash = bucket::hash(&self.build_hasher, key);
+        let guard = &crossbeam_epoch::pin();
+
+        self.bucket_array_ref(hash)
+            .get_bucket(guard, key, hash)
+            .and_then(|b| unsafe { EntryPin::new(b) })
+    }
+
+    /// Returns a [`Ref`] to the current entry of the key of `handle`, or `None`
+    /// if the key is not in the map.
+    ///
+    /// The slot that held the entry when `handle` was last resolved is checked
+    /// first. If the entry has been moved to a new bucket pointer array since,
+    /// the key is looked up again and `handle` is updated with its new slot.
+    ///
+    /// [`Ref`]: ../../map/struct.Ref.html
+    pub fn resolve(&self, handle: &EntryHandle<K>) -> Option<Ref<'_, K, V>> {
+        let hash = handle.hash();
+        let guard = crossbeam_epoch::pin();
+
+        let (bucket_ref, hint) = self.bucket_array_ref(hash).get_bucket_with_hint(
+            &guard,
+            handle.key(),
+            hash,
+            Some(handle.hint()),
+        )?;
+        let bucket_ptr: *const Bucket<K, V> = bucket_ref;
+        handle.update(hint);
+
+        Some(unsafe { Ref::new(guard, bucket_ptr) })
+    }
+
+    /// Returns the flags of the entry corresponding to the key.
+    ///
+    /// Each entry has the flag bits in [`ENTRY_FLAGS`], which are not used by
+    /// the map and can be set by embedders to mark entries, for example as
+    /// admitted or dirty, without maintaining an auxiliary map. The flags are
+    /// stored in the tag bits of the pointer to the entry, so they are updated
+    /// atomically with its value: they are kept when the value is modified or
+    /// replaced by an insertion, and cleared when the key is removed. A newly
+    /// inserted key has no flags set.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// This method is only available with the `entry-flags` feature.
+    ///
+    /// [`ENTRY_FLAGS`]: ../../map/constant.ENTRY_FLAGS.html
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[cfg(feature = "entry-flags")]
+    pub fn fetch_flags<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<u8>
+    where
+        K: Borrow<Q>,
+    {
+        let hash = bucket::hash(&self.build_hasher, key);
+
+        self.bucket_array_ref(hash)
+            .update_flags(key, hash, |f| f)
+            .map(|f| f as u8)
+    }
+
+    /// Sets the given flags of the entry corresponding to the key, returning
+    /// its previous flags.
+    ///
+    /// Returns `None` and does nothing if the key is not in the map.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `flags` has bits set outside of [`ENTRY_FLAGS`].
+    ///
+    /// This method is only available with the `entry-flags` feature.
+    ///
+    /// [`ENTRY_FLAGS`]: ../../map/constant.ENTRY_FLAGS.html
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[cfg(feature = "entry-flags")]
+    pub fn set_flags<Q: Hash + Eq + ?Sized>(&self, key: &Q, flags: u8) -> Option<u8>
+    where
+        K: Borrow<Q>,
+    {
+        assert_eq!(
+            flags & !ENTRY_FLAGS,
+            0,
+            "the flags must be within ENTRY_FLAGS"
+        );
+
+        let hash = bucket::hash(&self.build_hasher, key);
+
+        self.bucket_array_ref(hash)
+            .update_flags(key, hash, |f| f | flags as usize)
+            .map(|f| f as u8)
+    }
+
+    /// Clears the given flags of the entry corresponding to the key, returning
+    /// its previous flags.
+    ///
+    /// Returns `None` and does nothing if the key is not in the map.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `flags` has bits set outside of [`ENTRY_FLAGS`].
+    ///
+    /// This method is only available with the `entry-flags` feature.
+    ///
+    /// [`ENTRY_FLAGS`]: ../../map/constant.ENTRY_FLAGS.html
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[cfg(feature = "entry-flags")]
+    pub fn clear_flags<Q: Hash + Eq + ?Sized>(&self, key: &Q, flags: u8) -> Option<u8>
+    where
+        K: Borrow<Q>,
+    {
+        assert_eq!(
+            flags & !ENTRY_FLAGS,
+            0,
+            "the flags must be within ENTRY_FLAGS"
+        );
+
+        let hash = bucket::hash(&self.build_hasher, key);
+
+        self.bucket_array_ref(hash)
+            .update_flags(key, hash, |f| f & !(flags as usize))
+            .map(|f| f as u8)
+    }
+
+    /// An iterator visiting all entries in arbitrary order.
+    ///
+    /// The current thread is pinned while the iterator is alive, and the
+    /// entries it yields are [`Ref`]s that borrow the keys and values in place,
+    /// so nothing is cloned. Each segment's bucket pointer array is read after
+    /// any pending resize of the segment is completed, so every entry is
+    /// yielded at most once. The iterator is weakly consistent: it reflects
+    /// the contents of each segment at some point at or after its creation,
+    /// and it is not affected by modifications made while it is being
+    /// consumed.
+    ///
+    /// The segments are collected one at a time, and the entries of a
+    /// [deterministic] map are yielded in the order of the hashes of their
+    /// keys within each segment.
+    ///
+    /// [`Ref`]: ../../map/struct.Ref.html
+    /// [deterministic]: ./struct.Builder.html#method.deterministic
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(4);
+    /// map.insert("a", 1);
+    /// map.insert("b", 2);
+    ///
+    /// let mut entries: Vec<_> = map.iter().map(|r| (*r.key(), *r.value())).collect();
+    /// entries.sort_unstable();
+    ///
+    /// assert_eq!(entries, [("a", 1), ("b", 2)]);
+    /// ```
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let guard = crossbeam_epoch::pin();
+        let mut buckets = Vec::with_capacity(self.len());
+
+        for index in 0..self.segments.len() {
+            buckets.extend(self.segment_bucket_array_ref(index).live_buckets(&guard));
+        }
+
+        let buckets = bucket::into_ptrs(buckets);
+
+        unsafe { Iter::new(guard, buckets) }
+    }
+
+    /// An iterator visiting a clone of every key in arbitrary order.
+    ///
+    /// The keys are visited like the entries returned by [`iter`], and the
+    /// iterator is weakly consistent in the same way. Use [`iter`] to borrow
+    /// the keys in place instead of cloning them.
+    ///
+    /// [`iter`]: #method.iter
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(4);
+    /// map.insert("a", 1);
+    /// map.insert("b", 2);
+    ///
+    /// let mut keys: Vec<_> = map.keys().collect();
+    /// keys.sort_unstable();
+    ///
+    /// assert_eq!(keys, ["a", "b"]);
+    /// ```
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys::new(self.iter())
+    }
+
+    /// An iterator visiting references to all values in arbitrary order.
+    ///
+    /// The segments are collected one at a time when this is called, and
+    /// the returned references remain valid for as long as `guard` is held. No
+    /// values are cloned. A value may have been concurrently removed or
+    /// replaced since it was collected. See [`values_cloned`] for a version
+    /// that does not take a guard.
+    ///
+    /// [`values_cloned`]: #method.values_cloned
+    ///
+    /// # Panics
+    ///
+    /// Panics if `guard` is not pinned to the default collector of
+    /// `crossbeam-epoch`, which the map reclaims its values through.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::{epoch, SegmentedHashMap};
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(4);
+    /// map.insert("a", 1);
+    /// map.insert("b", 2);
+    ///
+    /// let guard = &epoch::pin();
+    /// let sum: i32 = map.values(guard).sum();
+    ///
+    /// assert_eq!(sum, 3);
+    /// ```
+    pub fn values<'g>(&'g self, guard: &'g Guard) -> Values<'g, K, V> {
+        bucket::check_guard(guard);
+
+        let mut buckets = Vec::with_capacity(self.len());
+
+        for index in 0..self.segments.len() {
+            buckets.extend(self.segment_bucket_array_ref(index).live_buckets(guard));
+        }
+
+        Values::new(buckets)
+    }
+
+    /// An iterator visiting a clone of every value in arbitrary order.
+    ///
+    /// The values are visited like the entries returned by [`iter`], and the
+    /// iterator is weakly consistent in the same way.
+    ///
+    /// [`iter`]: #method.iter
+    pub fn values_cloned(&self) -> ValuesCloned<'_, K, V> {
+        ValuesCloned::new(self.iter())
+    }
+
+    /// Invokes `f` with a reference to the key and value of every entry, in
+    /// arbitrary order.
+    ///
+    /// The bucket pointer arrays of the segments are walked in place under a
+    /// single epoch guard, one segment at a time, buffering at most one cluster
+    /// of occupied slots at a time, and the references passed to `f` are only
+    /// valid for the duration of the call. Nothing is cloned, and the entries
+    /// are visited in the order of their slots even if the map is
+    /// deterministic. Like [`iter`], the traversal is weakly consistent, and
+    /// `f` may modify the map, including the entry it is invoked with. An
+    /// entry is never visited twice, even if a segment is resized during the
+    /// traversal.
+    ///
+    /// [`iter`]: #method.iter
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(4);
+    /// map.insert("a", 1);
+    /// map.insert("b", 2);
+    ///
+    /// let mut sum = 0;
+    /// map.for_each(|_, v| sum += v);
+    ///
+    /// assert_eq!(sum, 3);
+    /// ```
+    pub fn for_each<F: FnMut(&K, &V)>(&self, mut f: F) {
+        let _panic_guard = self.panic_policy.enter();
+        let guard = &crossbeam_epoch::pin();
+
+        for index in 0..self.segments.len() {
+            for b in self.segment_bucket_array_ref(index).walk(guard) {
+                f(&b.key, unsafe { b.value() });
+            }
+        }
+    }
+
+    /// Invokes `collector` with a reference to the key and value of every
+    /// entry that satisfies `predicate`, in arbitrary order, returning the
+    /// number of matching entries.
+    ///
+    /// The entries are traversed in place like in [`for_each`], and
+    /// `predicate` is invoked with references to every entry as its slot is
+    /// read, so the entries that do not match are never cloned or collected.
+    /// Like [`for_each`], the traversal is weakly consistent.
+    ///
+    /// [`for_each`]: #method.for_each
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(4);
+    ///
+    /// // the last access time of each key
+    /// for i in 0..100u64 {
+    ///     map.insert(i, i * 10);
+    /// }
+    ///
+    /// let mut stale = Vec::new();
+    /// let num_stale = map.scan_filtered(|_, &accessed| accessed < 50, |&k, _| stale.push(k));
+    ///
+    /// stale.sort_unstable();
+    /// assert_eq!(num_stale, 5);
+    /// assert_eq!(stale, [0, 1, 2, 3, 4]);
+    /// ```
+    pub fn scan_filtered<P, C>(&self, mut predicate: P, mut collector: C) -> usize
+    where
+        P: FnMut(&K, &V) -> bool,
+        C: FnMut(&K, &V),
+    {
+        let _panic_guard = self.panic_policy.enter();
+        let guard = &crossbeam_epoch::pin();
+        let mut num_matching = 0;
+
+        for index in 0..self.segments.len() {
+            for b in self.segment_bucket_array_ref(index).walk(guard) {
+                let (key, value) = (&b.key, unsafe { b.value() });
+
+                if predicate(key, value) {
+                    collector(key, value);
+                    num_matching += 1;
+                }
+            }
+        }
+
+        num_matching
+    }
+
+    /// Folds every entry into an accumulator by invoking `f` with the
+    /// accumulator and a reference to the key and value of the entry, in
+    /// arbitrary order, returning the final accumulator.
+    ///
+    /// The entries are traversed in place like in [`for_each`], one segment at
+    /// a time under a single epoch guard, and the references passed to `f` are
+    /// only valid for the duration of the call, so sums, counts, or maxima can be computed
+    /// without cloning any entry. Like [`for_each`], the traversal is weakly
+    /// consistent.
+    ///
+    /// [`for_each`]: #method.for_each
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(4);
+    /// map.insert("a", 1);
+    /// map.insert("b", 2);
+    /// map.insert("c", 3);
+    ///
+    /// let (count, sum) = map.fold((0, 0), |(count, sum), _, v| (count + 1, sum + v));
+    ///
+    /// assert_eq!(count, 3);
+    /// assert_eq!(sum, 6);
+    /// ```
+    pub fn fold<A, F: FnMut(A, &K, &V) -> A>(&self, init: A, mut f: F) -> A {
+        let _panic_guard = self.panic_policy.enter();
+        let guard = &crossbeam_epoch::pin();
+        let mut acc = init;
+
+        for index in 0..self.segments.len() {
+            for b in self.segment_bucket_array_ref(index).walk(guard) {
+                acc = f(acc, &b.key, unsafe { b.value() });
+            }
+        }
+
+        acc
+    }
+
+    /// Maps every entry to a value with `map`, invoked with a reference to the
+    /// key and value of the entry, and reduces those values to a single one
+    /// with `reduce`, in arbitrary order. Returns `None` if the map is empty.
+    ///
+    /// The entries are traversed like in [`fold`], so nothing is cloned unless
+    /// `map` clones it. Like [`for_each`], the traversal is weakly consistent.
+    ///
+    /// [`fold`]: #method.fold
+    /// [`for_each`]: #method.for_each
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(4);
+    /// map.insert("a", 3);
+    /// map.insert("b", 7);
+    /// map.insert("c", 5);
+    ///
+    /// assert_eq!(map.reduce(|_, &v| v, i32::max), Some(7));
+    ///
+    /// let empty = SegmentedHashMap::<&str, i32>::with_num_segments(4);
+    /// assert_eq!(empty.reduce(|_, &v| v, i32::max), None);
+    /// ```
+    pub fn reduce<T, M, R>(&self, mut map: M, mut reduce: R) -> Option<T>
+    where
+        M: FnMut(&K, &V) -> T,
+        R: FnMut(T, T) -> T,
+    {
+        self.fold(None, |acc, k, v| {
+            let value = map(k, v);
+
+            Some(match acc {
+                Some(acc) => reduce(acc, value),
+                None => value,
+            })
+        })
+    }
+
+    /// Invokes `f` with a reference to the key and value of every entry, in
+    /// arbitrary order, replacing the value of the entry with the one `f`
+    /// returns, if any.
+    ///
+    /// The replacement is applied like [`modify`] applies its new values: a
+    /// new bucket holding a clone of the key and the replacement is swapped in
+    /// atomically, so concurrent readers see either the old value or the new
+    /// one. If the entry is concurrently modified, `f` is invoked again with
+    /// the current value, and if it is concurrently removed, it stays removed.
+    /// Like [`for_each`], the traversal is weakly consistent. `f` should not
+    /// modify the entry it is invoked with, as that makes the replacement
+    /// retry.
+    ///
+    /// [`modify`]: #method.modify
+    /// [`for_each`]: #method.for_each
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(4);
+    /// map.insert("a", -1);
+    /// map.insert("b", 2);
+    ///
+    /// // clamp the negative values to zero
+    /// map.for_each_modify(|_, &v| if v < 0 { Some(0) } else { None });
+    ///
+    /// assert_eq!(map.get("a"), Some(0));
+    /// assert_eq!(map.get("b"), Some(2));
+    /// ```
+    pub fn for_each_modify<F: FnMut(&K, &V) -> Option<V>>(&self, mut f: F)
+    where
+        K: Clone,
+    {
+        let _panic_guard = self.panic_policy.enter();
+        let guard = &crossbeam_epoch::pin();
+
+        for index in 0..self.segments.len() {
+            let bucket_array_ref = self.segment_bucket_array_ref(index);
+
+            for b in bucket_array_ref.live_buckets(guard) {
+                let hash = bucket::hash(&self.build_hasher, &b.key);
+                bucket_array_ref.try_modify_entry_and(b.key.clone(), hash, &mut f, |_, _| ());
+            }
+        }
+    }
+
+    /// An iterator visiting all entries of the `index`-th segment in arbitrary
+    /// order, yielding a [`Ref`] to each of them.
+    ///
+    /// The entries are collected like those of a single segment by [`iter`],
+    /// and the iterator is weakly consistent in the same way. Together with
+    /// [`num_segments`], this lets maintenance tasks process a bounded slice of
+    /// the map at a time, for example one segment per tick.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than the number of segments. See
+    /// [`try_iter_segment`] for a non-panicking version.
+    ///
+    /// [`Ref`]: ../../map/struct.Ref.html
+    /// [`iter`]: #method.iter
+    /// [`num_segments`]: #method.num_segments
+    /// [`try_iter_segment`]: #method.try_iter_segment
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(4);
+    ///
+    /// for i in 0..64 {
+    ///     map.insert(i, i);
+    /// }
+    ///
+    /// let index = map.segment_index(&7);
+    /// assert!(map.iter_segment(index).any(|r| *r.key() == 7));
+    ///
+    /// let num_entries: usize = (0..map.num_segments())
+    ///     .map(|index| map.iter_segment(index).count())
+    ///     .sum();
+    /// assert_eq!(num_entries, 64);
+    /// ```
+    pub fn iter_segment(&self, index: usize) -> Iter<'_, K, V> {
+        self.try_iter_segment(index)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// An iterator visiting all entries of the `index`-th segment in arbitrary
+    /// order, or an error if there is no such segment.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::SegmentIndexOutOfBounds`] if `index` is not less than
+    /// the number of segments.
+    ///
+    /// [`Error::SegmentIndexOutOfBounds`]: ../../enum.Error.html#variant.SegmentIndexOutOfBounds
+    pub fn try_iter_segment(&self, index: usize) -> Result<Iter<'_, K, V>, Error> {
+        if index >= self.segments.len() {
+            return Err(Error::SegmentIndexOutOfBounds {
+                index,
+                num_segments: self.segments.len(),
+            });
+        }
+
+        let guard = crossbeam_epoch::pin();
+        let buckets = bucket::into_ptrs(self.segment_bucket_array_ref(index).live_buckets(&guard));
+
+        Ok(unsafe { Iter::new(guard, buckets) })
+    }
+
+    /// Invokes `f` with a reference to the key and value of every entry of the
+    /// `index`-th segment, in arbitrary order.
+    ///
+    /// The entries are visited like those of a single segment by
+    /// [`for_each`], so the references passed to `f` are only valid for the
+    /// duration of the call, and `f` may modify the map.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than the number of segments. See
+    /// [`try_for_each_in_segment`] for a non-panicking version.
+    ///
+    /// [`for_each`]: #method.for_each
+    /// [`try_for_each_in_segment`]: #method.try_for_each_in_segment
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(4);
+    ///
+    /// for i in 0..64 {
+    ///     map.insert(i, i);
+    /// }
+    ///
+    /// // one segment per tick
+    /// let mut sum = 0;
+    ///
+    /// for tick in 0..map.num_segments() {
+    ///     map.for_each_in_segment(tick, |_, v| sum += v);
+    /// }
+    ///
+    /// assert_eq!(sum, (0..64).sum());
+    /// ```
+    pub fn for_each_in_segment<F: FnMut(&K, &V)>(&self, index: usize, f: F) {
+        self.try_for_each_in_segment(index, f)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Invokes `f` with a reference to the key and value of every entry of the
+    /// `index`-th segment, in arbitrary order, or returns an error if there is
+    /// no such segment.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::SegmentIndexOutOfBounds`] if `index` is not less than
+    /// the number of segments, without invoking `f`.
+    ///
+    /// [`Error::SegmentIndexOutOfBounds`]: ../../enum.Error.html#variant.SegmentIndexOutOfBounds
+    pub fn try_for_each_in_segment<F: FnMut(&K, &V)>(
+        &self,
+        index: usize,
+        mut f: F,
+    ) -> Result<(), Error> {
+        if index >= self.segments.len() {
+            return Err(Error::SegmentIndexOutOfBounds {
+                index,
+                num_segments: self.segments.len(),
+            });
+        }
+
+        let _panic_guard = self.panic_policy.enter();
+        let guard = &crossbeam_epoch::pin();
+
+        for b in self.segment_bucket_array_ref(index).live_buckets(guard) {
+            f(&b.key, unsafe { b.value() });
+        }
+
+        Ok(())
+    }
+
+    /// Invokes `f` with a reference to the key and value of every entry, in
+    /// arbitrary order, on the threads of the rayon pool.
+    ///
+    /// Each segment is visited by a task of the pool, which collects its
+    /// entries under an epoch guard of its own and splits them into chunks for
+    /// other tasks if the segment has many entries. Like [`for_each`], the
+    /// traversal is weakly consistent, and `f` may modify the map.
+    ///
+    /// This method is only available with the `rayon` feature.
+    ///
+    /// [`for_each`]: #method.for_each
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// use std::sync::atomic::{AtomicU64, Ordering};
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(8);
+    ///
+    /// for i in 0..100_000u64 {
+    ///     map.insert(i, i);
+    /// }
+    ///
+    /// let sum = AtomicU64::new(0);
+    /// map.par_for_each(|_, v| {
+    ///     sum.fetch_add(*v, Ordering::Relaxed);
+    /// });
+    ///
+    /// assert_eq!(sum.into_inner(), (0..100_000).sum());
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn par_for_each<F: Fn(&K, &V) + Sync>(&self, f: F)
+    where
+        K: Send + Sync,
+        V: Send + Sync,
+        S: Sync,
+    {
+        use rayon::prelude::*;
+
+        let _panic_guard = self.panic_policy.enter();
+
+        (0..self.segments.len()).into_par_iter().for_each(|index| {
+            let guard = &crossbeam_epoch::pin();
+            let buckets = self
+                .segment_bucket_array_ref(index)
+                .live_buckets_in_memory_order(guard);
+
+            crate::map::parallel::for_each(&buckets, &f);
+        });
+    }
+
+    /// Returns a parallel iterator over clones of the key-value pairs of the
+    /// map, in arbitrary order.
+    ///
+    /// The entries of each segment are cloned by a task of the rayon pool
+    /// when the iterator gets to the segment, since the epoch guard that
+    /// protects them can not outlive the task. Prefer [`par_for_each`] to
+    /// avoid the clones.
+    ///
+    /// This method is only available with the `rayon` feature.
+    ///
+    /// [`par_for_each`]: #method.par_for_each
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    /// use rayon::prelude::*;
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(8);
+    ///
+    /// for i in 0..100_000u64 {
+    ///     map.insert(i, i);
+    /// }
+    ///
+    /// assert_eq!(map.par_iter().filter(|(_, v)| v % 2 == 0).count(), 50_000);
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn par_iter(&self) -> impl rayon::iter::ParallelIterator<Item = (K, V)> + '_
+    where
+        K: Clone + Send + Sync,
+        V: Clone + Send + Sync,
+        S: Sync,
+    {
+        use rayon::prelude::*;
+
+        (0..self.segments.len())
+            .into_par_iter()
+            .flat_map_iter(move |index| {
+                let guard = &crossbeam_epoch::pin();
+                let buckets = self
+                    .segment_bucket_array_ref(index)
+                    .live_buckets_in_memory_order(guard);
+
+                crate::map::parallel::cloned(&buckets)
+            })
+    }
+
+    /// An iterator visiting all entries segment by segment, and in the order
+    /// of the slots that hold them within each segment, which is the fastest
+    /// way to traverse the map.
+    ///
+    /// Nothing is collected up front: the bucket pointer arrays are read in
+    /// place as the iterator is consumed, one cluster of occupied slots at a
+    /// time, and the buckets of each cluster are prefetched as soon as it is
+    /// read. The order is arbitrary even for a [deterministic] map, and the
+    /// iterator is weakly consistent, as described for [`UnorderedIter`].
+    /// Prefer this for full scans, such as those of maintenance tasks, that do
+    /// not depend on the order of the entries.
+    ///
+    /// [deterministic]: ./struct.Builder.html#method.deterministic
+    /// [`UnorderedIter`]: ../../map/struct.UnorderedIter.html
+    pub fn iter_unordered_fast(&self) -> UnorderedIter<'_, K, V, S> {
+        UnorderedIter::new(
+            (0..self.segments.len())
+                .map(|index| self.segment_bucket_array_ref(index))
+                .collect(),
+        )
+    }
+
+    /// An iterator visiting the entries whose generation is at most
+    /// `generation`, in arbitrary order.
+    ///
+    /// A traversal started with the value returned by [`advance_generation`]
+    /// never observes keys inserted after that call returned, which is a
+    /// weaker but much cheaper form of consistency than a snapshot. Entries
+    /// removed or modified during the traversal may or may not be observed,
+    /// and insertions that overlap the call to `advance_generation` may be
+    /// stamped with either generation.
+    ///
+    /// The segments are collected one at a time.
+    ///
+    /// This method is only available with the `generations` feature.
+    ///
+    /// [`advance_generation`]: #method.advance_generation
+    #[cfg(feature = "generations")]
+    pub fn iter_upto_generation(&self, generation: u64) -> Iter<'_, K, V> {
+        let guard = crossbeam_epoch::pin();
+        let mut buckets = Vec::new();
+
+        for index in 0..self.segments.len() {
+            buckets.extend(
+                self.segment_bucket_array_ref(index)
+                    .live_buckets(&guard)
+                    .into_iter()
+                    .filter(|b| b.generation() <= generation),
+            );
+        }
+
+        let buckets = bucket::into_ptrs(buckets);
+
+        unsafe { Iter::new(guard, buckets) }
+    }
+
+    /// An iterator visiting the entries whose keys hash into the inclusive
+    /// range from `lo` to `hi`, in arbitrary order.
+    ///
+    /// The iterator is weakly consistent, like the one returned by
+    /// [`iter`]. See the [`sharding`] module for details.
+    ///
+    /// Only the segments that keys in the range can belong to are scanned,
+    /// which are all of them if the map has a [segment salt].
+    ///
+    /// [`iter`]: #method.iter
+    /// [`sharding`]: ../../sharding/index.html
+    /// [segment salt]: #method.set_segment_salt
+    pub fn entries_in_hash_range(&self, lo: u64, hi: u64) -> Iter<'_, K, V> {
+        let guard = crossbeam_epoch::pin();
+        let mut buckets = Vec::new();
+
+        if lo <= hi {
+            let segments = if self.segment_salt == 0 {
+                self.segment_index_from_hash(lo)..=self.segment_index_from_hash(hi)
+            } else {
+                0..=self.segments.len() - 1
+            };
+
+            for index in segments {
+                buckets.extend(
+                    self.segment_bucket_array_ref(index)
+                        .live_buckets(&guard)
+                        .into_iter()
+                        .filter(|b| (lo..=hi).contains(&bucket::hash(&self.build_hasher, &b.key))),
+                );
+            }
+        }
+
+        let buckets = bucket::into_ptrs(buckets);
+
+        unsafe { Iter::new(guard, buckets) }
+    }
+
+    /// Returns a [`Ref`] to an entry of the map picked at random, or `None` if
+    /// the map is empty.
+    ///
+    /// A segment and a slot of its bucket pointer array are picked at random,
+    /// and the first entry from that slot on is returned. If the segment is
+    /// empty, the following segments are tried in turn. This takes constant
+    /// time on average and does not collect the entries, which makes it
+    /// suitable for random eviction and sampling-based estimates. The choice
+    /// is only approximately uniform: the entries of segments with fewer
+    /// entries are more likely to be returned, and so is an entry that
+    /// follows a long run of empty slots.
+    ///
+    /// [`Ref`]: ../../map/struct.Ref.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(4);
+    /// assert!(map.random_entry().is_none());
+    ///
+    /// for i in 0..100 {
+    ///     map.insert(i, i * 2);
+    /// }
+    ///
+    /// let entry = map.random_entry().unwrap();
+    /// assert_eq!(*entry.value(), entry.key() * 2);
+    /// ```
+    pub fn random_entry(&self) -> Option<Ref<'_, K, V>> {
+        let guard = crossbeam_epoch::pin();
+        let num_segments = self.segments.len();
+        let first = metrics::random() as usize % num_segments;
+        let slot = metrics::random();
+
+        let bucket_ptr: *const Bucket<K, V> = (0..num_segments)
+            .map(|i| (first + i) % num_segments)
+            .find_map(|index| {
+                self.segment_bucket_array_ref(index)
+                    .random_bucket(&guard, slot)
+            })?;
+
+        Some(unsafe { Ref::new(guard, bucket_ptr) })
+    }
+
+    /// Returns the states of scans over each of the segments, which can be
+    /// moved to separate threads or tasks and advanced with [`scan_segment`]
+    /// to scan the map in parallel.
+    ///
+    /// See [`SegmentScan`] for details.
+    ///
+    /// [`scan_segment`]: #method.scan_segment
+    /// [`SegmentScan`]: ./struct.SegmentScan.html
+    pub fn iter_segments_owned(&self) -> Vec<SegmentScan> {
+        (0..self.segments.len()).map(SegmentScan::new).collect()
+    }
+
+    /// Returns an iterator over the next entries of the scan at `cursor`, and
+    /// moves `cursor` past them.
+    ///
+    /// The segments are scanned one after the other. At least `limit` entries
+    /// are returned unless the scan completes, and possibly a few more, as the
+    /// entries are visited by the slots of their keys in the bucket pointer
+    /// arrays. The map may be modified and its segments resized between the
+    /// calls. See [`ScanCursor`] for which entries are returned.
+    ///
+    /// [`ScanCursor`]: ../../map/struct.ScanCursor.html
+    pub fn scan(&self, cursor: &mut ScanCursor, limit: usize) -> Iter<'_, K, V> {
+        let guard = crossbeam_epoch::pin();
+        let mut buckets = Vec::new();
+
+        if !cursor.is_done() {
+            loop {
+                let (segment_buckets, next) = self.segment_bucket_array_ref(cursor.segment()).scan(
+                    &guard,
+                    cursor.position(),
+                    limit.saturating_sub(buckets.len()),
+                );
+                buckets.extend(segment_buckets);
+                cursor.advance(next, self.segments.len());
+
+                if cursor.is_done() || buckets.len() >= limit {
+                    break;
+                }
+            }
+        }
+
+        let buckets = bucket::into_ptrs(buckets);
+
+        unsafe { Iter::new(guard, buckets) }
+    }
+
+    /// Invokes `f` with the next entries of the scan at `cursor`, and returns
+    /// the cursor moved past them.
+    ///
+    /// This visits the same entries as [`scan`], but no epoch guard outlives
+    /// the call, so a large map can be swept a batch at a time, for example a
+    /// few thousand entries per tick, by keeping only the returned cursor
+    /// between the calls.
+    ///
+    /// [`scan`]: #method.scan
+    pub fn scan_and<F: FnMut(&K, &V)>(
+        &self,
+        mut cursor: ScanCursor,
+        limit: usize,
+        mut f: F,
+    ) -> ScanCursor {
+        if cursor.is_done() {
+            return cursor;
+        }
+
+        let guard = &crossbeam_epoch::pin();
+        let mut visited = 0;
+
+        loop {
+            let (buckets, next) = self.segment_bucket_array_ref(cursor.segment()).scan(
+                guard,
+                cursor.position(),
+                limit.saturating_sub(visited),
+            );
+            visited += buckets.len();
+
+            for b in buckets {
+                f(&b.key, unsafe { b.value() });
+            }
+
+            cursor.advance(next, self.segments.len());
+
+            if cursor.is_done() || visited >= limit {
+                return cursor;
+            }
+        }
+    }
+
+    /// Returns a stream of clones of the key-value pairs of the map, which are
+    /// scanned in batches of about `batch_size` entries.
+    ///
+    /// Each batch is scanned like with [`scan_and`] when the stream runs out
+    /// of cloned entries, under an epoch guard that is dropped before the
+    /// entries are yielded, so the stream never holds a guard across an
+    /// `.await`, and a poll never visits more than one batch. The stream has
+    /// the guarantees of a scan with a [`ScanCursor`], even if the map is
+    /// modified or resized between the batches.
+    ///
+    /// This method is only available with the `futures` feature.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `batch_size` is 0.
+    ///
+    /// [`scan_and`]: #method.scan_and
+    /// [`ScanCursor`]: ../../map/struct.ScanCursor.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    /// use futures::{executor, StreamExt};
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(4);
+    ///
+    /// for i in 0..1000 {
+    ///     map.insert(i, i);
+    /// }
+    ///
+    /// let stream = map.entry_stream(64);
+    /// let sum = executor::block_on(stream.fold(0, |sum, (_, v)| async move { sum + v }));
+    ///
+    /// assert_eq!(sum, (0..1000).sum());
+    /// ```
+    #[cfg(feature = "futures")]
+    pub fn entry_stream(&self, batch_size: usize) -> impl futures_core::Stream<Item = (K, V)> + '_
+    where
+        K: Clone,
+        V: Clone,
+    {
+        crate::map::entry_stream::EntryStream::new(batch_size, move |cursor, limit, batch| {
+            self.scan_and(cursor, limit, |k, v| batch.push((k.clone(), v.clone())))
+        })
+    }
+
+    /// Splits the map into `n` disjoint [`Partition`]s, which can be scanned
+    /// independently with [`scan_partition`], for example on the threads of a
+    /// pool.
+    ///
+    /// The partitions split the scan positions of the keys of all segments,
+    /// one segment after the other, into ranges of equal size, so they hold
+    /// about the same number of entries. A partition covers whole segments if
+    /// `n` divides the number of segments, and a part of a segment if `n` is
+    /// a multiple of it. See [`Partition`] for details.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is 0.
+    ///
+    /// [`Partition`]: ../../map/struct.Partition.html
+    /// [`scan_partition`]: #method.scan_partition
+    pub fn partitions(&self, n: usize) -> Vec<Partition> {
+        Partition::split(n, self.segments.len())
+    }
+
+    /// Returns an iterator over the next entries of `partition`, and moves
+    /// `partition` past them.
+    ///
+    /// At least `limit` entries are returned unless the scan of the partition
+    /// completes, and possibly a few more, like [`scan`]. See [`Partition`]
+    /// for details.
+    ///
+    /// [`scan`]: #method.scan
+    /// [`Partition`]: ../../map/struct.Partition.html
+    pub fn scan_partition(&self, partition: &mut Partition, limit: usize) -> Iter<'_, K, V> {
+        let guard = crossbeam_epoch::pin();
+        let mut buckets = Vec::new();
+
+        while !partition.is_done() {
+            let (segment_buckets, next) = self.segment_bucket_array_ref(partition.segment()).scan(
+                &guard,
+                partition.position(),
+                limit.saturating_sub(buckets.len()),
+            );
+
+            match partition.cut(next) {
+                Some(end) => buckets.extend(segment_buckets.into_iter().filter(|b| {
+                    bucket::scan_position(bucket::hash(&self.build_hasher, &b.key)) < end
+                })),
+                None => buckets.extend(segment_buckets),
+            }
+
+            partition.advance(next);
+
+            if buckets.len() >= limit {
+                break;
+            }
+        }
+
+        let buckets = bucket::into_ptrs(buckets);
+
+        unsafe { Iter::new(guard, buckets) }
+    }
+
+    /// Returns an iterator over at most `limit` entries of the map,
+    /// approximately least recently used first.
+    ///
+    /// An entry is used when its key is inserted, its value is modified, or it
+    /// is found by a lookup. Uses are recorded as ticks of a coarse access
+    /// clock that every call to this method advances, so entries used between
+    /// the same two calls are equally stale and are yielded in arbitrary order.
+    /// Embedders can implement eviction by calling this method periodically and
+    /// removing the yielded entries until they are under budget, without
+    /// maintaining a separate LRU list.
+    ///
+    /// This scans the whole map and sorts the stalest entries.
+    ///
+    /// This method is only available with the `access-times` feature.
+    #[cfg(feature = "access-times")]
+    pub fn scan_by_staleness(&self, limit: usize) -> Iter<'_, K, V> {
+        let guard = crossbeam_epoch::pin();
+        self.access_clock.fetch_add(1, ordering::RELAXED);
+
+        let mut buckets = Vec::new();
+
+        for index in 0..self.segments.len() {
+            buckets.extend(self.segment_bucket_array_ref(index).live_buckets(&guard));
+        }
+
+        let buckets = bucket::into_ptrs(bucket::stalest(buckets, limit));
+
+        unsafe { Iter::new(guard, buckets) }
+    }
+
+    /// Returns a point-in-time copy of the entries of the map, along with their
+    /// hashes.
+    ///
+    /// See the [`snapshot`] module for details.
+    ///
+    /// [`snapshot`]: ../../snapshot/index.html
+    pub fn snapshot(&self) -> Snapshot<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let guard = &crossbeam_epoch::pin();
+        let mut entries = Vec::new();
+
+        for index in 0..self.segments.len() {
+            entries.extend(
+                self.segment_bucket_array_ref(index)
+                    .live_buckets(guard)
+                    .into_iter()
+                    .map(|b| {
+                        (bucket::hash(&self.build_hasher, &b.key), &b.key, unsafe {
+                            b.value()
+                        })
+                    }),
+            );
+        }
+
+        Snapshot::new(
+            entries
+                .into_iter()
+                .map(|(h, k, v)| (h, k.clone(), v.clone()))
+                .collect(),
+        )
+    }
+
+    /// Returns the keys that were added, removed, or whose values changed since
+    /// `snapshot` was taken.
+    ///
+    /// The values of the map are compared in place without being cloned.
+    /// `snapshot` must have been taken from this map. See the [`snapshot`]
+    /// module for details.
+    ///
+    /// [`snapshot`]: ../../snapshot/index.html
+    pub fn changes_since(&self, snapshot: &Snapshot<K, V>) -> SnapshotDiff<K>
+    where
+        K: Clone,
+        V: PartialEq,
+    {
+        let guard = &crossbeam_epoch::pin();
+        let mut entries = Vec::new();
+
+        for index in 0..self.segments.len() {
+            entries.extend(
+                self.segment_bucket_array_ref(index)
+                    .live_buckets(guard)
+                    .into_iter()
+                    .map(|b| {
+                        (bucket::hash(&self.build_hasher, &b.key), &b.key, unsafe {
+                            b.value()
+                        })
+                    }),
+            );
+        }
+
+        entries.sort_unstable_by_key(|&(hash, _, _)| hash);
+
+        SnapshotDiff::between(&snapshot.sorted_entries(), &entries)
+    }
+
+    /// Returns clones of the key-value pairs of the map, in arbitrary order.
+    ///
+    /// The entries are cloned under a single epoch guard. The result is weakly
+    /// consistent: entries that are inserted, modified, or removed
+    /// concurrently may or may not be reflected in it. Unlike [`snapshot`],
+    /// the hashes of the keys are not computed, so this is the cheapest way to
+    /// dump the map, for example for debugging.
+    ///
+    /// [`snapshot`]: #method.snapshot
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(4);
+    /// map.insert("a", 1);
+    /// map.insert("b", 2);
+    ///
+    /// let mut entries = map.entries_snapshot();
+    /// entries.sort_unstable();
+    ///
+    /// assert_eq!(entries, [("a", 1), ("b", 2)]);
+    /// ```
+    pub fn entries_snapshot(&self) -> Vec<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let guard = &crossbeam_epoch::pin();
+
+        (0..self.segments.len())
+            .flat_map(|index| self.segment_bucket_array_ref(index).live_buckets(guard))
+            .map(|b| (b.key.clone(), unsafe { b.value() }.clone()))
+            .collect()
+    }
+
+    /// Returns clones of the keys of the map, in arbitrary order.
+    ///
+    /// See [`entries_snapshot`] for details.
+    ///
+    /// [`entries_snapshot`]: #method.entries_snapshot
+    pub fn keys_snapshot(&self) -> Vec<K>
+    where
+        K: Clone,
+    {
+        let guard = &crossbeam_epoch::pin();
+
+        (0..self.segments.len())
+            .flat_map(|index| self.segment_bucket_array_ref(index).live_buckets(guard))
+            .map(|b| b.key.clone())
+            .collect()
+    }
+
+    /// Returns clones of the values of the map, in arbitrary order.
+    ///
+    /// See [`entries_snapshot`] for details.
+    ///
+    /// [`entries_snapshot`]: #method.entries_snapshot
+    pub fn values_snapshot(&self) -> Vec<V>
+    where
+        V: Clone,
+    {
+        let guard = &crossbeam_epoch::pin();
+
+        (0..self.segments.len())
+            .flat_map(|index| self.segment_bucket_array_ref(index).live_buckets(guard))
+            .map(|b| unsafe { b.value() }.clone())
+            .collect()
+    }
+
+    /// Returns a view of the entries of the map, which can be read and
+    /// iterated repeatedly while the map is modified.
+    ///
+    /// The view keeps the current thread pinned until it is dropped. See
+    /// [`SnapshotView`] for details.
+    ///
+    /// [`SnapshotView`]: ../../map/struct.SnapshotView.html
+    pub fn snapshot_view(&self) -> SnapshotView<'_, K, V, S> {
+        let guard = crossbeam_epoch::pin();
+        let buckets = (0..self.segments.len())
+            .flat_map(|index| self.segment_bucket_array_ref(index).live_buckets(&guard))
+            .map(|b| (bucket::hash(&self.build_hasher, &b.key), b as *const _))
+            .collect();
+
+        unsafe { SnapshotView::new(guard, buckets, &self.build_hasher) }
+    }
+
+    /// Inserts a key-value pair into the map, returning a clone of the value
+    /// previously corresponding to the key.
+    ///
+    /// If the map did have this key present, both the key and value are
+    /// updated.
+    ///
+    /// The new value is always stored in a new bucket, and the previous one
+    /// is retired to the garbage collector, even if the values have the same
+    /// layout: other threads may still hold references to the previous value,
+    /// through a [`Ref`], an iterator, or a [`SnapshotView`], so it must not
+    /// be overwritten in place. [`insert_mut`] overwrites it in place, as it
+    /// borrows the map exclusively.
+    ///
+    /// [`Ref`]: ../../map/struct.Ref.html
+    /// [`SnapshotView`]: ../../map/struct.SnapshotView.html
+    /// [`insert_mut`]: #method.insert_mut
+    #[inline]
+    pub fn insert(&self, key: K, value: V) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.insert_entry_and(key, value, |_, v| v.clone())
+    }
+
+    /// Inserts a key-value pair into the map, returning a clone of the
+    /// key-value pair previously corresponding to the supplied key.
+    ///
+    /// If the map did have this key present, both the key and value are
+    /// updated.
+    #[inline]
+    pub fn insert_entry(&self, key: K, value: V) -> Option<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.insert_entry_and(key, value, |k, v| (k.clone(), v.clone()))
+    }
+
+    /// Inserts a key-value pair into the map, returning the result of invoking
+    /// a function with a reference to the value previously corresponding to the
+    /// key.
+    ///
+    /// If the map did have this key present, both the key and value are
+    /// updated.
+    #[inline]
+    pub fn insert_and<F: FnOnce(&V) -> T, T>(
+        &self,
+        key: K,
+        value: V,
+        with_previous_value: F,
+    ) -> Option<T> {
+        self.insert_entry_and(key, value, move |_, v| with_previous_value(v))
+    }
+
+    /// Inserts a key-value pair into the map, returning the result of invoking
+    /// a function with a reference to the key-value pair previously
+    /// corresponding to the supplied key.
+    ///
+    /// If the map did have this key present, both the key and value are
+    /// updated.
+    #[inline]
+    pub fn insert_entry_and<F: FnOnce(&K, &V) -> T, T>(
+        &self,
+        key: K,
+        value: V,
+        with_previous_entry: F,
+    ) -> Option<T> {
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        let result =
+            self.bucket_array_ref(hash)
+                .insert_entry_and(key, hash, value, with_previous_entry);
+
+        if result.is_none() {
+            self.len.fetch_add(1, ordering::RELAXED);
+        }
+
+        result
+    }
+
+    /// Inserts a key-value pair into the map like [`insert`], unless the
+    /// weight of the pair does not fit in the memory budget of the map.
+    ///
+    /// A map without a memory budget always inserts the pair. See
+    /// [`MemoryBudget`] for details.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MemoryBudgetExceeded`] if charging the weight of the
+    /// pair would exceed the limit of the memory budget of the map, in which
+    /// case the key and the value are dropped and the map is left unchanged.
+    ///
+    /// [`insert`]: #method.insert
+    /// [`MemoryBudget`]: ../../map/struct.MemoryBudget.html
+    /// [`Error::MemoryBudgetExceeded`]: ../../enum.Error.html#variant.MemoryBudgetExceeded
+    pub fn try_insert(&self, key: K, value: V) -> Result<Option<V>, Error>
+    where
+        V: Clone,
+    {
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        let result =
+            self.bucket_array_ref(hash)
+                .try_insert_entry_and(key, hash, value, |_, v| v.clone())?;
+
+        if result.is_none() {
+            self.len.fetch_add(1, ordering::RELAXED);
+        }
+
+        Ok(result)
+    }
+
+    /// Inserts a key-value pair into the map, returning a clone of the value
+    /// previously corresponding to the key.
+    ///
+    /// This is equivalent to [`insert`], except that the key is written
+    /// directly into the slot described by `hint` if it is still valid. A hint
+    /// that was obtained for another key or that is no longer valid is ignored.
+    ///
+    /// [`insert`]: #method.insert
+    pub fn insert_with_hint(&self, key: K, value: V, hint: SlotHint) -> Option<V>
+    where
+        V: Clone,
+    {
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        let result = self.bucket_array_ref(hash).insert_entry_with_hint_and(
+            key,
+            hash,
+            value,
+            Some(hint),
+            |_, v| v.clone(),
+        );
+
+        if result.is_none() {
+            self.len.fetch_add(1, ordering::RELAXED);
+        }
+
+        result
+    }
+
+    /// Inserts a key-value pair into the map if `condition` returns `true` for
+    /// the current [`MapState`], returning a clone of the value previously
+    /// corresponding to the key.
+    ///
+    /// A slot is reserved in the lengths of the map and of the segment of the
+    /// key before `condition` is called, and released afterwards, so that
+    /// concurrent calls see distinct lengths and cannot all admit a key past a
+    /// bound on either length. While the reservation is held, [`len`] may be
+    /// overestimated.
+    ///
+    /// Returns the key-value pair back if `condition` returned `false`.
+    ///
+    /// [`MapState`]: ../../map/struct.MapState.html
+    /// [`len`]: #method.len
+    pub fn insert_if<F: FnOnce(&MapState) -> bool>(
+        &self,
+        key: K,
+        value: V,
+        condition: F,
+    ) -> Result<Option<V>, (K, V)>
+    where
+        V: Clone,
+    {
+        let hash = bucket::hash(&self.build_hasher, &key);
+        let index = self.segment_index_from_hash(hash);
+        let _panic_guard = self.panic_policy.enter();
+        let (_map_reservation, map_len) = Reservation::acquire(&self.len);
+        let (_segment_reservation, segment_len) = Reservation::acquire(&self.segments[index].len);
+        let state = MapState {
+            map_len,
+            segment_len,
+            segment_capacity: self.segment_capacity(index),
+        };
+
+        if condition(&state) {
+            let result =
+                self.segment_bucket_array_ref(index)
+                    .insert_entry_and(key, hash, value, |_, v| v.clone());
+
+            if result.is_none() {
+                self.len.fetch_add(1, ordering::RELAXED);
+            }
+
+            Ok(result)
+        } else {
+            Err((key, value))
+        }
+    }
+
+    /// Removes a key from the map, returning a clone of the value previously
+    /// corresponding to the key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[inline]
+    pub fn remove<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        V: Clone,
+    {
+        self.remove_entry_if_and(key, |_, _| true, |_, v| v.clone())
+    }
+
+    /// Removes a key from the map, returning a clone of the key-value pair
+    /// previously corresponding to the key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[inline]
+    pub fn remove_entry<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q> + Clone,
+        V: Clone,
+    {
+        self.remove_entry_if_and(key, |_, _| true, |k, v| (k.clone(), v.clone()))
+    }
+
+    /// Remove a key from the map, returning the result of invoking a function
+    /// with a reference to the value previously corresponding to the key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[inline]
+    pub fn remove_and<Q: Hash + Eq + ?Sized, F: FnOnce(&V) -> T, T>(
+        &self,
+        key: &Q,
+        with_previous_value: F,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+    {
+        self.remove_entry_if_and(key, |_, _| true, move |_, v| with_previous_value(v))
+    }
+
+    /// Removes a key from the map, returning the result of invoking a function
+    /// with a reference to the key-value pair previously corresponding to the
+    /// key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[inline]
+    pub fn remove_entry_and<Q: Hash + Eq + ?Sized, F: FnOnce(&K, &V) -> T, T>(
+        &self,
+        key: &Q,
+        with_previous_entry: F,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+    {
+        self.remove_entry_if_and(key, |_, _| true, with_previous_entry)
+    }
+
+    /// Removes a key from the map, returning the result of invoking a function
+    /// with a reference to the key-value pair previously corresponding to the
+    /// key, along with a hint of the slot it occupied.
+    ///
+    /// Passing the hint to [`insert_with_hint`] reinserts the key into the same
+    /// slot without probing, if the slot is still valid. This makes refreshing
+    /// an entry by removing and reinserting it cheaper.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`insert_with_hint`]: #method.insert_with_hint
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn remove_entry_with_hint_and<Q: Hash + Eq + ?Sized, F: FnOnce(&K, &V) -> T, T>(
+        &self,
+        key: &Q,
+        with_previous_entry: F,
+    ) -> Option<(T, SlotHint)>
+    where
+        K: Borrow<Q>,
+    {
+        let hash = bucket::hash(&self.build_hasher, key);
+
+        self.bucket_array_ref(hash).remove_entry_if_with_hint_and(
+            key,
+            hash,
+            |_, _| true,
+            move |k, v| {
+                self.len.fetch_sub(1, ordering::RELAXED);
+
+                with_previous_entry(k, v)
+            },
+        )
+    }
+
+    /// Removes a key from the map if a condition is met, returning a clone of
+    /// the value previously corresponding to the key.
+    ///
+    /// `condition` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    pub fn remove_if<Q: Hash + Eq + ?Sized, F: FnMut(&K, &V) -> bool>(
+        &self,
+        key: &Q,
+        condition: F,
+    ) -> Option<V>
+    where
+        K: Borrow<Q>,
+        V: Clone,
+    {
+        self.remove_entry_if_and(key, condition, move |_, v| v.clone())
+    }
+
+    /// Removes a key from the map if a condition is met, returning a clone of
+    /// the value previously corresponding to the key, or why it was not
+    /// removed.
+    ///
+    /// This is [`remove_if`], except that a failed removal tells apart a key
+    /// that was absent, an entry that `condition` rejected, and an entry that
+    /// `condition` accepted but that was removed concurrently before this
+    /// removal could be applied. If the entry is replaced concurrently,
+    /// `condition` is invoked again with the new value, and the removal fails
+    /// only if it rejects it.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`NotRemoved`] with the reason why the entry was not
+    /// removed, in which case the map is left unchanged.
+    ///
+    /// [`remove_if`]: #method.remove_if
+    /// [`NotRemoved`]: ../../map/struct.NotRemoved.html
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::{map::NotRemovedReason, SegmentedHashMap};
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(4);
+    /// map.insert("pinned", true);
+    /// map.insert("stale", false);
+    ///
+    /// let evict = |_: &&str, &pinned: &bool| !pinned;
+    ///
+    /// assert_eq!(map.try_remove_if("stale", evict), Ok(false));
+    /// assert_eq!(
+    ///     map.try_remove_if("stale", evict).unwrap_err().reason(),
+    ///     NotRemovedReason::Absent
+    /// );
+    /// assert_eq!(
+    ///     map.try_remove_if("pinned", evict).unwrap_err().reason(),
+    ///     NotRemovedReason::ConditionFalse
+    /// );
+    /// ```
+    pub fn try_remove_if<Q: Hash + Eq + ?Sized, F: FnMut(&K, &V) -> bool>(
+        &self,
+        key: &Q,
+        mut condition: F,
+    ) -> Result<V, NotRemoved>
+    where
+        K: Borrow<Q>,
+        V: Clone,
+    {
+        let mut last_decision = None;
+
+        self.remove_entry_if_and(
+            key,
+            |k, v| {
+                let decision = condition(k, v);
+                last_decision = Some(decision);
+
+                decision
+            },
+            |_, v| v.clone(),
+        )
+        .ok_or_else(|| NotRemoved::from_last_decision(last_decision))
+    }
+
+    /// Removes a key from the map if a condition is met, returning a clone of
+    /// the key-value pair previously corresponding to the key.
+    ///
+    /// `condition` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    #[inline]
+    pub fn remove_entry_if<Q: Hash + Eq + ?Sized, F: FnMut(&K, &V) -> bool>(
+        &self,
+        key: &Q,
+        condition: F,
+    ) -> Option<(K, V)>
+    where
+        K: Clone + Borrow<Q>,
+        V: Clone,
+    {
+        self.remove_entry_if_and(key, condition, move |k, v| (k.clone(), v.clone()))
+    }
+
+    /// Remove a key from the map if a condition is met, returning the result of
+    /// invoking a function with a reference to the value previously
+    /// corresponding to the key.
+    ///
+    /// `condition` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    #[inline]
+    pub fn remove_if_and<Q: Hash + Eq + ?Sized, F: FnMut(&K, &V) -> bool, G: FnOnce(&V) -> T, T>(
+        &self,
+        key: &Q,
+        condition: F,
+        with_previous_value: G,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+    {
+        self.remove_entry_if_and(key, condition, move |_, v| with_previous_value(v))
+    }
+
+    /// Removes a key from the map if a condition is met, evaluating the
+    /// condition only once against a clone of the value, and returning the
+    /// result of invoking a function with a reference to the value previously
+    /// corresponding to the key.
+    ///
+    /// Unlike [`remove_if_and`], `condition` is invoked at most once, on a
+    /// clone of the value taken before the removal is attempted, and while the
+    /// current thread is not pinned, so it may be expensive or non-reentrant.
+    /// The semantics are weaker: the key is only removed if its value is still
+    /// equal to the clone when the removal is attempted. If the value has been
+    /// modified in the meantime, nothing is removed and [`None`] is returned,
+    /// without invoking `condition` again. This is
+    /// [`remove_if_unchanged_and`] applied to the result of a lookup.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`remove_if_and`]: #method.remove_if_and
+    /// [`remove_if_unchanged_and`]: #method.remove_if_unchanged_and
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    pub fn remove_if_cloned_and<
+        Q: Hash + Eq + ?Sized,
+        F: FnOnce(&V) -> bool,
+        G: FnOnce(&V) -> T,
+        T,
+    >(
+        &self,
+        key: &Q,
+        condition: F,
+        with_previous_value: G,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+        V: Clone + PartialEq,
+    {
+        let value = self.get(key)?;
+
+        if condition(&value) {
+            self.remove_if_unchanged_and(key, &value, with_previous_value)
+        } else {
+            None
+        }
+    }
+
+    /// Removes a key from the map if its value is equal to `expected`,
+    /// returning the result of invoking a function with a reference to the
+    /// value previously corresponding to the key.
+    ///
+    /// This completes a removal whose condition was evaluated on a clone of the
+    /// value obtained earlier, for example by [`get`], possibly across an
+    /// `.await` point of an asynchronous predicate. The removal only takes
+    /// place if the value has not been modified since, as far as [`PartialEq`]
+    /// can tell.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`get`]: #method.get
+    /// [`PartialEq`]: https://doc.rust-lang.org/std/cmp/trait.PartialEq.html
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[inline]
+    pub fn remove_if_unchanged_and<Q: Hash + Eq + ?Sized, G: FnOnce(&V) -> T, T>(
+        &self,
+        key: &Q,
+        expected: &V,
+        with_previous_value: G,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+        V: PartialEq,
+    {
+        self.remove_if_and(key, |_, v| v == expected, with_previous_value)
+    }
+
+    /// Removes every entry for which `predicate` returns `false`, returning
+    /// the number of entries removed.
+    ///
+    /// The segments are visited one after the other, each under its own epoch
+    /// guard, and the entries of a segment in the order of their slots. Each
+    /// entry is removed with the same conditional removal as [`remove_if`], so
+    /// `predicate` is evaluated against the value the removal takes effect on.
+    /// It is invoked at least once for every entry that is in the map for the
+    /// whole call, and may be invoked again for an entry that is concurrently
+    /// modified. Like a sequence of calls to [`remove_if`], this is not
+    /// atomic: entries that are inserted concurrently may or may not be
+    /// visited.
+    ///
+    /// The removed entries are destroyed once the garbage of the current
+    /// thread is flushed. A map built with the `flush_after_bulk_removals`
+    /// option of its builder flushes it after each segment that it removes entries from.
+    ///
+    /// [`remove_if`]: #method.remove_if
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// let sessions = SegmentedHashMap::with_num_segments(4);
+    ///
+    /// for id in 0..10 {
+    ///     sessions.insert(id, id * 100);
+    /// }
+    ///
+    /// // drop the sessions that were last seen before 500
+    /// assert_eq!(sessions.retain(|_, &last_seen| last_seen >= 500), 5);
+    /// assert_eq!(sessions.len(), 5);
+    /// assert_eq!(sessions.get(&7), Some(700));
+    /// ```
+    pub fn retain<F: FnMut(&K, &V) -> bool>(&self, mut predicate: F) -> usize {
+        self.extract_if_and(|k, v| !predicate(k, v), |_, _| ())
+    }
+
+    /// Removes every entry, invoking `with_entry` with each removed key-value
+    /// pair, and returns the number of entries removed.
+    ///
+    /// The slots of the bucket pointer array of each segment are visited in order, and each
+    /// live bucket is turned into a tombstone with a single atomic operation,
+    /// without hashing its key or probing for it as [`remove`] would. Each
+    /// entry is handed to exactly one caller: an entry removed or replaced
+    /// concurrently is either drained or left to the operation that won the
+    /// race. Like a sequence of calls to [`remove`], this is not atomic:
+    /// entries that are inserted concurrently may or may not be drained. See
+    /// [`retain`] for when the drained entries are destroyed.
+    ///
+    /// The references passed to `with_entry` are only valid for the duration
+    /// of the call.
+    ///
+    /// [`remove`]: #method.remove
+    /// [`retain`]: #method.retain
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// let dirty = SegmentedHashMap::with_num_segments(4);
+    /// dirty.insert("a", 1);
+    /// dirty.insert("b", 2);
+    ///
+    /// let mut flushed = Vec::new();
+    /// assert_eq!(dirty.drain_and(|k, v| flushed.push((*k, *v))), 2);
+    ///
+    /// flushed.sort_unstable();
+    /// assert_eq!(flushed, [("a", 1), ("b", 2)]);
+    /// assert!(dirty.is_empty());
+    /// ```
+    pub fn drain_and<F: FnMut(&K, &V)>(&self, mut with_entry: F) -> usize {
+        let mut num_removed = 0;
+
+        for index in 0..self.segments.len() {
+            num_removed += self.drain_segment_and(index, &mut with_entry);
+        }
+
+        num_removed
+    }
+
+    /// Removes every entry, returning clones of the removed key-value pairs in
+    /// arbitrary order.
+    ///
+    /// See [`drain_and`] for details.
+    ///
+    /// [`drain_and`]: #method.drain_and
+    pub fn drain(&self) -> Vec<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let mut entries = Vec::with_capacity(self.len());
+        self.drain_and(|k, v| entries.push((k.clone(), v.clone())));
+
+        entries
+    }
+
+    /// Removes every entry, invoking `with_entry` with each removed key-value
+    /// pair on the threads of the rayon pool, and returns the number of
+    /// entries removed.
+    ///
+    /// Each segment is drained like in [`drain_and`] by a task of the pool, so
+    /// the segments are drained and their entries processed concurrently. The
+    /// garbage of the removed entries is left to the threads of the pool, which
+    /// flush it before moving on to another segment if the map was built with
+    /// the `flush_after_bulk_removals` option.
+    ///
+    /// This method is only available with the `rayon` feature.
+    ///
+    /// [`drain_and`]: #method.drain_and
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// use std::sync::atomic::{AtomicU64, Ordering};
+    ///
+    /// let buffer = SegmentedHashMap::with_num_segments(8);
+    ///
+    /// for i in 0..100_000u64 {
+    ///     buffer.insert(i, i);
+    /// }
+    ///
+    /// let written = AtomicU64::new(0);
+    /// let num_drained = buffer.par_drain_and(|_, v| {
+    ///     written.fetch_add(*v, Ordering::Relaxed);
+    /// });
+    ///
+    /// assert_eq!(num_drained, 100_000);
+    /// assert_eq!(written.into_inner(), (0..100_000).sum());
+    /// assert!(buffer.is_empty());
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn par_drain_and<F: Fn(&K, &V) + Sync>(&self, with_entry: F) -> usize
+    where
+        K: Send + Sync,
+        V: Send + Sync,
+        S: Sync,
+    {
+        use rayon::prelude::*;
+
+        (0..self.segments.len())
+            .into_par_iter()
+            .map(|index| self.drain_segment_and(index, &with_entry))
+            .sum()
+    }
+
+    /// Removes every entry, returning clones of the removed key-value pairs in
+    /// arbitrary order, which are cloned on the threads of the rayon pool.
+    ///
+    /// See [`par_drain_and`] for details.
+    ///
+    /// This method is only available with the `rayon` feature.
+    ///
+    /// [`par_drain_and`]: #method.par_drain_and
+    #[cfg(feature = "rayon")]
+    pub fn par_drain(&self) -> Vec<(K, V)>
+    where
+        K: Clone + Send + Sync,
+        V: Clone + Send + Sync,
+        S: Sync,
+    {
+        use rayon::prelude::*;
+
+        (0..self.segments.len())
+            .into_par_iter()
+            .flat_map_iter(|index| {
+                let mut entries = Vec::new();
+                self.drain_segment_and(index, |k, v| entries.push((k.clone(), v.clone())));
+
+                entries
+            })
+            .collect()
+    }
+
+    fn drain_segment_and<F: FnMut(&K, &V)>(&self, index: usize, with_entry: F) -> usize {
+        let num_removed = self.segment_bucket_array_ref(index).drain_and(with_entry);
+
+        self.len.fetch_sub(num_removed, ordering::RELAXED);
+        self.reclaim_bulk_removals(num_removed);
+
+        num_removed
+    }
+
+    /// Removes every entry for which `predicate` returns `true`, invoking
+    /// `with_entry` with each removed key-value pair, and returns the number of
+    /// entries removed.
+    ///
+    /// This is [`retain`] with the opposite predicate, except that the removed
+    /// entries are handed to `with_entry`, which is invoked at most once for
+    /// each of them with the value that `predicate` last accepted. See
+    /// [`retain`] for how concurrent modifications are handled.
+    ///
+    /// The references passed to `with_entry` are only valid for the duration
+    /// of the call.
+    ///
+    /// [`retain`]: #method.retain
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// let cache = SegmentedHashMap::with_num_segments(4);
+    ///
+    /// for id in 0..10 {
+    ///     cache.insert(id, id * 100);
+    /// }
+    ///
+    /// // expire the entries that were last seen before 300
+    /// let mut expired = Vec::new();
+    /// let num_expired = cache.extract_if_and(
+    ///     |_, &last_seen| last_seen < 300,
+    ///     |&id, _| expired.push(id),
+    /// );
+    ///
+    /// expired.sort_unstable();
+    /// assert_eq!(num_expired, 3);
+    /// assert_eq!(expired, [0, 1, 2]);
+    /// assert_eq!(cache.len(), 7);
+    /// ```
+    pub fn extract_if_and<F: FnMut(&K, &V) -> bool, G: FnMut(&K, &V)>(
+        &self,
+        mut predicate: F,
+        mut with_entry: G,
+    ) -> usize {
+        let mut removed = 0;
+
+        for index in 0..self.segments.len() {
+            let bucket_array_ref = self.segment_bucket_array_ref(index);
+            let mut removed_from_segment = 0;
+
+            {
+                let guard = &crossbeam_epoch::pin();
+
+                for b in bucket_array_ref.live_buckets(guard) {
+                    let hash = bucket::hash(&self.build_hasher, &b.key);
+
+                    if bucket_array_ref
+                        .remove_entry_if_and(&b.key, hash, &mut predicate, &mut with_entry)
+                        .is_some()
+                    {
+                        removed_from_segment += 1;
+                    }
+                }
+            }
+
+            self.len.fetch_sub(removed_from_segment, ordering::RELAXED);
+            self.reclaim_bulk_removals(removed_from_segment);
+            removed += removed_from_segment;
+        }
+
+        removed
+    }
+
+    /// Removes every entry for which `predicate` returns `true`, returning
+    /// clones of the removed key-value pairs in arbitrary order.
+    ///
+    /// See [`extract_if_and`] for details.
+    ///
+    /// [`extract_if_and`]: #method.extract_if_and
+    pub fn extract_if<F: FnMut(&K, &V) -> bool>(&self, predicate: F) -> Vec<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let mut entries = Vec::new();
+        self.extract_if_and(predicate, |k, v| entries.push((k.clone(), v.clone())));
+
+        entries
+    }
+
+    /// Moves the entry of a key to `destination`, replacing the value of the
+    /// key there, if any, and returns whether the key was in this map.
+    ///
+    /// The entry is inserted into `destination` before it is removed from this
+    /// map, so there is no moment at which it is in neither map. It is in
+    /// both between the insertion and the removal, a window that only spans
+    /// the two operations of the calling thread and never waits for other
+    /// threads. The removal only takes effect if the entry was not modified
+    /// since it was copied:
+    ///
+    /// - If the entry is concurrently modified, the move is retried with the
+    ///   new value, so `destination` ends up with the value the entry had when
+    ///   it was removed.
+    /// - If the entry is concurrently removed, the move is abandoned, and the
+    ///   copy inserted into `destination` is removed again unless it was
+    ///   replaced since. The value `destination` held for the key before the
+    ///   move is not restored.
+    ///
+    /// The key and the value are cloned into `destination`, which may use
+    /// another hasher than this map.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// let hot = SegmentedHashMap::with_num_segments(4);
+    /// let cold = SegmentedHashMap::with_num_segments(4);
+    /// cold.insert("foo", 1);
+    ///
+    /// // promote the entry to the hot tier
+    /// assert!(cold.move_entry("foo", &hot));
+    /// assert_eq!(hot.get("foo"), Some(1));
+    /// assert_eq!(cold.get("foo"), None);
+    ///
+    /// assert!(!cold.move_entry("foo", &hot));
+    /// ```
+    pub fn move_entry<Q: Hash + Eq + ?Sized, S2: BuildHasher>(
+        &self,
+        key: &Q,
+        destination: &HashMap<K, V, S2>,
+    ) -> bool
+    where
+        K: Borrow<Q> + Clone,
+        V: Clone,
+    {
+        let _panic_guard = self.panic_policy.enter();
+        let guard = &crossbeam_epoch::pin();
+        let hash = bucket::hash(&self.build_hasher, key);
+        let source_ref = self.bucket_array_ref(hash);
+        // the bucket last inserted into the destination and the hash of its key
+        let mut copy: Option<(&Bucket<K, V>, u64)> = None;
+
+        loop {
+            let bucket = match source_ref.get_bucket(guard, key, hash) {
+                Some(b) => b,
+                None => {
+                    if let Some((copy, copy_hash)) = copy {
+                        let copy_value = unsafe { copy.value() };
+
+                        if destination
+                            .bucket_array_ref(copy_hash)
+                            .remove_entry_if_and::<K, _, _, _>(
+                                &copy.key,
+                                copy_hash,
+                                |_, v| ptr::eq(v, copy_value),
+                                |_, _| (),
+                            )
+                            .is_some()
+                        {
+                            destination.len.fetch_sub(1, ordering::RELAXED);
+                        }
+                    }
+
+                    return false;
+                }
+            };
+
+            let value = unsafe { bucket.value() };
+            let destination_hash = bucket::hash(&destination.build_hasher, &bucket.key);
+            let (previous, current) = destination
+                .bucket_array_ref(destination_hash)
+                .insert_with_or_modify(
+                    guard,
+                    bucket.key.clone(),
+                    destination_hash,
+                    |_| value.clone(),
+                    |_, _| value.clone(),
+                );
+
+            if previous.is_none() {
+                destination.len.fetch_add(1, ordering::RELAXED);
+            }
+
+            // buckets are replaced rather than mutated, so the value is at the
+            // same address as long as the entry was not modified
+            if source_ref
+                .remove_entry_if_and(key, hash, |_, v| ptr::eq(v, value), |_, _| ())
+                .is_some()
+            {
+                self.len.fetch_sub(1, ordering::RELAXED);
+
+                return true;
+            }
+
+            copy = Some((current, destination_hash));
+        }
+    }
+
+    /// Removes a key from the map if a condition is met, returning the result
+    /// of invoking a function with a reference to the key-value pair previously
+    /// corresponding to the key.
+    ///
+    /// `condition` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    #[inline]
+    pub fn remove_entry_if_and<
+        Q: Hash + Eq + ?Sized,
+        F: FnMut(&K, &V) -> bool,
+        G: FnOnce(&K, &V) -> T,
+        T,
+    >(
+        &self,
+        key: &Q,
+        condition: F,
+        with_previous_entry: G,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+    {
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        self.bucket_array_ref(hash)
+            .remove_entry_if_and(key, hash, condition, move |k, v| {
+                self.len.fetch_sub(1, ordering::RELAXED);
+
+                with_previous_entry(k, v)
+            })
+    }
+
+    /// Removes a key from the map, but keeps its entry until the next call to
+    /// [`purge`] so that [`undelete`] can restore it. Returns `true` if the key
+    /// was in the map.
+    ///
+    /// The entry is hidden from all reads, and counts neither in [`len`] nor
+    /// against the memory budget of the map, as if it had been removed; its
+    /// key and value are kept without being cloned. Soft-removing the key
+    /// again after it was inserted again replaces the entry that was kept.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// This method is only available with the `entry-pins` feature.
+    ///
+    /// [`purge`]: #method.purge
+    /// [`undelete`]: #method.undelete
+    /// [`len`]: #method.len
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[cfg(feature = "entry-pins")]
+    pub fn soft_remove<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        let hash = bucket::hash(&self.build_hasher, key);
+        let bucket_array_ref = self.bucket_array_ref(hash);
+        let guard = &crossbeam_epoch::pin();
+
+        unsafe {
+            self.soft_removals.soft_remove(
+                hash,
+                || bucket_array_ref.get_bucket(guard, key, hash),
+                |pinned| {
+                    bucket_array_ref
+                        .remove_entry_if_and(
+                            key,
+                            hash,
+                            |k, _| ptr::eq(k, pinned),
+                            |_, _| self.len.fetch_sub(1, ordering::RELAXED),
+                        )
+                        .is_some()
+                },
+            )
+        }
+    }
+
+    /// Restores the entry of a key that was soft-removed by [`soft_remove`]
+    /// and not purged since, returning `true` if it was restored.
+    ///
+    /// The entry is not restored if the key was inserted again since it was
+    /// soft-removed, in which case the entry is dropped. Its key and value are
+    /// cloned back into the map.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// This method is only available with the `entry-pins` feature.
+    ///
+    /// [`soft_remove`]: #method.soft_remove
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[cfg(feature = "entry-pins")]
+    pub fn undelete<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q> + Clone,
+        V: Clone,
+    {
+        let hash = bucket::hash(&self.build_hasher, key);
+
+        let pinned = match self.soft_removals.take(hash, key) {
+            Some(pinned) => pinned,
+            None => return false,
+        };
+
+        let guard = &crossbeam_epoch::pin();
+
+        let (_, inserted) = self.bucket_array_ref(hash).get_or_insert_with(
+            guard,
+            pinned.key().clone(),
+            hash,
+            || pinned.value().clone(),
+        );
+
+        if inserted {
+            self.len.fetch_add(1, ordering::RELAXED);
+        }
+
+        inserted
+    }
+
+    /// Drops the entries that were soft-removed by [`soft_remove`] and not
+    /// restored since, returning their number.
+    ///
+    /// A value that a concurrent reader may still be reading is dropped once
+    /// the reader is done with it.
+    ///
+    /// This method is only available with the `entry-pins` feature.
+    ///
+    /// [`soft_remove`]: #method.soft_remove
+    #[cfg(feature = "entry-pins")]
+    pub fn purge(&self) -> usize {
+        self.soft_removals.purge()
+    }
+
+    /// Inserts a key-value pair into the map, returning a clone of the value
+    /// previously corresponding to the key and the sequence number assigned
+    /// to the insertion.
+    ///
+    /// A map built with [`Builder::sequence_numbers`] assigns increasing
+    /// sequence numbers, starting from 1, to the insertions, modifications and
+    /// removals of each segment. The numbers of the mutations of a key increase
+    /// in the order in which the mutations take effect, so that consumers such
+    /// as change logs or replicas can apply them in the same order without a
+    /// global lock. The numbers of keys in different segments are unrelated.
+    /// Other maps return 0.
+    ///
+    /// Every mutation is assigned a number, including those made by methods
+    /// that do not return it, and a mutation whose publication is retried may
+    /// skip numbers. The number of the last mutation of a key is returned by
+    /// [`get_sequence`].
+    ///
+    /// This method is only available with the `sequence-numbers` feature.
+    ///
+    /// [`Builder::sequence_numbers`]: ./struct.Builder.html#method.sequence_numbers
+    /// [`get_sequence`]: #method.get_sequence
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::segment::map::Builder;
+    ///
+    /// let map = Builder::new(4).sequence_numbers().build();
+    ///
+    /// let (_, first) = map.insert_sequenced("foo", 1);
+    /// let (_, second) = map.insert_sequenced("foo", 2);
+    /// assert!(second > first);
+    /// assert_eq!(map.get_sequence("foo"), Some(second));
+    /// ```
+    #[cfg(feature = "sequence-numbers")]
+    pub fn insert_sequenced(&self, key: K, value: V) -> (Option<V>, u64)
+    where
+        V: Clone,
+    {
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        let result =
+            self.bucket_array_ref(hash)
+                .insert_sequenced_entry_and(key, hash, value, |_, v| v.clone());
+
+        if result.0.is_none() {
+            self.len.fetch_add(1, ordering::RELAXED);
+        }
+
+        result
+    }
+
+    /// Removes a key from the map, returning a clone of the value previously
+    /// corresponding to the key and the sequence number assigned to the
+    /// removal.
+    ///
+    /// See [`insert_sequenced`] for how sequence numbers are assigned.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// This method is only available with the `sequence-numbers` feature.
+    ///
+    /// [`insert_sequenced`]: #method.insert_sequenced
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[cfg(feature = "sequence-numbers")]
+    pub fn remove_sequenced<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<(V, u64)>
+    where
+        K: Borrow<Q>,
+        V: Clone,
+    {
+        let hash = bucket::hash(&self.build_hasher, key);
+
+        self.bucket_array_ref(hash).remove_sequenced_entry_if_and(
+            key,
+            hash,
+            |_, _| true,
+            move |_, v| {
+                self.len.fetch_sub(1, ordering::RELAXED);
+
+                v.clone()
+            },
+        )
+    }
+
+    /// Inserts a key-value pair into the map, returning a clone of the value
+    /// previously corresponding to the key and the [`OpReport`] of the
+    /// insertion.
+    ///
+    /// The report tells how expensive the insertion was, so that adaptive
+    /// callers can back off from the keys where writes get slow. See the
+    /// [`metrics`] module for details.
+    ///
+    /// This method is only available with the `instrument` feature.
+    ///
+    /// [`OpReport`]: ../../metrics/struct.OpReport.html
+    /// [`metrics`]: ../../metrics/index.html
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(4);
+    ///
+    /// let (previous, report) = map.insert_reported("foo", 1);
+    /// assert_eq!(previous, None);
+    /// assert_eq!(report.probe_length(), 1);
+    /// assert_eq!(report.retries(), 0);
+    /// ```
+    #[cfg(feature = "instrument")]
+    pub fn insert_reported(&self, key: K, value: V) -> (Option<V>, OpReport)
+    where
+        V: Clone,
+    {
+        crate::metrics::record(|| self.insert(key, value))
+    }
+
+    /// Removes a key from the map, returning a clone of the value previously
+    /// corresponding to the key and the [`OpReport`] of the removal.
+    ///
+    /// See [`insert_reported`] for details.
+    ///
+    /// This method is only available with the `instrument` feature.
+    ///
+    /// [`OpReport`]: ../../metrics/struct.OpReport.html
+    /// [`insert_reported`]: #method.insert_reported
+    #[cfg(feature = "instrument")]
+    pub fn remove_reported<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> (Option<V>, OpReport)
+    where
+        K: Borrow<Q>,
+        V: Clone,
+    {
+        crate::metrics::record(|| self.remove(key))
+    }
+
+    /// Returns the sequence number assigned to the last mutation of the entry
+    /// of a key, or 0 if the map does not assign them.
+    ///
+    /// See [`insert_sequenced`] for how sequence numbers are assigned.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// This method is only available with the `sequence-numbers` feature.
+    ///
+    /// [`insert_sequenced`]: #method.insert_sequenced
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    #[cfg(feature = "sequence-numbers")]
+    pub fn get_sequence<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<u64>
+    where
+        K: Borrow<Q>,
+    {
+        let hash = bucket::hash(&self.build_hasher, key);
+
+        self.bucket_array_ref(hash).get_sequence(key, hash)
+    }
+
+    /// If no value corresponds to the key, insert a new key-value pair into
+    /// the map. Otherwise, modify the existing value and return a clone of the
+    /// value previously corresponding to the key.
+    ///
+    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    #[inline]
+    pub fn insert_or_modify<F: FnMut(&K, &V) -> V>(
+        &self,
+        key: K,
+        value: V,
+        on_modify: F,
+    ) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.insert_with_or_modify_entry_and(key, move || value, on_modify, |_, v| v.clone())
+    }
+
+    /// If no value corresponds to the key, insert a new key-value pair into
+    /// the map. Otherwise, modify the existing value and return a clone of the
+    /// key-value pair previously corresponding to the key.
+    ///
+    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    #[inline]
+    pub fn insert_or_modify_entry<F: FnMut(&K, &V) -> V>(
+        &self,
+        key: K,
+        value: V,
+        on_modify: F,
+    ) -> Option<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.insert_with_or_modify_entry_and(
+            key,
+            move || value,
+            on_modify,
+            |k, v| (k.clone(), v.clone()),
+        )
+    }
+
+    /// If no value corresponds to the key, invoke a default function to insert
+    /// a new key-value pair into the map. Otherwise, modify the existing value
+    /// and return a clone of the value previously corresponding to the key.
+    ///
+    /// `on_insert` may be invoked, even if [`None`] is returned.
+    ///
+    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    #[inline]
+    pub fn insert_with_or_modify<F: FnOnce() -> V, G: FnMut(&K, &V) -> V>(
+        &self,
+        key: K,
+        on_insert: F,
+        on_modify: G,
+    ) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.insert_with_or_modify_entry_and(key, on_insert, on_modify, |_, v| v.clone())
+    }
+
+    /// If no value corresponds to the key, invoke a default function to insert
+    /// a new key-value pair into the map. Otherwise, modify the existing value
+    /// and return a clone of the key-value pair previously corresponding to the
+    /// key.
+    ///
+    /// `on_insert` may be invoked, even if [`None`] is returned.
+    ///
+    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    #[inline]
+    pub fn insert_with_or_modify_entry<F: FnOnce() -> V, G: FnMut(&K, &V) -> V>(
+        &self,
+        key: K,
+        on_insert: F,
+        on_modify: G,
+    ) -> Option<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.insert_with_or_modify_entry_and(key, on_insert, on_modify, |k, v| {
+            (k.clone(), v.clone())
+        })
+    }
+
+    /// If no value corresponds to the key, insert a new key-value pair into
+    /// the map. Otherwise, modify the existing value and return the result of
+    /// invoking a function with a reference to the value previously
+    /// corresponding to the key.
+    ///
+    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    #[inline]
+    pub fn insert_or_modify_and<F: FnMut(&K, &V) -> V, G: FnOnce(&V) -> T, T>(
+        &self,
+        key: K,
+        value: V,
+        on_modify: F,
+        with_old_value: G,
+    ) -> Option<T> {
+        self.insert_with_or_modify_entry_and(
+            key,
+            move || value,
+            on_modify,
+            move |_, v| with_old_value(v),
+        )
+    }
+
+    /// If no value corresponds to the key, insert a new key-value pair into
+    /// the map. Otherwise, modify the existing value and return the result of
+    /// invoking a function with a reference to the key-value pair previously
+    /// corresponding to the supplied key.
+    ///
+    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    #[inline]
+    pub fn insert_or_modify_entry_and<F: FnMut(&K, &V) -> V, G: FnOnce(&K, &V) -> T, T>(
+        &self,
+        key: K,
+        value: V,
+        on_modify: F,
+        with_old_entry: G,
+    ) -> Option<T> {
+        self.insert_with_or_modify_entry_and(key, move || value, on_modify, with_old_entry)
+    }
+
+    /// If no value corresponds to the key, invoke a default function to insert
+    /// a new key-value pair into the map. Otherwise, modify the existing value
+    /// and return the result of invoking a function with a reference to the
+    /// value previously corresponding to the key.
+    ///
+    /// `on_insert` may be invoked, even if [`None`] is returned.
+    ///
+    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    #[inline]
+    pub fn insert_with_or_modify_and<
+        F: FnOnce() -> V,
+        G: FnMut(&K, &V) -> V,
+        H: FnOnce(&V) -> T,
+        T,
+    >(
+        &self,
+        key: K,
+        on_insert: F,
+        on_modify: G,
+        with_old_value: H,
+    ) -> Option<T> {
+        self.insert_with_or_modify_entry_and(key, on_insert, on_modify, move |_, v| {
+            with_old_value(v)
+        })
+    }
+
+    /// If no value corresponds to the key, invoke a default function to insert
+    /// a new key-value pair into the map. Otherwise, modify the existing value
+    /// and return the result of invoking a function with a reference to the
+    /// key-value pair previously corresponding to the supplied key.
+    ///
+    /// `on_insert` may be invoked, even if [`None`] is returned.
+    ///
+    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    #[inline]
+    pub fn insert_with_or_modify_entry_and<
+        F: FnOnce() -> V,
+        G: FnMut(&K, &V) -> V,
+        H: FnOnce(&K, &V) -> T,
+        T,
+    >(
+        &self,
+        key: K,
+        on_insert: F,
+        on_modify: G,
+        with_old_entry: H,
+    ) -> Option<T> {
+        self.insert_with_key_or_modify_entry_and(
+            key,
+            move |_| on_insert(),
+            on_modify,
+            with_old_entry,
+        )
+    }
+
+    /// If no value corresponds to the key, invoke a default function with a
+    /// reference to the key to insert a new key-value pair into the map.
+    /// Otherwise, modify the existing value and return a clone of the value
+    /// previously corresponding to the key.
+    ///
+    /// This is equivalent to [`insert_with_or_modify`], except that `on_insert`
+    /// can use the key without it being cloned beforehand.
+    ///
+    /// `on_insert` may be invoked, even if [`None`] is returned.
+    ///
+    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// [`insert_with_or_modify`]: #method.insert_with_or_modify
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    #[inline]
+    pub fn insert_with_key_or_modify<F: FnOnce(&K) -> V, G: FnMut(&K, &V) -> V>(
+        &self,
+        key: K,
+        on_insert: F,
+        on_modify: G,
+    ) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.insert_with_key_or_modify_entry_and(key, on_insert, on_modify, |_, v| v.clone())
+    }
+
+    /// If no value corresponds to the key, invoke a default function with a
+    /// reference to the key to insert a new key-value pair into the map.
+    /// Otherwise, modify the existing value and return a clone of the
+    /// key-value pair previously corresponding to the key.
+    ///
+    /// `on_insert` may be invoked, even if [`None`] is returned.
+    ///
+    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    #[inline]
+    pub fn insert_with_key_or_modify_entry<F: FnOnce(&K) -> V, G: FnMut(&K, &V) -> V>(
+        &self,
+        key: K,
+        on_insert: F,
+        on_modify: G,
+    ) -> Option<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.insert_with_key_or_modify_entry_and(key, on_insert, on_modify, |k, v| {
+            (k.clone(), v.clone())
+        })
+    }
+
+    /// If no value corresponds to the key, invoke a default function with a
+    /// reference to the key to insert a new key-value pair into the map.
+    /// Otherwise, modify the existing value and return the result of invoking
+    /// a function with a reference to the value previously corresponding to
+    /// the key.
+    ///
+    /// `on_insert` may be invoked, even if [`None`] is returned.
+    ///
+    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    #[inline]
+    pub fn insert_with_key_or_modify_and<
+        F: FnOnce(&K) -> V,
+        G: FnMut(&K, &V) -> V,
+        H: FnOnce(&V) -> T,
+        T,
+    >(
+        &self,
+        key: K,
+        on_insert: F,
+        on_modify: G,
+        with_old_value: H,
+    ) -> Option<T> {
+        self.insert_with_key_or_modify_entry_and(key, on_insert, on_modify, move |_, v| {
+            with_old_value(v)
+        })
+    }
+
+    /// If no value corresponds to the key, invoke a default function with a
+    /// reference to the key to insert a new key-value pair into the map.
+    /// Otherwise, modify the existing value and return the result of invoking
+    /// a function with a reference to the key-value pair previously
+    /// corresponding to the supplied key.
+    ///
+    /// `on_insert` may be invoked, even if [`None`] is returned.
+    ///
+    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    #[inline]
+    pub fn insert_with_key_or_modify_entry_and<
+        F: FnOnce(&K) -> V,
+        G: FnMut(&K, &V) -> V,
+        H: FnOnce(&K, &V) -> T,
+        T,
+    >(
+        &self,
+        key: K,
+        on_insert: F,
+        on_modify: G,
+        with_old_entry: H,
+    ) -> Option<T> {
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        let result = self.bucket_array_ref(hash).insert_with_or_modify_entry_and(
+            key,
+            hash,
+            on_insert,
+            on_modify,
+            with_old_entry,
+        );
+
+        if result.is_none() {
+            self.len.fetch_add(1, ordering::RELAXED);
+        }
+
+        result
+    }
+
+    /// If no value corresponds to the key, invoke a default function to insert
+    /// a new key-value pair into the map. Otherwise, modify the existing value
+    /// and return a clone of the value previously corresponding to the key.
+    ///
+    /// This is equivalent to [`insert_with_or_modify`], except that both
+    /// functions are passed `state` by mutable reference. `on_modify` may be
+    /// invoked more than once when other threads concurrently modify the
+    /// entry, and the state is kept across those invocations, so work that
+    /// does not depend on the current value, such as parsing or lookups, can be
+    /// done on the first invocation and reused by the retries. The state is
+    /// left with the caller afterwards.
+    ///
+    /// `on_insert` may be invoked, even if [`None`] is returned.
+    ///
+    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
+    /// may also be invoked one or more times if [`None`] is returned.
+    ///
+    /// [`insert_with_or_modify`]: #method.insert_with_or_modify
+    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    #[inline]
+    pub fn insert_with_or_modify_with_state<
+        T,
+        F: FnOnce(&mut T) -> V,
+        G: FnMut(&mut T, &K, &V) -> V,
+    >(
+        &self,
+        key: K,
+        state: &mut T,
+        on_insert: F,
+        mut on_modify: G,
+    ) -> Option<V>
+    where
+        V: Clone,
+    {
+        // the functions are never invoked at the same time, but they are both
+        // alive for the whole operation.
+        let state = RefCell::new(state);
+
+        self.insert_with_or_modify_entry_and(
+            key,
+            || on_insert(&mut state.borrow_mut()),
+            |k, v| on_modify(&mut state.borrow_mut(), k, v),
+            |_, v| v.clone(),
+        )
+    }
+
+    /// If no value corresponds to the key, invoke a default function to insert
+    /// a new key-value pair into the map. Otherwise, modify the existing value.
+    /// Returns a [`Ref`] to the resulting key-value pair.
+    ///
+    /// This is equivalent to calling [`insert_with_or_modify`] followed by a
+    /// lookup, but only traverses the map once. The returned value is the one
+    /// written by this call, even if another thread has since replaced it.
+    ///
+    /// `on_insert` may be invoked, even if the key was present.
+    ///
+    /// `on_modify` may be invoked one or more times, even if the key was not
+    /// present.
+    ///
+    /// [`Ref`]: ../map/struct.Ref.html
+    /// [`insert_with_or_modify`]: #method.insert_with_or_modify
+    #[inline]
+    pub fn upsert_and_get<F: FnOnce() -> V, G: FnMut(&K, &V) -> V>(
+        &self,
+        key: K,
+        on_insert: F,
+        on_modify: G,
+    ) -> Ref<'_, K, V> {
+        let hash = bucket::hash(&self.build_hasher, &key);
+        let guard = crossbeam_epoch::pin();
+
+        let (previous_bucket_ref, current_bucket_ref) = self
+            .bucket_array_ref(hash)
+            .insert_with_or_modify(&guard, key, hash, move |_| on_insert(), on_modify);
+        let current_bucket_ptr: *const Bucket<K, V> = current_bucket_ref;
+
+        if previous_bucket_ref.is_none() {
+            self.len.fetch_add(1, ordering::RELAXED);
+        }
+
+        unsafe { Ref::new(guard, current_bucket_ptr) }
+    }
+
+    /// Modifies the value corresponding to a key, returning a clone of the
+    /// value previously corresponding to that key.
+    #[inline]
+    pub fn modify<F: FnMut(&K, &V) -> V>(&self, key: K, on_modify: F) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.modify_entry_and(key, on_modify, |_, v| v.clone())
+    }
+
+    /// Modifies the value corresponding to a key, returning a clone of the
+    /// key-value pair previously corresponding to that key.
+    #[inline]
+    pub fn modify_entry<F: FnMut(&K, &V) -> V>(&self, key: K, on_modify: F) -> Option<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.modify_entry_and(key, on_modify, |k, v| (k.clone(), v.clone()))
+    }
+
+    /// Modifies the value corresponding to a key, returning the result of
+    /// invoking a function with a reference to the value previously
+    /// corresponding to the key.
+    #[inline]
+    pub fn modify_and<F: FnMut(&K, &V) -> V, G: FnOnce(&V) -> T, T>(
+        &self,
+        key: K,
+        on_modify: F,
+        with_old_value: G,
+    ) -> Option<T> {
+        self.modify_entry_and(key, on_modify, move |_, v| with_old_value(v))
+    }
+
+    /// Modifies the value corresponding to a key, returning the result of
+    /// invoking a function with a reference to the key-value pair previously
+    /// corresponding to the supplied key.
+    #[inline]
+    pub fn modify_entry_and<F: FnMut(&K, &V) -> V, G: FnOnce(&K, &V) -> T, T>(
+        &self,
+        key: K,
+        on_modify: F,
+        with_old_entry: G,
+    ) -> Option<T> {
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        self.bucket_array_ref(hash)
+            .modify_entry_and(key, hash, on_modify, with_old_entry)
+    }
+
+    /// Looks up the value corresponding to a key and keeps, replaces or
+    /// removes it as `decide` returns, returning a clone of the value that
+    /// `decide` was last called with.
+    ///
+    /// Unlike a [`get`] followed by a [`modify`] or a [`remove`], this probes
+    /// for the key once and carries out the decision at the slot where the key
+    /// was found. If another thread replaces the value before the decision is
+    /// carried out, `decide` is called again with the new value, so the
+    /// decision is always made on the value it changes. `decide` is not called
+    /// if the key is not in the map.
+    ///
+    /// [`get`]: #method.get
+    /// [`modify`]: #method.modify
+    /// [`remove`]: #method.remove
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::{map::Decision, SegmentedHashMap};
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(4);
+    /// map.insert("foo", 0);
+    ///
+    /// let value = map.get_then_modify("foo", |_, _| Decision::Remove);
+    /// assert_eq!(value, Some(0));
+    /// assert!(map.is_empty());
+    /// ```
+    #[inline]
+    pub fn get_then_modify<F: FnMut(&K, &V) -> Decision<V>>(&self, key: K, decide: F) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.get_then_modify_and(key, decide, |_, v| v.clone())
+    }
+
+    /// Looks up the value corresponding to a key and keeps, replaces or
+    /// removes it as `decide` returns, returning the result of invoking a
+    /// function with a reference to the key-value pair that `decide` was last
+    /// called with.
+    ///
+    /// See [`get_then_modify`] for details.
+    ///
+    /// [`get_then_modify`]: #method.get_then_modify
+    pub fn get_then_modify_and<F: FnMut(&K, &V) -> Decision<V>, G: FnOnce(&K, &V) -> T, T>(
+        &self,
+        key: K,
+        decide: F,
+        with_entry: G,
+    ) -> Option<T> {
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        let (result, decided) = self
+            .bucket_array_ref(hash)
+            .get_then_modify_entry_and(key, hash, decide, with_entry)?;
+
+        if decided == Decided::Removed {
+            self.len.fetch_sub(1, ordering::RELAXED);
+        }
+
+        Some(result)
+    }
+
+    /// Inserts a key-value pair into the map, coalescing it with the other
+    /// coalesced writes to the key within the coalescing window of the map.
+    ///
+    /// The first coalesced write to a key is applied right away and opens a
+    /// window for the key, which lasts as long as set with the
+    /// [`coalesce_window`] method of the builder. The coalesced writes to the
+    /// key while the window is open are kept pending outside of the map, each
+    /// one replacing the previous one, and only the last is applied when the
+    /// window closes. The values in between are dropped right away instead of
+    /// being deferred to the garbage collector, which suits maps whose keys are
+    /// overwritten many times per second, such as telemetry.
+    ///
+    /// Lookups do not see the pending values. A window is closed by the next
+    /// coalesced write to a key that shares its lock, so it may stay open past
+    /// its deadline while the map is not written to; [`flush_coalesced`]
+    /// closes all windows. A pending value is only applied if the key still
+    /// has a value then, so a removal of the key discards it.
+    ///
+    /// If the map has no coalescing window, this behaves like [`insert`].
+    ///
+    /// [`coalesce_window`]: ./struct.Builder.html#method.coalesce_window
+    /// [`flush_coalesced`]: #method.flush_coalesced
+    /// [`insert`]: #method.insert
+    pub fn insert_coalesced(&self, key: K, value: V)
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let coalescing = match &self.coalescing {
+            Some(coalescing) => coalescing,
+            None => {
+                self.insert_entry_and(key, value, |_, _| ());
+
+                return;
+            }
+        };
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        coalescing.insert(
+            hash,
+            key,
+            value,
+            |k, v| {
+                self.insert_entry_and(k, v, |_, _| ());
+            },
+            |k, v| self.apply_pending(k, v),
+        );
+    }
+
+    /// Modifies the value corresponding to a key, coalescing the modification
+    /// with the other coalesced writes to the key within the coalescing window
+    /// of the map, and returns `false` if the key has no value.
+    ///
+    /// While the window of the key is open, `on_modify` is invoked with the
+    /// pending value of the key, if any, and otherwise with the value in the
+    /// map. It is invoked under a lock that the coalesced writes to other keys
+    /// may share, so it must not write to the map with coalescing. See
+    /// [`insert_coalesced`] for how the writes are coalesced.
+    ///
+    /// If the map has no coalescing window, this behaves like [`modify`].
+    ///
+    /// [`insert_coalesced`]: #method.insert_coalesced
+    /// [`modify`]: #method.modify
+    pub fn modify_coalesced<F: FnMut(&K, &V) -> V>(&self, key: K, on_modify: F) -> bool
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let coalescing = match &self.coalescing {
+            Some(coalescing) => coalescing,
+            None => return self.modify_entry_and(key, on_modify, |_, _| ()).is_some(),
+        };
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        coalescing.modify(
+            hash,
+            key,
+            on_modify,
+            |k, f| self.modify_entry_and(k, f, |_, _| ()).is_some(),
+            |k, f| self.get_key_value_and(k, f),
+            |k, v| self.apply_pending(k, v),
+        )
+    }
+
+    /// Applies the pending values of the coalesced writes to the map and
+    /// closes their windows.
+    ///
+    /// See [`insert_coalesced`] for details.
+    ///
+    /// [`insert_coalesced`]: #method.insert_coalesced
+    pub fn flush_coalesced(&self)
+    where
+        K: Clone,
+        V: Clone,
+    {
+        if let Some(coalescing) = &self.coalescing {
+            coalescing.flush(|k, v| self.apply_pending(k, v));
+        }
+    }
+
+    // the value is cloned because it may be applied more than once if the
+    // bucket is replaced concurrently.
+    fn apply_pending(&self, key: K, value: V)
+    where
+        V: Clone,
+    {
+        self.modify_entry_and(key, move |_, _| value.clone(), |_, _| ());
+    }
+}
+
+impl<K: Hash + Eq + Send + Sync, V: Send + Sync, S: BuildHasher + Sync> HashMap<K, V, S> {
+    /// Groups the entries of the map by the result of a function, returning the
+    /// number of entries in each group.
+    ///
+    /// This is a shorthand for [`group_fold`]; see its documentation for the
+    /// consistency guarantees.
+    ///
+    /// [`group_fold`]: #method.group_fold
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(4);
+    ///
+    /// for i in 0..10 {
+    ///     map.insert(i, i * i);
+    /// }
+    ///
+    /// let counts = map.group_count(|_, v| v % 2 == 0);
+    ///
+    /// assert_eq!(counts[&true], 5);
+    /// assert_eq!(counts[&false], 5);
+    /// ```
+    pub fn group_count<G: Hash + Eq + Send, F: Fn(&K, &V) -> G + Sync>(
+        &self,
+        group_by: F,
+    ) -> StdHashMap<G, usize> {
+        self.group_fold(
+            group_by,
+            || 0,
+            |count, _, _| *count += 1,
+            |count, other| *count += other,
+        )
+    }
+
+    /// Groups the entries of the map by the result of a function, folding the
+    /// entries of each group into an accumulator.
+    ///
+    /// For each entry, `group_by` selects its group, and `fold` is invoked with
+    /// the accumulator of that group, which is created by `init` on the first
+    /// entry of the group. The bucket pointer array of each segment is walked
+    /// in place like in [`fold_segments`]. With the `rayon` feature, the
+    /// segments are processed in parallel on the threads of the rayon pool, and
+    /// the accumulators of the same group are merged by `combine`; without it,
+    /// they are processed one after the other on the calling thread.
+    ///
+    /// The result is weakly consistent: entries that are inserted, modified, or
+    /// removed concurrently may or may not be reflected in it, and the result
+    /// need not correspond to any single state of the map.
+    ///
+    /// [`fold_segments`]: #method.fold_segments
+    pub fn group_fold<G, A, F, I, U, C>(
+        &self,
+        group_by: F,
+        init: I,
+        fold: U,
+        combine: C,
+    ) -> StdHashMap<G, A>
+    where
+        G: Hash + Eq + Send,
+        A: Send,
+        F: Fn(&K, &V) -> G + Sync,
+        I: Fn() -> A + Sync,
+        U: Fn(&mut A, &K, &V) + Sync,
+        C: Fn(&mut A, A) + Sync,
+    {
+        self.process_segments(
+            StdHashMap::new,
+            |groups, index| {
+                self.walk_segment(index, |key, value| {
+                    let accumulator = groups.entry(group_by(key, value)).or_insert_with(&init);
+
+                    fold(accumulator, key, value);
+                })
+            },
+            |groups, other| {
+                for (group, accumulator) in other {
+                    match groups.entry(group) {
+                        Entry::Occupied(mut e) => combine(e.get_mut(), accumulator),
+                        Entry::Vacant(e) => {
+                            e.insert(accumulator);
+                        }
+                    }
+                }
+            },
+        )
+    }
+
+    /// Folds the entries of each segment into a partial result, and combines
+    /// the partial results into one.
+    ///
+    /// For each segment, `fold` is invoked with every entry and an accumulator
+    /// created by `init`, under a single epoch guard, and the partial result
+    /// of the segment is merged into the one of the previous segments by
+    /// `combine`. The bucket pointer array of each segment is walked in place
+    /// like in [`fold`], so nothing is cloned or collected, and aggregates
+    /// such as sums, histograms, or the top entries can be computed in one
+    /// pass. The segments are processed one after the other on the calling
+    /// thread; [`par_fold_segments`] processes them in parallel. Like
+    /// [`for_each`], the traversal is weakly consistent.
+    ///
+    /// [`fold`]: #method.fold
+    /// [`par_fold_segments`]: #method.par_fold_segments
+    /// [`for_each`]: #method.for_each
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(4);
+    ///
+    /// for i in 0..100 {
+    ///     map.insert(i, i);
+    /// }
+    ///
+    /// // the three largest values
+    /// let top = map.fold_segments(
+    ///     Vec::new,
+    ///     |top, _, &v| {
+    ///         top.push(v);
+    ///         top.sort_unstable_by(|a, b| b.cmp(a));
+    ///         top.truncate(3);
+    ///     },
+    ///     |top, other| {
+    ///         top.extend(other);
+    ///         top.sort_unstable_by(|a, b| b.cmp(a));
+    ///         top.truncate(3);
+    ///     },
+    /// );
+    ///
+    /// assert_eq!(top, [99, 98, 97]);
+    /// ```
+    pub fn fold_segments<A, I, U, C>(&self, mut init: I, mut fold: U, mut combine: C) -> A
+    where
+        I: FnMut() -> A,
+        U: FnMut(&mut A, &K, &V),
+        C: FnMut(&mut A, A),
+    {
+        let _panic_guard = self.panic_policy.enter();
+        let mut result = None;
+
+        for index in 0..self.segments.len() {
+            let partial_result = self.fold_segment(index, &mut init, &mut fold);
+
+            match &mut result {
+                Some(result) => combine(result, partial_result),
+                None => result = Some(partial_result),
+            }
+        }
+
+        result.expect("a map has at least one segment")
+    }
+
+    /// Folds the entries of each segment into a partial result on the threads
+    /// of the rayon pool, and combines the partial results into one.
+    ///
+    /// Each segment is folded by a task of the pool like in [`fold_segments`],
+    /// walking its bucket pointer array in place under an epoch guard of its
+    /// own, and the partial results are combined by `combine` in arbitrary
+    /// order as the tasks finish.
+    ///
+    /// This method is only available with the `rayon` feature.
+    ///
+    /// [`fold_segments`]: #method.fold_segments
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(8);
+    ///
+    /// for i in 0..100_000u64 {
+    ///     map.insert(i, i);
+    /// }
+    ///
+    /// let sum = map.par_fold_segments(|| 0, |sum, _, v| *sum += v, |sum, other| *sum += other);
+    ///
+    /// assert_eq!(sum, (0..100_000).sum());
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn par_fold_segments<A, I, U, C>(&self, init: I, fold: U, combine: C) -> A
+    where
+        K: Send + Sync,
+        V: Send + Sync,
+        S: Sync,
+        A: Send,
+        I: Fn() -> A + Sync,
+        U: Fn(&mut A, &K, &V) + Sync,
+        C: Fn(&mut A, A) + Sync,
+    {
+        use rayon::prelude::*;
+
+        let _panic_guard = self.panic_policy.enter();
+
+        (0..self.segments.len())
+            .into_par_iter()
+            .map(|index| self.fold_segment(index, &init, &fold))
+            .reduce_with(|mut result, partial_result| {
+                combine(&mut result, partial_result);
+
+                result
+            })
+            .expect("a map has at least one segment")
+    }
+
+    fn fold_segment<A>(
+        &self,
+        index: usize,
+        init: impl FnOnce() -> A,
+        mut fold: impl FnMut(&mut A, &K, &V),
+    ) -> A {
+        let mut accumulator = init();
+        self.walk_segment(index, |key, value| fold(&mut accumulator, key, value));
+
+        accumulator
+    }
+
+    /// Invokes `visit` with every entry of the `index`-th segment, walking its
+    /// bucket pointer array in place under a single epoch guard.
+    fn walk_segment(&self, index: usize, mut visit: impl FnMut(&K, &V)) {
+        let guard = &crossbeam_epoch::pin();
+
+        for b in self.segment_bucket_array_ref(index).walk(guard) {
+            visit(&b.key, unsafe { b.value() });
+        }
+    }
+
+    /// Returns an order-independent fingerprint of the entries of the map.
+    ///
+    /// The fingerprint is the wrapping sum of the hashes of all key-value
+    /// pairs, computed with the map's hasher. Maps with equal entries have
+    /// equal fingerprints if their hashers produce the same hashes, which is
+    /// not the case for two instances of the default hasher, so maps compared
+    /// across replicas should use a deterministic hasher. The segments are
+    /// processed as in [`group_fold`], with the same consistency guarantees.
+    ///
+    /// [`group_fold`]: #method.group_fold
+    pub fn content_fingerprint(&self) -> u64
+    where
+        V: Hash,
+    {
+        self.process_segments(
+            || 0u64,
+            |fingerprint, index| {
+                self.walk_segment(index, |key, value| {
+                    let hash = bucket::hash(&self.build_hasher, &(key, value));
+                    *fingerprint = fingerprint.wrapping_add(hash);
+                })
+            },
+            |fingerprint, other| *fingerprint = fingerprint.wrapping_add(other),
+        )
+    }
+
+    /// Removes every entry whose key satisfies a predicate, returning the
+    /// number of entries removed.
+    ///
+    /// This is a shorthand for [`remove_matching_with_progress`]; see its
+    /// documentation for details.
+    ///
+    /// [`remove_matching_with_progress`]: #method.remove_matching_with_progress
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(4);
+    ///
+    /// for i in 0..10 {
+    ///     map.insert(format!("{}/{}", i % 2, i), i);
+    /// }
+    ///
+    /// assert_eq!(map.remove_matching(|k| k.starts_with("0/")), 5);
+    /// assert_eq!(map.len(), 5);
+    /// assert_eq!(map.get("1/3"), Some(3));
+    /// ```
+    pub fn remove_matching<F: Fn(&K) -> bool + Sync>(&self, predicate: F) -> usize {
+        self.remove_matching_with_progress(predicate, |_, _| ())
+    }
+
+    /// Removes every entry whose key satisfies a predicate, returning the
+    /// number of entries removed, and reporting the progress segment by
+    /// segment.
+    ///
+    /// The segments are processed as in [`group_fold`]. Within a segment, the
+    /// keys are tested and the matching entries are removed in the order of
+    /// their slots under a single epoch guard, and the length of
+    /// the map is updated once. After a segment is done, `on_segment` is
+    /// invoked with its index and the number of entries removed from it, from
+    /// the thread that processed it.
+    ///
+    /// Like a sequence of calls to [`remove`], this is not atomic: entries
+    /// that are inserted concurrently may or may not be removed, even if
+    /// their keys satisfy the predicate.
+    ///
+    /// [`group_fold`]: #method.group_fold
+    /// [`remove`]: #method.remove
+    pub fn remove_matching_with_progress<F, P>(&self, predicate: F, on_segment: P) -> usize
+    where
+        F: Fn(&K) -> bool + Sync,
+        P: Fn(usize, usize) + Sync,
+    {
+        self.process_segments(
+            || 0,
+            |removed, index| {
+                let bucket_array_ref = self.segment_bucket_array_ref(index);
+                let guard = &crossbeam_epoch::pin();
+                let mut removed_from_segment = 0;
+
+                for b in self.segment_bucket_array_ref(index).walk(guard) {
+                    if !predicate(&b.key) {
+                        continue;
+                    }
+
+                    let hash = bucket::hash(&self.build_hasher, &b.key);
+
+                    if bucket_array_ref
+                        .remove_entry_if_and(&b.key, hash, |_, _| true, |_, _| ())
+                        .is_some()
+                    {
+                        removed_from_segment += 1;
+                    }
+                }
+
+                self.len.fetch_sub(removed_from_segment, ordering::RELAXED);
+                *removed += removed_from_segment;
+                on_segment(index, removed_from_segment);
+            },
+            |removed, other| *removed += other,
+        )
+    }
+
+    /// Processes the segments of the map, passing an accumulator created by
+    /// `init` and the index of every segment to `process`, and merges the
+    /// accumulators with `combine`.
+    ///
+    /// The segments are split among the tasks of the rayon pool.
+    #[cfg(feature = "rayon")]
+    fn process_segments<A, I, P, C>(&self, init: I, process: P, combine: C) -> A
+    where
+        A: Send,
+        I: Fn() -> A + Sync,
+        P: Fn(&mut A, usize) + Sync,
+        C: Fn(&mut A, A) + Sync,
+    {
+        use rayon::prelude::*;
+
+        (0..self.segments.len())
+            .into_par_iter()
+            .fold(&init, |mut accumulator, index| {
+                process(&mut accumulator, index);
+
+                accumulator
+            })
+            .reduce(&init, |mut accumulator, other| {
+                combine(&mut accumulator, other);
+
+                accumulator
+            })
+    }
+
+    /// Processes the segments of the map one after the other on the calling
+    /// thread, passing a single accumulator created by `init` and the index of
+    /// every segment to `process`.
+    #[cfg(not(feature = "rayon"))]
+    fn process_segments<A, I, P, C>(&self, init: I, process: P, _combine: C) -> A
+    where
+        A: Send,
+        I: Fn() -> A + Sync,
+        P: Fn(&mut A, usize) + Sync,
+        C: Fn(&mut A, A) + Sync,
+    {
+        let mut accumulator = init();
+
+        for index in 0..self.segments.len() {
+            process(&mut accumulator, index);
+        }
+
+        accumulator
+    }
+}
+
+#[cfg(feature = "num-cpus")]
+impl<K, V, S: Default> Default for HashMap<K, V, S> {
+    fn default() -> Self {
+        HashMap::with_num_segments_capacity_and_hasher(default_num_segments(), 0, S::default())
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> IntoIterator for HashMap<K, V, S> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    /// Creates an iterator that moves the entries out of the map in arbitrary
+    /// order, yielding the key and the value of each of them without cloning
+    /// them.
+    ///
+    /// A resize left unfinished by other threads is finished first. The
+    /// entries of a deterministic map are yielded segment by segment, and in
+    /// the order of their hashes within each segment. The writes still pending
+    /// in the coalescing windows of the map are dropped along with it; call
+    /// [`flush_coalesced`] first to keep them.
+    ///
+    /// [`flush_coalesced`]: #method.flush_coalesced
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use moka_cht::SegmentedHashMap;
+    ///
+    /// let map = SegmentedHashMap::with_num_segments(4);
+    /// map.insert(1, String::from("a"));
+    /// map.insert(2, String::from("b"));
+    ///
+    /// let mut entries: Vec<_> = map.into_iter().collect();
+    /// entries.sort_unstable();
+    ///
+    /// assert_eq!(entries, [(1, String::from("a")), (2, String::from("b"))]);
+    /// ```
+    fn into_iter(mut self) -> IntoIter<K, V> {
+        // the buckets of the soft-removed entries are pinned, so the pins have
+        // to be dropped before the buckets are reclaimed
+        #[cfg(feature = "entry-pins")]
+        self.soft_removals.purge();
+
+        for index in 0..self.segments.len() {
+            crate::map::exclusive::settle(self.segment_bucket_array_ref(index));
+        }
+
+        let build_hasher = &self.build_hasher;
+        let mut entries = Vec::with_capacity(*self.len.get_mut());
+
+        for segment in self.segments.iter_mut() {
+            let start = entries.len();
+            crate::map::exclusive::take_entries(
+                &mut segment.bucket_array,
+                self.memory.as_ref(),
+                &mut entries,
+            );
+            *segment.len.get_mut() = 0;
+
+            if self.deterministic {
+                entries[start..].sort_by_cached_key(|(k, _)| bucket::hash(build_hasher, k));
+            }
+        }
+
+        *self.len.get_mut() = 0;
+
+        IntoIter::new(entries)
+    }
+}
+
+impl<K, V, S> Drop for HashMap<K, V, S> {
+    fn drop(&mut self) {
+        // the buckets of the soft-removed entries are pinned, so the pins have
+        // to be dropped before the buckets are reclaimed
+        #[cfg(feature = "entry-pins")]
+        self.soft_removals.purge();
+
+        let guard = unsafe { &crossbeam_epoch::unprotected() };
+        atomic::fence(ordering::ACQUIRE);
+
+        let memory = &self.memory;
+
+        for Segment {
+            bucket_array: this_bucket_array,
+            ..
+        } in self.segments.iter()
+        {
+            let current_ptr = this_bucket_array.load(ordering::RELAXED, guard);
+
+            unsafe {
+                bucket::destroy_bucket_arrays(guard, current_ptr, |b| {
+                    if let Some(memory) = memory {
+                        memory.release(&b.key, b.value());
+                    }
+                })
+            };
+        }
+    }
+}
+
+impl<K, V, S> HashMap<K, V, S> {
+    #[inline]
+    fn bucket_array_ref(&'_ self, hash: u64) -> BucketArrayRef<'_, K, V, S> {
+        self.segment_bucket_array_ref(self.segment_index_from_hash(hash))
+    }
+
+    fn reclaim_bulk_removals(&self, num_removed: usize) {
+        if self.flush_after_bulk_removals && num_removed > 0 {
+            housekeeping::reclaim_garbage();
+        }
+    }
+
+    #[inline]
+    fn segment_bucket_array_ref(&'_ self, index: usize) -> BucketArrayRef<'_, K, V, S> {
+        let Segment {
+            ref bucket_array,
+            ref len,
+            ref fingerprint,
+            ref reserved_slots,
+            ref sequence,
+        } = self.segments[index];
+
+        BucketArrayRef {
+            bucket_array,
+            build_hasher: &self.build_hasher,
+            len,
+            generation: &self.generation,
+            access_clock: &self.access_clock,
+            deterministic: self.deterministic,
+            panic_policy: self.panic_policy,
+            tombstone_policy: self.tombstone_policy,
+            memory: self.memory.as_ref(),
+            fingerprint: Some(fingerprint),
+            reserved_slots,
+            sequence: if self.sequenced { Some(sequence) } else { None },
+            sampler: self.sampler.as_ref(),
+            segment: index,
+            context: None,
+            #[cfg(feature = "instrument")]
+            latencies: &self.latencies,
+        }
+    }
+
+    #[inline]
+    fn segment_index_from_hash(&'_ self, hash: u64) -> usize {
+        segment_index_from_salted_hash(hash, self.segment_salt, self.segment_shift)
+    }
+}
+
+struct Segment<K, V> {
+    bucket_array: Atomic<BucketArray<K, V>>,
+    len: AtomicUsize,
+    // a bit for each of the hashes inserted into the segment since it was last
+    // reset, which lets lookups of keys that were never inserted skip probing
+    fingerprint: AtomicU64,
+    // the slots held by reservation tokens
+    reserved_slots: AtomicUsize,
+    // the last sequence number assigned to a mutation of the segment
+    sequence: AtomicU64,
+}
+
+#[cfg(feature = "num-cpus")]
+fn default_num_segments() -> usize {
+    num_cpus::get() * 2
+}
+
+/// Selects a segment by the most significant bits of `hash`, or of a mix of
+/// `hash` and `salt` if `salt` is not 0.
+fn segment_index_from_salted_hash(hash: u64, salt: u64, segment_shift: u32) -> usize {
+    if segment_shift == 64 {
+        return 0;
+    }
+
+    let hash = if salt == 0 {
+        hash
+    } else {
+        // the finalizer of murmur3 is a bijection, so the salted hashes are
+        // as evenly distributed as the hashes themselves.
+        let mut hash = hash ^ salt;
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        hash ^= hash >> 33;
+
+        hash
+    };
+
+    (hash >> segment_shift) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "num-cpus")]
+    use crate::write_test_cases_for_me;
+
+    use super::*;
+
+    use crate::map::DeterministicState;
+
+    use std::{
+        collections::{hash_map::DefaultHasher, HashSet},
+        hash::BuildHasherDefault,
+        sync::Arc,
+    };
+
+    // the shared test cases create maps with `new` and `builder`
+    #[cfg(feature = "num-cpus")]
+    write_test_cases_for_me!(HashMap);
+
+    #[test]
+    fn iter_segments_owned() {
+        const NUM_VALUES: u32 = 4096;
+
+        let map = Arc::new(HashMap::with_num_segments(8));
+
+        for i in 0..NUM_VALUES {
+            map.insert(i, i);
+        }
+
+        let scans = map.iter_segments_owned();
+        assert_eq!(scans.len(), 8);
+
+        let threads: Vec<_> = scans
+            .into_iter()
+            .map(|mut scan| {
+                let map = Arc::clone(&map);
+
+                std::thread::spawn(move || {
+                    let mut keys = Vec::new();
+
+                    while !scan.is_done() {
+                        for r in map.scan_segment(&mut scan, 32) {
+                            assert_eq!(map.segment_index(r.key()), scan.segment());
+                            keys.push(*r.key());
+                        }
+
+                        // the segments may grow during the scans
+                        map.insert(NUM_VALUES + keys.len() as u32, 0);
+                    }
+
+                    // a completed scan returns nothing
+                    assert_eq!(map.scan_segment(&mut scan, 32).count(), 0);
+
+                    keys
+                })
+            })
+            .collect();
+
+        let mut keys: Vec<_> = threads
+            .into_iter()
+            .flat_map(|t| t.join().unwrap())
+            .filter(|&k| k < NUM_VALUES)
+            .collect();
+        keys.sort_unstable();
+
+        assert_eq!(keys, (0..NUM_VALUES).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn group_fold() {
+        const NUM_VALUES: u32 = 1024;
+
+        let map = HashMap::with_num_segments(8);
+
+        for i in 0..NUM_VALUES {
+            map.insert(i, i);
+        }
+
+        let counts = map.group_count(|k, _| k % 3);
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts.values().sum::<usize>(), NUM_VALUES as usize);
+        assert_eq!(counts[&0], 342);
+
+        let sums = map.group_fold(
+            |k, _| k % 2 == 0,
+            || 0u64,
+            |sum, _, v| *sum += u64::from(*v),
+            |sum, other| *sum += other,
+        );
+        let total = u64::from(NUM_VALUES) * u64::from(NUM_VALUES - 1) / 2;
+        assert_eq!(sums[&true] + sums[&false], total);
+        assert_eq!(sums[&false] - sums[&true], u64::from(NUM_VALUES) / 2);
+
+        crate::test_util::run_deferred();
+    }
+
+    #[test]
+    fn try_constructors() {
+        assert_eq!(
+            HashMap::<i32, i32>::try_with_num_segments(0).err(),
+            Some(Error::ZeroSegments)
+        );
+        assert_eq!(
+            HashMap::<i32, i32>::try_with_num_segments(usize::MAX).err(),
+            Some(Error::CapacityOverflow)
+        );
+        assert_eq!(
+            HashMap::<i32, i32>::try_with_num_segments_and_capacity(1, usize::MAX / 2).err(),
+            Some(Error::CapacityOverflow)
+        );
+
+        let map = HashMap::<i32, i32>::try_with_num_segments_and_capacity(4, 16).unwrap();
+        assert_eq!(map.num_segments(), 4);
+        assert_eq!(map.try_segment_capacity(3), Ok(16));
+        assert_eq!(
+            map.try_segment_capacity(4),
+            Err(Error::SegmentIndexOutOfBounds {
+                index: 4,
+                num_segments: 4
+            })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "the number of segments must be greater than zero")]
+    fn zero_segments() {
+        let _ = HashMap::<i32, i32>::with_num_segments(0);
+    }
+
+    #[test]
+    fn run_housekeeping() {
+        const NUM_VALUES: i32 = 4096;
+
+        let map = HashMap::with_num_segments(4);
+
+        for i in 0..NUM_VALUES {
+            map.insert(i, i);
+        }
+
+        let capacity = map.capacity();
+
+        for i in (0..NUM_VALUES).filter(|i| i % 16 != 0) {
+            assert_eq!(map.remove(&i), Some(i));
+        }
+
+        // each call processes a single segment with a budget of one bucket
+        let report = map.run_housekeeping(Budget::new(1));
+        assert_eq!(report.num_segments_visited(), 1);
+        assert_eq!(report.num_shrunk(), 1);
+        assert!(!report.is_pass_complete());
+
+        while !map.run_housekeeping(Budget::new(1)).is_pass_complete() {}
+        assert!(map.capacity() < capacity);
+
+        for i in 0..NUM_VALUES {
+            let expected = if i % 16 == 0 { Some(i) } else { None };
+            assert_eq!(map.get(&i), expected);
+        }
+
+        // a full pass over a map that needs no maintenance does nothing
+        while map.run_housekeeping(Budget::unlimited()).num_shrunk() > 0 {}
+        let report = map.run_housekeeping(Budget::unlimited());
+        assert_eq!(report.num_segments_visited(), 4);
+        assert_eq!(report.num_shrunk() + report.num_compacted(), 0);
+        assert!(report.is_pass_complete());
+
+        crate::test_util::run_deferred();
+    }
+
+    #[test]
+    fn tombstone_policy() {
+        const NUM_VALUES: i32 = 4096;
+
+        let num_tombstones = |map: &HashMap<i32, i32>| {
+            map.probe_stats()
+                .iter()
+                .map(|stats| stats.num_occupied - stats.num_entries)
+                .sum::<usize>()
+        };
+
+        for policy in [
+            TombstonePolicy::KeepUntilRehash,
+            TombstonePolicy::BackwardShiftInExclusiveMode,
+        ] {
+            let mut map = Builder::new(4).tombstone_policy(policy).build();
+
+            for i in 0..NUM_VALUES {
+                map.insert(i, i);
+            }
+
+            for i in (0..NUM_VALUES).filter(|i| i % 4 == 0) {
+                assert_eq!(map.remove(&i), Some(i));
+            }
+
+            let report = map.run_housekeeping(Budget::unlimited());
+            assert_eq!(report.num_compacted(), 0);
+            assert_eq!(num_tombstones(&map), 1024);
+
+            map.exclusive();
+            let expected = if policy == TombstonePolicy::KeepUntilRehash {
+                1024
+            } else {
+                0
+            };
+            assert_eq!(num_tombstones(&map), expected);
+
+            for i in 0..NUM_VALUES {
+                let expected = if i % 4 == 0 { None } else { Some(i) };
+                assert_eq!(map.get(&i), expected);
+            }
+
+            assert_eq!(map.len(), 3072);
+        }
+
+        crate::test_util::run_deferred();
+    }
+
+    #[test]
+    fn builder() {
+        let map = Builder::new(3).capacity(64).build();
+        map.insert(0, 0);
+
+        assert_eq!(map.num_segments(), 4);
+        assert!(map.capacity() >= 64);
+        assert_eq!(map.get(&0), Some(0));
+
+        let map = Builder::new(2).deterministic().build();
+        map.insert(0, 0);
+        assert_eq!(map.num_segments(), 2);
+        assert_eq!(map.key_hash(&0), crate::hash(&DeterministicState, &0));
+
+        assert_eq!(
+            Builder::<i32, i32>::new(0).try_build().err(),
+            Some(Error::ZeroSegments)
+        );
+    }
+
+    #[test]
+    fn segment_salt() {
+        const NUM_VALUES: i32 = 1024;
+
+        let mut map = Builder::new(8).deterministic().build();
+
+        for i in 0..NUM_VALUES {
+            assert_eq!(map.insert(i, i), None);
+        }
+
+        for i in (0..NUM_VALUES).step_by(4) {
+            assert_eq!(map.remove(&i), Some(i));
+        }
+
+        #[cfg(feature = "entry-flags")]
+        assert_eq!(map.set_flags(&1, 0b10), Some(0));
+        #[cfg(feature = "generations")]
+        let generation = map.advance_generation();
+        assert_eq!(map.insert(NUM_VALUES, NUM_VALUES), None);
+
+        let hot_keys: Vec<i32> = (0..NUM_VALUES)
+            .filter(|i| i % 4 != 0 && map.segment_index(i) == 0)
+            .take(8)
+            .collect();
+        assert_eq!(hot_keys.len(), 8);
+
+        let salt = map.balanced_segment_salt(hot_keys.iter().map(|k| (k, 100)), 16);
+        assert_ne!(salt, 0);
+
+        let segments: HashSet<usize> = hot_keys.iter().map(|k| map.segment_index(k)).collect();
+        assert_eq!(segments.len(), 1);
+
+        map.set_segment_salt(salt);
+        assert_eq!(map.segment_salt(), salt);
+        assert_eq!(map.len(), (NUM_VALUES - NUM_VALUES / 4 + 1) as usize);
+
+        let segments: HashSet<usize> = hot_keys.iter().map(|k| map.segment_index(k)).collect();
+        assert!(segments.len() > 1);
+
+        for i in 0..NUM_VALUES {
+            if i % 4 == 0 {
+                assert_eq!(map.get(&i), None);
+            } else {
+                assert_eq!(map.get(&i), Some(i));
+            }
+        }
+
+        #[cfg(feature = "entry-flags")]
+        {
+            assert_eq!(map.fetch_flags(&1), Some(0b10));
+            assert_eq!(map.fetch_flags(&2), Some(0));
+        }
+        #[cfg(feature = "generations")]
+        assert_eq!(map.iter_upto_generation(generation).count(), map.len() - 1);
+        assert_eq!(map.entries_in_hash_range(0, u64::MAX).count(), map.len());
+        assert_eq!(
+            map.balanced_segment_salt(hot_keys.iter().map(|k| (k, 100)), 0),
+            salt
+        );
+
+        for i in NUM_VALUES + 1..NUM_VALUES * 4 {
+            assert_eq!(map.insert(i, i), None);
+        }
+
+        assert_eq!(map.remove(&1), Some(1));
+        assert_eq!(map.len(), (NUM_VALUES * 4 - NUM_VALUES / 4 - 1) as usize);
+
+        map.set_segment_salt(0);
+
+        for i in 2..NUM_VALUES * 4 {
+            if i % 4 == 0 && i < NUM_VALUES {
+                assert_eq!(map.get(&i), None);
+            } else {
+                assert_eq!(map.get(&i), Some(i));
+            }
+        }
+
+        let map = Builder::new(8).segment_salt(salt).build();
+        assert_eq!(map.segment_salt(), salt);
+        assert_eq!(map.insert(0, 0), None);
+        assert_eq!(map.get(&0), Some(0));
+    }
+
+    #[test]
+    fn single_segment() {
+        let map = HashMap::with_num_segments(1);
+
+        assert!(map.is_empty());
+        assert_eq!(map.len(), 0);
+
+        assert_eq!(map.insert("foo", 5), None);
+        assert_eq!(map.get("foo"), Some(5));
+
+        assert!(!map.is_empty());
+        assert_eq!(map.len(), 1);
+
+        assert_eq!(map.remove("foo"), Some(5));
+        assert!(map.is_empty());
+        assert_eq!(map.len(), 0);
+    }
+
+    #[test]
+    fn from_hash_sorted_entries() {
+        const NUM_VALUES: i32 = 512;
+
+        let hasher = BuildHasherDefault::<DefaultHasher>::default();
+        let map = HashMap::with_num_segments_and_hasher(8, hasher.clone());
+
+        for i in 0..NUM_VALUES {
+            assert_eq!(map.insert(i, i), None);
+        }
+
+        let entries = map.snapshot().into_hashed_entries();
+        let loaded = HashMap::from_hash_sorted_entries(4, entries, hasher.clone()).unwrap();
+        assert_eq!(loaded.len(), NUM_VALUES as usize);
+
+        for i in 0..NUM_VALUES {
+            assert_eq!(loaded.get(&i), Some(i));
+        }
+
+        assert_eq!(loaded.insert(NUM_VALUES, NUM_VALUES), None);
+        assert_eq!(loaded.remove(&0), Some(0));
+        assert_eq!(loaded.len(), NUM_VALUES as usize);
+
+        let mut entries = map.snapshot().into_hashed_entries();
+        let index = entries.iter().position(|&(_, k, _)| k == 0).unwrap();
+        entries.insert(index + 1, (entries[index].0, 0, NUM_VALUES));
+        let loaded = HashMap::from_hash_sorted_entries(4, entries, hasher.clone()).unwrap();
+        assert_eq!(loaded.len(), NUM_VALUES as usize);
+        assert_eq!(loaded.get(&0), Some(NUM_VALUES));
+
+        let mut entries = map.snapshot().into_hashed_entries();
+        entries.swap(0, 1);
+        assert_eq!(
+            HashMap::from_hash_sorted_entries(4, entries, hasher.clone()).err(),
+            Some(Error::UnsortedEntries)
+        );
+
+        let entries: Vec<(u64, i32, i32)> = Vec::new();
+        let loaded = HashMap::from_hash_sorted_entries(4, entries, hasher).unwrap();
+        assert!(loaded.is_empty());
     }
 
-    /// If no value corresponds to the key, invoke a default function to insert
-    /// a new key-value pair into the map. Otherwise, modify the existing value
-    /// and return the result of invoking a function with a reference to the
-    /// key-value pair previously corresponding to the supplied key.
-    ///
-    /// `on_insert` may be invoked, even if [`None`] is returned.
-    ///
-    /// `on_modify` will be invoked at least once if [`Some`] is returned. It
-    /// may also be invoked one or more times if [`None`] is returned.
-    ///
-    /// [`Some`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.Some
-    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
-    #[inline]
-    pub fn insert_with_or_modify_entry_and<
-        F: FnOnce() -> V,
-        G: FnMut(&K, &V) -> V,
-        H: FnOnce(&K, &V) -> T,
-        T,
-    >(
-        &self,
-        key: K,
-        on_insert: F,
-        on_modify: G,
-        with_old_entry: H,
-    ) -> Option<T> {
-        let hash = bucket::hash(&self.build_hasher, &key);
+    #[test]
+    fn clear_segment() {
+        const NUM_VALUES: usize = 1024;
 
-        let result = self.bucket_array_ref(hash).insert_with_or_modify_entry_and(
-            key,
-            hash,
-            on_insert,
-            on_modify,
-            with_old_entry,
+        let value_parents: Vec<_> =
+            std::iter::repeat_with(|| std::sync::Arc::new(crate::test_util::DropNotifier::new()))
+                .take(NUM_VALUES)
+                .collect();
+
+        let mut map = HashMap::with_num_segments(4);
+
+        for (i, this_value_parent) in value_parents.iter().enumerate() {
+            let value =
+                crate::test_util::NoisyDropper::new(std::sync::Arc::clone(this_value_parent), i);
+            assert!(map.insert_and(i, value, |_| ()).is_none());
+        }
+
+        // leave a tombstone in the segment to be cleared
+        let removed_key = (0..NUM_VALUES).find(|i| map.segment_index(i) == 1).unwrap();
+        assert!(map.remove_and(&removed_key, |_| ()).is_some());
+        crate::test_util::run_deferred();
+
+        let expected = (0..NUM_VALUES)
+            .filter(|&i| i != removed_key && map.segment_index(&i) == 1)
+            .count();
+        assert!(expected > 0);
+
+        assert_eq!(map.clear_segment(1), expected);
+        assert_eq!(map.len(), NUM_VALUES - 1 - expected);
+        assert_eq!(map.segment_capacity(1), 0);
+
+        for (i, this_value_parent) in value_parents.iter().enumerate() {
+            let in_segment = map.segment_index(&i) == 1;
+            assert_eq!(this_value_parent.was_dropped(), in_segment);
+            assert_eq!(map.get_and(&i, |_| ()).is_some(), !in_segment);
+        }
+
+        assert_eq!(map.clear_segment(1), 0);
+        assert_eq!(
+            map.try_clear_segment(4),
+            Err(Error::SegmentIndexOutOfBounds {
+                index: 4,
+                num_segments: 4
+            })
         );
 
-        if result.is_none() {
-            self.len.fetch_add(1, Ordering::Relaxed);
+        let value_parent = std::sync::Arc::new(crate::test_util::DropNotifier::new());
+        let value = crate::test_util::NoisyDropper::new(value_parent, removed_key);
+        assert!(map.insert_and(removed_key, value, |_| ()).is_none());
+        assert_eq!(map.len(), NUM_VALUES - expected);
+    }
+
+    #[test]
+    fn remove_matching() {
+        const NUM_VALUES: u32 = 4096;
+
+        let map = HashMap::with_num_segments(8);
+
+        for i in 0..NUM_VALUES {
+            assert_eq!(map.insert(i, i), None);
         }
 
-        result
+        let progress = std::sync::Mutex::new(Vec::new());
+        let removed = map.remove_matching_with_progress(
+            |k| k % 3 == 0,
+            |index, removed| progress.lock().unwrap().push((index, removed)),
+        );
+
+        let expected = (0..NUM_VALUES).filter(|k| k % 3 == 0).count();
+        assert_eq!(removed, expected);
+        assert_eq!(map.len(), NUM_VALUES as usize - expected);
+
+        let mut progress = progress.into_inner().unwrap();
+        progress.sort_unstable();
+        assert_eq!(
+            progress.iter().map(|&(index, _)| index).collect::<Vec<_>>(),
+            (0..8).collect::<Vec<_>>()
+        );
+
+        for (index, removed) in progress {
+            let expected = (0..NUM_VALUES)
+                .filter(|k| k % 3 == 0 && map.segment_index(k) == index)
+                .count();
+            assert_eq!(removed, expected);
+        }
+
+        for i in 0..NUM_VALUES {
+            let expected = if i % 3 == 0 { None } else { Some(i) };
+            assert_eq!(map.get(&i), expected);
+        }
+
+        assert_eq!(map.remove_matching(|k| k % 3 == 0), 0);
+
+        let len = map.len();
+        assert_eq!(map.remove_matching(|_| true), len);
+        assert!(map.is_empty());
     }
 
-    /// Modifies the value corresponding to a key, returning a clone of the
-    /// value previously corresponding to that key.
-    #[inline]
-    pub fn modify<F: FnMut(&K, &V) -> V>(&self, key: K, on_modify: F) -> Option<V>
-    where
-        V: Clone,
-    {
-        self.modify_entry_and(key, on_modify, |_, v| v.clone())
+    #[test]
+    fn memory_budget() {
+        const NUM_VALUES: usize = 64;
+
+        let exceeded = Arc::new(AtomicUsize::new(0));
+        let budget = {
+            let exceeded = Arc::clone(&exceeded);
+
+            MemoryBudget::with_listener(NUM_VALUES * 2, move |_| {
+                exceeded.fetch_add(1, ordering::RELAXED);
+            })
+        };
+        let weigh = |_: &usize, v: &usize| *v;
+
+        let mut map = Builder::new(4).memory_budget(budget.clone(), weigh).build();
+        let other = crate::HashMap::builder()
+            .memory_budget(budget.clone(), weigh)
+            .build();
+
+        for i in 0..NUM_VALUES {
+            assert_eq!(map.try_insert(i, 1), Ok(None));
+            assert_eq!(other.try_insert(i, 1), Ok(None));
+        }
+
+        assert_eq!(budget.used(), NUM_VALUES * 2);
+        assert_eq!(
+            map.try_insert(NUM_VALUES, 1),
+            Err(Error::MemoryBudgetExceeded)
+        );
+        assert_eq!(
+            other.try_insert(NUM_VALUES, 1),
+            Err(Error::MemoryBudgetExceeded)
+        );
+        assert_eq!(map.len(), NUM_VALUES);
+        assert_eq!(exceeded.load(ordering::RELAXED), 2);
+
+        assert_eq!(map.insert(NUM_VALUES, 1), None);
+        assert_eq!(exceeded.load(ordering::RELAXED), 3);
+
+        let removed = map.remove_matching(|k| k % 2 == 0);
+        assert_eq!(budget.used(), NUM_VALUES * 2 + 1 - removed);
+
+        let index = map.segment_index(&1);
+        let removed = map.clear_segment(index) + removed;
+        assert_eq!(budget.used(), NUM_VALUES * 2 + 1 - removed);
+        assert_eq!(map.len(), NUM_VALUES + 1 - removed);
+
+        drop(map);
+        assert_eq!(budget.used(), NUM_VALUES);
+
+        drop(other);
+        assert_eq!(budget.used(), 0);
     }
 
-    /// Modifies the value corresponding to a key, returning a clone of the
-    /// key-value pair previously corresponding to that key.
-    #[inline]
-    pub fn modify_entry<F: FnMut(&K, &V) -> V>(&self, key: K, on_modify: F) -> Option<(K, V)>
-    where
-        K: Clone,
-        V: Clone,
-    {
-        self.modify_entry_and(key, on_modify, |k, v| (k.clone(), v.clone()))
+    #[test]
+    fn export_import_segment() {
+        const NUM_VALUES: u64 = 512;
+
+        let encode = |k: &u64, v: &u64, buf: &mut Vec<u8>| {
+            buf.extend_from_slice(&k.to_le_bytes());
+            buf.extend_from_slice(&v.to_le_bytes());
+        };
+        use std::convert::TryInto;
+
+        let decode = |bytes: &[u8]| {
+            let (k, v) = bytes.split_at(8);
+
+            Ok((
+                u64::from_le_bytes(k.try_into().unwrap()),
+                u64::from_le_bytes(v.try_into().unwrap()),
+            ))
+        };
+
+        let old = HashMap::with_num_segments(8);
+        let new = HashMap::with_num_segments(2);
+
+        for i in 0..NUM_VALUES {
+            assert_eq!(old.insert(i, i * 2), None);
+        }
+
+        let mut exported = 0;
+
+        for index in 0..old.num_segments() {
+            let mut stream = Vec::new();
+            let num_entries = old.export_segment(index, &mut stream, encode).unwrap();
+            exported += num_entries;
+
+            let import = new.import_segment(stream.as_slice(), decode).unwrap();
+            assert_eq!(import.segment_index(), index);
+            assert_eq!(import.num_segments(), 8);
+            assert_eq!(import.num_entries(), num_entries);
+            assert_eq!(new.len(), exported);
+        }
+
+        assert_eq!(exported, NUM_VALUES as usize);
+
+        for i in 0..NUM_VALUES {
+            assert_eq!(new.get(&i), Some(i * 2));
+        }
+
+        let e = old.export_segment(8, Vec::new(), encode).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+
+        let mut stream = Vec::new();
+        old.export_segment(0, &mut stream, encode).unwrap();
+        let e = new
+            .import_segment(stream.as_slice(), |_| {
+                Err(io::Error::new(io::ErrorKind::InvalidData, "decode"))
+            })
+            .unwrap_err();
+        assert_eq!(e.to_string(), "decode");
     }
 
-    /// Modifies the value corresponding to a key, returning the result of
-    /// invoking a function with a reference to the value previously
-    /// corresponding to the key.
-    #[inline]
-    pub fn modify_and<F: FnMut(&K, &V) -> V, G: FnOnce(&V) -> T, T>(
-        &self,
-        key: K,
-        on_modify: F,
-        with_old_value: G,
-    ) -> Option<T> {
-        self.modify_entry_and(key, on_modify, move |_, v| with_old_value(v))
+    #[test]
+    fn get_or_load() {
+        let map = Builder::new(4)
+            .loader(|k: &u32| if *k < 4 { Some(k * 2) } else { None })
+            .build();
+
+        assert_eq!(map.insert(2, 0), None);
+
+        for i in 0..8 {
+            let expected = match i {
+                2 => Some(0),
+                _ if i < 4 => Some(i * 2),
+                _ => None,
+            };
+            assert_eq!(map.get_or_load(&i), expected);
+        }
+
+        assert_eq!(map.len(), 4);
+        assert_eq!(map.get(&3), Some(6));
+        assert_eq!(HashMap::with_num_segments(1).get_or_load(&0), None::<u32>);
     }
 
-    /// Modifies the value corresponding to a key, returning the result of
-    /// invoking a function with a reference to the key-value pair previously
-    /// corresponding to the supplied key.
-    #[inline]
-    pub fn modify_entry_and<F: FnMut(&K, &V) -> V, G: FnOnce(&K, &V) -> T, T>(
-        &self,
-        key: K,
-        on_modify: F,
-        with_old_entry: G,
-    ) -> Option<T> {
-        let hash = bucket::hash(&self.build_hasher, &key);
+    #[test]
+    fn lookups_skip_segments_without_the_key() {
+        const NUM_SEGMENTS: usize = 64;
+        const NUM_VALUES: i32 = 1024;
 
-        self.bucket_array_ref(hash)
-            .modify_entry_and(key, hash, on_modify, with_old_entry)
+        let mut map = HashMap::with_num_segments(NUM_SEGMENTS);
+
+        for i in 0..NUM_VALUES {
+            assert_eq!(map.get(&i), None);
+            assert_eq!(map.remove(&i), None);
+            assert_eq!(map.modify(i, |_, v| v + 1), None);
+        }
+
+        // no lookup allocated a bucket pointer array
+        assert!((0..NUM_SEGMENTS).all(|i| map.segment_capacity(i) == 0));
+
+        assert_eq!(map.insert(0, 0), None);
+        let index = map.segment_index(&0);
+        assert_eq!(map.get(&0), Some(0));
+        assert!(map.segment_capacity(index) > 0);
+        assert_eq!(map.clear_segment(index), 1);
+
+        for i in (0..NUM_VALUES).filter(|i| map.segment_index(i) == index) {
+            assert_eq!(map.get(&i), None);
+        }
+
+        assert_eq!(map.segment_capacity(index), 0);
     }
-}
 
-#[cfg(feature = "num-cpus")]
-impl<K, V, S: Default> Default for HashMap<K, V, S> {
-    fn default() -> Self {
-        HashMap::with_num_segments_capacity_and_hasher(default_num_segments(), 0, S::default())
+    #[test]
+    fn sampled_segments() {
+        use crate::metrics::OperationSample;
+        use std::sync::Mutex;
+
+        let samples: Arc<Mutex<Vec<OperationSample>>> = Arc::default();
+        let map = {
+            let samples = Arc::clone(&samples);
+
+            Builder::new(8)
+                .sample_operations(1, move |s| samples.lock().unwrap().push(*s))
+                .build()
+        };
+
+        for i in 0..256 {
+            map.insert(i, i);
+        }
+
+        let samples = samples.lock().unwrap();
+        assert_eq!(samples.len(), 256);
+
+        for (i, sample) in samples.iter().enumerate() {
+            assert_eq!(sample.segment(), map.segment_index(&(i as i32)));
+        }
+
+        assert!((0..8).all(|j| samples.iter().any(|s| s.segment() == j)));
     }
-}
 
-impl<K, V, S> Drop for HashMap<K, V, S> {
-    fn drop(&mut self) {
-        let guard = unsafe { &crossbeam_epoch::unprotected() };
-        atomic::fence(Ordering::Acquire);
+    #[test]
+    fn iter_segment() {
+        const NUM_VALUES: i32 = 1024;
 
-        for Segment {
-            bucket_array: this_bucket_array,
-            ..
-        } in self.segments.iter()
-        {
-            let mut current_ptr = this_bucket_array.load(Ordering::Relaxed, guard);
+        let map = HashMap::with_num_segments(8);
 
-            while let Some(current_ref) = unsafe { current_ptr.as_ref() } {
-                let next_ptr = current_ref.next.load(Ordering::Relaxed, guard);
+        for i in 0..NUM_VALUES {
+            map.insert(i, i);
+        }
 
-                for this_bucket_ptr in current_ref
-                    .buckets
-                    .iter()
-                    .map(|b| b.load(Ordering::Relaxed, guard))
-                    .filter(|p| !p.is_null())
-                    .filter(|p| next_ptr.is_null() || p.tag() & bucket::TOMBSTONE_TAG == 0)
-                {
-                    // only delete tombstones from the newest bucket array
-                    // the only way this becomes a memory leak is if there was a panic during a rehash,
-                    // in which case i'm going to say that running destructors and freeing memory is
-                    // best-effort, and my best effort is to not do it
-                    unsafe { bucket::defer_acquire_destroy(guard, this_bucket_ptr) };
-                }
+        let mut seen = Vec::new();
 
-                unsafe { bucket::defer_acquire_destroy(guard, current_ptr) };
+        for index in 0..map.num_segments() {
+            let mut keys: Vec<_> = map.iter_segment(index).map(|r| *r.key()).collect();
+            assert!(keys.iter().all(|k| map.segment_index(k) == index));
 
-                current_ptr = next_ptr;
-            }
+            let mut visited = Vec::new();
+            map.for_each_in_segment(index, |k, v| {
+                assert_eq!(k, v);
+                visited.push(*k);
+            });
+
+            keys.sort_unstable();
+            visited.sort_unstable();
+            assert_eq!(keys, visited);
+
+            seen.extend(keys);
         }
+
+        seen.sort_unstable();
+        assert_eq!(seen, (0..NUM_VALUES).collect::<Vec<_>>());
+
+        assert!(matches!(
+            map.try_iter_segment(8),
+            Err(Error::SegmentIndexOutOfBounds {
+                index: 8,
+                num_segments: 8
+            })
+        ));
+        assert!(map
+            .try_for_each_in_segment(8, |_, _| unreachable!())
+            .is_err());
+
+        crate::test_util::run_deferred();
     }
-}
 
-impl<K, V, S> HashMap<K, V, S> {
-    #[inline]
-    fn bucket_array_ref(&'_ self, hash: u64) -> BucketArrayRef<'_, K, V, S> {
-        let index = self.segment_index_from_hash(hash);
+    #[test]
+    #[should_panic]
+    fn iter_segment_out_of_bounds() {
+        HashMap::<i32, i32>::with_num_segments(2).iter_segment(2);
+    }
 
-        let Segment {
-            ref bucket_array,
-            ref len,
-        } = self.segments[index];
+    #[test]
+    fn bucket_array_introspection() {
+        let map = HashMap::with_num_segments(4);
+        assert_eq!(map.segment_shift(), 62);
+        assert_eq!(
+            HashMap::<i32, i32>::with_num_segments(1).segment_shift(),
+            64
+        );
+        assert_eq!(map.bucket_array_generation(0), 0);
 
-        BucketArrayRef {
-            bucket_array,
-            build_hasher: &self.build_hasher,
-            len,
+        for i in 0..1000 {
+            map.insert(i, i);
         }
+
+        let generations: Vec<_> = (0..4).map(|i| map.bucket_array_generation(i)).collect();
+        assert!(generations.iter().all(|&g| g > 0));
+
+        let estimate = map.keys_per_slot_estimate();
+        assert!(estimate > 0.0 && estimate <= 0.5);
+
+        let mut map = map;
+        map.clear_segment(0);
+        assert_eq!(map.bucket_array_generation(0), 0);
+        assert_eq!(map.bucket_array_generation(1), generations[1]);
+
+        assert!(matches!(
+            map.try_bucket_array_generation(4),
+            Err(Error::SegmentIndexOutOfBounds {
+                index: 4,
+                num_segments: 4
+            })
+        ));
+
+        crate::test_util::run_deferred();
     }
 
-    #[inline]
-    fn segment_index_from_hash(&'_ self, hash: u64) -> usize {
-        if self.segment_shift == 64 {
-            0
-        } else {
-            (hash >> self.segment_shift) as usize
+    #[test]
+    fn fold_segments() {
+        let map = HashMap::with_num_segments(8);
+
+        for i in 0..1000 {
+            map.insert(i, i);
         }
+
+        let num_partial_results = AtomicUsize::new(0);
+        let (sum, len) = map.fold_segments(
+            || {
+                num_partial_results.fetch_add(1, ordering::RELAXED);
+
+                (0, 0)
+            },
+            |(sum, len), k, v| {
+                assert_eq!(k, v);
+                *sum += v;
+                *len += 1;
+            },
+            |(sum, len), (other_sum, other_len)| {
+                *sum += other_sum;
+                *len += other_len;
+            },
+        );
+
+        assert_eq!(num_partial_results.into_inner(), 8);
+        assert_eq!(sum, (0..1000).sum());
+        assert_eq!(len, 1000);
+
+        let map = HashMap::<i32, i32>::with_num_segments(1);
+        assert_eq!(map.fold_segments(|| 7, |_, _, _| (), |a, b| *a += b), 7);
+
+        crate::test_util::run_deferred();
     }
-}
 
-struct Segment<K, V> {
-    bucket_array: Atomic<BucketArray<K, V>>,
-    len: AtomicUsize,
-}
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_fold_segments() {
+        let map = HashMap::with_num_segments(8);
 
-#[cfg(feature = "num-cpus")]
-fn default_num_segments() -> usize {
-    num_cpus::get() * 2
-}
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
 
-#[cfg(test)]
-mod tests {
-    use crate::write_test_cases_for_me;
+        let (sum, len) = map.par_fold_segments(
+            || (0, 0),
+            |(sum, len), k, v| {
+                assert_eq!(k, v);
+                *sum += v;
+                *len += 1;
+            },
+            |(sum, len), (other_sum, other_len)| {
+                *sum += other_sum;
+                *len += other_len;
+            },
+        );
 
-    use super::*;
+        assert_eq!(sum, (0..1000).sum());
+        assert_eq!(len, 1000);
 
-    write_test_cases_for_me!(HashMap);
+        let map = HashMap::<i32, i32>::with_num_segments(1);
+        assert_eq!(map.par_fold_segments(|| 7, |_, _, _| (), |a, b| *a += b), 7);
 
+        crate::test_util::run_deferred();
+    }
+
+    #[cfg(feature = "rayon")]
     #[test]
-    fn single_segment() {
-        let map = HashMap::with_num_segments(1);
+    fn par_drain() {
+        let map = HashMap::with_num_segments(8);
 
+        for i in 0..10_000 {
+            map.insert(i, i);
+        }
+
+        let sum = AtomicUsize::new(0);
+        let num_drained = map.par_drain_and(|k, v| {
+            assert_eq!(k, v);
+            sum.fetch_add(*v, ordering::RELAXED);
+        });
+
+        assert_eq!(num_drained, 10_000);
+        assert_eq!(sum.into_inner(), (0..10_000).sum());
         assert!(map.is_empty());
-        assert_eq!(map.len(), 0);
+        assert_eq!(map.par_drain_and(|_, _| panic!()), 0);
 
-        assert_eq!(map.insert("foo", 5), None);
-        assert_eq!(map.get("foo"), Some(5));
+        for i in 0..10_000 {
+            map.insert(i, i);
+        }
 
-        assert!(!map.is_empty());
-        assert_eq!(map.len(), 1);
+        let mut entries = map.par_drain();
+        entries.sort_unstable();
 
-        assert_eq!(map.remove("foo"), Some(5));
+        assert_eq!(entries, (0..10_000).map(|i| (i, i)).collect::<Vec<_>>());
         assert!(map.is_empty());
-        assert_eq!(map.len(), 0);
+
+        crate::test_util::run_deferred();
     }
 }
@@ -0,0 +1,151 @@
+use super::{DefaultHashBuilder, HashMap};
+use crate::{
+    map::{batch::BatchOperation, bucket},
+    ordering,
+};
+
+use std::hash::{BuildHasher, Hash};
+
+/// A batch of insertions and removals that are applied to a segmented
+/// [`HashMap`] together when the batch is committed.
+///
+/// A `Batch` is created by [`HashMap::batch`]. Its operations are buffered
+/// without touching the map, so none of them is visible to other threads
+/// before [`commit`] is called. Committing hashes nothing, as the keys are
+/// hashed when they are added to the batch, and applies the operations one
+/// segment at a time, in the order of the slots of their keys, under a single
+/// epoch guard. The length of the map is updated once per segment rather than
+/// once per entry.
+///
+/// The operations on the same key are applied in the order they were added to
+/// the batch, but each of them becomes visible to other threads as soon as it
+/// is applied, so a thread that reads the map while a batch is being committed
+/// may observe some of its operations and not others.
+///
+/// [`HashMap`]: struct.HashMap.html
+/// [`HashMap::batch`]: struct.HashMap.html#method.batch
+/// [`commit`]: #method.commit
+///
+/// # Examples
+///
+/// ```rust
+/// use moka_cht::SegmentedHashMap;
+///
+/// let map = SegmentedHashMap::with_num_segments(4);
+/// map.insert(0, "zero");
+///
+/// let mut batch = map.batch();
+/// batch.insert(1, "one");
+/// batch.insert(2, "two");
+/// batch.remove(0);
+/// assert_eq!(map.len(), 1);
+///
+/// batch.commit();
+/// assert_eq!(map.len(), 2);
+/// assert_eq!(map.get(&0), None);
+/// assert_eq!(map.get(&2), Some("two"));
+/// ```
+pub struct Batch<'a, K, V, S = DefaultHashBuilder> {
+    map: &'a HashMap<K, V, S>,
+    operations: Vec<(u64, BatchOperation<K, V>)>,
+}
+
+impl<'a, K, V, S> Batch<'a, K, V, S> {
+    pub(crate) fn new(map: &'a HashMap<K, V, S>) -> Self {
+        Self {
+            map,
+            operations: Vec::new(),
+        }
+    }
+
+    /// Returns the number of operations in the batch.
+    pub fn len(&self) -> usize {
+        self.operations.len()
+    }
+
+    /// Returns `true` if the batch contains no operations.
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> Batch<'a, K, V, S> {
+    /// Adds the insertion of a key-value pair to the batch. When the batch is
+    /// committed, the value replaces the one previously corresponding to the
+    /// key, if any.
+    pub fn insert(&mut self, key: K, value: V) -> &mut Self {
+        let hash = bucket::hash(&self.map.build_hasher, &key);
+        self.operations
+            .push((hash, BatchOperation::Insert(key, value)));
+
+        self
+    }
+
+    /// Adds the removal of a key to the batch. Committing the batch does
+    /// nothing for this key if no value corresponds to it by then.
+    pub fn remove(&mut self, key: K) -> &mut Self {
+        let hash = bucket::hash(&self.map.build_hasher, &key);
+        self.operations.push((hash, BatchOperation::Remove(key)));
+
+        self
+    }
+
+    /// Applies the operations of the batch to the map.
+    pub fn commit(self) {
+        let map = self.map;
+
+        // the operations pin the thread again, which is cheap while it is
+        // already pinned
+        let guard = &crossbeam_epoch::pin();
+        let masks: Vec<usize> = map
+            .segments
+            .iter()
+            .map(|segment| {
+                unsafe { ordering::load_consume(&segment.bucket_array, guard).as_ref() }
+                    .map_or(0, |array| array.buckets.len() - 1)
+            })
+            .collect();
+
+        let mut operations: Vec<_> = self
+            .operations
+            .into_iter()
+            .map(|(hash, operation)| (map.segment_index_from_hash(hash), hash, operation))
+            .collect();
+        // the sort is stable, so the operations on the same key stay in order
+        operations.sort_by_key(|&(index, hash, _)| (index, hash as usize & masks[index]));
+
+        let mut operations = operations.into_iter().peekable();
+
+        while let Some(&(index, _, _)) = operations.peek() {
+            let bucket_array_ref = map.segment_bucket_array_ref(index);
+            let mut inserted = 0;
+            let mut removed = 0;
+
+            while let Some((_, hash, operation)) = operations.next_if(|&(i, _, _)| i == index) {
+                match operation {
+                    BatchOperation::Insert(key, value) => {
+                        if bucket_array_ref
+                            .insert_entry_and(key, hash, value, |_, _| ())
+                            .is_none()
+                        {
+                            inserted += 1;
+                        }
+                    }
+                    BatchOperation::Remove(key) => {
+                        if bucket_array_ref
+                            .remove_entry_if_and(&key, hash, |_, _| true, |_, _| ())
+                            .is_some()
+                        {
+                            removed += 1;
+                        }
+                    }
+                }
+            }
+
+            // the removals only remove entries that were counted, so applying
+            // the insertions first never makes the length underflow
+            map.len.fetch_add(inserted, ordering::RELAXED);
+            map.len.fetch_sub(removed, ordering::RELAXED);
+        }
+    }
+}
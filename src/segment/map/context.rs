@@ -0,0 +1,284 @@
+use super::HashMap;
+use crate::{
+    map::{bucket, bucket_array_ref::BucketArrayRef, DefaultHashBuilder},
+    ordering, Error,
+};
+
+use std::{
+    any::Any,
+    borrow::Borrow,
+    fmt::{self, Debug, Formatter},
+    hash::{BuildHasher, Hash},
+};
+
+/// A view of a segmented [`HashMap`] that passes a context to the hooks of the map for
+/// the operations performed through it.
+///
+/// A `WithContext` is created by [`HashMap::with_context`]. The context is an
+/// opaque reference, for example to the ID of the request that performs the
+/// operations, which is passed as is to the callback set with
+/// [`Builder::sample_operations_with_context`] and to the listener set with
+/// [`MemoryBudget::with_context_listener`], so that they can attribute the
+/// operations to their callers without thread-locals. The operations performed
+/// through the map itself pass no context.
+///
+/// [`HashMap`]: ./struct.HashMap.html
+/// [`HashMap::with_context`]: ./struct.HashMap.html#method.with_context
+/// [`Builder::sample_operations_with_context`]: ./struct.Builder.html#method.sample_operations_with_context
+/// [`MemoryBudget::with_context_listener`]: ../../map/struct.MemoryBudget.html#method.with_context_listener
+///
+/// # Examples
+///
+/// ```rust
+/// use moka_cht::segment::map::Builder;
+///
+/// use std::sync::{Arc, Mutex};
+///
+/// let sampled = Arc::new(Mutex::new(Vec::new()));
+/// let map = {
+///     let sampled = Arc::clone(&sampled);
+///
+///     Builder::new(4)
+///         .sample_operations_with_context(1, move |_, context| {
+///             let request_id = context.and_then(|c| c.downcast_ref::<u64>());
+///             sampled.lock().unwrap().push(request_id.copied());
+///         })
+///         .build()
+/// };
+///
+/// map.with_context(&42u64).insert("a", 1);
+/// map.insert("b", 2);
+///
+/// assert_eq!(*sampled.lock().unwrap(), [Some(42), None]);
+/// ```
+pub struct WithContext<'a, K, V, S = DefaultHashBuilder> {
+    map: &'a HashMap<K, V, S>,
+    context: &'a dyn Any,
+}
+
+impl<'a, K, V, S> WithContext<'a, K, V, S> {
+    pub(crate) fn new(map: &'a HashMap<K, V, S>, context: &'a dyn Any) -> Self {
+        Self { map, context }
+    }
+
+    /// Returns the map.
+    pub fn map(&self) -> &'a HashMap<K, V, S> {
+        self.map
+    }
+
+    /// Returns the context passed to the hooks of the map.
+    pub fn context(&self) -> &'a dyn Any {
+        self.context
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> WithContext<'_, K, V, S> {
+    /// Returns a clone of the value corresponding to the key.
+    ///
+    /// See [`HashMap::get`].
+    ///
+    /// [`HashMap::get`]: ./struct.HashMap.html#method.get
+    pub fn get<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        V: Clone,
+    {
+        self.get_and(key, V::clone)
+    }
+
+    /// Returns the result of invoking a function with a reference to the
+    /// value corresponding to the key.
+    ///
+    /// See [`HashMap::get_and`].
+    ///
+    /// [`HashMap::get_and`]: ./struct.HashMap.html#method.get_and
+    pub fn get_and<Q: Hash + Eq + ?Sized, F: FnOnce(&V) -> T, T>(
+        &self,
+        key: &Q,
+        with_value: F,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+    {
+        let hash = bucket::hash(&self.map.build_hasher, &key);
+
+        self.bucket_array_ref(hash)
+            .get_key_value_and(key, hash, move |_, v| with_value(v))
+    }
+
+    /// Inserts a key-value pair into the map, returning a clone of the value
+    /// previously corresponding to the key.
+    ///
+    /// See [`HashMap::insert`].
+    ///
+    /// [`HashMap::insert`]: ./struct.HashMap.html#method.insert
+    pub fn insert(&self, key: K, value: V) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.insert_and(key, value, V::clone)
+    }
+
+    /// Inserts a key-value pair into the map, returning the result of invoking
+    /// a function with a reference to the value previously corresponding to
+    /// the key.
+    ///
+    /// See [`HashMap::insert_and`].
+    ///
+    /// [`HashMap::insert_and`]: ./struct.HashMap.html#method.insert_and
+    pub fn insert_and<F: FnOnce(&V) -> T, T>(
+        &self,
+        key: K,
+        value: V,
+        with_previous_value: F,
+    ) -> Option<T> {
+        let hash = bucket::hash(&self.map.build_hasher, &key);
+
+        let result = self
+            .bucket_array_ref(hash)
+            .insert_entry_and(key, hash, value, move |_, v| with_previous_value(v));
+        self.count_insertion(result.is_none());
+
+        result
+    }
+
+    /// Inserts a key-value pair into the map, unless the weight of the pair
+    /// does not fit in the memory budget of the map.
+    ///
+    /// See [`HashMap::try_insert`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MemoryBudgetExceeded`] if charging the weight of the
+    /// pair would exceed the limit of the memory budget of the map.
+    ///
+    /// [`HashMap::try_insert`]: ./struct.HashMap.html#method.try_insert
+    /// [`Error::MemoryBudgetExceeded`]: ../enum.Error.html#variant.MemoryBudgetExceeded
+    pub fn try_insert(&self, key: K, value: V) -> Result<Option<V>, Error>
+    where
+        V: Clone,
+    {
+        let hash = bucket::hash(&self.map.build_hasher, &key);
+
+        let result =
+            self.bucket_array_ref(hash)
+                .try_insert_entry_and(key, hash, value, |_, v| v.clone())?;
+        self.count_insertion(result.is_none());
+
+        Ok(result)
+    }
+
+    /// Inserts a value for a key if it is absent, or else replaces its value
+    /// with the result of `on_modify`.
+    ///
+    /// See [`HashMap::insert_or_modify`].
+    ///
+    /// [`HashMap::insert_or_modify`]: ./struct.HashMap.html#method.insert_or_modify
+    pub fn insert_or_modify<F: FnMut(&K, &V) -> V>(
+        &self,
+        key: K,
+        value: V,
+        on_modify: F,
+    ) -> Option<V>
+    where
+        V: Clone,
+    {
+        let hash = bucket::hash(&self.map.build_hasher, &key);
+
+        let result = self.bucket_array_ref(hash).insert_with_or_modify_entry_and(
+            key,
+            hash,
+            move |_| value,
+            on_modify,
+            |_, v| v.clone(),
+        );
+        self.count_insertion(result.is_none());
+
+        result
+    }
+
+    /// Modifies the value corresponding to a key, returning a clone of the
+    /// value previously corresponding to that key.
+    ///
+    /// See [`HashMap::modify`].
+    ///
+    /// [`HashMap::modify`]: ./struct.HashMap.html#method.modify
+    pub fn modify<F: FnMut(&K, &V) -> V>(&self, key: K, on_modify: F) -> Option<V>
+    where
+        V: Clone,
+    {
+        let hash = bucket::hash(&self.map.build_hasher, &key);
+
+        self.bucket_array_ref(hash)
+            .modify_entry_and(key, hash, on_modify, |_, v| v.clone())
+    }
+
+    /// Removes a key from the map, returning a clone of the value previously
+    /// corresponding to the key.
+    ///
+    /// See [`HashMap::remove`].
+    ///
+    /// [`HashMap::remove`]: ./struct.HashMap.html#method.remove
+    pub fn remove<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        V: Clone,
+    {
+        self.remove_and(key, V::clone)
+    }
+
+    /// Removes a key from the map, returning the result of invoking a function
+    /// with a reference to the value previously corresponding to the key.
+    ///
+    /// See [`HashMap::remove_and`].
+    ///
+    /// [`HashMap::remove_and`]: ./struct.HashMap.html#method.remove_and
+    pub fn remove_and<Q: Hash + Eq + ?Sized, F: FnOnce(&V) -> T, T>(
+        &self,
+        key: &Q,
+        with_previous_value: F,
+    ) -> Option<T>
+    where
+        K: Borrow<Q>,
+    {
+        let hash = bucket::hash(&self.map.build_hasher, &key);
+
+        self.bucket_array_ref(hash).remove_entry_if_and(
+            key,
+            hash,
+            |_, _| true,
+            move |_, v| {
+                self.map.len.fetch_sub(1, ordering::RELAXED);
+
+                with_previous_value(v)
+            },
+        )
+    }
+
+    fn bucket_array_ref(&self, hash: u64) -> BucketArrayRef<'_, K, V, S> {
+        let mut bucket_array_ref = self.map.bucket_array_ref(hash);
+        bucket_array_ref.context = Some(self.context);
+
+        bucket_array_ref
+    }
+
+    fn count_insertion(&self, inserted: bool) {
+        if inserted {
+            self.map.len.fetch_add(1, ordering::RELAXED);
+        }
+    }
+}
+
+impl<K, V, S> Clone for WithContext<'_, K, V, S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K, V, S> Copy for WithContext<'_, K, V, S> {}
+
+impl<K, V, S> Debug for WithContext<'_, K, V, S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WithContext").finish_non_exhaustive()
+    }
+}
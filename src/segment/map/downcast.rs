@@ -0,0 +1,94 @@
+use super::HashMap;
+
+use crate::{any_map::AsAny, map::bucket};
+
+use std::{
+    any::Any,
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+};
+
+/// Typed views of maps over boxed trait objects whose traits have [`AsAny`]
+/// as a supertrait. See [`AsAny`] for an example.
+///
+/// [`AsAny`]: ../../any_map/trait.AsAny.html
+impl<K: Hash + Eq, D: AsAny + ?Sized, S: BuildHasher> HashMap<K, Box<D>, S> {
+    /// Returns a clone of the value corresponding to the key if it is of type
+    /// `T`.
+    ///
+    /// Returns `None` if the map does not contain the key or if the value is
+    /// of a different type.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn get_as<Q: Hash + Eq + ?Sized, T: Any + Clone>(&self, key: &Q) -> Option<T>
+    where
+        K: Borrow<Q>,
+    {
+        self.get_as_and(key, T::clone)
+    }
+
+    /// Returns the result of invoking a function with a reference to the value
+    /// corresponding to the key if it is of type `T`.
+    ///
+    /// Returns `None` if the map does not contain the key or if the value is
+    /// of a different type, in which case `with_value` is not invoked.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn get_as_and<Q: Hash + Eq + ?Sized, T: Any, F: FnOnce(&T) -> R, R>(
+        &self,
+        key: &Q,
+        with_value: F,
+    ) -> Option<R>
+    where
+        K: Borrow<Q>,
+    {
+        self.get_and(key, |v| (**v).as_any().downcast_ref().map(with_value))
+            .flatten()
+    }
+
+    /// Modifies the value corresponding to a key if it is of type `T`,
+    /// returning `true` if it was modified.
+    ///
+    /// If the value is of a different type, it is left unchanged and
+    /// `on_modify` is not invoked.
+    ///
+    /// `on_modify` may be invoked multiple times, even if `false` is returned.
+    pub fn modify_as<T: Any, F: FnMut(&K, &T) -> Box<D>>(&self, key: K, on_modify: F) -> bool {
+        self.modify_as_and(key, on_modify, |_| ()).is_some()
+    }
+
+    /// Modifies the value corresponding to a key if it is of type `T`,
+    /// returning the result of invoking a function with a reference to the
+    /// value previously corresponding to the key.
+    ///
+    /// If the value is of a different type, it is left unchanged, neither
+    /// function is invoked, and `None` is returned.
+    ///
+    /// `on_modify` may be invoked multiple times, even if `None` is returned.
+    pub fn modify_as_and<T: Any, F: FnMut(&K, &T) -> Box<D>, G: FnOnce(&T) -> R, R>(
+        &self,
+        key: K,
+        mut on_modify: F,
+        with_old_value: G,
+    ) -> Option<R> {
+        let hash = bucket::hash(&self.build_hasher, &key);
+
+        self.bucket_array_ref(hash).try_modify_entry_and(
+            key,
+            hash,
+            |k, v| (**v).as_any().downcast_ref().map(|t| on_modify(k, t)),
+            // the old value is the one that `on_modify` was invoked with
+            |_, v| with_old_value((**v).as_any().downcast_ref().unwrap()),
+        )
+    }
+}
@@ -0,0 +1,207 @@
+use super::{DefaultHashBuilder, HashMap};
+use crate::{
+    map::{
+        bucket::{self, Stamp},
+        bucket_array_ref::fingerprint_bit,
+        exclusive, TombstonePolicy,
+    },
+    ordering,
+};
+
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+};
+
+/// A view of a segmented [`HashMap`] that is borrowed exclusively, which
+/// inserts and removes entries without synchronizing with other threads.
+///
+/// An `Exclusive` view is created by [`HashMap::exclusive`]. As long as it
+/// exists, no other thread can access the map, so insertions write the bucket
+/// pointer arrays of the segments with plain loads and stores instead of
+/// compare-and-swap loops, and replace the values of existing entries in
+/// place. Removals do not leave tombstones behind: the entries that follow a
+/// removed one are shifted back into its slot. If the tombstone policy of the
+/// map is [`BackwardShiftInExclusiveMode`], creating the view also reclaims the
+/// tombstones left by the removals that were performed while the map was
+/// shared, in the same way. The length of the map and of the segments are
+/// updated without atomic read-modify-write operations either.
+///
+/// [`HashMap`]: struct.HashMap.html
+/// [`HashMap::exclusive`]: struct.HashMap.html#method.exclusive
+/// [`BackwardShiftInExclusiveMode`]: ../../map/enum.TombstonePolicy.html#variant.BackwardShiftInExclusiveMode
+///
+/// # Examples
+///
+/// ```rust
+/// use moka_cht::SegmentedHashMap;
+///
+/// let mut map = SegmentedHashMap::with_num_segments(4);
+///
+/// let mut exclusive = map.exclusive();
+///
+/// for i in 0..1024 {
+///     exclusive.insert(i, i * 2);
+/// }
+///
+/// for i in 0..512 {
+///     assert_eq!(exclusive.remove(&i), Some(i * 2));
+/// }
+///
+/// assert_eq!(exclusive.get(&768), Some(&1536));
+/// assert_eq!(map.len(), 512);
+/// ```
+pub struct Exclusive<'a, K, V, S = DefaultHashBuilder> {
+    map: &'a mut HashMap<K, V, S>,
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> Exclusive<'a, K, V, S> {
+    pub(crate) fn new(map: &'a mut HashMap<K, V, S>) -> Self {
+        for index in 0..map.segments.len() {
+            exclusive::settle(map.segment_bucket_array_ref(index));
+        }
+
+        if map.tombstone_policy == TombstonePolicy::BackwardShiftInExclusiveMode {
+            for segment in map.segments.iter_mut() {
+                exclusive::purge_tombstones(&mut segment.bucket_array, &map.build_hasher);
+            }
+        }
+
+        Self { map }
+    }
+}
+
+impl<'a, K, V, S> Exclusive<'a, K, V, S> {
+    /// Returns the number of elements in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the map contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> Exclusive<'a, K, V, S> {
+    /// Returns a reference to the value corresponding to the key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn get<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        let hash = bucket::hash(&self.map.build_hasher, key);
+        let segment = &self.map.segments[self.map.segment_index_from_hash(hash)];
+        let access = self.map.access_clock.load(ordering::RELAXED);
+
+        exclusive::get(&segment.bucket_array, hash, key, access).map(|b| unsafe { b.value() })
+    }
+
+    /// Inserts a key-value pair into the map, returning the value previously
+    /// corresponding to the key.
+    ///
+    /// If the key was already present, its value is replaced in place and the
+    /// key passed in is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bucket pointer array of the segment would need to grow
+    /// past the maximum allocation size.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = bucket::hash(&self.map.build_hasher, &key);
+        let index = self.map.segment_index_from_hash(hash);
+        let map = &mut *self.map;
+        let segment = &mut map.segments[index];
+        let stamp = Stamp {
+            generation: *map.generation.get_mut(),
+            access: *map.access_clock.get_mut(),
+            sequence: if map.sequenced {
+                Some(&segment.sequence)
+            } else {
+                None
+            },
+        };
+        let segment_len = segment.len.get_mut();
+        *segment.fingerprint.get_mut() |= fingerprint_bit(hash);
+
+        exclusive::charge_insert(
+            &segment.bucket_array,
+            map.memory.as_ref(),
+            hash,
+            &key,
+            &value,
+        );
+
+        let previous = exclusive::insert(
+            &mut segment.bucket_array,
+            &map.build_hasher,
+            *segment_len,
+            hash,
+            key,
+            value,
+            stamp,
+        );
+
+        if previous.is_none() {
+            *segment_len += 1;
+            *map.len.get_mut() += 1;
+        }
+
+        previous
+    }
+
+    /// Removes a key from the map, returning the value corresponding to the
+    /// key if the key was previously in the map.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn remove<Q: Hash + Eq + ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        self.remove_entry(key).map(|(_, v)| v)
+    }
+
+    /// Removes a key from the map, returning the stored key and value if the
+    /// key was previously in the map.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn remove_entry<Q: Hash + Eq + ?Sized>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+    {
+        let hash = bucket::hash(&self.map.build_hasher, key);
+        let index = self.map.segment_index_from_hash(hash);
+        let map = &mut *self.map;
+        let segment = &mut map.segments[index];
+
+        let removed =
+            exclusive::remove_entry(&mut segment.bucket_array, &map.build_hasher, hash, key);
+
+        if let Some((k, v)) = &removed {
+            *segment.len.get_mut() -= 1;
+            *map.len.get_mut() -= 1;
+
+            if let Some(memory) = &map.memory {
+                memory.release(k, v);
+            }
+        }
+
+        removed
+    }
+}
@@ -0,0 +1,141 @@
+use super::{bucket, DefaultHashBuilder, HashMap};
+
+use crate::ordering;
+
+use std::hash::{BuildHasher, Hash};
+
+/// A number of slots reserved in each segment of a [`HashMap`], which
+/// guarantees that the insertions made through the token do not grow the
+/// segments.
+///
+/// A `ReservationToken` is created by [`HashMap::reserve_slots`], which grows
+/// the bucket pointer arrays of the segments up front if needed. The segments
+/// of the keys to be inserted are not known in advance, so the slots are
+/// reserved in every segment: the token holds the reserved number of slots in
+/// each segment, and an insertion through it uses a slot of the segment of
+/// its key. The insertions made without the token leave the reserved slots
+/// free, and grow the segments instead of taking them.
+///
+/// An insertion through the token that adds a new key uses one of the slots
+/// of its segment, while an insertion that replaces the value of a key uses
+/// none. Once the slots of a segment are used up, the token inserts into it
+/// like the map does. The slots left are released when the token is dropped.
+///
+/// An insertion through the token may still have to help other threads finish
+/// a resize that they started, or grow a segment if the removals of other
+/// keys have left no free slot behind them.
+///
+/// [`HashMap`]: struct.HashMap.html
+/// [`HashMap::reserve_slots`]: struct.HashMap.html#method.reserve_slots
+///
+/// # Examples
+///
+/// ```rust
+/// use moka_cht::SegmentedHashMap;
+///
+/// let map = SegmentedHashMap::with_num_segments(4);
+/// let mut token = map.reserve_slots(16);
+/// assert!(map.capacity() >= 16);
+///
+/// for i in 0..16 {
+///     token.insert(i, i);
+/// }
+///
+/// assert_eq!(token.remaining(), 48);
+/// ```
+pub struct ReservationToken<'a, K, V, S = DefaultHashBuilder> {
+    map: &'a HashMap<K, V, S>,
+    // the slots left in each segment
+    remaining: Box<[usize]>,
+}
+
+impl<'a, K, V, S> ReservationToken<'a, K, V, S> {
+    pub(crate) fn new(map: &'a HashMap<K, V, S>, per_segment: usize) -> Self {
+        Self {
+            map,
+            remaining: vec![per_segment; map.segments.len()].into_boxed_slice(),
+        }
+    }
+
+    /// Returns the number of slots left in all segments.
+    pub fn remaining(&self) -> usize {
+        self.remaining.iter().sum()
+    }
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> ReservationToken<'a, K, V, S> {
+    /// Inserts a key-value pair into the map, returning a clone of the value
+    /// previously corresponding to the key.
+    ///
+    /// See [`HashMap::insert`].
+    ///
+    /// [`HashMap::insert`]: struct.HashMap.html#method.insert
+    pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.insert_entry_and(key, value, |_, v| v.clone())
+    }
+
+    /// Inserts a key-value pair into the map, returning the result of invoking
+    /// a function with a reference to the value previously corresponding to
+    /// the key.
+    ///
+    /// See [`HashMap::insert_and`].
+    ///
+    /// [`HashMap::insert_and`]: struct.HashMap.html#method.insert_and
+    pub fn insert_and<F: FnOnce(&V) -> T, T>(
+        &mut self,
+        key: K,
+        value: V,
+        with_previous_value: F,
+    ) -> Option<T> {
+        self.insert_entry_and(key, value, move |_, v| with_previous_value(v))
+    }
+
+    /// Inserts a key-value pair into the map, returning the result of invoking
+    /// a function with a reference to the key-value pair previously
+    /// corresponding to the supplied key.
+    ///
+    /// See [`HashMap::insert_entry_and`].
+    ///
+    /// [`HashMap::insert_entry_and`]: struct.HashMap.html#method.insert_entry_and
+    pub fn insert_entry_and<F: FnOnce(&K, &V) -> T, T>(
+        &mut self,
+        key: K,
+        value: V,
+        with_previous_entry: F,
+    ) -> Option<T> {
+        let hash = bucket::hash(&self.map.build_hasher, &key);
+        let index = self.map.segment_index_from_hash(hash);
+
+        if self.remaining[index] == 0 {
+            return self.map.insert_entry_and(key, value, with_previous_entry);
+        }
+
+        let result = self
+            .map
+            .segment_bucket_array_ref(index)
+            .insert_reserved_entry_and(key, hash, value, with_previous_entry);
+
+        if result.is_none() {
+            self.remaining[index] -= 1;
+            self.map.segments[index]
+                .reserved_slots
+                .fetch_sub(1, ordering::RELAXED);
+            self.map.len.fetch_add(1, ordering::RELAXED);
+        }
+
+        result
+    }
+}
+
+impl<'a, K, V, S> Drop for ReservationToken<'a, K, V, S> {
+    fn drop(&mut self) {
+        for (segment, &remaining) in self.map.segments.iter().zip(self.remaining.iter()) {
+            segment
+                .reserved_slots
+                .fetch_sub(remaining, ordering::RELAXED);
+        }
+    }
+}
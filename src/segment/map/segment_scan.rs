@@ -0,0 +1,127 @@
+use super::HashMap;
+
+use crate::map::{bucket, Iter};
+
+use std::hash::{BuildHasher, Hash};
+
+/// The state of a scan over one segment of a [`HashMap`], which can be moved
+/// to another thread or task.
+///
+/// The states of all segments are created by
+/// [`HashMap::iter_segments_owned`]. A state holds no reference to the map and
+/// no epoch guard, only the position of the scan in its segment, so it is
+/// `Send` and `'static`, and the segments of a map can be scanned in parallel
+/// by separate tasks. Each call to [`next_batch`] pins the current thread only
+/// for as long as the returned iterator is alive, which should therefore be
+/// consumed before the task awaits.
+///
+/// The scan visits the entries in the same order and with the same guarantees
+/// as the scans started with a [`ScanCursor`]: the segment may grow or shrink
+/// between batches, and an entry that is in the map for the whole scan is
+/// returned exactly once.
+///
+/// A state must only be used with the map that created it.
+///
+/// [`HashMap`]: ./struct.HashMap.html
+/// [`HashMap::iter_segments_owned`]: ./struct.HashMap.html#method.iter_segments_owned
+/// [`next_batch`]: #method.next_batch
+/// [`ScanCursor`]: ../../map/struct.ScanCursor.html
+///
+/// # Examples
+///
+/// ```rust
+/// use moka_cht::SegmentedHashMap;
+///
+/// use std::{sync::Arc, thread};
+///
+/// let map = Arc::new(SegmentedHashMap::with_num_segments(4));
+///
+/// for i in 0..1000 {
+///     map.insert(i, i);
+/// }
+///
+/// let threads: Vec<_> = map
+///     .iter_segments_owned()
+///     .into_iter()
+///     .map(|mut scan| {
+///         let map = Arc::clone(&map);
+///
+///         thread::spawn(move || {
+///             let mut sum = 0;
+///
+///             while !scan.is_done() {
+///                 sum += map.scan_segment(&mut scan, 64).map(|r| *r.value()).sum::<i32>();
+///             }
+///
+///             sum
+///         })
+///     })
+///     .collect();
+///
+/// let sum: i32 = threads.into_iter().map(|t| t.join().unwrap()).sum();
+/// assert_eq!(sum, (0..1000).sum());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SegmentScan {
+    segment: usize,
+    position: u64,
+    done: bool,
+}
+
+impl SegmentScan {
+    pub(crate) fn new(segment: usize) -> Self {
+        Self {
+            segment,
+            position: 0,
+            done: false,
+        }
+    }
+
+    /// Returns the index of the segment that is scanned.
+    pub fn segment(&self) -> usize {
+        self.segment
+    }
+
+    /// Returns `true` if the scan is complete.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> HashMap<K, V, S> {
+    /// Returns an iterator over the next entries of the segment scan at
+    /// `scan`, and moves `scan` past them.
+    ///
+    /// At least `limit` entries are returned unless the scan completes, and
+    /// possibly a few more, like [`scan`]. See [`SegmentScan`] for details.
+    ///
+    /// [`scan`]: #method.scan
+    /// [`SegmentScan`]: ./struct.SegmentScan.html
+    ///
+    /// # Panics
+    ///
+    /// Panics if `scan` is for a segment that the map does not have.
+    pub fn scan_segment(&self, scan: &mut SegmentScan, limit: usize) -> Iter<'_, K, V> {
+        assert!(
+            scan.segment < self.segments.len(),
+            "the scan is for a segment that the map does not have"
+        );
+
+        let guard = crossbeam_epoch::pin();
+        let mut buckets = Vec::new();
+
+        if !scan.done {
+            let (segment_buckets, next) =
+                self.segment_bucket_array_ref(scan.segment)
+                    .scan(&guard, scan.position, limit);
+            buckets = bucket::into_ptrs(segment_buckets);
+
+            match next {
+                Some(position) => scan.position = position,
+                None => scan.done = true,
+            }
+        }
+
+        unsafe { Iter::new(guard, buckets) }
+    }
+}
@@ -0,0 +1,241 @@
+use std::io::{self, Read};
+
+// the stream starts with a header holding the magic bytes, the version of the
+// format, the index of the exported segment and the number of segments of the
+// exporting map. each entry follows as a frame made of `ENTRY_TAG`, the
+// sequence number of the entry, the length of its payload and the payload, and
+// the stream ends with `END_TAG` and the number of entries. all integers are
+// little-endian.
+const MAGIC: [u8; 8] = *b"MOKACHT\0";
+const VERSION: u32 = 1;
+const ENTRY_TAG: u8 = 1;
+const END_TAG: u8 = 0;
+
+/// A summary of a segment imported into a segmented [`HashMap`] from a stream
+/// written by [`HashMap::export_segment`].
+///
+/// [`HashMap`]: struct.HashMap.html
+/// [`HashMap::export_segment`]: struct.HashMap.html#method.export_segment
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SegmentImport {
+    segment_index: usize,
+    num_segments: usize,
+    num_entries: usize,
+}
+
+impl SegmentImport {
+    /// Returns the index of the segment in the map that exported it.
+    pub fn segment_index(&self) -> usize {
+        self.segment_index
+    }
+
+    /// Returns the number of segments of the map that exported the segment.
+    pub fn num_segments(&self) -> usize {
+        self.num_segments
+    }
+
+    /// Returns the number of entries imported.
+    pub fn num_entries(&self) -> usize {
+        self.num_entries
+    }
+}
+
+/// Appends the stream of a segment to a buffer.
+pub(crate) struct SegmentWriter {
+    buf: Vec<u8>,
+    next_sequence: u64,
+}
+
+impl SegmentWriter {
+    pub(crate) fn new(segment_index: usize, num_segments: usize) -> Self {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&MAGIC);
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+        buf.extend_from_slice(&(segment_index as u64).to_le_bytes());
+        buf.extend_from_slice(&(num_segments as u64).to_le_bytes());
+
+        Self {
+            buf,
+            next_sequence: 0,
+        }
+    }
+
+    /// Appends the frame of an entry whose payload is written by `encode`.
+    pub(crate) fn entry<F: FnOnce(&mut Vec<u8>)>(&mut self, encode: F) {
+        self.buf.push(ENTRY_TAG);
+        self.buf
+            .extend_from_slice(&self.next_sequence.to_le_bytes());
+
+        let len_start = self.buf.len();
+        self.buf.extend_from_slice(&0u64.to_le_bytes());
+        encode(&mut self.buf);
+
+        let len = (self.buf.len() - len_start - 8) as u64;
+        self.buf[len_start..len_start + 8].copy_from_slice(&len.to_le_bytes());
+        self.next_sequence += 1;
+    }
+
+    /// Ends the stream, returning it and the number of entries in it.
+    pub(crate) fn finish(mut self) -> (Vec<u8>, usize) {
+        self.buf.push(END_TAG);
+        self.buf
+            .extend_from_slice(&self.next_sequence.to_le_bytes());
+
+        (self.buf, self.next_sequence as usize)
+    }
+}
+
+/// Reads the stream of a segment, checking its framing.
+pub(crate) struct SegmentReader<R> {
+    reader: R,
+    segment_index: usize,
+    num_segments: usize,
+    next_sequence: u64,
+    payload: Vec<u8>,
+}
+
+impl<R: Read> SegmentReader<R> {
+    pub(crate) fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+
+        if magic != MAGIC {
+            return Err(invalid_data("not a segment stream"));
+        }
+
+        let mut version = [0; 4];
+        reader.read_exact(&mut version)?;
+
+        if u32::from_le_bytes(version) != VERSION {
+            return Err(invalid_data("unsupported segment stream version"));
+        }
+
+        let segment_index = read_u64(&mut reader)? as usize;
+        let num_segments = read_u64(&mut reader)? as usize;
+
+        if segment_index >= num_segments {
+            return Err(invalid_data("segment index out of bounds"));
+        }
+
+        Ok(Self {
+            reader,
+            segment_index,
+            num_segments,
+            next_sequence: 0,
+            payload: Vec::new(),
+        })
+    }
+
+    /// Returns the payload of the next entry, or `None` at the end of the
+    /// stream.
+    pub(crate) fn next_entry(&mut self) -> io::Result<Option<&[u8]>> {
+        let mut tag = [0; 1];
+        self.reader.read_exact(&mut tag)?;
+
+        let sequence = read_u64(&mut self.reader)?;
+
+        match tag[0] {
+            ENTRY_TAG if sequence == self.next_sequence => {
+                let len = read_u64(&mut self.reader)?;
+
+                // the payload is not allocated up front, so that a corrupt
+                // length cannot exhaust the memory.
+                self.payload.clear();
+                (&mut self.reader)
+                    .take(len)
+                    .read_to_end(&mut self.payload)?;
+
+                if self.payload.len() as u64 != len {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+
+                self.next_sequence += 1;
+
+                Ok(Some(&self.payload))
+            }
+            END_TAG if sequence == self.next_sequence => Ok(None),
+            ENTRY_TAG | END_TAG => Err(invalid_data("out-of-sequence segment stream frame")),
+            _ => Err(invalid_data("unknown segment stream frame")),
+        }
+    }
+
+    pub(crate) fn finish(self) -> SegmentImport {
+        SegmentImport {
+            segment_index: self.segment_index,
+            num_segments: self.num_segments,
+            num_entries: self.next_sequence as usize,
+        }
+    }
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(payloads: &[&[u8]]) -> Vec<u8> {
+        let mut writer = SegmentWriter::new(1, 4);
+
+        for payload in payloads {
+            writer.entry(|buf| buf.extend_from_slice(payload));
+        }
+
+        let (stream, num_entries) = writer.finish();
+        assert_eq!(num_entries, payloads.len());
+
+        stream
+    }
+
+    fn read_all(stream: &[u8]) -> io::Result<(Vec<Vec<u8>>, SegmentImport)> {
+        let mut reader = SegmentReader::new(stream)?;
+        let mut payloads = Vec::new();
+
+        while let Some(payload) = reader.next_entry()? {
+            payloads.push(payload.to_vec());
+        }
+
+        Ok((payloads, reader.finish()))
+    }
+
+    #[test]
+    fn framing() {
+        let payloads: [&[u8]; 3] = [b"foo", b"", b"bar"];
+        let stream = stream(&payloads);
+
+        let (read, import) = read_all(&stream).unwrap();
+        assert_eq!(read, payloads);
+        assert_eq!(import.segment_index(), 1);
+        assert_eq!(import.num_segments(), 4);
+        assert_eq!(import.num_entries(), 3);
+
+        // truncated
+        for len in [0, 20, stream.len() - 1] {
+            let e = read_all(&stream[..len]).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        }
+
+        // not a segment stream
+        let mut corrupt = stream.clone();
+        corrupt[0] = b'X';
+        let e = read_all(&corrupt).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        // the second frame is dropped
+        let header_len = MAGIC.len() + 4 + 8 + 8;
+        let frame_len = 1 + 8 + 8 + 3;
+        let mut dropped = stream[..header_len + frame_len].to_vec();
+        dropped.extend_from_slice(&stream[header_len + frame_len + 17..]);
+        let e = read_all(&dropped).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,90 @@
+//! Helpers for handing off portions of a hash map by hash range.
+//!
+//! The 64-bit hash space of a map can be split into contiguous ranges with
+//! [`split_hash_space`], and the entries whose keys hash into a range can be
+//! visited with the `entries_in_hash_range` methods of `HashMap` and
+//! `SegmentedHashMap`. This enables consistent-hashing style handoff of parts
+//! of a map to other processes during scale-out, with the owner of a key found
+//! by hashing it with the `key_hash` methods.
+//!
+//! The ranges are only meaningful for maps whose hashers produce the same
+//! hashes, which is not the case for two instances of the default hasher.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use moka_cht::{sharding, HashMap};
+//!
+//! let map = HashMap::new();
+//!
+//! for i in 0..100 {
+//!     map.insert(i, i);
+//! }
+//!
+//! let ranges = sharding::split_hash_space(4);
+//! let total: usize = ranges
+//!     .iter()
+//!     .map(|r| map.entries_in_hash_range(*r.start(), *r.end()).count())
+//!     .sum();
+//!
+//! assert_eq!(total, 100);
+//!
+//! let owner = ranges.iter().position(|r| r.contains(&map.key_hash(&42)));
+//! assert!(owner.is_some());
+//! ```
+//!
+//! [`split_hash_space`]: ./fn.split_hash_space.html
+
+use std::ops::RangeInclusive;
+
+/// Splits the 64-bit hash space into `n` contiguous, inclusive ranges of
+/// nearly equal size, in ascending order.
+///
+/// # Panics
+///
+/// Panics if `n` is 0.
+pub fn split_hash_space(n: usize) -> Vec<RangeInclusive<u64>> {
+    assert!(
+        n > 0,
+        "the hash space must be split into at least one range"
+    );
+
+    const SPACE: u128 = 1 << 64;
+    let n = n as u128;
+
+    (0..n)
+        .map(|i| {
+            let start = i * SPACE / n;
+            let end = (i + 1) * SPACE / n - 1;
+
+            start as u64..=end as u64
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_covers_hash_space() {
+        assert_eq!(split_hash_space(1), vec![0..=u64::MAX]);
+
+        for &n in &[2, 3, 7, 64, 1000] {
+            let ranges = split_hash_space(n);
+            assert_eq!(ranges.len(), n);
+            assert_eq!(*ranges[0].start(), 0);
+            assert_eq!(*ranges[n - 1].end(), u64::MAX);
+
+            for pair in ranges.windows(2) {
+                assert_eq!(*pair[0].end() + 1, *pair[1].start());
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn split_into_zero() {
+        split_hash_space(0);
+    }
+}
@@ -0,0 +1,767 @@
+//! An experimental hash map that can be shared between processes.
+//!
+//! The maps of the rest of this crate store their entries in heap-allocated
+//! buckets that are linked by pointers and reclaimed by an epoch-based garbage
+//! collector, none of which is meaningful in another address space. A
+//! [`SharedHashMap`] instead lays out its header and a fixed number of slots
+//! in a region of memory provided by the caller, such as a POSIX shared memory
+//! object mapped into several processes, and refers to its slots by their
+//! offsets only. Keys and values are `Copy` and stored inline in the slots, so
+//! nothing has to be allocated or reclaimed.
+//!
+//! One process initializes the region with [`init`], and the others attach to
+//! it with [`attach`]. The operations have the same names and the same
+//! semantics as those of [`HashMap`], restricted to `Copy` keys and values
+//! that are returned by value.
+//!
+//! Each slot is guarded by a sequence lock: readers never block and retry if
+//! they raced with a writer, and writers claim a slot for the duration of
+//! copying a key or a value into it. A process that dies while it holds a slot
+//! leaves it claimed, so operations on its key spin forever.
+//!
+//! This module is only available when the `shared-memory` feature is enabled,
+//! and its layout is not stable across versions of this crate.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use moka_cht::shared_memory::SharedHashMap;
+//!
+//! // a region that would be mapped from a shared memory object in practice
+//! let len = SharedHashMap::<u64, u64>::required_len(64);
+//! let mut region = vec![0u64; (len + 7) / 8];
+//! let ptr = region.as_mut_ptr() as *mut u8;
+//!
+//! let map = unsafe { SharedHashMap::<u64, u64>::init(ptr, len, 64) }.unwrap();
+//! map.insert(1, 10);
+//!
+//! // another process attaches to the same region
+//! let other = unsafe { SharedHashMap::<u64, u64>::attach(ptr, len) }.unwrap();
+//! assert_eq!(other.get(&1), Some(10));
+//! ```
+//!
+//! [`SharedHashMap`]: ./struct.SharedHashMap.html
+//! [`init`]: ./struct.SharedHashMap.html#method.init
+//! [`attach`]: ./struct.SharedHashMap.html#method.attach
+//! [`HashMap`]: ../map/struct.HashMap.html
+
+use crate::{map::bucket, ordering, Error};
+
+use std::{
+    borrow::Borrow,
+    cell::UnsafeCell,
+    collections::hash_map::DefaultHasher,
+    fmt::{self, Debug, Formatter},
+    hash::{BuildHasher, BuildHasherDefault, Hash},
+    hint,
+    marker::PhantomData,
+    mem::{self, MaybeUninit},
+    ptr,
+    sync::atomic::{self, AtomicU64},
+};
+
+/// The default hasher for a [`SharedHashMap`], which hashes a key to the same
+/// value in every process that runs the same build of a program.
+///
+/// [`SharedHashMap`]: ./struct.SharedHashMap.html
+pub type DefaultSharedHashBuilder = BuildHasherDefault<DefaultHasher>;
+
+const MAGIC: u64 = u64::from_le_bytes(*b"mokacht\x01");
+
+// the low bits of the state of a slot
+const STATUS_MASK: u64 = 0b11;
+const EMPTY: u64 = 0;
+const OCCUPIED: u64 = 1;
+const REMOVED: u64 = 2;
+const WRITING: u64 = 3;
+
+// the increment of the version in the high bits of the state of a slot
+const VERSION_UNIT: u64 = STATUS_MASK + 1;
+
+#[repr(C)]
+struct Header {
+    // set last by `init`, so that `attach` never sees a partial header
+    magic: AtomicU64,
+    capacity: u64,
+    key_size: u32,
+    key_align: u32,
+    value_size: u32,
+    value_align: u32,
+    len: AtomicU64,
+}
+
+#[repr(C)]
+struct Slot<K, V> {
+    state: AtomicU64,
+    key: UnsafeCell<MaybeUninit<K>>,
+    value: UnsafeCell<MaybeUninit<V>>,
+}
+
+impl<K: Copy, V: Copy> Slot<K, V> {
+    /// Returns the state of the slot along with copies of its key and value,
+    /// if it has a key.
+    ///
+    /// The key of a slot never changes once it is written, and a removed entry
+    /// keeps its key and its last value.
+    fn read(&self) -> (u64, Option<(K, V)>) {
+        loop {
+            let state = self.state.load(ordering::ACQUIRE);
+
+            match state & STATUS_MASK {
+                EMPTY => return (state, None),
+                WRITING => {
+                    hint::spin_loop();
+                    continue;
+                }
+                _ => (),
+            }
+
+            // Like the sequence lock of `crossbeam_utils::atomic::AtomicCell`,
+            // this reads memory that may be concurrently written, and only
+            // uses the copies if the version did not change in the meantime.
+            let (key, value) = unsafe {
+                (
+                    ptr::read_volatile(self.key.get()),
+                    ptr::read_volatile(self.value.get()),
+                )
+            };
+            atomic::fence(ordering::ACQUIRE);
+
+            if self.state.load(ordering::RELAXED) == state {
+                return (state, unsafe {
+                    Some((key.assume_init(), value.assume_init()))
+                });
+            }
+        }
+    }
+
+    /// Claims the slot for writing if it is still in `state`.
+    fn lock(&self, state: u64) -> bool {
+        let locked = (state & !STATUS_MASK) | WRITING;
+
+        if self
+            .state
+            .compare_exchange(state, locked, ordering::ACQUIRE, ordering::RELAXED)
+            .is_err()
+        {
+            return false;
+        }
+
+        atomic::fence(ordering::RELEASE);
+
+        true
+    }
+
+    /// Releases a slot claimed from `state`, giving it a new version with
+    /// `status`.
+    fn unlock(&self, state: u64, status: u64) {
+        let version = (state & !STATUS_MASK).wrapping_add(VERSION_UNIT);
+
+        self.state.store(version | status, ordering::RELEASE);
+    }
+}
+
+/// A fixed-capacity hash map laid out in a region of memory that can be
+/// shared between processes.
+///
+/// See the [module documentation] for details.
+///
+/// Every slot that has ever held a key keeps it, so that concurrent inserts
+/// of the same key always find the same slot: a removed entry leaves its key
+/// behind, and inserting that key again reuses its slot. The capacity of the
+/// map therefore bounds the number of distinct keys that are ever inserted,
+/// not the number of entries at a time, and the probes get longer as it is
+/// approached.
+///
+/// [module documentation]: ./index.html
+pub struct SharedHashMap<K, V, S = DefaultSharedHashBuilder> {
+    header: *const Header,
+    slots: *const Slot<K, V>,
+    capacity: usize,
+    build_hasher: S,
+    _marker: PhantomData<(K, V)>,
+}
+
+// The slots are only accessed through atomics and sequence locks, and keys and
+// values are copied in and out of them.
+unsafe impl<K: Send, V: Send, S: Send> Send for SharedHashMap<K, V, S> {}
+unsafe impl<K: Send + Sync, V: Send + Sync, S: Sync> Sync for SharedHashMap<K, V, S> {}
+
+impl<K: Copy, V: Copy> SharedHashMap<K, V, DefaultSharedHashBuilder> {
+    /// Initializes an empty map with room for at least `capacity` distinct
+    /// keys in the region of `len` bytes at `region`.
+    ///
+    /// The capacity is rounded up to a power of two, and the region must be at
+    /// least [`required_len`] bytes long for it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidSharedRegion`] if the region is too short or not
+    /// aligned for the header and the slots of the map, or
+    /// [`Error::CapacityOverflow`] if the length of such a region overflows
+    /// `usize`.
+    ///
+    /// # Safety
+    ///
+    /// The region must be valid for reads and writes for as long as the map
+    /// or any map attached to it is used, and no other map may use it while
+    /// it is initialized. `K` and `V` must not hold pointers or references if
+    /// the region is shared with other processes.
+    ///
+    /// [`required_len`]: #method.required_len
+    /// [`Error::InvalidSharedRegion`]: ../enum.Error.html#variant.InvalidSharedRegion
+    /// [`Error::CapacityOverflow`]: ../enum.Error.html#variant.CapacityOverflow
+    pub unsafe fn init(region: *mut u8, len: usize, capacity: usize) -> Result<Self, Error> {
+        Self::init_with_hasher(region, len, capacity, DefaultSharedHashBuilder::default())
+    }
+
+    /// Attaches to a map that was initialized with [`init`] in the region of
+    /// `len` bytes at `region`, possibly by another process.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidSharedRegion`] if the region does not hold an
+    /// initialized map, holds one whose keys or values have another size or
+    /// alignment, or is shorter than the map.
+    ///
+    /// # Safety
+    ///
+    /// The region must be valid for reads and writes for as long as the map
+    /// is used. The map in the region must have been initialized with the same
+    /// key and value types, and with a hasher that hashes keys to the same
+    /// values.
+    ///
+    /// [`init`]: #method.init
+    /// [`Error::InvalidSharedRegion`]: ../enum.Error.html#variant.InvalidSharedRegion
+    pub unsafe fn attach(region: *mut u8, len: usize) -> Result<Self, Error> {
+        Self::attach_with_hasher(region, len, DefaultSharedHashBuilder::default())
+    }
+}
+
+impl<K: Copy, V: Copy, S> SharedHashMap<K, V, S> {
+    /// Initializes an empty map like [`init`], which hashes keys with
+    /// `build_hasher`.
+    ///
+    /// Every process must use a hasher that hashes keys to the same values,
+    /// so hashers seeded at random, such as [`RandomState`], cannot be used.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidSharedRegion`] if the region is too short or not
+    /// aligned for the header and the slots of the map, or
+    /// [`Error::CapacityOverflow`] if the length of such a region overflows
+    /// `usize`.
+    ///
+    /// # Safety
+    ///
+    /// See [`init`].
+    ///
+    /// [`init`]: #method.init
+    /// [`RandomState`]: https://doc.rust-lang.org/std/collections/hash_map/struct.RandomState.html
+    /// [`Error::InvalidSharedRegion`]: ../enum.Error.html#variant.InvalidSharedRegion
+    /// [`Error::CapacityOverflow`]: ../enum.Error.html#variant.CapacityOverflow
+    pub unsafe fn init_with_hasher(
+        region: *mut u8,
+        len: usize,
+        capacity: usize,
+        build_hasher: S,
+    ) -> Result<Self, Error> {
+        let capacity = capacity
+            .max(1)
+            .checked_next_power_of_two()
+            .ok_or(Error::CapacityOverflow)?;
+
+        if !Self::is_aligned(region) || len < Self::try_required_len(capacity)? {
+            return Err(Error::InvalidSharedRegion);
+        }
+
+        let header = region as *mut Header;
+        let slots = region.add(Self::slots_offset()) as *mut Slot<K, V>;
+
+        ptr::write(
+            header,
+            Header {
+                magic: AtomicU64::new(0),
+                capacity: capacity as u64,
+                key_size: mem::size_of::<K>() as u32,
+                key_align: mem::align_of::<K>() as u32,
+                value_size: mem::size_of::<V>() as u32,
+                value_align: mem::align_of::<V>() as u32,
+                len: AtomicU64::new(0),
+            },
+        );
+        // an all-zero slot is empty
+        ptr::write_bytes(slots, 0, capacity);
+        (*header).magic.store(MAGIC, ordering::RELEASE);
+
+        Ok(Self::from_parts(header, slots, capacity, build_hasher))
+    }
+
+    /// Attaches to a map like [`attach`], which hashes keys with
+    /// `build_hasher`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidSharedRegion`] if the region does not hold an
+    /// initialized map, holds one whose keys or values have another size or
+    /// alignment, or is shorter than the map.
+    ///
+    /// # Safety
+    ///
+    /// See [`attach`].
+    ///
+    /// [`attach`]: #method.attach
+    /// [`Error::InvalidSharedRegion`]: ../enum.Error.html#variant.InvalidSharedRegion
+    pub unsafe fn attach_with_hasher(
+        region: *mut u8,
+        len: usize,
+        build_hasher: S,
+    ) -> Result<Self, Error> {
+        if !Self::is_aligned(region) || len < mem::size_of::<Header>() {
+            return Err(Error::InvalidSharedRegion);
+        }
+
+        let header = region as *const Header;
+
+        if (*header).magic.load(ordering::ACQUIRE) != MAGIC
+            || (*header).key_size as usize != mem::size_of::<K>()
+            || (*header).key_align as usize != mem::align_of::<K>()
+            || (*header).value_size as usize != mem::size_of::<V>()
+            || (*header).value_align as usize != mem::align_of::<V>()
+        {
+            return Err(Error::InvalidSharedRegion);
+        }
+
+        let capacity = (*header).capacity as usize;
+
+        if !capacity.is_power_of_two()
+            || Self::try_required_len(capacity).map_or(true, |required| len < required)
+        {
+            return Err(Error::InvalidSharedRegion);
+        }
+
+        let slots = region.add(Self::slots_offset()) as *const Slot<K, V>;
+
+        Ok(Self::from_parts(header, slots, capacity, build_hasher))
+    }
+
+    /// Returns the number of bytes of the region that holds a map with room
+    /// for at least `capacity` distinct keys.
+    ///
+    /// The region must also be aligned to the larger of 8 and the alignments
+    /// of `K` and `V`, which memory mapped from a shared memory object always
+    /// is.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the length overflows `usize`.
+    pub fn required_len(capacity: usize) -> usize {
+        capacity
+            .max(1)
+            .checked_next_power_of_two()
+            .ok_or(Error::CapacityOverflow)
+            .and_then(Self::try_required_len)
+            .expect("capacity overflow")
+    }
+
+    /// Returns the number of distinct keys that the map has room for.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of entries in the map.
+    ///
+    /// The count is shared by all the processes that use the map, and is
+    /// updated after the entries are inserted or removed, so it may briefly
+    /// lag behind them.
+    pub fn len(&self) -> usize {
+        unsafe { (*self.header).len.load(ordering::RELAXED) as usize }
+    }
+
+    /// Returns `true` if the map contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns a reference to the map's [`BuildHasher`].
+    ///
+    /// [`BuildHasher`]: https://doc.rust-lang.org/std/hash/trait.BuildHasher.html
+    pub fn hasher(&self) -> &S {
+        &self.build_hasher
+    }
+
+    /// Invokes `f` with the key and value of every entry, in arbitrary order.
+    ///
+    /// The slots are read one after the other, so the traversal is weakly
+    /// consistent like the ones of [`HashMap`].
+    ///
+    /// [`HashMap`]: ../map/struct.HashMap.html
+    pub fn for_each<F: FnMut(K, V)>(&self, mut f: F) {
+        for slot in self.slots() {
+            if let (state, Some((key, value))) = slot.read() {
+                if state & STATUS_MASK == OCCUPIED {
+                    f(key, value);
+                }
+            }
+        }
+    }
+
+    fn from_parts(
+        header: *const Header,
+        slots: *const Slot<K, V>,
+        capacity: usize,
+        build_hasher: S,
+    ) -> Self {
+        Self {
+            header,
+            slots,
+            capacity,
+            build_hasher,
+            _marker: PhantomData,
+        }
+    }
+
+    fn slots_offset() -> usize {
+        let align = mem::align_of::<Slot<K, V>>();
+
+        (mem::size_of::<Header>() + align - 1) & !(align - 1)
+    }
+
+    fn try_required_len(capacity: usize) -> Result<usize, Error> {
+        mem::size_of::<Slot<K, V>>()
+            .checked_mul(capacity)
+            .and_then(|len| len.checked_add(Self::slots_offset()))
+            .ok_or(Error::CapacityOverflow)
+    }
+
+    fn is_aligned(region: *mut u8) -> bool {
+        let align = mem::align_of::<Header>().max(mem::align_of::<Slot<K, V>>());
+
+        !region.is_null() && region as usize & (align - 1) == 0
+    }
+
+    fn slots(&self) -> &[Slot<K, V>] {
+        unsafe { std::slice::from_raw_parts(self.slots, self.capacity) }
+    }
+
+    fn add_len(&self, delta: i64) {
+        unsafe {
+            (*self.header)
+                .len
+                .fetch_add(delta as u64, ordering::RELAXED)
+        };
+    }
+}
+
+impl<K: Copy + Hash + Eq, V: Copy, S: BuildHasher> SharedHashMap<K, V, S> {
+    /// Returns a copy of the value corresponding to the key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn get<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        for slot in self.probe(key) {
+            match slot.read() {
+                (_, None) => return None,
+                (state, Some((k, v))) if k.borrow() == key => {
+                    return Some(v).filter(|_| state & STATUS_MASK == OCCUPIED);
+                }
+                _ => (),
+            }
+        }
+
+        None
+    }
+
+    /// Returns `true` if the map contains a value for the specified key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn contains_key<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Inserts a key-value pair into the map, returning a copy of the value
+    /// previously corresponding to the key.
+    ///
+    /// If the map did have this key present, the value is overwritten in the
+    /// slot of the key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is not in the map and all of its slots hold other
+    /// keys. See [`try_insert`] for a non-panicking version.
+    ///
+    /// [`try_insert`]: #method.try_insert
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.try_insert(key, value)
+            .expect("the shared hash map is full")
+    }
+
+    /// Inserts a key-value pair into the map like [`insert`], unless the map
+    /// is full.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::SharedMapFull`] if the key is not in the map and all
+    /// of its slots hold other keys, in which case the map is left unchanged.
+    ///
+    /// [`insert`]: #method.insert
+    /// [`Error::SharedMapFull`]: ../enum.Error.html#variant.SharedMapFull
+    pub fn try_insert(&self, key: K, value: V) -> Result<Option<V>, Error> {
+        for slot in self.probe(&key) {
+            loop {
+                match slot.read() {
+                    (state, None) => {
+                        if slot.lock(state) {
+                            unsafe {
+                                ptr::write(slot.key.get(), MaybeUninit::new(key));
+                                ptr::write(slot.value.get(), MaybeUninit::new(value));
+                            }
+                            slot.unlock(state, OCCUPIED);
+                            self.add_len(1);
+
+                            return Ok(None);
+                        }
+                    }
+                    (state, Some((k, v))) if k == key => {
+                        if slot.lock(state) {
+                            unsafe { ptr::write(slot.value.get(), MaybeUninit::new(value)) };
+                            slot.unlock(state, OCCUPIED);
+
+                            if state & STATUS_MASK == OCCUPIED {
+                                return Ok(Some(v));
+                            }
+
+                            self.add_len(1);
+
+                            return Ok(None);
+                        }
+                    }
+                    _ => break,
+                }
+            }
+        }
+
+        Err(Error::SharedMapFull)
+    }
+
+    /// Removes a key from the map, returning a copy of the value at the key
+    /// if the key was previously in the map.
+    ///
+    /// The key keeps its slot, so inserting it again does not take up another
+    /// one.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// [`Hash`]: https://doc.rust-lang.org/std/hash/trait.Hash.html
+    /// [`Eq`]: https://doc.rust-lang.org/std/cmp/trait.Eq.html
+    pub fn remove<Q: Hash + Eq + ?Sized>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        for slot in self.probe(key) {
+            loop {
+                match slot.read() {
+                    (_, None) => return None,
+                    (state, Some((k, v))) if k.borrow() == key => {
+                        if state & STATUS_MASK == REMOVED {
+                            return None;
+                        }
+
+                        if slot.lock(state) {
+                            slot.unlock(state, REMOVED);
+                            self.add_len(-1);
+
+                            return Some(v);
+                        }
+                    }
+                    _ => break,
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns the slots of the probe sequence of a key, starting from its
+    /// home slot.
+    fn probe<Q: Hash + ?Sized>(&self, key: &Q) -> impl Iterator<Item = &Slot<K, V>> {
+        let slots = self.slots();
+        let home = bucket::hash(&self.build_hasher, key) as usize;
+
+        (0..self.capacity).map(move |i| &slots[home.wrapping_add(i) & (slots.len() - 1)])
+    }
+}
+
+impl<K: Copy + Debug, V: Copy + Debug, S> Debug for SharedHashMap<K, V, S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut map = f.debug_map();
+        self.for_each(|k, v| {
+            map.entry(&k, &v);
+        });
+
+        map.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    // a zeroed region aligned to 8 bytes, like a freshly mapped one
+    fn region<K: Copy, V: Copy>(capacity: usize) -> (Vec<u64>, usize) {
+        let len = SharedHashMap::<K, V>::required_len(capacity);
+
+        (vec![0; len / 8 + 1], len)
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        let (mut region, len) = region::<u32, u64>(8);
+        let ptr = region.as_mut_ptr() as *mut u8;
+        let map = unsafe { SharedHashMap::<u32, u64>::init(ptr, len, 8) }.unwrap();
+
+        assert_eq!(map.capacity(), 8);
+        assert!(map.is_empty());
+        assert_eq!(map.insert(1, 10), None);
+        assert_eq!(map.insert(2, 20), None);
+        assert_eq!(map.insert(1, 11), Some(10));
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&1), Some(11));
+        assert!(map.contains_key(&2));
+        assert!(!map.contains_key(&3));
+
+        assert_eq!(map.remove(&1), Some(11));
+        assert_eq!(map.remove(&1), None);
+        assert_eq!(map.get(&1), None);
+        assert_eq!(map.len(), 1);
+
+        // the removed key is inserted into its previous slot
+        assert_eq!(map.insert(1, 12), None);
+        assert_eq!(map.get(&1), Some(12));
+        assert_eq!(map.len(), 2);
+
+        let mut entries = Vec::new();
+        map.for_each(|k, v| entries.push((k, v)));
+        entries.sort_unstable();
+        assert_eq!(entries, [(1, 12), (2, 20)]);
+    }
+
+    #[test]
+    fn attach() {
+        let (mut region, len) = region::<u64, u64>(16);
+        let ptr = region.as_mut_ptr() as *mut u8;
+
+        assert_eq!(
+            unsafe { SharedHashMap::<u64, u64>::attach(ptr, len) }.unwrap_err(),
+            Error::InvalidSharedRegion
+        );
+
+        let map = unsafe { SharedHashMap::<u64, u64>::init(ptr, len, 10) }.unwrap();
+        map.insert(1, 1);
+
+        let other = unsafe { SharedHashMap::<u64, u64>::attach(ptr, len) }.unwrap();
+        assert_eq!(other.capacity(), 16);
+        assert_eq!(other.get(&1), Some(1));
+        other.insert(2, 2);
+        assert_eq!(map.get(&2), Some(2));
+        assert_eq!(map.len(), 2);
+
+        // the layout of the keys and values must match
+        assert_eq!(
+            unsafe { SharedHashMap::<u64, u32>::attach(ptr, len) }.unwrap_err(),
+            Error::InvalidSharedRegion
+        );
+        assert_eq!(
+            unsafe { SharedHashMap::<u64, u64>::attach(ptr, len - 1) }.unwrap_err(),
+            Error::InvalidSharedRegion
+        );
+        assert_eq!(
+            unsafe { SharedHashMap::<u64, u64>::init(ptr, len, 32) }.unwrap_err(),
+            Error::InvalidSharedRegion
+        );
+    }
+
+    #[test]
+    fn full() {
+        let (mut region, len) = region::<u64, u64>(4);
+        let ptr = region.as_mut_ptr() as *mut u8;
+        let map = unsafe { SharedHashMap::<u64, u64>::init(ptr, len, 4) }.unwrap();
+
+        for i in 0..4 {
+            assert_eq!(map.try_insert(i, i), Ok(None));
+        }
+
+        assert_eq!(map.try_insert(4, 4), Err(Error::SharedMapFull));
+        assert_eq!(map.try_insert(0, 1), Ok(Some(0)));
+
+        // removed keys keep their slots
+        assert_eq!(map.remove(&0), Some(1));
+        assert_eq!(map.try_insert(4, 4), Err(Error::SharedMapFull));
+        assert_eq!(map.try_insert(0, 2), Ok(None));
+    }
+
+    #[test]
+    fn concurrent_attached_maps() {
+        const NUM_THREADS: u64 = 4;
+        const NUM_KEYS: u64 = 256;
+
+        let (mut region, len) = region::<u64, (u64, u64)>(NUM_KEYS as usize);
+        let ptr = region.as_mut_ptr() as *mut u8;
+        let map = unsafe { SharedHashMap::<u64, (u64, u64)>::init(ptr, len, 256) }.unwrap();
+
+        let addr = ptr as usize;
+        let threads: Vec<_> = (0..NUM_THREADS)
+            .map(|t| {
+                thread::spawn(move || {
+                    let map =
+                        unsafe { SharedHashMap::<u64, (u64, u64)>::attach(addr as *mut u8, len) }
+                            .unwrap();
+
+                    for round in 0..100 {
+                        for key in 0..NUM_KEYS {
+                            if (key + round) % NUM_THREADS == t {
+                                map.insert(key, (key, round));
+                                map.remove(&key);
+                                map.insert(key, (key, round));
+                            }
+
+                            // the two halves of a value are never torn
+                            if let Some((k, _)) = map.get(&key) {
+                                assert_eq!(k, key);
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        assert_eq!(map.len(), NUM_KEYS as usize);
+
+        for key in 0..NUM_KEYS {
+            assert_eq!(map.get(&key).map(|(k, _)| k), Some(key));
+        }
+    }
+}
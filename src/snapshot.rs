@@ -0,0 +1,222 @@
+//! Point-in-time copies of the hash maps in this crate and the differences
+//! between them.
+//!
+//! A [`Snapshot`] is taken with the `snapshot` methods of `HashMap` and
+//! `SegmentedHashMap`. It stores the hash of every entry next to a clone of it,
+//! sorted by hash, so that two snapshots can be compared with a single merge
+//! pass that only compares keys and values whose hashes are equal. A snapshot
+//! can also be compared against the live map with the `changes_since` methods,
+//! which do not clone the values of the map.
+//!
+//! This is intended for cache-coherency protocols that broadcast the keys that
+//! changed rather than full dumps of the map.
+//!
+//! A snapshot is only comparable with snapshots of the same map, or of maps
+//! whose hashers produce the same hashes. Comparing it with a snapshot of
+//! another map gives meaningless results.
+//!
+//! To read the entries of a map repeatedly without cloning them, take a
+//! [`SnapshotView`] with the `snapshot_view` methods instead, which keeps the
+//! entries alive for as long as it is held, and can be turned into a
+//! `Snapshot` later.
+//!
+//! Taking a snapshot is weakly consistent: it reflects the contents of the map
+//! at some point at or after the start of the call, and modifications made
+//! while it is being taken may or may not be observed.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use moka_cht::HashMap;
+//!
+//! let map = HashMap::new();
+//! map.insert("foo", 1);
+//! map.insert("bar", 2);
+//!
+//! let snapshot = map.snapshot();
+//!
+//! map.insert("foo", 3);
+//! map.remove("bar");
+//! map.insert("baz", 4);
+//!
+//! let diff = map.changes_since(&snapshot);
+//! assert_eq!(diff.added(), &["baz"]);
+//! assert_eq!(diff.removed(), &["bar"]);
+//! assert_eq!(diff.changed(), &["foo"]);
+//!
+//! assert_eq!(snapshot.diff(&map.snapshot()), diff);
+//! ```
+//!
+//! [`Snapshot`]: ./struct.Snapshot.html
+//! [`SnapshotView`]: ../map/struct.SnapshotView.html
+
+/// A point-in-time copy of the entries of a hash map.
+///
+/// See the [module-level documentation](./index.html) for details.
+#[derive(Clone, Debug)]
+pub struct Snapshot<K, V> {
+    entries: Vec<(u64, K, V)>,
+}
+
+impl<K, V> Snapshot<K, V> {
+    pub(crate) fn new(mut entries: Vec<(u64, K, V)>) -> Self {
+        entries.sort_unstable_by_key(|&(hash, _, _)| hash);
+
+        Self { entries }
+    }
+
+    /// Returns the number of entries in the snapshot.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the snapshot contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// An iterator visiting all key-value pairs of the snapshot in arbitrary
+    /// order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(_, k, v)| (k, v))
+    }
+
+    /// Converts the snapshot into its entries, each preceded by the hash of
+    /// its key, sorted by hash.
+    ///
+    /// The entries can be loaded into a new map with the
+    /// `from_hash_sorted_entries` methods of the hash maps.
+    pub fn into_hashed_entries(self) -> Vec<(u64, K, V)> {
+        self.entries
+    }
+
+    pub(crate) fn sorted_entries(&self) -> Vec<(u64, &K, &V)> {
+        self.entries.iter().map(|(h, k, v)| (*h, k, v)).collect()
+    }
+}
+
+impl<K: Eq + Clone, V: PartialEq> Snapshot<K, V> {
+    /// Returns the keys that were added, removed, or whose values changed
+    /// between this snapshot and the `newer` one.
+    pub fn diff(&self, newer: &Snapshot<K, V>) -> SnapshotDiff<K> {
+        SnapshotDiff::between(&self.sorted_entries(), &newer.sorted_entries())
+    }
+}
+
+/// The keys that differ between two snapshots of a hash map.
+///
+/// This `struct` is created by the [`Snapshot::diff`] method and the
+/// `changes_since` methods of the hash maps. The keys are in arbitrary order.
+///
+/// [`Snapshot::diff`]: ./struct.Snapshot.html#method.diff
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotDiff<K> {
+    added: Vec<K>,
+    removed: Vec<K>,
+    changed: Vec<K>,
+}
+
+impl<K> SnapshotDiff<K> {
+    /// Returns the keys that are only present in the newer snapshot.
+    pub fn added(&self) -> &[K] {
+        &self.added
+    }
+
+    /// Returns the keys that are only present in the older snapshot.
+    pub fn removed(&self) -> &[K] {
+        &self.removed
+    }
+
+    /// Returns the keys that are present in both snapshots with values that are
+    /// not equal.
+    pub fn changed(&self) -> &[K] {
+        &self.changed
+    }
+
+    /// Returns `true` if the snapshots contain equal entries.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl<K: Eq + Clone> SnapshotDiff<K> {
+    /// Compares two lists of entries sorted by hash.
+    pub(crate) fn between<V: PartialEq>(older: &[(u64, &K, &V)], newer: &[(u64, &K, &V)]) -> Self {
+        let mut diff = Self {
+            added: Vec::new(),
+            removed: Vec::new(),
+            changed: Vec::new(),
+        };
+
+        let (mut i, mut j) = (0, 0);
+
+        while i < older.len() || j < newer.len() {
+            let older_hash = older.get(i).map(|&(h, _, _)| h);
+            let newer_hash = newer.get(j).map(|&(h, _, _)| h);
+
+            let hash = match (older_hash, newer_hash) {
+                (Some(o), Some(n)) => o.min(n),
+                (Some(o), None) => o,
+                (None, Some(n)) => n,
+                (None, None) => unreachable!(),
+            };
+
+            let older_end = i + older[i..]
+                .iter()
+                .take_while(|&&(h, _, _)| h == hash)
+                .count();
+            let newer_end = j + newer[j..]
+                .iter()
+                .take_while(|&&(h, _, _)| h == hash)
+                .count();
+
+            diff.compare_run(&older[i..older_end], &newer[j..newer_end]);
+
+            i = older_end;
+            j = newer_end;
+        }
+
+        diff
+    }
+
+    // compares entries with equal hashes, which are almost always at most one
+    // per side
+    fn compare_run<V: PartialEq>(&mut self, older: &[(u64, &K, &V)], newer: &[(u64, &K, &V)]) {
+        for &(_, key, value) in older {
+            match newer.iter().find(|&&(_, k, _)| k == key) {
+                Some(&(_, _, v)) if v != value => self.changed.push(key.clone()),
+                Some(_) => (),
+                None => self.removed.push(key.clone()),
+            }
+        }
+
+        for &(_, key, _) in newer {
+            if !older.iter().any(|&(_, k, _)| k == key) {
+                self.added.push(key.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_with_colliding_hashes() {
+        let older = Snapshot::new(vec![(1, "a", 1), (1, "b", 2), (2, "c", 3), (4, "d", 4)]);
+        let newer = Snapshot::new(vec![(1, "b", 5), (1, "e", 6), (2, "c", 3), (3, "f", 7)]);
+
+        let diff = older.diff(&newer);
+        assert_eq!(diff.added(), &["e", "f"]);
+        assert_eq!(diff.removed(), &["a", "d"]);
+        assert_eq!(diff.changed(), &["b"]);
+
+        let diff = newer.diff(&older);
+        assert_eq!(diff.added(), &["a", "d"]);
+        assert_eq!(diff.removed(), &["e", "f"]);
+        assert_eq!(diff.changed(), &["b"]);
+
+        assert!(older.diff(&older.clone()).is_empty());
+    }
+}
@@ -334,6 +334,66 @@ macro_rules! write_test_cases_for_me {
             $crate::test_util::run_deferred();
         }
 
+        #[test]
+        fn concurrent_shrink_and_insertion() {
+            const MAX_VALUE: i32 = 2048;
+            const NUM_THREADS: usize = 8;
+            const NUM_ROUNDS: usize = 8;
+            const MAX_INSERTED_VALUE: i32 = (NUM_THREADS as i32) * MAX_VALUE;
+
+            for _ in 0..NUM_ROUNDS {
+                // start sparse so that housekeeping shrinks the map while it
+                // is being filled
+                let map = std::sync::Arc::new($m::with_capacity(MAX_INSERTED_VALUE as usize));
+                let is_done = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+                let barrier = std::sync::Arc::new(std::sync::Barrier::new(NUM_THREADS + 1));
+
+                let insert_threads: Vec<_> = (0..NUM_THREADS)
+                    .map(|i| {
+                        let map = std::sync::Arc::clone(&map);
+                        let barrier = std::sync::Arc::clone(&barrier);
+
+                        std::thread::spawn(move || {
+                            barrier.wait();
+
+                            for j in (0..MAX_VALUE).map(|j| j + (i as i32 * MAX_VALUE)) {
+                                assert_eq!(map.insert(j, j), None);
+                            }
+                        })
+                    })
+                    .collect();
+
+                let housekeeping_thread = {
+                    let map = std::sync::Arc::clone(&map);
+                    let is_done = std::sync::Arc::clone(&is_done);
+                    let barrier = std::sync::Arc::clone(&barrier);
+
+                    std::thread::spawn(move || {
+                        barrier.wait();
+
+                        while !is_done.load(std::sync::atomic::Ordering::Acquire) {
+                            map.run_housekeeping($crate::housekeeping::Budget::unlimited());
+                        }
+                    })
+                };
+
+                for result in insert_threads.into_iter().map(|t| t.join()) {
+                    assert!(result.is_ok());
+                }
+
+                is_done.store(true, std::sync::atomic::Ordering::Release);
+                assert!(housekeeping_thread.join().is_ok());
+
+                assert_eq!(map.len(), MAX_INSERTED_VALUE as usize);
+
+                for i in 0..MAX_INSERTED_VALUE {
+                    assert_eq!(map.get(&i), Some(i));
+                }
+            }
+
+            $crate::test_util::run_deferred();
+        }
+
         #[test]
         fn modify() {
             let map = $m::new();
@@ -461,6 +521,98 @@ macro_rules! write_test_cases_for_me {
             $crate::test_util::run_deferred();
         }
 
+        #[test]
+        fn insert_with_key_or_modify() {
+            let map = $m::new();
+
+            assert_eq!(
+                map.insert_with_key_or_modify("foo".to_owned(), |k| k.len(), |_, x| x + 1),
+                None
+            );
+            assert_eq!(map.get("foo"), Some(3));
+
+            assert_eq!(
+                map.insert_with_key_or_modify("foo".to_owned(), |_| unreachable!(), |_, x| x + 1),
+                Some(3)
+            );
+            assert_eq!(map.get("foo"), Some(4));
+
+            assert_eq!(
+                map.insert_with_key_or_modify_entry("quux".to_owned(), |k| k.len(), |_, x| x + 1),
+                None
+            );
+            assert_eq!(
+                map.insert_with_key_or_modify_entry("quux".to_owned(), |k| k.len(), |_, x| x * 2),
+                Some(("quux".to_owned(), 4))
+            );
+            assert_eq!(map.get("quux"), Some(8));
+
+            assert_eq!(
+                map.insert_with_key_or_modify_and(
+                    "foo".to_owned(),
+                    |k| k.len(),
+                    |_, x| x + 1,
+                    |x| { x * 10 }
+                ),
+                Some(40)
+            );
+            assert_eq!(map.get("foo"), Some(5));
+            assert_eq!(map.len(), 2);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn insert_with_or_modify_with_state() {
+            const NUM_THREADS: usize = 16;
+            const NUM_UPDATES: usize = 256;
+
+            // parses the increment on the first invocation of either function,
+            // and counts how many times it was parsed.
+            fn parsed(state: &mut (Option<usize>, usize)) -> usize {
+                if state.0.is_none() {
+                    state.0 = Some("1".parse().unwrap());
+                    state.1 += 1;
+                }
+
+                state.0.unwrap()
+            }
+
+            let map = std::sync::Arc::new($m::new());
+            let barrier = std::sync::Arc::new(std::sync::Barrier::new(NUM_THREADS));
+
+            let threads: Vec<_> = (0..NUM_THREADS)
+                .map(|_| {
+                    let map = std::sync::Arc::clone(&map);
+                    let barrier = std::sync::Arc::clone(&barrier);
+
+                    std::thread::spawn(move || {
+                        barrier.wait();
+
+                        for _ in 0..NUM_UPDATES {
+                            let mut state = (None, 0);
+                            map.insert_with_or_modify_with_state(
+                                "foo",
+                                &mut state,
+                                |s| parsed(s),
+                                |s, _, v| v + parsed(s),
+                            );
+
+                            assert_eq!(state, (Some(1), 1));
+                        }
+                    })
+                })
+                .collect();
+
+            for result in threads.into_iter().map(std::thread::JoinHandle::join) {
+                assert!(result.is_ok());
+            }
+
+            assert_eq!(map.get("foo"), Some(NUM_THREADS * NUM_UPDATES));
+
+            $crate::test_util::run_deferred();
+        }
+
         #[test]
         fn concurrent_insert_or_modify() {
             const NUM_THREADS: usize = 64;
@@ -899,6 +1051,145 @@ macro_rules! write_test_cases_for_me {
             }
         }
 
+        #[test]
+        fn batch() {
+            const NUM_VALUES: i32 = 512;
+
+            let map = $m::new();
+
+            for i in 0..NUM_VALUES {
+                assert_eq!(map.insert(i, i), None);
+            }
+
+            let mut batch = map.batch();
+            assert!(batch.is_empty());
+
+            for i in 0..NUM_VALUES * 2 {
+                batch.insert(i, i + 1);
+            }
+
+            for i in (0..NUM_VALUES * 2).step_by(2) {
+                batch.remove(i).insert(i, i + 2).remove(i);
+            }
+
+            batch.remove(NUM_VALUES * 4);
+            assert_eq!(batch.len(), NUM_VALUES as usize * 5 + 1);
+            assert_eq!(map.len(), NUM_VALUES as usize);
+            assert_eq!(map.get(&(NUM_VALUES + 1)), None);
+
+            batch.commit();
+            assert_eq!(map.len(), NUM_VALUES as usize);
+
+            for i in 0..NUM_VALUES * 2 {
+                if i % 2 == 0 {
+                    assert_eq!(map.get(&i), None);
+                } else {
+                    assert_eq!(map.get(&i), Some(i + 1));
+                }
+            }
+
+            map.batch().commit();
+            assert_eq!(map.len(), NUM_VALUES as usize);
+        }
+
+        #[test]
+        fn exclusive() {
+            const NUM_VALUES: i32 = 512;
+
+            let mut map = $m::new();
+
+            // leave tombstones behind for the exclusive view to reuse
+            for i in 0..NUM_VALUES {
+                assert_eq!(map.insert(i, i), None);
+            }
+
+            for i in (0..NUM_VALUES).step_by(2) {
+                assert_eq!(map.remove(&i), Some(i));
+            }
+
+            let mut exclusive = map.exclusive();
+            assert_eq!(exclusive.len(), NUM_VALUES as usize / 2);
+
+            for i in 0..NUM_VALUES * 4 {
+                let expected = if i < NUM_VALUES && i % 2 == 1 {
+                    Some(i)
+                } else {
+                    None
+                };
+                assert_eq!(exclusive.insert(i, i + 1), expected);
+            }
+
+            assert_eq!(exclusive.len(), NUM_VALUES as usize * 4);
+
+            for i in (0..NUM_VALUES * 4).step_by(3) {
+                assert_eq!(exclusive.remove_entry(&i), Some((i, i + 1)));
+                assert_eq!(exclusive.remove(&i), None);
+            }
+
+            for i in 0..NUM_VALUES * 4 {
+                if i % 3 == 0 {
+                    assert_eq!(exclusive.get(&i), None);
+                } else {
+                    assert_eq!(exclusive.get(&i), Some(&(i + 1)));
+                }
+            }
+
+            let len = exclusive.len();
+            assert_eq!(map.len(), len);
+
+            for i in 0..NUM_VALUES * 4 {
+                if i % 3 == 0 {
+                    assert_eq!(map.get(&i), None);
+                    assert_eq!(map.insert(i, i), None);
+                } else {
+                    assert_eq!(map.get(&i), Some(i + 1));
+                    assert_eq!(map.remove(&i), Some(i + 1));
+                }
+            }
+
+            assert_eq!(map.len(), NUM_VALUES as usize * 4 - len);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn get_mut_insert_mut_iter_mut() {
+            const NUM_VALUES: i32 = 512;
+
+            let mut map = $m::new();
+
+            for i in 0..NUM_VALUES {
+                assert_eq!(map.insert_mut(i, i), None);
+            }
+
+            assert_eq!(map.insert_mut(0, 1), Some(0));
+            assert_eq!(map.remove(&1), Some(1));
+            assert_eq!(map.len(), NUM_VALUES as usize - 1);
+
+            for i in 0..NUM_VALUES {
+                match map.get_mut(&i) {
+                    Some(v) => *v += 1,
+                    None => assert_eq!(i, 1),
+                }
+            }
+
+            assert_eq!(map.get_mut(&NUM_VALUES), None);
+
+            let mut keys = std::collections::HashSet::new();
+
+            for (k, v) in map.iter_mut() {
+                assert!(keys.insert(*k));
+                *v *= 2;
+            }
+
+            assert_eq!(keys.len(), NUM_VALUES as usize - 1);
+            assert_eq!(map.get(&0), Some(4));
+
+            for i in 2..NUM_VALUES {
+                assert_eq!(map.get(&i), Some((i + 1) * 2));
+            }
+        }
+
         #[test]
         fn remove_if() {
             const NUM_VALUES: i32 = 512;
@@ -963,5 +1254,2791 @@ macro_rules! write_test_cases_for_me {
 
             $crate::test_util::run_deferred();
         }
+
+        #[test]
+        fn get_many_ref() {
+            const NUM_VALUES: i32 = 512;
+
+            let map = $m::new();
+
+            for i in (0..NUM_VALUES).filter(|i| i % 2 == 0) {
+                assert_eq!(map.insert(i, i.to_string()), None);
+            }
+
+            let keys: Vec<_> = (0..NUM_VALUES).rev().collect();
+            let guard = $crate::epoch::pin();
+            let values = map.get_many_ref(&keys, &guard);
+
+            assert_eq!(values.len(), keys.len());
+
+            for (key, value) in keys.iter().zip(values) {
+                if key % 2 == 0 {
+                    assert_eq!(value, Some(&key.to_string()));
+                } else {
+                    assert_eq!(value, None);
+                }
+            }
+
+            drop(guard);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        #[should_panic(expected = "the guard must be pinned to the default collector")]
+        fn get_many_ref_foreign_guard() {
+            let map = $m::new();
+            map.insert(0, 0);
+
+            let collector = $crate::epoch::Collector::new();
+            let handle = collector.register();
+
+            map.get_many_ref(&[0], &handle.pin());
+        }
+
+        #[test]
+        fn upsert_and_get() {
+            let map = $m::new();
+
+            let r = map.upsert_and_get("foo", || 1, |_, _| unreachable!());
+            assert_eq!(r.pair(), (&"foo", &1));
+            drop(r);
+
+            let r = map.upsert_and_get("foo", || unreachable!(), |_, v| v + 1);
+            assert_eq!(*r, 2);
+
+            // the reference stays valid after the entry is replaced or removed
+            assert_eq!(map.insert("foo", 3), Some(2));
+            assert_eq!(map.remove("foo"), Some(3));
+            assert_eq!(*r, 2);
+            drop(r);
+
+            assert!(map.is_empty());
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        #[cfg(feature = "generations")]
+        fn iter_upto_generation() {
+            let map = $m::new();
+            assert_eq!(map.generation(), 0);
+
+            for i in 0..8 {
+                map.insert(i, i);
+            }
+
+            let generation = map.advance_generation();
+            assert_eq!(generation, 0);
+            assert_eq!(map.generation(), 1);
+
+            // keys inserted afterwards are skipped
+            for i in 8..16 {
+                map.insert(i, i);
+            }
+
+            // modified values keep their generation, but reinserted keys do not
+            map.insert(0, 100);
+            map.insert_or_modify(1, 0, |_, v| v + 100);
+            map.remove(&2);
+            map.insert(2, 2);
+            map.remove(&3);
+
+            let mut entries: Vec<_> = map
+                .iter_upto_generation(generation)
+                .map(|r| (*r.key(), *r.value()))
+                .collect();
+            entries.sort_unstable();
+
+            assert_eq!(
+                entries,
+                vec![(0, 100), (1, 101), (4, 4), (5, 5), (6, 6), (7, 7)]
+            );
+            assert_eq!(map.iter_upto_generation(map.generation()).count(), 15);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn snapshot_diff() {
+            let map = $m::new();
+
+            for i in 0..64 {
+                map.insert(i, i);
+            }
+
+            let snapshot = map.snapshot();
+            assert_eq!(snapshot.len(), 64);
+            assert!(map.changes_since(&snapshot).is_empty());
+
+            for i in 0..8 {
+                map.remove(&i);
+            }
+
+            for i in 8..16 {
+                map.insert(i, i + 1);
+            }
+
+            // writing an equal value is not a change
+            map.insert(16, 16);
+
+            for i in 64..72 {
+                map.insert(i, i);
+            }
+
+            let diff = map.changes_since(&snapshot);
+
+            let sorted = |keys: &[i32]| {
+                let mut keys = keys.to_vec();
+                keys.sort_unstable();
+
+                keys
+            };
+
+            assert_eq!(sorted(diff.added()), (64..72).collect::<Vec<_>>());
+            assert_eq!(sorted(diff.removed()), (0..8).collect::<Vec<_>>());
+            assert_eq!(sorted(diff.changed()), (8..16).collect::<Vec<_>>());
+
+            assert_eq!(snapshot.diff(&map.snapshot()), diff);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn entries_snapshot() {
+            let map = $m::new();
+            assert!(map.entries_snapshot().is_empty());
+
+            for i in 0..100 {
+                map.insert(i, i * 2);
+            }
+
+            map.remove(&0);
+
+            let mut entries = map.entries_snapshot();
+            entries.sort_unstable();
+            assert_eq!(entries, (1..100).map(|i| (i, i * 2)).collect::<Vec<_>>());
+
+            let mut keys = map.keys_snapshot();
+            keys.sort_unstable();
+            assert_eq!(keys, (1..100).collect::<Vec<_>>());
+
+            let mut values = map.values_snapshot();
+            values.sort_unstable();
+            assert_eq!(values, (1..100).map(|i| i * 2).collect::<Vec<_>>());
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn random_entry() {
+            let map = $m::new();
+            assert!(map.random_entry().is_none());
+
+            for i in 0..100 {
+                map.insert(i, i * 2);
+            }
+
+            let mut seen = std::collections::HashSet::new();
+
+            for _ in 0..1000 {
+                let entry = map.random_entry().unwrap();
+                assert_eq!(*entry.value(), entry.key() * 2);
+                seen.insert(*entry.key());
+            }
+
+            assert!(seen.len() > 50);
+
+            for i in 1..100 {
+                map.remove(&i);
+            }
+
+            assert_eq!(*map.random_entry().unwrap().key(), 0);
+
+            map.remove(&0);
+            assert!(map.random_entry().is_none());
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn snapshot_view() {
+            let map = $m::new();
+
+            for i in 0..100 {
+                map.insert(i, i);
+            }
+
+            let view = map.snapshot_view();
+
+            // writers continue while the view is held
+            for i in 0..50 {
+                map.remove(&i);
+            }
+
+            for i in 50..100 {
+                map.insert(i, i * 2);
+            }
+
+            for i in 100..1000 {
+                map.insert(i, i);
+            }
+
+            assert_eq!(view.len(), 100);
+
+            for _ in 0..2 {
+                let mut entries: Vec<_> = view.iter().map(|(&k, &v)| (k, v)).collect();
+                entries.sort_unstable();
+                assert_eq!(entries, (0..100).map(|i| (i, i)).collect::<Vec<_>>());
+            }
+
+            assert_eq!(view.get(&0), Some(&0));
+            assert_eq!(view.get_key_value(&99), Some((&99, &99)));
+            assert!(!view.contains_key(&100));
+
+            let snapshot = view.to_snapshot();
+            drop(view);
+
+            assert_eq!(snapshot.len(), 100);
+            assert_eq!(map.changes_since(&snapshot).added().len(), 900);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn config() {
+            let builder = $m::builder()
+                .tombstone_policy($crate::map::TombstonePolicy::KeepUntilRehash)
+                .flush_after_bulk_removals();
+            #[cfg(feature = "sequence-numbers")]
+            let builder = builder.sequence_numbers();
+            let map: $m<i32, i32> = builder.build();
+            let config = map.config();
+
+            assert_eq!(
+                config.segment_shift,
+                64 - config.num_segments.trailing_zeros()
+            );
+            assert!(config.hasher.contains("RandomState"));
+            assert_eq!(config.sequence_numbers, cfg!(feature = "sequence-numbers"));
+            assert_eq!(config.memory_budget_limit, None);
+            assert_eq!(config.instrument, cfg!(feature = "instrument"));
+
+            let default_config = $m::<i32, i32>::new().config();
+            assert_eq!(default_config.diff(&default_config), Vec::<&str>::new());
+            let expected: Vec<_> = [
+                "tombstone_policy",
+                "sequence_numbers",
+                "flush_after_bulk_removals",
+            ]
+            .iter()
+            .copied()
+            .filter(|&f| f != "sequence_numbers" || cfg!(feature = "sequence-numbers"))
+            .collect();
+            assert_eq!(config.diff(&default_config), expected);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn get_or_default() {
+            let map: $m<i32, Vec<i32>> = $m::builder().default_value(|k: &i32| vec![*k]).build();
+
+            assert_eq!(map.get_or_default(1), [1]);
+            assert_eq!(map.get(&1), Some(vec![1]));
+            assert_eq!(map.len(), 1);
+
+            // the value of a key in the map is kept
+            map.insert(2, vec![]);
+            assert_eq!(map.get_or_default(2), Vec::<i32>::new());
+            assert_eq!(map.len(), 2);
+
+            assert!(map.config().default_value);
+            assert_eq!(
+                map.config().diff(&$m::<i32, Vec<i32>>::new().config()),
+                ["default_value"]
+            );
+
+            let result = std::panic::catch_unwind(|| $m::<i32, i32>::new().get_or_default(1));
+            assert!(result.is_err());
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn scan_filtered() {
+            let map = $m::new();
+
+            for i in 0..1000 {
+                map.insert(i, i);
+            }
+
+            let mut matching = Vec::new();
+            let num_matching = map.scan_filtered(
+                |k, v| {
+                    assert_eq!(k, v);
+
+                    v % 10 == 0
+                },
+                |&k, _| matching.push(k),
+            );
+
+            matching.sort_unstable();
+            assert_eq!(num_matching, 100);
+            assert_eq!(matching, (0..1000).step_by(10).collect::<Vec<_>>());
+
+            assert_eq!(map.scan_filtered(|_, _| false, |_, _| panic!()), 0);
+
+            // the insertions resize the bucket arrays being walked, and no
+            // entry is visited twice
+            let mut next_key = 1000;
+            let mut matching = Vec::new();
+            map.scan_filtered(
+                |_, v| v % 10 == 0,
+                |&k, _| {
+                    for _ in 0..40 {
+                        map.insert(next_key, next_key);
+                        next_key += 1;
+                    }
+
+                    matching.push(k);
+                },
+            );
+
+            let num_matching = matching.len();
+            matching.sort_unstable();
+            matching.dedup();
+            assert_eq!(matching.len(), num_matching);
+            assert!((0..1000)
+                .step_by(10)
+                .all(|i| matching.binary_search(&i).is_ok()));
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn try_remove_if() {
+            use $crate::map::NotRemovedReason;
+
+            let map = $m::new();
+            map.insert(1, 1);
+            map.insert(2, 2);
+
+            assert_eq!(
+                map.try_remove_if(&0, |_, _| panic!()).unwrap_err().reason(),
+                NotRemovedReason::Absent
+            );
+            assert_eq!(
+                map.try_remove_if(&1, |_, &v| v > 1).unwrap_err().reason(),
+                NotRemovedReason::ConditionFalse
+            );
+            assert_eq!(map.try_remove_if(&2, |_, &v| v > 1), Ok(2));
+            assert_eq!(map.get(&1), Some(1));
+            assert_eq!(map.len(), 1);
+
+            // another removal wins the race after the condition accepts the
+            // entry
+            let error = map
+                .try_remove_if(&1, |k, _| {
+                    assert_eq!(map.remove(k), Some(1));
+
+                    true
+                })
+                .unwrap_err();
+            assert_eq!(error.reason(), NotRemovedReason::Contended);
+            assert!(map.is_empty());
+
+            // a concurrent replacement is judged by its new value
+            map.insert(3, 3);
+            let mut num_invocations = 0;
+            let error = map
+                .try_remove_if(&3, |k, &v| {
+                    num_invocations += 1;
+
+                    if v == 3 {
+                        map.insert(*k, 4);
+                    }
+
+                    v == 3
+                })
+                .unwrap_err();
+            assert_eq!(error.reason(), NotRemovedReason::ConditionFalse);
+            assert_eq!(num_invocations, 2);
+            assert_eq!(map.get(&3), Some(4));
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn fold_and_reduce() {
+            let map = $m::new();
+
+            assert_eq!(map.fold(0, |count, _, _| count + 1), 0);
+            assert_eq!(map.reduce(|_, &v| v, i32::max), None);
+
+            for i in 0..1000 {
+                map.insert(i, i * 2);
+            }
+
+            let (count, sum) = map.fold((0, 0), |(count, sum), k, v| {
+                assert_eq!(k * 2, *v);
+
+                (count + 1, sum + v)
+            });
+            assert_eq!(count, 1000);
+            assert_eq!(sum, (0..1000).map(|i| i * 2).sum::<i32>());
+
+            assert_eq!(map.reduce(|_, &v| v, i32::max), Some(1998));
+            assert_eq!(map.reduce(|&k, _| k, i32::min), Some(0));
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn fold_during_growth() {
+            let map = $m::new();
+
+            for i in 0..1000 {
+                map.insert(i, i);
+            }
+
+            // the insertions resize the bucket arrays being walked
+            let mut next_key = 1000;
+            let mut visited = map.fold(Vec::new(), |mut visited, &k, _| {
+                for _ in 0..4 {
+                    map.insert(next_key, next_key);
+                    next_key += 1;
+                }
+
+                visited.push(k);
+                visited
+            });
+
+            let num_visited = visited.len();
+            visited.sort_unstable();
+            visited.dedup();
+            assert_eq!(visited.len(), num_visited);
+            assert!((0..1000).all(|i| visited.binary_search(&i).is_ok()));
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[cfg(feature = "futures")]
+        #[test]
+        fn entry_stream() {
+            use futures::{executor, StreamExt};
+
+            let map = $m::new();
+
+            for i in 0..1000 {
+                map.insert(i, i);
+            }
+
+            let mut stream = map.entry_stream(64);
+
+            // no guard is held between the polls
+            assert!(executor::block_on(stream.next()).is_some());
+            assert!(!crossbeam_epoch::is_pinned());
+
+            let mut entries = executor::block_on(stream.collect::<Vec<_>>());
+            entries.sort_unstable();
+
+            assert_eq!(entries.len(), 999);
+            assert!(entries.iter().all(|(k, v)| k == v));
+
+            // the map may be modified while it is streamed
+            let mut stream = map.entry_stream(1);
+            let mut num_entries = 0;
+
+            while let Some((k, _)) = executor::block_on(stream.next()) {
+                map.remove(&k);
+                num_entries += 1;
+            }
+
+            assert_eq!(num_entries, 1000);
+            assert!(map.is_empty());
+
+            let map = $m::<i32, i32>::new();
+            assert_eq!(executor::block_on(map.entry_stream(64).count()), 0);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        #[cfg(feature = "access-times")]
+        fn scan_by_staleness() {
+            let map = $m::new();
+
+            for i in 0..8 {
+                map.insert(i, i);
+            }
+
+            assert_eq!(map.scan_by_staleness(100).count(), 8);
+
+            // use the even keys, so that the odd ones become stalest
+            for i in (0..8).step_by(2) {
+                assert_eq!(map.get(&i), Some(i));
+            }
+
+            let mut stalest: Vec<_> = map.scan_by_staleness(4).map(|r| *r.key()).collect();
+            stalest.sort_unstable();
+            assert_eq!(stalest, vec![1, 3, 5, 7]);
+
+            map.insert(1, 10);
+            map.modify(3, |_, v| v + 10);
+
+            let keys: Vec<_> = map.scan_by_staleness(8).map(|r| *r.key()).collect();
+            assert_eq!(keys.len(), 8);
+
+            let mut stalest = keys[..2].to_vec();
+            stalest.sort_unstable();
+            assert_eq!(stalest, vec![5, 7]);
+
+            let mut freshest = keys[6..].to_vec();
+            freshest.sort_unstable();
+            assert_eq!(freshest, vec![1, 3]);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn content_fingerprint() {
+            use std::{collections::hash_map::DefaultHasher, hash::BuildHasherDefault};
+
+            let build_hasher = BuildHasherDefault::<DefaultHasher>::default();
+            let map1 = $m::with_hasher(build_hasher.clone());
+            let map2 = $m::with_hasher(build_hasher);
+
+            assert_eq!(map1.content_fingerprint(), 0);
+
+            for i in 0..64 {
+                map1.insert(i, i);
+                map2.insert(63 - i, 63 - i);
+            }
+
+            let fingerprint = map1.content_fingerprint();
+            assert_eq!(map2.content_fingerprint(), fingerprint);
+
+            map2.insert(0, 1);
+            assert_ne!(map2.content_fingerprint(), fingerprint);
+
+            map2.insert(0, 0);
+            map2.insert(64, 64);
+            assert_ne!(map2.content_fingerprint(), fingerprint);
+
+            map2.remove(&64);
+            assert_eq!(map2.content_fingerprint(), fingerprint);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn entries_in_hash_range() {
+            let map = $m::new();
+
+            for i in 0..256 {
+                map.insert(i, i);
+            }
+
+            let mut keys = Vec::new();
+
+            for range in $crate::sharding::split_hash_space(5) {
+                for r in map.entries_in_hash_range(*range.start(), *range.end()) {
+                    assert!(range.contains(&map.key_hash(r.key())));
+                    keys.push(*r.key());
+                }
+            }
+
+            keys.sort_unstable();
+            assert_eq!(keys, (0..256).collect::<Vec<_>>());
+
+            let hash = map.key_hash(&7);
+            let keys: Vec<_> = map
+                .entries_in_hash_range(hash, hash)
+                .map(|r| *r.key())
+                .collect();
+            assert_eq!(keys, vec![7]);
+
+            assert_eq!(map.entries_in_hash_range(1, 0).count(), 0);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn scan() {
+            let map = $m::new();
+
+            for i in 0..1000 {
+                map.insert(i, i);
+            }
+
+            let mut cursor = $crate::map::ScanCursor::new();
+            let mut seen = std::collections::HashMap::new();
+            let mut num_batches = 0;
+
+            while !cursor.is_done() {
+                for r in map.scan(&mut cursor, 64) {
+                    *seen.entry(*r.key()).or_insert(0) += 1;
+                }
+
+                num_batches += 1;
+
+                // grow the map between the batches, then shrink it back
+                if num_batches == 2 {
+                    for i in 1000..10000 {
+                        map.insert(i, i);
+                    }
+                } else if num_batches == 4 {
+                    for i in 1000..10000 {
+                        map.remove(&i);
+                    }
+
+                    map.run_housekeeping($crate::housekeeping::Budget::unlimited());
+                }
+            }
+
+            assert!(num_batches > 4);
+            assert!(seen.values().all(|&n| n == 1));
+
+            for i in 0..1000 {
+                assert!(seen.contains_key(&i));
+            }
+
+            assert_eq!(map.scan(&mut cursor, 64).count(), 0);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn scan_and() {
+            let map = $m::new();
+
+            for i in 0..1000 {
+                map.insert(i, i);
+            }
+
+            let mut cursor = $crate::map::ScanCursor::new();
+            let mut seen = std::collections::HashMap::new();
+            let mut num_batches = 0;
+
+            while !cursor.is_done() {
+                let mut batch_len = 0;
+
+                cursor = map.scan_and(cursor, 64, |k, v| {
+                    assert_eq!(k, v);
+                    *seen.entry(*k).or_insert(0) += 1;
+                    batch_len += 1;
+                });
+
+                assert!(batch_len >= 64 || cursor.is_done());
+                num_batches += 1;
+
+                // modify the map between the batches, which holds no guard
+                if num_batches == 2 {
+                    for i in 1000..5000 {
+                        map.insert(i, i);
+                    }
+                } else if num_batches == 4 {
+                    for i in 1000..5000 {
+                        map.remove(&i);
+                    }
+                }
+            }
+
+            assert!(num_batches > 4);
+            assert!(seen.values().all(|&n| n == 1));
+
+            for i in 0..1000 {
+                assert!(seen.contains_key(&i));
+            }
+
+            assert_eq!(map.scan_and(cursor, 64, |_, _| panic!()), cursor);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn scan_partition() {
+            let map = $m::new();
+
+            for i in 0..1000 {
+                map.insert(i, i);
+            }
+
+            for &n in &[1, 3, 8, 100] {
+                let mut partitions = map.partitions(n);
+                assert_eq!(partitions.len(), n);
+
+                let mut seen = std::collections::HashMap::new();
+                let mut num_batches = 0;
+
+                while partitions.iter().any(|p| !p.is_done()) {
+                    for partition in partitions.iter_mut() {
+                        for r in map.scan_partition(partition, 16) {
+                            *seen.entry(*r.key()).or_insert(0) += 1;
+                        }
+                    }
+
+                    num_batches += 1;
+
+                    // resize the map between the batches
+                    if num_batches == 1 {
+                        for i in 1000..5000 {
+                            map.insert(i, i);
+                        }
+                    } else if num_batches == 2 {
+                        for i in 1000..5000 {
+                            map.remove(&i);
+                        }
+
+                        map.run_housekeeping($crate::housekeeping::Budget::unlimited());
+                    }
+                }
+
+                assert!(seen.values().all(|&n| n == 1));
+
+                for i in 0..1000 {
+                    assert!(seen.contains_key(&i));
+                }
+            }
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn clear_in_batches() {
+            use $crate::housekeeping::Budget;
+
+            let map = $m::new();
+
+            for i in 0..5000 {
+                map.insert(i, i);
+            }
+
+            let mut num_removed = 0;
+            let mut num_calls = 0;
+
+            loop {
+                let progress = map.clear_in_batches(Budget::new(256));
+                num_removed += progress.num_removed();
+                num_calls += 1;
+
+                if progress.is_complete() {
+                    break;
+                }
+
+                // the map may be resized during the clear
+                if num_calls == 2 {
+                    for i in 5000..20000 {
+                        map.insert(i, i);
+                    }
+                }
+            }
+
+            assert!(num_calls > 5000 / 256);
+            assert!(num_removed >= 5000);
+            assert_eq!(map.len(), 20000 - num_removed);
+
+            for i in 0..5000 {
+                assert_eq!(map.get(&i), None);
+            }
+
+            // the next call starts a new clear
+            let progress = map.clear_in_batches(Budget::unlimited());
+            assert!(progress.is_complete());
+            assert_eq!(progress.num_removed(), 20000 - num_removed);
+            assert!(map.is_empty());
+
+            let progress = map.clear_in_batches(Budget::new(1));
+            assert_eq!(progress.num_removed(), 0);
+            assert!(progress.is_complete());
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn get_then_modify() {
+            use $crate::map::Decision;
+
+            const NUM_THREADS: usize = 8;
+            const NUM_INCREMENTS: usize = 1000;
+
+            let map = std::sync::Arc::new($m::new());
+            map.insert(0, 0);
+            map.insert(1, 1);
+
+            assert_eq!(map.get_then_modify(0, |_, _| Decision::Keep), Some(0));
+            assert_eq!(map.get(&0), Some(0));
+            assert_eq!(
+                map.get_then_modify(0, |_, v| Decision::Replace(v + 5)),
+                Some(0)
+            );
+            assert_eq!(map.get(&0), Some(5));
+            assert_eq!(map.get_then_modify(1, |_, _| Decision::Remove), Some(1));
+            assert_eq!(map.get(&1), None);
+            assert_eq!(map.len(), 1);
+            assert_eq!(
+                map.get_then_modify(1, |_, _| -> Decision<usize> { panic!() }),
+                None
+            );
+
+            // the decisions are made on the values they change, so no
+            // increment is lost
+            let barrier = std::sync::Arc::new(std::sync::Barrier::new(NUM_THREADS));
+
+            let threads: Vec<_> = (0..NUM_THREADS)
+                .map(|_| {
+                    let map = std::sync::Arc::clone(&map);
+                    let barrier = std::sync::Arc::clone(&barrier);
+
+                    std::thread::spawn(move || {
+                        barrier.wait();
+
+                        for _ in 0..NUM_INCREMENTS {
+                            map.get_then_modify(0, |_, v| Decision::Replace(v + 1));
+                        }
+                    })
+                })
+                .collect();
+
+            for result in threads.into_iter().map(std::thread::JoinHandle::join) {
+                assert!(result.is_ok());
+            }
+
+            assert_eq!(map.get(&0), Some(5 + NUM_THREADS * NUM_INCREMENTS));
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn iter() {
+            const NUM_THREADS: usize = 4;
+            const NUM_KEYS: usize = 1000;
+
+            let map = $m::new();
+            assert_eq!(map.iter().count(), 0);
+
+            for i in 0..NUM_KEYS {
+                map.insert(i, i * 2);
+            }
+
+            for i in (0..NUM_KEYS).step_by(3) {
+                map.remove(&i);
+            }
+
+            let mut entries: Vec<_> = map.iter().map(|r| (*r.key(), *r.value())).collect();
+            entries.sort_unstable();
+
+            let expected: Vec<_> = (0..NUM_KEYS)
+                .filter(|i| i % 3 != 0)
+                .map(|i| (i, i * 2))
+                .collect();
+            assert_eq!(entries, expected);
+
+            // entries are yielded once even while the map is resized
+            let map = std::sync::Arc::new($m::new());
+
+            for i in 0..NUM_KEYS {
+                map.insert(i, i);
+            }
+
+            let threads: Vec<_> = (0..NUM_THREADS)
+                .map(|t| {
+                    let map = std::sync::Arc::clone(&map);
+
+                    std::thread::spawn(move || {
+                        for i in 0..NUM_KEYS {
+                            map.insert(NUM_KEYS * (t + 1) + i, i);
+                        }
+                    })
+                })
+                .collect();
+
+            let mut keys: Vec<_> = map.iter().map(|r| *r.key()).collect();
+
+            for result in threads.into_iter().map(std::thread::JoinHandle::join) {
+                assert!(result.is_ok());
+            }
+
+            keys.sort_unstable();
+            let len = keys.len();
+            keys.dedup();
+            assert_eq!(keys.len(), len);
+            assert!((0..NUM_KEYS).all(|i| keys.binary_search(&i).is_ok()));
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn keys() {
+            let map = $m::new();
+            assert_eq!(map.keys().count(), 0);
+
+            for i in 0..100 {
+                map.insert(i.to_string(), i);
+            }
+
+            for i in (0..100).step_by(2) {
+                map.remove(&i.to_string());
+            }
+
+            let mut keys: Vec<_> = map.keys().collect();
+            keys.sort_unstable();
+
+            let mut expected: Vec<_> = (1..100).step_by(2).map(|i| i.to_string()).collect();
+            expected.sort_unstable();
+            assert_eq!(keys, expected);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn values() {
+            let map = $m::new();
+            assert_eq!(map.values(&$crate::epoch::pin()).count(), 0);
+            assert_eq!(map.values_cloned().count(), 0);
+
+            for i in 0..100 {
+                map.insert(i, i.to_string());
+            }
+
+            for i in (0..100).step_by(2) {
+                map.remove(&i);
+            }
+
+            let mut expected: Vec<_> = (1..100).step_by(2).map(|i| i.to_string()).collect();
+            expected.sort_unstable();
+
+            let mut values: Vec<_> = map.values_cloned().collect();
+            values.sort_unstable();
+            assert_eq!(values, expected);
+
+            let guard = &$crate::epoch::pin();
+            let mut values: Vec<_> = map.values(guard).collect();
+
+            // the references stay valid after the entries are removed
+            for i in 0..100 {
+                map.remove(&i);
+            }
+
+            assert!(map.is_empty());
+
+            values.sort_unstable();
+            assert_eq!(values, expected.iter().collect::<Vec<_>>());
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        #[should_panic(expected = "the guard must be pinned to the default collector")]
+        fn values_foreign_guard() {
+            let map = $m::new();
+            map.insert(0, 0);
+
+            let collector = $crate::epoch::Collector::new();
+            let handle = collector.register();
+
+            map.values(&handle.pin());
+        }
+
+        #[test]
+        fn for_each() {
+            let map = $m::new();
+            map.for_each(|_, _| unreachable!());
+
+            for i in 0..100 {
+                map.insert(i, i * 2);
+            }
+
+            let mut entries = Vec::new();
+            map.for_each(|k, v| entries.push((*k, *v)));
+            entries.sort_unstable();
+            assert_eq!(entries, (0..100).map(|i| (i, i * 2)).collect::<Vec<_>>());
+
+            // the closure may remove the entry it is invoked with
+            let mut num_visited = 0;
+            map.for_each(|k, v| {
+                assert_eq!(map.remove(k), Some(*v));
+                num_visited += 1;
+            });
+            assert_eq!(num_visited, 100);
+            assert!(map.is_empty());
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn for_each_modify() {
+            let map = $m::new();
+            map.for_each_modify(|_, _| unreachable!());
+
+            for i in 0..100 {
+                map.insert(i, i);
+            }
+
+            let mut num_visited = 0;
+            map.for_each_modify(|k, v| {
+                assert_eq!(k, v);
+                num_visited += 1;
+
+                if k % 2 == 0 {
+                    Some(v * 10)
+                } else {
+                    None
+                }
+            });
+
+            assert_eq!(num_visited, 100);
+            assert_eq!(map.len(), 100);
+
+            for i in 0..100 {
+                let expected = if i % 2 == 0 { i * 10 } else { i };
+                assert_eq!(map.get(&i), Some(expected));
+            }
+
+            // concurrent increments are not lost to the replacements
+            let threads: Vec<_> = (0..4)
+                .map(|_| {
+                    let map = &map;
+
+                    move || {
+                        for i in 0..100 {
+                            map.modify(i, |_, v| v + 1);
+                        }
+                    }
+                })
+                .collect();
+
+            std::thread::scope(|s| {
+                for t in threads {
+                    s.spawn(t);
+                }
+
+                s.spawn(|| map.for_each_modify(|_, v| Some(v + 1000)));
+            });
+
+            for i in 0..100 {
+                let expected = if i % 2 == 0 { i * 10 } else { i };
+                assert_eq!(map.get(&i), Some(expected + 1004));
+            }
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn retain() {
+            let map = $m::new();
+            assert_eq!(map.retain(|_, _| unreachable!()), 0);
+
+            for i in 0..1000 {
+                map.insert(i, i);
+            }
+
+            // keys inserted concurrently are kept by the predicate
+            std::thread::scope(|s| {
+                s.spawn(|| {
+                    for i in 1000..2000 {
+                        map.insert(i, i);
+                    }
+                });
+
+                assert_eq!(map.retain(|k, v| k == v && (k % 2 == 0 || *k >= 1000)), 500);
+            });
+
+            assert_eq!(map.len(), 1500);
+
+            for i in 0..2000 {
+                let expected = if i % 2 == 0 || i >= 1000 {
+                    Some(i)
+                } else {
+                    None
+                };
+                assert_eq!(map.get(&i), expected);
+            }
+
+            assert_eq!(map.retain(|_, _| false), 1500);
+            assert!(map.is_empty());
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn drain() {
+            const NUM_VALUES: i32 = 1024;
+
+            let map = $m::new();
+            assert_eq!(map.drain_and(|_, _| unreachable!()), 0);
+
+            for i in 0..NUM_VALUES {
+                map.insert(i, i);
+            }
+
+            // every entry is handed to either the drain or a concurrent removal
+            let (mut drained, removed) = std::thread::scope(|s| {
+                let remover = s.spawn(|| {
+                    (0..NUM_VALUES)
+                        .rev()
+                        .filter(|i| map.remove(i).is_some())
+                        .collect::<Vec<_>>()
+                });
+
+                let mut drained = Vec::new();
+                map.drain_and(|k, v| {
+                    assert_eq!(k, v);
+                    drained.push(*k);
+                });
+
+                (drained, remover.join().unwrap())
+            });
+
+            drained.extend(removed);
+            drained.sort_unstable();
+            assert_eq!(drained, (0..NUM_VALUES).collect::<Vec<_>>());
+            assert!(map.is_empty());
+
+            // entries inserted during a drain, which resize the map, are
+            // either drained or left in it
+            for i in 0..NUM_VALUES {
+                map.insert(i, i);
+            }
+
+            let mut drained = std::thread::scope(|s| {
+                s.spawn(|| {
+                    for i in NUM_VALUES..NUM_VALUES * 8 {
+                        map.insert(i, i);
+                    }
+                });
+
+                map.drain()
+            });
+
+            drained.extend(map.drain());
+            drained.sort_unstable();
+            assert_eq!(
+                drained,
+                (0..NUM_VALUES * 8).map(|i| (i, i)).collect::<Vec<_>>()
+            );
+            assert_eq!(map.len(), 0);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn into_iter() {
+            use std::sync::Arc;
+            use $crate::test_util::{DropNotifier, NoisyDropper};
+
+            const NUM_VALUES: usize = 512;
+
+            let parents: Vec<_> = (0..NUM_VALUES)
+                .map(|_| (Arc::new(DropNotifier::new()), Arc::new(DropNotifier::new())))
+                .collect();
+            let map: $m<i32, i32> = $m::new();
+            assert_eq!(map.into_iter().count(), 0);
+
+            let budget = $crate::map::MemoryBudget::new(NUM_VALUES);
+            let map = $m::builder()
+                .memory_budget(budget.clone(), |_, _| 1)
+                .build();
+
+            for (i, (key_parent, value_parent)) in parents.iter().enumerate() {
+                map.insert_and(
+                    NoisyDropper::new(Arc::clone(key_parent), i),
+                    NoisyDropper::new(Arc::clone(value_parent), i),
+                    |_| unreachable!(),
+                );
+            }
+
+            // the removed entries leave tombstones, which are not yielded
+            for i in (0..NUM_VALUES).step_by(2) {
+                assert!(map.remove_and(&i, |_| ()).is_some());
+            }
+
+            $crate::test_util::run_deferred();
+
+            let mut entries: Vec<_> = map.into_iter().collect();
+            assert_eq!(budget.used(), 0);
+
+            // the entries are moved out, not dropped
+            assert!(parents
+                .iter()
+                .skip(1)
+                .step_by(2)
+                .all(|(k, v)| !k.was_dropped() && !v.was_dropped()));
+
+            entries.sort_unstable_by_key(|(k, _)| k.elem);
+            assert!(entries
+                .iter()
+                .map(|(k, v)| (k.elem, v.elem))
+                .eq((1..NUM_VALUES).step_by(2).map(|i| (i, i))));
+
+            drop(entries);
+            assert!(parents
+                .iter()
+                .all(|(k, v)| k.was_dropped() && v.was_dropped()));
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn flush_after_bulk_removals() {
+            use std::sync::Arc;
+            use $crate::test_util::{DropNotifier, NoisyDropper};
+
+            const NUM_VALUES: usize = 1024;
+
+            let parents: Vec<_> = (0..NUM_VALUES)
+                .map(|_| Arc::new(DropNotifier::new()))
+                .collect();
+            let map = $m::builder().flush_after_bulk_removals().build();
+
+            for (i, parent) in parents.iter().enumerate() {
+                map.insert_and(i, NoisyDropper::new(Arc::clone(parent), i), |_| ());
+            }
+
+            assert_eq!(map.retain(|&k, _| k % 2 == 0), NUM_VALUES / 2);
+            assert_eq!(
+                map.extract_if_and(|&k, _| k % 4 == 0, |_, _| ()),
+                NUM_VALUES / 4
+            );
+            assert_eq!(map.retain(|_, _| true), 0);
+
+            let mut drained = Vec::new();
+            assert_eq!(map.drain_and(|&k, _| drained.push(k)), NUM_VALUES / 4);
+            assert!(map.is_empty());
+
+            drained.sort_unstable();
+            assert!(drained.into_iter().eq((2..NUM_VALUES).step_by(4)));
+
+            $crate::test_util::run_deferred();
+            assert!(parents.iter().all(|p| p.was_dropped()));
+        }
+
+        #[test]
+        fn extract_if() {
+            const NUM_VALUES: i32 = 1024;
+
+            let map = $m::new();
+            assert!(map.extract_if(|_, _| true).is_empty());
+
+            for i in 0..NUM_VALUES {
+                map.insert(i, i);
+            }
+
+            let mut extracted = map.extract_if(|k, _| k % 3 == 0);
+            extracted.sort_unstable();
+            assert_eq!(
+                extracted,
+                (0..NUM_VALUES)
+                    .step_by(3)
+                    .map(|i| (i, i))
+                    .collect::<Vec<_>>()
+            );
+            assert_eq!(map.len(), (NUM_VALUES - extracted.len() as i32) as usize);
+            assert!(map.iter().all(|r| r.key() % 3 != 0));
+
+            // an entry modified concurrently is extracted with the value the
+            // predicate accepted, or left in the map if it no longer matches
+            let len = map.len();
+            let extracted = std::thread::scope(|s| {
+                s.spawn(|| {
+                    for i in 0..NUM_VALUES {
+                        map.modify(i, |_, v| v + NUM_VALUES);
+                    }
+                });
+
+                let mut extracted = Vec::new();
+                map.extract_if_and(|_, v| v % 2 == 0, |k, v| extracted.push((*k, *v)));
+
+                extracted
+            });
+
+            assert!(extracted.iter().all(|(_, v)| v % 2 == 0));
+            assert!(extracted.iter().all(|(k, _)| map.get(k).is_none()));
+            assert_eq!(map.len(), len - extracted.len());
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[cfg(feature = "entry-pins")]
+        #[test]
+        fn wipe_tenant() {
+            use std::{sync::Arc, time::Duration};
+            use $crate::test_util::{DropNotifier, NoisyDropper};
+
+            const NUM_VALUES: usize = 512;
+
+            let parents: Vec<_> = (0..NUM_VALUES)
+                .map(|_| (Arc::new(DropNotifier::new()), Arc::new(DropNotifier::new())))
+                .collect();
+            let budget = $crate::map::MemoryBudget::new(NUM_VALUES);
+            let mut map = $m::builder()
+                .memory_budget(budget.clone(), |_, _| 1)
+                .build();
+            assert_eq!(map.wipe_tenant(|_| true), 0);
+
+            for (i, (key_parent, value_parent)) in parents.iter().enumerate() {
+                map.insert_and(
+                    NoisyDropper::new(Arc::clone(key_parent), i),
+                    NoisyDropper::new(Arc::clone(value_parent), i),
+                    |_| unreachable!(),
+                );
+            }
+
+            // the even keys belong to the wiped tenant. some of them leave
+            // tombstones and some are soft-removed
+            for i in (0..NUM_VALUES).step_by(4) {
+                assert!(map.remove_and(&i, |_| ()).is_some());
+                assert!(map.soft_remove(&(i + 2)));
+            }
+
+            assert_eq!(map.wipe_tenant(|k| k.elem % 2 == 0), 0);
+            assert_eq!(map.len(), NUM_VALUES / 2);
+            assert_eq!(budget.used(), NUM_VALUES / 2);
+
+            // the keys of the tombstones and the soft-removed entries are
+            // dropped right away
+            for (i, (key_parent, value_parent)) in parents.iter().enumerate() {
+                assert_eq!(key_parent.was_dropped(), i % 2 == 0);
+
+                if i % 4 == 2 {
+                    assert!(value_parent.was_dropped());
+                }
+            }
+
+            for i in (1..NUM_VALUES).step_by(2) {
+                assert_eq!(map.get_and(&i, |v| v.elem), Some(i));
+            }
+
+            assert_eq!(map.wipe_tenant(|k| k.elem % 4 == 1), NUM_VALUES / 4);
+            assert_eq!(map.len(), NUM_VALUES / 4);
+            assert_eq!(budget.used(), NUM_VALUES / 4);
+            assert!(parents.iter().enumerate().all(|(i, (k, v))| (i % 4 == 1)
+                == (k.was_dropped() && v.was_dropped())
+                || i % 2 == 0));
+
+            $crate::test_util::run_deferred();
+            assert!(parents
+                .iter()
+                .enumerate()
+                .all(|(i, (k, v))| (i % 4 != 3) == (k.was_dropped() && v.was_dropped())));
+
+            // pending coalesced writes are discarded instead of published, and
+            // soft-removed entries can not be restored
+            let mut map = $m::builder()
+                .coalesce_window(Duration::from_secs(3600))
+                .build();
+            map.insert_coalesced(1, 1);
+            map.insert_coalesced(1, 2);
+            map.insert_coalesced(2, 1);
+            map.insert_coalesced(2, 2);
+            map.insert(3, 3);
+            map.insert(4, 4);
+            assert!(map.soft_remove(&3));
+            assert!(map.soft_remove(&4));
+
+            assert_eq!(map.wipe_tenant(|&k| k % 2 == 1), 1);
+            map.flush_coalesced();
+            assert_eq!(map.get(&1), None);
+            assert_eq!(map.get(&2), Some(2));
+            assert!(!map.undelete(&3));
+            assert!(map.undelete(&4));
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[cfg(feature = "rayon")]
+        #[test]
+        fn par_for_each() {
+            use rayon::prelude::*;
+            use std::sync::{
+                atomic::{AtomicUsize, Ordering},
+                Mutex,
+            };
+
+            // enough entries to be split into several chunks
+            const NUM_VALUES: usize = 5 * $crate::map::parallel::CHUNK_LEN;
+
+            let map = $m::new();
+            map.par_for_each(|_: &usize, _: &usize| unreachable!());
+            assert_eq!(map.par_iter().count(), 0);
+
+            for i in 0..NUM_VALUES {
+                map.insert(i, i);
+            }
+
+            let visited = Mutex::new(vec![false; NUM_VALUES]);
+            let num_visited = AtomicUsize::new(0);
+            map.par_for_each(|&k, &v| {
+                assert_eq!(k, v);
+                assert!(!std::mem::replace(&mut visited.lock().unwrap()[k], true));
+                num_visited.fetch_add(1, Ordering::Relaxed);
+            });
+            assert_eq!(num_visited.into_inner(), NUM_VALUES);
+
+            // the entries can be removed while they are visited
+            map.par_for_each(|k, _| {
+                if k % 2 == 0 {
+                    map.remove(k);
+                }
+            });
+            assert_eq!(map.len(), NUM_VALUES / 2);
+
+            let mut entries: Vec<_> = map.par_iter().collect();
+            entries.sort_unstable();
+            assert!(entries
+                .into_iter()
+                .eq((1..NUM_VALUES).step_by(2).map(|i| (i, i))));
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[cfg(feature = "instrument")]
+        #[test]
+        fn reported_writes() {
+            let map = $m::new();
+            let mut num_resizes = 0;
+
+            for i in 0..1000 {
+                let (previous, report) = map.insert_reported(i, i);
+                assert_eq!(previous, None);
+                assert!(report.probe_length() >= 1);
+                assert_eq!(report.retries(), 0);
+
+                if report.caused_resize() {
+                    num_resizes += 1;
+                }
+            }
+
+            assert!(num_resizes > 0);
+
+            // replacing a value never resizes the map
+            map.insert(1000, 1000);
+            let (previous, report) = map.insert_reported(1000, 0);
+            assert_eq!(previous, Some(1000));
+            assert!(!report.caused_resize());
+
+            let (previous, report) = map.remove_reported(&0);
+            assert_eq!(previous, Some(0));
+            assert!(report.probe_length() >= 1);
+
+            let (previous, _) = map.remove_reported(&0);
+            assert_eq!(previous, None);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[cfg(all(feature = "entry-pins", feature = "zeroize"))]
+        #[test]
+        fn wipe_tenant_zeroized() {
+            use std::{
+                sync::{
+                    atomic::{AtomicUsize, Ordering},
+                    Arc,
+                },
+                time::Duration,
+            };
+
+            #[derive(Clone)]
+            struct Secret(Arc<AtomicUsize>);
+
+            impl zeroize::Zeroize for Secret {
+                fn zeroize(&mut self) {
+                    self.0.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+
+            let zeroized = Arc::new(AtomicUsize::new(0));
+            let mut map = $m::builder()
+                .coalesce_window(Duration::from_secs(3600))
+                .build();
+
+            for i in 0..64u64 {
+                map.insert(i, Secret(Arc::clone(&zeroized)));
+            }
+
+            // a live entry, a soft-removed entry and a pending write of the
+            // wiped tenant are zeroed, but the tombstone has no value left
+            assert!(map.remove_and(&0, |_| ()).is_some());
+            assert!(map.soft_remove(&2));
+            map.insert_coalesced(100, Secret(Arc::clone(&zeroized)));
+            map.insert_coalesced(100, Secret(Arc::clone(&zeroized)));
+
+            // 29 of the keys below 64 and the published key 100 are live
+            assert_eq!(map.wipe_tenant_zeroized(|&k| k % 2 == 0 && k != 4), 30);
+            assert_eq!(zeroized.load(Ordering::Relaxed), 30 + 1 + 1);
+            assert_eq!(map.len(), 33);
+            assert!(map.get_and(&4, |_| ()).is_some());
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn move_entry() {
+            const NUM_VALUES: i32 = 512;
+
+            let source = $m::new();
+            let destination = $m::new();
+
+            assert!(!source.move_entry(&0, &destination));
+
+            source.insert(0, 0);
+            destination.insert(0, 100);
+            assert!(source.move_entry(&0, &destination));
+            assert_eq!(source.get(&0), None);
+            assert_eq!(destination.get(&0), Some(0));
+            assert_eq!((source.len(), destination.len()), (0, 1));
+
+            for i in 1..NUM_VALUES {
+                source.insert(i, i);
+            }
+
+            // a key being moved is always in one of the maps, and concurrent
+            // modifications of the source are carried over
+            std::thread::scope(|s| {
+                s.spawn(|| {
+                    for i in 1..NUM_VALUES {
+                        source.modify(i, |_, v| v + NUM_VALUES);
+                    }
+                });
+
+                s.spawn(|| {
+                    for i in 1..NUM_VALUES {
+                        assert!(source.move_entry(&i, &destination));
+                    }
+                });
+
+                for i in 1..NUM_VALUES {
+                    assert!(source.get(&i).is_some() || destination.get(&i).is_some());
+                }
+            });
+
+            assert!(source.is_empty());
+            assert_eq!(destination.len(), NUM_VALUES as usize);
+
+            for i in 1..NUM_VALUES {
+                let value = destination.get(&i).unwrap();
+                assert!(value == i || value == i + NUM_VALUES);
+            }
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn sample_operations() {
+            use std::sync::{Arc, Mutex};
+            use $crate::metrics::{OperationSample, SampledOperation};
+
+            let samples: Arc<Mutex<Vec<OperationSample>>> = Arc::default();
+            let map = {
+                let samples = Arc::clone(&samples);
+
+                $m::builder()
+                    .sample_operations(1, move |s| samples.lock().unwrap().push(*s))
+                    .build()
+            };
+
+            for i in 0..100 {
+                map.insert(i, i);
+            }
+
+            for i in 0..100 {
+                assert_eq!(map.get(&i), Some(i));
+            }
+
+            for i in 0..50 {
+                assert_eq!(map.remove(&i), Some(i));
+            }
+
+            map.insert_with_or_modify(0, || 0, |_, v| v + 1);
+
+            let samples = samples.lock().unwrap();
+            let count = |op| samples.iter().filter(|s| s.operation() == op).count();
+            assert_eq!(count(SampledOperation::Insert), 101);
+            assert_eq!(count(SampledOperation::Get), 100);
+            assert_eq!(count(SampledOperation::Remove), 50);
+
+            let keys = (0..100).chain(0..100).chain(0..50).chain(0..1);
+
+            for (key, sample) in keys.zip(samples.iter()) {
+                assert_eq!(sample.key_hash(), map.key_hash(&key));
+                assert!(sample.probe_length() >= 1);
+            }
+
+            // the samples of a key all come from the same segment
+            for sample in samples.iter() {
+                let first = samples
+                    .iter()
+                    .find(|s| s.key_hash() == sample.key_hash())
+                    .unwrap();
+                assert_eq!(sample.segment(), first.segment());
+            }
+
+            drop(samples);
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn with_context() {
+            use std::sync::{
+                atomic::{AtomicUsize, Ordering},
+                Arc, Mutex,
+            };
+            use $crate::{map::MemoryBudget, metrics::SampledOperation};
+
+            let sampled: Arc<Mutex<Vec<(SampledOperation, Option<u64>)>>> = Arc::default();
+            let exceeded_by = Arc::new(AtomicUsize::new(0));
+            let map = {
+                let sampled = Arc::clone(&sampled);
+                let exceeded_by = Arc::clone(&exceeded_by);
+                let budget = MemoryBudget::with_context_listener(2, move |_, context| {
+                    let request_id = context.and_then(|c| c.downcast_ref::<u64>()).unwrap();
+                    exceeded_by.store(*request_id as usize, Ordering::Relaxed);
+                });
+
+                $m::builder()
+                    .memory_budget(budget, |_, _| 1)
+                    .sample_operations_with_context(1, move |s, context| {
+                        let request_id = context.and_then(|c| c.downcast_ref::<u64>());
+                        sampled
+                            .lock()
+                            .unwrap()
+                            .push((s.operation(), request_id.copied()));
+                    })
+                    .build()
+            };
+
+            let request = map.with_context(&7u64);
+            assert_eq!(request.insert(1, 1), None);
+            assert_eq!(request.insert_or_modify(1, 0, |_, v| v + 1), Some(1));
+            assert_eq!(request.modify(1, |_, v| v + 1), Some(2));
+            assert_eq!(request.get(&1), Some(3));
+            assert_eq!(map.insert(2, 2), None);
+            assert_eq!(map.len(), 2);
+
+            assert_eq!(map.with_context(&8u64).insert(3, 3), None);
+            assert_eq!(exceeded_by.load(Ordering::Relaxed), 8);
+            assert!(matches!(
+                request.try_insert(4, 4),
+                Err($crate::Error::MemoryBudgetExceeded)
+            ));
+            assert_eq!(exceeded_by.load(Ordering::Relaxed), 7);
+
+            assert_eq!(request.remove(&3), Some(3));
+            assert_eq!(request.remove(&3), None);
+            assert_eq!(map.len(), 2);
+
+            assert_eq!(
+                *sampled.lock().unwrap(),
+                [
+                    (SampledOperation::Insert, Some(7)),
+                    (SampledOperation::Insert, Some(7)),
+                    (SampledOperation::Get, Some(7)),
+                    (SampledOperation::Insert, None),
+                    (SampledOperation::Insert, Some(8)),
+                    (SampledOperation::Remove, Some(7)),
+                    (SampledOperation::Remove, Some(7)),
+                ]
+            );
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn iter_unordered_fast() {
+            let map = $m::new();
+            assert_eq!(map.iter_unordered_fast().count(), 0);
+
+            for i in 0..1000 {
+                map.insert(i, i * 2);
+            }
+
+            for i in (0..1000).step_by(3) {
+                map.remove(&i);
+            }
+
+            let mut entries: Vec<_> = map
+                .iter_unordered_fast()
+                .map(|r| (*r.key(), *r.value()))
+                .collect();
+            entries.sort_unstable();
+
+            let expected: Vec<_> = (0..1000)
+                .filter(|i| i % 3 != 0)
+                .map(|i| (i, i * 2))
+                .collect();
+            assert_eq!(entries, expected);
+
+            // a deterministic map is traversed in memory order too
+            let map = $m::builder().deterministic().build();
+
+            for i in 0..100 {
+                map.insert(i, i);
+            }
+
+            let mut keys: Vec<_> = map.iter_unordered_fast().map(|r| *r.key()).collect();
+            keys.sort_unstable();
+            assert_eq!(keys, (0..100).collect::<Vec<_>>());
+
+            // the iterator is lazy, so the insertions between its steps resize
+            // the bucket arrays it reads, and no entry is yielded twice
+            let mut next_key = 100;
+            let mut keys = Vec::new();
+
+            for r in map.iter_unordered_fast() {
+                for _ in 0..4 {
+                    map.insert(next_key, next_key);
+                    next_key += 1;
+                }
+
+                keys.push(*r.key());
+            }
+
+            let num_keys = keys.len();
+            keys.sort_unstable();
+            keys.dedup();
+            assert_eq!(keys.len(), num_keys);
+            assert!((0..100).all(|i| keys.binary_search(&i).is_ok()));
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn prefetch() {
+            let map = $m::new();
+
+            // prefetching does not allocate the bucket pointer array
+            map.prefetch(&0);
+            assert_eq!(map.capacity(), 0);
+
+            for i in 0..100 {
+                map.insert(i, i);
+            }
+
+            for i in 0..200 {
+                map.prefetch(&i);
+            }
+
+            for i in 0..200 {
+                assert_eq!(map.get(&i), if i < 100 { Some(i) } else { None });
+            }
+
+            assert_eq!(map.len(), 100);
+        }
+
+        #[test]
+        #[cfg(feature = "sequence-numbers")]
+        fn sequence_numbers() {
+            const NUM_THREADS: usize = 8;
+            const NUM_WRITES: usize = 1000;
+
+            let map = $m::new();
+            assert_eq!(map.insert_sequenced(0, 0), (None, 0));
+            assert_eq!(map.get_sequence(&0), Some(0));
+            assert_eq!(map.remove_sequenced(&0), Some((0, 0)));
+
+            let map = std::sync::Arc::new($m::builder().sequence_numbers().build());
+            let (previous, first) = map.insert_sequenced(0, 0);
+            assert_eq!(previous, None);
+            assert!(first > 0);
+            assert_eq!(map.get_sequence(&0), Some(first));
+
+            // the mutations made by other methods are assigned numbers too
+            map.insert_or_modify(0, 0, |_, v| v + 1);
+            let second = map.get_sequence(&0).unwrap();
+            assert!(second > first);
+
+            let (value, third) = map.remove_sequenced(&0).unwrap();
+            assert_eq!(value, 1);
+            assert!(third > second);
+            assert_eq!(map.get_sequence(&0), None);
+            assert_eq!(map.remove_sequenced(&0), None);
+            assert_eq!(map.len(), 0);
+
+            // the last write of a key to take effect has the greatest number
+            let barrier = std::sync::Arc::new(std::sync::Barrier::new(NUM_THREADS));
+
+            let threads: Vec<_> = (0..NUM_THREADS)
+                .map(|i| {
+                    let map = std::sync::Arc::clone(&map);
+                    let barrier = std::sync::Arc::clone(&barrier);
+
+                    std::thread::spawn(move || {
+                        barrier.wait();
+
+                        (0..NUM_WRITES)
+                            .map(|j| {
+                                let value = i * NUM_WRITES + j;
+                                (map.insert_sequenced(1, value).1, value)
+                            })
+                            .max()
+                            .unwrap()
+                    })
+                })
+                .collect();
+
+            let (sequence, value) = threads
+                .into_iter()
+                .map(|t| t.join().unwrap())
+                .max()
+                .unwrap();
+
+            assert_eq!(map.get_sequence(&1), Some(sequence));
+            assert_eq!(map.get(&1), Some(value));
+            assert_eq!(map.len(), 1);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn insert_with_hint() {
+            let map = $m::new();
+
+            map.insert(0, 1);
+            map.insert(1, 2);
+
+            let (value, hint) = map.remove_entry_with_hint_and(&0, |_, v| *v).unwrap();
+            assert_eq!(value, 1);
+            assert_eq!(map.len(), 1);
+
+            assert_eq!(map.insert_with_hint(0, 3, hint), None);
+            assert_eq!(map.get(&0), Some(3));
+            assert_eq!(map.len(), 2);
+
+            // the slot now holds a live entry, which is replaced
+            assert_eq!(map.insert_with_hint(0, 4, hint), Some(3));
+            assert_eq!(map.get(&0), Some(4));
+
+            // a hint for another key is ignored
+            assert_eq!(map.insert_with_hint(2, 5, hint), None);
+            assert_eq!(map.get(&2), Some(5));
+            assert_eq!(map.get(&0), Some(4));
+            assert_eq!(map.len(), 3);
+
+            assert_eq!(map.remove_entry_with_hint_and(&3, |_, _| ()), None);
+
+            // a hint is ignored after the map has been resized
+            let (_, hint) = map.remove_entry_with_hint_and(&1, |_, _| ()).unwrap();
+
+            for i in 3..1027 {
+                map.insert(i, i);
+            }
+
+            assert_eq!(map.insert_with_hint(1, 6, hint), None);
+            assert_eq!(map.get(&1), Some(6));
+            assert_eq!(map.len(), 1027);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn entry_handle() {
+            let map = $m::new();
+
+            map.insert(0, 1);
+            assert!(map.entry_handle(&1).is_none());
+
+            let handle = map.entry_handle(&0).unwrap();
+            assert_eq!(handle.key(), &0);
+            assert_eq!(map.resolve(&handle).map(|r| *r), Some(1));
+
+            map.insert(0, 2);
+            assert_eq!(map.resolve(&handle).map(|r| *r), Some(2));
+
+            // the handle survives the migration of the entry by a resize
+            let hint = handle.hint();
+            let mut i = 1;
+
+            while handle.hint() == hint {
+                assert!(i < 1 << 20);
+                map.insert(i, i);
+                i += 1;
+
+                assert_eq!(map.resolve(&handle).map(|r| (*r.key(), *r)), Some((0, 2)));
+            }
+
+            let hint = handle.hint();
+            assert_eq!(map.resolve(&handle).map(|r| *r), Some(2));
+            assert_eq!(handle.hint(), hint);
+
+            assert_eq!(map.remove(&0), Some(2));
+            assert!(map.resolve(&handle).is_none());
+
+            map.insert(0, 3);
+            assert_eq!(map.resolve(&handle.clone()).map(|r| *r), Some(3));
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        #[cfg(feature = "entry-flags")]
+        fn entry_flags() {
+            const ADMITTED: u8 = 0b01;
+            const DIRTY: u8 = 0b10;
+
+            let map = $m::new();
+
+            assert_eq!(map.fetch_flags(&0), None);
+            assert_eq!(map.set_flags(&0, ADMITTED), None);
+
+            map.insert(0, 0);
+            assert_eq!(map.fetch_flags(&0), Some(0));
+            assert_eq!(map.set_flags(&0, ADMITTED), Some(0));
+            assert_eq!(map.set_flags(&0, DIRTY), Some(ADMITTED));
+            assert_eq!(map.fetch_flags(&0), Some(ADMITTED | DIRTY));
+            assert_eq!(map.clear_flags(&0, DIRTY), Some(ADMITTED | DIRTY));
+            assert_eq!(map.fetch_flags(&0), Some(ADMITTED));
+
+            // the flags are kept when the value is modified or replaced
+            assert_eq!(map.modify(0, |_, v| v + 1), Some(0));
+            assert_eq!(map.insert(0, 2), Some(1));
+            assert_eq!(map.insert_or_modify(0, 3, |_, v| v + 1), Some(2));
+            assert_eq!(map.get(&0), Some(3));
+            assert_eq!(map.fetch_flags(&0), Some(ADMITTED));
+
+            // and when the map is resized
+            for i in 1..1025 {
+                map.insert(i, i);
+                map.set_flags(&i, DIRTY);
+            }
+
+            assert_eq!(map.fetch_flags(&0), Some(ADMITTED));
+
+            for i in 1..1025 {
+                assert_eq!(map.fetch_flags(&i), Some(DIRTY));
+            }
+
+            // but cleared when the key is removed
+            assert_eq!(map.remove(&0), Some(3));
+            assert_eq!(map.fetch_flags(&0), None);
+            map.insert(0, 4);
+            assert_eq!(map.fetch_flags(&0), Some(0));
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn insert_if() {
+            let map = $m::new();
+
+            assert_eq!(map.insert_if(0, 1, |s| s.map_len() == 1), Ok(None));
+            assert_eq!(map.insert_if(1, 2, |_| false), Err((1, 2)));
+            assert_eq!(map.insert_if(0, 3, |s| s.map_len() == 2), Ok(Some(1)));
+            assert_eq!(
+                map.insert_if(1, 4, |s| s.segment_len() >= 1
+                    && s.segment_len() <= s.map_len()),
+                Ok(None)
+            );
+            assert_eq!(map.len(), 2);
+            assert_eq!(map.get(&0), Some(3));
+            assert_eq!(map.get(&1), Some(4));
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn concurrent_insert_if() {
+            const BOUND: usize = 100;
+            const MAX_VALUE: i32 = 64;
+            const NUM_THREADS: usize = 16;
+
+            let map = std::sync::Arc::new($m::new());
+            let barrier = std::sync::Arc::new(std::sync::Barrier::new(NUM_THREADS));
+
+            let threads: Vec<_> = (0..NUM_THREADS)
+                .map(|i| {
+                    let map = std::sync::Arc::clone(&map);
+                    let barrier = std::sync::Arc::clone(&barrier);
+
+                    std::thread::spawn(move || {
+                        barrier.wait();
+
+                        for j in (0..MAX_VALUE).map(|j| j + (i as i32 * MAX_VALUE)) {
+                            let _ = map.insert_if(j, j, |s| s.map_len() <= BOUND);
+                        }
+                    })
+                })
+                .collect();
+
+            for result in threads.into_iter().map(std::thread::JoinHandle::join) {
+                assert!(result.is_ok());
+            }
+
+            assert_eq!(map.len(), BOUND);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn remove_if_cloned() {
+            let map = $m::new();
+
+            map.insert(0, 1);
+            map.insert(1, 2);
+
+            let mut calls = 0;
+            assert_eq!(
+                map.remove_if_cloned_and(
+                    &0,
+                    |v| {
+                        calls += 1;
+                        *v == 1
+                    },
+                    |v| *v
+                ),
+                Some(1)
+            );
+            assert_eq!(calls, 1);
+            assert_eq!(map.get(&0), None);
+
+            assert_eq!(map.remove_if_cloned_and(&0, |_| true, |v| *v), None);
+            assert_eq!(map.remove_if_cloned_and(&1, |v| *v == 1, |v| *v), None);
+            assert_eq!(map.get(&1), Some(2));
+
+            // the value is modified while the condition is evaluated
+            assert_eq!(
+                map.remove_if_cloned_and(
+                    &1,
+                    |_| {
+                        map.insert(1, 3);
+                        true
+                    },
+                    |v| *v
+                ),
+                None
+            );
+            assert_eq!(map.get(&1), Some(3));
+
+            assert_eq!(map.remove_if_unchanged_and(&1, &2, |v| *v), None);
+            assert_eq!(map.remove_if_unchanged_and(&1, &3, |v| *v), Some(3));
+            assert!(map.is_empty());
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn deterministic() {
+            let first = $m::builder().deterministic().build();
+            let second = $m::builder().capacity(4096).deterministic().build();
+
+            for i in 0..512 {
+                first.insert(i, i);
+                second.insert(511 - i, 511 - i);
+                second.insert(512 + i, i);
+            }
+
+            for i in 512..1024 {
+                second.remove(&i);
+            }
+
+            let keys =
+                |map: &$m<i32, i32, _>| -> Vec<i32> { map.iter().map(|r| *r.key()).collect() };
+
+            let first_keys = keys(&first);
+            assert_eq!(first_keys.len(), 512);
+            assert_eq!(first_keys, keys(&second));
+
+            let hashes: Vec<_> = first_keys.iter().map(|k| first.key_hash(k)).collect();
+            assert!(hashes.windows(2).all(|w| w[0] <= w[1]));
+
+            #[cfg(feature = "access-times")]
+            {
+                let stalest = |map: &$m<i32, i32, _>| -> Vec<i32> {
+                    map.scan_by_staleness(16).map(|r| *r.key()).collect()
+                };
+
+                assert_eq!(stalest(&first), stalest(&second));
+            }
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn reserve() {
+            let map = $m::new();
+
+            map.reserve(1000);
+            let capacity = map.capacity();
+            assert!(capacity >= 1000);
+
+            for i in 0..1000 {
+                map.insert(i, i);
+            }
+
+            map.reserve(0);
+            assert_eq!(map.capacity(), capacity);
+
+            map.reserve(4000);
+            assert!(map.capacity() >= 4000);
+
+            for i in 0..1000 {
+                assert_eq!(map.get(&i), Some(i));
+            }
+
+            assert_eq!(
+                map.try_reserve(usize::MAX).err(),
+                Some($crate::Error::CapacityOverflow)
+            );
+            assert_eq!(
+                map.try_reserve(usize::MAX / 2).err(),
+                Some($crate::Error::CapacityOverflow)
+            );
+            assert_eq!(map.len(), 1000);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn reserve_slots() {
+            let map = $m::new();
+
+            // a failed reservation holds no slots
+            assert_eq!(
+                map.try_reserve_slots(usize::MAX).err(),
+                Some($crate::Error::CapacityOverflow)
+            );
+
+            let mut token = map.reserve_slots(1000);
+            let reserved = token.remaining();
+            assert!(reserved >= 1000);
+            assert!(map.capacity() >= 1000);
+
+            // the insertions made without the token grow the map rather than
+            // use the reserved slots
+            let len = map.capacity();
+
+            for i in 0..len {
+                map.insert(i, i);
+            }
+
+            let capacity = map.capacity();
+            assert!(capacity > len);
+
+            for i in len..len + 1000 {
+                assert_eq!(token.insert(i, i), None);
+            }
+
+            // replacing a value uses no slot
+            assert_eq!(token.insert(len, 0), Some(len));
+            assert_eq!(token.remaining(), reserved - 1000);
+            assert_eq!(map.capacity(), capacity);
+            assert_eq!(map.len(), len + 1000);
+            drop(token);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[cfg(feature = "entry-pins")]
+        #[test]
+        fn soft_remove() {
+            let map = $m::new();
+
+            for i in 0..8 {
+                map.insert(i, i);
+            }
+
+            assert!(map.soft_remove(&0));
+            assert!(map.soft_remove(&1));
+            assert!(map.soft_remove(&2));
+            assert!(!map.soft_remove(&8));
+
+            // soft-removed entries are hidden from reads
+            assert_eq!(map.get(&0), None);
+            assert_eq!(map.len(), 5);
+            assert_eq!(map.get(&2), None);
+
+            assert!(map.undelete(&0));
+            assert_eq!(map.get(&0), Some(0));
+            assert_eq!(map.len(), 6);
+            assert!(!map.undelete(&0));
+
+            // a key inserted again is not restored
+            map.insert(1, 10);
+            assert!(!map.undelete(&1));
+            assert_eq!(map.get(&1), Some(10));
+
+            // a key soft-removed again keeps its newest entry
+            assert!(map.soft_remove(&1));
+            assert_eq!(map.purge(), 2);
+            assert!(!map.undelete(&1));
+            assert!(!map.undelete(&2));
+            assert_eq!(map.purge(), 0);
+
+            map.insert(1, 11);
+            assert!(map.soft_remove(&1));
+            map.insert(1, 12);
+            assert!(map.soft_remove(&1));
+            assert!(map.undelete(&1));
+            assert_eq!(map.get(&1), Some(12));
+            assert_eq!(map.len(), 7);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[cfg(feature = "entry-pins")]
+        #[test]
+        fn purge_with_guarded_reader() {
+            let parent = std::sync::Arc::new($crate::test_util::DropNotifier::new());
+
+            let map = $m::new();
+            map.insert_and(
+                0,
+                $crate::test_util::NoisyDropper::new(std::sync::Arc::clone(&parent), 0),
+                |_| (),
+            );
+            let barrier = std::sync::Barrier::new(2);
+
+            std::thread::scope(|s| {
+                let reader = s.spawn(|| {
+                    map.get_and(&0, |v| {
+                        barrier.wait();
+                        // the entry was soft-removed and purged while the
+                        // guard was held
+                        barrier.wait();
+                        assert!(!parent.was_dropped());
+
+                        v.elem
+                    })
+                });
+
+                barrier.wait();
+                assert!(map.soft_remove(&0));
+                assert_eq!(map.purge(), 1);
+                $crate::test_util::run_deferred();
+                barrier.wait();
+
+                assert_eq!(reader.join().unwrap(), Some(0));
+            });
+
+            $crate::test_util::run_deferred();
+            assert!(parent.was_dropped());
+        }
+
+        #[test]
+        fn typed_views() {
+            trait Handler: $crate::any_map::AsAny + Send + Sync {
+                fn name(&self) -> String;
+            }
+
+            #[derive(Clone, Debug, PartialEq)]
+            struct Counter(u32);
+
+            impl Handler for Counter {
+                fn name(&self) -> String {
+                    format!("counter {}", self.0)
+                }
+            }
+
+            impl Handler for String {
+                fn name(&self) -> String {
+                    self.clone()
+                }
+            }
+
+            let map: $m<u32, Box<dyn Handler>> = $m::new();
+            map.insert_and(0, Box::new(Counter(1)), |_| ());
+            map.insert_and(1, Box::new("foo".to_string()), |_| ());
+
+            assert_eq!(map.get_as::<_, Counter>(&0), Some(Counter(1)));
+            assert_eq!(map.get_as::<_, Counter>(&1), None);
+            assert_eq!(map.get_as::<_, Counter>(&2), None);
+            assert_eq!(map.get_as_and(&1, |s: &String| s.len()), Some(3));
+
+            assert_eq!(
+                map.modify_as_and(0, |_, c: &Counter| Box::new(Counter(c.0 + 1)), |c| c.0),
+                Some(1)
+            );
+            assert_eq!(map.get_and(&0, |h| h.name()), Some("counter 2".to_string()));
+
+            // values of other types are left unchanged
+            assert!(!map.modify_as(1, |_, _: &Counter| unreachable!()));
+            assert!(!map.modify_as(2, |_, _: &Counter| unreachable!()));
+            assert_eq!(map.get_and(&1, |h| h.name()), Some("foo".to_string()));
+
+            // the modified value may be of another type
+            assert!(map.modify_as(1, |_, s: &String| Box::new(Counter(s.len() as u32))));
+            assert_eq!(map.get_as::<_, Counter>(&1), Some(Counter(3)));
+            assert_eq!(map.len(), 2);
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[test]
+        fn coalesced_writes() {
+            let map = $m::builder()
+                .coalesce_window(std::time::Duration::from_secs(3600))
+                .build();
+            assert_eq!(
+                map.coalesce_window(),
+                Some(std::time::Duration::from_secs(3600))
+            );
+
+            // the first write is applied and the others are pending
+            for i in 0..100 {
+                map.insert_coalesced(0, i);
+            }
+
+            assert_eq!(map.get(&0), Some(0));
+            assert!(map.modify_coalesced(0, |_, v| v + 1));
+            assert!(!map.modify_coalesced(1, |_, v| v + 1));
+            assert_eq!(map.get(&0), Some(0));
+
+            map.flush_coalesced();
+            assert_eq!(map.get(&0), Some(100));
+            assert_eq!(map.len(), 1);
+
+            // a removal discards the pending value
+            map.insert_coalesced(1, 0);
+            map.insert_coalesced(1, 1);
+            assert_eq!(map.remove(&1), Some(0));
+            map.flush_coalesced();
+            assert_eq!(map.get(&1), None);
+            assert_eq!(map.len(), 1);
+
+            // without a window, the writes are applied right away
+            let map = $m::new();
+            assert_eq!(map.coalesce_window(), None);
+            map.insert_coalesced(0, 0);
+            assert!(map.modify_coalesced(0, |_, v| v + 1));
+            assert_eq!(map.get(&0), Some(1));
+        }
+
+        #[test]
+        fn map_handles() {
+            struct Recorder(std::sync::Mutex<Vec<(u64, $crate::metrics::HandleOpCounts)>>);
+
+            impl $crate::metrics::MetricsRecorder for Recorder {
+                fn record_handle_op_counts(
+                    &self,
+                    handle: u64,
+                    counts: &$crate::metrics::HandleOpCounts,
+                ) {
+                    self.0.lock().unwrap().push((handle, *counts));
+                }
+            }
+
+            let map = std::sync::Arc::new($m::new());
+            let first = map.handle();
+            let second = map.handle();
+            assert_eq!((first.id(), second.id()), (0, 1));
+
+            assert_eq!(first.insert(0, 0), None);
+            assert_eq!(first.insert_or_modify(0, 0, |_, v| v + 1), Some(0));
+            assert_eq!(first.get(&0), Some(1));
+            assert_eq!(second.get(&1), None);
+            assert_eq!(second.modify(0, |_, v| v + 1), Some(1));
+            assert_eq!(second.remove(&0), Some(2));
+            assert_eq!(second.remove(&0), None);
+
+            // the clones of a handle share its counts
+            let clone = first.clone();
+            assert_eq!(clone.id(), 0);
+            assert_eq!(clone.get(&0), None);
+            assert_eq!(first.op_counts(), clone.op_counts());
+
+            let recorder = Recorder(std::sync::Mutex::new(Vec::new()));
+            map.report_handle_op_counts(&recorder);
+            assert_eq!(
+                recorder.0.into_inner().unwrap(),
+                [
+                    (
+                        0,
+                        $crate::metrics::HandleOpCounts {
+                            hits: 1,
+                            misses: 1,
+                            inserts: 2,
+                            ..Default::default()
+                        }
+                    ),
+                    (
+                        1,
+                        $crate::metrics::HandleOpCounts {
+                            misses: 1,
+                            modifies: 1,
+                            removes: 2,
+                            ..Default::default()
+                        }
+                    ),
+                ]
+            );
+
+            // the counts of a handle are kept when its last clone is dropped
+            drop(first);
+            assert_eq!(map.handle_op_counts().0.len(), 2);
+            drop(clone);
+
+            let (handles, dropped) = map.handle_op_counts();
+            assert_eq!(handles.len(), 1);
+            assert_eq!(handles[0].id, 1);
+            assert_eq!(dropped.inserts, 2);
+            assert_eq!(map.handle().id(), 2);
+        }
+
+        #[test]
+        fn get_within() {
+            const NUM_VALUES: i32 = 512;
+
+            let map = $m::new();
+
+            for i in 0..NUM_VALUES {
+                assert_eq!(map.insert(i, i), None);
+            }
+
+            let unlimited = $crate::map::ReadBudget::unlimited();
+            let exhausted = $crate::map::ReadBudget::new(0, 0);
+
+            for i in 0..NUM_VALUES * 2 {
+                let expected = if i < NUM_VALUES { Some(i) } else { None };
+                assert_eq!(map.get_within(&i, unlimited), Ok(expected));
+                assert_eq!(
+                    map.get_and_within(&i, unlimited, |v| v * 2),
+                    Ok(expected.map(|v| v * 2))
+                );
+
+                // a segment may know that a key is absent without probing it
+                let result = map.get_and_within(&i, exhausted, |_| unreachable!());
+
+                if expected.is_some() {
+                    assert_eq!(result, Err($crate::Error::Busy));
+                } else {
+                    assert!(matches!(result, Ok(None) | Err($crate::Error::Busy)));
+                }
+            }
+        }
+
+        #[cfg(feature = "entry-pins")]
+        #[test]
+        fn pin_entry() {
+            let parents: Vec<_> = std::iter::repeat_with(|| {
+                std::sync::Arc::new($crate::test_util::DropNotifier::new())
+            })
+            .take(4)
+            .collect();
+            let value = |i: usize| {
+                $crate::test_util::NoisyDropper::new(std::sync::Arc::clone(&parents[i]), i)
+            };
+
+            let map = $m::new();
+            assert!(map.pin_entry(&0).is_none());
+
+            // removed while pinned
+            map.insert_and(0, value(0), |_| ());
+            let pin = map.pin_entry(&0).unwrap();
+            let other_pin = pin.clone();
+            let (k, v) = pin.pair();
+            assert_eq!((*k, v.elem), (0, 0));
+
+            assert!(map.remove_and(&0, |_| ()).is_some());
+            assert!(map.pin_entry(&0).is_none());
+            $crate::test_util::run_deferred();
+            assert!(!parents[0].was_dropped());
+            assert_eq!(pin.value().elem, 0);
+
+            std::mem::drop(pin);
+            assert!(!parents[0].was_dropped());
+            assert_eq!(other_pin.value().elem, 0);
+            std::mem::drop(other_pin);
+            $crate::test_util::run_deferred();
+            assert!(parents[0].was_dropped());
+
+            // replaced while pinned
+            map.insert_and(1, value(1), |_| ());
+            let pin = map.pin_entry(&1).unwrap();
+            assert!(map.insert_and(1, value(2), |_| ()).is_some());
+            $crate::test_util::run_deferred();
+            assert!(!parents[1].was_dropped());
+            assert_eq!(pin.value().elem, 1);
+            assert_eq!(map.pin_entry(&1).unwrap().value().elem, 2);
+
+            std::mem::drop(pin);
+            $crate::test_util::run_deferred();
+            assert!(parents[1].was_dropped());
+
+            // removed while pinned, and the tombstone reclaimed
+            map.insert_and(3, value(3), |_| ());
+            let pin = map.pin_entry(&3).unwrap();
+            assert!(map.remove_and(&3, |_| ()).is_some());
+            map.run_housekeeping($crate::housekeeping::Budget::unlimited());
+            $crate::test_util::run_deferred();
+            assert!(!parents[3].was_dropped());
+            assert_eq!(pin.key(), &3);
+            assert_eq!(pin.value().elem, 3);
+
+            std::mem::drop(pin);
+            $crate::test_util::run_deferred();
+            assert!(parents[3].was_dropped());
+            assert!(!parents[2].was_dropped());
+        }
+
+        #[cfg(feature = "entry-pins")]
+        #[test]
+        fn concurrent_pin_entry() {
+            const NUM_THREADS: usize = 8;
+            const NUM_KEYS: usize = 64;
+            const NUM_ROUNDS: usize = 512;
+
+            let map = std::sync::Arc::new($m::new());
+            let barrier = std::sync::Arc::new(std::sync::Barrier::new(NUM_THREADS));
+
+            let threads: Vec<_> = (0..NUM_THREADS)
+                .map(|i| {
+                    let map = std::sync::Arc::clone(&map);
+                    let barrier = std::sync::Arc::clone(&barrier);
+
+                    std::thread::spawn(move || {
+                        barrier.wait();
+
+                        for round in 0..NUM_ROUNDS {
+                            let key = (round * NUM_THREADS + i) % NUM_KEYS;
+
+                            if i % 2 == 0 {
+                                map.insert(key, key.to_string());
+
+                                if round % 3 == 0 {
+                                    map.remove(&key);
+                                }
+                            } else if let Some(pin) = map.pin_entry(&key) {
+                                let pins: Vec<_> =
+                                    std::iter::repeat_with(|| pin.clone()).take(4).collect();
+                                std::mem::drop(pin);
+                                std::thread::yield_now();
+
+                                for pin in pins {
+                                    assert_eq!(pin.value().parse::<usize>().unwrap(), key);
+                                }
+                            }
+                        }
+                    })
+                })
+                .collect();
+
+            for result in threads.into_iter().map(|t| t.join()) {
+                assert!(result.is_ok());
+            }
+
+            $crate::test_util::run_deferred();
+        }
+
+        #[cfg(feature = "entry-pins")]
+        #[test]
+        fn unpin_with_guarded_reader() {
+            let parent = std::sync::Arc::new($crate::test_util::DropNotifier::new());
+
+            let map = $m::new();
+            map.insert_and(
+                0,
+                $crate::test_util::NoisyDropper::new(std::sync::Arc::clone(&parent), 0),
+                |_| (),
+            );
+            let pin = map.pin_entry(&0).unwrap();
+            let barrier = std::sync::Barrier::new(2);
+
+            std::thread::scope(|s| {
+                let reader = s.spawn(|| {
+                    map.get_and(&0, |v| {
+                        barrier.wait();
+                        // the last pin was dropped while the guard was held
+                        barrier.wait();
+                        assert!(!parent.was_dropped());
+
+                        v.elem
+                    })
+                });
+
+                barrier.wait();
+                assert!(map.remove_and(&0, |_| ()).is_some());
+                std::mem::drop(pin);
+                $crate::test_util::run_deferred();
+                barrier.wait();
+
+                assert_eq!(reader.join().unwrap(), Some(0));
+            });
+
+            $crate::test_util::run_deferred();
+            assert!(parent.was_dropped());
+        }
+
+        #[test]
+        fn panicking_closures() {
+            use std::panic::{self, AssertUnwindSafe};
+
+            let parents: Vec<_> = std::iter::repeat_with(|| {
+                std::sync::Arc::new($crate::test_util::DropNotifier::new())
+            })
+            .take(3)
+            .collect();
+            let value = |i: usize| {
+                $crate::test_util::NoisyDropper::new(std::sync::Arc::clone(&parents[i]), i)
+            };
+
+            let map = $m::new();
+            assert_eq!(map.insert_and(0, value(0), |_| ()), None);
+
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                map.modify_and(0, |_, _| panic!("on_modify"), |_| ())
+            }));
+            assert!(result.is_err());
+            assert_eq!(map.get_and(&0, |v| v.elem), Some(0));
+            assert_eq!(map.len(), 1);
+
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                map.insert_or_modify_and(0, value(1), |_, _| panic!("on_modify"), |_| ())
+            }));
+            assert!(result.is_err());
+            assert_eq!(map.get_and(&0, |v| v.elem), Some(0));
+            assert_eq!(map.len(), 1);
+            assert!(parents[1].was_dropped());
+
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                map.insert_with_or_modify_and(
+                    1,
+                    || panic!("on_insert"),
+                    |_, _| unreachable!(),
+                    |_| (),
+                )
+            }));
+            assert!(result.is_err());
+            assert_eq!(map.get_and(&1, |_| ()), None);
+            assert_eq!(map.len(), 1);
+
+            // the closures invoked after the map is changed keep the change.
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                map.insert_and(0, value(2), |_| panic!("with_previous_value"))
+            }));
+            assert!(result.is_err());
+            assert_eq!(map.get_and(&0, |v| v.elem), Some(2));
+            assert_eq!(map.len(), 1);
+
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                map.remove_and(&0, |_| panic!("with_previous_value"))
+            }));
+            assert!(result.is_err());
+            assert_eq!(map.get_and(&0, |_| ()), None);
+            assert_eq!(map.len(), 0);
+
+            $crate::test_util::run_deferred();
+
+            assert!(parents.iter().all(|p| p.was_dropped()));
+
+            let map = $m::new();
+            map.insert(0, 0);
+
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                map.insert_if(1, 1, |_| panic!("condition"))
+            }));
+            assert!(result.is_err());
+            assert_eq!(map.get(&1), None);
+            assert_eq!(map.len(), 1);
+            assert_eq!(map.insert_if(1, 1, |s| s.map_len() == 2), Ok(None));
+        }
+
+        #[test]
+        fn panic_during_rehash() {
+            use std::{
+                panic::{self, AssertUnwindSafe},
+                sync::{
+                    atomic::{AtomicBool, Ordering},
+                    Arc,
+                },
+            };
+
+            const NUM_VALUES: i32 = 512;
+            const POISONED: i32 = NUM_VALUES / 2;
+
+            // hashing the poisoned key panics while this is set, which happens
+            // when the rehash gets to its bucket
+            static POISON: AtomicBool = AtomicBool::new(false);
+
+            // the clones of `keys` count the keys that were not dropped
+            #[derive(Debug)]
+            struct Key {
+                id: i32,
+                _count: Arc<()>,
+            }
+
+            impl std::hash::Hash for Key {
+                fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                    if self.id == POISONED && POISON.load(Ordering::Relaxed) {
+                        panic!("poisoned key");
+                    }
+
+                    self.id.hash(state);
+                }
+            }
+
+            impl PartialEq for Key {
+                fn eq(&self, other: &Self) -> bool {
+                    self.id == other.id
+                }
+            }
+
+            impl Eq for Key {}
+
+            impl std::borrow::Borrow<i32> for Key {
+                fn borrow(&self) -> &i32 {
+                    &self.id
+                }
+            }
+
+            let keys = Arc::new(());
+            let values = Arc::new(());
+
+            let new_map = || {
+                let map = $m::new();
+
+                for i in 0..NUM_VALUES {
+                    let key = Key {
+                        id: i,
+                        _count: Arc::clone(&keys),
+                    };
+                    map.insert(key, Arc::clone(&values));
+                }
+
+                // the tombstones are left in the old bucket pointer array
+                for i in (0..NUM_VALUES).step_by(3).filter(|&i| i != POISONED) {
+                    map.remove(&i);
+                }
+
+                POISON.store(true, Ordering::Relaxed);
+                let result =
+                    panic::catch_unwind(AssertUnwindSafe(|| map.reserve(NUM_VALUES as usize * 4)));
+                POISON.store(false, Ordering::Relaxed);
+                assert!(result.is_err());
+
+                map
+            };
+
+            // the next operation resumes the rehash and finds every entry
+            let map = new_map();
+            let is_present = |i: i32| i % 3 != 0 || i == POISONED;
+
+            for i in 0..NUM_VALUES {
+                assert_eq!(map.get_and(&i, |_| ()).is_some(), is_present(i));
+            }
+
+            let num_present = (0..NUM_VALUES).filter(|&i| is_present(i)).count();
+            assert_eq!(map.len(), num_present);
+            assert_eq!(map.iter().count(), num_present);
+
+            let key = Key {
+                id: NUM_VALUES,
+                _count: Arc::clone(&keys),
+            };
+            map.insert(key, Arc::clone(&values));
+            assert!(map.remove(&POISONED).is_some());
+            assert_eq!(map.len(), num_present);
+
+            drop(map);
+            $crate::test_util::run_deferred();
+            assert_eq!(Arc::strong_count(&keys), 1);
+            assert_eq!(Arc::strong_count(&values), 1);
+
+            // dropping the map without resuming the rehash reclaims every key
+            // and value once
+            drop(new_map());
+            $crate::test_util::run_deferred();
+            assert_eq!(Arc::strong_count(&keys), 1);
+            assert_eq!(Arc::strong_count(&values), 1);
+        }
     };
 }
@@ -0,0 +1,530 @@
+//! Synthetic workloads for evaluating hash map configurations.
+//!
+//! A [`Workload`] drives a mix of lookups, insertions, and removals of integer
+//! keys against a map from several threads, and returns a [`WorkloadReport`]
+//! with the throughput and the latency histograms of each type of operation.
+//! It lets embedders compare segment counts, hashers, and capacities on their
+//! own hardware and inside their own binaries, rather than relying on the
+//! numbers of the benchmarks of this crate.
+//!
+//! The maps are driven through the [`Target`] trait, which is implemented by
+//! [`HashMap`] and [`SegmentedHashMap`] with `u64` keys and values, and can be
+//! implemented by other types to compare them under the same workload.
+//!
+//! This module is only available when the `workload` feature is enabled.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use moka_cht::{
+//!     workload::{KeyDistribution, Workload},
+//!     SegmentedHashMap,
+//! };
+//!
+//! let workload = Workload::new()
+//!     .num_threads(2)
+//!     .operations_per_thread(1000)
+//!     .key_space(256)
+//!     .operation_mix(90, 8, 2)
+//!     .key_distribution(KeyDistribution::Zipf(1.0));
+//!
+//! for &num_segments in &[1, 4, 16] {
+//!     let map = SegmentedHashMap::with_num_segments(num_segments);
+//!     let report = workload.run(&map);
+//!
+//!     assert_eq!(report.operations(), 2000);
+//!     println!(
+//!         "{} segments: {:.0} ops/s, p99 get latency {:?}",
+//!         num_segments,
+//!         report.throughput(),
+//!         report.get().value_at_quantile(0.99),
+//!     );
+//! }
+//! ```
+//!
+//! [`Workload`]: ./struct.Workload.html
+//! [`WorkloadReport`]: ./struct.WorkloadReport.html
+//! [`Target`]: ./trait.Target.html
+//! [`HashMap`]: ../map/struct.HashMap.html
+//! [`SegmentedHashMap`]: ../segment/map/struct.HashMap.html
+
+use crate::{
+    map::HashMap,
+    metrics::{LatencySnapshot, LocalHistogram},
+    segment::HashMap as SegmentedHashMap,
+};
+
+use std::{
+    hash::BuildHasher,
+    sync::Barrier,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A concurrent map that a [`Workload`] can be run against.
+///
+/// [`Workload`]: ./struct.Workload.html
+pub trait Target: Sync {
+    /// Looks up a key, returning whether it was present.
+    fn get(&self, key: u64) -> bool;
+
+    /// Inserts a key-value pair.
+    fn insert(&self, key: u64, value: u64);
+
+    /// Removes a key, returning whether it was present.
+    fn remove(&self, key: u64) -> bool;
+}
+
+impl<S: BuildHasher + Sync> Target for HashMap<u64, u64, S> {
+    fn get(&self, key: u64) -> bool {
+        self.get_and(&key, |_| ()).is_some()
+    }
+
+    fn insert(&self, key: u64, value: u64) {
+        self.insert_and(key, value, |_| ());
+    }
+
+    fn remove(&self, key: u64) -> bool {
+        self.remove_and(&key, |_| ()).is_some()
+    }
+}
+
+impl<S: BuildHasher + Sync> Target for SegmentedHashMap<u64, u64, S> {
+    fn get(&self, key: u64) -> bool {
+        self.get_and(&key, |_| ()).is_some()
+    }
+
+    fn insert(&self, key: u64, value: u64) {
+        self.insert_and(key, value, |_| ());
+    }
+
+    fn remove(&self, key: u64) -> bool {
+        self.remove_and(&key, |_| ()).is_some()
+    }
+}
+
+/// The distribution of the keys that the operations of a [`Workload`] are
+/// performed on.
+///
+/// [`Workload`]: ./struct.Workload.html
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyDistribution {
+    /// Every key of the key space is equally likely.
+    Uniform,
+    /// The `i`-th key of the key space is chosen with a probability
+    /// proportional to `1 / (i + 1)^s`, where `s` is the exponent. An
+    /// exponent of about 1 models the skewed popularity of cached items.
+    ///
+    /// The cumulative distribution is precomputed, so running a workload
+    /// allocates 8 bytes per key of the key space.
+    Zipf(f64),
+}
+
+/// A configurable synthetic workload.
+///
+/// A workload is created with [`new`] and configured by its setters. Running
+/// it with [`run`] first inserts the prefilled part of the key space into the
+/// map, then starts the threads at once, each of which performs its
+/// operations on keys drawn from the key distribution.
+///
+/// The operations are selected and the keys are drawn by a pseudorandom
+/// number generator seeded from [`seed`], so a workload performs the same
+/// sequence of operations on each thread every time it is run.
+///
+/// [`new`]: #method.new
+/// [`run`]: #method.run
+/// [`seed`]: #method.seed
+#[derive(Clone, Debug)]
+pub struct Workload {
+    num_threads: usize,
+    operations_per_thread: u64,
+    key_space: u64,
+    reads: u32,
+    inserts: u32,
+    removes: u32,
+    distribution: KeyDistribution,
+    prefill: f64,
+    seed: u64,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Workload {
+    /// Creates a workload of 1,000,000 operations on each of 4 threads, over
+    /// a key space of 65,536 uniformly distributed keys of which half are
+    /// prefilled, with 80% lookups, 10% insertions, and 10% removals.
+    pub fn new() -> Self {
+        Self {
+            num_threads: 4,
+            operations_per_thread: 1_000_000,
+            key_space: 1 << 16,
+            reads: 80,
+            inserts: 10,
+            removes: 10,
+            distribution: KeyDistribution::Uniform,
+            prefill: 0.5,
+            seed: 0,
+        }
+    }
+
+    /// Sets the number of threads that perform operations concurrently.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_threads` is 0.
+    pub fn num_threads(self, num_threads: usize) -> Self {
+        assert!(num_threads > 0, "a workload needs at least one thread");
+
+        Self {
+            num_threads,
+            ..self
+        }
+    }
+
+    /// Sets the number of operations performed by each thread.
+    pub fn operations_per_thread(self, operations_per_thread: u64) -> Self {
+        Self {
+            operations_per_thread,
+            ..self
+        }
+    }
+
+    /// Sets the number of distinct keys, which are the integers from 0 up to
+    /// `key_space`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key_space` is 0.
+    pub fn key_space(self, key_space: u64) -> Self {
+        assert!(key_space > 0, "a workload needs at least one key");
+
+        Self { key_space, ..self }
+    }
+
+    /// Sets the relative weights of lookups, insertions, and removals.
+    ///
+    /// # Panics
+    ///
+    /// Panics if all weights are 0.
+    pub fn operation_mix(self, reads: u32, inserts: u32, removes: u32) -> Self {
+        assert!(
+            u64::from(reads) + u64::from(inserts) + u64::from(removes) > 0,
+            "a workload needs at least one type of operation"
+        );
+
+        Self {
+            reads,
+            inserts,
+            removes,
+            ..self
+        }
+    }
+
+    /// Sets the distribution of the keys.
+    pub fn key_distribution(self, distribution: KeyDistribution) -> Self {
+        Self {
+            distribution,
+            ..self
+        }
+    }
+
+    /// Sets the fraction of the key space that is inserted into the map
+    /// before the operations start. It is clamped to the range `[0, 1]`.
+    pub fn prefill(self, prefill: f64) -> Self {
+        Self {
+            prefill: prefill.clamp(0.0, 1.0),
+            ..self
+        }
+    }
+
+    /// Sets the seed of the pseudorandom number generators of the threads.
+    pub fn seed(self, seed: u64) -> Self {
+        Self { seed, ..self }
+    }
+
+    /// Runs the workload against `target`, returning its throughput and
+    /// latencies.
+    ///
+    /// The latencies include the time spent reading the clock, which is
+    /// typically a few tens of nanoseconds per operation.
+    pub fn run<T: Target + ?Sized>(&self, target: &T) -> WorkloadReport {
+        let num_prefilled = (self.key_space as f64 * self.prefill) as u64;
+
+        for key in 0..num_prefilled {
+            target.insert(key, key);
+        }
+
+        let sampler = KeySampler::new(self.distribution, self.key_space);
+        let barrier = Barrier::new(self.num_threads + 1);
+        let (sampler, barrier) = (&sampler, &barrier);
+
+        let (start, thread_reports) = thread::scope(|scope| {
+            let handles: Vec<_> = (0..self.num_threads)
+                .map(|index| {
+                    let rng = SplitMix64::new(self.seed.wrapping_add(index as u64));
+
+                    scope.spawn(move || {
+                        barrier.wait();
+
+                        self.run_thread(target, sampler, rng)
+                    })
+                })
+                .collect();
+
+            barrier.wait();
+            let start = Instant::now();
+
+            let thread_reports: Vec<_> = handles
+                .into_iter()
+                .map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+                .collect();
+
+            (start, thread_reports)
+        });
+        let elapsed = start.elapsed();
+
+        let mut total = ThreadReport::default();
+
+        for thread_report in &thread_reports {
+            total.merge(thread_report);
+        }
+
+        WorkloadReport {
+            operations: self.operations_per_thread * self.num_threads as u64,
+            elapsed,
+            get_hits: total.get_hits,
+            get: total.get.snapshot(),
+            insert: total.insert.snapshot(),
+            remove: total.remove.snapshot(),
+        }
+    }
+
+    fn run_thread<T: Target + ?Sized>(
+        &self,
+        target: &T,
+        sampler: &KeySampler,
+        mut rng: SplitMix64,
+    ) -> ThreadReport {
+        let total_weight =
+            u64::from(self.reads) + u64::from(self.inserts) + u64::from(self.removes);
+        let mut report = ThreadReport::default();
+
+        for _ in 0..self.operations_per_thread {
+            let choice = rng.next_below(total_weight);
+            let key = sampler.sample(&mut rng);
+
+            if choice < u64::from(self.reads) {
+                let start = Instant::now();
+                let hit = target.get(key);
+                report.get.record(start.elapsed());
+
+                report.get_hits += u64::from(hit);
+            } else if choice < u64::from(self.reads) + u64::from(self.inserts) {
+                let start = Instant::now();
+                target.insert(key, key);
+                report.insert.record(start.elapsed());
+            } else {
+                let start = Instant::now();
+                target.remove(key);
+                report.remove.record(start.elapsed());
+            }
+        }
+
+        report
+    }
+}
+
+/// The results of running a [`Workload`].
+///
+/// [`Workload`]: ./struct.Workload.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WorkloadReport {
+    operations: u64,
+    elapsed: Duration,
+    get_hits: u64,
+    get: LatencySnapshot,
+    insert: LatencySnapshot,
+    remove: LatencySnapshot,
+}
+
+impl WorkloadReport {
+    /// Returns the number of operations performed by all threads.
+    pub fn operations(&self) -> u64 {
+        self.operations
+    }
+
+    /// Returns the wall-clock time from the start of the operations until all
+    /// threads finished, excluding the prefill.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Returns the number of operations per second over all threads.
+    pub fn throughput(&self) -> f64 {
+        self.operations as f64 / self.elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+
+    /// Returns the fraction of lookups that found their key, or `None` if no
+    /// lookups were performed.
+    pub fn get_hit_ratio(&self) -> Option<f64> {
+        match self.get.count() {
+            0 => None,
+            count => Some(self.get_hits as f64 / count as f64),
+        }
+    }
+
+    /// Returns the latencies of the lookups.
+    pub fn get(&self) -> &LatencySnapshot {
+        &self.get
+    }
+
+    /// Returns the latencies of the insertions.
+    pub fn insert(&self) -> &LatencySnapshot {
+        &self.insert
+    }
+
+    /// Returns the latencies of the removals.
+    pub fn remove(&self) -> &LatencySnapshot {
+        &self.remove
+    }
+}
+
+#[derive(Default)]
+struct ThreadReport {
+    get_hits: u64,
+    get: LocalHistogram,
+    insert: LocalHistogram,
+    remove: LocalHistogram,
+}
+
+impl ThreadReport {
+    fn merge(&mut self, other: &Self) {
+        self.get_hits += other.get_hits;
+        self.get.merge(&other.get);
+        self.insert.merge(&other.insert);
+        self.remove.merge(&other.remove);
+    }
+}
+
+/// Draws keys from a `KeyDistribution`.
+enum KeySampler {
+    Uniform(u64),
+    // the cumulative distribution, normalized to `u64::MAX`
+    Zipf(Vec<u64>),
+}
+
+impl KeySampler {
+    fn new(distribution: KeyDistribution, key_space: u64) -> Self {
+        match distribution {
+            KeyDistribution::Uniform => KeySampler::Uniform(key_space),
+            KeyDistribution::Zipf(exponent) => {
+                let weights: Vec<_> = (0..key_space)
+                    .map(|i| ((i + 1) as f64).powf(-exponent))
+                    .collect();
+                let total: f64 = weights.iter().sum();
+                let mut cumulative = 0.0;
+
+                let cdf = weights
+                    .into_iter()
+                    .map(|w| {
+                        cumulative += w;
+
+                        (cumulative / total * u64::MAX as f64) as u64
+                    })
+                    .collect();
+
+                KeySampler::Zipf(cdf)
+            }
+        }
+    }
+
+    fn sample(&self, rng: &mut SplitMix64) -> u64 {
+        match self {
+            KeySampler::Uniform(key_space) => rng.next_below(*key_space),
+            KeySampler::Zipf(cdf) => {
+                let x = rng.next_u64();
+
+                (cdf.partition_point(|&c| c < x) as u64).min(cdf.len() as u64 - 1)
+            }
+        }
+    }
+}
+
+/// The SplitMix64 pseudorandom number generator, which is fast and good
+/// enough to select operations and keys.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `[0, bound)`, with a negligible bias for the bounds
+    /// used by workloads.
+    fn next_below(&mut self, bound: u64) -> u64 {
+        ((u128::from(self.next_u64()) * u128::from(bound)) >> 64) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run() {
+        let workload = Workload::new()
+            .num_threads(2)
+            .operations_per_thread(1000)
+            .key_space(64)
+            .operation_mix(1, 0, 0)
+            .prefill(1.0);
+
+        let map = HashMap::new();
+        let report = workload.run(&map);
+
+        assert_eq!(report.operations(), 2000);
+        assert_eq!(report.get().count(), 2000);
+        assert_eq!(report.insert().count(), 0);
+        assert_eq!(report.remove().count(), 0);
+        assert_eq!(report.get_hit_ratio(), Some(1.0));
+        assert_eq!(map.len(), 64);
+
+        let workload = workload.operation_mix(0, 1, 1).prefill(0.0);
+        let map = SegmentedHashMap::with_num_segments(4);
+        let report = workload.run(&map);
+
+        assert_eq!(report.get_hit_ratio(), None);
+        assert_eq!(report.insert().count() + report.remove().count(), 2000);
+        assert!(map.len() <= 64);
+    }
+
+    #[test]
+    fn zipf_is_skewed() {
+        let sampler = KeySampler::new(KeyDistribution::Zipf(1.0), 1000);
+        let mut rng = SplitMix64::new(0);
+        let mut counts = vec![0; 1000];
+
+        for _ in 0..100_000 {
+            counts[sampler.sample(&mut rng) as usize] += 1;
+        }
+
+        // the most popular key is drawn with a probability of 1 / H(1000),
+        // about 13%, and the probabilities decrease with the rank.
+        assert!((12_000..15_000).contains(&counts[0]));
+        assert!(counts[0] > counts[1] && counts[1] > counts[9] && counts[9] > counts[999]);
+    }
+}